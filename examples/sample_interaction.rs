@@ -17,10 +17,11 @@ use molasses::{
         ciphersuite::{CipherSuite, X25519_SHA256_AES128GCM},
         sig::{SigPublicKey, SigSecretKey, SignatureScheme, ED25519_IMPL},
     },
+    group_id::GroupId,
     group_state::{GroupState, Welcome},
     handshake::{Handshake, ProtocolVersion, UserInitKey, MLS_DUMMY_VERSION},
-    tls_de::TlsDeserializer,
-    tls_ser::TlsSerializer,
+    tls_de::{TlsDeserialize, TlsDeserializer},
+    tls_ser::{TlsSerialize, TlsSerializer},
     upcast::{CryptoCtx, CryptoUpcast},
 };
 
@@ -29,8 +30,6 @@ use std::thread;
 use crossbeam::channel;
 use rand;
 use rot13::rot13;
-use serde::de::Deserialize;
-use serde::ser::Serialize;
 
 const COMMON_CIPHER_SUITE: &'static CipherSuite = &X25519_SHA256_AES128GCM;
 const COMMON_SIG_SCHEME: &'static SignatureScheme = &ED25519_IMPL;
@@ -43,11 +42,11 @@ fn pause_for_effect() {
 }
 
 // Deserializes and upcasts MLS data structures
-pub fn deserialize<'de, T: Deserialize<'de> + CryptoUpcast>(bytes: &[u8]) -> T {
+pub fn deserialize<T: TlsDeserialize + CryptoUpcast>(bytes: &[u8]) -> T {
     // Deserialize
     let mut cursor = bytes;
     let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
-    let mut val = <T as Deserialize>::deserialize(&mut deserializer).unwrap();
+    let mut val = T::tls_deserialize(&mut deserializer).unwrap();
 
     // Punt on negotiating ciphersuites and signature schemes. I don't wanna deal with that
     let ctx = CryptoCtx::new()
@@ -61,9 +60,9 @@ pub fn deserialize<'de, T: Deserialize<'de> + CryptoUpcast>(bytes: &[u8]) -> T {
 }
 
 // Serializes MLS data structures
-fn serialize<T: Serialize>(value: &T) -> Vec<u8> {
+fn serialize<T: TlsSerialize>(value: &T) -> Vec<u8> {
     let mut serializer = TlsSerializer::new();
-    value.serialize(&mut serializer).unwrap();
+    value.tls_serialize(&mut serializer).unwrap();
     serializer.into_vec()
 }
 
@@ -170,7 +169,7 @@ fn alice(tx: channel::Sender<Vec<u8>>, rx: channel::Receiver<Vec<u8>>) {
         SigPublicKey::new_from_secret_key(COMMON_SIG_SCHEME, &identity_secret_key);
 
     // Make up a group ID
-    let group_id = b"suspicions_rising".to_vec();
+    let group_id = GroupId::new(b"suspicions_rising".to_vec()).unwrap();
 
     // Make up a credential
     let credential = {
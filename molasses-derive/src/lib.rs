@@ -0,0 +1,495 @@
+//! Derives `TlsSerialize` and `TlsDeserialize`, which are how molasses writes and reads its wire
+//! types. A type's encoding is the concatenation of its fields, in order, as modified by these
+//! attributes:
+//!
+//! * `#[tls(bound = "u16")]` on a field makes it a vector with a length prefix of that width,
+//!   i.e., `opaque field<0..2^16-1>`. The widths are `u8`, `u16`, `u24`, `u32`, `u64`, and
+//!   `varint`, which is a variable-length integer, as in `opaque field<V>`. On a newtype struct,
+//!   this bounds its one field, so that the type itself is the vector.
+//! * `#[tls(limit = "tree")]` or `#[tls(limit = "extensions")]` alongside a `bound` puts the
+//!   vector under the count limit of that name in `DecodeLimits`, for lists whose entries are
+//!   cheap to send but expensive to process. Other vectors are only limited by their length.
+//! * `#[tls(skip)]` on a field leaves it out. It's `Default::default()` when deserialized.
+//! * `#[tls(tag = "u8")]` on an enum, which is required, tags each variant with a number of that
+//!   width, `u8` or `u16`, followed by the variant's fields. Variants are numbered from 0 in
+//!   order, unless a variant says otherwise with `#[tls(discriminant = 3)]`.
+//!
+//! Everything else is up to the fields' own `TlsSerialize` and `TlsDeserialize` impls: integers
+//! are big-endian, `Option`s are `optional<T>`, and `Vec`s and arrays are their items in order.
+//! The generated impls name molasses' items by their `crate::` paths, so these derives only work
+//! inside of molasses itself.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Attribute, Data, DeriveInput, Error, Fields,
+    GenericParam, Generics, Ident, Index, Lit, Member, Meta, NestedMeta,
+};
+
+/// Derives `molasses::tls_ser::TlsSerialize`. See the crate documentation for the attributes.
+#[proc_macro_derive(TlsSerialize, attributes(tls))]
+pub fn derive_tls_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_serialize(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Derives `molasses::tls_de::TlsDeserialize`. See the crate documentation for the attributes.
+#[proc_macro_derive(TlsDeserialize, attributes(tls))]
+pub fn derive_tls_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deserialize(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// The width of an enum's tag
+#[derive(Clone, Copy)]
+enum TagWidth {
+    U8,
+    U16,
+}
+
+impl TagWidth {
+    fn max(self) -> u64 {
+        match self {
+            TagWidth::U8 => u64::from(u8::MAX),
+            TagWidth::U16 => u64::from(u16::MAX),
+        }
+    }
+
+    fn ty(self) -> TokenStream2 {
+        match self {
+            TagWidth::U8 => quote!(u8),
+            TagWidth::U16 => quote!(u16),
+        }
+    }
+
+    /// Returns `tag` as a literal of this width
+    ///
+    /// Requires: `tag <= self.max()`
+    fn literal(self, tag: u64) -> Literal {
+        match self {
+            TagWidth::U8 => Literal::u8_suffixed(tag as u8),
+            TagWidth::U16 => Literal::u16_suffixed(tag as u16),
+        }
+    }
+}
+
+/// The `tls` attributes on a struct or enum
+#[derive(Default)]
+struct ContainerAttrs {
+    /// The width of the length prefix of a newtype struct's field, if it's a vector
+    bound: Option<TokenStream2>,
+    /// The count limit that a newtype struct's vector is under, if any
+    limit: Option<TokenStream2>,
+    /// The width of an enum's tag
+    tag: Option<TagWidth>,
+}
+
+/// The `tls` attributes on a field
+#[derive(Default)]
+struct FieldAttrs {
+    /// The width of the field's length prefix, if it's a vector
+    bound: Option<TokenStream2>,
+    /// The count limit that the field's vector is under, if any
+    limit: Option<TokenStream2>,
+    /// Whether the field is left out of the encoding
+    skip: bool,
+}
+
+/// A field, and how the generated code refers to it
+struct FieldInfo {
+    /// The field's name or index, as it appears in struct expressions and patterns
+    member: Member,
+    /// The local variable that the field's value is bound to
+    binding: Ident,
+    /// The name of the field, or of its newtype struct, that errors refer to it by
+    name: String,
+    attrs: FieldAttrs,
+}
+
+/// Returns the items in every `#[tls(...)]` attribute in `attrs`
+fn tls_items(attrs: &[Attribute]) -> syn::Result<Vec<NestedMeta>> {
+    let mut items = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("tls")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested),
+            other => return Err(Error::new(other.span(), "expected #[tls(...)]")),
+        }
+    }
+
+    Ok(items)
+}
+
+/// Returns the `Bound` that the value of a `bound = "..."` attribute names
+fn parse_bound(lit: &Lit) -> syn::Result<TokenStream2> {
+    let width = match lit {
+        Lit::Str(s) => s.value(),
+        _ => return Err(Error::new(lit.span(), "expected a string, like bound = \"u16\"")),
+    };
+    let variant = match width.as_str() {
+        "u8" => quote!(U8),
+        "u16" => quote!(U16),
+        "u24" => quote!(U24),
+        "u32" => quote!(U32),
+        "u64" => quote!(U64),
+        "varint" => quote!(Varint),
+        _ => {
+            return Err(Error::new(
+                lit.span(),
+                "bound must be one of u8, u16, u24, u32, u64, or varint",
+            ))
+        }
+    };
+
+    Ok(quote!(crate::tls_ser::Bound::#variant))
+}
+
+/// Returns the `ItemLimit` that the value of a `limit = "..."` attribute names
+fn parse_limit(lit: &Lit) -> syn::Result<TokenStream2> {
+    let variant = match lit {
+        Lit::Str(s) if s.value() == "tree" => quote!(TreeSize),
+        Lit::Str(s) if s.value() == "extensions" => quote!(Extensions),
+        _ => return Err(Error::new(lit.span(), "limit must be \"tree\" or \"extensions\"")),
+    };
+
+    Ok(quote!(crate::tls_de::ItemLimit::#variant))
+}
+
+fn container_attrs(attrs: &[Attribute]) -> syn::Result<ContainerAttrs> {
+    let mut ret = ContainerAttrs::default();
+    for item in tls_items(attrs)? {
+        match item {
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("bound") => {
+                ret.bound = Some(parse_bound(&nv.lit)?);
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("limit") => {
+                ret.limit = Some(parse_limit(&nv.lit)?);
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("tag") => {
+                let width = match nv.lit {
+                    Lit::Str(ref s) if s.value() == "u8" => TagWidth::U8,
+                    Lit::Str(ref s) if s.value() == "u16" => TagWidth::U16,
+                    _ => return Err(Error::new(nv.lit.span(), "tag must be \"u8\" or \"u16\"")),
+                };
+                ret.tag = Some(width);
+            }
+            other => return Err(Error::new(other.span(), "unknown tls attribute")),
+        }
+    }
+
+    if ret.limit.is_some() && ret.bound.is_none() {
+        return Err(Error::new(Span::call_site(), "only a struct with a bound can have a limit"));
+    }
+    Ok(ret)
+}
+
+fn field_attrs(attrs: &[Attribute]) -> syn::Result<FieldAttrs> {
+    let mut ret = FieldAttrs::default();
+    for item in tls_items(attrs)? {
+        match item {
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("bound") => {
+                ret.bound = Some(parse_bound(&nv.lit)?);
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("limit") => {
+                ret.limit = Some(parse_limit(&nv.lit)?);
+            }
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("skip") => ret.skip = true,
+            other => return Err(Error::new(other.span(), "unknown tls field attribute")),
+        }
+    }
+
+    if ret.skip && ret.bound.is_some() {
+        return Err(Error::new(Span::call_site(), "a skipped field can't have a bound"));
+    }
+    if ret.limit.is_some() && ret.bound.is_none() {
+        return Err(Error::new(Span::call_site(), "only a field with a bound can have a limit"));
+    }
+    Ok(ret)
+}
+
+/// Returns the tag given by a variant's `discriminant = N` attribute, if it has one
+fn variant_discriminant(attrs: &[Attribute]) -> syn::Result<Option<u64>> {
+    let mut ret = None;
+    for item in tls_items(attrs)? {
+        match item {
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("discriminant") => {
+                match nv.lit {
+                    Lit::Int(ref i) => ret = Some(i.base10_parse::<u64>()?),
+                    _ => return Err(Error::new(nv.lit.span(), "expected an integer")),
+                }
+            }
+            other => return Err(Error::new(other.span(), "unknown tls variant attribute")),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Collects the fields of a struct or variant. A newtype struct's `bound` and `limit` go on its
+/// field, and it's named after the struct.
+fn field_infos(
+    fields: &Fields,
+    container_name: &Ident,
+    container: &ContainerAttrs,
+) -> syn::Result<Vec<FieldInfo>> {
+    if container.bound.is_some() && fields.len() != 1 {
+        return Err(Error::new(
+            container_name.span(),
+            "only structs with exactly one field can have a bound",
+        ));
+    }
+
+    let mut infos = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let mut attrs = field_attrs(&field.attrs)?;
+        let (member, mut name) = match field.ident {
+            Some(ref ident) => (Member::Named(ident.clone()), ident.to_string()),
+            None => (Member::Unnamed(Index::from(i)), i.to_string()),
+        };
+        if let Some(ref bound) = container.bound {
+            if attrs.bound.is_some() || attrs.skip {
+                return Err(Error::new(field.span(), "the struct already has a bound"));
+            }
+            attrs.bound = Some(bound.clone());
+            attrs.limit = container.limit.clone();
+            name = container_name.to_string();
+        }
+
+        infos.push(FieldInfo {
+            member,
+            binding: format_ident!("__field{}", i),
+            name,
+            attrs,
+        });
+    }
+
+    Ok(infos)
+}
+
+/// Adds `bound` to every type parameter
+fn add_bounds(mut generics: Generics, bound: TokenStream2) -> Generics {
+    for param in generics.params.iter_mut() {
+        if let GenericParam::Type(ref mut ty) = *param {
+            ty.bounds.push(parse_quote!(#bound));
+        }
+    }
+    generics
+}
+
+/// Makes the statements that serialize the given fields, each of whose values is a reference
+/// that `value` makes
+fn serialize_fields<F>(fields: &[FieldInfo], value: F) -> TokenStream2
+where
+    F: Fn(&FieldInfo) -> TokenStream2,
+{
+    let stmts = fields.iter().filter(|field| !field.attrs.skip).map(|field| {
+        let value = value(field);
+        match field.attrs.bound {
+            Some(ref bound) => quote! {
+                serializer.write_bounded(#bound, #value)?;
+            },
+            None => quote! {
+                crate::tls_ser::TlsSerialize::tls_serialize(#value, serializer)?;
+            },
+        }
+    });
+
+    quote!(#(#stmts)*)
+}
+
+/// Makes a pattern that binds every field that isn't skipped to its `binding`
+fn pattern(path: TokenStream2, fields: &[FieldInfo]) -> TokenStream2 {
+    let bindings = fields.iter().filter(|field| !field.attrs.skip).map(|field| {
+        let member = &field.member;
+        let binding = &field.binding;
+        quote!(#member: #binding,)
+    });
+
+    quote!(#path { #(#bindings)* .. })
+}
+
+/// Makes the statements that deserialize the given fields, in order, into their `binding`s
+fn deserialize_fields(fields: &[FieldInfo]) -> TokenStream2 {
+    let stmts = fields.iter().map(|field| {
+        let binding = &field.binding;
+        let name = &field.name;
+        let value = if field.attrs.skip {
+            quote!(::core::default::Default::default())
+        } else if let Some(ref bound) = field.attrs.bound {
+            let limit = match field.attrs.limit {
+                Some(ref limit) => quote!(::core::option::Option::Some(#limit)),
+                None => quote!(::core::option::Option::None),
+            };
+            quote!(deserializer.read_bounded(#bound, #limit, #name)?)
+        } else {
+            quote!(crate::tls_de::TlsDeserialize::tls_deserialize(deserializer)?)
+        };
+        quote! {
+            let #binding = #value;
+        }
+    });
+
+    quote!(#(#stmts)*)
+}
+
+/// Makes a struct expression out of the fields' `binding`s
+fn construct(path: TokenStream2, fields: &[FieldInfo]) -> TokenStream2 {
+    let members = fields.iter().map(|field| &field.member);
+    let bindings = fields.iter().map(|field| &field.binding);
+
+    quote!(#path { #(#members: #bindings),* })
+}
+
+/// Returns the tag of every variant of an enum, and checks that they fit and are distinct
+fn variant_tags(input: &DeriveInput, width: TagWidth) -> syn::Result<Vec<Literal>> {
+    let variants = match input.data {
+        Data::Enum(ref data) => &data.variants,
+        _ => unreachable!("only enums have variants"),
+    };
+
+    let mut tags: Vec<u64> = Vec::new();
+    for (i, variant) in variants.iter().enumerate() {
+        let tag = variant_discriminant(&variant.attrs)?.unwrap_or(i as u64);
+        if tag > width.max() {
+            return Err(Error::new(variant.span(), "the variant's tag doesn't fit in its width"));
+        }
+        if tags.contains(&tag) {
+            return Err(Error::new(variant.span(), "two variants have the same tag"));
+        }
+        tags.push(tag);
+    }
+
+    Ok(tags.into_iter().map(|tag| width.literal(tag)).collect())
+}
+
+/// Returns the enum's tag width, which it has to have
+fn tag_width(input: &DeriveInput, attrs: &ContainerAttrs) -> syn::Result<TagWidth> {
+    if attrs.bound.is_some() {
+        return Err(Error::new(input.ident.span(), "enums can't have a bound"));
+    }
+    attrs.tag.ok_or_else(|| {
+        Error::new(input.ident.span(), "enums need a tag width, like #[tls(tag = \"u8\")]")
+    })
+}
+
+fn expand_serialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let attrs = container_attrs(&input.attrs)?;
+
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            if attrs.tag.is_some() {
+                return Err(Error::new(name.span(), "only enums can have a tag"));
+            }
+            let fields = field_infos(&data.fields, name, &attrs)?;
+            serialize_fields(&fields, |field| {
+                let member = &field.member;
+                quote!(&self.#member)
+            })
+        }
+        Data::Enum(ref data) => {
+            let width = tag_width(input, &attrs)?;
+            let tags = variant_tags(input, width)?;
+            let mut arms = Vec::new();
+            for (variant, tag) in data.variants.iter().zip(tags) {
+                let variant_name = &variant.ident;
+                let fields =
+                    field_infos(&variant.fields, variant_name, &ContainerAttrs::default())?;
+                let pat = pattern(quote!(#name::#variant_name), &fields);
+                let write_fields = serialize_fields(&fields, |field| {
+                    let binding = &field.binding;
+                    quote!(#binding)
+                });
+                arms.push(quote! {
+                    #pat => {
+                        crate::tls_ser::TlsSerialize::tls_serialize(&#tag, serializer)?;
+                        #write_fields
+                    }
+                });
+            }
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new(name.span(), "unions can't be serialized")),
+    };
+
+    let generics = add_bounds(input.generics.clone(), quote!(crate::tls_ser::TlsSerialize));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics crate::tls_ser::TlsSerialize for #name #ty_generics #where_clause {
+            fn tls_serialize(
+                &self,
+                serializer: &mut crate::tls_ser::TlsSerializer,
+            ) -> ::core::result::Result<(), crate::error::Error> {
+                #body
+                Ok(())
+            }
+        }
+    })
+}
+
+fn expand_deserialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let attrs = container_attrs(&input.attrs)?;
+
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            if attrs.tag.is_some() {
+                return Err(Error::new(name.span(), "only enums can have a tag"));
+            }
+            let fields = field_infos(&data.fields, name, &attrs)?;
+            let read_fields = deserialize_fields(&fields);
+            let value = construct(quote!(#name), &fields);
+            quote! {
+                #read_fields
+                Ok(#value)
+            }
+        }
+        Data::Enum(ref data) => {
+            let width = tag_width(input, &attrs)?;
+            let tags = variant_tags(input, width)?;
+            let mut arms = Vec::new();
+            for (variant, tag) in data.variants.iter().zip(tags) {
+                let variant_name = &variant.ident;
+                let fields =
+                    field_infos(&variant.fields, variant_name, &ContainerAttrs::default())?;
+                let read_fields = deserialize_fields(&fields);
+                let value = construct(quote!(#name::#variant_name), &fields);
+                arms.push(quote! {
+                    #tag => {
+                        #read_fields
+                        Ok(#value)
+                    }
+                });
+            }
+            let tag_ty = width.ty();
+            let name_str = name.to_string();
+            quote! {
+                let tag: #tag_ty = crate::tls_de::TlsDeserialize::tls_deserialize(deserializer)?;
+                match tag {
+                    #(#arms)*
+                    _ => Err(crate::tls_de::unknown_variant(#name_str, u64::from(tag))),
+                }
+            }
+        }
+        Data::Union(_) => return Err(Error::new(name.span(), "unions can't be deserialized")),
+    };
+
+    let generics = add_bounds(input.generics.clone(), quote!(crate::tls_de::TlsDeserialize));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics crate::tls_de::TlsDeserialize for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn tls_deserialize<__R: ::std::io::Read>(
+                deserializer: &mut crate::tls_de::TlsDeserializer<'_, __R>,
+            ) -> ::core::result::Result<Self, crate::error::Error> {
+                #body
+            }
+        }
+    })
+}
@@ -2,22 +2,28 @@
 //! messages
 
 use crate::{
+    credential::{Credential, Roster},
     crypto::{
-        aead::{AeadKey, AeadNonce},
+        aead::{AeadKey, AeadNonce, StreamNonceSequence},
         ciphersuite::CipherSuite,
-        hkdf,
+        hash::HashContext,
         hmac::HmacKey,
+        kdf,
+        secret::Secret,
         sig::Signature,
     },
     error::Error,
+    framing::{self, ContentType},
+    group_id::GroupId,
     group_state::{ApplicationSecret, GroupState},
-    tls_de::TlsDeserializer,
+    tls_de::{DecodeLimits, TlsDeserialize, TlsDeserializer},
     tls_ser,
 };
 
 use core::convert::TryFrom;
+use std::sync::Arc;
 
-use serde::de::Deserialize;
+use subtle::ConstantTimeEq;
 
 /// Contains a secret that is unique to a member of the group. This is part of the application key
 /// schedule defined in the "Encryption Keys" section of the spec.
@@ -45,7 +51,7 @@ pub struct ApplicationKeyChain {
     group_cs: &'static CipherSuite,
 
     /// The creating group's ID
-    group_id: Vec<u8>,
+    group_id: GroupId,
 
     /// The creating group's epoch at the time of creation. This is important for making the
     /// `ApplicationKeyChain` work independently from the creating `GroupState`.
@@ -71,18 +77,18 @@ impl ApplicationKeyChain {
         let write_secrets_and_gens = (0u32..roster_len)
             .map(|roster_idx: u32| {
                 // write_secret_[sender] =
-                //     HKDF-Expand-Label(application_secret, "app sender", sender, Hash.length)
+                //     ExpandWithLabel(application_secret, "app sender", sender, Hash.length)
                 //  where sender is serialized as usual as a u32
-                let mut write_secret_buf = vec![0u8; group_state.cs.hash_impl.digest_size()];
+                let mut write_secret = HmacKey::new_from_zeros(group_state.cs.hash_impl);
                 let serialized_roster_idx = tls_ser::serialize_to_bytes(&roster_idx).unwrap();
-                hkdf::expand_label(
+                kdf::expand_with_label(
                     group_state.cs.hash_impl,
                     &prk,
                     b"app sender",
                     &serialized_roster_idx,
-                    write_secret_buf.as_mut_slice(),
+                    write_secret.0.as_mut_bytes(),
                 );
-                let write_secret = WriteSecret(HmacKey::new_from_bytes(&write_secret_buf));
+                let write_secret = WriteSecret(write_secret);
 
                 // (write_secret, generation=0)
                 (write_secret, 0)
@@ -105,34 +111,41 @@ impl ApplicationKeyChain {
     /// `roster_idx`. Returns an `Error` if `roster_idx` is out of bounds or something goes wrong
     /// in the creation of the key/nonce from bytes.
     fn get_key_nonce_gen(&self, roster_idx: usize) -> Result<(AeadKey, AeadNonce, u32), Error> {
-        // Get a reference to the write secret and current generation. We update these in-place at
-        // the end.
+        let (key, nonce_buf, generation) = self.get_key_nonce_bytes_gen(roster_idx)?;
+        let nonce = AeadNonce::new_from_bytes(self.group_cs.aead_impl, nonce_buf.as_bytes())?;
+        Ok((key, nonce, generation))
+    }
+
+    /// Like `get_key_nonce_gen`, but returns the nonce as bytes. Streams need this, since they
+    /// derive a new nonce for every chunk from this one.
+    fn get_key_nonce_bytes_gen(&self, roster_idx: usize) -> Result<(AeadKey, Secret, u32), Error> {
+        // Get a reference to the write secret and current generation
         let (write_secret, generation) = self
             .write_secrets_and_gens
             .get(roster_idx)
             .ok_or(Error::ValidationError("Roster index out of bounds of application key chain"))?;
 
-        // Derive the key and nonce
-        let mut key_buf = vec![0u8; self.group_cs.aead_impl.key_size()];
-        let mut nonce_buf = vec![0u8; self.group_cs.aead_impl.nonce_size()];
-        hkdf::expand_label(
-            self.group_cs.hash_impl,
+        // write_key_[sender]_[gen] = DeriveTreeSecret(write_secret, "key", gen, AEAD.Nk)
+        // write_nonce_[sender]_[gen] = DeriveTreeSecret(write_secret, "nonce", gen, AEAD.Nn)
+        let hash_impl = self.group_cs.hash_impl;
+        let aead_impl = self.group_cs.aead_impl;
+        let key_buf = kdf::derive_tree_secret(
+            hash_impl,
             &write_secret.0,
             b"key",
-            b"",
-            key_buf.as_mut_slice(),
+            *generation,
+            aead_impl.key_size(),
         );
-        hkdf::expand_label(
-            self.group_cs.hash_impl,
+        let nonce_buf = kdf::derive_tree_secret(
+            hash_impl,
             &write_secret.0,
             b"nonce",
-            b"",
-            nonce_buf.as_mut_slice(),
+            *generation,
+            aead_impl.nonce_size(),
         );
 
-        let key = AeadKey::new_from_bytes(self.group_cs.aead_impl, &key_buf)?;
-        let nonce = AeadNonce::new_from_bytes(self.group_cs.aead_impl, &nonce_buf)?;
-        Ok((key, nonce, *generation))
+        let key = AeadKey::new_from_bytes(aead_impl, key_buf.as_bytes())?;
+        Ok((key, nonce_buf, *generation))
     }
 
     /// Ratchets `write_secrets_[roster_idx]` forward, as per section 9.1 of the MLS spec
@@ -145,13 +158,13 @@ impl ApplicationKeyChain {
         // sake. From the spec, we derive the new keys as follows:
         //     application_secret_[sender]_[N-1]
         //               |
-        //               +--> HKDF-Expand-Label(.,"nonce", "", nonce_length)
+        //               +--> DeriveTreeSecret(., "nonce", N-1, AEAD.Nn)
         //               |    = write_nonce_[sender]_[N-1]
         //               |
-        //               +--> HKDF-Expand-Label(.,"key", "", key_length)
+        //               +--> DeriveTreeSecret(., "key", N-1, AEAD.Nk)
         //               |    = write_key_[sender]_[N-1]
         //               V
-        //     HKDF-Expand-Label(., "app sender", [sender], Hash.length)
+        //     DeriveTreeSecret(., "secret", N-1, Hash.length)
         //               |
         //               V
         //     application_secret_[sender]_[N]
@@ -161,22 +174,19 @@ impl ApplicationKeyChain {
             .write_secrets_and_gens
             .get_mut(roster_idx)
             .ok_or(Error::ValidationError("Roster index out of bounds of application key chain"))?;
-        let current_secret = write_secret.clone();
 
         // Ratchet the write secret, using its current value as a key
         // write_secret_[sender]_[n] =
-        //     HKDF-Expand-Label(write_secret_[sender]_[n-1], "app sender", sender, Hash.length)
-        let roster_idx = u32::try_from(roster_idx)
-            .map_err(|_| Error::ValidationError("Roster index exceeds u32::MAX"))?;
-        let serialized_roster_idx = tls_ser::serialize_to_bytes(&roster_idx).unwrap();
-        let prk: HmacKey = current_secret.into();
-        hkdf::expand_label(
-            self.group_cs.hash_impl,
-            &prk,
-            b"app sender",
-            &serialized_roster_idx,
-            (write_secret.0).0.as_mut_slice(), // Overwrite the undelrying HmacKey
+        //     DeriveTreeSecret(write_secret_[sender]_[n-1], "secret", n-1, Hash.length)
+        let hash_impl = self.group_cs.hash_impl;
+        let new_secret = kdf::derive_tree_secret(
+            hash_impl,
+            &write_secret.0,
+            b"secret",
+            *generation,
+            hash_impl.digest_size(),
         );
+        *write_secret = WriteSecret(HmacKey(new_secret));
 
         // Increment the generation
         *generation = generation
@@ -210,42 +220,130 @@ impl ApplicationKeyChain {
 //
 
 /// A signed payload of an application message. This can be padded at the end by an arbitrary
-/// number of zeros. This property is checked in constant time upon deserialization
-#[derive(Deserialize, Serialize)]
-#[serde(rename = "ApplicationMessageContent__zero_padded")]
+/// number of zeros. This property is checked in constant time upon deserialization, by
+/// `deserialize_message_content`.
+#[derive(TlsDeserialize, TlsSerialize)]
 struct ApplicationMessageContent {
     // opaque content<0..2^32-1>;
     /// The unencrypted message bytes
-    #[serde(rename = "content__bound_u32")]
+    #[tls(bound = "u32")]
     content: Vec<u8>,
 
     // opaque signature<0..2^16-1>;
     /// A signature over this message's associated `SignatureContent`
-    #[serde(rename = "signature__bound_u16")]
+    #[tls(bound = "u16")]
     signature: Vec<u8>,
 }
 
+/// Deserializes a decrypted `ApplicationMessageContent` under `limits`, and strips its padding
+///
+/// Returns: `Ok(content)` on success. If anything after the content isn't a zero, returns an
+/// `Error::ValidationError`. Otherwise, if the content is malformed, returns some sort of `Error`.
+fn deserialize_message_content(
+    bytes: &[u8],
+    limits: DecodeLimits,
+) -> Result<ApplicationMessageContent, Error> {
+    let mut cursor = bytes;
+    let message_content = {
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor).with_limits(limits);
+        ApplicationMessageContent::tls_deserialize(&mut deserializer)?
+    };
+
+    // The rest is padding
+    check_zero_padding(cursor)?;
+    Ok(message_content)
+}
+
+/// Checks that `padding` is all zeros. Everything is ORed together, so that checking takes the
+/// same time no matter where a nonzero byte is.
+///
+/// Returns: `Ok(())` if it's all zeros. Otherwise, returns an `Error::ValidationError`.
+pub(crate) fn check_zero_padding(padding: &[u8]) -> Result<(), Error> {
+    let padding_or = padding.iter().fold(0u8, |acc, &b| acc | b);
+    if bool::from(padding_or.ct_eq(&0)) {
+        Ok(())
+    } else {
+        Err(Error::ValidationError("Message padding isn't all zeros"))
+    }
+}
+
+/// How the content of an application message is padded before it's encrypted, so that the length
+/// of the ciphertext says less about the length of the plaintext. The padding is zeros after the
+/// serialized plaintext and signature, inside the encryption, and it's stripped upon decryption, so
+/// recipients don't need to know which scheme the sender used. This applies to every application
+/// message and encrypted handshake a `GroupState` makes, but not to streams.
+#[derive(Clone)]
+pub enum PaddingScheme {
+    /// No padding at all. This is the default.
+    None,
+    /// Pad to the next multiple of the given number of bytes, which has to be nonzero
+    Block(usize),
+    /// Pad to the next power of two
+    PowerOfTwo,
+    /// Pad to whatever length the callback returns when given the unpadded length. This has to be
+    /// at least the unpadded length.
+    Custom(Arc<dyn Fn(usize) -> usize + Send + Sync>),
+}
+
+impl PaddingScheme {
+    /// Returns the length that content of `len` bytes is padded to under this scheme
+    ///
+    /// Returns: `Ok(padded_len)` on success. If the block size is zero, or the callback returns a
+    /// length shorter than `len`, or the padded length overflows, returns an
+    /// `Error::ValidationError`.
+    pub fn padded_len(&self, len: usize) -> Result<usize, Error> {
+        let overflow = Error::ValidationError("Padded length of application message overflows");
+        match self {
+            PaddingScheme::None => Ok(len),
+            PaddingScheme::Block(0) => {
+                Err(Error::ValidationError("Padding block size must be nonzero"))
+            }
+            PaddingScheme::Block(block_size) => {
+                let rounded_up = len.checked_add(block_size - 1).ok_or(overflow)?;
+                Ok(rounded_up - rounded_up % block_size)
+            }
+            PaddingScheme::PowerOfTwo => len.checked_next_power_of_two().ok_or(overflow),
+            PaddingScheme::Custom(callback) => {
+                let padded_len = callback(len);
+                if padded_len < len {
+                    Err(Error::ValidationError(
+                        "Padding callback shortened the application message",
+                    ))
+                } else {
+                    Ok(padded_len)
+                }
+            }
+        }
+    }
+}
+
+impl Default for PaddingScheme {
+    fn default() -> PaddingScheme {
+        PaddingScheme::None
+    }
+}
+
 /// An application message that's strongly bound to the state of the group and application key
 /// schedule at the time of sending
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
 pub struct ApplicationMessage {
-    #[serde(rename = "group_id__bound_u8")]
-    group_id: Vec<u8>,
-    epoch: u32,
-    generation: u32,
-    sender: u32,
-    #[serde(rename = "encrypted_content__bound_u32")]
-    encrypted_content: Vec<u8>,
+    pub(crate) group_id: GroupId,
+    pub(crate) epoch: u32,
+    pub(crate) generation: u32,
+    pub(crate) sender: u32,
+    #[tls(bound = "u32")]
+    pub(crate) encrypted_content: Vec<u8>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(TlsSerialize)]
 struct SignatureContent<'a> {
-    #[serde(rename = "group_id__bound_u8")]
-    group_id: &'a [u8],
+    group_id: &'a GroupId,
     epoch: u32,
     generation: u32,
     sender: u32,
-    #[serde(rename = "content__bound_u32")]
+    #[tls(bound = "u32")]
+    authenticated_data: &'a [u8],
+    #[tls(bound = "u32")]
     content: &'a [u8],
 }
 
@@ -265,32 +363,69 @@ pub fn encrypt_application_message(
     // Check that this key chain really does belong to this group_state
     app_key_chain.validate_against_group_state(group_state)?;
 
-    // The validation above ensures these values are the same for the key chain as for the group
+    // This really really shouldn't be able to happen. A preliminary GroupState couldn't even
+    // produce an ApplicationSecret to make this key chain in the first place.
+    let my_roster_idx = group_state
+        .roster_index
+        .ok_or(Error::ValidationError("Cannot encrypt a message with a preliminary GroupState"))?;
+    let (key, nonce, generation) = app_key_chain.get_key_nonce_gen(my_roster_idx as usize)?;
+
+    // The epoch we use is the one that was current at the time of the creation of the key chain.
+    // This way, we could have multiple key chains in use at the same time and still be able to
+    // update the GroupState
+    let app_message = seal_application_message(
+        group_state,
+        app_key_chain.group_epoch_at_creation,
+        generation,
+        key,
+        nonce,
+        plaintext,
+        &[],
+    )?;
+
+    // All good. Now ratchet the write secret forward
+    app_key_chain.ratchet(my_roster_idx as usize)?;
+
+    Ok(app_message)
+}
+
+/// Signs `plaintext` as this member of `group_state`, and encrypts it along with the signature
+/// under the given key and nonce, padded as the group's `PaddingScheme` says. `epoch` and
+/// `generation` are what the message says it was encrypted under. `authenticated_data` is signed
+/// and bound to the ciphertext too, but not encrypted. This is what encrypting with an
+/// `ApplicationKeyChain` and with the group's secret tree have in common.
+///
+/// Returns: `Ok(app_message)` on success. Otherwise, if one of myriad things goes wrong, returns
+/// some sort of `Error`.
+pub(crate) fn seal_application_message(
+    group_state: &GroupState,
+    epoch: u32,
+    generation: u32,
+    key: AeadKey,
+    nonce: AeadNonce,
+    plaintext: Vec<u8>,
+    authenticated_data: &[u8],
+) -> Result<ApplicationMessage, Error> {
     let group_id = &group_state.group_id;
     let cs = group_state.cs;
 
     // Get the signature scheme from this member of the group_state
     let ss = group_state.get_signature_scheme();
-
-    // This really really shouldn't be able to happen. A preliminary GroupState couldn't even
-    // produce an ApplicationSecret to make this key chain in the first place.
     let my_roster_idx = group_state
         .roster_index
         .ok_or(Error::ValidationError("Cannot encrypt a message with a preliminary GroupState"))?;
-    let (key, nonce, generation) = app_key_chain.get_key_nonce_gen(my_roster_idx as usize)?;
 
-    // Sign the message. The epoch we use is the one that was current at the time of the creation of
-    // the key chain. This way, we could have multiple key chains in use at the same time and still
-    // be able to update the GroupState
+    // Sign the message
     let signature_content = SignatureContent {
-        group_id: &group_id,
-        epoch: app_key_chain.group_epoch_at_creation,
+        group_id,
+        epoch,
         generation,
         sender: my_roster_idx,
+        authenticated_data,
         content: &plaintext,
     };
     let hashed_signature_content = cs.hash_impl.hash_serializable(&signature_content)?;
-    let sig = ss.sign(&group_state.identity_key, hashed_signature_content.as_bytes());
+    let sig = ss.sign(&group_state.identity_key, hashed_signature_content.as_bytes())?;
 
     // Pack the plaintext and signature together and encrypt it
     let message_content = ApplicationMessageContent {
@@ -298,21 +433,22 @@ pub fn encrypt_application_message(
         signature: sig.as_bytes(),
     };
     let encrypted_content = {
-        // Serialize the ApplicationMessageContent and make room for the tag
+        // Serialize the ApplicationMessageContent, pad it, and make room for the tag
         let mut serialized_message_content = tls_ser::serialize_to_bytes(&message_content)?;
+        let padded_len = group_state.padding_scheme.padded_len(serialized_message_content.len())?;
+        serialized_message_content.resize(padded_len, 0u8);
         serialized_message_content.extend(vec![0u8; cs.aead_impl.tag_size()]);
 
         // Encrypt it
-        cs.aead_impl.seal(&key, nonce, &mut serialized_message_content)?;
+        let aad =
+            framing::content_aad(group_id, epoch, ContentType::Application, authenticated_data)?;
+        cs.aead_impl.seal_with_aad(&key, nonce, &aad, &mut serialized_message_content)?;
         serialized_message_content
     };
 
-    // All good. Now ratchet the write secret forward
-    app_key_chain.ratchet(my_roster_idx as usize)?;
-
     Ok(ApplicationMessage {
-        group_id: group_state.group_id.clone(),
-        epoch: app_key_chain.group_epoch_at_creation,
+        group_id: group_id.clone(),
+        epoch,
         generation,
         sender: my_roster_idx,
         encrypted_content,
@@ -327,19 +463,16 @@ pub fn encrypt_application_message(
 // Note that this still has to take in a `GroupState` because the group's roster is liable to change
 // over time, and the roster is necessary to verify message signatures.
 pub fn decrypt_application_message(
-    mut app_message: ApplicationMessage,
+    app_message: ApplicationMessage,
     group_state: &GroupState,
     app_key_chain: &mut ApplicationKeyChain,
 ) -> Result<Vec<u8>, Error> {
     // Check that this key chain really does belong to this group_state
     app_key_chain.validate_against_group_state(group_state)?;
 
-    // The validation above ensures these values are the same for the key chain as for the group
-    let group_id = &group_state.group_id;
-    let cs = group_state.cs;
-
-    // Check that the message was for this group
-    if &app_message.group_id != group_id {
+    // Check that the message was for this group. The validation above ensures that the key
+    // chain's group is this one.
+    if app_message.group_id != group_state.group_id {
         return Err(Error::ValidationError(
             "Application message's group_id differs from the key chain's",
         ));
@@ -354,6 +487,10 @@ pub fn decrypt_application_message(
         ));
     }
 
+    // This fails if the sender has been removed from the group, so we do it before the key chain
+    // is touched
+    get_sender_credential(&group_state.roster, app_message.sender)?;
+
     // Get the secrets necessary to decrypt it
     let (key, nonce, generation) = app_key_chain.get_key_nonce_gen(app_message.sender as usize)?;
 
@@ -364,69 +501,362 @@ pub fn decrypt_application_message(
         ));
     }
 
-    // Get the sender's public key and preferred signature scheme from the roster. There are two
-    // things that can go wrong here: either the sender index is bad, or the index is good but the
-    // roster entry is empty.
-    let sender_credential = group_state
-        .roster
-        .0
-        .get(app_message.sender as usize)
-        .ok_or(Error::ValidationError("Application message's sender index is out of bounds"))?
-        .as_ref()
-        .ok_or(Error::ValidationError("Application message's sender credential is empty"))?;
+    // See above for why we use group_epoch_at_creation
+    let sender = app_message.sender;
+    let plaintext = open_application_message(
+        group_state,
+        &group_state.roster,
+        app_key_chain.group_epoch_at_creation,
+        key,
+        nonce,
+        app_message,
+        &[],
+    )?;
+
+    // All good. Now ratchet the write secret forward
+    app_key_chain.ratchet(sender as usize)?;
+
+    Ok(plaintext)
+}
+
+/// Decrypts `app_message` under the given key and nonce, and checks the sender's signature over
+/// it. `epoch` is the epoch that the signature covers, `roster` is the roster the sender is looked
+/// up in, which is that of `epoch`, and `authenticated_data` is what the sender bound to the
+/// message without encrypting it. This is what decrypting with an
+/// `ApplicationKeyChain` and with the group's secret tree have in common.
+///
+/// Returns: `Ok(plaintext)` on success. Otherwise, if one of myriad things goes wrong, returns some
+/// sort of `Error`.
+pub(crate) fn open_application_message(
+    group_state: &GroupState,
+    roster: &Roster,
+    epoch: u32,
+    key: AeadKey,
+    nonce: AeadNonce,
+    mut app_message: ApplicationMessage,
+    authenticated_data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let cs = group_state.cs;
+
+    // Get the sender's public key and preferred signature scheme from the roster
+    let sender_credential = get_sender_credential(roster, app_message.sender)?;
     let sender_pubkey = sender_credential.get_public_key();
     let sender_ss = sender_credential.get_signature_scheme();
 
     // Reconstruct the content of the message as well as its signature
+    let aad = framing::content_aad(
+        &group_state.group_id,
+        epoch,
+        ContentType::Application,
+        authenticated_data,
+    )?;
     let serialized_message_content =
-        cs.aead_impl.open(&key, nonce, &mut app_message.encrypted_content)?;
-    let message_content = {
-        let mut cursor: &[u8] = serialized_message_content;
-        let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
-        ApplicationMessageContent::deserialize(&mut deserializer)?
-    };
+        cs.aead_impl.open_with_aad(&key, nonce, &aad, &mut app_message.encrypted_content)?;
+    let message_content =
+        deserialize_message_content(serialized_message_content, group_state.decode_limits)?;
     let plaintext = message_content.content;
     let signature = Signature::new_from_bytes(sender_ss, &message_content.signature)?;
 
-    // Create the stuff that the signature is over, then verify the signature. See above for why we
-    // use group_epoch_at_creation
+    // Create the stuff that the signature is over, then verify the signature
     let signature_content = SignatureContent {
-        group_id,
-        epoch: app_key_chain.group_epoch_at_creation,
-        generation,
+        group_id: &group_state.group_id,
+        epoch,
+        generation: app_message.generation,
         sender: app_message.sender,
+        authenticated_data,
         content: &plaintext,
     };
     let hashed_signature_content = cs.hash_impl.hash_serializable(&signature_content)?;
     sender_ss.verify(sender_pubkey, hashed_signature_content.as_bytes(), &signature)?;
 
-    // All good. Now ratchet the write secret forward
-    app_key_chain.ratchet(app_message.sender as usize)?;
-
     Ok(plaintext)
 }
 
+/// Looks up the credential of the sender of an application message or stream in `roster`. There
+/// are two things that can go wrong here: either the sender index is bad, or the index is good but
+/// the roster entry is empty. Both are `Error::ValidationError`s.
+pub(crate) fn get_sender_credential(roster: &Roster, sender: u32) -> Result<&Credential, Error> {
+    roster
+        .0
+        .get(sender as usize)
+        .ok_or(Error::ValidationError("Application message's sender index is out of bounds"))?
+        .as_ref()
+        .ok_or(Error::ValidationError("Application message's sender credential is empty"))
+}
+
+/// The header of an application stream. This is sent ahead of the stream's chunks, and tells the
+/// receiver which write secret the stream is encrypted under.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+pub struct ApplicationStreamHeader {
+    group_id: GroupId,
+    epoch: u32,
+    generation: u32,
+    sender: u32,
+}
+
+/// What the sender of a stream signs. The label keeps this from being confused with the
+/// `SignatureContent` of an ordinary application message.
+#[derive(TlsSerialize)]
+struct StreamSignatureContent<'a> {
+    #[tls(bound = "u8")]
+    label: &'a [u8],
+    header: &'a ApplicationStreamHeader,
+    #[tls(bound = "u8")]
+    content_hash: &'a [u8],
+}
+
+const STREAM_SIGNATURE_LABEL: &[u8] = b"molasses application stream";
+
+/// Encrypts an arbitrarily long application payload chunk by chunk, without ever holding the
+/// whole thing in memory. The whole stream is encrypted under a single generation of the sender's
+/// write secret, using the STREAM construction to derive a nonce for every chunk. The final chunk
+/// carries the sender's signature over a hash of every chunk.
+pub struct ApplicationStreamSealer {
+    header: ApplicationStreamHeader,
+    cs: &'static CipherSuite,
+    key: AeadKey,
+    nonces: StreamNonceSequence,
+    content_hash: HashContext,
+}
+
+/// Decrypts a stream made by an `ApplicationStreamSealer`. Until `finish` succeeds, the chunks
+/// returned by `open_chunk` are only known to come from _some_ member of the group, in order. It's
+/// `finish` that checks the sender's signature.
+pub struct ApplicationStreamOpener {
+    header: ApplicationStreamHeader,
+    cs: &'static CipherSuite,
+    key: AeadKey,
+    nonces: StreamNonceSequence,
+    content_hash: HashContext,
+}
+
+/// Starts encrypting a stream under the sender's current `WriteSecret` in this application key
+/// chain. The header has to reach the receivers before any of the chunks do.
+///
+/// Returns: `Ok((header, sealer))` on success. Otherwise, if one of myriad things goes wrong,
+/// returns some sort of `Error`.
+pub fn begin_application_stream(
+    group_state: &GroupState,
+    app_key_chain: &mut ApplicationKeyChain,
+) -> Result<(ApplicationStreamHeader, ApplicationStreamSealer), Error> {
+    // Check that this key chain really does belong to this group_state
+    app_key_chain.validate_against_group_state(group_state)?;
+    let cs = group_state.cs;
+
+    let my_roster_idx = group_state
+        .roster_index
+        .ok_or(Error::ValidationError("Cannot encrypt a stream with a preliminary GroupState"))?;
+    let (key, base_nonce, generation) =
+        app_key_chain.get_key_nonce_bytes_gen(my_roster_idx as usize)?;
+    let nonces = StreamNonceSequence::new(cs.aead_impl, base_nonce.as_bytes())?;
+
+    // The stream gets this generation all to itself
+    app_key_chain.ratchet(my_roster_idx as usize)?;
+
+    let header = ApplicationStreamHeader {
+        group_id: group_state.group_id.clone(),
+        epoch: app_key_chain.group_epoch_at_creation,
+        generation,
+        sender: my_roster_idx,
+    };
+    let sealer = ApplicationStreamSealer {
+        header: header.clone(),
+        cs,
+        key,
+        nonces,
+        content_hash: cs.hash_impl.new_context(),
+    };
+
+    Ok((header, sealer))
+}
+
+impl ApplicationStreamSealer {
+    /// Encrypts the next chunk of the stream. Chunks can be any size, and don't all have to be the
+    /// same size.
+    ///
+    /// Returns: `Ok(ciphertext)` on success. If the stream has run out of chunks, or encryption
+    /// fails, returns an `Error::EncryptionError`.
+    pub fn seal_chunk(&mut self, mut chunk: Vec<u8>) -> Result<Vec<u8>, Error> {
+        self.content_hash.feed_bytes(&chunk);
+
+        // Make room for the tag and encrypt
+        chunk.extend(vec![0u8; self.cs.aead_impl.tag_size()]);
+        let nonce = self.nonces.next_nonce(false)?;
+        self.cs.aead_impl.seal(&self.key, nonce, &mut chunk)?;
+
+        Ok(chunk)
+    }
+
+    /// Encrypts the final chunk of the stream, along with the sender's signature over the whole
+    /// stream
+    ///
+    /// Requires: `group_state` is the `GroupState` that started this stream
+    ///
+    /// Returns: `Ok(ciphertext)` on success. Otherwise, if one of myriad things goes wrong,
+    /// returns some sort of `Error`.
+    // Like encrypt_application_message, this takes a GroupState so we don't have to keep a copy
+    // of the identity key around
+    pub fn finish(
+        mut self,
+        last_chunk: Vec<u8>,
+        group_state: &GroupState,
+    ) -> Result<Vec<u8>, Error> {
+        if group_state.group_id != self.header.group_id
+            || group_state.roster_index != Some(self.header.sender)
+        {
+            return Err(Error::ValidationError("Stream was not started by this group state"));
+        }
+
+        // Sign the label, header, and hash of all the chunks
+        self.content_hash.feed_bytes(&last_chunk);
+        let content_hash = self.content_hash.finalize();
+        let signature_content = StreamSignatureContent {
+            label: STREAM_SIGNATURE_LABEL,
+            header: &self.header,
+            content_hash: content_hash.as_bytes(),
+        };
+        let hashed_signature_content = self.cs.hash_impl.hash_serializable(&signature_content)?;
+        let ss = group_state.get_signature_scheme();
+        let sig = ss.sign(&group_state.identity_key, hashed_signature_content.as_bytes())?;
+
+        // Pack the last chunk and signature together and encrypt it as the final chunk
+        let message_content = ApplicationMessageContent {
+            content: last_chunk,
+            signature: sig.as_bytes(),
+        };
+        let mut serialized_message_content = tls_ser::serialize_to_bytes(&message_content)?;
+        serialized_message_content.extend(vec![0u8; self.cs.aead_impl.tag_size()]);
+        let nonce = self.nonces.next_nonce(true)?;
+        self.cs.aead_impl.seal(&self.key, nonce, &mut serialized_message_content)?;
+
+        Ok(serialized_message_content)
+    }
+}
+
+/// Starts decrypting the stream with the given header, using the sender's current `WriteSecret`
+/// in this application key chain
+///
+/// Returns: `Ok(opener)` on success. Otherwise, if one of myriad things goes wrong, returns some
+/// sort of `Error`.
+pub fn open_application_stream(
+    header: &ApplicationStreamHeader,
+    group_state: &GroupState,
+    app_key_chain: &mut ApplicationKeyChain,
+) -> Result<ApplicationStreamOpener, Error> {
+    // Check that this key chain really does belong to this group_state
+    app_key_chain.validate_against_group_state(group_state)?;
+    let cs = group_state.cs;
+
+    // Check that the stream is for this group and key chain. See decrypt_application_message for
+    // why we use group_epoch_at_creation.
+    if header.group_id != group_state.group_id {
+        return Err(Error::ValidationError("Stream's group_id differs from the key chain's"));
+    }
+    if header.epoch != app_key_chain.group_epoch_at_creation {
+        return Err(Error::ValidationError("Stream's epoch differs from the key chain's"));
+    }
+    // Don't ratchet anything for senders who aren't in the group
+    get_sender_credential(&group_state.roster, header.sender)?;
+
+    let (key, base_nonce, generation) =
+        app_key_chain.get_key_nonce_bytes_gen(header.sender as usize)?;
+    if header.generation != generation {
+        return Err(Error::ValidationError("Stream's generation differs from the write secret's"));
+    }
+    let nonces = StreamNonceSequence::new(cs.aead_impl, base_nonce.as_bytes())?;
+
+    // The sender's stream used up this generation
+    app_key_chain.ratchet(header.sender as usize)?;
+
+    Ok(ApplicationStreamOpener {
+        header: header.clone(),
+        cs,
+        key,
+        nonces,
+        content_hash: cs.hash_impl.new_context(),
+    })
+}
+
+impl ApplicationStreamOpener {
+    /// Decrypts the next chunk of the stream. This MUST NOT be called on the final chunk. Use
+    /// `finish` for that.
+    ///
+    /// Returns: `Ok(plaintext)` on success. If the chunk is out of order, was tampered with, or
+    /// is the final chunk, returns an `Error::EncryptionError`.
+    pub fn open_chunk(&mut self, mut chunk: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let nonce = self.nonces.next_nonce(false)?;
+        let plaintext_len = self.cs.aead_impl.open(&self.key, nonce, &mut chunk)?.len();
+        chunk.truncate(plaintext_len);
+
+        self.content_hash.feed_bytes(&chunk);
+        Ok(chunk)
+    }
+
+    /// Decrypts the final chunk of the stream and checks the sender's signature over the whole
+    /// stream
+    ///
+    /// Returns: `Ok(plaintext)` on success. If the chunk isn't the final one or was tampered with,
+    /// returns an `Error::EncryptionError`. If the signature doesn't verify, returns an
+    /// `Error::SignatureError`. Otherwise, if one of myriad things goes wrong, returns some sort
+    /// of `Error`.
+    pub fn finish(
+        mut self,
+        mut last_chunk: Vec<u8>,
+        group_state: &GroupState,
+    ) -> Result<Vec<u8>, Error> {
+        let nonce = self.nonces.next_nonce(true)?;
+        let serialized_message_content =
+            self.cs.aead_impl.open(&self.key, nonce, &mut last_chunk)?;
+        let message_content =
+            deserialize_message_content(serialized_message_content, group_state.decode_limits)?;
+
+        // Check the signature over the label, header, and hash of all the chunks
+        let sender_credential = get_sender_credential(&group_state.roster, self.header.sender)?;
+        let sender_ss = sender_credential.get_signature_scheme();
+        let signature = Signature::new_from_bytes(sender_ss, &message_content.signature)?;
+
+        self.content_hash.feed_bytes(&message_content.content);
+        let content_hash = self.content_hash.finalize();
+        let signature_content = StreamSignatureContent {
+            label: STREAM_SIGNATURE_LABEL,
+            header: &self.header,
+            content_hash: content_hash.as_bytes(),
+        };
+        let hashed_signature_content = self.cs.hash_impl.hash_serializable(&signature_content)?;
+        sender_ss.verify(
+            sender_credential.get_public_key(),
+            hashed_signature_content.as_bytes(),
+            &signature,
+        )?;
+
+        Ok(message_content.content)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
         application::{
-            decrypt_application_message, encrypt_application_message, ApplicationKeyChain,
+            begin_application_stream, decrypt_application_message, encrypt_application_message,
+            open_application_stream, ApplicationKeyChain, PaddingScheme,
         },
         crypto::{
             aead::{AeadKey, AeadNonce},
-            ciphersuite::X25519_SHA256_AES128GCM,
             hmac::HmacKey,
             rng::CryptoRng,
         },
+        error::Error,
+        framing::{ContentType, MessageSizeLimits, PrivateMessage},
         group_state::GroupState,
         ratchet_tree::PathSecret,
+        secret_tree::OutOfOrderPolicy,
         test_utils,
-        tls_de::TlsDeserializer,
     };
 
+    use std::sync::Arc;
+
     use quickcheck_macros::quickcheck;
-    use rand::{self, SeedableRng};
-    use serde::de::Deserialize;
+    use rand::{self, seq::SliceRandom, Rng, RngCore, SeedableRng};
 
     // Does an update operation on the two given groups and returns the resulting key chains
     fn do_update_op<R: CryptoRng>(
@@ -496,162 +926,89 @@ mod test {
         assert_eq!(plaintext, orig_msg);
     }
 
-    // The following test vector is from
-    // https://github.com/mlswg/mls-implementations/tree/68d1cf562d6e489c3025a4b6d0e4e18725674349/test_vectors
-    //
-    // File: app_key_schedule.bin
-    //
-    // struct {
-    //   opaque secret<0..255>;
-    //   opaque key<0..255>;
-    //   opaque nonce<0..255>;
-    // } AppKeyStep;
-    //
-    // AppKeyScheduleStep AppKeySequence<0..2^32-1>;
-    // KeySequence AppKeyScheduleCase<0..2^32-1>;
-    //
-    // struct {
-    //   uint32_t n_members;
-    //   uint32_t n_generations;
-    //   opaque application_secret<0..255>;
-    //
-    //   AppKeyScheduleCase case_p256;
-    //   AppKeyScheduleCase case_x25519;
-    // } AppKeyScheduleTestVectors;
-    //
-    // For each ciphersuite, the AppKeyScheduleTestVectors struct provides an AppKeyScheduleCase
-    // that describes the outputs of the MLS application key schedule, for each participant in a
-    // group over several generations.
-    //
-    // * The n_members field specifies the number of members in the group. Each AppKeyScheduleCase
-    //   vector should have this many entries. The entry case[j] represents the vector of
-    //   application keys for participant j.
-    // * The n_generations field specifies the number of generations of application keys that are
-    //   generated per participant. Each vector case[j] should have this many entries. The entry
-    //   case[j][k] represents the values at generation k for participant j.
-    // * The application_secret field represents the root application secret for this epoch (the
-    //   one derived from the epoch_secret).
-    // * For a given participant and generation, the AppKeyStep, the fields in the AppKeyStep
-    //   object represent the following values:
-    //   * secret represents application_secret_[j]_[k]
-    //   * key represents write_key_[j]_[k]
-    //   * nonce represents write_nonce_[j]_[k]
-    //
-    // Given the inputs as described above, your implementation should replicate the outputs of the
-    // key schedule for each participant and generation.
-
-    #[derive(Debug, Deserialize)]
-    struct AppKeyStep {
-        #[serde(rename = "secret__bound_u8")]
-        secret: Vec<u8>,
-        #[serde(rename = "key__bound_u8")]
-        key: Vec<u8>,
-        #[serde(rename = "nonce__bound_u8")]
-        nonce: Vec<u8>,
+    #[derive(serde::Deserialize)]
+    struct AppKeyGeneration {
+        generation: u32,
+        key: String,
+        nonce: String,
     }
 
-    #[derive(Debug, Deserialize)]
-    #[serde(rename = "AppKeySequence__bound_u32")]
-    struct AppKeySequence(Vec<AppKeyStep>);
-
-    #[derive(Debug, Deserialize)]
-    #[serde(rename = "AppKeyScheduleCase__bound_u32")]
-    struct AppKeyScheduleCase(Vec<AppKeySequence>);
-
-    #[derive(Debug, Deserialize)]
-    struct AppKeyScheduleVectors {
-        num_members: u32,
-        num_generations: u32,
-        #[serde(rename = "application_secret__bound_u8")]
-        application_secret: Vec<u8>,
-        case_p256: AppKeyScheduleCase,
-        case_x25519: AppKeyScheduleCase,
+    #[derive(serde::Deserialize)]
+    struct AppKeyScheduleVector {
+        cipher_suite: u16,
+        application_secret: String,
+        sender: u32,
+        generations: Vec<AppKeyGeneration>,
     }
 
+    // Tests the key chain against test_vectors/application-key-schedule.json. Those vectors are
+    // made by test_vectors/generate.py, which derives this chain's write secrets and keys straight
+    // from the definitions above, independently of this crate. Each vector has the keys and
+    // nonces of the first few generations of one sender.
     #[test]
     fn application_key_schedule_kat() {
+        let f = std::fs::File::open("test_vectors/application-key-schedule.json").unwrap();
+        let vectors: Vec<AppKeyScheduleVector> = serde_json::from_reader(f).unwrap();
+
         // There's no need to make this a quickcheck test, since it's pretty much impossible for
         // this to succeed due to random chance. But still, we need an rng for some of these ops,
         // and the test should still be deterministic, so just seed it with 0.
         let mut rng = rand::rngs::StdRng::seed_from_u64(0);
 
-        // Deserialize the test vectors (no need to upcast, there's nothing but vectors here)
-        let mut f = std::fs::File::open("test_vectors/app_key_schedule.bin").unwrap();
-        let mut deserializer = TlsDeserializer::from_reader(&mut f);
-        let test_vecs = AppKeyScheduleVectors::deserialize(&mut deserializer).unwrap();
-
-        // These values hold for all test vectors
-        let num_members = test_vecs.num_members as usize;
-        let app_secret = HmacKey::new_from_bytes(&test_vecs.application_secret).into();
-
-        // Test the X25519 case
-        // TODO: Test P256 when it's ready
-        let case = test_vecs.case_x25519;
-
-        // The ciphersuite and signature scheme for this test case
-        let cs = &X25519_SHA256_AES128GCM;
-
-        // Make a dummy GroupState, just so we can pass it to
-        // ApplicationKeyChain::from_application_secret. The only thing that matters is that the
-        // length of the roster and number of leaves in the tree is at least num_members
-        let (dummy_group_state, _) =
-            test_utils::random_full_group_state(test_vecs.num_members, &mut rng);
-        // Finally make the application key chain with the given application secret and correct
-        // number of members
-        let mut app_key_chain =
-            ApplicationKeyChain::from_application_secret(&dummy_group_state, app_secret);
-
-        // The element at index i of this vector is a sequence of write_secrets belonging to member
-        // i of the group. The sequence goes in generational order, starting at 0.
-        let member_key_sequences = case.0;
-        // Check that the number of members is what the test vector says it is
-        assert_eq!(member_key_sequences.len(), num_members);
-
-        // Go through each member in the group
-        for (roster_idx, member_key_seq) in member_key_sequences.into_iter().enumerate() {
-            // Check that the number of generations we're checking is  what the test vector says
-            assert_eq!(member_key_seq.0.len(), test_vecs.num_generations as usize);
-
-            // Go through each generation of this members write_secret/write_key/write_nonce
-            for key_step in member_key_seq.0.into_iter() {
-                // We don't test write_secret directly, because we don't actually expose that
-                // anywhere. Instead, we test the key and nonce values. This ought to be enough
-                // because the key and nonce are derived from the write_secret.
-                let given_key = AeadKey::new_from_bytes(cs.aead_impl, &key_step.key).unwrap();
-                let given_nonce = AeadNonce::new_from_bytes(cs.aead_impl, &key_step.nonce).unwrap();
-
-                // Ok so we don't actually test equality of keys or nonces, because I've wrapped
-                // them in a bunch of opaque types. So let's do a sample encryption/decryption
-                // instead. That should be enough, right? Right?!
-
+        let mut num_checked = 0;
+        for vector in vectors {
+            // Make a dummy GroupState, just so we can pass it to
+            // ApplicationKeyChain::from_application_secret. The only things that matter are its
+            // cipher suite, which has to be the vector's, and that the sender is in the roster.
+            let (dummy_group_state, _) =
+                test_utils::random_full_group_state(vector.sender + 1, &mut rng);
+            let cs = dummy_group_state.cs;
+            if cs.id() != vector.cipher_suite {
+                continue;
+            }
+            let app_secret =
+                HmacKey::new_from_bytes(&hex::decode(&vector.application_secret).unwrap()).into();
+            let mut app_key_chain =
+                ApplicationKeyChain::from_application_secret(&dummy_group_state, app_secret);
+            let sender = vector.sender as usize;
+
+            for g in vector.generations.iter() {
+                let given_key =
+                    AeadKey::new_from_bytes(cs.aead_impl, &hex::decode(&g.key).unwrap()).unwrap();
+                let given_nonce =
+                    AeadNonce::new_from_bytes(cs.aead_impl, &hex::decode(&g.nonce).unwrap())
+                        .unwrap();
+
+                // We can't compare keys or nonces directly, since they're opaque types. So encrypt
+                // with the given key/nonce and decrypt with the derived ones instead.
                 let orig_msg = b"No future, no future, no future for you";
-
-                // The given key/nonce will be used to encrypt
                 let mut ciphertext = {
                     // Make room for the tag
                     let mut plaintext = orig_msg.to_vec();
                     plaintext.extend(vec![0u8; cs.aead_impl.tag_size()]);
 
-                    // Encrypt the thing in-place and return the mutated plaintext
                     cs.aead_impl.seal(&given_key, given_nonce, &mut plaintext).unwrap();
                     plaintext
                 };
 
-                // We'll use the derived key/nonce will decrypt it. The unwrap() inside this block
-                // should fail if the key/nonce aren't the ones used above.
+                // The unwrap() inside this block should fail if the key/nonce aren't the ones used
+                // above
                 let plaintext = {
-                    let (derived_key, derived_nonce, _) =
-                        app_key_chain.get_key_nonce_gen(roster_idx).unwrap();
+                    let (derived_key, derived_nonce, derived_gen) =
+                        app_key_chain.get_key_nonce_gen(sender).unwrap();
+                    assert_eq!(derived_gen, g.generation);
                     cs.aead_impl.open(&derived_key, derived_nonce, &mut ciphertext).unwrap()
                 };
-
-                // Make sure the decrypted ciphertext is equal to the original message
                 assert_eq!(plaintext, &orig_msg[..]);
 
                 // Ratchet forward this member's secrets
-                app_key_chain.ratchet(roster_idx).unwrap();
+                app_key_chain.ratchet(sender).unwrap();
             }
+
+            num_checked += 1;
         }
+
+        assert!(num_checked > 0);
     }
 
     //
@@ -827,4 +1184,507 @@ mod test {
         )
         .is_err());
     }
+
+    // Checks that a stream of chunks decrypts to what went in, and that reordering, truncating,
+    // or tampering with the stream is caught
+    #[quickcheck]
+    fn application_stream_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Make two perspectives of the same group
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let new_roster_idx = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 =
+            test_utils::change_self_index(&group_state1, &identity_keys, new_roster_idx);
+
+        // Key chains made from the same application secret are identical. We need a few fresh
+        // ones for the receiver, since opening a stream ratchets the key chain.
+        let app_secret_bytes = {
+            let mut buf = vec![0u8; group_state1.cs.hash_impl.digest_size()];
+            rng.fill_bytes(&mut buf);
+            buf
+        };
+        let new_key_chain = |group_state: &GroupState| {
+            let app_secret = HmacKey::new_from_bytes(&app_secret_bytes).into();
+            ApplicationKeyChain::from_application_secret(group_state, app_secret)
+        };
+        let mut app_key_chain1 = new_key_chain(&group_state1);
+
+        // Make a stream out of a few chunks of differing sizes
+        let chunks: Vec<Vec<u8>> = vec![vec![1u8; 1000], vec![], vec![2u8; 17], vec![3u8; 5]];
+        let (header, mut sealer) =
+            begin_application_stream(&group_state1, &mut app_key_chain1).unwrap();
+        let mut sealed: Vec<Vec<u8>> =
+            chunks[..3].iter().map(|c| sealer.seal_chunk(c.clone()).unwrap()).collect();
+        sealed.push(sealer.finish(chunks[3].clone(), &group_state1).unwrap());
+
+        // The stream used up a generation, so a regular message works afterwards
+        let app_message =
+            encrypt_application_message(b"hi".to_vec(), &group_state1, &mut app_key_chain1)
+                .unwrap();
+
+        // Open the stream in order
+        let mut app_key_chain2 = new_key_chain(&group_state2);
+        let mut opener =
+            open_application_stream(&header, &group_state2, &mut app_key_chain2).unwrap();
+        for (chunk, sealed_chunk) in chunks[..3].iter().zip(sealed.iter()) {
+            assert_eq!(&opener.open_chunk(sealed_chunk.clone()).unwrap(), chunk);
+        }
+        assert_eq!(opener.finish(sealed[3].clone(), &group_state2).unwrap(), chunks[3]);
+        let plaintext =
+            decrypt_application_message(app_message, &group_state2, &mut app_key_chain2).unwrap();
+        assert_eq!(plaintext, b"hi");
+
+        // Swapping two chunks should fail
+        let mut opener =
+            open_application_stream(&header, &group_state2, &mut new_key_chain(&group_state2))
+                .unwrap();
+        assert!(opener.open_chunk(sealed[1].clone()).is_err());
+
+        // Truncating the stream should fail, both by ending it early and by passing the final
+        // chunk off as a regular one
+        let mut opener =
+            open_application_stream(&header, &group_state2, &mut new_key_chain(&group_state2))
+                .unwrap();
+        opener.open_chunk(sealed[0].clone()).unwrap();
+        assert!(opener.finish(sealed[1].clone(), &group_state2).is_err());
+        let mut opener =
+            open_application_stream(&header, &group_state2, &mut new_key_chain(&group_state2))
+                .unwrap();
+        for sealed_chunk in sealed[..3].iter() {
+            opener.open_chunk(sealed_chunk.clone()).unwrap();
+        }
+        assert!(opener.open_chunk(sealed[3].clone()).is_err());
+
+        // Tampering with a chunk should fail
+        let mut tampered = sealed[2].clone();
+        tampered[0] ^= 1;
+        let mut opener =
+            open_application_stream(&header, &group_state2, &mut new_key_chain(&group_state2))
+                .unwrap();
+        opener.open_chunk(sealed[0].clone()).unwrap();
+        opener.open_chunk(sealed[1].clone()).unwrap();
+        assert!(opener.open_chunk(tampered).is_err());
+    }
+
+    // Once a member is removed, the rest of the group shouldn't accept their old-epoch Handshakes,
+    // or application messages that claim to be from them in the new epoch
+    #[quickcheck]
+    fn removed_sender_rejected(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        // Make a group of at least 3 people, and copies of it from two other perspectives: the
+        // member who'll be removed, and a member who stays
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let index1 = group_state1.roster_index.unwrap() as usize;
+        let removed_index = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[index1],
+            &mut rng,
+        );
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[index1, removed_index as usize],
+            &mut rng,
+        );
+        let removed_group_state =
+            test_utils::change_self_index(&group_state1, &identity_keys, removed_index);
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+
+        // Remove the member
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (remove_handshake, group_state1, mut app_key_chain1) = group_state1
+            .create_and_apply_remove_handshake(removed_index, new_path_secret, &mut rng)
+            .unwrap();
+        let (group_state2, mut app_key_chain2) =
+            group_state2.process_handshake(&remove_handshake).unwrap();
+
+        // The removed member is still in the old epoch. Anything they do there is stale.
+        let new_path_secret = PathSecret::new_from_random(removed_group_state.cs, &mut rng);
+        let (stale_handshake, _, _) = removed_group_state
+            .create_and_apply_update_handshake(new_path_secret, &mut rng)
+            .unwrap();
+        assert!(group_state2.process_handshake(&stale_handshake).is_err());
+
+        // A message in the new epoch that claims to be from the removed member should be rejected
+        let mut app_message =
+            encrypt_application_message(b"hello".to_vec(), &group_state1, &mut app_key_chain1)
+                .unwrap();
+        app_message.sender = removed_index;
+        assert!(
+            decrypt_application_message(app_message, &group_state2, &mut app_key_chain2).is_err()
+        );
+    }
+
+    // Check that messages encrypted under the group's secret tree decrypt for another member of
+    // the same epoch, in order, and only once, and that they don't decrypt in any other epoch
+    #[quickcheck]
+    fn secret_tree_messages(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+
+        // There's no secret tree until the first epoch change
+        assert!(group_state1.encrypt_application_message(b"too early").is_err());
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+
+        let messages: Vec<PrivateMessage> =
+            (0u8..3).map(|i| group_state1.encrypt_application_message(&[i; 10]).unwrap()).collect();
+        for (i, message) in messages.iter().enumerate() {
+            let plaintext = group_state2.decrypt_application_message(message.clone()).unwrap();
+            assert_eq!(plaintext, vec![i as u8; 10]);
+        }
+
+        // A message can't be decrypted twice, and a tampered one can't be decrypted at all
+        match group_state2.decrypt_application_message(messages[0].clone()) {
+            Err(Error::ReplayedMessage(_, sender, 0)) => {
+                assert_eq!(sender, group_state1.roster_index.unwrap())
+            }
+            _ => panic!("replayed message was not reported as a replay"),
+        }
+        let mut tampered = group_state1.encrypt_application_message(b"tampered").unwrap();
+        tampered.ciphertext[0] ^= 1;
+        assert!(group_state2.decrypt_application_message(tampered).is_err());
+
+        // Nor can one from an epoch whose secrets are gone
+        let old_message = group_state1.encrypt_application_message(b"old news").unwrap();
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+        match group_state2.decrypt_application_message(old_message) {
+            Err(Error::StaleEpoch(..)) => (),
+            _ => panic!("message from an earlier epoch was not reported as stale"),
+        }
+    }
+
+    // Check that messages sent just before a commit still decrypt after it, for as many epochs as
+    // past epochs' secrets are kept, and only once
+    #[quickcheck]
+    fn past_epoch_messages(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        group_state2.set_past_epoch_retention(2);
+        group_state2.set_replay_retention(2);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+
+        // Two messages are in flight across two commits
+        let in_flight1 = group_state1.encrypt_application_message(b"in flight").unwrap();
+        let in_flight2 = group_state1.encrypt_application_message(b"in flight too").unwrap();
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+        let current = group_state1.encrypt_application_message(b"current").unwrap();
+
+        assert_eq!(group_state2.decrypt_application_message(current).unwrap(), b"current");
+        let plaintext = group_state2.decrypt_application_message(in_flight2).unwrap();
+        assert_eq!(plaintext, b"in flight too");
+        match group_state2.decrypt_application_message(in_flight1.clone()) {
+            Ok(plaintext) => assert_eq!(plaintext, b"in flight"),
+            Err(e) => panic!("message from a kept epoch didn't decrypt: {:?}", e),
+        }
+        match group_state2.decrypt_application_message(in_flight1.clone()) {
+            Err(Error::ReplayedMessage(..)) => (),
+            _ => panic!("replayed message from a past epoch was not reported as a replay"),
+        }
+
+        // Narrowing the retention deletes the secrets outside of it right away
+        let in_flight = group_state1.encrypt_application_message(b"too late").unwrap();
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+        group_state2.set_past_epoch_retention(0);
+        match group_state2.decrypt_application_message(in_flight) {
+            Err(Error::StaleEpoch(..)) => (),
+            _ => panic!("message from a deleted epoch was not reported as stale"),
+        }
+    }
+
+    // Check that authenticated data comes out of decryption exactly as it went in, whether it's
+    // sent along with the message or out of band, and that changing it breaks decryption
+    #[quickcheck]
+    fn authenticated_data(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+
+        // Sent along with the message
+        let message =
+            group_state1.encrypt_application_message_with_aad(b"hello", b"conversation 1").unwrap();
+        assert_eq!(message.authenticated_data(), b"conversation 1");
+        let mut tampered = message.clone();
+        tampered.set_authenticated_data(b"conversation 2".to_vec());
+        assert!(group_state2.decrypt_application_message_with_aad(tampered).is_err());
+        let (plaintext, aad) = group_state2.decrypt_application_message_with_aad(message).unwrap();
+        assert_eq!((plaintext.as_slice(), aad.as_slice()), (&b"hello"[..], &b"conversation 1"[..]));
+
+        // Sent out of band. Leaving it out breaks decryption, and putting it back fixes it.
+        let mut message =
+            group_state1.encrypt_application_message_with_aad(b"bye", b"server metadata").unwrap();
+        let aad = message.take_authenticated_data();
+        assert!(message.authenticated_data().is_empty());
+        assert!(group_state2.decrypt_application_message(message.clone()).is_err());
+        message.set_authenticated_data(aad);
+        assert_eq!(group_state2.decrypt_application_message(message).unwrap(), b"bye");
+    }
+
+    // Check that a processed message says who sent it, under which credential, and in which
+    // epoch, along with its authenticated data, including when it was kept for a later epoch
+    #[quickcheck]
+    fn processed_message_attribution(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+        let index1 = group_state1.roster_index.unwrap();
+        let credential1 = group_state1.roster.0[index1 as usize].clone().unwrap();
+
+        let message = group_state1.encrypt_application_message_with_aad(b"hello", b"aad").unwrap();
+        let processed = group_state2.process_application_message(message).unwrap();
+        assert_eq!(processed.sender(), index1);
+        assert_eq!(processed.credential(), &credential1);
+        assert_eq!(processed.epoch(), group_state1.epoch);
+        assert_eq!(processed.content_type(), ContentType::Application);
+        assert_eq!(processed.authenticated_data(), b"aad");
+        assert_eq!(processed.into_plaintext(), b"hello");
+
+        // A message that was kept for the next epoch is attributed just the same
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake, mut new_group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let message =
+            new_group_state1.encrypt_application_message_with_aad(b"later", b"more aad").unwrap();
+        assert!(group_state2.process_application_message(message).is_err());
+        let (mut new_group_state2, _) = group_state2.process_handshake(&handshake).unwrap();
+        let buffered = new_group_state2.take_buffered_messages();
+        assert_eq!(buffered.len(), 1);
+        assert_eq!(buffered[0].sender(), index1);
+        assert_eq!(buffered[0].epoch(), new_group_state1.epoch);
+        assert_eq!(buffered[0].authenticated_data(), b"more aad");
+        assert_eq!(buffered[0].plaintext(), b"later");
+    }
+
+    // Check that messages that are too big are refused on both ends, and that a payload that's too
+    // big for one message goes through in fragments
+    #[quickcheck]
+    fn size_limits_and_fragments(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+
+        let limits = MessageSizeLimits::new(256, 1024, 4096);
+        group_state1.set_message_size_limits(limits);
+        match group_state1.encrypt_application_message(&[0u8; 257]) {
+            Err(Error::MessageTooLarge(257, 256)) => (),
+            _ => panic!("plaintext over the limit was encrypted"),
+        }
+
+        // The recipient has a lower ciphertext limit than the sender's message
+        let message = group_state1.encrypt_application_message(&[0u8; 256]).unwrap();
+        group_state2.set_message_size_limits(MessageSizeLimits::new(256, 128, 4096));
+        match group_state2.decrypt_application_message(message.clone()) {
+            Err(Error::MessageTooLarge(..)) => (),
+            _ => panic!("ciphertext over the limit was decrypted"),
+        }
+        group_state2.set_message_size_limits(limits);
+        group_state2.decrypt_application_message(message).unwrap();
+
+        // A payload 8 times the plaintext limit makes it through in fragments, in any order
+        let mut payload = vec![0u8; 2048];
+        rng.fill_bytes(&mut payload);
+        assert!(group_state1.encrypt_application_message(&payload).is_err());
+        let mut messages = group_state1.encrypt_fragmented(&payload, 150).unwrap();
+        assert_eq!(messages.len(), 14);
+        messages.shuffle(&mut rng);
+        let last = messages.pop().unwrap();
+        for message in messages {
+            assert!(group_state2.decrypt_fragment(message).unwrap().is_none());
+        }
+        assert_eq!(group_state2.decrypt_fragment(last).unwrap().unwrap(), payload);
+
+        // But not past the reassembly limit
+        match group_state1.encrypt_fragmented(&[0u8; 4097], 150) {
+            Err(Error::MessageTooLarge(4097, 4096)) => (),
+            _ => panic!("payload over the limit was fragmented"),
+        }
+    }
+
+    // Check that messages from the next epoch that arrive before the commit that leads there are
+    // kept, as far as the limit allows, and decrypted once the commit is processed. Messages from
+    // further ahead aren't kept.
+    #[quickcheck]
+    fn future_epoch_messages(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+
+        // The first member moves on to the next epoch and starts talking before the second member
+        // has seen the commit
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (handshake, mut new_group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let messages: Vec<PrivateMessage> = (0u8..3)
+            .map(|i| new_group_state1.encrypt_application_message(&[i; 10]).unwrap())
+            .collect();
+
+        // A message from two epochs ahead isn't kept, even though there's room
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (_, mut newer_group_state1, _) =
+            new_group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let too_new = newer_group_state1.encrypt_application_message(&[0xff; 10]).unwrap();
+        match group_state2.decrypt_application_message(too_new) {
+            Err(Error::FutureEpoch(..)) => (),
+            _ => panic!("message from two epochs ahead was kept"),
+        }
+
+        group_state2.set_future_message_limit(2);
+        for message in messages[..2].iter() {
+            match group_state2.decrypt_application_message(message.clone()) {
+                Err(Error::MessageBuffered(..)) => (),
+                _ => panic!("message from the next epoch wasn't kept"),
+            }
+        }
+        match group_state2.decrypt_application_message(messages[2].clone()) {
+            Err(Error::FutureEpoch(..)) => (),
+            _ => panic!("message was kept beyond the limit"),
+        }
+
+        // The kept messages decrypt along with the commit, and the other one can be decrypted now
+        let (mut new_group_state2, _) = group_state2.process_handshake(&handshake).unwrap();
+        assert_eq!(new_group_state2.take_buffered_plaintexts(), vec![vec![0u8; 10], vec![1u8; 10]]);
+        assert!(new_group_state2.take_buffered_plaintexts().is_empty());
+        let plaintext = new_group_state2.decrypt_application_message(messages[2].clone()).unwrap();
+        assert_eq!(plaintext, vec![2u8; 10]);
+        match new_group_state2.decrypt_application_message(messages[0].clone()) {
+            Err(Error::ReplayedMessage(..)) => (),
+            _ => panic!("kept message was decrypted twice"),
+        }
+    }
+
+    // Check that messages that arrive out of order decrypt, as far as the group's policy allows
+    #[quickcheck]
+    fn out_of_order_messages(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+
+        // Shuffled messages all decrypt under the default policy, each of them once
+        let mut messages: Vec<(u8, PrivateMessage)> = (0u8..8)
+            .map(|i| (i, group_state1.encrypt_application_message(&[i; 10]).unwrap()))
+            .collect();
+        messages.shuffle(&mut rng);
+        for (i, message) in messages.iter() {
+            let plaintext = group_state2.decrypt_application_message(message.clone()).unwrap();
+            assert_eq!(plaintext, vec![*i; 10]);
+        }
+        for (_, message) in messages.iter() {
+            assert!(group_state2.decrypt_application_message(message.clone()).is_err());
+        }
+
+        // Under a strict policy, a message that skips ahead is rejected, and the one it skipped
+        // over still decrypts
+        group_state2.set_out_of_order_policy(OutOfOrderPolicy::in_order());
+        let skipped = group_state1.encrypt_application_message(b"skipped").unwrap();
+        let ahead = group_state1.encrypt_application_message(b"ahead").unwrap();
+        assert!(group_state2.decrypt_application_message(ahead.clone()).is_err());
+        assert_eq!(group_state2.decrypt_application_message(skipped).unwrap(), b"skipped");
+        assert_eq!(group_state2.decrypt_application_message(ahead).unwrap(), b"ahead");
+    }
+
+    // Check that every padding scheme pads to the length it says, and that padding is stripped on
+    // decryption no matter what scheme the recipient uses
+    #[quickcheck]
+    fn padding_schemes(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        assert!(PaddingScheme::Block(0).padded_len(1).is_err());
+        assert_eq!(PaddingScheme::Block(16).padded_len(16).unwrap(), 16);
+        assert_eq!(PaddingScheme::Block(16).padded_len(17).unwrap(), 32);
+        assert_eq!(PaddingScheme::PowerOfTwo.padded_len(17).unwrap(), 32);
+        assert!(PaddingScheme::PowerOfTwo.padded_len(usize::MAX).is_err());
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        do_update_op(&mut group_state1, &mut group_state2, &mut rng);
+        group_state2.set_padding_scheme(PaddingScheme::Block(7));
+
+        let tag_size = group_state1.cs.aead_impl.tag_size();
+        let schemes = vec![
+            PaddingScheme::None,
+            PaddingScheme::Block(64),
+            PaddingScheme::PowerOfTwo,
+            PaddingScheme::Custom(Arc::new(|len| len + 1000 - len % 1000)),
+        ];
+        for scheme in schemes {
+            group_state1.set_padding_scheme(scheme.clone());
+            let mut plaintext = vec![0u8; rng.gen_range(0, 300)];
+            rng.fill_bytes(&mut plaintext);
+
+            let message = group_state1.encrypt_application_message(&plaintext).unwrap();
+            let padded_len = message.ciphertext.len() - tag_size;
+            let is_padded = match scheme {
+                PaddingScheme::None => true,
+                PaddingScheme::Block(block_size) => padded_len % block_size == 0,
+                PaddingScheme::PowerOfTwo => padded_len.is_power_of_two(),
+                PaddingScheme::Custom(_) => padded_len % 1000 == 0,
+            };
+            assert!(is_padded);
+            assert_eq!(group_state2.decrypt_application_message(message).unwrap(), plaintext);
+        }
+
+        // A callback can't cut the message short
+        group_state1.set_padding_scheme(PaddingScheme::Custom(Arc::new(|len| len - 1)));
+        assert!(group_state1.encrypt_application_message(b"too short").is_err());
+    }
 }
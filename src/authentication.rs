@@ -0,0 +1,37 @@
+//! Defines `AuthenticationService`, which is how a `GroupState` asks the deployment's
+//! authentication service about the credentials it encounters, i.e., which signature keys an
+//! identity really has, and whether a credential has been revoked
+
+use crate::{
+    credential::{Credential, Identity},
+    crypto::sig::SigPublicKey,
+};
+
+/// A trait representing the deployment's authentication service. This is consulted whenever a
+/// credential is encountered in an Add or an Update, including the Adds of external joins and the
+/// new credentials of members changing theirs. A handshake with a credential that the service
+/// doesn't vouch for fails with an `Error::CredentialRejected`.
+pub trait AuthenticationService: Send + Sync {
+    /// Returns the signature public keys that `identity` is known to have. A credential for
+    /// `identity` is only accepted if its key is one of them. If the service doesn't know, e.g.,
+    /// because it can't be reached, this returns `None`, and any key is accepted.
+    fn expected_signature_keys(&self, identity: &Identity) -> Option<Vec<SigPublicKey>>;
+
+    /// Returns whether `credential` has been revoked
+    fn is_revoked(&self, credential: &Credential) -> bool;
+}
+
+/// An authentication service that's never reachable, so it vouches for every credential. This is
+/// the `AuthenticationService` that every `GroupState` starts out with.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OfflineAuthenticationService;
+
+impl AuthenticationService for OfflineAuthenticationService {
+    fn expected_signature_keys(&self, _identity: &Identity) -> Option<Vec<SigPublicKey>> {
+        None
+    }
+
+    fn is_revoked(&self, _credential: &Credential) -> bool {
+        false
+    }
+}
@@ -0,0 +1,591 @@
+//! Defines `Client`, which holds every group a member is in, along with the pool of `UserInitKey`s
+//! they've published, and processes whatever `MlsMessage` arrives for any of them. This is the
+//! entry point for applications that would rather not route messages to the right `GroupState`
+//! themselves.
+
+use crate::{
+    application::ApplicationKeyChain,
+    credential::Credential,
+    crypto::{
+        ciphersuite::CipherSuite,
+        provider::{CryptoProvider, DEFAULT_PROVIDER},
+        rng::CryptoRng,
+        sig::SigSecretKey,
+    },
+    error::Error,
+    extensions::{Extension, LAST_RESORT_TYPE},
+    framing::{ContentType, HandshakeMessage, ProcessedMessage},
+    group_id::GroupId,
+    group_state::{GroupInfo, GroupState},
+    handshake::{ProtocolVersion, UserInitKey},
+    message::MlsMessage,
+    tls_de::DecodeLimits,
+    upcast::{CryptoCtx, CryptoUpcast},
+};
+
+use std::collections::HashMap;
+
+/// What processing an `MlsMessage` did
+pub enum ClientEvent {
+    /// This member joined the group with this ID from a `Welcome`
+    Joined(GroupId),
+    /// This member joined the group with this ID from a `Welcome` made with their last-resort
+    /// `UserInitKey`, which can be used this many more times. This means the rest of their
+    /// published `UserInitKey`s ran out, so publish more, and replace the last-resort key with
+    /// `Client::set_last_resort_init_key`.
+    JoinedWithLastResortKey(GroupId, u32),
+    /// The group with this ID changed epochs. This is the application key chain of its new epoch.
+    EpochChanged(GroupId, ApplicationKeyChain),
+    /// This application message was decrypted in the group with this ID. It says who sent it.
+    ApplicationMessage(GroupId, ProcessedMessage),
+    /// This `GroupInfo`'s signature verified. Its group can be joined with
+    /// `GroupState::new_from_external_commit`.
+    GroupInfo(GroupInfo),
+    /// This `UserInitKey`'s signature verified. Its owner can be added to a group with it.
+    KeyPackage(UserInitKey),
+}
+
+/// Every group a member is in, by group ID, and the `UserInitKey`s they can be invited with
+pub struct Client {
+    identity_key: SigSecretKey,
+    /// The `UserInitKey`s this member published, with their private keys, so that `Welcome`s
+    /// encrypted to them can be decrypted. These are keyed by their hash under their first
+    /// cipher suite, as `Client::init_key_hash` computes it. Each of them is forgotten once it's
+    /// used.
+    init_keys: HashMap<Vec<u8>, UserInitKey>,
+    /// The hashes of the `UserInitKey`s that were used up since the application last asked
+    consumed_init_keys: Vec<Vec<u8>>,
+    /// The last-resort `UserInitKey`, with its private keys, its hash, and how many more
+    /// `Welcome`s it can be used for
+    last_resort_init_key: Option<(UserInitKey, Vec<u8>, u32)>,
+    groups: HashMap<GroupId, GroupState>,
+    /// What messages are deserialized with, and what the groups joined from `Welcome`s look up
+    /// algorithms in
+    provider: &'static dyn CryptoProvider,
+}
+
+impl Client {
+    /// Makes a client with no groups, whose member signs with `identity_key`, and which uses the
+    /// default `CryptoProvider`
+    // This is just a wrapper around new_with_provider
+    pub fn new(identity_key: SigSecretKey) -> Client {
+        Client::new_with_provider(identity_key, DEFAULT_PROVIDER)
+    }
+
+    /// Makes a client with no groups, whose member signs with `identity_key`, and which looks up
+    /// cipher suites and signature schemes in `provider`. Groups this member joins from a
+    /// `Welcome` use `provider` too. Groups added with `Client::add_group` keep their own.
+    pub fn new_with_provider(
+        identity_key: SigSecretKey,
+        provider: &'static dyn CryptoProvider,
+    ) -> Client {
+        Client {
+            identity_key,
+            init_keys: HashMap::new(),
+            consumed_init_keys: Vec::new(),
+            last_resort_init_key: None,
+            groups: HashMap::new(),
+            provider,
+        }
+    }
+
+    /// Deserializes a message of any kind, like `MlsMessage::from_bytes`, looking up the cipher
+    /// suites and signature schemes in it in this client's `CryptoProvider`. This is how messages
+    /// under algorithms that only a custom provider implements get to `Client::process`.
+    ///
+    /// Returns: `Ok(message)` on success. Otherwise, returns what
+    /// `MlsMessage::from_bytes_with_provider` does.
+    pub fn decode_message(&self, bytes: &[u8]) -> Result<MlsMessage, Error> {
+        MlsMessage::from_bytes_with_provider(bytes, DecodeLimits::default(), self.provider)
+    }
+
+    /// Keeps `init_key`, so that a `Welcome` encrypted to it can be processed. Publish it as an
+    /// `MlsMessage::KeyPackage`, without its private keys.
+    ///
+    /// Returns: `Ok(())` on success. If `init_key` has no private keys, returns an
+    /// `Error::ValidationError`.
+    pub fn add_init_key(&mut self, init_key: UserInitKey) -> Result<(), Error> {
+        if init_key.private_keys.is_none() {
+            return Err(Error::ValidationError("UserInitKey has no private keys"));
+        }
+        self.init_keys.insert(Client::init_key_hash(&init_key)?, init_key);
+        Ok(())
+    }
+
+    /// Generates `count` fresh `UserInitKey`s for `credential`, whose secret key is this client's
+    /// identity key, with their own init keys and random key IDs, and keeps them like
+    /// `Client::add_init_key` does. Delivery services hand out one `UserInitKey` per invitation,
+    /// so publish plenty of these, and make more as `Client::take_consumed_init_keys` says
+    /// they're used up.
+    ///
+    /// Returns: `Ok(init_keys)` on success, without their private keys, ready to be published as
+    /// `MlsMessage::KeyPackage`s. If `cipher_suites` is empty, returns an
+    /// `Error::ValidationError`. Otherwise, returns whatever making a `UserInitKey` returns.
+    pub fn generate_init_keys<R>(
+        &mut self,
+        count: usize,
+        credential: &Credential,
+        cipher_suites: Vec<&'static CipherSuite>,
+        supported_versions: Vec<ProtocolVersion>,
+        csprng: &mut R,
+    ) -> Result<Vec<UserInitKey>, Error>
+    where
+        R: CryptoRng,
+    {
+        if cipher_suites.is_empty() {
+            return Err(Error::ValidationError("Cannot make UserInitKeys with no cipher suites"));
+        }
+
+        let mut published = Vec::with_capacity(count);
+        for _ in 0..count {
+            // Key IDs have to be unique among this client's UserInitKeys, so make them random
+            let mut user_init_key_id = vec![0u8; 16];
+            csprng.fill_bytes(&mut user_init_key_id);
+            let init_key = UserInitKey::new_from_random(
+                &self.identity_key,
+                user_init_key_id,
+                credential.clone(),
+                cipher_suites.clone(),
+                supported_versions.clone(),
+                csprng,
+            )?;
+
+            let mut public = init_key.clone();
+            public.private_keys = None;
+            published.push(public);
+            self.add_init_key(init_key)?;
+        }
+
+        Ok(published)
+    }
+
+    /// Generates a fresh last-resort `UserInitKey` for `credential`, like
+    /// `Client::generate_init_keys` does, which replaces any last-resort key this client had.
+    /// Unlike the rest, it's kept until `max_uses` `Welcome`s have used it, so publish it for the
+    /// delivery service to hand out once every other `UserInitKey` is used up. Every join with it
+    /// is a `ClientEvent::JoinedWithLastResortKey`. Reusing an init key means that anyone who
+    /// learns its private key can read every `Welcome` made to it, so keep `max_uses` small and
+    /// replace the key soon after it's used.
+    ///
+    /// Returns: `Ok(init_key)` on success, without its private keys. If `max_uses` is 0 or
+    /// `cipher_suites` is empty, returns an `Error::ValidationError`. Otherwise, returns
+    /// whatever making a `UserInitKey` returns.
+    pub fn set_last_resort_init_key<R>(
+        &mut self,
+        max_uses: u32,
+        credential: &Credential,
+        cipher_suites: Vec<&'static CipherSuite>,
+        supported_versions: Vec<ProtocolVersion>,
+        csprng: &mut R,
+    ) -> Result<UserInitKey, Error>
+    where
+        R: CryptoRng,
+    {
+        if max_uses == 0 {
+            return Err(Error::ValidationError("Last-resort UserInitKey has to be usable"));
+        }
+        if cipher_suites.is_empty() {
+            return Err(Error::ValidationError("Cannot make UserInitKeys with no cipher suites"));
+        }
+
+        let mut user_init_key_id = vec![0u8; 16];
+        csprng.fill_bytes(&mut user_init_key_id);
+        let init_key = UserInitKey::new_from_random(
+            &self.identity_key,
+            user_init_key_id,
+            credential.clone(),
+            cipher_suites,
+            supported_versions,
+            csprng,
+        )?
+        .with_extensions(&self.identity_key, vec![Extension::new(LAST_RESORT_TYPE, Vec::new())])?;
+
+        let mut public = init_key.clone();
+        public.private_keys = None;
+        let hash = Client::init_key_hash(&init_key)?;
+        // The old last-resort key is used up, as far as the delivery service is concerned
+        if let Some((_, old_hash, _)) = self.last_resort_init_key.take() {
+            self.consumed_init_keys.push(old_hash);
+        }
+        self.last_resort_init_key = Some((init_key, hash, max_uses));
+
+        Ok(public)
+    }
+
+    /// Returns how many more `Welcome`s the last-resort `UserInitKey` can be used for, if this
+    /// client has one
+    pub fn last_resort_uses_left(&self) -> Option<u32> {
+        self.last_resort_init_key.as_ref().map(|(_, _, uses_left)| *uses_left)
+    }
+
+    /// Returns how many of the `UserInitKey`s this client kept haven't been used yet
+    pub fn unused_init_key_count(&self) -> usize {
+        self.init_keys.len()
+    }
+
+    /// Returns the hashes, as `Client::init_key_hash` computes them, of the `UserInitKey`s that
+    /// `Welcome`s have used up since this was last called, including last-resort keys that ran
+    /// out of uses or were replaced. Their private keys are gone, so have the delivery service
+    /// stop handing them out.
+    pub fn take_consumed_init_keys(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.consumed_init_keys)
+    }
+
+    /// Returns the hash that a `Client` keeps `init_key` under, i.e., its
+    /// `UserInitKey::key_package_hash` under the first cipher suite it offers
+    ///
+    /// Returns: `Ok(hash)` on success. If `init_key` offers no cipher suites, returns an
+    /// `Error::ValidationError`.
+    pub fn init_key_hash(init_key: &UserInitKey) -> Result<Vec<u8>, Error> {
+        let cs = init_key
+            .cipher_suites
+            .first()
+            .ok_or(Error::ValidationError("UserInitKey offers no cipher suites"))?;
+        init_key.key_package_hash(cs)
+    }
+
+    /// Adds a group that this member has a `GroupState` for, e.g., one they started, to the
+    /// groups messages are processed for. This replaces any group with the same ID, and returns
+    /// it.
+    pub fn add_group(&mut self, group_state: GroupState) -> Option<GroupState> {
+        self.groups.insert(group_state.group_id().clone(), group_state)
+    }
+
+    /// Returns the group with the given ID, if this member is in it
+    pub fn group(&self, group_id: &GroupId) -> Option<&GroupState> {
+        self.groups.get(group_id)
+    }
+
+    /// Returns the group with the given ID, if this member is in it, so that handshakes and
+    /// messages can be made in it
+    pub fn group_mut(&mut self, group_id: &GroupId) -> Option<&mut GroupState> {
+        self.groups.get_mut(group_id)
+    }
+
+    /// Stops processing messages for the group with the given ID, and returns it
+    pub fn remove_group(&mut self, group_id: &GroupId) -> Option<GroupState> {
+        self.groups.remove(group_id)
+    }
+
+    /// Processes `message`, whatever kind of message it is. Handshakes and application messages
+    /// go to the group they're for, as `GroupState::process_handshake_message` and
+    /// `GroupState::process_application_message` would process them, and the group moves to its
+    /// new epoch if there is one. A `Welcome` is decrypted with the `UserInitKey` it was made for,
+    /// and the group it's for is added. `GroupInfo`s and `KeyPackage`s are only verified, since
+    /// what to do with them is up to the application.
+    ///
+    /// Returns: `Ok(event)` on success, saying what happened. If the message is for a group this
+    /// member isn't in, or a `Welcome` isn't for any of the kept `UserInitKey`s, or is for a group
+    /// this member is already in, returns an `Error::ValidationError`. If a signature doesn't
+    /// verify, returns an `Error::SignatureError`. Otherwise, returns whatever processing the
+    /// message returns.
+    pub fn process(&mut self, message: MlsMessage) -> Result<ClientEvent, Error> {
+        match message {
+            MlsMessage::PublicMessage(mut public) => {
+                let group_state = self.group_for(public.group_id())?;
+                public.upcast_crypto_values(&CryptoCtx::new().set_cipher_suite(group_state.cs))?;
+                Client::process_handshake_message(group_state, HandshakeMessage::Public(public))
+            }
+            MlsMessage::PrivateMessage(private) => {
+                let group_state = self.group_for(private.group_id())?;
+                match private.content_type() {
+                    ContentType::Handshake => Client::process_handshake_message(
+                        group_state,
+                        HandshakeMessage::Private(private),
+                    ),
+                    ContentType::Application => {
+                        let group_id = group_state.group_id().clone();
+                        let processed = group_state.process_application_message(private)?;
+                        Ok(ClientEvent::ApplicationMessage(group_id, processed))
+                    }
+                }
+            }
+            MlsMessage::Welcome(welcome) => {
+                let key_id = welcome.get_user_init_key_id();
+                let pooled_hash = self
+                    .init_keys
+                    .iter()
+                    .find(|(_, init_key)| init_key.user_init_key_id == key_id)
+                    .map(|(hash, _)| hash.clone());
+                let init_key = match (&pooled_hash, &self.last_resort_init_key) {
+                    (Some(hash), _) => self.init_keys[hash].clone(),
+                    (None, Some((init_key, _, _))) if init_key.user_init_key_id == key_id => {
+                        init_key.clone()
+                    }
+                    _ => {
+                        return Err(Error::ValidationError(
+                            "Welcome isn't for any of this client's keys",
+                        ))
+                    }
+                };
+                let mut group_state =
+                    GroupState::from_welcome(welcome, self.identity_key.clone(), init_key)?;
+                group_state.set_crypto_provider(self.provider);
+                let group_id = group_state.group_id().clone();
+                if self.groups.contains_key(&group_id) {
+                    return Err(Error::ValidationError("Client is already in the Welcome's group"));
+                }
+                self.groups.insert(group_id.clone(), group_state);
+
+                // A pooled init key is used up now. The last-resort one has one less use left.
+                if let Some(hash) = pooled_hash {
+                    self.init_keys.remove(&hash);
+                    self.consumed_init_keys.push(hash);
+                    return Ok(ClientEvent::Joined(group_id));
+                }
+                let uses_left = match self.last_resort_init_key {
+                    Some((_, _, ref mut uses_left)) => {
+                        *uses_left -= 1;
+                        *uses_left
+                    }
+                    // The Welcome was for the last-resort key, so there is one
+                    None => unreachable!(),
+                };
+                if uses_left == 0 {
+                    if let Some((_, hash, _)) = self.last_resort_init_key.take() {
+                        self.consumed_init_keys.push(hash);
+                    }
+                }
+                Ok(ClientEvent::JoinedWithLastResortKey(group_id, uses_left))
+            }
+            MlsMessage::GroupInfo(group_info) => {
+                group_info.verify()?;
+                Ok(ClientEvent::GroupInfo(group_info))
+            }
+            MlsMessage::KeyPackage(init_key) => {
+                init_key.verify_sig()?;
+                Ok(ClientEvent::KeyPackage(init_key))
+            }
+        }
+    }
+
+    /// Returns the group with the given ID
+    ///
+    /// Returns: `Ok(group_state)` on success. If this member isn't in the group, returns an
+    /// `Error::ValidationError`.
+    fn group_for(&mut self, group_id: &GroupId) -> Result<&mut GroupState, Error> {
+        self.groups
+            .get_mut(group_id)
+            .ok_or(Error::ValidationError("Message is for a group this client isn't in"))
+    }
+
+    /// Processes a framed handshake in `group_state`, and moves the group to the epoch it leads to
+    fn process_handshake_message(
+        group_state: &mut GroupState,
+        message: HandshakeMessage,
+    ) -> Result<ClientEvent, Error> {
+        let (new_group_state, app_key_chain) = group_state.process_handshake_message(&message)?;
+        *group_state = new_group_state;
+        Ok(ClientEvent::EpochChanged(group_state.group_id().clone(), app_key_chain))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        client::{Client, ClientEvent},
+        crypto::ciphersuite::X25519_SHA256_AES128GCM,
+        group_state::Welcome,
+        handshake::{UserInitKey, MLS_DUMMY_VERSION},
+        message::MlsMessage,
+        ratchet_tree::PathSecret,
+        test_utils,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Sends a message over the wire
+    fn send(message: impl Into<MlsMessage>) -> MlsMessage {
+        let bytes = message.into().to_bytes().unwrap();
+        MlsMessage::from_bytes(&bytes).unwrap()
+    }
+
+    // Check that every kind of message gets where it's going after a trip over the wire
+    #[quickcheck]
+    fn message_dispatch(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        let group_id = group_state1.group_id.clone();
+
+        // Both members need the membership key and secret tree of an epoch first
+        let cs = group_state1.cs;
+        let (handshake, new_group_state1, _) = group_state1
+            .create_and_apply_update_handshake(PathSecret::new_from_random(cs, &mut rng), &mut rng)
+            .unwrap();
+        group_state1 = new_group_state1;
+        let (group_state2, _) = group_state2.process_handshake(&handshake).unwrap();
+        let mut client2 = Client::new(identity_keys[index2 as usize].clone());
+        assert!(client2.add_group(group_state2).is_none());
+
+        // A commit in a PublicMessage moves client 2's group to the next epoch
+        let (handshake, new_group_state1, _) = group_state1
+            .create_and_apply_commit_handshake(
+                Vec::new(),
+                PathSecret::new_from_random(cs, &mut rng),
+                &mut rng,
+            )
+            .unwrap();
+        let message = group_state1.frame_handshake(handshake).unwrap();
+        group_state1 = new_group_state1;
+        match client2.process(send(message)).unwrap() {
+            ClientEvent::EpochChanged(id, _) => assert_eq!(id, group_id),
+            _ => panic!("commit didn't change the group's epoch"),
+        }
+        assert_eq!(client2.group(&group_id).unwrap().epoch, group_state1.epoch);
+
+        // An application message decrypts in the right group
+        let message = group_state1.encrypt_application_message(b"hello").unwrap();
+        match client2.process(send(message)).unwrap() {
+            ClientEvent::ApplicationMessage(id, processed) => {
+                assert_eq!(id, group_id);
+                assert_eq!(processed.sender(), group_state1.roster_index.unwrap());
+                assert_eq!(processed.plaintext(), b"hello");
+            }
+            _ => panic!("application message wasn't decrypted"),
+        }
+
+        // So does a GroupInfo
+        let group_info = group_state1.group_info().unwrap();
+        match client2.process(send(group_info)).unwrap() {
+            ClientEvent::GroupInfo(group_info) => assert_eq!(group_info.group_id(), &group_id),
+            _ => panic!("GroupInfo wasn't verified"),
+        }
+
+        // Someone new publishes a KeyPackage and is welcomed with it, but only once
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"client 3".to_vec(),
+            credential,
+            vec![&X25519_SHA256_AES128GCM],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let mut client3 = Client::new(identity_key);
+        client3.add_init_key(init_key.clone()).unwrap();
+        match client2.process(send(init_key.clone())).unwrap() {
+            ClientEvent::KeyPackage(key_package) => {
+                assert_eq!(key_package.user_init_key_id, b"client 3")
+            }
+            _ => panic!("KeyPackage wasn't verified"),
+        }
+
+        let (welcome, _) = Welcome::from_group_state(&group_state1, &init_key, &mut rng).unwrap();
+        match client3.process(send(welcome)).unwrap() {
+            ClientEvent::Joined(id) => assert_eq!(id, group_id),
+            _ => panic!("Welcome didn't join the group"),
+        }
+        assert!(client3.group(&group_id).is_some());
+        let (welcome, _) = Welcome::from_group_state(&group_state1, &init_key, &mut rng).unwrap();
+        assert!(client3.process(send(welcome)).is_err());
+
+        // Messages for groups the client isn't in are rejected
+        let message = group_state1.encrypt_application_message(b"hello").unwrap();
+        assert!(client2.remove_group(&group_id).is_some());
+        assert!(client2.process(send(message)).is_err());
+    }
+
+    // Check that a pool of UserInitKeys is published without its private keys, and that the
+    // client says which ones Welcomes used up
+    #[quickcheck]
+    fn init_key_pool(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(1, &mut rng);
+
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let mut client = Client::new(identity_key);
+        let published = client
+            .generate_init_keys(
+                3,
+                &credential,
+                vec![&X25519_SHA256_AES128GCM],
+                vec![MLS_DUMMY_VERSION],
+                &mut rng,
+            )
+            .unwrap();
+        assert_eq!(published.len(), 3);
+        assert_eq!(client.unused_init_key_count(), 3);
+        for (i, init_key) in published.iter().enumerate() {
+            init_key.verify_sig().unwrap();
+            assert!(init_key.private_keys.is_none());
+            assert_eq!(init_key.credential, credential);
+            for other in &published[i + 1..] {
+                assert_ne!(init_key.user_init_key_id, other.user_init_key_id);
+                assert_ne!(init_key.init_keys[0].as_bytes(), other.init_keys[0].as_bytes());
+            }
+        }
+        assert!(client.take_consumed_init_keys().is_empty());
+
+        // Being welcomed with one of them uses it up, and only it
+        let (welcome, _) =
+            Welcome::from_group_state(&group_state, &published[1], &mut rng).unwrap();
+        match client.process(send(welcome)).unwrap() {
+            ClientEvent::Joined(id) => assert_eq!(id, group_state.group_id),
+            _ => panic!("Welcome didn't join the group"),
+        }
+        assert_eq!(client.unused_init_key_count(), 2);
+        let consumed = client.take_consumed_init_keys();
+        assert_eq!(consumed, vec![Client::init_key_hash(&published[1]).unwrap()]);
+        assert!(client.take_consumed_init_keys().is_empty());
+    }
+
+    // Check that a last-resort UserInitKey can be used for as many Welcomes as it says and no
+    // more, and that every join with it says so
+    #[quickcheck]
+    fn last_resort_init_key(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let group_states: Vec<_> =
+            (0..3).map(|_| test_utils::random_full_group_state(1, &mut rng).0).collect();
+
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let mut client = Client::new(identity_key);
+        let cipher_suites = vec![&X25519_SHA256_AES128GCM];
+        let versions = vec![MLS_DUMMY_VERSION];
+        assert!(client
+            .set_last_resort_init_key(
+                0,
+                &credential,
+                cipher_suites.clone(),
+                versions.clone(),
+                &mut rng
+            )
+            .is_err());
+        let last_resort = client
+            .set_last_resort_init_key(2, &credential, cipher_suites, versions, &mut rng)
+            .unwrap();
+        last_resort.verify_sig().unwrap();
+        assert!(last_resort.is_last_resort());
+        assert!(last_resort.private_keys.is_none());
+        assert_eq!(client.unused_init_key_count(), 0);
+        assert_eq!(client.last_resort_uses_left(), Some(2));
+
+        // Every join with it is a warning, and the last one uses it up
+        for (group_state, uses_left) in group_states.iter().zip(&[1, 0]) {
+            let (welcome, _) =
+                Welcome::from_group_state(group_state, &last_resort, &mut rng).unwrap();
+            match client.process(send(welcome)).unwrap() {
+                ClientEvent::JoinedWithLastResortKey(id, n) => {
+                    assert_eq!(id, group_state.group_id);
+                    assert_eq!(n, *uses_left);
+                }
+                _ => panic!("join with the last-resort key wasn't flagged"),
+            }
+        }
+        assert_eq!(client.last_resort_uses_left(), None);
+        let consumed = client.take_consumed_init_keys();
+        assert_eq!(consumed, vec![Client::init_key_hash(&last_resort).unwrap()]);
+
+        let (welcome, _) =
+            Welcome::from_group_state(&group_states[2], &last_resort, &mut rng).unwrap();
+        assert!(client.process(send(welcome)).is_err());
+    }
+}
@@ -1,164 +1,109 @@
 //! Defines specialized serialization and deserialization routines for various types
 
-use crate::crypto::{
-    ciphersuite::{CipherSuite, P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM},
-    dh::{DhPublicKey, DhPublicKeyRaw},
-    sig::{
-        SigPublicKey, SigPublicKeyRaw, Signature, SignatureRaw, SignatureScheme, ECDSA_P256_IMPL,
-        ED25519_IMPL,
+use crate::{
+    crypto::{
+        ciphersuite::CipherSuite,
+        dh::{DhPublicKey, DhPublicKeyRaw},
+        sig::{SigPublicKey, SigPublicKeyRaw, Signature, SignatureRaw, SignatureScheme},
     },
+    error::Error,
+    tls_de::{make_custom_error, TlsDeserialize, TlsDeserializer},
+    tls_ser::{TlsSerialize, TlsSerializer},
 };
 
-use serde::{
-    de::{Deserialize, Deserializer},
-    ser::{Serialize, Serializer},
-};
-
-const CIPHERSUITE_NAME_IDS: &[(&CipherSuite, &str, u16)] = &[
-    (&P256_SHA256_AES128GCM, "P256_SHA256_AES128GCM", 0x0000),
-    (&X25519_SHA256_AES128GCM, "X25519_SHA256_AES128GCM", 0x0001),
-];
-const SIGSCHEME_NAME_IDS: &[(&SignatureScheme, &str, u16)] = &[
-    (&ECDSA_P256_IMPL, "dummy_ecdsa_secp256r1_sha256", 0x0403),
-    (&ED25519_IMPL, "ed25519", 0x0807),
-];
+// Implement TlsSerialize for our CipherSuites and SignatureSchemes. This just serializes their ID
 
-// Implement Serialize for our CipherSuites and SignatureSchemes. This just serializes their ID
-
-impl Serialize for CipherSuite {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let my_name = self.name;
-        for (_, name, id) in CIPHERSUITE_NAME_IDS {
-            if name == &my_name {
-                return serializer.serialize_u16(*id);
-            }
-        }
-        panic!("tried to serialize unknown ciphersuite: {}", self.name);
+impl TlsSerialize for CipherSuite {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        self.id.tls_serialize(serializer)
     }
 }
 
-impl<'de> Deserialize<'de> for &'static CipherSuite {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        // Make a visitor type that just deserializes from u8 to an enum variant
-        struct Visitor;
-
-        impl<'de> serde::de::Visitor<'de> for Visitor {
-            type Value = &'static CipherSuite;
-
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("a u16 representing a cipher suite")
-            }
-
-            fn visit_u16<E>(self, value: u16) -> Result<&'static CipherSuite, E>
-            where
-                E: serde::de::Error,
-            {
-                for (cs, _, id) in CIPHERSUITE_NAME_IDS {
-                    if value == *id {
-                        return Ok(cs);
-                    }
-                }
-                Err(E::custom(format_args!(
-                    "could not deserialize 0x{:04x} into cipher suite",
-                    value
-                )))
-            }
-        }
-
-        deserializer.deserialize_u16(Visitor)
+// Deserialize from u16 to a registered cipher suite
+impl TlsDeserialize for &'static CipherSuite {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<&'static CipherSuite, Error> {
+        let id = u16::tls_deserialize(deserializer)?;
+        deserializer.provider().cipher_suite_by_id(id).ok_or_else(|| {
+            make_custom_error(format_args!("could not deserialize 0x{:04x} into cipher suite", id))
+        })
     }
 }
 
-impl Serialize for SignatureScheme {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let my_name = self.name();
-        for (_, name, id) in SIGSCHEME_NAME_IDS {
-            if name == &my_name {
-                return serializer.serialize_u16(*id);
-            }
-        }
-        panic!("tried to serialize unknown signature scheme: {}", my_name);
+impl TlsSerialize for SignatureScheme {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        self.id().tls_serialize(serializer)
     }
 }
 
-impl<'de> Deserialize<'de> for &'static SignatureScheme {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct Visitor;
-
-        impl<'de> serde::de::Visitor<'de> for Visitor {
-            type Value = &'static SignatureScheme;
-
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("a u16 representing a signature scheme")
-            }
-
-            fn visit_u16<E>(self, value: u16) -> Result<&'static SignatureScheme, E>
-            where
-                E: serde::de::Error,
-            {
-                for (ss, _, id) in SIGSCHEME_NAME_IDS {
-                    if value == *id {
-                        return Ok(*ss);
-                    }
-                }
-                Err(E::custom(format_args!(
-                    "could not deserialize 0x{:04x} into signature scheme",
-                    value
-                )))
-            }
-        }
-
-        deserializer.deserialize_u16(Visitor)
+impl TlsDeserialize for &'static SignatureScheme {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<&'static SignatureScheme, Error> {
+        let id = u16::tls_deserialize(deserializer)?;
+        deserializer.provider().signature_scheme_by_id(id).ok_or_else(|| {
+            make_custom_error(format_args!(
+                "could not deserialize 0x{:04x} into signature scheme",
+                id
+            ))
+        })
     }
 }
 
-impl Serialize for DhPublicKey {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+impl TlsSerialize for DhPublicKey {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
         // If it's not already, convert it to a Raw public key, then serialize that
         match self {
-            DhPublicKey::Raw(p) => p.serialize(serializer),
-            p => DhPublicKeyRaw(p.as_bytes().to_vec()).serialize(serializer),
+            DhPublicKey::Raw(p) => p.tls_serialize(serializer),
+            p => DhPublicKeyRaw(p.as_bytes().to_vec()).tls_serialize(serializer),
         }
     }
 }
 
-impl<'de> Deserialize<'de> for DhPublicKey {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+impl TlsDeserialize for DhPublicKey {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<DhPublicKey, Error> {
         // Deserialize everything as a raw vec. We deal with variants in CryptoUpcast
-        DhPublicKeyRaw::deserialize(deserializer).map(DhPublicKey::Raw)
+        DhPublicKeyRaw::tls_deserialize(deserializer).map(DhPublicKey::Raw)
     }
 }
 
-impl Serialize for SigPublicKey {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+impl TlsSerialize for SigPublicKey {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
         // If it's not already, convert it to a Raw public key, then serialize that
         match self {
-            SigPublicKey::Raw(p) => p.serialize(serializer),
-            p => SigPublicKeyRaw(p.as_bytes().to_vec()).serialize(serializer),
+            SigPublicKey::Raw(p) => p.tls_serialize(serializer),
+            p => SigPublicKeyRaw(p.as_bytes().to_vec()).tls_serialize(serializer),
         }
     }
 }
 
-impl<'de> Deserialize<'de> for SigPublicKey {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+impl TlsDeserialize for SigPublicKey {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<SigPublicKey, Error> {
         // Deserialize everything as a raw vec. We deal with variants in CryptoUpcast
-        SigPublicKeyRaw::deserialize(deserializer).map(SigPublicKey::Raw)
+        SigPublicKeyRaw::tls_deserialize(deserializer).map(SigPublicKey::Raw)
     }
 }
 
-impl Serialize for Signature {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+impl TlsSerialize for Signature {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
         // If it's not already, convert it to a Raw signature, then serialize that
         match self {
-            Signature::Raw(p) => p.serialize(serializer),
-            p => SignatureRaw(p.as_bytes().to_vec()).serialize(serializer),
+            Signature::Raw(p) => p.tls_serialize(serializer),
+            p => SignatureRaw(p.as_bytes().to_vec()).tls_serialize(serializer),
         }
     }
 }
 
-impl<'de> Deserialize<'de> for Signature {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+impl TlsDeserialize for Signature {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<Signature, Error> {
         // Deserialize everything as a raw vec. We deal with variants in CryptoUpcast
-        SignatureRaw::deserialize(deserializer).map(Signature::Raw)
+        SignatureRaw::tls_deserialize(deserializer).map(Signature::Raw)
     }
 }
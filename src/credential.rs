@@ -2,12 +2,14 @@
 
 use crate::crypto::sig::{SigPublicKey, SignatureScheme};
 use crate::error::Error;
+use crate::pem;
+use crate::x509::{self, Certificate, TrustStore};
 
 // TODO: Decide whether we check the size on the lower end while (de)serializing
 
 /// A `Roster`, as it appears in a `GroupState`, is a list of optional `Credential`s
 // Invariant: Rosters can never be empty
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
 pub struct Roster(pub(crate) Vec<Option<Credential>>);
 
 impl Roster {
@@ -54,17 +56,248 @@ impl Roster {
 }
 
 // opaque cert_data<1..2^24-1>;
-/// A bunch of bytes representing an X.509 certificate.
-///
-/// NOTE: This currently doesn't do anything.
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-#[serde(rename = "X509CertData__bound_u24")]
-pub struct X509CertData(Vec<u8>);
+/// The DER encoding of an X.509 certificate
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(bound = "u24")]
+pub struct X509CertData(pub(crate) Vec<u8>);
+
+impl X509CertData {
+    /// Makes an `X509CertData` from a DER-encoded certificate. Nothing is checked until it's part
+    /// of an `X509Credential`.
+    pub fn from_der(der: Vec<u8>) -> X509CertData {
+        X509CertData(der)
+    }
+
+    /// Returns the DER encoding of the certificate
+    pub fn as_der(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+/// What's read out of the leaf certificate of an `X509Credential`
+#[derive(Clone, Debug)]
+pub(crate) struct X509Leaf {
+    /// The DER encoding of the leaf's subject
+    pub(crate) identity: Identity,
+    /// The signature scheme of the leaf's key
+    pub(crate) signature_scheme: &'static SignatureScheme,
+    /// The leaf's key
+    pub(crate) public_key: SigPublicKey,
+}
+
+// struct {
+//     X509CertData chain<1..2^32-1>;
+// } X509Credential;
+/// A user credential that's an X.509 certificate chain, leaf first. The member's identity is the
+/// DER encoding of the leaf certificate's subject, and their signature key is the leaf's key.
+/// Whether the chain is trusted is a separate matter: a `GroupState` checks that against its
+/// `TrustStore` whenever someone is added with one of these.
+#[derive(Clone, Debug, TlsDeserialize, TlsSerialize)]
+pub struct X509Credential {
+    #[tls(bound = "u32")]
+    pub(crate) chain: Vec<X509CertData>,
+
+    /// This is read from the chain when the credential is made, or when it's upcast after being
+    /// deserialized
+    #[tls(skip)]
+    pub(crate) leaf: Option<X509Leaf>,
+}
+
+impl X509Credential {
+    /// Makes a credential from the given certificate chain, leaf first
+    ///
+    /// Returns: `Ok(credential)` on success. If the chain is empty, or its leaf is malformed or
+    /// has a key of an unsupported type, returns an `Error::CertificateError`. If the leaf's key
+    /// isn't valid, returns an `Error::SignatureError`.
+    pub fn new(chain: Vec<X509CertData>) -> Result<X509Credential, Error> {
+        let mut credential = X509Credential {
+            chain,
+            leaf: None,
+        };
+        credential.read_leaf()?;
+        Ok(credential)
+    }
+
+    /// Returns the certificate chain, leaf first
+    pub fn chain(&self) -> &[X509CertData] {
+        &self.chain
+    }
+
+    /// Makes a credential from the `CERTIFICATE` PEM blocks in `pem`, in order, leaf first, which
+    /// is how tools like OpenSSL write chains out
+    ///
+    /// Returns: `Ok(credential)` on success. If `pem` is malformed, returns an `Error::PemError`.
+    /// If it has no certificates in it, returns an `Error::CertificateError`. Otherwise, returns
+    /// an error as `X509Credential::new` does.
+    pub fn from_pem(pem: &str) -> Result<X509Credential, Error> {
+        let ders = pem::decode_all(pem, pem::CERTIFICATE_LABEL)?;
+        X509Credential::new(ders.into_iter().map(X509CertData::from_der).collect())
+    }
+
+    /// Writes the certificate chain out as `CERTIFICATE` PEM blocks, leaf first
+    pub fn to_pem(&self) -> String {
+        self.chain.iter().map(|cert| pem::encode(pem::CERTIFICATE_LABEL, cert.as_der())).collect()
+    }
+
+    /// Reads the member's identity and key out of the leaf certificate
+    ///
+    /// Returns: `Ok(())` on success. Otherwise, returns an error as `X509Credential::new` does.
+    pub(crate) fn read_leaf(&mut self) -> Result<(), Error> {
+        let leaf_der =
+            self.chain.first().ok_or(Error::CertificateError("Certificate chain is empty"))?;
+        let leaf = Certificate::parse(leaf_der.as_der())?;
+        self.leaf = Some(X509Leaf {
+            identity: Identity(leaf.subject.to_vec()),
+            signature_scheme: leaf.signature_scheme,
+            public_key: leaf.public_key,
+        });
+        Ok(())
+    }
+
+    /// Checks that the chain ends at one of the roots in `store`, or that its leaf's key is pinned
+    /// there, and that it's valid at `now`, in seconds since the Unix epoch
+    ///
+    /// Returns: `Ok(())` if the chain is valid. If it isn't, returns an `Error::CertificateError`,
+    /// or an `Error::SignatureError` if a signature in it doesn't verify.
+    // This is just a wrapper around x509::validate_against_store
+    pub fn validate(&self, store: &TrustStore, now: u64) -> Result<(), Error> {
+        x509::validate_against_store(&self.chain, store, now)
+    }
+
+    /// Returns what's been read out of the leaf certificate
+    ///
+    /// Panics: If the credential was deserialized and hasn't been upcast yet
+    pub(crate) fn leaf(&self) -> &X509Leaf {
+        self.leaf.as_ref().expect("X.509 credential hasn't been upcast")
+    }
+}
+
+// Everything in the leaf comes from the chain
+impl PartialEq for X509Credential {
+    fn eq(&self, other: &X509Credential) -> bool {
+        self.chain == other.chain
+    }
+}
+
+impl Eq for X509Credential {}
+
+// enum {
+//     jwt(0),
+//     verifiable_credential(1),
+//     (255)
+// } TokenFormat;
+/// The kind of signed token in a `TokenCredential`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(tag = "u8")]
+pub enum TokenFormat {
+    /// A JSON Web Token, in its compact serialization
+    Jwt,
+    /// A W3C Verifiable Credential, in whatever serialization the `TokenVerifier` expects
+    VerifiableCredential,
+}
+
+/// What a `TokenVerifier` found in a token once its signature verified
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenClaims {
+    /// The identity that the token's issuer vouches for, e.g., a JWT's `sub` claim
+    pub identity: Vec<u8>,
+    /// The encoding of the MLS signature public key that the token binds to the identity, e.g.,
+    /// from a JWT's `cnf` claim
+    pub signature_key: Vec<u8>,
+    /// When the token expires, in seconds since the Unix epoch, if it does
+    pub not_after: Option<u64>,
+}
+
+/// A trait representing whatever checks the signatures on the tokens in `TokenCredential`s, e.g.,
+/// against the keys an SSO provider publishes. A `GroupState` has none until the application sets
+/// one, and until then nobody with a token credential can be added.
+pub trait TokenVerifier: Send + Sync {
+    /// Verifies the signature on `token`, which is in the given format, and reads its claims
+    ///
+    /// Returns: `Ok(claims)` if the token verifies. Otherwise, returns why not.
+    fn verify(&self, format: TokenFormat, token: &[u8]) -> Result<TokenClaims, &'static str>;
+}
+
+// struct {
+//     TokenFormat format;
+//     opaque token<1..2^32-1>;
+//     Identity identity;
+//     SignatureScheme signature_scheme;
+//     SignaturePublicKey public_key;
+// } TokenCredential;
+/// A user credential that's a signed token, like a JWT or a W3C Verifiable Credential, that binds
+/// the member's signature key to their identity. The identity and key are carried alongside the
+/// token, and a `GroupState` checks that the token vouches for both with its `TokenVerifier`
+/// whenever someone is added with one of these.
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct TokenCredential {
+    pub(crate) format: TokenFormat,
+
+    #[tls(bound = "u32")]
+    pub(crate) token: Vec<u8>,
+
+    /// The identity the token is for
+    pub(crate) identity: Identity,
+
+    /// The signature scheme of the key the token is for
+    pub(crate) signature_scheme: &'static SignatureScheme,
+
+    /// The key the token is for
+    pub(crate) public_key: SigPublicKey,
+}
+
+impl TokenCredential {
+    /// Makes a credential from the given token, which should vouch for `identity` having
+    /// `public_key`. Nothing is checked until someone is added with it.
+    pub fn new(
+        format: TokenFormat,
+        token: Vec<u8>,
+        identity: Identity,
+        ss: &'static SignatureScheme,
+        public_key: SigPublicKey,
+    ) -> TokenCredential {
+        TokenCredential {
+            format,
+            token,
+            identity,
+            signature_scheme: ss,
+            public_key,
+        }
+    }
+
+    /// Returns the kind of token this is
+    pub fn format(&self) -> TokenFormat {
+        self.format
+    }
+
+    /// Returns the token
+    pub fn token(&self) -> &[u8] {
+        &self.token
+    }
+
+    /// Checks that the token verifies under `verifier`, that it vouches for this credential's
+    /// identity and key, and that it hasn't expired as of `now`, in seconds since the Unix epoch
+    ///
+    /// Returns: `Ok(())` if the token is valid. Otherwise, returns an `Error::TokenError`.
+    pub fn validate(&self, verifier: &dyn TokenVerifier, now: u64) -> Result<(), Error> {
+        let claims = verifier.verify(self.format, &self.token).map_err(Error::TokenError)?;
+        if claims.identity != self.identity.as_bytes() {
+            return Err(Error::TokenError("Token is for another identity"));
+        }
+        if claims.signature_key != self.public_key.as_bytes() {
+            return Err(Error::TokenError("Token is for another signature key"));
+        }
+        match claims.not_after {
+            Some(not_after) if now > not_after => Err(Error::TokenError("Token has expired")),
+            _ => Ok(()),
+        }
+    }
+}
 
 // opaque identity<0..2^16-1>;
 /// A bytestring that should uniquely identify the user in the Group
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-#[serde(rename = "Identity__bound_u16")]
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(bound = "u16")]
 pub struct Identity(pub(crate) Vec<u8>);
 
 impl Identity {
@@ -80,7 +313,7 @@ impl Identity {
 }
 
 /// A user credential without respect to any standard credential format
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
 pub struct BasicCredential {
     /// This is a user ID
     pub(crate) identity: Identity,
@@ -107,34 +340,214 @@ impl BasicCredential {
     }
 }
 
+// uint16 CredentialType;
+/// Identifies a kind of credential, as listed in `Capabilities` and required_capabilities
+/// extensions
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct CredentialType(pub u16);
+
+impl CredentialType {
+    /// The type of `Credential::Basic`
+    pub const BASIC: CredentialType = CredentialType(0x0001);
+    /// The type of `Credential::X509`
+    pub const X509: CredentialType = CredentialType(0x0002);
+    /// The type of `Credential::Token`. There's no registered type for these, so this is in the
+    /// private use range.
+    pub const TOKEN: CredentialType = CredentialType(0xF000);
+}
+
+/// Where a `CredentialValidator` is being asked about a credential
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CredentialContext {
+    /// Someone is being added with this credential, by an Add proposal or a `GroupAdd`
+    Add,
+    /// The member with this credential is updating their leaf
+    Update,
+    /// Someone is joining with this credential by an external commit
+    ExternalJoin,
+}
+
+/// A trait representing the application's identity policy, e.g., which domains identities may be
+/// in, which keys are pinned, or what a directory says. This is consulted whenever a credential is
+/// encountered in a handshake, after the credential's own checks have passed. A handshake with a
+/// credential that's rejected fails with an `Error::CredentialRejected`.
+pub trait CredentialValidator: Send + Sync {
+    /// Checks whether `credential` is acceptable in `context`
+    ///
+    /// Returns: `Ok(())` if it is. Otherwise, returns why not.
+    fn validate(
+        &self,
+        credential: &Credential,
+        context: CredentialContext,
+    ) -> Result<(), &'static str>;
+
+    /// Checks whether a member whose credential is `old_credential` may replace it with
+    /// `new_credential` in an Update, e.g., whether a renamed identity still belongs to the same
+    /// person. `new_credential` has already passed `validate` by then. By default, every change
+    /// is accepted.
+    ///
+    /// Returns: `Ok(())` if it may. Otherwise, returns why not.
+    fn validate_change(
+        &self,
+        _old_credential: &Credential,
+        _new_credential: &Credential,
+    ) -> Result<(), &'static str> {
+        Ok(())
+    }
+}
+
 /// A user credential specifies the member's identity, public signing key, and signature scheme the
 /// member will use to sign messages
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-#[serde(rename = "Credential__enum_u8")]
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(tag = "u8")]
 pub enum Credential {
     Basic(BasicCredential),
-    X509(X509CertData),
+    X509(X509Credential),
+    Token(TokenCredential),
 }
 
 impl Credential {
+    /// Returns the type of this credential
+    pub fn credential_type(&self) -> CredentialType {
+        match self {
+            Credential::Basic(_) => CredentialType::BASIC,
+            Credential::X509(_) => CredentialType::X509,
+            Credential::Token(_) => CredentialType::TOKEN,
+        }
+    }
+
     pub(crate) fn get_public_key(&self) -> &SigPublicKey {
         match self {
             Credential::Basic(ref basic) => &basic.public_key,
-            Credential::X509(_) => unimplemented!(),
+            Credential::X509(ref x509) => &x509.leaf().public_key,
+            Credential::Token(ref token) => &token.public_key,
         }
     }
 
     pub(crate) fn get_signature_scheme(&self) -> &'static SignatureScheme {
         match self {
             Credential::Basic(ref basic) => basic.signature_scheme,
-            Credential::X509(_) => unimplemented!(),
+            Credential::X509(ref x509) => x509.leaf().signature_scheme,
+            Credential::Token(ref token) => token.signature_scheme,
         }
     }
 
     pub fn get_identity(&self) -> &Identity {
         match self {
             Credential::Basic(ref basic) => &basic.identity,
-            Credential::X509(_) => unimplemented!(),
+            Credential::X509(ref x509) => &x509.leaf().identity,
+            Credential::Token(ref token) => &token.identity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        credential::{Credential, TokenClaims, TokenCredential, TokenFormat, TokenVerifier},
+        error::Error,
+        group_state::GroupState,
+        handshake::{Proposal, UserInitKey, MLS_DUMMY_VERSION},
+        ratchet_tree::PathSecret,
+        test_utils,
+        tls_de::{TlsDeserialize, TlsDeserializer},
+        tls_ser,
+        upcast::{CryptoCtx, CryptoUpcast},
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    // A verifier that knows which JWTs were issued, and what they say
+    struct IssuedTokens(Vec<(Vec<u8>, TokenClaims)>);
+
+    impl TokenVerifier for IssuedTokens {
+        fn verify(&self, format: TokenFormat, token: &[u8]) -> Result<TokenClaims, &'static str> {
+            self.0
+                .iter()
+                .find(|(issued, _)| format == TokenFormat::Jwt && issued.as_slice() == token)
+                .map(|(_, claims)| claims.clone())
+                .ok_or("Token's signature doesn't verify")
         }
     }
+
+    // Check that token credentials survive a trip over the wire, and that someone with one can only
+    // be added once the group's verifier says the token vouches for their identity and key
+    #[quickcheck]
+    fn token_credential_checked(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(1, &mut rng);
+
+        let (basic, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let token = [&b"jwt for "[..], basic.get_identity().as_bytes()].concat();
+        let credential = Credential::Token(TokenCredential::new(
+            TokenFormat::Jwt,
+            token.clone(),
+            basic.get_identity().clone(),
+            basic.get_signature_scheme(),
+            basic.get_public_key().clone(),
+        ));
+
+        let bytes = tls_ser::serialize_to_bytes(&credential).unwrap();
+        let mut cursor = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+        let mut received = Credential::tls_deserialize(&mut deserializer).unwrap();
+        received.upcast_crypto_values(&CryptoCtx::new()).unwrap();
+        assert_eq!(received, credential);
+
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"token add".to_vec(),
+            credential,
+            vec![group_state.cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let commit_add = |group_state: &GroupState, rng: &mut rand::rngs::StdRng| {
+            let new_path_secret = PathSecret::new_from_random(group_state.cs, rng);
+            // The new member runs this crate too, so they support the group's basic credentials
+            let capabilities = group_state.capabilities();
+            let add = Proposal::new_add_with_capabilities(init_key.clone(), capabilities);
+            group_state.create_and_apply_commit_handshake(vec![add], new_path_secret, rng)
+        };
+        let verified_with = |claims: TokenClaims| {
+            let mut group_state = group_state.clone();
+            group_state.set_token_verifier(Arc::new(IssuedTokens(vec![(token.clone(), claims)])));
+            group_state
+        };
+        let token_error = |result: Result<_, Error>| match result {
+            Err(Error::InvalidCommit(_, err)) => match *err {
+                Error::InvalidKeyPackage(_, err) => matches!(*err, Error::TokenError(_)),
+                _ => false,
+            },
+            _ => false,
+        };
+
+        // Without a verifier, tokens mean nothing
+        assert!(token_error(commit_add(&group_state, &mut rng)));
+
+        // A token for someone else's key, or one that's expired, doesn't get anyone in
+        let claims = TokenClaims {
+            identity: basic.get_identity().as_bytes().to_vec(),
+            signature_key: basic.get_public_key().as_bytes().to_vec(),
+            not_after: None,
+        };
+        let (other, _) = test_utils::random_basic_credential(&mut rng);
+        let other_key = TokenClaims {
+            signature_key: other.get_public_key().as_bytes().to_vec(),
+            ..claims.clone()
+        };
+        assert!(token_error(commit_add(&verified_with(other_key), &mut rng)));
+        let expired = TokenClaims {
+            not_after: Some(0),
+            ..claims.clone()
+        };
+        assert!(token_error(commit_add(&verified_with(expired), &mut rng)));
+
+        // A token that vouches for both does
+        commit_add(&verified_with(claims), &mut rng).unwrap();
+    }
 }
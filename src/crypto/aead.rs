@@ -1,7 +1,16 @@
-use crate::error::Error;
+//! Defines `AeadScheme`, the authenticated encryption part of a cipher suite, along with its keys
+//! and nonces. Implement `AeadSchemeInterface` to bring an AEAD this crate doesn't ship with.
+
+use crate::{crypto::secret::Secret, error::Error};
 
 /// A singleton object representing the AES-128-GCM AEAD scheme
-pub(crate) const AES128GCM_IMPL: AeadScheme = AeadScheme(&Aes128Gcm);
+pub const AES128GCM_IMPL: AeadScheme = AeadScheme(&Aes128Gcm);
+
+/// A singleton object representing the AES-256-GCM AEAD scheme
+pub const AES256GCM_IMPL: AeadScheme = AeadScheme(&Aes256Gcm);
+
+/// A singleton object representing the ChaCha20-Poly1305 AEAD scheme
+pub const CHACHA20POLY1305_IMPL: AeadScheme = AeadScheme(&ChaCha20Poly1305);
 
 /// Size of opening / sealing keys, in bytes
 const AES_128_GCM_KEY_SIZE: usize = 128 / 8;
@@ -10,10 +19,31 @@ const AES_128_GCM_TAG_SIZE: usize = 128 / 8;
 /// Size of nonces, in bytes
 const AES_128_GCM_NONCE_SIZE: usize = 96 / 8;
 
+/// Size of opening / sealing keys, in bytes
+const AES_256_GCM_KEY_SIZE: usize = 256 / 8;
+/// Size of tag, in bytes
+const AES_256_GCM_TAG_SIZE: usize = 128 / 8;
+/// Size of nonces, in bytes
+const AES_256_GCM_NONCE_SIZE: usize = 96 / 8;
+
+/// Size of opening / sealing keys, in bytes
+const CHACHA20_POLY1305_KEY_SIZE: usize = 256 / 8;
+/// Size of tag, in bytes
+const CHACHA20_POLY1305_TAG_SIZE: usize = 128 / 8;
+/// Size of nonces, in bytes
+const CHACHA20_POLY1305_NONCE_SIZE: usize = 96 / 8;
+
 /// An enum of possible types for an AEAD key, depending on the underlying algorithm
-pub(crate) enum AeadKey {
+pub enum AeadKey {
     /// An opening / sealing key in AES-128-GCM
     Aes128GcmKey(Aes128GcmKey),
+    /// An opening / sealing key in AES-256-GCM
+    Aes256GcmKey(Aes256GcmKey),
+    /// An opening / sealing key in ChaCha20-Poly1305
+    ChaCha20Poly1305Key(ChaCha20Poly1305Key),
+    /// The bytes of a key in an AEAD that isn't built in. This is what an `AeadSchemeInterface`
+    /// that this crate doesn't ship with makes its keys out of.
+    Custom(Secret),
 }
 
 impl AeadKey {
@@ -36,9 +66,15 @@ impl core::fmt::Debug for AeadKey {
 }
 
 /// An enum of possible types for an AEAD nonce, depending on the underlying algorithm
-pub(crate) enum AeadNonce {
+pub enum AeadNonce {
     /// A nonce in AES-128-GCM
-    Aes128GcmNonce(ring::aead::Nonce),
+    Aes128GcmNonce(backend::Nonce),
+    /// A nonce in AES-256-GCM
+    Aes256GcmNonce(backend::Nonce),
+    /// A nonce in ChaCha20-Poly1305
+    ChaCha20Poly1305Nonce(backend::Nonce),
+    /// The bytes of a nonce in an AEAD that isn't built in
+    Custom(Vec<u8>),
 }
 
 impl AeadNonce {
@@ -57,12 +93,25 @@ impl AeadNonce {
 // Secondly, I would like to support methods like AeadKey::new_from_bytes which would take in an
 // AeadSchemeInterface, but this leaves two ways of instantiating an AeadKey: either with
 // new_from_bytes or with AeadSchemeInterface::key_from_bytes. I think there should only be one way
-// of doing this, so we'll wrap the trait object and not export the trait. Thirdly, this is in
-// keeping with the design of SignatureScheme. Reasoning for that mess can be found in sig.rs.
+// of doing this, so we'll wrap the trait object and only give out the wrapper. The trait is
+// exported so that providers can implement it, but nothing in the crate takes a bare trait object.
+// Thirdly, this is in keeping with the design of SignatureScheme. Reasoning for that mess can be
+// found in sig.rs.
 /// A type representing an authenticated encryption algorithm
-pub(crate) struct AeadScheme(&'static dyn AeadSchemeInterface);
+pub struct AeadScheme(&'static dyn AeadSchemeInterface);
 
 impl AeadScheme {
+    /// Wraps an implementation of an AEAD, so that it can go in a `CipherSuite`
+    pub const fn new(scheme: &'static dyn AeadSchemeInterface) -> AeadScheme {
+        AeadScheme(scheme)
+    }
+
+    // This just passes through to AeadSchemeInterface::aead_id
+    /// Returns the HPKE AEAD ID of this scheme
+    pub(crate) fn aead_id(&self) -> u16 {
+        self.0.aead_id()
+    }
+
     // This just passes through to AeadSchemeInterface::key_size
     /// Returns the size of encryption keys in this scheme
     pub(crate) fn key_size(&self) -> usize {
@@ -99,7 +148,24 @@ impl AeadScheme {
         nonce: AeadNonce,
         ciphertext_and_tag_modified_in_place: &'a mut [u8],
     ) -> Result<&'a mut [u8], Error> {
-        self.0.open(key, nonce, ciphertext_and_tag_modified_in_place)
+        self.0.open(key, nonce, &[], ciphertext_and_tag_modified_in_place)
+    }
+
+    // This just passes through to AeadSchemeInterface::open
+    /// Does the same as `open`, except that the ciphertext also has to have been sealed with the
+    /// associated data `aad`
+    ///
+    /// Returns: `Ok(plaintext)` on sucess. If there is an error in any part of this process,
+    /// including `aad` being different from what the ciphertext was sealed with, it will be
+    /// returned as an `Error::CryptoError` with description "Unspecified".
+    pub(crate) fn open_with_aad<'a>(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        aad: &[u8],
+        ciphertext_and_tag_modified_in_place: &'a mut [u8],
+    ) -> Result<&'a mut [u8], Error> {
+        self.0.open(key, nonce, aad, ciphertext_and_tag_modified_in_place)
     }
 
     // This just passes through to AeadSchemeInterface::seal
@@ -120,33 +186,133 @@ impl AeadScheme {
         nonce: AeadNonce,
         plaintext: &mut [u8],
     ) -> Result<(), Error> {
-        self.0.seal(key, nonce, plaintext)
+        self.0.seal(key, nonce, &[], plaintext)
+    }
+
+    // This just passes through to AeadSchemeInterface::seal
+    /// Does the same as `seal`, except that the associated data `aad` is authenticated along with
+    /// the plaintext. `aad` isn't encrypted, nor is it put in the output, so whoever opens the
+    /// ciphertext has to know it already.
+    ///
+    /// Requires: `plaintext.len() >= self.tag_size()`
+    ///
+    /// Returns: `Ok(())` on sucess. If there is an error in any part of this process, it will be
+    /// returned as an `Error::CryptoError` with description "Unspecified".
+    pub(crate) fn seal_with_aad(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        aad: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), Error> {
+        self.0.seal(key, nonce, aad, plaintext)
     }
 }
 
-/// A trait representing an authenticated encryption algorithm. Note that this makes no mention of
-/// associated data, since it is not used anywhere in MLS.
+/// Produces the per-chunk nonces of the STREAM construction of Hoang, Reyhanitabar, Rogaway, and
+/// Vizár ("Online Authenticated-Encryption and its Nonce-Reuse Misuse-Resistance"). The nonce of
+/// chunk `i` is `base_nonce XOR (0 || i || last)`, where `i` is a big-endian `u32` and `last` is a
+/// byte that's 1 iff this is the final chunk. Since every chunk gets its own nonce, chunks can't
+/// be reordered, and since the final chunk is marked, a stream can't be truncated without the
+/// receiver noticing.
+pub(crate) struct StreamNonceSequence {
+    scheme: &'static AeadScheme,
+    base_nonce: Vec<u8>,
+    counter: u32,
+}
+
+impl StreamNonceSequence {
+    /// Makes a new nonce sequence from the given base nonce
+    ///
+    /// Requires: `base_nonce.len() == scheme.nonce_size()`
+    ///
+    /// Returns: `Ok(seq)` on success. If the above requirement is not met, returns an
+    /// `Error::EncryptionError`.
+    pub(crate) fn new(
+        scheme: &'static AeadScheme,
+        base_nonce: &[u8],
+    ) -> Result<StreamNonceSequence, Error> {
+        // We need room for the counter and the last-chunk flag
+        if base_nonce.len() != scheme.nonce_size() || base_nonce.len() < 5 {
+            return Err(Error::EncryptionError("Invalid base nonce for a STREAM"));
+        }
+
+        Ok(StreamNonceSequence {
+            scheme,
+            base_nonce: base_nonce.to_vec(),
+            counter: 0,
+        })
+    }
+
+    /// Returns the nonce of the next chunk in the stream and advances the counter. `last` says
+    /// whether this is the final chunk. Once a final nonce has been returned, the sequence
+    /// shouldn't be used again.
+    ///
+    /// Returns: `Ok(nonce)` on success. If the stream has run out of chunks, returns an
+    /// `Error::EncryptionError`.
+    pub(crate) fn next_nonce(&mut self, last: bool) -> Result<AeadNonce, Error> {
+        let mut nonce_bytes = self.base_nonce.clone();
+        let len = nonce_bytes.len();
+
+        // XOR the counter and the flag into the last 5 bytes
+        let counter_bytes = self.counter.to_be_bytes();
+        for (b, c) in nonce_bytes[len - 5..len - 1].iter_mut().zip(counter_bytes.iter()) {
+            *b ^= c;
+        }
+        nonce_bytes[len - 1] ^= last as u8;
+
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(Error::EncryptionError("STREAM chunk counter has hit its max"))?;
+
+        AeadNonce::new_from_bytes(self.scheme, &nonce_bytes)
+    }
+}
+
+/// A trait representing an authenticated encryption algorithm. The associated data that `open`
+/// and `seal` take is authenticated but not encrypted, and is empty for most uses in MLS. An
+/// implementation that isn't built in keeps its keys and nonces in `AeadKey::Custom` and
+/// `AeadNonce::Custom`, and is only ever handed back the keys and nonces it made.
 // ring does algorithm specification at runtime, but I'd rather encode these things in the type
-// system. So, similar to the Digest trait, we're making an AuthenticatedEncryption trait. I don't
-// think we'll need associated data in this crate, so we leave it out for simplicity
-trait AeadSchemeInterface {
+// system. So, similar to the Digest trait, we're making an AuthenticatedEncryption trait.
+pub trait AeadSchemeInterface: Sync {
+    /// The identifier of this scheme in the HPKE AEAD registry
+    fn aead_id(&self) -> u16;
+
     // Recall we can't have const trait methods if we want this to be a trait object
+    /// Returns the size of keys, in bytes
     fn key_size(&self) -> usize;
+    /// Returns the size of nonces, in bytes
     fn nonce_size(&self) -> usize;
+    /// Returns the size of authentication tags, in bytes
     fn tag_size(&self) -> usize;
 
+    /// Makes a key from `key_size()` bytes
     fn key_from_bytes(&self, key_bytes: &[u8]) -> Result<AeadKey, Error>;
 
+    /// Makes a nonce from `nonce_size()` bytes
     fn nonce_from_bytes(&self, nonce_bytes: &[u8]) -> Result<AeadNonce, Error>;
 
+    /// Decrypts `ciphertext || tag` in place, and returns the plaintext part of the buffer. See
+    /// `AeadScheme::open_with_aad`.
     fn open<'a>(
         &self,
         key: &AeadKey,
         nonce: AeadNonce,
+        aad: &[u8],
         ciphertext_and_tag: &'a mut [u8],
     ) -> Result<&'a mut [u8], Error>;
 
-    fn seal(&self, key: &AeadKey, nonce: AeadNonce, plaintext: &mut [u8]) -> Result<(), Error>;
+    /// Encrypts `plaintext || extra` in place into `ciphertext || tag`. See
+    /// `AeadScheme::seal_with_aad`.
+    fn seal(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        aad: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), Error>;
 }
 
 /// This represents the AES-128-GCM authenticated encryption algorithm. Notably, it implements
@@ -154,15 +320,14 @@ trait AeadSchemeInterface {
 pub(crate) struct Aes128Gcm;
 
 /// An opening / sealing key for use with the `Aes128Gcm` algorithm
-// These will just be two copies of the same thing. They're different types because ring requires
-// an OpeningKey for opening and a SealingKey for sealing. This incurs some 64 bytes of storage
-// overhead, but I frankly don't care.
-pub(crate) struct Aes128GcmKey {
-    opening_key: ring::aead::OpeningKey,
-    sealing_key: ring::aead::SealingKey,
-}
+pub struct Aes128GcmKey(backend::Key);
 
 impl AeadSchemeInterface for Aes128Gcm {
+    /// Returns the HPKE AEAD ID of AES-128-GCM, which is `0x0001`
+    fn aead_id(&self) -> u16 {
+        0x0001
+    }
+
     /// Returns `AES_128_GCM_KEY_SIZE`
     fn key_size(&self) -> usize {
         AES_128_GCM_KEY_SIZE
@@ -189,16 +354,7 @@ impl AeadSchemeInterface for Aes128Gcm {
             return Err(Error::EncryptionError("AES-GCM-128 requires 128-bit keys"));
         }
 
-        // Again, the opening and sealing keys for AES-GCM are the same.
-        let opening_key = ring::aead::OpeningKey::new(&ring::aead::AES_128_GCM, key_bytes)
-            .map_err(|_| Error::EncryptionError("Unspecified"))?;
-        let sealing_key = ring::aead::SealingKey::new(&ring::aead::AES_128_GCM, key_bytes)
-            .map_err(|_| Error::EncryptionError("Unspecified"))?;
-
-        let key = Aes128GcmKey {
-            opening_key,
-            sealing_key,
-        };
+        let key = Aes128GcmKey(backend::aes128gcm_key(key_bytes)?);
         Ok(AeadKey::Aes128GcmKey(key))
     }
 
@@ -215,7 +371,7 @@ impl AeadSchemeInterface for Aes128Gcm {
 
         let mut nonce = [0u8; AES_128_GCM_NONCE_SIZE];
         nonce.copy_from_slice(nonce_bytes);
-        Ok(AeadNonce::Aes128GcmNonce(ring::aead::Nonce::assume_unique_for_key(nonce)))
+        Ok(AeadNonce::Aes128GcmNonce(backend::nonce(nonce)))
     }
 
     /// Does an in-place authenticated decryption of the given ciphertext and tag. The input should
@@ -232,24 +388,19 @@ impl AeadSchemeInterface for Aes128Gcm {
         &self,
         key: &AeadKey,
         nonce: AeadNonce,
+        aad: &[u8],
         ciphertext_and_tag_modified_in_place: &'a mut [u8],
     ) -> Result<&'a mut [u8], Error> {
         let key = enum_variant!(key, AeadKey::Aes128GcmKey);
         let nonce = enum_variant!(nonce, AeadNonce::Aes128GcmNonce);
 
-        // We use the standard decryption function with no associated data, and no "prefix bytes".
-        // The length of the buffer is checked by the ring library. The function returns a
-        // plaintext = ciphertext_and_tag[..plaintext.len()] For more details on this function, see
-        // docs on ring::aead::open_in_place at
-        // https://briansmith.org/rustdoc/ring/aead/fn.open_in_place.html
-        ring::aead::open_in_place(
-            &key.opening_key,
+        backend::open(
+            &key.0,
             nonce,
-            ring::aead::Aad::empty(),
-            0,
+            aad,
             ciphertext_and_tag_modified_in_place,
+            AES_128_GCM_TAG_SIZE,
         )
-        .map_err(|_| Error::EncryptionError("Unspecified"))
     }
 
     /// Does an in-place authenticated encryption of the given plaintext. The input MUST look like
@@ -262,28 +413,441 @@ impl AeadSchemeInterface for Aes128Gcm {
     /// Returns: `Ok(())` on sucess, indicating that the inputted buffer contains the tagged
     /// ciphertext. If there is an error in any part of this process, it will be returned as an
     /// `Error::CryptoError` with description "Unspecified".
-    fn seal(&self, key: &AeadKey, nonce: AeadNonce, plaintext: &mut [u8]) -> Result<(), Error> {
+    fn seal(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        aad: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), Error> {
         let key = enum_variant!(key, AeadKey::Aes128GcmKey);
         let nonce = enum_variant!(nonce, AeadNonce::Aes128GcmNonce);
 
-        // We use the standard encryption function with no associated data. The length of the
-        // buffer is checked by the ring library.
+        backend::seal(&key.0, nonce, aad, plaintext, AES_128_GCM_TAG_SIZE)
+    }
+}
+
+/// This represents the AES-256-GCM authenticated encryption algorithm. Notably, it implements
+/// `AuthenticatedEncryption`.
+pub(crate) struct Aes256Gcm;
+
+/// An opening / sealing key for use with the `Aes256Gcm` algorithm
+pub struct Aes256GcmKey(backend::Key);
+
+impl AeadSchemeInterface for Aes256Gcm {
+    /// Returns the HPKE AEAD ID of AES-256-GCM, which is `0x0002`
+    fn aead_id(&self) -> u16 {
+        0x0002
+    }
+
+    /// Returns `AES_256_GCM_KEY_SIZE`
+    fn key_size(&self) -> usize {
+        AES_256_GCM_KEY_SIZE
+    }
+
+    /// Returns `AES_256_GCM_NONCE_SIZE`
+    fn nonce_size(&self) -> usize {
+        AES_256_GCM_NONCE_SIZE
+    }
+
+    /// Returns `AES_256_GCM_TAG_SIZE`
+    fn tag_size(&self) -> usize {
+        AES_256_GCM_TAG_SIZE
+    }
+
+    /// Makes a new AES-GCM key from the given key bytes.
+    ///
+    /// Requires: `key_bytes.len() == AES_256_GCM_KEY_SIZE`
+    ///
+    /// Returns: `Ok(key)` on success. On error, returns an `Error::EncryptionError`.
+    fn key_from_bytes(&self, key_bytes: &[u8]) -> Result<AeadKey, Error> {
+        if key_bytes.len() != AES_256_GCM_KEY_SIZE {
+            return Err(Error::EncryptionError("AES-GCM-256 requires 256-bit keys"));
+        }
+
+        let key = Aes256GcmKey(backend::aes256gcm_key(key_bytes)?);
+        Ok(AeadKey::Aes256GcmKey(key))
+    }
+
+    /// Makes a new AES-GCM nonce from the given bytes.
+    ///
+    /// Requires: `nonce_bytes.len() == AES_256_GCM_NONCE_SIZE`
+    ///
+    /// Returns: `Ok(nonce)` on sucess. If the above requirement is not met, returns an
+    /// `Error::EncryptionError`.
+    fn nonce_from_bytes(&self, nonce_bytes: &[u8]) -> Result<AeadNonce, Error> {
+        if nonce_bytes.len() != AES_256_GCM_NONCE_SIZE {
+            return Err(Error::EncryptionError("AES-GCM-256 requires 96-bit nonces"));
+        }
+
+        let mut nonce = [0u8; AES_256_GCM_NONCE_SIZE];
+        nonce.copy_from_slice(nonce_bytes);
+        Ok(AeadNonce::Aes256GcmNonce(backend::nonce(nonce)))
+    }
+
+    /// Does an in-place authenticated decryption of the given ciphertext and tag. This has the
+    /// same semantics as `Aes128Gcm::open`.
+    ///
+    /// Returns: `Ok(plaintext)` on sucess. If there is an error in any part of this process, it
+    /// will be returned as an `Error::CryptoError` with description "Unspecified".
+    fn open<'a>(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        aad: &[u8],
+        ciphertext_and_tag_modified_in_place: &'a mut [u8],
+    ) -> Result<&'a mut [u8], Error> {
+        let key = enum_variant!(key, AeadKey::Aes256GcmKey);
+        let nonce = enum_variant!(nonce, AeadNonce::Aes256GcmNonce);
+
+        backend::open(
+            &key.0,
+            nonce,
+            aad,
+            ciphertext_and_tag_modified_in_place,
+            AES_256_GCM_TAG_SIZE,
+        )
+    }
+
+    /// Does an in-place authenticated encryption of the given plaintext. This has the same
+    /// semantics as `Aes128Gcm::seal`.
+    ///
+    /// Requires: `plaintext.len() >= 16`
+    ///
+    /// Returns: `Ok(())` on sucess, indicating that the inputted buffer contains the tagged
+    /// ciphertext. If there is an error in any part of this process, it will be returned as an
+    /// `Error::CryptoError` with description "Unspecified".
+    fn seal(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        aad: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), Error> {
+        let key = enum_variant!(key, AeadKey::Aes256GcmKey);
+        let nonce = enum_variant!(nonce, AeadNonce::Aes256GcmNonce);
+
+        backend::seal(&key.0, nonce, aad, plaintext, AES_256_GCM_TAG_SIZE)
+    }
+}
+
+/// This represents the ChaCha20-Poly1305 authenticated encryption algorithm, as defined in RFC
+/// 8439. Notably, it implements `AuthenticatedEncryption`.
+pub(crate) struct ChaCha20Poly1305;
+
+/// An opening / sealing key for use with the `ChaCha20Poly1305` algorithm
+pub struct ChaCha20Poly1305Key(backend::Key);
+
+impl AeadSchemeInterface for ChaCha20Poly1305 {
+    /// Returns the HPKE AEAD ID of ChaCha20-Poly1305, which is `0x0003`
+    fn aead_id(&self) -> u16 {
+        0x0003
+    }
+
+    /// Returns `CHACHA20_POLY1305_KEY_SIZE`
+    fn key_size(&self) -> usize {
+        CHACHA20_POLY1305_KEY_SIZE
+    }
+
+    /// Returns `CHACHA20_POLY1305_NONCE_SIZE`
+    fn nonce_size(&self) -> usize {
+        CHACHA20_POLY1305_NONCE_SIZE
+    }
+
+    /// Returns `CHACHA20_POLY1305_TAG_SIZE`
+    fn tag_size(&self) -> usize {
+        CHACHA20_POLY1305_TAG_SIZE
+    }
+
+    /// Makes a new ChaCha20-Poly1305 key from the given key bytes.
+    ///
+    /// Requires: `key_bytes.len() == CHACHA20_POLY1305_KEY_SIZE`
+    ///
+    /// Returns: `Ok(key)` on success. On error, returns an `Error::EncryptionError`.
+    fn key_from_bytes(&self, key_bytes: &[u8]) -> Result<AeadKey, Error> {
+        if key_bytes.len() != CHACHA20_POLY1305_KEY_SIZE {
+            return Err(Error::EncryptionError("ChaCha20-Poly1305 requires 256-bit keys"));
+        }
+
+        let key = ChaCha20Poly1305Key(backend::chacha20poly1305_key(key_bytes)?);
+        Ok(AeadKey::ChaCha20Poly1305Key(key))
+    }
+
+    /// Makes a new ChaCha20-Poly1305 nonce from the given bytes.
+    ///
+    /// Requires: `nonce_bytes.len() == CHACHA20_POLY1305_NONCE_SIZE`
+    ///
+    /// Returns: `Ok(nonce)` on sucess. If the above requirement is not met, returns an
+    /// `Error::EncryptionError`.
+    fn nonce_from_bytes(&self, nonce_bytes: &[u8]) -> Result<AeadNonce, Error> {
+        if nonce_bytes.len() != CHACHA20_POLY1305_NONCE_SIZE {
+            return Err(Error::EncryptionError("ChaCha20-Poly1305 requires 96-bit nonces"));
+        }
+
+        let mut nonce = [0u8; CHACHA20_POLY1305_NONCE_SIZE];
+        nonce.copy_from_slice(nonce_bytes);
+        Ok(AeadNonce::ChaCha20Poly1305Nonce(backend::nonce(nonce)))
+    }
+
+    /// Does an in-place authenticated decryption of the given ciphertext and tag. This has the
+    /// same semantics as `Aes128Gcm::open`.
+    ///
+    /// Returns: `Ok(plaintext)` on sucess. If there is an error in any part of this process, it
+    /// will be returned as an `Error::CryptoError` with description "Unspecified".
+    fn open<'a>(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        aad: &[u8],
+        ciphertext_and_tag_modified_in_place: &'a mut [u8],
+    ) -> Result<&'a mut [u8], Error> {
+        let key = enum_variant!(key, AeadKey::ChaCha20Poly1305Key);
+        let nonce = enum_variant!(nonce, AeadNonce::ChaCha20Poly1305Nonce);
+
+        backend::open(
+            &key.0,
+            nonce,
+            aad,
+            ciphertext_and_tag_modified_in_place,
+            CHACHA20_POLY1305_TAG_SIZE,
+        )
+    }
+
+    /// Does an in-place authenticated encryption of the given plaintext. This has the same
+    /// semantics as `Aes128Gcm::seal`.
+    ///
+    /// Requires: `plaintext.len() >= 16`
+    ///
+    /// Returns: `Ok(())` on sucess, indicating that the inputted buffer contains the tagged
+    /// ciphertext. If there is an error in any part of this process, it will be returned as an
+    /// `Error::CryptoError` with description "Unspecified".
+    fn seal(
+        &self,
+        key: &AeadKey,
+        nonce: AeadNonce,
+        aad: &[u8],
+        plaintext: &mut [u8],
+    ) -> Result<(), Error> {
+        let key = enum_variant!(key, AeadKey::ChaCha20Poly1305Key);
+        let nonce = enum_variant!(nonce, AeadNonce::ChaCha20Poly1305Nonce);
+
+        backend::seal(&key.0, nonce, aad, plaintext, CHACHA20_POLY1305_TAG_SIZE)
+    }
+}
+
+/// The ring implementations of the AEAD primitives
+#[cfg(not(feature = "rustcrypto"))]
+mod backend {
+    use crate::error::Error;
+
+    /// An opening / sealing key
+    // These will just be two copies of the same thing. They're different types because ring
+    // requires an OpeningKey for opening and a SealingKey for sealing. This incurs some 64 bytes
+    // of storage overhead, but I frankly don't care.
+    // Note that ring doesn't zero its key schedules when they're dropped. The RustCrypto backend
+    // does.
+    pub(crate) struct Key {
+        opening_key: ring::aead::OpeningKey,
+        sealing_key: ring::aead::SealingKey,
+    }
+
+    pub(crate) type Nonce = ring::aead::Nonce;
+
+    fn key(alg: &'static ring::aead::Algorithm, key_bytes: &[u8]) -> Result<Key, Error> {
+        let opening_key = ring::aead::OpeningKey::new(alg, key_bytes)
+            .map_err(|_| Error::EncryptionError("Unspecified"))?;
+        let sealing_key = ring::aead::SealingKey::new(alg, key_bytes)
+            .map_err(|_| Error::EncryptionError("Unspecified"))?;
+
+        Ok(Key {
+            opening_key,
+            sealing_key,
+        })
+    }
+
+    pub(crate) fn aes128gcm_key(key_bytes: &[u8]) -> Result<Key, Error> {
+        key(&ring::aead::AES_128_GCM, key_bytes)
+    }
+
+    pub(crate) fn aes256gcm_key(key_bytes: &[u8]) -> Result<Key, Error> {
+        key(&ring::aead::AES_256_GCM, key_bytes)
+    }
+
+    pub(crate) fn chacha20poly1305_key(key_bytes: &[u8]) -> Result<Key, Error> {
+        key(&ring::aead::CHACHA20_POLY1305, key_bytes)
+    }
+
+    pub(crate) fn nonce(nonce_bytes: [u8; 12]) -> Nonce {
+        ring::aead::Nonce::assume_unique_for_key(nonce_bytes)
+    }
+
+    pub(crate) fn open<'a>(
+        key: &Key,
+        nonce: Nonce,
+        aad: &[u8],
+        ciphertext_and_tag_modified_in_place: &'a mut [u8],
+        _tag_size: usize,
+    ) -> Result<&'a mut [u8], Error> {
+        // We use the standard decryption function with no "prefix bytes".
+        // The length of the buffer is checked by the ring library. The function returns a
+        // plaintext = ciphertext_and_tag[..plaintext.len()] For more details on this function, see
+        // docs on ring::aead::open_in_place at
+        // https://briansmith.org/rustdoc/ring/aead/fn.open_in_place.html
+        ring::aead::open_in_place(
+            &key.opening_key,
+            nonce,
+            ring::aead::Aad::from(aad),
+            0,
+            ciphertext_and_tag_modified_in_place,
+        )
+        .map_err(|_| Error::EncryptionError("Unspecified"))
+    }
+
+    pub(crate) fn seal(
+        key: &Key,
+        nonce: Nonce,
+        aad: &[u8],
+        plaintext: &mut [u8],
+        tag_size: usize,
+    ) -> Result<(), Error> {
+        // We use the standard encryption function. The length of the buffer is checked by the ring
+        // library.
         // For more details on this function, see docs on ring::aead::seal_in_place at
         // https://briansmith.org/rustdoc/ring/aead/fn.seal_in_place.html
-        let res = ring::aead::seal_in_place(
+        ring::aead::seal_in_place(
             &key.sealing_key,
             nonce,
-            ring::aead::Aad::empty(),
+            ring::aead::Aad::from(aad),
             plaintext,
-            AES_128_GCM_TAG_SIZE,
-        );
+            tag_size,
+        )
+        .map(|_| ())
+        .map_err(|_| Error::EncryptionError("Unspecified"))
+    }
+}
+
+/// The pure-Rust implementations of the AEAD primitives, from the `aes-gcm` and
+/// `chacha20poly1305` crates
+#[cfg(feature = "rustcrypto")]
+mod backend {
+    use crate::error::Error;
 
-        if res.is_ok() {
-            Ok(())
-        } else {
-            Err(Error::EncryptionError("Unspecified"))
+    use aes_gcm::aead::{generic_array::GenericArray, AeadInPlace, KeyInit};
+
+    /// An opening / sealing key. Unlike ring, RustCrypto uses the same type for both.
+    pub(crate) enum Key {
+        Aes128Gcm(aes_gcm::Aes128Gcm),
+        Aes256Gcm(aes_gcm::Aes256Gcm),
+        ChaCha20Poly1305(chacha20poly1305::ChaCha20Poly1305),
+    }
+
+    pub(crate) type Nonce = [u8; 12];
+
+    pub(crate) fn aes128gcm_key(key_bytes: &[u8]) -> Result<Key, Error> {
+        aes_gcm::Aes128Gcm::new_from_slice(key_bytes)
+            .map(Key::Aes128Gcm)
+            .map_err(|_| Error::EncryptionError("Unspecified"))
+    }
+
+    pub(crate) fn aes256gcm_key(key_bytes: &[u8]) -> Result<Key, Error> {
+        aes_gcm::Aes256Gcm::new_from_slice(key_bytes)
+            .map(Key::Aes256Gcm)
+            .map_err(|_| Error::EncryptionError("Unspecified"))
+    }
+
+    pub(crate) fn chacha20poly1305_key(key_bytes: &[u8]) -> Result<Key, Error> {
+        chacha20poly1305::ChaCha20Poly1305::new_from_slice(key_bytes)
+            .map(Key::ChaCha20Poly1305)
+            .map_err(|_| Error::EncryptionError("Unspecified"))
+    }
+
+    pub(crate) fn nonce(nonce_bytes: [u8; 12]) -> Nonce {
+        nonce_bytes
+    }
+
+    pub(crate) fn open<'a>(
+        key: &Key,
+        nonce: Nonce,
+        aad: &[u8],
+        ciphertext_and_tag_modified_in_place: &'a mut [u8],
+        tag_size: usize,
+    ) -> Result<&'a mut [u8], Error> {
+        match key {
+            Key::Aes128Gcm(k) => {
+                open_with(k, nonce, aad, ciphertext_and_tag_modified_in_place, tag_size)
+            }
+            Key::Aes256Gcm(k) => {
+                open_with(k, nonce, aad, ciphertext_and_tag_modified_in_place, tag_size)
+            }
+            Key::ChaCha20Poly1305(k) => {
+                open_with(k, nonce, aad, ciphertext_and_tag_modified_in_place, tag_size)
+            }
+        }
+    }
+
+    pub(crate) fn seal(
+        key: &Key,
+        nonce: Nonce,
+        aad: &[u8],
+        plaintext: &mut [u8],
+        tag_size: usize,
+    ) -> Result<(), Error> {
+        match key {
+            Key::Aes128Gcm(k) => seal_with(k, nonce, aad, plaintext, tag_size),
+            Key::Aes256Gcm(k) => seal_with(k, nonce, aad, plaintext, tag_size),
+            Key::ChaCha20Poly1305(k) => seal_with(k, nonce, aad, plaintext, tag_size),
         }
     }
+
+    // The input looks like ciphertext || tag. We decrypt the ciphertext in place, and return the
+    // subslice that holds the plaintext.
+    fn open_with<'a, C: AeadInPlace>(
+        cipher: &C,
+        nonce: Nonce,
+        aad: &[u8],
+        ciphertext_and_tag: &'a mut [u8],
+        tag_size: usize,
+    ) -> Result<&'a mut [u8], Error> {
+        if ciphertext_and_tag.len() < tag_size {
+            return Err(Error::EncryptionError("Unspecified"));
+        }
+        let ciphertext_len = ciphertext_and_tag.len() - tag_size;
+        let (ciphertext, tag) = ciphertext_and_tag.split_at_mut(ciphertext_len);
+
+        cipher
+            .decrypt_in_place_detached(
+                GenericArray::from_slice(&nonce),
+                aad,
+                ciphertext,
+                GenericArray::from_slice(tag),
+            )
+            .map_err(|_| Error::EncryptionError("Unspecified"))?;
+
+        Ok(ciphertext)
+    }
+
+    // The input looks like plaintext || extra. We encrypt the plaintext in place, and write the
+    // tag over the extra bytes.
+    fn seal_with<C: AeadInPlace>(
+        cipher: &C,
+        nonce: Nonce,
+        aad: &[u8],
+        plaintext_and_extra: &mut [u8],
+        tag_size: usize,
+    ) -> Result<(), Error> {
+        if plaintext_and_extra.len() < tag_size {
+            return Err(Error::EncryptionError("Unspecified"));
+        }
+        let plaintext_len = plaintext_and_extra.len() - tag_size;
+        let (plaintext, tag_out) = plaintext_and_extra.split_at_mut(plaintext_len);
+
+        let tag = cipher
+            .encrypt_in_place_detached(GenericArray::from_slice(&nonce), aad, plaintext)
+            .map_err(|_| Error::EncryptionError("Unspecified"))?;
+        tag_out.copy_from_slice(&tag);
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -320,35 +884,142 @@ mod test {
 
     // Test that decrypt_k(encrypt_k(m)) == m
     #[quickcheck]
-    fn aes_gcm_correctness(plaintext: Vec<u8>, rng_seed: u64) {
-        // We're only working with AES-128 GCM
-        let scheme: &AeadScheme = &AES128GCM_IMPL;
-
+    fn aead_correctness(plaintext: Vec<u8>, rng_seed: u64) {
         let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
 
-        // The open method consumes our nonce, so make two nonces
-        let (nonce1, nonce2) = gen_nonce_pair(scheme, &mut rng);
-        let key = gen_key(scheme, &mut rng);
+        // Test every AEAD we have
+        for scheme in &[&AES128GCM_IMPL, &AES256GCM_IMPL, &CHACHA20POLY1305_IMPL] {
+            // The open method consumes our nonce, so make two nonces
+            let (nonce1, nonce2) = gen_nonce_pair(scheme, &mut rng);
+            let key = gen_key(scheme, &mut rng);
+
+            // Make sure there's enough room in the plaintext for the tag
+            let mut extended_plaintext = {
+                let tag_space = vec![0u8; scheme.tag_size()];
+                let mut pt_copy = plaintext.clone();
+                pt_copy.extend(tag_space);
+                pt_copy
+            };
+
+            // Encrypt
+            scheme
+                .seal(&key, nonce1, extended_plaintext.as_mut_slice())
+                .expect("failed to encrypt");
+
+            // Rename for clarity, since plaintext was modified in-place
+            let auth_ciphertext = extended_plaintext.as_mut_slice();
+
+            let recovered_plaintext =
+                scheme.open(&key, nonce2, auth_ciphertext).expect("failed to decrypt");
+
+            // Make sure we get out what we put in
+            assert_eq!(plaintext, recovered_plaintext);
+        }
+    }
 
-        // Make sure there's enough room in the plaintext for the tag
-        let mut extended_plaintext = {
-            let tag_space = vec![0u8; scheme.tag_size()];
-            let mut pt_copy = plaintext.clone();
-            pt_copy.extend(tag_space);
-            pt_copy
-        };
+    // Test that a ciphertext sealed with associated data only opens with the same associated data
+    #[quickcheck]
+    fn aead_aad_binding(plaintext: Vec<u8>, aad: Vec<u8>, rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
 
-        // Encrypt
-        scheme.seal(&key, nonce1, extended_plaintext.as_mut_slice()).expect("failed to encrypt");
+        for scheme in &[&AES128GCM_IMPL, &AES256GCM_IMPL, &CHACHA20POLY1305_IMPL] {
+            let key = gen_key(scheme, &mut rng);
+            let mut nonce_buf = vec![0u8; scheme.nonce_size()];
+            rng.fill_bytes(&mut nonce_buf);
+            let nonce = || AeadNonce::new_from_bytes(scheme, &nonce_buf).unwrap();
+
+            let mut ciphertext = plaintext.clone();
+            ciphertext.extend(vec![0u8; scheme.tag_size()]);
+            scheme.seal_with_aad(&key, nonce(), &aad, &mut ciphertext).unwrap();
+
+            // Different associated data, including none at all if there was some, doesn't open
+            let mut other_aad = aad.clone();
+            other_aad.push(0x01);
+            assert!(scheme
+                .open_with_aad(&key, nonce(), &other_aad, &mut ciphertext.clone())
+                .is_err());
+            if !aad.is_empty() {
+                assert!(scheme.open(&key, nonce(), &mut ciphertext.clone()).is_err());
+            }
+
+            let recovered = scheme.open_with_aad(&key, nonce(), &aad, &mut ciphertext).unwrap();
+            assert_eq!(recovered, plaintext.as_slice());
+        }
+    }
 
-        // Rename for clarity, since plaintext was modified in-place
-        let auth_ciphertext = extended_plaintext.as_mut_slice();
+    // Chunks sealed with a STREAM nonce sequence should only open at the same position, with the
+    // same last-chunk flag
+    #[quickcheck]
+    fn stream_nonce_positions(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let scheme = &AES128GCM_IMPL;
+        let key = gen_key(scheme, &mut rng);
+        let mut base_nonce = vec![0u8; scheme.nonce_size()];
+        rng.fill_bytes(&mut base_nonce);
+
+        // Seal chunk 1 of a stream whose last chunk is chunk 2
+        let mut seal_seq = StreamNonceSequence::new(scheme, &base_nonce).unwrap();
+        seal_seq.next_nonce(false).unwrap();
+        let mut chunk = vec![0u8; 3 + scheme.tag_size()];
+        chunk[..3].copy_from_slice(b"abc");
+        scheme.seal(&key, seal_seq.next_nonce(false).unwrap(), &mut chunk).unwrap();
+
+        // Opening it as chunk 0 or as a final chunk 1 should fail
+        let mut open_seq = StreamNonceSequence::new(scheme, &base_nonce).unwrap();
+        assert!(scheme
+            .open(&key, open_seq.next_nonce(false).unwrap(), &mut chunk.clone())
+            .is_err());
+        let mut last_seq = StreamNonceSequence::new(scheme, &base_nonce).unwrap();
+        last_seq.next_nonce(false).unwrap();
+        assert!(scheme.open(&key, last_seq.next_nonce(true).unwrap(), &mut chunk.clone()).is_err());
+
+        // Opening it as a non-final chunk 1 should succeed
+        let recovered = scheme.open(&key, open_seq.next_nonce(false).unwrap(), &mut chunk).unwrap();
+        assert_eq!(recovered, b"abc");
+    }
 
-        let recovered_plaintext =
-            scheme.open(&key, nonce2, auth_ciphertext).expect("failed to decrypt");
+    // AEAD test vector from https://tools.ietf.org/html/rfc8439#section-2.8.2
+    #[test]
+    fn chacha20poly1305_kat() {
+        let scheme = &CHACHA20POLY1305_IMPL;
+
+        let key = {
+            let bytes =
+                hex::decode("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")
+                    .unwrap();
+            AeadKey::new_from_bytes(scheme, &bytes).unwrap()
+        };
+        // The nonce is the 32-bit constant 0x07000000 followed by the 64-bit IV
+        let nonce_bytes = hex::decode("070000004041424344454647").unwrap();
+        let nonce = || AeadNonce::new_from_bytes(scheme, &nonce_bytes).unwrap();
+        let aad = hex::decode("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+                                 only one tip for the future, sunscreen would be it.";
+        let expected_ciphertext = hex::decode(
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca96712\
+             82fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58\
+             fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b6116",
+        )
+        .unwrap();
+        let expected_tag = hex::decode("1ae10b594f09e26a7e902ecbd0600691").unwrap();
+
+        let mut buf = plaintext.to_vec();
+        buf.extend(vec![0u8; scheme.tag_size()]);
+        scheme.seal_with_aad(&key, nonce(), &aad, &mut buf).unwrap();
+        let (ciphertext, tag) = buf.split_at(plaintext.len());
+        assert_eq!(ciphertext, expected_ciphertext.as_slice());
+        assert_eq!(tag, expected_tag.as_slice());
+
+        let recovered = scheme.open_with_aad(&key, nonce(), &aad, &mut buf).unwrap();
+        assert_eq!(recovered, plaintext);
+    }
 
-        // Make sure we get out what we put in
-        assert_eq!(plaintext, recovered_plaintext);
+    // AES-128 and AES-256 keys are not interchangeable
+    #[test]
+    fn aes_gcm_key_sizes() {
+        assert!(AeadKey::new_from_bytes(&AES128GCM_IMPL, &[0u8; 32]).is_err());
+        assert!(AeadKey::new_from_bytes(&AES256GCM_IMPL, &[0u8; 16]).is_err());
+        assert!(AeadKey::new_from_bytes(&AES256GCM_IMPL, &[0u8; 32]).is_ok());
     }
 
     // Test that perturbations in auth_ct := encrypt_k(m) make it fail to decrypt. This includes
@@ -2,9 +2,14 @@
 
 use crate::{
     crypto::{
-        aead::{AeadScheme, AES128GCM_IMPL},
-        dh::{DhPrivateKey, DhPublicKey, DhScheme, P256_IMPL, X25519_IMPL},
-        hash::{HashFunction, SHA256_IMPL},
+        aead::{AeadScheme, AES128GCM_IMPL, AES256GCM_IMPL, CHACHA20POLY1305_IMPL},
+        dh::{
+            DhPrivateKey, DhPublicKey, DhScheme, P521_IMPL, X25519_IMPL, X25519_MLKEM768_IMPL,
+            X448_IMPL,
+        },
+        hash::{HashFunction, SHA256_IMPL, SHA512_IMPL},
+        provider::DEFAULT_PROVIDER,
+        secret::Secret,
     },
     error::Error,
 };
@@ -12,14 +17,60 @@ use crate::{
 /// This represents the X25519-SHA256-AES128GCM ciphersuite
 pub const X25519_SHA256_AES128GCM: CipherSuite = CipherSuite {
     name: "X25519_SHA256_AES128GCM",
+    id: 0x0001,
     dh_impl: &X25519_IMPL,
     aead_impl: &AES128GCM_IMPL,
     hash_impl: &SHA256_IMPL,
 };
 
+/// This represents the X25519-SHA256-ChaCha20Poly1305 ciphersuite
+pub const X25519_SHA256_CHACHA20POLY1305: CipherSuite = CipherSuite {
+    name: "X25519_SHA256_CHACHA20POLY1305",
+    id: 0x0003,
+    dh_impl: &X25519_IMPL,
+    aead_impl: &CHACHA20POLY1305_IMPL,
+    hash_impl: &SHA256_IMPL,
+};
+
+/// This represents the X448-SHA512-AES256GCM ciphersuite
+pub const X448_SHA512_AES256GCM: CipherSuite = CipherSuite {
+    name: "X448_SHA512_AES256GCM",
+    id: 0x0004,
+    dh_impl: &X448_IMPL,
+    aead_impl: &AES256GCM_IMPL,
+    hash_impl: &SHA512_IMPL,
+};
+
+/// This represents the P521-SHA512-AES256GCM ciphersuite
+pub const P521_SHA512_AES256GCM: CipherSuite = CipherSuite {
+    name: "P521_SHA512_AES256GCM",
+    id: 0x0005,
+    dh_impl: &P521_IMPL,
+    aead_impl: &AES256GCM_IMPL,
+    hash_impl: &SHA512_IMPL,
+};
+
+/// This represents an experimental post-quantum hybrid ciphersuite. Key agreement is done with
+/// X25519 and ML-KEM-768 at the same time, so this is at least as strong as
+/// X25519_SHA256_AES128GCM. This is not standardized and should not be expected to interoperate.
+pub const X25519MLKEM768_SHA256_AES128GCM: CipherSuite = CipherSuite {
+    name: "X25519MLKEM768_SHA256_AES128GCM",
+    // This is experimental, so it gets an ID from the private-use range
+    id: 0xff01,
+    dh_impl: &X25519_MLKEM768_IMPL,
+    aead_impl: &AES128GCM_IMPL,
+    hash_impl: &SHA256_IMPL,
+};
+
+/// This represents the P256-SHA256-AES128GCM ciphersuite. P-256 public keys can be parsed, but
+/// there is no P-256 key agreement yet, so making or using a P-256 private key returns an
+/// `Error::DhError`. Until there is, this isn't in any provider, so it's never decoded off of the
+/// wire, and only the tests that parse the draft message vectors use it.
+#[cfg(test)]
 pub(crate) const P256_SHA256_AES128GCM: CipherSuite = CipherSuite {
     name: "P256_SHA256_AES128GCM",
-    dh_impl: &P256_IMPL,
+    id: 0x0002,
+    dh_impl: &crate::crypto::dh::P256_IMPL,
     aead_impl: &AES128GCM_IMPL,
     hash_impl: &SHA256_IMPL,
 };
@@ -30,6 +81,10 @@ pub struct CipherSuite {
     /// The name of this cipher suite
     pub(crate) name: &'static str,
 
+    /// The identifier of this cipher suite in the MLS cipher suite registry. This is what goes on
+    /// the wire.
+    pub(crate) id: u16,
+
     /// The struct that implements our key exchange functionality
     pub(crate) dh_impl: &'static DhScheme,
 
@@ -37,41 +92,84 @@ pub struct CipherSuite {
     pub(crate) aead_impl: &'static AeadScheme,
 
     /// The struct that implements our hashing functionality
-    pub(crate) hash_impl: &'static HashFunction,
+    pub(crate) hash_impl: &'static dyn HashFunction,
 }
 
-// TODO: Remove this impl if Add messages come with public_key indices in the future
-// CipherSuites are uniquely identified by their tags. We need this in order to dedup ciphersuite
+// CipherSuites are uniquely identified by their IDs. We need this in order to dedup ciphersuite
 // lists in UserInitKeys
 impl PartialEq for CipherSuite {
     fn eq(&self, other: &CipherSuite) -> bool {
-        self.name.eq(other.name)
+        self.id == other.id
     }
 }
 
+impl Eq for CipherSuite {}
+
 impl CipherSuite {
-    /// Given an arbitrary number of bytes, derives a Diffie-Hellman keypair. For this ciphersuite,
-    /// the function is simply `scalar: [u8; 32] = SHA256(bytes)`.
+    /// Makes a cipher suite out of the given parts. This is how a `CryptoProvider` offers a suite
+    /// that isn't built in, or a built-in suite with some of its algorithms swapped out. `id` is
+    /// what goes on the wire, so it has to be the suite's ID in the MLS cipher suite registry, or
+    /// one from the private-use range that every member agrees on.
+    pub const fn new(
+        name: &'static str,
+        id: u16,
+        dh_impl: &'static DhScheme,
+        aead_impl: &'static AeadScheme,
+        hash_impl: &'static dyn HashFunction,
+    ) -> CipherSuite {
+        CipherSuite {
+            name,
+            id,
+            dh_impl,
+            aead_impl,
+            hash_impl,
+        }
+    }
+
+    /// Looks up the cipher suite with the given registry ID in the default `CryptoProvider`
+    ///
+    /// Returns: `Some(cs)` if the default provider implements the cipher suite, and `None`
+    /// otherwise
+    pub fn from_id(id: u16) -> Option<&'static CipherSuite> {
+        DEFAULT_PROVIDER.cipher_suite_by_id(id)
+    }
+
+    /// Returns the identifier of this cipher suite in the MLS cipher suite registry
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Returns the name of this cipher suite, e.g., `"X25519_SHA256_AES128GCM"`
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Given an arbitrary number of bytes, derives a Diffie-Hellman keypair. For
+    /// X25519_SHA256_AES128GCM, the function is simply `scalar: [u8; 32] = SHA256(bytes)`. For
+    /// X448_SHA512_AES256GCM, the scalar is the first 56 bytes of `SHA512(bytes)`. For
+    /// P521_SHA512_AES256GCM, the scalar is `SHA512(bytes)` interpreted as a big-endian integer.
+    /// For X25519MLKEM768_SHA256_AES128GCM, `SHA256(bytes)` is the X25519 scalar, and the ML-KEM
+    /// seed is derived from it.
     ///
-    /// Requires: `bytes.len() == self.hash_impl.digest_size()`
+    /// This is the draft's Derive-Key-Pair, not RFC 9180's `DeriveKeyPair`, so keys derived here
+    /// (e.g., an epoch's external key pair) won't match an RFC 9420 implementation's.
     ///
-    /// Returns: `Ok((pubkey, privkey))` on success. If the above condition is not met, returns an
-    /// `Error::ValidationError`. If something goes wrong in key derivation, returns an
-    /// `Error::CryptoError`.
+    /// This is deterministic, so it takes no RNG. To make a random key pair, use
+    /// `DhPrivateKey::new_from_random` with a `CryptoRng`.
+    ///
+    /// Input of any length is accepted, since it is hashed before use. Callers in the protocol
+    /// pass `Hash.length` bytes of derived secret; the official crypto test vectors use shorter
+    /// seeds.
+    ///
+    /// Returns: `Ok((pubkey, privkey))` on success. If something goes wrong in key derivation,
+    /// returns an `Error::CryptoError`.
     pub(crate) fn derive_key_pair(
         &self,
         bytes: &[u8],
     ) -> Result<(DhPublicKey, DhPrivateKey), Error> {
-        // The spec requires this condition in the definition of Derive-Key-Pair
-        // TODO: URGENT: Uncomment this check once the official crypto test cases use appropriately
-        // sized input values for this function
-        //if bytes.len() != self.hash_impl.digest_size() {
-        //    return Err(Error::ValidationError("Derive-Key-Pair input length != Hash.length"));
-        //}
-
-        // Hash the input and use the digest as a private key
-        let digest = self.hash_impl.hash_bytes(bytes);
-        let privkey = DhPrivateKey::new_from_bytes(self.dh_impl, digest.as_bytes())?;
+        // Hash the input and use the digest as a private key. The digest is as secret as the key.
+        let digest = Secret::from(self.hash_impl.hash_bytes(bytes));
+        let privkey = DhPrivateKey::new_from_digest(self.dh_impl, digest.as_bytes())?;
         // Derive the pubkey
         let pubkey = DhPublicKey::new_from_private_key(self.dh_impl, &privkey);
 
@@ -85,3 +183,27 @@ impl core::fmt::Debug for CipherSuite {
         f.write_str(self.name)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The suites with a registry entry have to go on the wire with the IDs from the MLS cipher
+    // suite registry in RFC 9420 §17.1
+    #[test]
+    fn registry_ids() {
+        assert_eq!(X25519_SHA256_AES128GCM.id(), 0x0001);
+        assert_eq!(P256_SHA256_AES128GCM.id(), 0x0002);
+        assert_eq!(X25519_SHA256_CHACHA20POLY1305.id(), 0x0003);
+        assert_eq!(X448_SHA512_AES256GCM.id(), 0x0004);
+        assert_eq!(P521_SHA512_AES256GCM.id(), 0x0005);
+
+        // And they should be found by those IDs, except for P-256, which can't be used yet
+        for &id in [0x0001, 0x0003, 0x0004, 0x0005].iter() {
+            assert_eq!(CipherSuite::from_id(id).unwrap().id(), id);
+        }
+        assert!(CipherSuite::from_id(0x0002).is_none());
+        // 0x0000 is reserved
+        assert!(CipherSuite::from_id(0x0000).is_none());
+    }
+}
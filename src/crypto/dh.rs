@@ -1,21 +1,99 @@
-use crate::crypto::rng::CryptoRng;
+//! Defines `DhScheme`, the key agreement part of a cipher suite, along with its keys and shared
+//! secrets. Implement `DhSchemeInterface` to bring a DH scheme or KEM this crate doesn't ship
+//! with.
+
+use crate::crypto::{
+    hash::{HashFunction, SHA512_IMPL},
+    key_ops::DhPrivateKeyOps,
+    rng::CryptoRng,
+    secret::Secret,
+};
 use crate::error::Error;
 
+use std::sync::Arc;
+
+use zeroize::Zeroize;
+
 /// A type representing the X25519 DH scheme
-pub(crate) const X25519_IMPL: DhScheme = DhScheme(&X25519);
+pub const X25519_IMPL: DhScheme = DhScheme(&X25519);
+
+/// A type representing the X448 DH scheme
+pub const X448_IMPL: DhScheme = DhScheme(&X448);
+
+/// A type representing the P-521 ECDH scheme
+pub const P521_IMPL: DhScheme = DhScheme(&P521);
+
+/// A type representing the experimental X25519 + ML-KEM-768 hybrid KEM
+pub const X25519_MLKEM768_IMPL: DhScheme = DhScheme(&X25519MlKem768);
 
+/// A placeholder for the P-256 DH scheme. Only P-256 public keys can be parsed, so only the tests
+/// that parse the draft message vectors use this.
+#[cfg(test)]
 pub(crate) const P256_IMPL: DhScheme = DhScheme(&DummyP256);
 
 const X25519_POINT_SIZE: usize = 32;
 const X25519_SCALAR_SIZE: usize = 32;
 
+const X448_POINT_SIZE: usize = 56;
+const X448_SCALAR_SIZE: usize = 56;
+
+// Public keys are uncompressed SEC1 points: 0x04 || x || y
+const P521_POINT_SIZE: usize = 1 + 2 * 66;
+const P521_SCALAR_SIZE: usize = 66;
+
+const MLKEM768_ENCAPSULATION_KEY_SIZE: usize = 1184;
+const MLKEM768_CIPHERTEXT_SIZE: usize = 1088;
+const MLKEM768_SEED_SIZE: usize = 64;
+
+// Hybrid public keys are X25519 point || ML-KEM-768 encapsulation key. Hybrid private keys are
+// X25519 scalar || ML-KEM-768 key generation seed. Hybrid encapsulations are X25519 ephemeral
+// point || ML-KEM-768 ciphertext.
+const X25519_MLKEM768_PUBLIC_KEY_SIZE: usize = X25519_POINT_SIZE + MLKEM768_ENCAPSULATION_KEY_SIZE;
+const X25519_MLKEM768_PRIVATE_KEY_SIZE: usize = X25519_SCALAR_SIZE + MLKEM768_SEED_SIZE;
+const X25519_MLKEM768_ENCAPSULATION_SIZE: usize = X25519_POINT_SIZE + MLKEM768_CIPHERTEXT_SIZE;
+
 /// An enum of possible types for a private DH value, depending on the underlying algorithm. In EC
 /// terminology, this is a scalar in the base field. In finite-field terminology, this is an
 /// exponent.
 #[derive(Clone)]
-pub(crate) enum DhPrivateKey {
+pub enum DhPrivateKey {
     /// A scalar value in Curve25519
     X25519PrivateKey(x25519_dalek::StaticSecret),
+    /// A scalar value in Curve448. We store the unclamped bytes, since `x448::Secret` isn't
+    /// `Clone`. Clamping happens every time the scalar is used. This is always 56 bytes long.
+    X448PrivateKey(Secret),
+    /// A nonzero scalar value modulo the order of the P-521 group
+    P521PrivateKey(p521::SecretKey),
+    /// An X25519 scalar together with the seed an ML-KEM-768 key pair is generated from
+    X25519MlKem768PrivateKey(HybridPrivateKey),
+    /// A key held outside of this process. All we have is a handle and the public key.
+    External(ExternalDhPrivateKey),
+    /// The bytes of a private key in a scheme that isn't built in. This is what a
+    /// `DhSchemeInterface` that this crate doesn't ship with makes its private keys out of.
+    Custom(Secret),
+}
+
+/// A handle to a private key held outside of this process, along with its public key. The public
+/// key is checked to be valid for the DH scheme when the handle is made.
+#[derive(Clone)]
+pub struct ExternalDhPrivateKey {
+    ops: Arc<dyn DhPrivateKeyOps>,
+    public_key: DhPublicKey,
+}
+
+/// The private key of the X25519 + ML-KEM-768 hybrid KEM. We store the ML-KEM seed rather than
+/// the expanded decapsulation key, since that's 64 bytes instead of 2400.
+#[derive(Clone)]
+pub struct HybridPrivateKey {
+    x25519: x25519_dalek::StaticSecret,
+    mlkem_seed: [u8; MLKEM768_SEED_SIZE],
+}
+
+// StaticSecret zeroes itself, but the seed is a plain array
+impl Drop for HybridPrivateKey {
+    fn drop(&mut self) {
+        self.mlkem_seed.zeroize();
+    }
 }
 
 impl DhPrivateKey {
@@ -30,6 +108,25 @@ impl DhPrivateKey {
         scheme.0.private_key_from_bytes(bytes)
     }
 
+    /// Returns the bytes that `DhPrivateKey::new_from_bytes` would make this key from
+    ///
+    /// Returns: `Some(bytes)` on success. If the key is held outside of this process, returns
+    /// `None`.
+    pub(crate) fn to_secret_bytes(&self) -> Option<Secret> {
+        match self {
+            DhPrivateKey::X25519PrivateKey(scalar) => {
+                Some(Secret::from(scalar.to_bytes().to_vec()))
+            }
+            DhPrivateKey::X448PrivateKey(scalar) => Some(scalar.clone()),
+            DhPrivateKey::P521PrivateKey(scalar) => Some(Secret::from(scalar.to_bytes().to_vec())),
+            DhPrivateKey::X25519MlKem768PrivateKey(hybrid) => Some(
+                hybrid.x25519.to_bytes().iter().chain(hybrid.mlkem_seed.iter()).cloned().collect(),
+            ),
+            DhPrivateKey::External(_) => None,
+            DhPrivateKey::Custom(bytes) => Some(bytes.clone()),
+        }
+    }
+
     // This just passes through to DhSchemeInterface::private_key_from_random
     /// Generates a random private key
     ///
@@ -44,6 +141,31 @@ impl DhPrivateKey {
     {
         scheme.0.private_key_from_random(csprng)
     }
+
+    // This just passes through to DhSchemeInterface::private_key_from_digest
+    /// Makes a `DhPrivateKey` from the given hash digest. This is what's used in
+    /// `Derive-Key-Pair`.
+    ///
+    /// Returns: `Ok(private_key)` on success. Otherwise, if the digest isn't usable as a private
+    /// key for this scheme, returns `Error::DhError`.
+    pub(crate) fn new_from_digest(scheme: &DhScheme, digest: &[u8]) -> Result<DhPrivateKey, Error> {
+        scheme.0.private_key_from_digest(digest)
+    }
+
+    /// Makes a `DhPrivateKey` out of a handle to a key held outside of this process
+    ///
+    /// Returns: `Ok(private_key)` on success. If the handle's public key isn't a valid public key
+    /// for this scheme, returns `Error::DhError`.
+    pub(crate) fn new_external(
+        scheme: &DhScheme,
+        ops: Arc<dyn DhPrivateKeyOps>,
+    ) -> Result<DhPrivateKey, Error> {
+        let public_key = scheme.0.public_key_from_bytes(&ops.public_key_bytes())?;
+        Ok(DhPrivateKey::External(ExternalDhPrivateKey {
+            ops,
+            public_key,
+        }))
+    }
 }
 
 impl core::fmt::Debug for DhPrivateKey {
@@ -55,9 +177,19 @@ impl core::fmt::Debug for DhPrivateKey {
 /// An enum of possible types for a DH shared secret, depending on the underlying algorithm. This
 /// is mathematically the same as a point, but it is a secret value, not a public key, so we make
 /// the same distinction that `dalek` makes
-pub(crate) enum DhSharedSecret {
+pub enum DhSharedSecret {
     /// A Curve25519 shared secret
     X25519SharedSecret(x25519_dalek::SharedSecret),
+    /// A Curve448 shared secret
+    X448SharedSecret(x448::SharedSecret),
+    /// The x-coordinate of a P-521 shared point
+    P521SharedSecret(p521::elliptic_curve::ecdh::SharedSecret<p521::NistP521>),
+    /// The X25519 shared secret concatenated with the ML-KEM-768 shared key
+    X25519MlKem768SharedSecret(Secret),
+    /// A shared secret that was computed outside of this process
+    ExternalSharedSecret(Secret),
+    /// A shared secret in a scheme that isn't built in
+    Custom(Secret),
 }
 
 impl DhSharedSecret {
@@ -65,27 +197,45 @@ impl DhSharedSecret {
     pub(crate) fn as_bytes(&self) -> &[u8] {
         match self {
             DhSharedSecret::X25519SharedSecret(p) => p.as_bytes(),
+            DhSharedSecret::X448SharedSecret(p) => p.as_bytes(),
+            DhSharedSecret::P521SharedSecret(p) => p.raw_secret_bytes().as_slice(),
+            DhSharedSecret::X25519MlKem768SharedSecret(p) => p.as_bytes(),
+            DhSharedSecret::ExternalSharedSecret(p) => p.as_bytes(),
+            DhSharedSecret::Custom(p) => p.as_bytes(),
         }
     }
 }
 
 // opaque HPKEPublicKey<1..2^16-1>
 /// This is the form that all `DhPublicKey`s take when being sent or received over the wire
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
-#[serde(rename = "DhPublicKeyRaw__bound_u16")]
-pub(crate) struct DhPublicKeyRaw(pub(crate) Vec<u8>);
+#[tls(bound = "u16")]
+pub struct DhPublicKeyRaw(pub(crate) Vec<u8>);
 
 /// An enum of possible types for a public DH value, depending on the underlying algorithm. In EC
 /// terminology, this is a point on the curve. In finite-field terminology, this is a field
-/// element. The `Raw` variant only gets instantiated at the serialization/deserialization
+/// element. For KEMs, this is either a public key or an encapsulation of a shared secret. The
+/// `Raw` variant only gets instantiated at the serialization/deserialization
 /// boundary, and should never be dealt with directly. The `CryptoUpcast` trait should take care of
 /// this.
 #[derive(Clone)]
 #[cfg_attr(test, derive(Debug))]
-pub(crate) enum DhPublicKey {
+pub enum DhPublicKey {
     /// A curve point in Curve25519
     X25519PublicKey(x25519_dalek::PublicKey),
+    /// A curve point in Curve448
+    X448PublicKey([u8; X448_POINT_SIZE]),
+    /// A curve point in P-521, stored in its uncompressed SEC1 encoding. The encoding is checked
+    /// to be a valid point when the key is made.
+    P521PublicKey(Vec<u8>),
+    /// An X25519 point followed by an ML-KEM-768 encapsulation key
+    X25519MlKem768PublicKey(Vec<u8>),
+    /// An X25519 ephemeral point followed by an ML-KEM-768 ciphertext. This is what a hybrid
+    /// encapsulation looks like on the wire.
+    X25519MlKem768Encapsulation(Vec<u8>),
+    /// A public key or encapsulation in a scheme that isn't built in, as it goes on the wire
+    Custom(Vec<u8>),
     Raw(DhPublicKeyRaw),
 }
 
@@ -98,6 +248,11 @@ impl DhPublicKey {
     pub(crate) fn as_bytes(&self) -> &[u8] {
         match self {
             DhPublicKey::X25519PublicKey(p) => p.as_bytes(),
+            DhPublicKey::X448PublicKey(p) => &p[..],
+            DhPublicKey::P521PublicKey(p) => p.as_slice(),
+            DhPublicKey::X25519MlKem768PublicKey(p) => p.as_slice(),
+            DhPublicKey::X25519MlKem768Encapsulation(p) => p.as_slice(),
+            DhPublicKey::Custom(p) => p.as_slice(),
             DhPublicKey::Raw(p) => p.0.as_slice(),
         }
     }
@@ -113,13 +268,29 @@ impl DhPublicKey {
         scheme.0.public_key_from_bytes(bytes)
     }
 
-    // This just passes through to DhSchemeInterface::public_key_from_private_key
+    // This just passes through to DhSchemeInterface::encapsulation_from_bytes
+    /// Makes an encapsulation (the thing that's sent alongside a ciphertext) from the given
+    /// bytes. For DH schemes, this is the same thing as a public key.
+    ///
+    /// Returns: `Ok(encapsulation)` on success. Otherwise, returns `Error::DhError`.
+    pub(crate) fn new_encapsulation_from_bytes(
+        scheme: &DhScheme,
+        bytes: &[u8],
+    ) -> Result<DhPublicKey, Error> {
+        scheme.0.encapsulation_from_bytes(bytes)
+    }
+
+    // This passes through to DhSchemeInterface::public_key_from_private_key, unless the key is
+    // external, in which case there's nothing to derive
     /// Derives a public key from the given private key
     pub(crate) fn new_from_private_key(
         scheme: &DhScheme,
         private_key: &DhPrivateKey,
     ) -> DhPublicKey {
-        scheme.0.public_key_from_private_key(private_key)
+        match private_key {
+            DhPrivateKey::External(k) => k.public_key.clone(),
+            _ => scheme.0.public_key_from_private_key(private_key),
+        }
     }
 }
 
@@ -134,50 +305,159 @@ impl subtle::ConstantTimeEq for DhPublicKey {
 // Secondly, I would like to support methods like AeadKey::new_from_bytes which would take in an
 // DhSchemeInterface, but this leaves two ways of instantiating a DhPublicKey: either with
 // new_from_bytes or with DhSchemeInterface::public_key_from_bytes. I think there should only be
-// one way of doing this, so we'll wrap the trait object and not export the trait. Thirdly, this is
-// in keeping with the design of SignatureScheme. Reasoning for that mess can be found in sig.rs.
-pub(crate) struct DhScheme(&'static dyn DhSchemeInterface);
+// one way of doing this, so we'll wrap the trait object and only give out the wrapper. The trait
+// is exported so that providers can implement it. Thirdly, this is in keeping with the design of
+// SignatureScheme. Reasoning for that mess can be found in sig.rs.
+/// A type representing a DH scheme or KEM
+pub struct DhScheme(&'static dyn DhSchemeInterface);
 
 impl DhScheme {
+    /// Wraps an implementation of a DH scheme or KEM, so that it can go in a `CipherSuite`
+    pub const fn new(scheme: &'static dyn DhSchemeInterface) -> DhScheme {
+        DhScheme(scheme)
+    }
+
+    // This just passes through to DhSchemeInterface::kem_id
+    /// Returns the HPKE KEM ID of this scheme
+    pub(crate) fn kem_id(&self) -> u16 {
+        self.0.kem_id()
+    }
+
+    // Outside of tests, everything goes through encapsulate/decapsulate. Not every scheme can do
+    // plain DH.
     // This just passes through to DhSchemeInterface::diffie_hellman
     /// Computes `privkey * Pubkey` where `privkey` is your local secret (a scalar) and `Pubkey` is
     /// someone's public key (a curve point)
     ///
     /// Returns: `Ok(shared_secret)` on success. If the computed shared secret is all zeros,
     /// returns an `Error::DhError`, as required by the spec
+    #[cfg(test)]
     pub(crate) fn diffie_hellman(
         &self,
         privkey: &DhPrivateKey,
         pubkey: &DhPublicKey,
     ) -> Result<DhSharedSecret, Error> {
+        if let DhPrivateKey::External(_) = privkey {
+            return Err(Error::DhError("External private keys can only decapsulate"));
+        }
         self.0.diffie_hellman(privkey, pubkey)
     }
+
+    // This just passes through to DhSchemeInterface::encapsulate
+    /// Derives a shared secret for the holder of `pubkey`, using `ephemeral` as the source of
+    /// randomness. For DH schemes, this is `ephemeral * Pubkey`.
+    ///
+    /// Returns: `Ok((shared_secret, encapsulation))` on success, where `encapsulation` is what
+    /// the recipient needs in order to decapsulate. Otherwise, returns an `Error::DhError`. In
+    /// particular, external private keys can't be used as ephemeral keys.
+    pub(crate) fn encapsulate(
+        &self,
+        ephemeral: &DhPrivateKey,
+        pubkey: &DhPublicKey,
+    ) -> Result<(DhSharedSecret, DhPublicKey), Error> {
+        if let DhPrivateKey::External(_) = ephemeral {
+            return Err(Error::DhError("External private keys can only decapsulate"));
+        }
+        self.0.encapsulate(ephemeral, pubkey)
+    }
+
+    // This passes through to DhSchemeInterface::decapsulate, or to the key's handle if it's
+    // external
+    /// Recovers the shared secret from an encapsulation made with `encapsulate` to our public
+    /// key. For DH schemes, this is `privkey * Encapsulation`.
+    ///
+    /// Returns: `Ok(shared_secret)` on success. Otherwise, returns an `Error::DhError`.
+    pub(crate) fn decapsulate(
+        &self,
+        privkey: &DhPrivateKey,
+        encapsulation: &DhPublicKey,
+    ) -> Result<DhSharedSecret, Error> {
+        match privkey {
+            DhPrivateKey::External(k) => {
+                let shared_secret = k.ops.decapsulate(encapsulation.as_bytes())?;
+                Ok(DhSharedSecret::ExternalSharedSecret(Secret::from(shared_secret)))
+            }
+            _ => self.0.decapsulate(privkey, encapsulation),
+        }
+    }
 }
 
 /// A trait representing any DH-like key-agreement algorithm. The notation it uses in documentation
 /// is that of elliptic curves, but these concepts should generalize to finite-fields, SIDH, CSIDH,
-/// etc.
-trait DhSchemeInterface {
+/// etc. Algorithms that aren't DH-shaped, like lattice KEMs, only have to implement the KEM
+/// methods `encapsulate`, `decapsulate`, and `encapsulation_from_bytes`. DH schemes get these for
+/// free. An implementation that isn't built in keeps its keys in `DhPrivateKey::Custom` and
+/// `DhPublicKey::Custom`, and its shared secrets in `DhSharedSecret::Custom`. It's never handed a
+/// `DhPrivateKey::External`, since `DhScheme` deals with those itself.
+pub trait DhSchemeInterface: Sync {
+    /// The identifier of this scheme in the HPKE KEM registry
+    fn kem_id(&self) -> u16;
+
+    /// Returns the size of encoded public keys, in bytes
     fn public_key_size(&self) -> usize;
 
+    /// Returns the size of encoded private keys, in bytes
     fn private_key_size(&self) -> usize;
 
+    /// Decodes a public key, and checks that it's valid for this scheme
     fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<DhPublicKey, Error>;
 
+    /// Derives the public key of the given private key
     fn public_key_from_private_key(&self, scalar: &DhPrivateKey) -> DhPublicKey;
 
+    /// Decodes a private key, and checks that it's valid for this scheme
     fn private_key_from_bytes(&self, bytes: &[u8]) -> Result<DhPrivateKey, Error>;
 
     // This has to take a dyn CryptoRng because DiffieHellman is itself a trait object inside a
     // CipherSuite. Trait objects can't have associated types, associated constants, or generic
     // methods.
+    /// Generates a random private key
     fn private_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<DhPrivateKey, Error>;
 
+    /// Makes a private key from a hash digest. By default, this truncates the digest to the size
+    /// of a private key. Schemes whose private keys are larger than the digest have to override
+    /// this.
+    fn private_key_from_digest(&self, digest: &[u8]) -> Result<DhPrivateKey, Error> {
+        let scalar_size = self.private_key_size();
+        if digest.len() < scalar_size {
+            return Err(Error::DhError("Hash digest is too short to derive a private key from"));
+        }
+        self.private_key_from_bytes(&digest[..scalar_size])
+    }
+
+    /// Computes `privkey * Pubkey`. If the result is all zeros, this has to return an
+    /// `Error::DhError`. KEMs that can't do plain DH return an `Error::DhError` here, and
+    /// override `encapsulate` and `decapsulate`.
     fn diffie_hellman(
         &self,
         privkey: &DhPrivateKey,
         pubkey: &DhPublicKey,
     ) -> Result<DhSharedSecret, Error>;
+
+    /// A DH encapsulation of a shared secret is just the ephemeral public key
+    fn encapsulation_from_bytes(&self, bytes: &[u8]) -> Result<DhPublicKey, Error> {
+        self.public_key_from_bytes(bytes)
+    }
+
+    /// The DH version of this is `(ephemeral * Pubkey, ephemeral * P)`
+    fn encapsulate(
+        &self,
+        ephemeral: &DhPrivateKey,
+        pubkey: &DhPublicKey,
+    ) -> Result<(DhSharedSecret, DhPublicKey), Error> {
+        let shared_secret = self.diffie_hellman(ephemeral, pubkey)?;
+        let encapsulation = self.public_key_from_private_key(ephemeral);
+        Ok((shared_secret, encapsulation))
+    }
+
+    /// The DH version of this is `privkey * Encapsulation`
+    fn decapsulate(
+        &self,
+        privkey: &DhPrivateKey,
+        encapsulation: &DhPublicKey,
+    ) -> Result<DhSharedSecret, Error> {
+        self.diffie_hellman(privkey, encapsulation)
+    }
 }
 
 /// This represents the X25519 Diffie-Hellman key agreement protocol. Notably, it implements
@@ -185,6 +465,11 @@ trait DhSchemeInterface {
 pub(crate) struct X25519;
 
 impl DhSchemeInterface for X25519 {
+    /// Returns the HPKE KEM ID of DHKEM(X25519, HKDF-SHA256), which is `0x0020`
+    fn kem_id(&self) -> u16 {
+        0x0020
+    }
+
     /// Returns the size of a point
     fn public_key_size(&self) -> usize {
         X25519_POINT_SIZE
@@ -273,9 +558,481 @@ impl DhSchemeInterface for X25519 {
     }
 }
 
+/// This represents the X448 Diffie-Hellman key agreement protocol. Notably, it implements
+/// `DiffieHellman`.
+pub(crate) struct X448;
+
+impl DhSchemeInterface for X448 {
+    /// Returns the HPKE KEM ID of DHKEM(X448, HKDF-SHA512), which is `0x0021`
+    fn kem_id(&self) -> u16 {
+        0x0021
+    }
+
+    /// Returns the size of a point
+    fn public_key_size(&self) -> usize {
+        X448_POINT_SIZE
+    }
+
+    /// Returns the size of a scalar
+    fn private_key_size(&self) -> usize {
+        X448_SCALAR_SIZE
+    }
+
+    /// Makes a `DhPublicKey` from the given bytes
+    ///
+    /// Requires: `bytes.len() == X448_POINT_SIZE == 56`
+    ///
+    /// Returns: `Ok(public_key)` on success. Otherwise, if `bytes.len() != 56` or the point is of
+    /// low order, returns `Error::DhError`.
+    fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<DhPublicKey, Error> {
+        // This has to be the right length
+        if bytes.len() != X448_POINT_SIZE {
+            return Err(Error::DhError("Wrong public key size"));
+        }
+        // x448 rejects low-order points on construction. We check this here so that bad points
+        // are caught at upcast time rather than at DH time.
+        if x448::PublicKey::from_bytes(bytes).is_none() {
+            return Err(Error::DhError("X448 public key is of low order"));
+        }
+
+        let mut buf = [0u8; X448_POINT_SIZE];
+        buf.copy_from_slice(bytes);
+        Ok(DhPublicKey::X448PublicKey(buf))
+    }
+
+    /// Calculates `scalar * P`, where `P` is the standard X448 basepoint. This function is used
+    /// for creating public keys for DHE.
+    fn public_key_from_private_key(&self, scalar: &DhPrivateKey) -> DhPublicKey {
+        let scalar = enum_variant!(scalar, DhPrivateKey::X448PrivateKey);
+        // This can't fail, since the slice is always the right length
+        let secret = x448::Secret::from_bytes(scalar.as_bytes()).unwrap();
+        let public_key = x448::PublicKey::from(&secret);
+
+        let mut buf = [0u8; X448_POINT_SIZE];
+        buf.copy_from_slice(&public_key.as_bytes()[..]);
+        DhPublicKey::X448PublicKey(buf)
+    }
+
+    /// Uses the given bytes as a scalar in GF(2^448 - 2^224 - 1)
+    ///
+    /// Requires: `bytes.len() == 56`
+    ///
+    /// Returns: `Ok(private_key)` on success. Otherwise, if `bytes.len() != 56`, returns
+    /// `Error::DhError`.
+    fn private_key_from_bytes(&self, bytes: &[u8]) -> Result<DhPrivateKey, Error> {
+        if bytes.len() != X448_SCALAR_SIZE {
+            Err(Error::DhError("Wrong scalar size"))
+        } else {
+            Ok(DhPrivateKey::X448PrivateKey(Secret::new_from_bytes(bytes)))
+        }
+    }
+
+    /// Generates a random private key
+    ///
+    /// Returns: `Ok(private_key)` on success. Otherwise, if something goes wrong with the RNG, it
+    /// returns `Error::OutOfEntropy`.
+    fn private_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<DhPrivateKey, Error> {
+        let mut buf = Secret::new_from_zeros(X448_SCALAR_SIZE);
+        csprng.try_fill_bytes(buf.as_mut_bytes()).map_err(|_| Error::OutOfEntropy)?;
+        Ok(DhPrivateKey::X448PrivateKey(buf))
+    }
+
+    /// Computes `privkey * Pubkey` where `privkey` is your local secret (a scalar) and `Pubkey` is
+    /// someone's public key (a curve point)
+    ///
+    /// Returns: `Ok(shared_secret)` on success. If the computed shared secret is all zeros,
+    /// returns an `Error::DhError`, as required by the spec
+    fn diffie_hellman(
+        &self,
+        privkey: &DhPrivateKey,
+        pubkey: &DhPublicKey,
+    ) -> Result<DhSharedSecret, Error> {
+        let privkey = enum_variant!(privkey, DhPrivateKey::X448PrivateKey);
+        let pubkey = enum_variant!(pubkey, DhPublicKey::X448PublicKey);
+
+        // Neither of these can fail, since the lengths are right and the point was checked when
+        // the public key was made
+        let secret = x448::Secret::from_bytes(privkey.as_bytes()).unwrap();
+        let public_key = x448::PublicKey::from_bytes(&pubkey[..])
+            .ok_or(Error::DhError("X448 public key is of low order"))?;
+
+        // x448 returns None if the shared secret is all zeros
+        secret
+            .as_diffie_hellman(&public_key)
+            .map(DhSharedSecret::X448SharedSecret)
+            .ok_or(Error::DhError("DH resulted in shared secret of all zeros"))
+    }
+}
+
+/// This represents the ECDH key agreement protocol over the NIST P-521 curve. Notably, it
+/// implements `DiffieHellman`.
+pub(crate) struct P521;
+
+impl DhSchemeInterface for P521 {
+    /// Returns the HPKE KEM ID of DHKEM(P-521, HKDF-SHA512), which is `0x0012`
+    fn kem_id(&self) -> u16 {
+        0x0012
+    }
+
+    /// Returns the size of an uncompressed point
+    fn public_key_size(&self) -> usize {
+        P521_POINT_SIZE
+    }
+
+    /// Returns the size of a scalar
+    fn private_key_size(&self) -> usize {
+        P521_SCALAR_SIZE
+    }
+
+    /// Makes a `DhPublicKey` from the given uncompressed SEC1 point
+    ///
+    /// Requires: `bytes.len() == P521_POINT_SIZE == 133`
+    ///
+    /// Returns: `Ok(public_key)` on success. Otherwise, if `bytes.len() != 133` or the bytes do
+    /// not represent a point on the curve, returns `Error::DhError`.
+    fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<DhPublicKey, Error> {
+        if bytes.len() != P521_POINT_SIZE {
+            return Err(Error::DhError("Wrong public key size"));
+        }
+        // Make sure this is actually a point on the curve
+        p521::PublicKey::from_sec1_bytes(bytes)
+            .map_err(|_| Error::DhError("P-521 public key is not a valid curve point"))?;
+
+        Ok(DhPublicKey::P521PublicKey(bytes.to_vec()))
+    }
+
+    /// Calculates `scalar * G`, where `G` is the standard P-521 generator. This function is used
+    /// for creating public keys for DHE.
+    fn public_key_from_private_key(&self, scalar: &DhPrivateKey) -> DhPublicKey {
+        use p521::elliptic_curve::sec1::ToEncodedPoint;
+
+        let scalar = enum_variant!(scalar, DhPrivateKey::P521PrivateKey);
+        let point = scalar.public_key().to_encoded_point(false);
+        DhPublicKey::P521PublicKey(point.as_bytes().to_vec())
+    }
+
+    /// Interprets the given bytes as a big-endian integer modulo the order of the P-521 group
+    ///
+    /// Requires: `bytes.len() == 66` and the integer is nonzero and less than the group order
+    ///
+    /// Returns: `Ok(private_key)` on success. Otherwise, returns `Error::DhError`.
+    fn private_key_from_bytes(&self, bytes: &[u8]) -> Result<DhPrivateKey, Error> {
+        if bytes.len() != P521_SCALAR_SIZE {
+            return Err(Error::DhError("Wrong scalar size"));
+        }
+        let secret = p521::SecretKey::from_slice(bytes)
+            .map_err(|_| Error::DhError("P-521 scalar is zero or out of range"))?;
+
+        Ok(DhPrivateKey::P521PrivateKey(secret))
+    }
+
+    /// Generates a random private key by rejection sampling
+    ///
+    /// Returns: `Ok(private_key)` on success. Otherwise, if something goes wrong with the RNG, it
+    /// returns `Error::OutOfEntropy`.
+    fn private_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<DhPrivateKey, Error> {
+        let mut buf = [0u8; P521_SCALAR_SIZE];
+        loop {
+            csprng.try_fill_bytes(&mut buf).map_err(|_| Error::OutOfEntropy)?;
+            // The group order is a 521-bit number, so mask the top byte down to its lowest bit.
+            // This makes the probability of rejection less than 1/2.
+            buf[0] &= 0x01;
+            if let Ok(privkey) = self.private_key_from_bytes(&buf) {
+                return Ok(privkey);
+            }
+        }
+    }
+
+    /// A SHA-512 digest is shorter than a P-521 scalar, so we interpret the digest as a big-endian
+    /// integer and left-pad it with zeros
+    fn private_key_from_digest(&self, digest: &[u8]) -> Result<DhPrivateKey, Error> {
+        if digest.len() > P521_SCALAR_SIZE {
+            return Err(Error::DhError("Hash digest is too long to derive a private key from"));
+        }
+        let mut buf = [0u8; P521_SCALAR_SIZE];
+        buf[P521_SCALAR_SIZE - digest.len()..].copy_from_slice(digest);
+        self.private_key_from_bytes(&buf)
+    }
+
+    /// Computes `privkey * Pubkey` where `privkey` is your local secret (a scalar) and `Pubkey` is
+    /// someone's public key (a curve point). The shared secret is the x-coordinate of the
+    /// resulting point.
+    ///
+    /// Returns: `Ok(shared_secret)` on success. If the public key cannot be parsed, returns an
+    /// `Error::DhError`.
+    fn diffie_hellman(
+        &self,
+        privkey: &DhPrivateKey,
+        pubkey: &DhPublicKey,
+    ) -> Result<DhSharedSecret, Error> {
+        let privkey = enum_variant!(privkey, DhPrivateKey::P521PrivateKey);
+        let pubkey = enum_variant!(pubkey, DhPublicKey::P521PublicKey);
+
+        let pubkey = p521::PublicKey::from_sec1_bytes(pubkey)
+            .map_err(|_| Error::DhError("P-521 public key is not a valid curve point"))?;
+
+        // Since the private key is nonzero and the public key is a valid point in a prime-order
+        // group, the output cannot be the identity
+        let ss = p521::elliptic_curve::ecdh::diffie_hellman(
+            privkey.to_nonzero_scalar(),
+            pubkey.as_affine(),
+        );
+        Ok(DhSharedSecret::P521SharedSecret(ss))
+    }
+}
+
+/// Makes an ML-KEM-768 key pair from a 64-byte seed, as in FIPS 203's `ML-KEM.KeyGen_internal`
+fn mlkem768_key_pair(
+    seed: &[u8; MLKEM768_SEED_SIZE],
+) -> (
+    <ml_kem::MlKem768 as ml_kem::KemCore>::DecapsulationKey,
+    <ml_kem::MlKem768 as ml_kem::KemCore>::EncapsulationKey,
+) {
+    use ml_kem::KemCore;
+
+    let mut d = [0u8; 32];
+    let mut z = [0u8; 32];
+    d.copy_from_slice(&seed[..32]);
+    z.copy_from_slice(&seed[32..]);
+    ml_kem::MlKem768::generate_deterministic(&d.into(), &z.into())
+}
+
+/// This represents an experimental post-quantum hybrid KEM. It runs X25519 and ML-KEM-768 side by
+/// side and concatenates their shared secrets. If either of the two is unbroken, the shared secret
+/// is secure. This is not DH-shaped, so it only does key agreement through the KEM methods.
+pub(crate) struct X25519MlKem768;
+
+impl X25519MlKem768 {
+    /// Splits a hybrid public key into its X25519 point and its ML-KEM-768 encapsulation key
+    fn split_public_key(
+        pubkey: &[u8],
+    ) -> (x25519_dalek::PublicKey, <ml_kem::MlKem768 as ml_kem::KemCore>::EncapsulationKey) {
+        use ml_kem::EncodedSizeUser;
+
+        let mut x25519_buf = [0u8; X25519_POINT_SIZE];
+        x25519_buf.copy_from_slice(&pubkey[..X25519_POINT_SIZE]);
+        // The length was checked when the public key was made, so this can't fail
+        let mlkem_encoded = ml_kem::Encoded::<
+            <ml_kem::MlKem768 as ml_kem::KemCore>::EncapsulationKey,
+        >::try_from(&pubkey[X25519_POINT_SIZE..])
+        .unwrap();
+        let mlkem_key =
+            <ml_kem::MlKem768 as ml_kem::KemCore>::EncapsulationKey::from_bytes(&mlkem_encoded);
+
+        (x25519_buf.into(), mlkem_key)
+    }
+
+    /// Computes an X25519 shared secret, rejecting the all-zero output
+    fn x25519(
+        privkey: &x25519_dalek::StaticSecret,
+        pubkey: &x25519_dalek::PublicKey,
+    ) -> Result<x25519_dalek::SharedSecret, Error> {
+        let ss = privkey.diffie_hellman(pubkey);
+        if ss.as_bytes() == &[0u8; 32] {
+            Err(Error::DhError("DH resulted in shared secret of all zeros"))
+        } else {
+            Ok(ss)
+        }
+    }
+}
+
+impl DhSchemeInterface for X25519MlKem768 {
+    /// Returns `0xff01`. This KEM isn't registered with IANA, so we pick something from the
+    /// private-use range.
+    fn kem_id(&self) -> u16 {
+        0xff01
+    }
+
+    /// Returns the size of an X25519 point plus an ML-KEM-768 encapsulation key
+    fn public_key_size(&self) -> usize {
+        X25519_MLKEM768_PUBLIC_KEY_SIZE
+    }
+
+    /// Returns the size of an X25519 scalar plus an ML-KEM-768 seed
+    fn private_key_size(&self) -> usize {
+        X25519_MLKEM768_PRIVATE_KEY_SIZE
+    }
+
+    /// Makes a `DhPublicKey` from the given bytes
+    ///
+    /// Requires: `bytes.len() == X25519_MLKEM768_PUBLIC_KEY_SIZE`
+    ///
+    /// Returns: `Ok(public_key)` on success. Otherwise, returns `Error::DhError`.
+    fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<DhPublicKey, Error> {
+        if bytes.len() != X25519_MLKEM768_PUBLIC_KEY_SIZE {
+            Err(Error::DhError("Wrong public key size"))
+        } else {
+            Ok(DhPublicKey::X25519MlKem768PublicKey(bytes.to_vec()))
+        }
+    }
+
+    /// Computes the X25519 public key and expands the ML-KEM-768 seed into an encapsulation key
+    fn public_key_from_private_key(&self, scalar: &DhPrivateKey) -> DhPublicKey {
+        use ml_kem::EncodedSizeUser;
+
+        let privkey = enum_variant!(scalar, DhPrivateKey::X25519MlKem768PrivateKey);
+        let x25519_public_key: x25519_dalek::PublicKey = (&privkey.x25519).into();
+        let (_, mlkem_key) = mlkem768_key_pair(&privkey.mlkem_seed);
+
+        let bytes = [&x25519_public_key.as_bytes()[..], mlkem_key.as_bytes().as_slice()].concat();
+        DhPublicKey::X25519MlKem768PublicKey(bytes)
+    }
+
+    /// Interprets the given bytes as an X25519 scalar followed by an ML-KEM-768 seed
+    ///
+    /// Requires: `bytes.len() == X25519_MLKEM768_PRIVATE_KEY_SIZE`
+    ///
+    /// Returns: `Ok(private_key)` on success. Otherwise, returns `Error::DhError`.
+    fn private_key_from_bytes(&self, bytes: &[u8]) -> Result<DhPrivateKey, Error> {
+        if bytes.len() != X25519_MLKEM768_PRIVATE_KEY_SIZE {
+            return Err(Error::DhError("Wrong private key size"));
+        }
+
+        let mut x25519_buf = [0u8; X25519_SCALAR_SIZE];
+        let mut mlkem_seed = [0u8; MLKEM768_SEED_SIZE];
+        x25519_buf.copy_from_slice(&bytes[..X25519_SCALAR_SIZE]);
+        mlkem_seed.copy_from_slice(&bytes[X25519_SCALAR_SIZE..]);
+
+        let private_key = HybridPrivateKey {
+            x25519: x25519_buf.into(),
+            mlkem_seed,
+        };
+        // Arrays are Copy, so the above left the originals behind
+        x25519_buf.zeroize();
+        mlkem_seed.zeroize();
+
+        Ok(DhPrivateKey::X25519MlKem768PrivateKey(private_key))
+    }
+
+    /// Generates a random private key
+    ///
+    /// Returns: `Ok(private_key)` on success. Otherwise, if something goes wrong with the RNG, it
+    /// returns `Error::OutOfEntropy`.
+    fn private_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<DhPrivateKey, Error> {
+        let mut buf = Secret::new_from_zeros(X25519_MLKEM768_PRIVATE_KEY_SIZE);
+        csprng.try_fill_bytes(buf.as_mut_bytes()).map_err(|_| Error::OutOfEntropy)?;
+        self.private_key_from_bytes(buf.as_bytes())
+    }
+
+    /// The digest is used directly as the X25519 scalar. The ML-KEM-768 seed is
+    /// `SHA512("mls10 mlkem seed" || digest)`.
+    fn private_key_from_digest(&self, digest: &[u8]) -> Result<DhPrivateKey, Error> {
+        if digest.len() < X25519_SCALAR_SIZE {
+            return Err(Error::DhError("Hash digest is too short to derive a private key from"));
+        }
+        let x25519_scalar = &digest[..X25519_SCALAR_SIZE];
+        let mlkem_seed = {
+            let sha512: &dyn HashFunction = &SHA512_IMPL;
+            let mut ctx = sha512.new_context();
+            ctx.feed_bytes(b"mls10 mlkem seed");
+            ctx.feed_bytes(digest);
+            Secret::from(ctx.finalize())
+        };
+
+        let private_key_bytes = Secret::from([x25519_scalar, mlkem_seed.as_bytes()].concat());
+        self.private_key_from_bytes(private_key_bytes.as_bytes())
+    }
+
+    /// This is a KEM, not a DH scheme. Use `encapsulate` and `decapsulate` instead.
+    fn diffie_hellman(
+        &self,
+        _privkey: &DhPrivateKey,
+        _pubkey: &DhPublicKey,
+    ) -> Result<DhSharedSecret, Error> {
+        Err(Error::DhError("X25519+ML-KEM-768 is a KEM and cannot do plain DH"))
+    }
+
+    /// Makes a hybrid encapsulation from the given bytes
+    ///
+    /// Requires: `bytes.len() == X25519_MLKEM768_ENCAPSULATION_SIZE`
+    ///
+    /// Returns: `Ok(encapsulation)` on success. Otherwise, returns `Error::DhError`.
+    fn encapsulation_from_bytes(&self, bytes: &[u8]) -> Result<DhPublicKey, Error> {
+        if bytes.len() != X25519_MLKEM768_ENCAPSULATION_SIZE {
+            Err(Error::DhError("Wrong encapsulation size"))
+        } else {
+            Ok(DhPublicKey::X25519MlKem768Encapsulation(bytes.to_vec()))
+        }
+    }
+
+    /// Does an X25519 DH with the ephemeral scalar, and an ML-KEM-768 encapsulation whose
+    /// randomness is the first 32 bytes of the ephemeral ML-KEM seed. This makes encapsulation
+    /// deterministic in the ephemeral private key, which is what ECIES expects.
+    fn encapsulate(
+        &self,
+        ephemeral: &DhPrivateKey,
+        pubkey: &DhPublicKey,
+    ) -> Result<(DhSharedSecret, DhPublicKey), Error> {
+        use ml_kem::EncapsulateDeterministic;
+
+        let ephemeral = enum_variant!(ephemeral, DhPrivateKey::X25519MlKem768PrivateKey);
+        let pubkey = enum_variant!(pubkey, DhPublicKey::X25519MlKem768PublicKey);
+        let (x25519_public_key, mlkem_key) = X25519MlKem768::split_public_key(pubkey);
+
+        // Classical half
+        let x25519_ephemeral_public_key: x25519_dalek::PublicKey = (&ephemeral.x25519).into();
+        let x25519_ss = X25519MlKem768::x25519(&ephemeral.x25519, &x25519_public_key)?;
+
+        // Post-quantum half
+        let mut m = [0u8; 32];
+        m.copy_from_slice(&ephemeral.mlkem_seed[..32]);
+        let (mlkem_ct, mlkem_ss) = mlkem_key
+            .encapsulate_deterministic(&m.into())
+            .map_err(|_| Error::DhError("ML-KEM-768 encapsulation failed"))?;
+
+        let shared_secret = Secret::from([&x25519_ss.as_bytes()[..], mlkem_ss.as_slice()].concat());
+        let encapsulation =
+            [&x25519_ephemeral_public_key.as_bytes()[..], mlkem_ct.as_slice()].concat();
+        Ok((
+            DhSharedSecret::X25519MlKem768SharedSecret(shared_secret),
+            DhPublicKey::X25519MlKem768Encapsulation(encapsulation),
+        ))
+    }
+
+    /// Undoes `encapsulate`. Note that ML-KEM has implicit rejection, so a mangled ciphertext
+    /// results in a garbage shared secret rather than an error. The AEAD catches this later.
+    fn decapsulate(
+        &self,
+        privkey: &DhPrivateKey,
+        encapsulation: &DhPublicKey,
+    ) -> Result<DhSharedSecret, Error> {
+        use ml_kem::kem::Decapsulate;
+
+        let privkey = enum_variant!(privkey, DhPrivateKey::X25519MlKem768PrivateKey);
+        let encapsulation = enum_variant!(encapsulation, DhPublicKey::X25519MlKem768Encapsulation);
+
+        let (x25519_bytes, mlkem_bytes) = encapsulation.split_at(X25519_POINT_SIZE);
+
+        // Classical half
+        let x25519_ephemeral_public_key = {
+            let mut buf = [0u8; X25519_POINT_SIZE];
+            buf.copy_from_slice(x25519_bytes);
+            x25519_dalek::PublicKey::from(buf)
+        };
+        let x25519_ss = X25519MlKem768::x25519(&privkey.x25519, &x25519_ephemeral_public_key)?;
+
+        // Post-quantum half. The length was checked when the encapsulation was made.
+        let (mlkem_key, _) = mlkem768_key_pair(&privkey.mlkem_seed);
+        let mlkem_ct = ml_kem::Ciphertext::<ml_kem::MlKem768>::try_from(mlkem_bytes).unwrap();
+        let mlkem_ss = mlkem_key
+            .decapsulate(&mlkem_ct)
+            .map_err(|_| Error::DhError("ML-KEM-768 decapsulation failed"))?;
+
+        let shared_secret = Secret::from([&x25519_ss.as_bytes()[..], mlkem_ss.as_slice()].concat());
+        Ok(DhSharedSecret::X25519MlKem768SharedSecret(shared_secret))
+    }
+}
+
+#[cfg(test)]
 pub(crate) struct DummyP256;
 
+#[cfg(test)]
 impl DhSchemeInterface for DummyP256 {
+    /// Returns the HPKE KEM ID of DHKEM(P-256, HKDF-SHA256), which is `0x0010`
+    fn kem_id(&self) -> u16 {
+        0x0010
+    }
+
     fn public_key_size(&self) -> usize {
         65
     }
@@ -293,19 +1050,20 @@ impl DhSchemeInterface for DummyP256 {
         }
     }
 
+    // Every way of making a P-256 private key fails, so there's never one to derive from
     fn public_key_from_private_key(&self, _scalar: &DhPrivateKey) -> DhPublicKey {
-        unimplemented!()
+        unreachable!("P-256 private keys can't be made")
     }
 
     fn private_key_from_bytes(&self, _bytes: &[u8]) -> Result<DhPrivateKey, Error> {
-        unimplemented!()
+        Err(Error::DhError("P-256 key agreement isn't implemented"))
     }
 
     // This has to take a dyn CryptoRng because DhSchemeInterface is used as a trait object inside
     // DhScheme. Trait objects can't have associated types, associated constants, or generic
     // methods.
     fn private_key_from_random(&self, _csprng: &mut dyn CryptoRng) -> Result<DhPrivateKey, Error> {
-        unimplemented!()
+        Err(Error::DhError("P-256 key agreement isn't implemented"))
     }
 
     fn diffie_hellman(
@@ -313,7 +1071,7 @@ impl DhSchemeInterface for DummyP256 {
         _privkey: &DhPrivateKey,
         _pubkey: &DhPublicKey,
     ) -> Result<DhSharedSecret, Error> {
-        unimplemented!()
+        Err(Error::DhError("P-256 key agreement isn't implemented"))
     }
 }
 
@@ -399,6 +1157,181 @@ mod test {
         assert_eq!(shared1.as_bytes(), shared2.as_bytes());
     }
 
+    // Scalar multiplication test vectors from https://tools.ietf.org/html/rfc7748#section-5.2
+    #[test]
+    fn x448_scalar_mult_kat() {
+        let scheme: &'static DhScheme = &X448_IMPL;
+
+        let vectors = [
+            (
+                "3d262fddf9ec8e88495266fea19a34d28882acef045104d0d1aae121\
+                 700a779c984c24f8cdd78fbff44943eba368f54b29259a4f1c600ad3",
+                "06fce640fa3487bfda5f6cf2d5263f8aad88334cbd07437f020f08f9\
+                 814dc031ddbdc38c19c6da2583fa5429db94ada18aa7a7fb4ef8a086",
+                "ce3e4ff95a60dc6697da1db1d85e6afbdf79b50a2412d7546d5f239f\
+                 e14fbaadeb445fc66a01b0779d98223961111e21766282f73dd96b6f",
+            ),
+            (
+                "203d494428b8399352665ddca42f9de8fef600908e0d461cb021f8c5\
+                 38345dd77c3e4806e25f46d3315c44e0a5b4371282dd2c8d5be3095f",
+                "0fbcc2f993cd56d3305b0b7d9e55d4c1a8fb5dbb52f8e9a1e9b6201b\
+                 165d015894e56c4d3570bee52fe205e28a78b91cdfbde71ce8d157db",
+                "884a02576239ff7a2f2f63b2db6a9ff37047ac13568e1e30fe63c4a7\
+                 ad1b3ee3a5700df34321d62077e63633c575c1c954514e99da7c179d",
+            ),
+        ];
+
+        for (scalar_hex, point_hex, expected_hex) in vectors.iter() {
+            let scalar =
+                DhPrivateKey::new_from_bytes(scheme, &hex::decode(scalar_hex).unwrap()).unwrap();
+            let point =
+                DhPublicKey::new_from_bytes(scheme, &hex::decode(point_hex).unwrap()).unwrap();
+            let product = scheme.diffie_hellman(&scalar, &point).unwrap();
+            assert_eq!(hex::encode(product.as_bytes()), *expected_hex);
+        }
+    }
+
+    // Diffie Hellman test vectors from https://tools.ietf.org/html/rfc7748#section-6.2
+    #[test]
+    fn x448_kat() {
+        let scheme: &'static DhScheme = &X448_IMPL;
+
+        let alice_scalar = {
+            let hex_str = "9a8f4925d1519f5775cf46b04b5800d4ee9ee8bae8bc5565d498c28d\
+                           d9c9baf574a9419744897391006382a6f127ab1d9ac2d8c0a598726b";
+            let bytes = hex::decode(hex_str).unwrap();
+            DhPrivateKey::new_from_bytes(scheme, &bytes).expect("couldn't make scalar from bytes")
+        };
+        let bob_scalar = {
+            let hex_str = "1c306a7ac2a0e2e0990b294470cba339e6453772b075811d8fad0d1d\
+                           6927c120bb5ee8972b0d3e21374c9c921b09d1b0366f10b65173992d";
+            let bytes = hex::decode(hex_str).unwrap();
+            DhPrivateKey::new_from_bytes(scheme, &bytes).expect("couldn't make scalar from bytes")
+        };
+
+        let alice_pubkey = DhPublicKey::new_from_private_key(scheme, &alice_scalar);
+        let bob_pubkey = DhPublicKey::new_from_private_key(scheme, &bob_scalar);
+        let shared_secret_a = scheme.diffie_hellman(&alice_scalar, &bob_pubkey).unwrap();
+        let shared_secret_b = scheme.diffie_hellman(&bob_scalar, &alice_pubkey).unwrap();
+
+        // Known-answer for aP
+        assert_eq!(
+            hex::encode(alice_pubkey.as_bytes()),
+            "9b08f7cc31b7e3e67d22d5aea121074a273bd2b83de09c63faa73d2c\
+             22c5d9bbc836647241d953d40c5b12da88120d53177f80e532c41fa0"
+        );
+        // Known-answer for bP
+        assert_eq!(
+            hex::encode(bob_pubkey.as_bytes()),
+            "3eb7a829b0cd20f5bcfc0b599b6feccf6da4627107bdb0d4f345b430\
+             27d8b972fc3e34fb4232a13ca706dcb57aec3dae07bdc1c67bf33609"
+        );
+        // Test b(aP) == a(bP)
+        assert_eq!(shared_secret_a.as_bytes(), shared_secret_b.as_bytes());
+        // Known-answer for abP
+        assert_eq!(
+            hex::encode(shared_secret_a.as_bytes()),
+            "07fff4181ac6cc95ec1c16a94a0f74d12da232ce40a77552281d282b\
+             b60c0b56fd2464c335543936521c24403085d59a449a5037514a879d"
+        );
+    }
+
+    #[quickcheck]
+    fn x448_correctness(secret_seed: u64) {
+        let scheme: &'static DhScheme = &X448_IMPL;
+
+        // Make two random secret keys, deterministically
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        let scalar1 = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+        let scalar2 = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+
+        let (point1, point2) = (
+            DhPublicKey::new_from_private_key(scheme, &scalar1),
+            DhPublicKey::new_from_private_key(scheme, &scalar2),
+        );
+        let (shared1, shared2) = (
+            scheme.diffie_hellman(&scalar1, &point2).unwrap(),
+            scheme.diffie_hellman(&scalar2, &point1).unwrap(),
+        );
+
+        assert_eq!(shared1.as_bytes(), shared2.as_bytes());
+
+        // Also make sure that public keys survive a round trip through bytes
+        let point1_copy = DhPublicKey::new_from_bytes(scheme, point1.as_bytes()).unwrap();
+        assert_eq!(point1.as_bytes(), point1_copy.as_bytes());
+    }
+
+    #[quickcheck]
+    fn p521_correctness(secret_seed: u64) {
+        let scheme: &'static DhScheme = &P521_IMPL;
+
+        // Make two random secret keys, deterministically
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        let scalar1 = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+        let scalar2 = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+
+        let (point1, point2) = (
+            DhPublicKey::new_from_private_key(scheme, &scalar1),
+            DhPublicKey::new_from_private_key(scheme, &scalar2),
+        );
+        let (shared1, shared2) = (
+            scheme.diffie_hellman(&scalar1, &point2).unwrap(),
+            scheme.diffie_hellman(&scalar2, &point1).unwrap(),
+        );
+
+        assert_eq!(shared1.as_bytes(), shared2.as_bytes());
+
+        // Also make sure that public keys survive a round trip through bytes
+        let point1_copy = DhPublicKey::new_from_bytes(scheme, point1.as_bytes()).unwrap();
+        assert_eq!(point1.as_bytes(), point1_copy.as_bytes());
+    }
+
+    // Points that aren't on the curve should be rejected
+    #[test]
+    fn p521_invalid_point() {
+        let mut bytes = vec![0u8; P521_POINT_SIZE];
+        bytes[0] = 0x04;
+        bytes[P521_POINT_SIZE - 1] = 0x01;
+        assert!(DhPublicKey::new_from_bytes(&P521_IMPL, &bytes).is_err());
+    }
+
+    #[quickcheck]
+    fn x25519_mlkem768_correctness(secret_seed: u64) {
+        let scheme: &'static DhScheme = &X25519_MLKEM768_IMPL;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        let privkey = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+        let pubkey = DhPublicKey::new_from_private_key(scheme, &privkey);
+        let ephemeral = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+
+        // Encapsulate to the public key, and make sure it decapsulates to the same thing
+        let (shared1, encapsulation) = scheme.encapsulate(&ephemeral, &pubkey).unwrap();
+        let encapsulation =
+            DhPublicKey::new_encapsulation_from_bytes(scheme, encapsulation.as_bytes()).unwrap();
+        let shared2 = scheme.decapsulate(&privkey, &encapsulation).unwrap();
+        assert_eq!(shared1.as_bytes(), shared2.as_bytes());
+
+        // A different recipient gets a different shared secret
+        let other_privkey = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+        let shared3 = scheme.decapsulate(&other_privkey, &encapsulation).unwrap();
+        assert_ne!(shared1.as_bytes(), shared3.as_bytes());
+    }
+
+    // The DH schemes should agree with themselves when used as KEMs
+    #[quickcheck]
+    fn dh_as_kem_correctness(secret_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        for scheme in &[&X25519_IMPL, &X448_IMPL, &P521_IMPL] {
+            let privkey = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+            let pubkey = DhPublicKey::new_from_private_key(scheme, &privkey);
+            let ephemeral = DhPrivateKey::new_from_random(scheme, &mut rng).unwrap();
+
+            let (shared1, encapsulation) = scheme.encapsulate(&ephemeral, &pubkey).unwrap();
+            let shared2 = scheme.decapsulate(&privkey, &encapsulation).unwrap();
+            assert_eq!(shared1.as_bytes(), shared2.as_bytes());
+        }
+    }
+
     // This comes from
     // https://github.com/mlswg/mls-implementations/blob/master/test_vectors/treesnodes.md
     #[test]
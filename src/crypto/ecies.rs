@@ -9,11 +9,11 @@ use crate::crypto::{
 use crate::error::Error;
 
 /// A label struct used for ECIES key/nonce derivation
-#[derive(Serialize, Deserialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 struct EciesLabel {
     length: u16,
     // opaque label<12..255> = "mls10 ecies " + Label;
-    #[serde(rename = "label__bound_u8")]
+    #[tls(bound = "u8")]
     label: Vec<u8>,
 }
 
@@ -27,14 +27,14 @@ impl EciesLabel {
 }
 
 /// A short ciphertext encrypted with the enclosed ephemeral DH key
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct EciesCiphertext {
     /// Pubkey the ciphertext is encrypted under
     pub(crate) ephemeral_public_key: DhPublicKey,
     /// The payload
     // opaque ciphertext<0..2^32-1>;
-    #[serde(rename = "ciphertext__bound_u32")]
+    #[tls(bound = "u32")]
     ciphertext: Vec<u8>,
 }
 
@@ -57,7 +57,7 @@ where
     encrypt_with_scalar(cs, others_public_key, plaintext, my_ephemeral_secret)
 }
 
-// TODO: Make this function secret-aware by making it take only ClearOnDrop values
+// TODO: Make this function secret-aware by making it take only Secret values
 
 /// Performs an ECIES encryption of a given plaintext under a given DH public key and a fixed scalar
 /// value. This is the deterministic function underlying `ecies_encrypt`, and is important for
@@ -79,12 +79,10 @@ pub(crate) fn encrypt_with_scalar(
         .expect("plaintext is too large to be encrypted");
     plaintext.resize(tagged_plaintext_size, 0u8);
 
-    // If my_ephermeral_secret is `a`, let this be `aP`
-    let my_ephemeral_public_key =
-        DhPublicKey::new_from_private_key(cs.dh_impl, &my_ephemeral_secret);
-
-    // This is `abP` where `bP` is the other person's public key is `bP`
-    let shared_secret = cs.dh_impl.diffie_hellman(&my_ephemeral_secret, &others_public_key)?;
+    // If my_ephermeral_secret is `a`, this is `(abP, aP)` where `bP` is the other person's public
+    // key. For KEMs that aren't DH-shaped, this is the KEM shared secret and encapsulation.
+    let (shared_secret, my_ephemeral_public_key) =
+        cs.dh_impl.encapsulate(&my_ephemeral_secret, &others_public_key)?;
 
     let (key, nonce) = derive_ecies_key_nonce(cs, shared_secret.as_bytes());
 
@@ -114,7 +112,7 @@ pub(crate) fn decrypt(
         mut ciphertext,
     } = ciphertext;
     // This is `abP` where `bP` is the other person's public key is `bP` and my secret key is `a`
-    let shared_secret = cs.dh_impl.diffie_hellman(&my_secret_key, &ephemeral_public_key)?;
+    let shared_secret = cs.dh_impl.decapsulate(&my_secret_key, &ephemeral_public_key)?;
 
     // Derive the key and nonce, then open the ciphertext. The length of the subslice it gives is
     // the length we'll truncate the plaintext to. Recall this happens because there was a MAC at
@@ -171,7 +169,7 @@ fn derive_ecies_key_nonce(cs: &CipherSuite, shared_secret_bytes: &[u8]) -> (Aead
 #[cfg(test)]
 mod test {
     use crate::crypto::{
-        ciphersuite::{CipherSuite, X25519_SHA256_AES128GCM},
+        ciphersuite::{CipherSuite, X25519MLKEM768_SHA256_AES128GCM, X25519_SHA256_AES128GCM},
         dh::{DhPrivateKey, DhPublicKey},
         ecies::{self, EciesCiphertext},
     };
@@ -179,7 +177,8 @@ mod test {
     use quickcheck_macros::quickcheck;
     use rand::SeedableRng;
 
-    const CIPHERSUITES: &[CipherSuite] = &[X25519_SHA256_AES128GCM];
+    const CIPHERSUITES: &[CipherSuite] =
+        &[X25519_SHA256_AES128GCM, X25519MLKEM768_SHA256_AES128GCM];
 
     // Checks that decrypt(encrypt_k(m)) == m
     #[quickcheck]
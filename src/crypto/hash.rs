@@ -1,17 +1,50 @@
-use crate::{error::Error, tls_ser};
+//! Defines the `HashFunction` trait, which everything that hashes (including HMAC and HKDF) goes
+//! through. Outputs are always `Vec`-backed, so implementors don't have to deal with
+//! `generic_array` lengths.
 
-use serde::ser::Serialize;
+use crate::{
+    crypto::secret::Secret,
+    error::Error,
+    tls_ser::{self, TlsSerialize},
+};
+
+use core::marker::PhantomData;
 
-pub(crate) const SHA256_IMPL: HashFunction = HashFunction {
+/// A singleton object representing SHA-256, as implemented by ring
+#[cfg(not(feature = "rustcrypto"))]
+pub(crate) const SHA256_IMPL: RingHash = RingHash {
     hash_alg: &ring::digest::SHA256,
+    kdf_id: 0x0001,
 };
 
+/// A singleton object representing SHA-512, as implemented by ring
+#[cfg(not(feature = "rustcrypto"))]
+pub(crate) const SHA512_IMPL: RingHash = RingHash {
+    hash_alg: &ring::digest::SHA512,
+    kdf_id: 0x0003,
+};
+
+/// A singleton object representing SHA-256, as implemented by the `sha2` crate
+#[cfg(feature = "rustcrypto")]
+pub(crate) const SHA256_IMPL: DigestHash<sha2::Sha256> = DigestHash::new(0x0001);
+
+/// A singleton object representing SHA-512, as implemented by the `sha2` crate
+#[cfg(feature = "rustcrypto")]
+pub(crate) const SHA512_IMPL: DigestHash<sha2::Sha512> = DigestHash::new(0x0003);
+
+/// The built-in SHA-256, for cipher suites made with `CipherSuite::new`
+pub const SHA256: &dyn HashFunction = &SHA256_IMPL;
+
+/// The built-in SHA-512, for cipher suites made with `CipherSuite::new`
+pub const SHA512: &dyn HashFunction = &SHA512_IMPL;
+
 // This isn't ring::digest::Digest because you can't deserialize those (there's no constructor).
 // TODO: We could be more efficient by making this an ArrayVec internally.
-/// A message digest of a hash function
-#[derive(Clone, Deserialize, Serialize)]
+/// A message digest of a hash function. The `Default` digest is empty, and only ever stands in for
+/// one that hasn't been filled in yet, e.g., in a field that's deserialized separately.
+#[derive(Clone, Default, TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
-#[serde(rename = "Digest__bound_u8")]
+#[tls(bound = "u8")]
 pub(crate) struct Digest(Vec<u8>);
 
 impl Digest {
@@ -19,14 +52,15 @@ impl Digest {
         self.0.as_slice()
     }
 
-    pub(crate) fn new_from_zeros(hash_impl: &HashFunction) -> Digest {
+    pub(crate) fn new_from_zeros(hash_impl: &dyn HashFunction) -> Digest {
         Digest(vec![0u8; hash_impl.digest_size()])
     }
 }
 
-impl From<ring::digest::Digest> for Digest {
-    fn from(d: ring::digest::Digest) -> Digest {
-        Digest(d.as_ref().to_vec())
+// Digests of secret values, like the one in Derive-Key-Pair, are secret themselves
+impl From<Digest> for Secret {
+    fn from(d: Digest) -> Secret {
+        Secret::from(d.0)
     }
 }
 
@@ -36,13 +70,36 @@ impl subtle::ConstantTimeEq for Digest {
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct HashFunction {
-    pub(crate) hash_alg: &'static ring::digest::Algorithm,
+/// A trait representing a cryptographic hash function. HMAC and HKDF are built on top of this, so
+/// this is all a new hash function has to implement in order to be used in a cipher suite.
+pub trait HashFunction: Sync {
+    /// Returns the length of this hash function's digests, in bytes
+    fn digest_size(&self) -> usize;
+
+    /// Returns the length of the blocks this hash function consumes, in bytes. HMAC needs this.
+    fn block_size(&self) -> usize;
+
+    /// Returns the identifier of HKDF with this hash function in the HPKE KDF registry
+    fn kdf_id(&self) -> u16;
+
+    /// Starts a new incremental hashing computation
+    fn new_state(&self) -> Box<dyn HashState>;
+}
+
+/// The state of an incremental hashing computation
+pub trait HashState {
+    /// Feeds the given bytes into the hash function
+    fn update(&mut self, bytes: &[u8]);
+
+    /// Finishes the computation and returns the digest. The digest MUST be `digest_size()` bytes
+    /// long.
+    fn finish(self: Box<Self>) -> Vec<u8>;
 }
 
-impl HashFunction {
-    pub(crate) fn hash_serializable<S: Serialize>(&self, msg: &S) -> Result<Digest, Error> {
+// These are inherent methods rather than trait methods because hash_serializable is generic, and
+// trait objects can't have generic methods
+impl<'a> dyn HashFunction + 'a {
+    pub(crate) fn hash_serializable<S: TlsSerialize>(&self, msg: &S) -> Result<Digest, Error> {
         let mut ctx = self.new_context();
         ctx.feed_serializable(msg)?;
         Ok(ctx.finalize())
@@ -56,33 +113,160 @@ impl HashFunction {
 
     pub(crate) fn new_context(&self) -> HashContext {
         HashContext {
-            ctx: ring::digest::Context::new(self.hash_alg),
+            state: self.new_state(),
         }
     }
-
-    pub(crate) fn digest_size(&self) -> usize {
-        self.hash_alg.output_len
-    }
 }
 
 pub(crate) struct HashContext {
-    ctx: ring::digest::Context,
+    state: Box<dyn HashState>,
 }
 
 impl HashContext {
     // TODO: There's no need to allocate a Vec for this. Figure out how to make a Context Writeable
     // so that we can pass it as the TlsSerializer internal buffer
-    pub(crate) fn feed_serializable<S: Serialize>(&mut self, msg: &S) -> Result<(), Error> {
+    pub(crate) fn feed_serializable<S: TlsSerialize>(&mut self, msg: &S) -> Result<(), Error> {
         let bytes = tls_ser::serialize_to_bytes(msg)?;
         self.feed_bytes(&bytes);
         Ok(())
     }
 
     pub(crate) fn feed_bytes(&mut self, bytes: &[u8]) {
-        self.ctx.update(&bytes);
+        self.state.update(&bytes);
     }
 
     pub(crate) fn finalize(self) -> Digest {
-        self.ctx.finish().into()
+        Digest(self.state.finish())
+    }
+}
+
+/// A hash function from the SHA-2 family, as implemented by ring
+#[cfg(not(feature = "rustcrypto"))]
+pub(crate) struct RingHash {
+    hash_alg: &'static ring::digest::Algorithm,
+    kdf_id: u16,
+}
+
+#[cfg(not(feature = "rustcrypto"))]
+impl HashFunction for RingHash {
+    fn digest_size(&self) -> usize {
+        self.hash_alg.output_len
+    }
+
+    fn block_size(&self) -> usize {
+        self.hash_alg.block_len
+    }
+
+    fn kdf_id(&self) -> u16 {
+        self.kdf_id
+    }
+
+    fn new_state(&self) -> Box<dyn HashState> {
+        Box::new(RingHashState(ring::digest::Context::new(self.hash_alg)))
+    }
+}
+
+#[cfg(not(feature = "rustcrypto"))]
+struct RingHashState(ring::digest::Context);
+
+#[cfg(not(feature = "rustcrypto"))]
+impl HashState for RingHashState {
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes)
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.0.finish().as_ref().to_vec()
+    }
+}
+
+/// A `HashFunction` made from any hash function that implements the `digest` crate's traits. This
+/// is how to use SHA-3, BLAKE2, or any other RustCrypto hash function in a cipher suite. For
+/// example, `DigestHash::<sha3::Sha3_256>::new(kdf_id)`.
+pub struct DigestHash<D> {
+    kdf_id: u16,
+    // fn() -> D is Sync regardless of D
+    _marker: PhantomData<fn() -> D>,
+}
+
+impl<D> DigestHash<D> {
+    /// Makes a new `HashFunction` which uses `D`. `kdf_id` is the identifier of HKDF with this
+    /// hash function in the HPKE KDF registry. If there is none, pick one from the private-use
+    /// range.
+    pub const fn new(kdf_id: u16) -> DigestHash<D> {
+        DigestHash {
+            kdf_id,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<D> HashFunction for DigestHash<D>
+where
+    D: digest::Digest + digest::BlockInput + 'static,
+{
+    fn digest_size(&self) -> usize {
+        <D as digest::Digest>::output_size()
+    }
+
+    fn block_size(&self) -> usize {
+        use digest::generic_array::typenum::Unsigned;
+        <D as digest::BlockInput>::BlockSize::to_usize()
+    }
+
+    fn kdf_id(&self) -> u16 {
+        self.kdf_id
+    }
+
+    fn new_state(&self) -> Box<dyn HashState> {
+        Box::new(DigestHashState(D::new()))
+    }
+}
+
+struct DigestHashState<D>(D);
+
+impl<D: digest::Digest> HashState for DigestHashState<D> {
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes)
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Check the digest-crate adaptor against a SHA3-256 known answer, and check that it hashes
+    // incrementally correctly
+    #[test]
+    fn digest_hash_kat() {
+        let sha3: &dyn HashFunction = &DigestHash::<sha3::Sha3_256>::new(0xff00);
+        assert_eq!(sha3.digest_size(), 32);
+        assert_eq!(sha3.block_size(), 136);
+
+        let expected =
+            hex::decode("3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532")
+                .unwrap();
+        assert_eq!(sha3.hash_bytes(b"abc").as_bytes(), expected.as_slice());
+
+        let mut ctx = sha3.new_context();
+        ctx.feed_bytes(b"a");
+        ctx.feed_bytes(b"bc");
+        assert_eq!(ctx.finalize().as_bytes(), expected.as_slice());
+    }
+
+    // Make sure the SHA-2 hashes report the right sizes
+    #[test]
+    fn sha2_sizes() {
+        let sha256: &dyn HashFunction = &SHA256_IMPL;
+        let sha512: &dyn HashFunction = &SHA512_IMPL;
+
+        assert_eq!((sha256.digest_size(), sha256.block_size()), (32, 64));
+        assert_eq!((sha512.digest_size(), sha512.block_size()), (64, 128));
+        assert_eq!(sha256.hash_bytes(b"").as_bytes().len(), 32);
+        assert_eq!(sha512.hash_bytes(b"").as_bytes().len(), 64);
     }
 }
@@ -1,30 +1,13 @@
 use crate::{
     crypto::hash::HashFunction,
     crypto::hmac::{self, HmacKey},
+    crypto::secret::Secret,
     error::Error,
+    tls_ser::TlsSerialize,
 };
 
-use serde::ser::Serialize;
-
-const MLS_PREFIX: &[u8] = b"mls10 ";
-
-// This struct is only used in `hkdf::expand_label`
-#[derive(Serialize)]
-struct HkdfLabel<'a> {
-    // uint16 length = Length;
-    length: u16,
-
-    // opaque label<6..255> = "mls10 " + Label;
-    #[serde(rename = "label__bound_u8")]
-    label: &'a [u8],
-
-    // opaque context<0..2^32-1>
-    #[serde(rename = "context__bound_u32")]
-    context: &'a [u8],
-}
-
 /// An implementation of HKDF-Extract. Code mostly copied from `ring::hkdf::extract`.
-pub(crate) fn extract(hash_impl: &HashFunction, salt: &HmacKey, secret: &[u8]) -> HmacKey {
+pub(crate) fn extract(hash_impl: &dyn HashFunction, salt: &HmacKey, secret: &[u8]) -> HmacKey {
     // We can't just use `ring::hkdf::extract` because it returns a `SigningKey` which we can't get
     // any key bytes from. So we just reimplement it here. The below comment is copied from ring.
 
@@ -36,90 +19,40 @@ pub(crate) fn extract(hash_impl: &HashFunction, salt: &HmacKey, secret: &[u8]) -
     HmacKey::new_from_bytes(prk.as_bytes())
 }
 
-/// An implementation of HKDF-Extract. Passes through to `ring::hkdf::expand`.
-pub(crate) fn expand<S: Serialize>(
-    hash_impl: &HashFunction,
+/// An implementation of HKDF-Expand, as in RFC 5869
+///
+/// Panics: If `out_buf.len() > 255 * hash_impl.digest_size()`
+pub(crate) fn expand<S: TlsSerialize>(
+    hash_impl: &dyn HashFunction,
     salt: &HmacKey,
     info: &S,
     out_buf: &mut [u8],
 ) -> Result<(), Error> {
     let serialized_info = crate::tls_ser::serialize_to_bytes(info)?;
 
-    // Pass to ring
-    let prk = ring::hmac::SigningKey::new(hash_impl.hash_alg, &salt.0);
-    ring::hkdf::expand(&prk, &serialized_info, out_buf);
+    // HKDF can output at most 255 blocks
+    let digest_size = hash_impl.digest_size();
+    assert!(out_buf.len() <= 255 * digest_size);
+
+    // T(0) = empty string
+    // T(i) = HMAC(PRK, T(i-1) || info || i)
+    // OKM = T(1) || T(2) || ... truncated to the output length
+    let mut prev_block = Secret::new_from_zeros(0);
+    for (i, out_chunk) in out_buf.chunks_mut(digest_size).enumerate() {
+        let mut ctx = hmac::new_signing_context(hash_impl, salt);
+        ctx.feed_bytes(prev_block.as_bytes());
+        ctx.feed_bytes(&serialized_info);
+        // This can't overflow, since there are at most 255 chunks
+        ctx.feed_bytes(&[(i + 1) as u8]);
+        let block = Secret::new_from_bytes(ctx.finalize().as_bytes());
+
+        out_chunk.copy_from_slice(&block.as_bytes()[..out_chunk.len()]);
+        prev_block = block;
+    }
 
     Ok(())
 }
 
-// HKDF-Expand-Label(Secret, Label, Context, Length) = HKDF-Expand(Secret, HkdfLabel, Length)
-//
-// Where HkdfLabel is specified as:
-//
-// struct {
-//     uint16 length = Length;
-//     opaque label<7..255> = "mls10 " + Label;
-//     opaque context<0..2^32-1> = Context;
-// } HkdfLabel;
-/// Computes the `HKDF-Expand-Label` function defined in the "Key Schedule" section of the spec
-///
-/// Requires: `label_info.len() <= 255 - MLS_PREFIX.len() = 249
-///
-/// Panics: Iff the above requirement is not met
-pub(crate) fn expand_label(
-    hash_impl: &HashFunction,
-    secret: &HmacKey,
-    label_info: &[u8],
-    context: &[u8],
-    out_buf: &mut [u8],
-) {
-    // The label size is supposed to be at most 255 bytes after being prefixed with "mls10 "
-    assert!(label_info.len() <= 255 - MLS_PREFIX.len());
-    // The output length is also supposed to be representable by a u16
-    assert!(out_buf.len() <= std::u16::MAX as usize);
-
-    // full_label_info_slice = "mls10 " + Label
-    let mut full_label_info = [0u8; 255];
-    full_label_info[0..MLS_PREFIX.len()].copy_from_slice(MLS_PREFIX);
-    full_label_info[MLS_PREFIX.len()..MLS_PREFIX.len() + label_info.len()]
-        .copy_from_slice(label_info);
-    let full_label_info_slice = &full_label_info[0..MLS_PREFIX.len() + label_info.len()];
-
-    // We're gonna used the serialized label as the `info` parameter to HKDF-Expand
-    let label = HkdfLabel {
-        length: out_buf.len() as u16,
-        // Recall the def: opaque label<6..255> = "mls10 " + Label;
-        label: &full_label_info_slice,
-        context,
-    };
-
-    // Finally, do the HKDF-Expand operation with built-in serialization. This can't fail, since we
-    // check that the label isn't oversized with the assert above.
-    expand(hash_impl, secret, &label, out_buf).unwrap();
-}
-
-/// This is the `Derive-Secret` function defined in the "Key Schedule" section of the spec. It's
-/// used as a helper function for `update_epoch_secrets`
-///
-/// Returns: `Ok(hmac_key)` on success. If an error occurred with serialization, returns an
-/// `Error::SerdeError`.
-pub(crate) fn derive_secret<S: Serialize>(
-    hash_impl: &HashFunction,
-    secret: &HmacKey,
-    label_info: &[u8],
-    context: &S,
-) -> Result<HmacKey, Error> {
-    // Derive-Secret(Secret, Label, Context) =
-    //     HKDF-Expand-Label(Secret, Label, Hash(Context), Hash.length)
-    let key = {
-        let hashed_ctx = hash_impl.hash_serializable(context)?;
-        let mut key_buf = vec![0u8; hash_impl.digest_size()];
-        expand_label(hash_impl, secret, label_info, hashed_ctx.as_bytes(), key_buf.as_mut_slice());
-        HmacKey::new_from_bytes(&key_buf)
-    };
-    Ok(key)
-}
-
 #[cfg(test)]
 mod test {
     use crate::crypto::{
@@ -131,12 +64,13 @@ mod test {
     use quickcheck_macros::quickcheck;
 
     // Check that our implementation of hkdf::extract matches ring's implementation
+    #[cfg(not(feature = "rustcrypto"))]
     #[quickcheck]
     fn hkdf_extract_kat(salt_bytes: Vec<u8>, secret_bytes: Vec<u8>) {
         let hash_impl = &SHA256_IMPL;
 
         // Wrap the salt bytes in a signing key
-        let ring_salt = ring::hmac::SigningKey::new(hash_impl.hash_alg, &salt_bytes);
+        let ring_salt = ring::hmac::SigningKey::new(&ring::digest::SHA256, &salt_bytes);
         let my_salt = HmacKey::new_from_bytes(&salt_bytes);
 
         // prk = HKDF-Extract(salt, ikm=secret)
@@ -152,4 +86,23 @@ mod test {
 
         assert_eq!(ring_sig.as_ref(), my_sig.as_bytes());
     }
+
+    // Check that our implementation of hkdf::expand matches ring's implementation, for every
+    // output length up to a few blocks
+    #[cfg(not(feature = "rustcrypto"))]
+    #[quickcheck]
+    fn hkdf_expand_kat(prk_bytes: Vec<u8>, info: Vec<u8>, out_len: u8) {
+        let hash_impl = &SHA256_IMPL;
+
+        let ring_prk = ring::hmac::SigningKey::new(&ring::digest::SHA256, &prk_bytes);
+        let my_prk = HmacKey::new_from_bytes(&prk_bytes);
+
+        let mut ring_out = vec![0u8; out_len as usize];
+        let mut my_out = vec![0u8; out_len as usize];
+        ring::hkdf::expand(&ring_prk, &info, &mut ring_out);
+        // A Vec<u8> serializes to exactly its contents, so info is passed through unchanged
+        hkdf::expand(hash_impl, &my_prk, &info, &mut my_out).unwrap();
+
+        assert_eq!(ring_out, my_out);
+    }
 }
@@ -1,41 +1,54 @@
 use crate::{
-    crypto::{hash::HashFunction, rng::CryptoRng},
+    crypto::{
+        hash::{HashContext, HashFunction},
+        rng::CryptoRng,
+        secret::Secret,
+    },
     error::Error,
 };
 
+use subtle::ConstantTimeEq;
+
+/// The byte that `K'` is XORed with to make the inner key in HMAC
+const IPAD_BYTE: u8 = 0x36;
+
+/// The byte that `K'` is XORed with to make the outer key in HMAC
+const OPAD_BYTE: u8 = 0x5c;
+
 // TODO: Make these newtypes ArrayVecs
 
-/// An HMAC signing/verification key
-#[derive(Clone, Deserialize, Serialize)]
+/// An HMAC signing/verification key. Nearly every secret in the key schedule is one of these.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug, Eq, PartialEq))]
 // This is opaque <0..255> because WelcomeInfo::init_secret is
-#[serde(rename = "HmacKey__bound_u8")]
-pub(crate) struct HmacKey(pub(crate) Vec<u8>);
+#[tls(bound = "u8")]
+pub(crate) struct HmacKey(pub(crate) Secret);
 
 impl HmacKey {
     pub(crate) fn new_from_bytes(bytes: &[u8]) -> HmacKey {
-        HmacKey(bytes.to_vec())
+        HmacKey(Secret::new_from_bytes(bytes))
     }
 
-    pub fn new_from_random<R>(hash_impl: &HashFunction, csprng: &mut R) -> HmacKey
+    pub fn new_from_random<R>(hash_impl: &dyn HashFunction, csprng: &mut R) -> HmacKey
     where
         R: CryptoRng,
     {
-        let mut buf = vec![0u8; hash_impl.digest_size()];
-        csprng.fill_bytes(&mut buf);
-        HmacKey(buf)
+        HmacKey(Secret::new_from_random(hash_impl.digest_size(), csprng))
     }
 
-    pub(crate) fn new_from_zeros(hash_impl: &HashFunction) -> HmacKey {
-        let buf = vec![0u8; hash_impl.digest_size()];
-        HmacKey(buf)
+    pub(crate) fn new_from_zeros(hash_impl: &dyn HashFunction) -> HmacKey {
+        HmacKey(Secret::new_from_zeros(hash_impl.digest_size()))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
     }
 }
 
 // This is <0..255> since the only signature in MLS is
 // Handshake::confirmation<0..255>
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename = "Mac__bound_u8")]
+#[derive(Debug, TlsDeserialize, TlsSerialize)]
+#[tls(bound = "u8")]
 pub(crate) struct Mac(Vec<u8>);
 
 impl Mac {
@@ -44,52 +57,137 @@ impl Mac {
     }
 }
 
-impl From<ring::hmac::Signature> for Mac {
-    fn from(sig: ring::hmac::Signature) -> Mac {
-        Mac(sig.as_ref().to_vec())
-    }
-}
-
-pub(crate) fn sign(hash_impl: &HashFunction, key: &HmacKey, msg: &[u8]) -> Mac {
+/// Computes `HMAC(key, msg)`
+pub(crate) fn sign(hash_impl: &dyn HashFunction, key: &HmacKey, msg: &[u8]) -> Mac {
     let mut ctx = new_signing_context(hash_impl, key);
     ctx.feed_bytes(msg);
     ctx.finalize()
 }
 
+/// Checks that `sig == HMAC(key, msg)` in constant time
+///
+/// Returns: `Ok(())` if the MAC is valid. Otherwise, returns an `Error::SignatureError`.
 pub(crate) fn verify(
-    hash_impl: &HashFunction,
+    hash_impl: &dyn HashFunction,
     key: &HmacKey,
     msg: &[u8],
     sig: &Mac,
 ) -> Result<(), Error> {
-    let verification_key: ring::hmac::VerificationKey =
-        ring::hmac::VerificationKey::new(hash_impl.hash_alg, &key.0);
+    let expected = sign(hash_impl, key, msg);
 
-    // It's okay to reveal that the MAC is incorrect, because the ring::hmac::verify runs in
-    // constant time
-    ring::hmac::verify(&verification_key, msg, &sig.0)
-        .map_err(|_| Error::SignatureError("MAC verification failed"))
+    // It's okay to reveal that the MAC is incorrect, because the comparison runs in constant time
+    if expected.as_bytes().ct_eq(sig.as_bytes()).into() {
+        Ok(())
+    } else {
+        Err(Error::SignatureError("MAC verification failed"))
+    }
 }
 
-pub(crate) fn new_signing_context(hash_impl: &HashFunction, key: &HmacKey) -> HmacSigningContext {
-    let signing_key: ring::hmac::SigningKey =
-        ring::hmac::SigningKey::new(hash_impl.hash_alg, &key.0);
+/// Starts an incremental HMAC computation. This is the construction from RFC 2104:
+/// `HMAC(K, m) = H((K' ^ opad) || H((K' ^ ipad) || m))`, where `K'` is `K` padded (or hashed,
+/// if it's longer than a block) to the block size of `H`.
+pub(crate) fn new_signing_context<'a>(
+    hash_impl: &'a dyn HashFunction,
+    key: &HmacKey,
+) -> HmacSigningContext<'a> {
+    let block_size = hash_impl.block_size();
+
+    // Keys longer than a block get hashed first. Then everything gets zero-padded to a block.
+    let mut padded_key = Secret::new_from_zeros(block_size);
+    if key.0.len() > block_size {
+        let hashed_key = Secret::from(hash_impl.hash_bytes(key.as_bytes()));
+        padded_key.as_mut_bytes()[..hashed_key.len()].copy_from_slice(hashed_key.as_bytes());
+    } else {
+        padded_key.as_mut_bytes()[..key.0.len()].copy_from_slice(key.as_bytes());
+    }
+
+    let inner_key: Secret = padded_key.as_bytes().iter().map(|b| b ^ IPAD_BYTE).collect();
+    let outer_key: Secret = padded_key.as_bytes().iter().map(|b| b ^ OPAD_BYTE).collect();
+
+    let mut inner = hash_impl.new_context();
+    inner.feed_bytes(inner_key.as_bytes());
 
     HmacSigningContext {
-        ctx: ring::hmac::SigningContext::with_key(&signing_key),
+        hash_impl,
+        inner,
+        outer_key,
     }
 }
 
-pub(crate) struct HmacSigningContext {
-    ctx: ring::hmac::SigningContext,
+/// The state of an incremental HMAC computation
+pub(crate) struct HmacSigningContext<'a> {
+    hash_impl: &'a dyn HashFunction,
+    /// The inner hash, which has been fed `K' ^ ipad` and everything passed to `feed_bytes`
+    inner: HashContext,
+    /// `K' ^ opad`
+    outer_key: Secret,
 }
 
-impl HmacSigningContext {
+impl<'a> HmacSigningContext<'a> {
     pub(crate) fn feed_bytes(&mut self, bytes: &[u8]) {
-        self.ctx.update(bytes)
+        self.inner.feed_bytes(bytes)
     }
 
     pub(crate) fn finalize(self) -> Mac {
-        self.ctx.sign().into()
+        let inner_digest = self.inner.finalize();
+
+        let mut outer = self.hash_impl.new_context();
+        outer.feed_bytes(self.outer_key.as_bytes());
+        outer.feed_bytes(inner_digest.as_bytes());
+
+        Mac(outer.finalize().as_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::hash::{SHA256_IMPL, SHA512_IMPL};
+
+    use quickcheck_macros::quickcheck;
+
+    // Check that our HMAC agrees with ring's, for keys both shorter and longer than a block
+    #[cfg(not(feature = "rustcrypto"))]
+    #[quickcheck]
+    fn hmac_kat(key_bytes: Vec<u8>, long_key_seed: u8, msg: Vec<u8>) {
+        let long_key_bytes = vec![long_key_seed; 200];
+
+        for (hash_impl, ring_alg) in &[
+            (&SHA256_IMPL as &dyn HashFunction, &ring::digest::SHA256),
+            (&SHA512_IMPL as &dyn HashFunction, &ring::digest::SHA512),
+        ] {
+            for key in &[&key_bytes, &long_key_bytes] {
+                let ring_key = ring::hmac::SigningKey::new(ring_alg, key);
+                let ring_sig = ring::hmac::sign(&ring_key, &msg);
+
+                let my_key = HmacKey::new_from_bytes(key);
+                let my_sig = sign(*hash_impl, &my_key, &msg);
+
+                assert_eq!(ring_sig.as_ref(), my_sig.as_bytes());
+                assert!(verify(*hash_impl, &my_key, &msg, &my_sig).is_ok());
+            }
+        }
+    }
+
+    // This is test case 2 from RFC 4231
+    #[test]
+    fn hmac_sha256_kat() {
+        let key = HmacKey::new_from_bytes(b"Jefe");
+        let msg = b"what do ya want for nothing?";
+        let expected =
+            hex::decode("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+                .unwrap();
+
+        assert_eq!(sign(&SHA256_IMPL, &key, msg).as_bytes(), expected.as_slice());
+    }
+
+    // Make sure a wrong MAC doesn't verify
+    #[quickcheck]
+    fn hmac_soundness(key_bytes: Vec<u8>, msg: Vec<u8>) {
+        let key = HmacKey::new_from_bytes(&key_bytes);
+        let mut sig = sign(&SHA256_IMPL, &key, &msg);
+        sig.0[0] ^= 1;
+
+        assert!(verify(&SHA256_IMPL, &key, &msg, &sig).is_err());
     }
 }
@@ -0,0 +1,591 @@
+//! An implementation of the base mode of HPKE, as specified in RFC 9180. The KEM, KDF, and AEAD
+//! that HPKE uses are the ones in the given cipher suite.
+
+use crate::{
+    crypto::{
+        aead::{AeadKey, AeadNonce},
+        ciphersuite::CipherSuite,
+        dh::{DhPrivateKey, DhPublicKey},
+        hkdf,
+        hmac::HmacKey,
+        rng::CryptoRng,
+        secret::Secret,
+    },
+    error::Error,
+    tls_ser,
+};
+
+/// The prefix of every label that HPKE feeds into its KDF
+const HPKE_VERSION_LABEL: &[u8] = b"HPKE-v1";
+
+/// The mode byte for HPKE base mode, i.e., no PSK and no sender authentication
+const MODE_BASE: u8 = 0x00;
+
+/// The prefix of every label that MLS feeds into HPKE via `EncryptContext`
+const MLS_PREFIX: &[u8] = b"MLS 1.0 ";
+
+// struct {
+//     opaque label<V> = "MLS 1.0 " + Label;
+//     opaque context<V>;
+// } EncryptContext;
+/// The HPKE `info` parameter in `hpke::encrypt_with_label` and `hpke::decrypt_with_label`. The
+/// label here already has `MLS_PREFIX` prepended to it.
+#[derive(TlsSerialize)]
+struct EncryptContext<'a> {
+    #[tls(bound = "varint")]
+    label: Vec<u8>,
+    #[tls(bound = "varint")]
+    context: &'a [u8],
+}
+
+/// A ciphertext encrypted with HPKE. This has the same wire format as the old `ECIESCiphertext`.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct HpkeCiphertext {
+    /// What the recipient needs in order to decapsulate the shared secret. For DH-based KEMs, this
+    /// is the sender's ephemeral public key.
+    pub(crate) kem_output: DhPublicKey,
+    /// The payload
+    // opaque ciphertext<0..2^32-1>;
+    #[tls(bound = "u32")]
+    pub(crate) ciphertext: Vec<u8>,
+}
+
+/// The result of an HPKE setup. This holds the AEAD key, nonce state, and exporter secret that
+/// the sender and the recipient share.
+pub(crate) struct HpkeContext<'a> {
+    cs: &'a CipherSuite,
+    key: AeadKey,
+    base_nonce: Vec<u8>,
+    seq: u64,
+    exporter_secret: HmacKey,
+}
+
+/// Returns `"KEM" || I2OSP(kem_id, 2)`, the suite ID used inside the KEM
+fn kem_suite_id(cs: &CipherSuite) -> Vec<u8> {
+    [&b"KEM"[..], &cs.dh_impl.kem_id().to_be_bytes()[..]].concat()
+}
+
+/// Returns `"HPKE" || I2OSP(kem_id, 2) || I2OSP(kdf_id, 2) || I2OSP(aead_id, 2)`, the suite ID
+/// used in the HPKE key schedule
+fn hpke_suite_id(cs: &CipherSuite) -> Vec<u8> {
+    [
+        &b"HPKE"[..],
+        &cs.dh_impl.kem_id().to_be_bytes()[..],
+        &cs.hash_impl.kdf_id().to_be_bytes()[..],
+        &cs.aead_impl.aead_id().to_be_bytes()[..],
+    ]
+    .concat()
+}
+
+/// Computes `LabeledExtract(salt, label, ikm) = Extract(salt, "HPKE-v1" || suite_id || label ||
+/// ikm)`
+fn labeled_extract(
+    cs: &CipherSuite,
+    suite_id: &[u8],
+    salt: &[u8],
+    label: &[u8],
+    ikm: &[u8],
+) -> HmacKey {
+    let labeled_ikm = [HPKE_VERSION_LABEL, suite_id, label, ikm].concat();
+    hkdf::extract(cs.hash_impl, &HmacKey::new_from_bytes(salt), &labeled_ikm)
+}
+
+/// Computes `LabeledExpand(prk, label, info, L) = Expand(prk, I2OSP(L, 2) || "HPKE-v1" ||
+/// suite_id || label || info, L)` where `L = out_buf.len()`
+///
+/// Panics: If `out_buf.len() > 255 * Nh`, where `Nh` is the digest size of the hash function
+fn labeled_expand(
+    cs: &CipherSuite,
+    suite_id: &[u8],
+    prk: &HmacKey,
+    label: &[u8],
+    info: &[u8],
+    out_buf: &mut [u8],
+) {
+    // The output length has to be representable by a u16
+    assert!(out_buf.len() <= std::u16::MAX as usize);
+    let length = (out_buf.len() as u16).to_be_bytes();
+    let labeled_info = [&length[..], HPKE_VERSION_LABEL, suite_id, label, info].concat();
+
+    // A Vec<u8> serializes to exactly its contents, so this can't fail
+    hkdf::expand(cs.hash_impl, prk, &labeled_info, out_buf).unwrap();
+}
+
+/// This is `ExtractAndExpand` from the DHKEM section of RFC 9180. It turns the raw KEM output
+/// into a uniformly random shared secret that's bound to both the encapsulation and the
+/// recipient's public key. We use the cipher suite's hash function as the KEM's KDF, which
+/// matches RFC 9180 for every DH-based suite we implement.
+fn extract_and_expand(
+    cs: &CipherSuite,
+    kem_shared_secret: &[u8],
+    encapsulation: &DhPublicKey,
+    recipient_public_key: &DhPublicKey,
+) -> Secret {
+    let suite_id = kem_suite_id(cs);
+    let kem_context = [encapsulation.as_bytes(), recipient_public_key.as_bytes()].concat();
+
+    let eae_prk = labeled_extract(cs, &suite_id, b"", b"eae_prk", kem_shared_secret);
+    let mut shared_secret = Secret::new_from_zeros(cs.hash_impl.digest_size());
+    labeled_expand(
+        cs,
+        &suite_id,
+        &eae_prk,
+        b"shared_secret",
+        &kem_context,
+        shared_secret.as_mut_bytes(),
+    );
+
+    shared_secret
+}
+
+/// This is `KeySchedule` from RFC 9180, specialized to base mode (so `psk = psk_id = ""`)
+fn key_schedule<'a>(cs: &'a CipherSuite, shared_secret: &[u8], info: &[u8]) -> HpkeContext<'a> {
+    let suite_id = hpke_suite_id(cs);
+
+    let psk_id_hash = labeled_extract(cs, &suite_id, b"", b"psk_id_hash", b"");
+    let info_hash = labeled_extract(cs, &suite_id, b"", b"info_hash", info);
+    let key_schedule_context =
+        [&[MODE_BASE][..], psk_id_hash.as_bytes(), info_hash.as_bytes()].concat();
+
+    let secret = labeled_extract(cs, &suite_id, shared_secret, b"secret", b"");
+
+    let mut key_buf = Secret::new_from_zeros(cs.aead_impl.key_size());
+    let mut base_nonce = vec![0u8; cs.aead_impl.nonce_size()];
+    let mut exporter_secret = HmacKey(Secret::new_from_zeros(cs.hash_impl.digest_size()));
+    let ctx = &key_schedule_context;
+    labeled_expand(cs, &suite_id, &secret, b"key", ctx, key_buf.as_mut_bytes());
+    labeled_expand(cs, &suite_id, &secret, b"base_nonce", ctx, &mut base_nonce);
+    labeled_expand(cs, &suite_id, &secret, b"exp", ctx, exporter_secret.0.as_mut_bytes());
+
+    let key = AeadKey::new_from_bytes(cs.aead_impl, key_buf.as_bytes())
+        .expect("couldn't derive AEAD key from HKDF");
+
+    HpkeContext {
+        cs,
+        key,
+        base_nonce,
+        seq: 0,
+        exporter_secret,
+    }
+}
+
+/// This is `SetupBaseS` from RFC 9180. It sets up an HPKE context for sending to the holder of
+/// `recipient_public_key`, using a random ephemeral key.
+///
+/// Returns: `Ok((encapsulation, context))` on success, where `encapsulation` is what the
+/// recipient needs to pass to `setup_base_r`. If there is an issue with the RNG, returns
+/// `Error::OutOfEntropy`. If encapsulation fails, returns `Error::DhError`.
+pub(crate) fn setup_base_s<'a, R>(
+    cs: &'a CipherSuite,
+    recipient_public_key: &DhPublicKey,
+    info: &[u8],
+    csprng: &mut R,
+) -> Result<(DhPublicKey, HpkeContext<'a>), Error>
+where
+    R: CryptoRng,
+{
+    let ephemeral_secret = DhPrivateKey::new_from_random(cs.dh_impl, csprng)?;
+    setup_base_s_with_ephemeral(cs, recipient_public_key, info, &ephemeral_secret)
+}
+
+/// This is the deterministic function underlying `setup_base_s`, and is important for testing
+/// purposes
+///
+/// Returns: `Ok((encapsulation, context))` on success. If encapsulation fails, returns
+/// `Error::DhError`.
+pub(crate) fn setup_base_s_with_ephemeral<'a>(
+    cs: &'a CipherSuite,
+    recipient_public_key: &DhPublicKey,
+    info: &[u8],
+    ephemeral_secret: &DhPrivateKey,
+) -> Result<(DhPublicKey, HpkeContext<'a>), Error> {
+    let (kem_shared_secret, encapsulation) =
+        cs.dh_impl.encapsulate(ephemeral_secret, recipient_public_key)?;
+    let shared_secret =
+        extract_and_expand(cs, kem_shared_secret.as_bytes(), &encapsulation, recipient_public_key);
+
+    Ok((encapsulation, key_schedule(cs, shared_secret.as_bytes(), info)))
+}
+
+/// This is `SetupBaseR` from RFC 9180. It sets up an HPKE context for receiving from whoever made
+/// `encapsulation`.
+///
+/// Returns: `Ok(context)` on success. If decapsulation fails, returns `Error::DhError`.
+pub(crate) fn setup_base_r<'a>(
+    cs: &'a CipherSuite,
+    encapsulation: &DhPublicKey,
+    recipient_secret_key: &DhPrivateKey,
+    info: &[u8],
+) -> Result<HpkeContext<'a>, Error> {
+    let kem_shared_secret = cs.dh_impl.decapsulate(recipient_secret_key, encapsulation)?;
+    let recipient_public_key = DhPublicKey::new_from_private_key(cs.dh_impl, recipient_secret_key);
+    let shared_secret =
+        extract_and_expand(cs, kem_shared_secret.as_bytes(), encapsulation, &recipient_public_key);
+
+    Ok(key_schedule(cs, shared_secret.as_bytes(), info))
+}
+
+impl<'a> HpkeContext<'a> {
+    /// Computes the nonce for the current message, which is `base_nonce XOR I2OSP(seq, Nn)`, and
+    /// increments the sequence number
+    ///
+    /// Returns: `Ok(nonce)` on success. If the sequence number is exhausted, returns
+    /// `Error::EncryptionError`.
+    fn next_nonce(&mut self) -> Result<AeadNonce, Error> {
+        let seq_bytes = self.seq.to_be_bytes();
+        let mut nonce_buf = self.base_nonce.clone();
+        // The nonce is always at least 8 bytes long, so seq_bytes fits at the end of it
+        let offset = nonce_buf.len() - seq_bytes.len();
+        for (n, s) in nonce_buf[offset..].iter_mut().zip(seq_bytes.iter()) {
+            *n ^= s;
+        }
+
+        self.seq =
+            self.seq.checked_add(1).ok_or(Error::EncryptionError("HPKE message limit reached"))?;
+
+        AeadNonce::new_from_bytes(self.cs.aead_impl, &nonce_buf)
+    }
+
+    /// This is `ContextS.Seal` from RFC 9180 with empty associated data. It encrypts the given
+    /// plaintext under the next nonce in the sequence.
+    ///
+    /// Returns: `Ok(ciphertext)` on success. Otherwise, returns an `Error::EncryptionError`.
+    pub(crate) fn seal(&mut self, mut plaintext: Vec<u8>) -> Result<Vec<u8>, Error> {
+        // Make room for the tag and fill it with zeros
+        let tagged_plaintext_size = plaintext
+            .len()
+            .checked_add(self.cs.aead_impl.tag_size())
+            .expect("plaintext is too large to be encrypted");
+        plaintext.resize(tagged_plaintext_size, 0u8);
+
+        let nonce = self.next_nonce()?;
+        self.cs.aead_impl.seal(&self.key, nonce, plaintext.as_mut_slice())?;
+
+        // Rename for clarity
+        let ciphertext = plaintext;
+        Ok(ciphertext)
+    }
+
+    /// This is `ContextR.Open` from RFC 9180 with empty associated data. It decrypts the given
+    /// ciphertext under the next nonce in the sequence.
+    ///
+    /// Returns: `Ok(plaintext)` on success. Otherwise, returns an `Error::EncryptionError`.
+    pub(crate) fn open(&mut self, mut ciphertext: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let nonce = self.next_nonce()?;
+        let plaintext_len =
+            self.cs.aead_impl.open(&self.key, nonce, ciphertext.as_mut_slice())?.len();
+
+        // Rename for clarity
+        let mut plaintext = ciphertext;
+
+        plaintext.truncate(plaintext_len);
+        Ok(plaintext)
+    }
+
+    /// This is `Context.Export` from RFC 9180. It derives a secret of length `out_buf.len()` from
+    /// the exporter secret and the given context.
+    ///
+    /// Panics: If `out_buf.len() > 255 * Nh`, where `Nh` is the digest size of the hash function
+    pub(crate) fn export(&self, exporter_context: &[u8], out_buf: &mut [u8]) {
+        let suite_id = hpke_suite_id(self.cs);
+        labeled_expand(
+            self.cs,
+            &suite_id,
+            &self.exporter_secret,
+            b"sec",
+            exporter_context,
+            out_buf,
+        );
+    }
+}
+
+/// This is `SealBase` from RFC 9180 with empty associated data. It encrypts a single message to
+/// the holder of `recipient_public_key`.
+///
+/// Returns: `Ok(ciphertext)` on success. If there is an issue with random scalar generation or
+/// sealing the plaintext, an `Error` is returned.
+pub(crate) fn seal_base<R>(
+    cs: &CipherSuite,
+    recipient_public_key: &DhPublicKey,
+    info: &[u8],
+    plaintext: Vec<u8>,
+    csprng: &mut R,
+) -> Result<HpkeCiphertext, Error>
+where
+    R: CryptoRng,
+{
+    let (kem_output, mut ctx) = setup_base_s(cs, recipient_public_key, info, csprng)?;
+    let ciphertext = ctx.seal(plaintext)?;
+
+    Ok(HpkeCiphertext {
+        kem_output,
+        ciphertext,
+    })
+}
+
+/// This is `OpenBase` from RFC 9180 with empty associated data. It decrypts a single message that
+/// was encrypted with `seal_base`.
+///
+/// Returns: `Ok(plaintext)` on success. If decapsulation fails, returns an `Error::DhError`. If
+/// decryption fails, returns an `Error::EncryptionError`.
+pub(crate) fn open_base(
+    cs: &CipherSuite,
+    recipient_secret_key: &DhPrivateKey,
+    info: &[u8],
+    ciphertext: HpkeCiphertext,
+) -> Result<Vec<u8>, Error> {
+    let HpkeCiphertext {
+        kem_output,
+        ciphertext,
+    } = ciphertext;
+
+    let mut ctx = setup_base_r(cs, &kem_output, recipient_secret_key, info)?;
+    ctx.open(ciphertext)
+}
+
+/// Makes the HPKE `info` parameter for the given label and context, i.e., a serialized
+/// `EncryptContext`
+///
+/// Panics: If the label or context is too long for a varint length, i.e., `2^30` bytes or more
+fn encrypt_context(label: &[u8], context: &[u8]) -> Vec<u8> {
+    let encrypt_context = EncryptContext {
+        label: [MLS_PREFIX, label].concat(),
+        context,
+    };
+
+    // Serializing only fails if a vector is too long for its length prefix
+    tls_ser::serialize_to_bytes(&encrypt_context).expect("label or context is too long")
+}
+
+/// This is `EncryptWithLabel` from RFC 9420. It's `SealBase` where the `info` parameter is an
+/// `EncryptContext` made from the given label and context. This is how UpdatePath secrets and
+/// the secrets in `Welcome`s get encrypted.
+///
+/// Panics: Under the same conditions as `encrypt_context`
+///
+/// Returns: `Ok(ciphertext)` on success. If there is an issue with random scalar generation or
+/// sealing the plaintext, an `Error` is returned.
+pub(crate) fn encrypt_with_label<R>(
+    cs: &CipherSuite,
+    recipient_public_key: &DhPublicKey,
+    label: &[u8],
+    context: &[u8],
+    plaintext: Vec<u8>,
+    csprng: &mut R,
+) -> Result<HpkeCiphertext, Error>
+where
+    R: CryptoRng,
+{
+    let info = encrypt_context(label, context);
+    seal_base(cs, recipient_public_key, &info, plaintext, csprng)
+}
+
+/// This is `DecryptWithLabel` from RFC 9420. It undoes `encrypt_with_label`.
+///
+/// Panics: Under the same conditions as `encrypt_context`
+///
+/// Returns: `Ok(plaintext)` on success. If decapsulation fails, returns an `Error::DhError`. If
+/// decryption fails, returns an `Error::EncryptionError`.
+pub(crate) fn decrypt_with_label(
+    cs: &CipherSuite,
+    recipient_secret_key: &DhPrivateKey,
+    label: &[u8],
+    context: &[u8],
+    ciphertext: HpkeCiphertext,
+) -> Result<Vec<u8>, Error> {
+    let info = encrypt_context(label, context);
+    open_base(cs, recipient_secret_key, &info, ciphertext)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::ciphersuite::{
+        P521_SHA512_AES256GCM, X25519MLKEM768_SHA256_AES128GCM, X25519_SHA256_AES128GCM,
+        X25519_SHA256_CHACHA20POLY1305, X448_SHA512_AES256GCM,
+    };
+
+    use crate::crypto::key_ops::DhPrivateKeyOps;
+
+    use std::sync::Arc;
+
+    use quickcheck_macros::quickcheck;
+    use rand::{RngCore, SeedableRng};
+
+    const CIPHERSUITES: &[&CipherSuite] = &[
+        &X25519_SHA256_AES128GCM,
+        &X448_SHA512_AES256GCM,
+        &X25519_SHA256_CHACHA20POLY1305,
+        &P521_SHA512_AES256GCM,
+        &X25519MLKEM768_SHA256_AES128GCM,
+    ];
+
+    // Make sure that a sender and recipient context agree on everything, over multiple messages
+    #[quickcheck]
+    fn hpke_correctness(plaintexts: Vec<Vec<u8>>, info: Vec<u8>, rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+
+        for cs in CIPHERSUITES {
+            let (recip_public_key, recip_secret_key) = {
+                let mut seed = [0u8; 32];
+                rng.fill_bytes(&mut seed);
+                cs.derive_key_pair(&seed).unwrap()
+            };
+
+            let (encapsulation, mut sender_ctx) =
+                setup_base_s(cs, &recip_public_key, &info, &mut rng).unwrap();
+            let mut recip_ctx = setup_base_r(cs, &encapsulation, &recip_secret_key, &info).unwrap();
+
+            for plaintext in plaintexts.iter() {
+                let ciphertext = sender_ctx.seal(plaintext.clone()).unwrap();
+                let recovered = recip_ctx.open(ciphertext).unwrap();
+                assert_eq!(&recovered, plaintext);
+            }
+
+            // Exported secrets should also agree
+            let mut export1 = [0u8; 40];
+            let mut export2 = [0u8; 40];
+            sender_ctx.export(b"test export", &mut export1);
+            recip_ctx.export(b"test export", &mut export2);
+            assert_eq!(export1, export2);
+        }
+    }
+
+    // A stand-in for an HSM. It does X25519 in software, but all the crate gets to see is its
+    // public key and its decapsulations.
+    struct SoftwareX25519Hsm(DhPrivateKey);
+
+    impl DhPrivateKeyOps for SoftwareX25519Hsm {
+        fn public_key_bytes(&self) -> Vec<u8> {
+            let dh_impl = X25519_SHA256_AES128GCM.dh_impl;
+            DhPublicKey::new_from_private_key(dh_impl, &self.0).as_bytes().to_vec()
+        }
+
+        fn decapsulate(&self, encapsulation: &[u8]) -> Result<Vec<u8>, Error> {
+            let dh_impl = X25519_SHA256_AES128GCM.dh_impl;
+            let encapsulation = DhPublicKey::new_encapsulation_from_bytes(dh_impl, encapsulation)?;
+            let shared_secret = dh_impl.decapsulate(&self.0, &encapsulation)?;
+            Ok(shared_secret.as_bytes().to_vec())
+        }
+    }
+
+    // Make sure that an external private key can receive, and that it can't be used to send
+    #[quickcheck]
+    fn hpke_external_key(plaintext: Vec<u8>, rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs = &X25519_SHA256_AES128GCM;
+
+        let hsm = {
+            let software_key = DhPrivateKey::new_from_random(cs.dh_impl, &mut rng).unwrap();
+            Arc::new(SoftwareX25519Hsm(software_key))
+        };
+        let recip_secret_key = DhPrivateKey::new_external(cs.dh_impl, hsm).unwrap();
+        let recip_public_key = DhPublicKey::new_from_private_key(cs.dh_impl, &recip_secret_key);
+
+        let ciphertext = encrypt_with_label(
+            cs,
+            &recip_public_key,
+            b"label",
+            b"context",
+            plaintext.clone(),
+            &mut rng,
+        )
+        .unwrap();
+        let recovered =
+            decrypt_with_label(cs, &recip_secret_key, b"label", b"context", ciphertext).unwrap();
+        assert_eq!(recovered, plaintext);
+
+        // External keys only decapsulate
+        assert!(setup_base_s_with_ephemeral(cs, &recip_public_key, b"", &recip_secret_key).is_err());
+    }
+
+    // Make sure ciphertexts don't open out of order or under different info strings
+    #[quickcheck]
+    fn hpke_soundness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs = &X25519_SHA256_AES128GCM;
+        let (recip_public_key, recip_secret_key) = cs.derive_key_pair(b"hpke soundness").unwrap();
+
+        // Encrypt two messages. Opening the second one first should fail.
+        let (encapsulation, mut sender_ctx) =
+            setup_base_s(cs, &recip_public_key, b"info", &mut rng).unwrap();
+        let _ = sender_ctx.seal(b"first".to_vec()).unwrap();
+        let second = sender_ctx.seal(b"second".to_vec()).unwrap();
+        let mut recip_ctx = setup_base_r(cs, &encapsulation, &recip_secret_key, b"info").unwrap();
+        assert!(recip_ctx.open(second).is_err());
+
+        // Single-shot encryption under one label shouldn't decrypt under another
+        let ciphertext = encrypt_with_label(
+            cs,
+            &recip_public_key,
+            b"label",
+            b"context",
+            b"hello".to_vec(),
+            &mut rng,
+        )
+        .unwrap();
+        assert!(decrypt_with_label(
+            cs,
+            &recip_secret_key,
+            b"lab3l",
+            b"context",
+            ciphertext.clone()
+        )
+        .is_err());
+        assert_eq!(
+            decrypt_with_label(cs, &recip_secret_key, b"label", b"context", ciphertext).unwrap(),
+            b"hello"
+        );
+    }
+
+    // The info of EncryptWithLabel is an EncryptContext, with varint lengths and the RFC 9420 label
+    // prefix
+    #[test]
+    fn encrypt_context_format() {
+        let info = encrypt_context(b"Welcome", b"ctx");
+        assert_eq!(info, [&[0x0f][..], b"MLS 1.0 Welcome", &[0x03], b"ctx"].concat());
+    }
+
+    // This is the base mode test vector A.1.1 from RFC 9180, for DHKEM(X25519, HKDF-SHA256),
+    // HKDF-SHA256, and AES-128-GCM. We don't check the ciphertexts, since those use associated
+    // data.
+    #[test]
+    fn hpke_kat() {
+        let cs = &X25519_SHA256_AES128GCM;
+
+        let info = hex::decode("4f6465206f6e2061204772656369616e2055726e").unwrap();
+        let sk_em = hex::decode("52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736")
+            .unwrap();
+        let sk_rm = hex::decode("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8")
+            .unwrap();
+        let expected_enc =
+            hex::decode("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431")
+                .unwrap();
+        let expected_base_nonce = hex::decode("56d890e5accaaf011cff4b7d").unwrap();
+        let expected_exporter_secret =
+            hex::decode("45ff1c2e220db587171952c0592d5f5ebe103f1561a2614e38f2ffd47e99e3f8")
+                .unwrap();
+        let expected_export =
+            hex::decode("3853fe2b4035195a573ffc53856e77058e15d9ea064de3e59f4961d0095250ee")
+                .unwrap();
+
+        let sk_e = DhPrivateKey::new_from_bytes(cs.dh_impl, &sk_em).unwrap();
+        let sk_r = DhPrivateKey::new_from_bytes(cs.dh_impl, &sk_rm).unwrap();
+        let pk_r = DhPublicKey::new_from_private_key(cs.dh_impl, &sk_r);
+
+        let (enc, sender_ctx) = setup_base_s_with_ephemeral(cs, &pk_r, &info, &sk_e).unwrap();
+        assert_eq!(enc.as_bytes(), expected_enc.as_slice());
+        assert_eq!(sender_ctx.base_nonce, expected_base_nonce);
+        assert_eq!(sender_ctx.exporter_secret.as_bytes(), expected_exporter_secret.as_slice());
+
+        // The recipient should derive the same thing
+        let recip_ctx = setup_base_r(cs, &enc, &sk_r, &info).unwrap();
+        assert_eq!(recip_ctx.base_nonce, expected_base_nonce);
+
+        // Export with an empty context and length 32
+        let mut export = [0u8; 32];
+        recip_ctx.export(b"", &mut export);
+        assert_eq!(&export[..], expected_export.as_slice());
+    }
+}
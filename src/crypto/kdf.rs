@@ -0,0 +1,113 @@
+//! Defines the labeled key derivation functions of the "Key Schedule" section of RFC 9420:
+//! `ExpandWithLabel`, `DeriveSecret`, and `DeriveTreeSecret`. Every secret in the key schedule and
+//! the secret tree is derived through one of these.
+
+use crate::crypto::{hash::HashFunction, hkdf, hmac::HmacKey, secret::Secret};
+
+/// Every label passed to `ExpandWithLabel` gets this prepended to it
+const MLS_LABEL_PREFIX: &[u8] = b"MLS 1.0 ";
+
+// struct {
+//     uint16 length;
+//     opaque label<V> = "MLS 1.0 " + Label;
+//     opaque context<V>;
+// } KDFLabel;
+/// The `info` parameter to HKDF-Expand in `expand_with_label`. The label here already has
+/// `MLS_LABEL_PREFIX` prepended to it.
+#[derive(TlsSerialize)]
+struct KdfLabel<'a> {
+    length: u16,
+    #[tls(bound = "varint")]
+    label: Vec<u8>,
+    #[tls(bound = "varint")]
+    context: &'a [u8],
+}
+
+/// Computes `ExpandWithLabel(secret, label, context, out_buf.len())`, i.e., HKDF-Expand with a
+/// serialized `KDFLabel` as the `info` parameter
+///
+/// Panics: If `out_buf.len() > u16::MAX` or `out_buf.len() > 255 * hash_impl.digest_size()`, or
+/// if the label or context is too long for a varint length, i.e., `2^30` bytes or more
+pub(crate) fn expand_with_label(
+    hash_impl: &dyn HashFunction,
+    secret: &HmacKey,
+    label: &[u8],
+    context: &[u8],
+    out_buf: &mut [u8],
+) {
+    // The output length has to be representable by a u16
+    assert!(out_buf.len() <= std::u16::MAX as usize);
+
+    let kdf_label = KdfLabel {
+        length: out_buf.len() as u16,
+        label: [MLS_LABEL_PREFIX, label].concat(),
+        context,
+    };
+
+    // Serializing only fails if a vector is too long for its length prefix
+    hkdf::expand(hash_impl, secret, &kdf_label, out_buf).expect("label or context is too long");
+}
+
+/// Computes `DeriveSecret(secret, label) = ExpandWithLabel(secret, label, "", Hash.length)`
+pub(crate) fn derive_secret(
+    hash_impl: &dyn HashFunction,
+    secret: &HmacKey,
+    label: &[u8],
+) -> HmacKey {
+    let mut key = HmacKey::new_from_zeros(hash_impl);
+    expand_with_label(hash_impl, secret, label, b"", key.0.as_mut_bytes());
+    key
+}
+
+/// Computes `DeriveTreeSecret(secret, label, generation, length) = ExpandWithLabel(secret, label,
+/// generation, length)`, where `generation` is encoded as a big-endian `u32`
+///
+/// Panics: Under the same conditions as `expand_with_label`
+pub(crate) fn derive_tree_secret(
+    hash_impl: &dyn HashFunction,
+    secret: &HmacKey,
+    label: &[u8],
+    generation: u32,
+    length: usize,
+) -> Secret {
+    let mut out = Secret::new_from_zeros(length);
+    expand_with_label(hash_impl, secret, label, &generation.to_be_bytes(), out.as_mut_bytes());
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::hash::SHA256_IMPL;
+
+    // These answers were computed with an independent implementation of the RFC 9420 definitions
+    // over HMAC-SHA256. The context in the second case is long enough to need a 2-byte varint.
+    #[test]
+    fn labeled_kdf_kat() {
+        let hash_impl = &SHA256_IMPL;
+        let secret = HmacKey::new_from_bytes(&(0u8..32).collect::<Vec<u8>>());
+
+        let mut out = [0u8; 40];
+        expand_with_label(hash_impl, &secret, b"test", b"context", &mut out);
+        assert_eq!(
+            hex::encode(&out[..]),
+            "193095217454c5fb694eb3f263798a42ecc7e6eea9f3d7aa7b728dbdc502d065536062a6a3fa8d74"
+        );
+
+        let mut out = [0u8; 32];
+        expand_with_label(hash_impl, &secret, b"test", &[0xab; 100], &mut out);
+        assert_eq!(
+            hex::encode(&out[..]),
+            "b9a1d92d1a66a54caaa030ed1b7deb32c68d3f179c991ce81251d00aa37159ad"
+        );
+
+        let init_secret = derive_secret(hash_impl, &secret, b"init");
+        assert_eq!(
+            hex::encode(init_secret.as_bytes()),
+            "ef9780f7ca506687ae27e2c81f64e1d8006fddb0a2700a9aad248f4621dd4d88"
+        );
+
+        let key = derive_tree_secret(hash_impl, &secret, b"key", 5, 16);
+        assert_eq!(hex::encode(key.as_bytes()), "2c6a12c7752b1d812faa2afe27d29999");
+    }
+}
@@ -0,0 +1,39 @@
+//! Defines `SigPrivateKeyOps` and `DhPrivateKeyOps`, which let private keys live somewhere this
+//! crate can't read them, like a PKCS#11 token, a TPM, or the Secure Enclave. This crate never
+//! looks at the bytes of a private key directly. Everything it does with one goes through a
+//! signature scheme or DH scheme operation, and those are exactly the operations these traits ask
+//! for.
+
+use crate::error::Error;
+
+/// A trait representing a signing key whose secret part is held outside of this process. Wrap
+/// one of these in a `SigSecretKey` with `SigSecretKey::new_external`.
+pub trait SigPrivateKeyOps: Send + Sync {
+    /// Returns the public key corresponding to this private key, encoded the way the signature
+    /// scheme encodes public keys on the wire
+    fn public_key_bytes(&self) -> Vec<u8>;
+
+    /// Signs the given message
+    ///
+    /// Returns: `Ok(signature)` on success, where `signature` is encoded the way the signature
+    /// scheme encodes signatures on the wire. If the device refuses or fails, returns an
+    /// `Error::SignatureError`.
+    fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// A trait representing a DH or KEM private key whose secret part is held outside of this
+/// process. Such a key can only ever be used to receive, i.e., as an init key or a tree node key.
+/// It can't be used as an ephemeral key.
+pub trait DhPrivateKeyOps: Send + Sync {
+    /// Returns the public key corresponding to this private key, encoded the way the cipher
+    /// suite's DH scheme encodes public keys on the wire
+    fn public_key_bytes(&self) -> Vec<u8>;
+
+    /// Recovers the shared secret from the given encapsulation. For DH schemes, the encapsulation
+    /// is the sender's ephemeral public key, and the shared secret is the raw DH output (for NIST
+    /// curves, the x-coordinate of the shared point).
+    ///
+    /// Returns: `Ok(shared_secret)` on success. If the device refuses or fails, or the
+    /// encapsulation is invalid, returns an `Error::DhError`.
+    fn decapsulate(&self, encapsulation: &[u8]) -> Result<Vec<u8>, Error>;
+}
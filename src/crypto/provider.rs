@@ -0,0 +1,253 @@
+//! Defines `CryptoProvider`, which is where this crate gets its cryptographic algorithms from
+
+use crate::{
+    crypto::{
+        ciphersuite::{
+            CipherSuite, P521_SHA512_AES256GCM, X25519MLKEM768_SHA256_AES128GCM,
+            X25519_SHA256_AES128GCM, X25519_SHA256_CHACHA20POLY1305, X448_SHA512_AES256GCM,
+        },
+        sig::{SignatureScheme, ECDSA_P521_IMPL, ED25519_IMPL, ED448_IMPL},
+    },
+    error::Error,
+};
+
+/// The provider that's used whenever the crate has to look up an algorithm by itself and wasn't
+/// given another, e.g., when deserializing a cipher suite ID
+pub(crate) static DEFAULT_PROVIDER: &dyn CryptoProvider = &BUILTIN_PROVIDER;
+
+// This lets #[tls(skip)] fields hold a provider. Skipped fields are Default when deserialized.
+impl Default for &'static dyn CryptoProvider {
+    fn default() -> &'static dyn CryptoProvider {
+        DEFAULT_PROVIDER
+    }
+}
+
+impl<'a> core::fmt::Debug for dyn CryptoProvider + 'a {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The provider of all the algorithms that ship with this crate
+pub const BUILTIN_PROVIDER: BuiltinProvider = BuiltinProvider;
+
+/// A trait representing a backend for every cryptographic operation in MLS. A provider supplies
+/// cipher suites (which bundle a hash function, HKDF, an AEAD, and a DH scheme), signature
+/// schemes, and randomness. Algorithms are looked up by their MLS names.
+pub trait CryptoProvider: Sync {
+    /// Returns the name of this provider
+    fn name(&self) -> &'static str;
+
+    /// Returns every cipher suite this provider implements
+    fn cipher_suites(&self) -> &'static [&'static CipherSuite];
+
+    /// Returns every signature scheme this provider implements
+    fn signature_schemes(&self) -> &'static [&'static SignatureScheme];
+
+    /// Fills the given buffer with cryptographically secure random bytes
+    ///
+    /// Returns: `Ok(())` on success. If there is no randomness available, returns
+    /// `Error::OutOfEntropy`.
+    fn fill_random(&self, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Looks up the cipher suite with the given name, e.g., `"X25519_SHA256_AES128GCM"`
+    ///
+    /// Returns: `Some(cs)` if this provider implements the cipher suite, and `None` otherwise
+    fn cipher_suite(&self, name: &str) -> Option<&'static CipherSuite> {
+        self.cipher_suites().iter().find(|cs| cs.name == name).copied()
+    }
+
+    /// Looks up the cipher suite with the given registry ID, e.g., `0x0001`
+    ///
+    /// Returns: `Some(cs)` if this provider implements the cipher suite, and `None` otherwise
+    fn cipher_suite_by_id(&self, id: u16) -> Option<&'static CipherSuite> {
+        self.cipher_suites().iter().find(|cs| cs.id == id).copied()
+    }
+
+    /// Looks up the signature scheme with the given name, e.g., `"ed25519"`
+    ///
+    /// Returns: `Some(ss)` if this provider implements the signature scheme, and `None` otherwise
+    fn signature_scheme(&self, name: &str) -> Option<&'static SignatureScheme> {
+        self.signature_schemes().iter().find(|ss| ss.name() == name).copied()
+    }
+
+    /// Looks up the signature scheme with the given TLS registry ID, e.g., `0x0807`
+    ///
+    /// Returns: `Some(ss)` if this provider implements the signature scheme, and `None` otherwise
+    fn signature_scheme_by_id(&self, id: u16) -> Option<&'static SignatureScheme> {
+        self.signature_schemes().iter().find(|ss| ss.id() == id).copied()
+    }
+}
+
+/// The default `CryptoProvider`. This uses `ring` for SHA-2, AEADs, and randomness, the `dalek`
+/// crates for X25519 and Ed25519, `x448` for X448, `ed448-rust` for Ed448, `p521` for P-521 DH and
+/// ECDSA, and `ml-kem` for ML-KEM-768. With the `rustcrypto` feature, `ring` is replaced by
+/// `sha2`, `aes-gcm`, `chacha20poly1305`, and the OS RNG. There's no P-256 yet, so neither
+/// P256_SHA256_AES128GCM nor ECDSA-P256 is offered.
+pub struct BuiltinProvider;
+
+impl CryptoProvider for BuiltinProvider {
+    /// Returns `"builtin"`
+    fn name(&self) -> &'static str {
+        "builtin"
+    }
+
+    fn cipher_suites(&self) -> &'static [&'static CipherSuite] {
+        &[
+            &X25519_SHA256_AES128GCM,
+            &X448_SHA512_AES256GCM,
+            &X25519_SHA256_CHACHA20POLY1305,
+            &P521_SHA512_AES256GCM,
+            &X25519MLKEM768_SHA256_AES128GCM,
+        ]
+    }
+
+    fn signature_schemes(&self) -> &'static [&'static SignatureScheme] {
+        &[&ED25519_IMPL, &ED448_IMPL, &ECDSA_P521_IMPL]
+    }
+
+    /// Fills the given buffer using the OS's secure random number generator
+    #[cfg(not(feature = "rustcrypto"))]
+    fn fill_random(&self, buf: &mut [u8]) -> Result<(), Error> {
+        use ring::rand::SecureRandom;
+
+        ring::rand::SystemRandom::new().fill(buf).map_err(|_| Error::OutOfEntropy)
+    }
+
+    /// Fills the given buffer using the OS's secure random number generator
+    #[cfg(feature = "rustcrypto")]
+    fn fill_random(&self, buf: &mut [u8]) -> Result<(), Error> {
+        use rand::RngCore;
+
+        rand::rngs::OsRng.try_fill_bytes(buf).map_err(|_| Error::OutOfEntropy)
+    }
+}
+
+/// A `CryptoRng` that draws its randomness from a `CryptoProvider`. This lets the randomness of a
+/// provider be used anywhere this crate asks for a `CryptoRng`.
+pub struct ProviderRng<'a>(pub &'a dyn CryptoProvider);
+
+impl<'a> rand::RngCore for ProviderRng<'a> {
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0u8; 4];
+        self.fill_bytes(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0u8; 8];
+        self.fill_bytes(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest).expect("crypto provider ran out of entropy")
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.fill_random(dest).map_err(rand::Error::new)
+    }
+}
+
+// The provider's randomness is, by definition, cryptographically secure
+impl<'a> rand::CryptoRng for ProviderRng<'a> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{
+        crypto::{aead::AES128GCM_IMPL, dh::X25519_IMPL, hash::SHA256},
+        tls_de::{TlsDeserialize, TlsDeserializer},
+    };
+
+    use rand::RngCore;
+
+    // A suite from the private-use range, put together out of built-in parts
+    static PRIVATE_SUITE: CipherSuite = CipherSuite::new(
+        "X25519_SHA256_AES128GCM_PRIVATE",
+        0xff02,
+        &X25519_IMPL,
+        &AES128GCM_IMPL,
+        SHA256,
+    );
+
+    struct PrivateProvider;
+
+    impl CryptoProvider for PrivateProvider {
+        fn name(&self) -> &'static str {
+            "private"
+        }
+
+        fn cipher_suites(&self) -> &'static [&'static CipherSuite] {
+            &[&PRIVATE_SUITE]
+        }
+
+        fn signature_schemes(&self) -> &'static [&'static SignatureScheme] {
+            &[&ED25519_IMPL]
+        }
+
+        fn fill_random(&self, buf: &mut [u8]) -> Result<(), Error> {
+            BUILTIN_PROVIDER.fill_random(buf)
+        }
+    }
+
+    // Cipher suite IDs on the wire should be looked up in whatever provider the deserializer was
+    // given, and only there
+    #[test]
+    fn custom_provider_lookups() {
+        static PROVIDER: PrivateProvider = PrivateProvider;
+        let bytes = [0xffu8, 0x02];
+
+        // The default provider doesn't know this suite
+        let mut cursor = &bytes[..];
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+        assert!(<&CipherSuite>::tls_deserialize(&mut deserializer).is_err());
+
+        // The private one does
+        let mut cursor = &bytes[..];
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor).with_provider(&PROVIDER);
+        let cs = <&CipherSuite>::tls_deserialize(&mut deserializer).unwrap();
+        assert_eq!(cs, &PRIVATE_SUITE);
+        assert_eq!(cs.name(), "X25519_SHA256_AES128GCM_PRIVATE");
+
+        // And it doesn't know the built-in suites
+        let mut cursor = &[0x00u8, 0x01][..];
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor).with_provider(&PROVIDER);
+        assert!(<&CipherSuite>::tls_deserialize(&mut deserializer).is_err());
+    }
+
+    // Every cipher suite and signature scheme should be findable by its own name
+    #[test]
+    fn builtin_lookups() {
+        let provider: &dyn CryptoProvider = &BUILTIN_PROVIDER;
+
+        for cs in provider.cipher_suites() {
+            assert_eq!(provider.cipher_suite(cs.name), Some(*cs));
+            assert_eq!(provider.cipher_suite_by_id(cs.id), Some(*cs));
+            assert_eq!(CipherSuite::from_id(cs.id), Some(*cs));
+        }
+        for ss in provider.signature_schemes() {
+            assert_eq!(provider.signature_scheme(ss.name()), Some(*ss));
+            assert_eq!(provider.signature_scheme_by_id(ss.id()), Some(*ss));
+            assert_eq!(SignatureScheme::from_id(ss.id()), Some(*ss));
+        }
+
+        assert!(provider.cipher_suite("ROT13_MD5_XOR").is_none());
+        assert!(provider.cipher_suite_by_id(0xfffe).is_none());
+        assert!(provider.signature_scheme("rot13").is_none());
+        assert!(provider.signature_scheme_by_id(0xfffe).is_none());
+    }
+
+    // Sanity check that the provider's RNG does something
+    #[test]
+    fn provider_rng() {
+        let mut rng = ProviderRng(&BUILTIN_PROVIDER);
+        let mut buf1 = [0u8; 32];
+        let mut buf2 = [0u8; 32];
+        rng.fill_bytes(&mut buf1);
+        rng.fill_bytes(&mut buf2);
+
+        assert_ne!(buf1, buf2);
+    }
+}
@@ -1,4 +1,9 @@
-//! Defines `CryptoRng`, which is used for secure randomness throughout this crate
+//! Defines `CryptoRng`, which is used for secure randomness throughout this crate. Nothing in this
+//! crate draws randomness on its own. Every operation that needs it (key generation, `UserInitKey`
+//! creation, handshake creation, HPKE encryption) takes a `CryptoRng` argument. So a seeded RNG
+//! makes a run of the protocol reproducible, and a hardware RNG can be used by implementing
+//! `rand::RngCore` and `rand::CryptoRng` for it. To draw from a `CryptoProvider`, use
+//! `ProviderRng`.
 
 /// A trait representing a cryptographically secure random number generator
 pub trait CryptoRng: rand::RngCore + rand::CryptoRng {}
@@ -0,0 +1,112 @@
+//! Defines `Secret`, which is what every secret byte string in this crate is kept in. It zeroes
+//! its contents when it's dropped, and it compares in constant time.
+
+use crate::crypto::rng::CryptoRng;
+
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+/// A secret byte string. On the wire, this is the same as a `Vec<u8>`, so wrap it in a bounded
+/// newtype if it needs a length tag.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    /// Copies the given bytes into a new `Secret`. To avoid leaving an unzeroed copy behind, use
+    /// `Secret::from` on a `Vec<u8>` instead.
+    pub fn new_from_bytes(bytes: &[u8]) -> Secret {
+        Secret(bytes.to_vec())
+    }
+
+    pub(crate) fn new_from_zeros(len: usize) -> Secret {
+        Secret(vec![0u8; len])
+    }
+
+    pub(crate) fn new_from_random<R>(len: usize, csprng: &mut R) -> Secret
+    where
+        R: CryptoRng,
+    {
+        let mut s = Secret::new_from_zeros(len);
+        csprng.fill_bytes(s.as_mut_bytes());
+        s
+    }
+
+    /// Returns the contents of this secret
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    pub(crate) fn as_mut_bytes(&mut self) -> &mut [u8] {
+        self.0.as_mut_slice()
+    }
+
+    /// Returns the length of this secret, in bytes
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether this secret is zero bytes long
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// This takes ownership of the vector, so there's no unzeroed copy left behind
+impl From<Vec<u8>> for Secret {
+    fn from(bytes: Vec<u8>) -> Secret {
+        Secret(bytes)
+    }
+}
+
+impl core::iter::FromIterator<u8> for Secret {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Secret {
+        Secret(iter.into_iter().collect())
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl ConstantTimeEq for Secret {
+    fn ct_eq(&self, other: &Secret) -> subtle::Choice {
+        self.as_bytes().ct_eq(other.as_bytes())
+    }
+}
+
+// Comparing secrets with == shouldn't leak where they differ. The lengths aren't secret, though.
+impl PartialEq for Secret {
+    fn eq(&self, other: &Secret) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for Secret {}
+
+impl core::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        // Ensure that the secret value isn't accidentally logged
+        f.write_str("Secret: CONTENTS OMITTED")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Secrets should be equal iff their contents are
+    #[test]
+    fn secret_eq() {
+        let a = Secret::new_from_bytes(b"hello");
+        let b = Secret::from(b"hello".to_vec());
+        let c = Secret::new_from_bytes(b"hellp");
+        let d = Secret::new_from_bytes(b"hello!");
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, d);
+        assert_eq!(Secret::new_from_zeros(3).as_bytes(), &[0u8; 3]);
+    }
+}
@@ -1,9 +1,14 @@
 //! Defines `SignatureScheme` and other related digital signature-related data structures and
 //! algorithms used in MLS
 
-use crate::crypto::rng::CryptoRng;
+use crate::crypto::{
+    key_ops::SigPrivateKeyOps, provider::DEFAULT_PROVIDER, rng::CryptoRng, secret::Secret,
+};
 use crate::error::Error;
 
+use core::convert::TryFrom;
+use std::sync::Arc;
+
 use ed25519_dalek::ed25519::signature::Signature as SigTrait;
 use ed25519_dalek::Verifier;
 
@@ -11,13 +16,36 @@ use ed25519_dalek::Verifier;
 /// should use `&'static` references to this.
 pub const ED25519_IMPL: SignatureScheme = SignatureScheme(&Ed25519);
 
-/// A dummy placeholder for the canonical instantiation of the ECDSA-over-P256 `SignatureScheme`
+/// The canonical instantiation of the ed448 `SignatureScheme`. Things that use this algorithm
+/// should use `&'static` references to this.
+pub const ED448_IMPL: SignatureScheme = SignatureScheme(&Ed448);
+
+/// Size of Ed448 public and secret keys, in bytes
+const ED448_KEY_SIZE: usize = 57;
+/// Size of Ed448 signatures, in bytes
+const ED448_SIG_SIZE: usize = 114;
+
+/// The canonical instantiation of the ECDSA-over-P521-with-SHA512 `SignatureScheme`. Things that
+/// use this algorithm should use `&'static` references to this.
+pub const ECDSA_P521_IMPL: SignatureScheme = SignatureScheme(&EcdsaP521);
+
+/// Size of an uncompressed SEC1 encoding of a P-521 point, in bytes
+const P521_POINT_SIZE: usize = 1 + 2 * 66;
+/// Size of P-521 scalars, in bytes
+const P521_SCALAR_SIZE: usize = 66;
+/// Size of ECDSA-P521 signatures in their fixed-width `r || s` form, in bytes
+const P521_SIG_SIZE: usize = 2 * P521_SCALAR_SIZE;
+
+/// A dummy placeholder for the canonical instantiation of the ECDSA-over-P256 `SignatureScheme`.
+/// Only P-256 public keys and signatures can be parsed, so only the tests that parse the draft
+/// message vectors use this.
+#[cfg(test)]
 pub(crate) const ECDSA_P256_IMPL: SignatureScheme = SignatureScheme(&DummyEcdsaP256);
 
 // opaque SignaturePublicKey<1..2^16-1>
 /// The form that all `SigPublicKey`s take when being sent or received over the wire
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-#[serde(rename = "SigPublicKeyRaw__bound_u16")]
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(bound = "u16")]
 pub struct SigPublicKeyRaw(pub(crate) Vec<u8>);
 
 /// An enum of possible types for a signature scheme's public key, depending on the underlying
@@ -25,6 +53,14 @@ pub struct SigPublicKeyRaw(pub(crate) Vec<u8>);
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum SigPublicKey {
     Ed25519PublicKey(ed25519_dalek::PublicKey),
+    /// An Ed448 public key. This is checked to be a valid curve point when it's made.
+    Ed448PublicKey([u8; ED448_KEY_SIZE]),
+    /// An uncompressed SEC1 encoding of a P-521 point. This is checked to be a valid curve point
+    /// when it's made.
+    EcdsaP521PublicKey(Vec<u8>),
+    /// A public key in a signature scheme that isn't built in, as it goes on the wire, along with
+    /// the scheme it's for
+    Custom(&'static SignatureScheme, Vec<u8>),
     Raw(SigPublicKeyRaw),
 }
 
@@ -32,10 +68,24 @@ impl SigPublicKey {
     pub fn as_bytes(&self) -> &[u8] {
         match self {
             SigPublicKey::Ed25519PublicKey(p) => p.as_bytes(),
+            SigPublicKey::Ed448PublicKey(p) => &p[..],
+            SigPublicKey::EcdsaP521PublicKey(p) => p.as_slice(),
+            SigPublicKey::Custom(_, p) => p.as_slice(),
             SigPublicKey::Raw(p) => p.0.as_slice(),
         }
     }
 
+    /// Returns the signature scheme this key is for, or `None` if the key hasn't been upcast yet
+    pub fn signature_scheme(&self) -> Option<&'static SignatureScheme> {
+        match self {
+            SigPublicKey::Ed25519PublicKey(_) => Some(&ED25519_IMPL),
+            SigPublicKey::Ed448PublicKey(_) => Some(&ED448_IMPL),
+            SigPublicKey::EcdsaP521PublicKey(_) => Some(&ECDSA_P521_IMPL),
+            SigPublicKey::Custom(ss, _) => Some(*ss),
+            SigPublicKey::Raw(_) => None,
+        }
+    }
+
     // This just passes through to `SignatureSchemeInterface::public_key_from_bytes`
     /// Creates a public key from the provided bytes
     ///
@@ -45,10 +95,14 @@ impl SigPublicKey {
         ss.0.public_key_from_bytes(bytes)
     }
 
-    // This just passes through to `SignatureSchemeInterface::public_key_from_secret_key`
+    // This passes through to `SignatureSchemeInterface::public_key_from_secret_key`, unless the
+    // key is external, in which case there's nothing to derive
     /// Derives the public key corresponding to the given secret key
     pub fn new_from_secret_key(ss: &SignatureScheme, secret_key: &SigSecretKey) -> SigPublicKey {
-        ss.0.public_key_from_secret_key(secret_key)
+        match secret_key {
+            SigSecretKey::External(k) => k.public_key.clone(),
+            _ => ss.0.public_key_from_secret_key(secret_key),
+        }
     }
 }
 
@@ -56,6 +110,23 @@ impl SigPublicKey {
 /// algorithm
 pub enum SigSecretKey {
     Ed25519SecretKey(ed25519_dalek::SecretKey),
+    Ed448SecretKey(Secret),
+    EcdsaP521SecretKey(p521::SecretKey),
+    /// A key held outside of this process, e.g., in an HSM. Make this with
+    /// `SigSecretKey::new_external`.
+    External(ExternalSigSecretKey),
+    /// The bytes of a secret key in a signature scheme that isn't built in, along with the scheme
+    /// it's for. This is what a `SignatureSchemeInterface` that this crate doesn't ship with makes
+    /// its secret keys out of.
+    Custom(&'static SignatureScheme, Secret),
+}
+
+/// A handle to a signing key held outside of this process, along with its public key. The public
+/// key is checked to be valid for the signature scheme when the handle is made.
+#[derive(Clone)]
+pub struct ExternalSigSecretKey {
+    ops: Arc<dyn SigPrivateKeyOps>,
+    public_key: SigPublicKey,
 }
 
 impl SigSecretKey {
@@ -79,6 +150,46 @@ impl SigSecretKey {
     {
         ss.0.secret_key_from_random(csprng)
     }
+
+    /// Makes a secret key out of a handle to a key held outside of this process. Signing with the
+    /// result calls `ops.sign`.
+    ///
+    /// Returns: `Ok(secret_key)` on success. If the handle's public key isn't a valid public key
+    /// for this signature scheme, returns an `Error::SignatureError`.
+    pub fn new_external(
+        ss: &SignatureScheme,
+        ops: Arc<dyn SigPrivateKeyOps>,
+    ) -> Result<SigSecretKey, Error> {
+        let public_key = ss.0.public_key_from_bytes(&ops.public_key_bytes())?;
+        Ok(SigSecretKey::External(ExternalSigSecretKey {
+            ops,
+            public_key,
+        }))
+    }
+
+    /// Returns the signature scheme this key is for, or `None` if it's an external key whose
+    /// public key is of a scheme this crate only has a placeholder for
+    pub fn signature_scheme(&self) -> Option<&'static SignatureScheme> {
+        match self {
+            SigSecretKey::Ed25519SecretKey(_) => Some(&ED25519_IMPL),
+            SigSecretKey::Ed448SecretKey(_) => Some(&ED448_IMPL),
+            SigSecretKey::EcdsaP521SecretKey(_) => Some(&ECDSA_P521_IMPL),
+            SigSecretKey::External(k) => k.public_key.signature_scheme(),
+            SigSecretKey::Custom(ss, _) => Some(*ss),
+        }
+    }
+
+    /// Returns the key's bytes, in the form that `SigSecretKey::new_from_bytes` takes, or `None`
+    /// if the key is held outside of this process
+    pub(crate) fn to_secret_bytes(&self) -> Option<Secret> {
+        match self {
+            SigSecretKey::Ed25519SecretKey(s) => Some(Secret::new_from_bytes(s.as_bytes())),
+            SigSecretKey::Ed448SecretKey(s) => Some(s.clone()),
+            SigSecretKey::EcdsaP521SecretKey(s) => Some(Secret::new_from_bytes(&s.to_bytes())),
+            SigSecretKey::External(_) => None,
+            SigSecretKey::Custom(_, s) => Some(s.clone()),
+        }
+    }
 }
 
 // We only really need this in order to derive(Clone) for GroupState
@@ -89,6 +200,10 @@ impl Clone for SigSecretKey {
                 let inner_clone = ed25519_dalek::SecretKey::from_bytes(s.as_bytes()).unwrap();
                 SigSecretKey::Ed25519SecretKey(inner_clone)
             }
+            SigSecretKey::Ed448SecretKey(s) => SigSecretKey::Ed448SecretKey(s.clone()),
+            SigSecretKey::EcdsaP521SecretKey(s) => SigSecretKey::EcdsaP521SecretKey(s.clone()),
+            SigSecretKey::External(k) => SigSecretKey::External(k.clone()),
+            SigSecretKey::Custom(ss, s) => SigSecretKey::Custom(*ss, s.clone()),
         }
     }
 }
@@ -102,9 +217,9 @@ impl core::fmt::Debug for SigSecretKey {
 
 // opaque UserInitKey::signature<0..2^16-1>
 /// The form that all `Signature`s take when being sent or received over the wire
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
-#[serde(rename = "SignatureRaw__bound_u16")]
+#[tls(bound = "u16")]
 pub struct SignatureRaw(pub(crate) Vec<u8>);
 
 /// An enum of possible types for a signature scheme's signature, depending on the underlying
@@ -113,6 +228,10 @@ pub struct SignatureRaw(pub(crate) Vec<u8>);
 #[cfg_attr(test, derive(Debug))]
 pub enum Signature {
     Ed25519Signature(ed25519_dalek::Signature),
+    Ed448Signature([u8; ED448_SIG_SIZE]),
+    EcdsaP521Signature(p521::ecdsa::Signature),
+    /// A signature in a signature scheme that isn't built in, as it goes on the wire
+    Custom(Vec<u8>),
     Raw(SignatureRaw),
 }
 
@@ -121,6 +240,9 @@ impl Signature {
     pub(crate) fn as_bytes(&self) -> Vec<u8> {
         match self {
             Signature::Ed25519Signature(s) => s.to_bytes().to_vec(),
+            Signature::Ed448Signature(s) => s.to_vec(),
+            Signature::EcdsaP521Signature(s) => s.to_bytes().to_vec(),
+            Signature::Custom(s) => s.clone(),
             Signature::Raw(s) => s.0.clone(),
         }
     }
@@ -146,20 +268,54 @@ impl Signature {
 // should not be able to use, like `sign` and `verify. This is not ideal.
 // If on the other hand we expose a struct, we get more control over which methods are public.
 // We pay for this additional complexity with some code repetition, but I think it's worth it.
+// The trait is exported too, so that providers can implement it, but the only way to use a
+// scheme is still through this struct.
 /// A struct representing any signature scheme
 pub struct SignatureScheme(&'static dyn SignatureSchemeInterface);
 
 impl SignatureScheme {
+    /// Wraps an implementation of a signature scheme, so that a `CryptoProvider` can offer it
+    pub const fn new(scheme: &'static dyn SignatureSchemeInterface) -> SignatureScheme {
+        SignatureScheme(scheme)
+    }
+
+    /// Looks up the signature scheme with the given identifier in the TLS `SignatureScheme`
+    /// registry, e.g., `0x0807` for Ed25519. Signature schemes are chosen independently of cipher
+    /// suites, so any of these can be used with any cipher suite.
+    ///
+    /// Returns: `Some(ss)` if the default provider implements the signature scheme, and `None`
+    /// otherwise
+    pub fn from_id(id: u16) -> Option<&'static SignatureScheme> {
+        DEFAULT_PROVIDER.signature_scheme_by_id(id)
+    }
+
+    // This just passes through to `SignatureSchemeInterface::id`
+    /// Returns the identifier of this signature scheme in the TLS `SignatureScheme` registry
+    pub fn id(&self) -> u16 {
+        self.0.id()
+    }
+
     // This just passes through to `SignatureSchemeInterface::name`
     /// Returns the signature scheme's name, as per the MLS spec. Here, it is `ed25519`
     pub(crate) fn name(&self) -> &'static str {
         self.0.name()
     }
 
-    // This just passes through to `SignatureSchemeInterface::sign`
+    // This passes through to `SignatureSchemeInterface::sign`, or to the key's handle if it's
+    // external
     /// Computes a signature of the given message under the given secret key
-    pub(crate) fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Signature {
-        self.0.sign(secret, msg)
+    ///
+    /// Returns: `Ok(signature)` on success. If the secret key is external and the device fails,
+    /// or it returns something that isn't a signature in this scheme, returns an
+    /// `Error::SignatureError`.
+    pub(crate) fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Result<Signature, Error> {
+        match secret {
+            SigSecretKey::External(k) => {
+                let sig_bytes = k.ops.sign(msg)?;
+                self.0.signature_from_bytes(&sig_bytes)
+            }
+            _ => Ok(self.0.sign(secret, msg)),
+        }
     }
 
     // This just passes through to `SignatureSchemeInterface::verify`
@@ -185,31 +341,46 @@ impl core::fmt::Debug for SignatureScheme {
 
 impl PartialEq for SignatureScheme {
     fn eq(&self, other: &SignatureScheme) -> bool {
-        self.0.name() == other.0.name()
+        self.0.id() == other.0.id()
     }
 }
 
 impl Eq for SignatureScheme {}
 
-/// A trait representing any signature scheme
-trait SignatureSchemeInterface {
+/// A trait representing any signature scheme. An implementation that isn't built in keeps its
+/// keys in `SigPublicKey::Custom` and `SigSecretKey::Custom`, and its signatures in
+/// `Signature::Custom`. It's never handed a `SigSecretKey::External`, since `SignatureScheme`
+/// deals with those itself.
+pub trait SignatureSchemeInterface: Sync {
+    /// Returns the name of this scheme, as per the MLS spec, e.g., `ed25519`
     fn name(&self) -> &'static str;
 
+    /// Returns the identifier of this scheme in the TLS `SignatureScheme` registry
+    fn id(&self) -> u16;
+
+    /// Decodes a signature
     fn signature_from_bytes(&self, bytes: &[u8]) -> Result<Signature, Error>;
 
+    /// Decodes a public key, and checks that it's valid for this scheme
     fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<SigPublicKey, Error>;
 
+    /// Derives the public key of the given secret key
     fn public_key_from_secret_key(&self, secret: &SigSecretKey) -> SigPublicKey;
 
+    /// Decodes a secret key
     fn secret_key_from_bytes(&self, bytes: &[u8]) -> Result<SigSecretKey, Error>;
 
     // This has to take a dyn CryptoRng because SignatureSchemeInterface is used as a trait object
     // inside SignatureScheme. Trait objects can't have associated types, associated constants, or
     // generic methods.
+    /// Generates a random secret key
     fn secret_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<SigSecretKey, Error>;
 
+    /// Signs the given message
     fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Signature;
 
+    /// Verifies the signature of the given message. If it doesn't verify, this has to return an
+    /// `Error::SignatureError`.
     fn verify(&self, public_key: &SigPublicKey, msg: &[u8], sig: &Signature) -> Result<(), Error>;
 }
 
@@ -224,6 +395,11 @@ impl SignatureSchemeInterface for Ed25519 {
         "ed25519"
     }
 
+    /// Returns `ed25519`'s ID in the TLS `SignatureScheme` registry, which is `0x0807`
+    fn id(&self) -> u16 {
+        0x0807
+    }
+
     /// Creates a signature from the provided bytes
     ///
     /// Returns: `Ok(signature)` on success. If anything goes wrong, returns an
@@ -304,13 +480,241 @@ impl SignatureSchemeInterface for Ed25519 {
     }
 }
 
+/// Represents the Ed448 signature scheme, as defined in RFC 8032. Notably, it implements
+/// `SignatureSchemeInterface`.
+pub struct Ed448;
+
+impl SignatureSchemeInterface for Ed448 {
+    /// Returns the signature scheme's name, as per the MLS spec. Here, it is `ed448`
+    fn name(&self) -> &'static str {
+        "ed448"
+    }
+
+    /// Returns `ed448`'s ID in the TLS `SignatureScheme` registry, which is `0x0808`
+    fn id(&self) -> u16 {
+        0x0808
+    }
+
+    /// Creates a signature from the provided bytes
+    ///
+    /// Returns: `Ok(signature)` on success. If the signature isn't 114 bytes long, returns an
+    /// `Error::SignatureError`.
+    fn signature_from_bytes(&self, bytes: &[u8]) -> Result<Signature, Error> {
+        if bytes.len() != ED448_SIG_SIZE {
+            return Err(Error::SignatureError("Invalid signature bytes"));
+        }
+
+        let mut buf = [0u8; ED448_SIG_SIZE];
+        buf.copy_from_slice(bytes);
+        Ok(Signature::Ed448Signature(buf))
+    }
+
+    /// Creates a public key from the provided bytes
+    ///
+    /// Returns: `Ok(public_key)` on success. If the bytes aren't a valid encoding of a curve
+    /// point, returns an `Error::SignatureError`.
+    fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<SigPublicKey, Error> {
+        if bytes.len() != ED448_KEY_SIZE {
+            return Err(Error::SignatureError("Invalid public key bytes"));
+        }
+        // Make sure this decompresses to a real point
+        ed448_rust::PublicKey::try_from(bytes)
+            .map_err(|_| Error::SignatureError("Invalid public key bytes"))?;
+
+        let mut buf = [0u8; ED448_KEY_SIZE];
+        buf.copy_from_slice(bytes);
+        Ok(SigPublicKey::Ed448PublicKey(buf))
+    }
+
+    /// Derives the public key corresponding to the given secret key
+    fn public_key_from_secret_key(&self, secret: &SigSecretKey) -> SigPublicKey {
+        let secret = ed448_private_key(secret);
+        let public_key = ed448_rust::PublicKey::from(&secret);
+        SigPublicKey::Ed448PublicKey(public_key.as_byte())
+    }
+
+    /// Creates a key pair from the provided secret key bytes
+    ///
+    /// Returns: `Ok(secret_key)` on success. Returns an `Error::SignatureError` iff the number of
+    /// bytes is not precisely the size of a secret key.
+    fn secret_key_from_bytes(&self, bytes: &[u8]) -> Result<SigSecretKey, Error> {
+        if bytes.len() != ED448_KEY_SIZE {
+            return Err(Error::SignatureError("Invalid secret key"));
+        }
+
+        Ok(SigSecretKey::Ed448SecretKey(Secret::new_from_bytes(bytes)))
+    }
+
+    /// Generates a random key pair using the given CSPRNG
+    ///
+    /// Returns: `Ok(secret_key)` on success. On error, returns `Error::OutOfEntropy`.
+    fn secret_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<SigSecretKey, Error> {
+        let mut key_bytes = Secret::new_from_zeros(ED448_KEY_SIZE);
+        csprng.try_fill_bytes(key_bytes.as_mut_bytes()).map_err(|_| Error::OutOfEntropy)?;
+        Ok(SigSecretKey::Ed448SecretKey(key_bytes))
+    }
+
+    /// Computes a signature of the given message under the given secret key. MLS doesn't use
+    /// Ed448 contexts, so the context is empty.
+    fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Signature {
+        let secret = ed448_private_key(secret);
+        // This can only fail if the context is too long, and ours is empty
+        let sig = secret.sign(msg, None).expect("Ed448 signing failed with an empty context");
+        Signature::Ed448Signature(sig)
+    }
+
+    /// Verifies the signature of the given message under the given public key
+    ///
+    /// Returns: `Ok(())` iff the signature succeeded. Otherwise, returns an
+    /// `Err(Error::SignatureError)`.
+    fn verify(&self, public_key: &SigPublicKey, msg: &[u8], sig: &Signature) -> Result<(), Error> {
+        let public_key = enum_variant!(public_key, SigPublicKey::Ed448PublicKey);
+        let sig = enum_variant!(sig, Signature::Ed448Signature);
+
+        let public_key = ed448_rust::PublicKey::try_from(&public_key[..])
+            .map_err(|_| Error::SignatureError("Invalid public key bytes"))?;
+        public_key.verify(msg, &sig[..], None).map_err(|_| Error::SignatureError("Bad signature"))
+    }
+}
+
+/// Makes the `ed448_rust` key out of an Ed448 `SigSecretKey`. The bytes are read straight out of
+/// the `Secret`, so the only copy is the one inside `ed448_rust`, which lasts as long as the
+/// returned key.
+fn ed448_private_key(secret: &SigSecretKey) -> ed448_rust::PrivateKey {
+    let secret = enum_variant!(secret, SigSecretKey::Ed448SecretKey);
+    ed448_rust::PrivateKey::try_from(secret.as_bytes())
+        .expect("Ed448 secret keys are always ED448_KEY_SIZE bytes")
+}
+
+/// Represents ECDSA over P-521 with SHA-512, as defined in FIPS 186-4. Notably, it implements
+/// `SignatureSchemeInterface`. Signatures are encoded as the fixed-width big-endian `r || s`.
+pub struct EcdsaP521;
+
+impl SignatureSchemeInterface for EcdsaP521 {
+    /// Returns the signature scheme's name, as per the TLS registry. Here, it is
+    /// `ecdsa_secp521r1_sha512`
+    fn name(&self) -> &'static str {
+        "ecdsa_secp521r1_sha512"
+    }
+
+    /// Returns `ecdsa_secp521r1_sha512`'s ID in the TLS `SignatureScheme` registry, which is
+    /// `0x0603`
+    fn id(&self) -> u16 {
+        0x0603
+    }
+
+    /// Creates a signature from the provided bytes
+    ///
+    /// Returns: `Ok(signature)` on success. If the signature isn't 132 bytes long, or `r` or `s`
+    /// is zero or out of range, returns an `Error::SignatureError`.
+    fn signature_from_bytes(&self, bytes: &[u8]) -> Result<Signature, Error> {
+        if bytes.len() != P521_SIG_SIZE {
+            return Err(Error::SignatureError("Invalid signature bytes"));
+        }
+
+        p521::ecdsa::Signature::from_slice(bytes)
+            .map(Signature::EcdsaP521Signature)
+            .map_err(|_| Error::SignatureError("Invalid signature bytes"))
+    }
+
+    /// Creates a public key from the provided bytes
+    ///
+    /// Requires: `bytes.len() == P521_POINT_SIZE == 133`
+    ///
+    /// Returns: `Ok(public_key)` on success. If the bytes aren't an uncompressed encoding of a
+    /// curve point, returns an `Error::SignatureError`.
+    fn public_key_from_bytes(&self, bytes: &[u8]) -> Result<SigPublicKey, Error> {
+        if bytes.len() != P521_POINT_SIZE {
+            return Err(Error::SignatureError("Invalid public key bytes"));
+        }
+        // Make sure this is a real point
+        p521::PublicKey::from_sec1_bytes(bytes)
+            .map_err(|_| Error::SignatureError("Invalid public key bytes"))?;
+
+        Ok(SigPublicKey::EcdsaP521PublicKey(bytes.to_vec()))
+    }
+
+    /// Derives the public key corresponding to the given secret key
+    fn public_key_from_secret_key(&self, secret: &SigSecretKey) -> SigPublicKey {
+        use p521::elliptic_curve::sec1::ToEncodedPoint;
+
+        let secret = enum_variant!(secret, SigSecretKey::EcdsaP521SecretKey);
+        let point = secret.public_key().to_encoded_point(false);
+        SigPublicKey::EcdsaP521PublicKey(point.as_bytes().to_vec())
+    }
+
+    /// Interprets the given bytes as a big-endian integer modulo the order of the P-521 group
+    ///
+    /// Returns: `Ok(secret_key)` on success. Returns an `Error::SignatureError` iff the number of
+    /// bytes is not precisely 66, or the integer is zero or not less than the group order.
+    fn secret_key_from_bytes(&self, bytes: &[u8]) -> Result<SigSecretKey, Error> {
+        if bytes.len() != P521_SCALAR_SIZE {
+            return Err(Error::SignatureError("Invalid secret key"));
+        }
+
+        p521::SecretKey::from_slice(bytes)
+            .map(SigSecretKey::EcdsaP521SecretKey)
+            .map_err(|_| Error::SignatureError("Invalid secret key"))
+    }
+
+    /// Generates a random key pair by rejection sampling
+    ///
+    /// Returns: `Ok(secret_key)` on success. On error, returns `Error::OutOfEntropy`.
+    fn secret_key_from_random(&self, csprng: &mut dyn CryptoRng) -> Result<SigSecretKey, Error> {
+        let mut buf = [0u8; P521_SCALAR_SIZE];
+        loop {
+            csprng.try_fill_bytes(&mut buf).map_err(|_| Error::OutOfEntropy)?;
+            // Same trick as the P-521 DH scheme: the group order is a 521-bit number, so mask the
+            // top byte down to its lowest bit
+            buf[0] &= 0x01;
+            if let Ok(secret) = self.secret_key_from_bytes(&buf) {
+                return Ok(secret);
+            }
+        }
+    }
+
+    /// Computes a signature of the given message under the given secret key. The nonce is
+    /// derived deterministically, as per RFC 6979, so this doesn't need an RNG.
+    fn sign(&self, secret: &SigSecretKey, msg: &[u8]) -> Signature {
+        use p521::ecdsa::signature::Signer;
+
+        let secret = enum_variant!(secret, SigSecretKey::EcdsaP521SecretKey);
+        // A SecretKey is always a valid scalar, so this can't fail
+        let signing_key = p521::ecdsa::SigningKey::from_slice(&secret.to_bytes())
+            .expect("P-521 secret key is not a valid signing key");
+
+        Signature::EcdsaP521Signature(signing_key.sign(msg))
+    }
+
+    /// Verifies the signature of the given message under the given public key
+    ///
+    /// Returns: `Ok(())` iff the signature succeeded. Otherwise, returns an
+    /// `Err(Error::SignatureError)`.
+    fn verify(&self, public_key: &SigPublicKey, msg: &[u8], sig: &Signature) -> Result<(), Error> {
+        use p521::ecdsa::signature::Verifier;
+
+        let public_key = enum_variant!(public_key, SigPublicKey::EcdsaP521PublicKey);
+        let sig = enum_variant!(sig, Signature::EcdsaP521Signature);
+
+        let verifying_key = p521::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+            .map_err(|_| Error::SignatureError("Invalid public key bytes"))?;
+        verifying_key.verify(msg, sig).map_err(|_| Error::SignatureError("Bad signature"))
+    }
+}
+
+#[cfg(test)]
 pub(crate) struct DummyEcdsaP256;
 
+#[cfg(test)]
 impl SignatureSchemeInterface for DummyEcdsaP256 {
     fn name(&self) -> &'static str {
         "dummy_ecdsa_secp256r1_sha256"
     }
 
+    fn id(&self) -> u16 {
+        0x0403
+    }
+
     fn signature_from_bytes(&self, bytes: &[u8]) -> Result<Signature, Error> {
         if bytes.len() != 64 {
             Err(Error::SignatureError("P256 ECDSA signature isn't 64 bytes long"))
@@ -329,20 +733,22 @@ impl SignatureSchemeInterface for DummyEcdsaP256 {
         }
     }
 
+    // Every way of making a P-256 secret key fails, so there's never one to derive from or sign
+    // with
     fn public_key_from_secret_key(&self, _secret: &SigSecretKey) -> SigPublicKey {
-        unimplemented!()
+        unreachable!("ECDSA-P256 secret keys can't be made")
     }
 
     fn secret_key_from_bytes(&self, _bytes: &[u8]) -> Result<SigSecretKey, Error> {
-        unimplemented!()
+        Err(Error::SignatureError("ECDSA-P256 isn't implemented"))
     }
 
     fn secret_key_from_random(&self, _csprng: &mut dyn CryptoRng) -> Result<SigSecretKey, Error> {
-        unimplemented!()
+        Err(Error::SignatureError("ECDSA-P256 isn't implemented"))
     }
 
     fn sign(&self, _secret: &SigSecretKey, _msg: &[u8]) -> Signature {
-        unimplemented!()
+        unreachable!("ECDSA-P256 secret keys can't be made")
     }
 
     fn verify(
@@ -351,7 +757,7 @@ impl SignatureSchemeInterface for DummyEcdsaP256 {
         _msg: &[u8],
         _sig: &Signature,
     ) -> Result<(), Error> {
-        unimplemented!()
+        Err(Error::SignatureError("ECDSA-P256 isn't implemented"))
     }
 }
 
@@ -404,7 +810,7 @@ mod test {
             // Make sure the expected public key and the public key we derived are the same
             assert_eq!(expected_public.as_bytes(), derived_public.as_bytes());
 
-            let derived_sig = ss.sign(&secret, &msg);
+            let derived_sig = ss.sign(&secret, &msg).unwrap();
             let expected_sig = hex::decode(sig_hex).unwrap();
 
             assert_eq!(&expected_sig, &derived_sig.as_bytes());
@@ -427,9 +833,88 @@ mod test {
         let public_key = SigPublicKey::new_from_secret_key(ss, &secret_key);
 
         // Sign the random message we were given
-        let sig = ss.sign(&secret_key, &msg);
+        let sig = ss.sign(&secret_key, &msg).unwrap();
 
         // Make sure the signature we just made is valid
         assert!(ss.verify(&public_key, &msg, &sig).is_ok());
     }
+
+    #[quickcheck]
+    fn ed448_correctness(msg: Vec<u8>, secret_seed: u64) {
+        let ss: &'static SignatureScheme = &ED448_IMPL;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        let secret_key = SigSecretKey::new_from_random(ss, &mut rng).unwrap();
+        let public_key = SigPublicKey::new_from_secret_key(ss, &secret_key);
+
+        // Sign the random message we were given and make sure the signature is valid
+        let sig = ss.sign(&secret_key, &msg).unwrap();
+        assert!(ss.verify(&public_key, &msg, &sig).is_ok());
+
+        // Round-trip the public key and signature through bytes and check again
+        let public_key = SigPublicKey::new_from_bytes(ss, public_key.as_bytes()).unwrap();
+        let sig = Signature::new_from_bytes(ss, &sig.as_bytes()).unwrap();
+        assert!(ss.verify(&public_key, &msg, &sig).is_ok());
+
+        // A different message shouldn't verify
+        let mut other_msg = msg.clone();
+        other_msg.push(0x00);
+        assert!(ss.verify(&public_key, &other_msg, &sig).is_err());
+    }
+
+    #[quickcheck]
+    fn ecdsa_p521_correctness(msg: Vec<u8>, secret_seed: u64) {
+        let ss: &'static SignatureScheme = &ECDSA_P521_IMPL;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(secret_seed);
+        let secret_key = SigSecretKey::new_from_random(ss, &mut rng).unwrap();
+        let public_key = SigPublicKey::new_from_secret_key(ss, &secret_key);
+
+        // Sign the random message we were given and make sure the signature is valid
+        let sig = ss.sign(&secret_key, &msg).unwrap();
+        assert!(ss.verify(&public_key, &msg, &sig).is_ok());
+
+        // Round-trip the public key and signature through bytes and check again
+        let public_key = SigPublicKey::new_from_bytes(ss, public_key.as_bytes()).unwrap();
+        let sig = Signature::new_from_bytes(ss, &sig.as_bytes()).unwrap();
+        assert!(ss.verify(&public_key, &msg, &sig).is_ok());
+
+        // A different message shouldn't verify
+        let mut other_msg = msg.clone();
+        other_msg.push(0x00);
+        assert!(ss.verify(&public_key, &other_msg, &sig).is_err());
+    }
+
+    // A stand-in for an HSM. It does Ed25519 in software, but all the crate gets to see is its
+    // public key and its signatures.
+    struct SoftwareEd25519Hsm(SigSecretKey);
+
+    impl SigPrivateKeyOps for SoftwareEd25519Hsm {
+        fn public_key_bytes(&self) -> Vec<u8> {
+            SigPublicKey::new_from_secret_key(&ED25519_IMPL, &self.0).as_bytes().to_vec()
+        }
+
+        fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(ED25519_IMPL.sign(&self.0, msg)?.as_bytes())
+        }
+    }
+
+    // Signing with an external key should give the same signature as signing with the key itself
+    #[quickcheck]
+    fn external_key_correctness(msg: Vec<u8>, rng_seed: u64) {
+        let ss: &'static SignatureScheme = &ED25519_IMPL;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let software_key = SigSecretKey::new_from_random(ss, &mut rng).unwrap();
+        let external_key =
+            SigSecretKey::new_external(ss, Arc::new(SoftwareEd25519Hsm(software_key.clone())))
+                .unwrap();
+
+        let public_key = SigPublicKey::new_from_secret_key(ss, &external_key);
+        assert_eq!(public_key, SigPublicKey::new_from_secret_key(ss, &software_key));
+
+        let sig = ss.sign(&external_key, &msg).unwrap();
+        assert!(ss.verify(&public_key, &msg, &sig).is_ok());
+        assert_eq!(sig.as_bytes(), ss.sign(&software_key, &msg).unwrap().as_bytes());
+    }
 }
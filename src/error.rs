@@ -1,5 +1,11 @@
 //! Defines `Error`, which we use to represent anything that goes wrong in this crate
 
+use crate::{
+    credential::CredentialContext,
+    handshake::ProtocolVersion,
+    validation::{CommitRule, KeyPackageRule},
+};
+
 /// An error type for anything that goes wrong in this crate
 #[derive(Debug)]
 pub enum Error {
@@ -19,39 +25,63 @@ pub enum Error {
     TreeError(&'static str),
     /// For errors concerning invalid data structures
     ValidationError(&'static str),
+    /// For X.509 certificates that are malformed or unsupported, or chains of them that aren't
+    /// valid or don't end at a trust anchor, and for DER-encoded keys that are malformed or
+    /// unsupported
+    CertificateError(&'static str),
+    /// For PEM text that's malformed, or that doesn't have the kind of block that was asked for
+    PemError(&'static str),
+    /// For token credentials whose token doesn't verify, has expired, or doesn't bind the
+    /// credential's identity and key, or that arrive in a group with no `TokenVerifier`
+    TokenError(&'static str),
+    /// For credentials that the application's `CredentialValidator` rejected. This holds where the
+    /// credential was encountered, then the validator's reason.
+    CredentialRejected(CredentialContext, &'static str),
     /// For when we need randomness and there's none left
     OutOfEntropy,
     /// For when we've been removed from a group
     IAmRemoved,
+    /// For handshakes made in an epoch that's already over. This holds the group's current epoch,
+    /// then the handshake's. A commit gets this when another commit made in the same epoch was
+    /// processed first. Its committer can carry their proposals over to the new epoch with
+    /// `GroupState::rebase_pending_proposals`.
+    StaleEpoch(u32, u32),
+    /// For handshakes made in an epoch that this member hasn't reached yet, i.e., this member
+    /// missed some handshakes. This holds the group's current epoch, then the handshake's.
+    FutureEpoch(u32, u32),
+    /// For messages that this member already decrypted once, i.e., that were delivered again.
+    /// This holds the message's epoch, then its sender, then its generation.
+    ReplayedMessage(u32, u32, u32),
+    /// For application messages from a later epoch that were kept to be decrypted once this
+    /// member gets there, rather than dropped. This holds the group's current epoch, then the
+    /// message's. See `GroupState::take_buffered_plaintexts`.
+    MessageBuffered(u32, u32),
+    /// For messages or payloads that are longer than the group's `MessageSizeLimits` allow. This
+    /// holds the length, then the limit.
+    MessageTooLarge(usize, usize),
+    /// For messages, `Welcome`s, `GroupInfo`s, and `UserInitKey`s under a protocol version other
+    /// than the one they have to be under, e.g., the group's. This holds the version that was
+    /// expected, then the one that was found.
+    ProtocolVersionMismatch(ProtocolVersion, ProtocolVersion),
+    /// For commits that break one of the rules in `validation`. This says which rule was broken,
+    /// along with the error that broke it.
+    InvalidCommit(CommitRule, Box<Error>),
+    /// For `UserInitKey`s that can't be added to a group. This says which check failed, along
+    /// with the error that failed it.
+    InvalidKeyPackage(KeyPackageRule, Box<Error>),
 }
 
-// The only IO done in molasses is via serde, so this is a natural conversion
+// The only IO done in molasses is via tls_ser and tls_de, so this is a natural conversion
 impl<'a> std::convert::From<std::io::Error> for Error {
     fn from(other: std::io::Error) -> Error {
         crate::error::Error::SerdeError(other)
     }
 }
 
-// Serde requires that any Serializer's error type implement std::error::Error
 impl std::error::Error for Error {}
 
-// Serde also requires that any Serializer's error type implement std::fmt::Display
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         f.write_str(&self.to_string())
     }
 }
-
-// serde requires that any Serializer's error type implement serde::ser::Error
-impl serde::ser::Error for Error {
-    fn custom<T: std::fmt::Display>(msg: T) -> Self {
-        Error::SerdeError(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", msg)))
-    }
-}
-
-// serde requires that any Deserializer's error type implement serde::de::Error
-impl serde::de::Error for Error {
-    fn custom<T: std::fmt::Display>(msg: T) -> Self {
-        Error::SerdeError(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", msg)))
-    }
-}
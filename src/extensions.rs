@@ -0,0 +1,272 @@
+//! Defines extensions, which are typed blobs of data that extend what a group or its members say
+//! about themselves. Extensions this crate doesn't know about are carried around as opaque bytes,
+//! and re-serialized exactly as they were received, so the signatures and hashes that other
+//! implementations compute over KeyPackages, GroupInfos, and GroupContexts with them in still
+//! verify after they pass through here. Only the extensions this crate uses are parsed, and only
+//! when they're looked up, e.g., by `ExternalSenders::from_extensions`.
+
+use crate::{
+    credential::{Credential, CredentialType},
+    crypto::provider::{CryptoProvider, DEFAULT_PROVIDER},
+    error::Error,
+    handshake::{Capabilities, ProposalType},
+    tls_de::{DecodeLimits, TlsDeserialize, TlsDeserializer},
+    tls_ser,
+    upcast::{CryptoCtx, CryptoUpcast},
+};
+
+// uint16 ExtensionType;
+/// Identifies what kind of extension an `Extension` is, and thus how to interpret its data
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct ExtensionType(pub u16);
+
+// struct {
+//     ExtensionType extension_type;
+//     opaque extension_data<0..2^32-1>;
+// } Extension;
+/// A single extension. What the data means is up to whoever defined the extension type.
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct Extension {
+    pub(crate) extension_type: ExtensionType,
+    #[tls(bound = "u32")]
+    pub(crate) extension_data: Vec<u8>,
+}
+
+impl Extension {
+    /// Makes an extension of the given type with the given data
+    pub fn new(extension_type: ExtensionType, extension_data: Vec<u8>) -> Extension {
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+
+    /// Returns the type of this extension
+    pub fn extension_type(&self) -> ExtensionType {
+        self.extension_type
+    }
+
+    /// Returns the data of this extension
+    pub fn extension_data(&self) -> &[u8] {
+        &self.extension_data
+    }
+}
+
+// Extension extensions<V>;
+/// The extensions a member attached to their leaf, e.g., a device name or some policy attribute.
+/// This crate doesn't interpret them, it only carries them from the member's Add to everyone in
+/// the group. They're set when the member is added, and Updates don't touch them.
+#[derive(Clone, Debug, Default, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(bound = "varint", limit = "extensions")]
+pub(crate) struct LeafExtensions(pub(crate) Vec<Extension>);
+
+/// The type of the external_senders GroupContext extension, whose data is an `ExternalSenders`
+pub const EXTERNAL_SENDERS_TYPE: ExtensionType = ExtensionType(0x0005);
+
+// ExternalSender external_senders<V>;
+/// The credentials of the senders outside of the group, e.g., a delivery service, whose proposals
+/// the group's members accept. An external sender is identified by its index in this list. This is
+/// put in the group with a GroupContextExtensions proposal.
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(bound = "varint")]
+pub struct ExternalSenders(pub(crate) Vec<Credential>);
+
+impl ExternalSenders {
+    /// Makes a list of external senders with the given credentials, in order
+    pub fn new(senders: Vec<Credential>) -> ExternalSenders {
+        ExternalSenders(senders)
+    }
+
+    /// Returns the credential of the external sender at `sender_index`, if there is one
+    pub fn get(&self, sender_index: u32) -> Option<&Credential> {
+        self.0.get(sender_index as usize)
+    }
+
+    /// Packages this list up as an external_senders extension
+    pub fn to_extension(&self) -> Result<Extension, Error> {
+        Ok(Extension::new(EXTERNAL_SENDERS_TYPE, tls_ser::serialize_to_bytes(self)?))
+    }
+
+    /// Finds and parses the external_senders extension in the given list of extensions, under the
+    /// given decode limits. The signature schemes of the senders' credentials are looked up in
+    /// `provider`.
+    ///
+    /// Returns: `Ok(Some(senders))` if there's a well-formed external_senders extension,
+    /// `Ok(None)` if there's none, and an `Error` if it's malformed.
+    pub(crate) fn from_extensions(
+        extensions: &[Extension],
+        limits: DecodeLimits,
+        provider: &'static dyn CryptoProvider,
+    ) -> Result<Option<ExternalSenders>, Error> {
+        let mut senders: Option<ExternalSenders> =
+            parse_extension(extensions, EXTERNAL_SENDERS_TYPE, limits, provider)?;
+        if let Some(ref mut senders) = senders {
+            let ctx = CryptoCtx::new().set_decode_limits(limits).set_provider(provider);
+            senders.0.upcast_crypto_values(&ctx)?;
+        }
+
+        Ok(senders)
+    }
+}
+
+/// The type of the ratchet_tree extension, whose data is a group's public tree in the spec's
+/// `optional<Node> ratchet_tree<1..2^32-1>` encoding. See `GroupState::ratchet_tree_extension`.
+pub const RATCHET_TREE_TYPE: ExtensionType = ExtensionType(0x0002);
+
+/// The type of the required_capabilities GroupContext extension, whose data is a
+/// `RequiredCapabilities`
+pub const REQUIRED_CAPABILITIES_TYPE: ExtensionType = ExtensionType(0x0003);
+
+// struct {
+//     ExtensionType extension_types<V>;
+//     ProposalType proposal_types<V>;
+//     CredentialType credential_types<V>;
+// } RequiredCapabilities;
+/// The extension, proposal, and credential types that every member of the group has to support.
+/// Nobody can be added without them, and every member checks that they support them when they're
+/// set.
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct RequiredCapabilities {
+    #[tls(bound = "varint", limit = "extensions")]
+    pub(crate) extension_types: Vec<ExtensionType>,
+    #[tls(bound = "varint")]
+    pub(crate) proposal_types: Vec<ProposalType>,
+    #[tls(bound = "varint")]
+    pub(crate) credential_types: Vec<CredentialType>,
+}
+
+impl RequiredCapabilities {
+    /// Makes a requirement of the given extension, proposal, and credential types
+    pub fn new(
+        extension_types: Vec<ExtensionType>,
+        proposal_types: Vec<ProposalType>,
+        credential_types: Vec<CredentialType>,
+    ) -> RequiredCapabilities {
+        RequiredCapabilities {
+            extension_types,
+            proposal_types,
+            credential_types,
+        }
+    }
+
+    /// Packages this requirement up as a required_capabilities extension
+    pub fn to_extension(&self) -> Result<Extension, Error> {
+        Ok(Extension::new(REQUIRED_CAPABILITIES_TYPE, tls_ser::serialize_to_bytes(self)?))
+    }
+
+    /// Finds and parses the required_capabilities extension in the given list of extensions,
+    /// under the given decode limits
+    ///
+    /// Returns: `Ok(Some(required))` if there's a well-formed required_capabilities extension,
+    /// `Ok(None)` if there's none, and an `Error` if it's malformed.
+    pub(crate) fn from_extensions(
+        extensions: &[Extension],
+        limits: DecodeLimits,
+    ) -> Result<Option<RequiredCapabilities>, Error> {
+        // There are no algorithms in here to look up
+        parse_extension(extensions, REQUIRED_CAPABILITIES_TYPE, limits, DEFAULT_PROVIDER)
+    }
+
+    /// Returns whether a member with the given capabilities supports everything this requires
+    pub(crate) fn is_satisfied_by(&self, capabilities: &Capabilities) -> bool {
+        self.extension_types.iter().all(|t| capabilities.extensions.contains(t))
+            && self.proposal_types.iter().all(|t| capabilities.proposals.contains(t))
+            && self.credential_types.iter().all(|t| capabilities.credentials.contains(t))
+    }
+}
+
+/// The type of the lifetime leaf extension, whose data is a `Lifetime`. Lifetimes aren't an
+/// extension in the spec, so this is from the private use range.
+pub const LIFETIME_TYPE: ExtensionType = ExtensionType(0xF001);
+
+// struct {
+//     uint64 not_before;
+//     uint64 not_after;
+// } Lifetime;
+/// The span of time in which a new member's leaf key may be used, in seconds since the Unix epoch,
+/// both ends included. A member signs this into their `UserInitKey` with
+/// `UserInitKey::with_extensions`, or it's attached to their leaf in the Add proposal that adds
+/// them. Adds whose lifetimes don't cover the current time are rejected, and the lifetime stops
+/// applying once the member updates their leaf.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct Lifetime {
+    pub(crate) not_before: u64,
+    pub(crate) not_after: u64,
+}
+
+impl Lifetime {
+    /// Makes a lifetime that starts at `not_before` and ends at `not_after`
+    pub fn new(not_before: u64, not_after: u64) -> Lifetime {
+        Lifetime {
+            not_before,
+            not_after,
+        }
+    }
+
+    /// Returns the first second in which the key may be used
+    pub fn not_before(&self) -> u64 {
+        self.not_before
+    }
+
+    /// Returns the last second in which the key may be used
+    pub fn not_after(&self) -> u64 {
+        self.not_after
+    }
+
+    /// Returns whether the key may be used at `time`
+    pub fn covers(&self, time: u64) -> bool {
+        self.not_before <= time && time <= self.not_after
+    }
+
+    /// Packages this lifetime up as a lifetime extension
+    pub fn to_extension(&self) -> Result<Extension, Error> {
+        Ok(Extension::new(LIFETIME_TYPE, tls_ser::serialize_to_bytes(self)?))
+    }
+
+    /// Finds and parses the lifetime extension in the given list of extensions
+    ///
+    /// Returns: `Ok(Some(lifetime))` if there's a well-formed lifetime extension, `Ok(None)` if
+    /// there's none, and an `Error` if it's malformed.
+    pub(crate) fn from_extensions(extensions: &[Extension]) -> Result<Option<Lifetime>, Error> {
+        // A lifetime is two integers, so there's nothing in it for tighter limits to catch
+        parse_extension(extensions, LIFETIME_TYPE, DecodeLimits::default(), DEFAULT_PROVIDER)
+    }
+}
+
+/// The type of the last-resort `UserInitKey` extension, whose data is empty. A `UserInitKey` with
+/// this extension may be used for more than one invitation, so that its owner can still be added
+/// to groups once the rest of their published `UserInitKey`s are used up.
+pub const LAST_RESORT_TYPE: ExtensionType = ExtensionType(0x000A);
+
+/// Finds the extension of the given type in the given list of extensions, and deserializes its
+/// data under `limits`, looking up any algorithms in it in `provider`. The data has to be exactly
+/// one `T`.
+fn parse_extension<T: TlsDeserialize>(
+    extensions: &[Extension],
+    extension_type: ExtensionType,
+    limits: DecodeLimits,
+    provider: &'static dyn CryptoProvider,
+) -> Result<Option<T>, Error> {
+    let ext = match extensions.iter().find(|ext| ext.extension_type == extension_type) {
+        Some(ext) => ext,
+        None => return Ok(None),
+    };
+
+    let mut cursor = ext.extension_data.as_slice();
+    let mut deserializer =
+        TlsDeserializer::from_reader(&mut cursor).with_limits(limits).with_provider(provider);
+    let value = T::tls_deserialize(&mut deserializer)?;
+    if !cursor.is_empty() {
+        return Err(Error::ValidationError("Extension has trailing data"));
+    }
+
+    Ok(Some(value))
+}
+
+/// Returns whether any two of the given extensions have the same type. The spec forbids that in
+/// every list of extensions.
+pub(crate) fn has_duplicate_types(extensions: &[Extension]) -> bool {
+    extensions.iter().enumerate().any(|(i, ext)| {
+        extensions[..i].iter().any(|other| other.extension_type == ext.extension_type)
+    })
+}
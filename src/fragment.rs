@@ -0,0 +1,283 @@
+//! Defines `Fragment`, which is how a payload too big for one application message is split over
+//! several, and the `Reassembler`, which is how a member puts such payloads back together. Every
+//! fragment is a message of its own, signed and encrypted like any other, and says which payload
+//! it's part of, where in it it goes, and what the hash of the whole payload is, so a payload
+//! only comes out of reassembly if it's exactly what its sender split up.
+
+use crate::{
+    crypto::ciphersuite::CipherSuite,
+    error::Error,
+    tls_de::{DecodeLimits, TlsDeserialize, TlsDeserializer},
+};
+
+use std::collections::BTreeMap;
+
+use subtle::ConstantTimeEq;
+
+/// How many partially reassembled payloads a `Reassembler` holds on to. Once there are this many,
+/// the oldest is dropped to make room for a new one.
+const MAX_PENDING_PAYLOADS: usize = 8;
+
+// struct {
+//     uint32 first_generation;
+//     uint32 index;
+//     uint32 count;
+//     opaque payload_hash<0..255>;
+//     opaque data<0..2^32-1>;
+// } Fragment;
+/// One piece of a fragmented payload. This is the plaintext of an application message. The
+/// payload is identified by its sender and the generation of the application message that its
+/// first fragment is encrypted under, which is unique within an epoch.
+#[derive(TlsDeserialize, TlsSerialize)]
+pub(crate) struct Fragment {
+    pub(crate) first_generation: u32,
+    pub(crate) index: u32,
+    pub(crate) count: u32,
+    #[tls(bound = "u8")]
+    pub(crate) payload_hash: Vec<u8>,
+    #[tls(bound = "u32")]
+    pub(crate) data: Vec<u8>,
+}
+
+impl Fragment {
+    /// Splits `payload` into fragments of at most `max_fragment_len` bytes each. The first of them
+    /// will be encrypted under `first_generation` of the sender's application ratchet. An empty
+    /// payload is one empty fragment.
+    ///
+    /// Returns: `Ok(fragments)` on success. If `max_fragment_len` is 0, or there would be more
+    /// than `u32::MAX` fragments, returns an `Error::ValidationError`.
+    pub(crate) fn split(
+        cs: &'static CipherSuite,
+        payload: &[u8],
+        max_fragment_len: usize,
+        first_generation: u32,
+    ) -> Result<Vec<Fragment>, Error> {
+        if max_fragment_len == 0 {
+            return Err(Error::ValidationError("Fragments must be allowed at least one byte"));
+        }
+
+        let payload_hash = cs.hash_impl.hash_bytes(payload).as_bytes().to_vec();
+        let chunks: Vec<&[u8]> = if payload.is_empty() {
+            vec![payload]
+        } else {
+            payload.chunks(max_fragment_len).collect()
+        };
+        if chunks.len() > std::u32::MAX as usize {
+            return Err(Error::ValidationError("Payload has too many fragments"));
+        }
+
+        // We just checked that the number of chunks fits in a u32
+        let count = chunks.len() as u32;
+        let fragments = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, data)| Fragment {
+                first_generation,
+                index: index as u32,
+                count,
+                payload_hash: payload_hash.clone(),
+                data: data.to_vec(),
+            })
+            .collect();
+        Ok(fragments)
+    }
+
+    /// Deserializes a fragment from the plaintext of the application message it came in, under
+    /// the given decode limits
+    ///
+    /// Returns: `Ok(fragment)` on success. If there's anything after the fragment, returns an
+    /// `Error::ValidationError`. Otherwise, if the fragment is malformed, returns some sort of
+    /// `Error`.
+    pub(crate) fn from_plaintext(
+        plaintext: &[u8],
+        limits: DecodeLimits,
+    ) -> Result<Fragment, Error> {
+        let mut cursor = plaintext;
+        let fragment = {
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor).with_limits(limits);
+            Fragment::tls_deserialize(&mut deserializer)?
+        };
+        if !cursor.is_empty() {
+            return Err(Error::ValidationError("Fragment has trailing bytes"));
+        }
+        Ok(fragment)
+    }
+}
+
+/// The fragments of a payload that have arrived so far
+#[derive(Clone)]
+struct PendingPayload {
+    /// The epoch, sender, and first generation that identify the payload
+    id: (u32, u32, u32),
+    count: u32,
+    payload_hash: Vec<u8>,
+    /// The fragments that have arrived, by index
+    fragments: BTreeMap<u32, Vec<u8>>,
+    /// The total length of `fragments`
+    len: usize,
+}
+
+/// The payloads whose fragments a member is in the middle of receiving, oldest first. This is
+/// local bookkeeping, so it's never sent.
+#[derive(Clone, Default)]
+pub(crate) struct Reassembler {
+    pending: Vec<PendingPayload>,
+}
+
+impl Reassembler {
+    /// Adds `fragment`, which `sender` sent in `epoch`, to the payload it's part of. Payloads can't
+    /// be longer than `max_len` bytes.
+    ///
+    /// Returns: `Ok(Some(payload))` if this was the last fragment of its payload to arrive, and
+    /// `Ok(None)` if there are more to come. If the fragment disagrees with the others of its
+    /// payload, or the payload's hash doesn't match, returns an `Error::ValidationError`, and the
+    /// payload is dropped. If the payload would be longer than `max_len`, returns an
+    /// `Error::MessageTooLarge`, and the payload is dropped.
+    pub(crate) fn add(
+        &mut self,
+        cs: &'static CipherSuite,
+        epoch: u32,
+        sender: u32,
+        fragment: Fragment,
+        max_len: usize,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        if fragment.index >= fragment.count {
+            return Err(Error::ValidationError("Fragment's index is out of range"));
+        }
+
+        let id = (epoch, sender, fragment.first_generation);
+        let pos = match self.pending.iter().position(|pending| pending.id == id) {
+            Some(pos) => pos,
+            None => {
+                if self.pending.len() == MAX_PENDING_PAYLOADS {
+                    self.pending.remove(0);
+                }
+                self.pending.push(PendingPayload {
+                    id,
+                    count: fragment.count,
+                    payload_hash: fragment.payload_hash.clone(),
+                    fragments: BTreeMap::new(),
+                    len: 0,
+                });
+                self.pending.len() - 1
+            }
+        };
+
+        // Anything wrong with the fragment spoils the whole payload
+        let result = Reassembler::add_to_pending(&mut self.pending[pos], fragment, max_len);
+        let done = match result {
+            Ok(done) => done,
+            Err(e) => {
+                self.pending.remove(pos);
+                return Err(e);
+            }
+        };
+        if !done {
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(pos);
+        let payload: Vec<u8> = pending.fragments.into_iter().flat_map(|(_, data)| data).collect();
+        let payload_hash = cs.hash_impl.hash_bytes(&payload);
+        if payload_hash.as_bytes().ct_eq(&pending.payload_hash).into() {
+            Ok(Some(payload))
+        } else {
+            Err(Error::ValidationError("Reassembled payload doesn't match its hash"))
+        }
+    }
+
+    /// Adds `fragment` to `pending`, and returns whether that was the last one missing
+    fn add_to_pending(
+        pending: &mut PendingPayload,
+        fragment: Fragment,
+        max_len: usize,
+    ) -> Result<bool, Error> {
+        if fragment.count != pending.count || fragment.payload_hash != pending.payload_hash {
+            return Err(Error::ValidationError(
+                "Fragment disagrees with the others of its payload",
+            ));
+        }
+        if pending.fragments.contains_key(&fragment.index) {
+            return Err(Error::ValidationError("Payload has two fragments with the same index"));
+        }
+
+        let len = pending.len.saturating_add(fragment.data.len());
+        if len > max_len {
+            return Err(Error::MessageTooLarge(len, max_len));
+        }
+        pending.len = len;
+        pending.fragments.insert(fragment.index, fragment.data);
+
+        Ok(pending.fragments.len() == pending.count as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        crypto::ciphersuite::X25519_SHA256_AES128GCM,
+        error::Error,
+        fragment::{Fragment, Reassembler},
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::{seq::SliceRandom, SeedableRng};
+
+    // Check that a payload comes back out of reassembly whatever order its fragments arrive in,
+    // and that fragments of different payloads don't mix
+    #[quickcheck]
+    fn reassembly(payload: Vec<u8>, max_fragment_len: u8, rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs = &X25519_SHA256_AES128GCM;
+        let max_fragment_len = usize::from(max_fragment_len.max(1));
+
+        let mut fragments = Fragment::split(cs, &payload, max_fragment_len, 7).unwrap();
+        assert!(fragments.iter().all(|fragment| fragment.data.len() <= max_fragment_len));
+        fragments.shuffle(&mut rng);
+
+        // Another payload from another sender is going on at the same time
+        let mut other = Fragment::split(cs, b"other payload", 4, 7).unwrap();
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.add(cs, 1, 2, other.remove(0), 1024).unwrap().is_none());
+
+        let num_fragments = fragments.len();
+        for (i, fragment) in fragments.into_iter().enumerate() {
+            let result = reassembler.add(cs, 1, 0, fragment, 1024).unwrap();
+            if i + 1 < num_fragments {
+                assert!(result.is_none());
+            } else {
+                assert_eq!(result.unwrap(), payload);
+            }
+        }
+    }
+
+    // Check that fragments that don't belong together, or that add up to too much, are rejected
+    #[test]
+    fn bad_fragments() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut reassembler = Reassembler::default();
+
+        // A fragment that claims to be part of another payload spoils it
+        let mut fragments = Fragment::split(cs, b"hello world", 4, 0).unwrap();
+        let mut forged = Fragment::split(cs, b"goodbye world", 4, 0).unwrap();
+        assert!(reassembler.add(cs, 0, 0, fragments.remove(0), 1024).unwrap().is_none());
+        assert!(reassembler.add(cs, 0, 0, forged.remove(1), 1024).is_err());
+
+        // Swapping data between fragments is caught by the payload hash
+        let mut fragments = Fragment::split(cs, b"hello world", 4, 1).unwrap();
+        let data = fragments[0].data.clone();
+        fragments[0].data = fragments[1].data.clone();
+        fragments[1].data = data;
+        let results: Vec<_> =
+            fragments.into_iter().map(|f| reassembler.add(cs, 0, 0, f, 1024)).collect();
+        assert!(results[2].is_err());
+
+        // Payloads can't grow past the limit
+        let mut fragments = Fragment::split(cs, b"hello world", 4, 2).unwrap();
+        assert!(reassembler.add(cs, 0, 0, fragments.remove(0), 6).unwrap().is_none());
+        match reassembler.add(cs, 0, 0, fragments.remove(0), 6) {
+            Err(Error::MessageTooLarge(8, 6)) => (),
+            _ => panic!("payload was allowed to grow past the limit"),
+        }
+    }
+}
@@ -0,0 +1,1193 @@
+//! Defines the framings that handshakes are sent in. A `PublicMessage` carries a handshake in the
+//! clear, so that a delivery service can inspect the proposals and commits in it, and proves with
+//! a membership tag that its sender was in the group in the epoch it was made in. A
+//! `PrivateMessage` carries a handshake or an application message encrypted under one of its
+//! sender's ratchets, along with who its sender is, encrypted too, so that outsiders learn nothing
+//! about how the group changes or who in it is talking. A `WireFormatPolicy` says which framing a
+//! member's handshakes go out in, and which it accepts.
+
+use crate::{
+    application,
+    credential::Credential,
+    crypto::{
+        aead::{AeadKey, AeadNonce},
+        ciphersuite::CipherSuite,
+        hmac::{self, HmacKey, Mac},
+        kdf,
+        provider::CryptoProvider,
+        secret::Secret,
+    },
+    error::Error,
+    group_id::GroupId,
+    group_state::{GroupContext, GroupState},
+    handshake::{GroupOperation, Handshake, ProposalOrRef, ProposalType, ProtocolVersion},
+    secret_tree::RatchetType,
+    tls_de::{DecodeLimits, SliceReader, TlsDeserialize, TlsDeserializer},
+    tls_ser,
+    upcast::{CryptoCtx, CryptoUpcast},
+};
+
+/// The framings a handshake can be sent in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WireFormat {
+    /// In the clear, as a `PublicMessage`
+    PublicMessage,
+    /// Encrypted under the sender's handshake ratchet
+    PrivateMessage,
+}
+
+/// Which framing this member's handshakes go out in, and which framings it accepts other members'
+/// handshakes in. This is local policy, but every member of a group should have the same one,
+/// since a delivery service that has to inspect handshakes can't do anything with ones it can't
+/// read.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WireFormatPolicy {
+    pub(crate) outgoing: WireFormat,
+    pub(crate) accept_public: bool,
+    pub(crate) accept_private: bool,
+}
+
+impl WireFormatPolicy {
+    /// Handshakes go out as `PublicMessage`s, and only `PublicMessage`s are accepted. This is the
+    /// default.
+    pub const PUBLIC: WireFormatPolicy = WireFormatPolicy {
+        outgoing: WireFormat::PublicMessage,
+        accept_public: true,
+        accept_private: false,
+    };
+
+    /// Handshakes go out encrypted, and only encrypted ones are accepted
+    pub const PRIVATE: WireFormatPolicy = WireFormatPolicy {
+        outgoing: WireFormat::PrivateMessage,
+        accept_public: false,
+        accept_private: true,
+    };
+
+    /// Makes a policy under which handshakes go out as `outgoing`, and under which handshakes are
+    /// accepted as `PublicMessage`s iff `accept_public`, and encrypted iff `accept_private`
+    pub fn new(
+        outgoing: WireFormat,
+        accept_public: bool,
+        accept_private: bool,
+    ) -> WireFormatPolicy {
+        WireFormatPolicy {
+            outgoing,
+            accept_public,
+            accept_private,
+        }
+    }
+
+    /// Returns the framing that handshakes go out in
+    pub fn outgoing(&self) -> WireFormat {
+        self.outgoing
+    }
+
+    /// Returns whether handshakes framed as `format` are accepted
+    pub fn accepts(&self, format: WireFormat) -> bool {
+        match format {
+            WireFormat::PublicMessage => self.accept_public,
+            WireFormat::PrivateMessage => self.accept_private,
+        }
+    }
+}
+
+impl Default for WireFormatPolicy {
+    fn default() -> WireFormatPolicy {
+        WireFormatPolicy::PUBLIC
+    }
+}
+
+/// How big the application messages that this member sends and accepts may be. Messages that are
+/// too big are rejected with an `Error::MessageTooLarge`, and incoming ones are rejected before
+/// any decryption is attempted if their ciphertext is too big. Payloads bigger than a message may
+/// be can still be sent in fragments, with `GroupState::encrypt_fragmented`. This is local policy.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MessageSizeLimits {
+    pub(crate) max_plaintext_len: usize,
+    pub(crate) max_ciphertext_len: usize,
+    pub(crate) max_reassembled_len: usize,
+}
+
+impl MessageSizeLimits {
+    /// Makes limits under which plaintexts may be up to `max_plaintext_len` bytes long, the
+    /// ciphertexts of `PrivateMessage`s up to `max_ciphertext_len` bytes long, and fragmented
+    /// payloads up to `max_reassembled_len` bytes long once they're put back together
+    pub fn new(
+        max_plaintext_len: usize,
+        max_ciphertext_len: usize,
+        max_reassembled_len: usize,
+    ) -> MessageSizeLimits {
+        MessageSizeLimits {
+            max_plaintext_len,
+            max_ciphertext_len,
+            max_reassembled_len,
+        }
+    }
+
+    /// Returns how long the plaintext of an application message may be
+    pub fn max_plaintext_len(&self) -> usize {
+        self.max_plaintext_len
+    }
+
+    /// Returns how long the ciphertext of a `PrivateMessage` may be, padding and tag included
+    pub fn max_ciphertext_len(&self) -> usize {
+        self.max_ciphertext_len
+    }
+
+    /// Returns how long a fragmented payload may be once it's put back together
+    pub fn max_reassembled_len(&self) -> usize {
+        self.max_reassembled_len
+    }
+
+    /// Checks that a plaintext of length `len` is within the limits
+    ///
+    /// Returns: `Ok(())` if it is. Otherwise, returns an `Error::MessageTooLarge`.
+    pub(crate) fn check_plaintext_len(&self, len: usize) -> Result<(), Error> {
+        check_len(len, self.max_plaintext_len)
+    }
+
+    /// Checks that a ciphertext of length `len` is within the limits
+    ///
+    /// Returns: `Ok(())` if it is. Otherwise, returns an `Error::MessageTooLarge`.
+    pub(crate) fn check_ciphertext_len(&self, len: usize) -> Result<(), Error> {
+        check_len(len, self.max_ciphertext_len)
+    }
+}
+
+impl Default for MessageSizeLimits {
+    /// Plaintexts of up to 1 MiB, ciphertexts of up to 4 MiB, which leaves room for any padding
+    /// scheme that at most doubles a message, and reassembled payloads of up to 16 MiB
+    fn default() -> MessageSizeLimits {
+        MessageSizeLimits::new(1 << 20, 4 << 20, 16 << 20)
+    }
+}
+
+/// Returns an `Error::MessageTooLarge` if `len` is more than `limit`
+fn check_len(len: usize, limit: usize) -> Result<(), Error> {
+    if len > limit {
+        Err(Error::MessageTooLarge(len, limit))
+    } else {
+        Ok(())
+    }
+}
+
+// struct {
+//     ProtocolVersion version;
+//     opaque group_id<0..255>;
+//     Handshake handshake;
+//     opaque membership_tag<0..255>;
+// } PublicMessage;
+/// A handshake sent in the clear. Its signature says who made it, and its membership tag, which is
+/// a MAC under the epoch's membership key over the handshake and the group context it was made in,
+/// says that they were in the group in that epoch, with the same view of it as the recipient.
+/// Anybody can read what it does, but only members can check the tag.
+#[derive(TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct PublicMessage {
+    pub(crate) version: ProtocolVersion,
+    pub(crate) group_id: GroupId,
+    pub(crate) handshake: Handshake,
+    pub(crate) membership_tag: Mac,
+}
+
+// struct {
+//     GroupContext context;
+//     opaque group_id<0..255>;
+//     Handshake handshake;
+// } PublicMessageTBM;
+/// What a `PublicMessage`'s membership tag is over, with the group context of the epoch the
+/// handshake was made in
+#[derive(TlsSerialize)]
+struct PublicMessageTbm<'a> {
+    context: GroupContext<'a>,
+    group_id: &'a GroupId,
+    handshake: &'a Handshake,
+}
+
+impl PublicMessage {
+    /// Frames `handshake`, which was made in the current epoch of `group_state`, as a
+    /// `PublicMessage`
+    ///
+    /// Returns: `Ok(message)` on success. If `group_state` has no membership key yet, returns an
+    /// `Error::ValidationError`. If the handshake can't be serialized, returns an
+    /// `Error::SerdeError`.
+    pub(crate) fn new(
+        group_state: &GroupState,
+        handshake: Handshake,
+    ) -> Result<PublicMessage, Error> {
+        let group_id = group_state.group_id.clone();
+        // membership_tag = MAC(membership_key, PublicMessageTBM)
+        let tbm = PublicMessageTbm {
+            context: group_state.group_context(),
+            group_id: &group_id,
+            handshake: &handshake,
+        };
+        let membership_tag = hmac::sign(
+            group_state.cs.hash_impl,
+            group_state.membership_key()?,
+            &tls_ser::serialize_to_bytes(&tbm)?,
+        );
+
+        Ok(PublicMessage {
+            version: group_state.protocol_version,
+            group_id,
+            handshake,
+            membership_tag,
+        })
+    }
+
+    /// Checks this message's membership tag in constant time, under the membership key and group
+    /// context of the current epoch of `group_state`
+    ///
+    /// Returns: `Ok(())` if the tag is valid. If `group_state` has no membership key yet, returns
+    /// an `Error::ValidationError`. Otherwise, returns an `Error::SignatureError`.
+    pub(crate) fn verify_membership_tag(&self, group_state: &GroupState) -> Result<(), Error> {
+        let tbm = PublicMessageTbm {
+            context: group_state.group_context(),
+            group_id: &self.group_id,
+            handshake: &self.handshake,
+        };
+        hmac::verify(
+            group_state.cs.hash_impl,
+            group_state.membership_key()?,
+            &tls_ser::serialize_to_bytes(&tbm)?,
+            &self.membership_tag,
+        )
+    }
+
+    /// Returns the protocol version this message was made under
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the ID of the group this message is for
+    pub fn group_id(&self) -> &GroupId {
+        &self.group_id
+    }
+
+    /// Returns the epoch the handshake was made in
+    pub fn epoch(&self) -> u32 {
+        self.handshake.prior_epoch
+    }
+
+    /// Returns the roster index of the member who made the handshake. Nothing vouches for this
+    /// until a member checks the signature.
+    pub fn sender(&self) -> u32 {
+        self.handshake.signer_index
+    }
+
+    /// Returns the types of the proposals that the handshake applies. For a commit, these are the
+    /// types of the proposals that it carries by value, in order. Proposals by reference can't be
+    /// inspected, since they aren't in the message. Every other handshake applies a single
+    /// proposal, whose type is returned.
+    pub fn proposal_types(&self) -> Vec<ProposalType> {
+        match &self.handshake.operation {
+            GroupOperation::Init(_) => Vec::new(),
+            GroupOperation::Add(_) => vec![ProposalType::ADD],
+            GroupOperation::Update(_) => vec![ProposalType::UPDATE],
+            GroupOperation::Remove(_) => vec![ProposalType::REMOVE],
+            GroupOperation::Commit(commit) => commit
+                .proposals
+                .iter()
+                .filter_map(|proposal| match proposal {
+                    ProposalOrRef::Proposal(proposal) => Some(proposal.proposal_type()),
+                    ProposalOrRef::Reference(_) => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns whether the handshake is a commit
+    pub fn is_commit(&self) -> bool {
+        matches!(self.handshake.operation, GroupOperation::Commit(_))
+    }
+}
+
+// enum {
+//     application(0),
+//     handshake(1),
+//     (255)
+// } ContentType;
+/// What a `PrivateMessage` carries. This says which of the sender's ratchets it's encrypted with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(tag = "u8")]
+pub enum ContentType {
+    /// An application message, encrypted with the application ratchet
+    Application,
+    /// A handshake, encrypted with the handshake ratchet
+    Handshake,
+}
+
+impl ContentType {
+    /// Returns the type of ratchet that content of this type is encrypted with
+    pub fn ratchet_type(self) -> RatchetType {
+        match self {
+            ContentType::Application => RatchetType::Application,
+            ContentType::Handshake => RatchetType::Handshake,
+        }
+    }
+}
+
+// struct {
+//     uint32 sender;
+//     uint32 generation;
+// } SenderData;
+/// Who sent a `PrivateMessage`, and which generation of their ratchet it's encrypted under. This
+/// is encrypted separately from the content, so that the recipient knows which key to decrypt the
+/// content with, and nobody else knows who sent it.
+#[derive(TlsDeserialize, TlsSerialize)]
+struct SenderData {
+    sender: u32,
+    generation: u32,
+}
+
+// struct {
+//     opaque group_id<0..255>;
+//     uint32 epoch;
+//     ContentType content_type;
+// } SenderDataAAD;
+/// The associated data that the `SenderData` of a `PrivateMessage` is encrypted with
+#[derive(TlsSerialize)]
+struct SenderDataAad<'a> {
+    group_id: &'a GroupId,
+    epoch: u32,
+    content_type: ContentType,
+}
+
+// struct {
+//     opaque group_id<0..255>;
+//     uint32 epoch;
+//     ContentType content_type;
+//     opaque authenticated_data<0..2^32-1>;
+// } PrivateContentAAD;
+/// The associated data that the content of a `PrivateMessage` is encrypted with
+#[derive(TlsSerialize)]
+struct PrivateContentAad<'a> {
+    group_id: &'a GroupId,
+    epoch: u32,
+    content_type: ContentType,
+    #[tls(bound = "u32")]
+    authenticated_data: &'a [u8],
+}
+
+/// Returns the associated data that the content of a `PrivateMessage` with the given header is
+/// encrypted with
+pub(crate) fn content_aad(
+    group_id: &GroupId,
+    epoch: u32,
+    content_type: ContentType,
+    authenticated_data: &[u8],
+) -> Result<Vec<u8>, Error> {
+    tls_ser::serialize_to_bytes(&PrivateContentAad {
+        group_id,
+        epoch,
+        content_type,
+        authenticated_data,
+    })
+}
+
+// struct {
+//     ProtocolVersion version;
+//     opaque group_id<0..255>;
+//     uint32 epoch;
+//     ContentType content_type;
+//     opaque authenticated_data<0..2^32-1>;
+//     opaque encrypted_sender_data<0..255>;
+//     opaque ciphertext<0..2^32-1>;
+// } PrivateMessage;
+/// Content encrypted under one of its sender's ratchets in the secret tree of the epoch it was
+/// made in. The ciphertext is the serialized content followed by zero padding. The `SenderData`
+/// is encrypted under a key derived from the epoch's sender data secret and the start of the
+/// ciphertext. The rest of the message is in the clear, but it's all associated data of one
+/// encryption or the other, so none of it can be changed. In particular, the authenticated data
+/// is whatever the sender wanted bound to the content without encrypting it.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct PrivateMessage {
+    pub(crate) version: ProtocolVersion,
+    pub(crate) group_id: GroupId,
+    pub(crate) epoch: u32,
+    pub(crate) content_type: ContentType,
+    #[tls(bound = "u32")]
+    pub(crate) authenticated_data: Vec<u8>,
+    #[tls(bound = "u8")]
+    pub(crate) encrypted_sender_data: Vec<u8>,
+    #[tls(bound = "u32")]
+    pub(crate) ciphertext: Vec<u8>,
+}
+
+impl PrivateMessage {
+    /// Makes a `PrivateMessage` of `ciphertext`, which this member of `group_state` encrypted
+    /// under `generation` of their ratchet for `content_type` in the current epoch, with
+    /// `authenticated_data` as part of its associated data. The sender and generation are
+    /// encrypted under the epoch's sender data secret.
+    ///
+    /// Returns: `Ok(message)` on success. If `group_state` is preliminary, or has no sender data
+    /// secret yet, returns an `Error::ValidationError`. Otherwise, if encryption fails, returns
+    /// some sort of `Error`.
+    pub(crate) fn new(
+        group_state: &GroupState,
+        content_type: ContentType,
+        authenticated_data: Vec<u8>,
+        generation: u32,
+        ciphertext: Vec<u8>,
+    ) -> Result<PrivateMessage, Error> {
+        let cs = group_state.cs;
+        let sender = group_state.roster_index.ok_or(Error::ValidationError(
+            "Cannot make a PrivateMessage with a preliminary GroupState",
+        ))?;
+
+        let (key, nonce) =
+            sender_data_key_nonce(cs, group_state.sender_data_secret()?, &ciphertext)?;
+        let aad = tls_ser::serialize_to_bytes(&SenderDataAad {
+            group_id: &group_state.group_id,
+            epoch: group_state.epoch,
+            content_type,
+        })?;
+        let mut encrypted_sender_data = tls_ser::serialize_to_bytes(&SenderData {
+            sender,
+            generation,
+        })?;
+        encrypted_sender_data.extend(vec![0u8; cs.aead_impl.tag_size()]);
+        cs.aead_impl.seal_with_aad(&key, nonce, &aad, &mut encrypted_sender_data)?;
+
+        Ok(PrivateMessage {
+            version: group_state.protocol_version,
+            group_id: group_state.group_id.clone(),
+            epoch: group_state.epoch,
+            content_type,
+            authenticated_data,
+            encrypted_sender_data,
+            ciphertext,
+        })
+    }
+
+    /// Decrypts the sender data of this message under `sender_data_secret`, which is that of the
+    /// epoch the message was made in
+    ///
+    /// Returns: `Ok((sender, generation))` on success. If decryption fails, returns an
+    /// `Error::EncryptionError`. If the sender data is malformed, returns some sort of `Error`.
+    pub(crate) fn open_sender_data(
+        &self,
+        cs: &'static CipherSuite,
+        sender_data_secret: &HmacKey,
+    ) -> Result<(u32, u32), Error> {
+        let (key, nonce) = sender_data_key_nonce(cs, sender_data_secret, &self.ciphertext)?;
+        let aad = tls_ser::serialize_to_bytes(&SenderDataAad {
+            group_id: &self.group_id,
+            epoch: self.epoch,
+            content_type: self.content_type,
+        })?;
+        let mut encrypted_sender_data = self.encrypted_sender_data.clone();
+        let plaintext =
+            cs.aead_impl.open_with_aad(&key, nonce, &aad, &mut encrypted_sender_data)?;
+
+        let mut cursor: &[u8] = plaintext;
+        let sender_data = {
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            SenderData::tls_deserialize(&mut deserializer)?
+        };
+        if !cursor.is_empty() {
+            return Err(Error::ValidationError("Sender data has trailing bytes"));
+        }
+        Ok((sender_data.sender, sender_data.generation))
+    }
+
+    /// Encrypts `handshake`, which this member of `group_state` made in the current epoch, under
+    /// the key and nonce of `generation` of their handshake ratchet. It's padded as the group's
+    /// `PaddingScheme` says.
+    ///
+    /// Returns: `Ok(message)` on success. If `group_state` is preliminary, or the padding scheme
+    /// fails, or there's no sender data secret yet, returns an `Error::ValidationError`.
+    /// Otherwise, if serialization or encryption fails, returns some sort of `Error`.
+    pub(crate) fn seal_handshake(
+        group_state: &GroupState,
+        generation: u32,
+        key: AeadKey,
+        nonce: AeadNonce,
+        handshake: &Handshake,
+    ) -> Result<PrivateMessage, Error> {
+        let cs = group_state.cs;
+        let mut ciphertext = tls_ser::serialize_to_bytes(handshake)?;
+        let padded_len = group_state.padding_scheme.padded_len(ciphertext.len())?;
+        ciphertext.resize(padded_len, 0u8);
+        ciphertext.extend(vec![0u8; cs.aead_impl.tag_size()]);
+        let aad =
+            content_aad(&group_state.group_id, group_state.epoch, ContentType::Handshake, &[])?;
+        cs.aead_impl.seal_with_aad(&key, nonce, &aad, &mut ciphertext)?;
+
+        PrivateMessage::new(group_state, ContentType::Handshake, Vec::new(), generation, ciphertext)
+    }
+
+    /// Decrypts the handshake in this message under the given key and nonce, strips its padding,
+    /// and checks that it says the same epoch as this message, and that `sender`, whom the sender
+    /// data says sent it, signed it. The handshake is deserialized under `limits`, and its
+    /// algorithms are looked up in `provider`.
+    ///
+    /// Returns: `Ok(handshake)` on success. If this message isn't a handshake, or the padding
+    /// isn't all zeros, or the handshake disagrees with this message, returns an
+    /// `Error::ValidationError`. If decryption fails, returns an `Error::EncryptionError`.
+    /// Otherwise, if the handshake is malformed, returns some sort of `Error`.
+    pub(crate) fn open_handshake(
+        &self,
+        cs: &'static CipherSuite,
+        limits: DecodeLimits,
+        provider: &'static dyn CryptoProvider,
+        sender: u32,
+        key: AeadKey,
+        nonce: AeadNonce,
+    ) -> Result<Handshake, Error> {
+        if self.content_type != ContentType::Handshake {
+            return Err(Error::ValidationError("PrivateMessage doesn't contain a handshake"));
+        }
+
+        let aad =
+            content_aad(&self.group_id, self.epoch, self.content_type, &self.authenticated_data)?;
+        let mut ciphertext = self.ciphertext.clone();
+        let plaintext = cs.aead_impl.open_with_aad(&key, nonce, &aad, &mut ciphertext)?;
+        let mut cursor: &[u8] = plaintext;
+        let mut handshake = {
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor)
+                .with_limits(limits)
+                .with_provider(provider);
+            Handshake::tls_deserialize(&mut deserializer)?
+        };
+        application::check_zero_padding(cursor)?;
+        let ctx =
+            CryptoCtx::new().set_cipher_suite(cs).set_decode_limits(limits).set_provider(provider);
+        handshake.upcast_crypto_values(&ctx)?;
+
+        if handshake.prior_epoch != self.epoch || handshake.signer_index != sender {
+            return Err(Error::ValidationError(
+                "Encrypted handshake's epoch or signer differs from its PrivateMessage's",
+            ));
+        }
+        Ok(handshake)
+    }
+
+    /// Returns the protocol version this message was made under
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the ID of the group this message is for
+    pub fn group_id(&self) -> &GroupId {
+        &self.group_id
+    }
+
+    /// Returns the epoch this message was made in
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns what this message carries
+    pub fn content_type(&self) -> ContentType {
+        self.content_type
+    }
+
+    /// Returns the data that the sender bound to this message's content without encrypting it.
+    /// Nothing about it is checked until the message is decrypted.
+    pub fn authenticated_data(&self) -> &[u8] {
+        &self.authenticated_data
+    }
+
+    /// Takes the authenticated data out of this message, and returns it. This is for data that
+    /// the recipients get some other way, e.g., from the delivery service, so it needn't be sent
+    /// along. They have to put it back with `set_authenticated_data` before they decrypt the
+    /// message.
+    pub fn take_authenticated_data(&mut self) -> Vec<u8> {
+        std::mem::replace(&mut self.authenticated_data, Vec::new())
+    }
+
+    /// Puts back the authenticated data that the sender took out of this message with
+    /// `take_authenticated_data`. Decryption fails unless it's exactly what the sender bound to the
+    /// content.
+    pub fn set_authenticated_data(&mut self, authenticated_data: Vec<u8>) {
+        self.authenticated_data = authenticated_data;
+    }
+}
+
+/// A `PublicMessage` read in place, without copying anything out of the buffer it's in. Only the
+/// fields that come before the handshake are parsed, which is enough to route it by group and
+/// epoch. This is for relays and delivery services that pass along many messages that they never
+/// process themselves. `PublicMessageRef::to_message` parses the rest.
+#[derive(Clone, Copy, Debug)]
+pub struct PublicMessageRef<'a> {
+    version: ProtocolVersion,
+    group_id: &'a [u8],
+    epoch: u32,
+    /// The whole encoding of the message
+    encoding: &'a [u8],
+}
+
+impl<'a> PublicMessageRef<'a> {
+    /// Reads the `PublicMessage` that `bytes` is the encoding of. Everything after the group ID is
+    /// taken to be the handshake and membership tag, and isn't checked until `to_message`.
+    ///
+    /// Returns: `Ok(message)` on success. If `bytes` ends before the handshake's epoch, returns an
+    /// `Error::SerdeError`.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<PublicMessageRef<'a>, Error> {
+        let mut reader = SliceReader::new(bytes);
+        let version = ProtocolVersion(reader.read_u8()?);
+        let group_id = reader.read_opaque_u8()?;
+        // The handshake starts with its prior_epoch
+        let epoch = reader.read_u32()?;
+
+        Ok(PublicMessageRef {
+            version,
+            group_id,
+            epoch,
+            encoding: bytes,
+        })
+    }
+
+    /// Returns the protocol version this message was made under
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the ID of the group this message is for
+    pub fn group_id(&self) -> &'a [u8] {
+        self.group_id
+    }
+
+    /// Returns the epoch the handshake was made in
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Parses the whole message, e.g., once it's been routed to the member who processes it
+    ///
+    /// Returns: `Ok(message)` on success. If the message has trailing bytes, returns an
+    /// `Error::ValidationError`. Otherwise, if it's malformed, returns an `Error::SerdeError`.
+    pub fn to_message(&self) -> Result<PublicMessage, Error> {
+        let mut cursor = self.encoding;
+        let message = {
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            PublicMessage::tls_deserialize(&mut deserializer)?
+        };
+        if !cursor.is_empty() {
+            return Err(Error::ValidationError("PublicMessage has trailing bytes"));
+        }
+        Ok(message)
+    }
+}
+
+/// A `PrivateMessage` read in place, without copying any of its fields out of the buffer it's in.
+/// This is for relays and delivery services that route many messages by group and epoch, and
+/// never decrypt them. `PrivateMessageRef::to_message` copies it into a `PrivateMessage`.
+#[derive(Clone, Copy, Debug)]
+pub struct PrivateMessageRef<'a> {
+    version: ProtocolVersion,
+    group_id: &'a [u8],
+    epoch: u32,
+    content_type: ContentType,
+    authenticated_data: &'a [u8],
+    encrypted_sender_data: &'a [u8],
+    ciphertext: &'a [u8],
+}
+
+impl<'a> PrivateMessageRef<'a> {
+    /// Reads the `PrivateMessage` that `bytes` is the encoding of
+    ///
+    /// Returns: `Ok(message)` on success. If `bytes` has anything after the message, or the
+    /// content type is unknown, returns an `Error::ValidationError`. Otherwise, if `bytes` is too
+    /// short, returns an `Error::SerdeError`.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<PrivateMessageRef<'a>, Error> {
+        let mut reader = SliceReader::new(bytes);
+        let version = ProtocolVersion(reader.read_u8()?);
+        let group_id = reader.read_opaque_u8()?;
+        let epoch = reader.read_u32()?;
+        let content_type = match reader.read_u8()? {
+            0 => ContentType::Application,
+            1 => ContentType::Handshake,
+            _ => return Err(Error::ValidationError("PrivateMessage has an unknown content type")),
+        };
+        let authenticated_data = reader.read_opaque_u32()?;
+        let encrypted_sender_data = reader.read_opaque_u8()?;
+        let ciphertext = reader.read_opaque_u32()?;
+        if !reader.remaining().is_empty() {
+            return Err(Error::ValidationError("PrivateMessage has trailing bytes"));
+        }
+
+        Ok(PrivateMessageRef {
+            version,
+            group_id,
+            epoch,
+            content_type,
+            authenticated_data,
+            encrypted_sender_data,
+            ciphertext,
+        })
+    }
+
+    /// Returns the protocol version this message was made under
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the ID of the group this message is for
+    pub fn group_id(&self) -> &'a [u8] {
+        self.group_id
+    }
+
+    /// Returns the epoch this message was made in
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns what this message carries
+    pub fn content_type(&self) -> ContentType {
+        self.content_type
+    }
+
+    /// Returns the data that the sender bound to this message's content without encrypting it.
+    /// Nothing about it is checked until the message is decrypted.
+    pub fn authenticated_data(&self) -> &'a [u8] {
+        self.authenticated_data
+    }
+
+    /// Returns the encrypted content of this message
+    pub fn ciphertext(&self) -> &'a [u8] {
+        self.ciphertext
+    }
+
+    /// Copies this into a `PrivateMessage`, e.g., once it's been routed to the member who
+    /// decrypts it
+    ///
+    /// Returns: `Ok(message)` on success. This can't actually fail, since a group ID read off the
+    /// wire always fits in a `GroupId`, but that's checked anyway.
+    pub fn to_message(&self) -> Result<PrivateMessage, Error> {
+        Ok(PrivateMessage {
+            version: self.version,
+            group_id: GroupId::new(self.group_id.to_vec())?,
+            epoch: self.epoch,
+            content_type: self.content_type,
+            authenticated_data: self.authenticated_data.to_vec(),
+            encrypted_sender_data: self.encrypted_sender_data.to_vec(),
+            ciphertext: self.ciphertext.to_vec(),
+        })
+    }
+}
+
+/// Derives the key and nonce that the sender data of a `PrivateMessage` with content `ciphertext`
+/// is encrypted under, as per the "Sender Data Encryption" section of the spec
+fn sender_data_key_nonce(
+    cs: &'static CipherSuite,
+    sender_data_secret: &HmacKey,
+    ciphertext: &[u8],
+) -> Result<(AeadKey, AeadNonce), Error> {
+    // ciphertext_sample = ciphertext[0..KDF.Nh-1]
+    // sender_data_key = ExpandWithLabel(sender_data_secret, "key", ciphertext_sample, AEAD.Nk)
+    // sender_data_nonce = ExpandWithLabel(sender_data_secret, "nonce", ciphertext_sample, AEAD.Nn)
+    let (hash_impl, aead_impl) = (cs.hash_impl, cs.aead_impl);
+    let sample = &ciphertext[..ciphertext.len().min(hash_impl.digest_size())];
+    let mut key_buf = Secret::new_from_zeros(aead_impl.key_size());
+    kdf::expand_with_label(hash_impl, sender_data_secret, b"key", sample, key_buf.as_mut_bytes());
+    let mut nonce_buf = Secret::new_from_zeros(aead_impl.nonce_size());
+    kdf::expand_with_label(
+        hash_impl,
+        sender_data_secret,
+        b"nonce",
+        sample,
+        nonce_buf.as_mut_bytes(),
+    );
+
+    let key = AeadKey::new_from_bytes(aead_impl, key_buf.as_bytes())?;
+    let nonce = AeadNonce::new_from_bytes(aead_impl, nonce_buf.as_bytes())?;
+    Ok((key, nonce))
+}
+
+/// A `PrivateMessage` once it's been decrypted and its signature checked, along with who sent it
+/// and what else they bound to it. This is what `GroupState::process_application_message`
+/// returns, so that applications can attribute what they receive without digging through the
+/// message themselves.
+#[derive(Clone)]
+#[cfg_attr(test, derive(Debug))]
+pub struct ProcessedMessage {
+    pub(crate) sender: u32,
+    pub(crate) credential: Credential,
+    pub(crate) epoch: u32,
+    pub(crate) content_type: ContentType,
+    pub(crate) authenticated_data: Vec<u8>,
+    pub(crate) plaintext: Vec<u8>,
+}
+
+impl ProcessedMessage {
+    /// Returns the roster index of the member who sent the message
+    pub fn sender(&self) -> u32 {
+        self.sender
+    }
+
+    /// Returns the credential the sender had in the epoch the message was sent in. That's what
+    /// the signature was checked against.
+    pub fn credential(&self) -> &Credential {
+        &self.credential
+    }
+
+    /// Returns the epoch the message was sent in
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns what the message carried
+    pub fn content_type(&self) -> ContentType {
+        self.content_type
+    }
+
+    /// Returns the data that the sender bound to the message without encrypting it. Like the
+    /// plaintext, this has been checked against the sender's signature.
+    pub fn authenticated_data(&self) -> &[u8] {
+        &self.authenticated_data
+    }
+
+    /// Returns the decrypted content of the message
+    pub fn plaintext(&self) -> &[u8] {
+        &self.plaintext
+    }
+
+    /// Returns the decrypted content of the message, and drops the rest
+    pub fn into_plaintext(self) -> Vec<u8> {
+        self.plaintext
+    }
+}
+
+/// A handshake in some framing. This is what `GroupState::frame_handshake` returns, and what
+/// `GroupState::process_handshake_message` takes.
+#[cfg_attr(test, derive(Debug))]
+pub enum HandshakeMessage {
+    /// A handshake in the clear
+    Public(PublicMessage),
+    /// An encrypted handshake
+    Private(PrivateMessage),
+}
+
+impl HandshakeMessage {
+    /// Returns the framing of this message
+    pub fn wire_format(&self) -> WireFormat {
+        match self {
+            HandshakeMessage::Public(_) => WireFormat::PublicMessage,
+            HandshakeMessage::Private(_) => WireFormat::PrivateMessage,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        crypto::{
+            aead::{AeadKey, AeadNonce},
+            ciphersuite::CipherSuite,
+            hash::Digest,
+            hmac::HmacKey,
+        },
+        error::Error,
+        framing::{
+            sender_data_key_nonce, ContentType, HandshakeMessage, PublicMessage, PublicMessageRef,
+            WireFormat, WireFormatPolicy,
+        },
+        group_state::GroupState,
+        handshake::{Handshake, ProposalType, ProtocolVersion, MLS_DUMMY_VERSION},
+        message::{MessageHeader, MlsMessage, MlsMessageRef},
+        ratchet_tree::PathSecret,
+        test_utils, tls_ser,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // Makes two members of a group who both took part in its last epoch change, so they both
+    // have its membership key and secret tree. The first of them can't frame handshakes before
+    // that.
+    fn two_members_in_an_epoch(rng: &mut StdRng) -> (GroupState, GroupState) {
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        let cs = group_state1.cs;
+
+        let (handshake, _, _) = group_state1
+            .create_and_apply_update_handshake(PathSecret::new_from_random(cs, rng), rng)
+            .unwrap();
+        assert!(group_state1.frame_handshake(handshake).is_err());
+
+        let (handshake, new_group_state1, _) = group_state1
+            .create_and_apply_update_handshake(PathSecret::new_from_random(cs, rng), rng)
+            .unwrap();
+        let (new_group_state2, _) = group_state2.process_handshake(&handshake).unwrap();
+        (new_group_state1, new_group_state2)
+    }
+
+    // Makes an empty commit from the given member, and returns it along with their new state
+    fn empty_commit(group_state: &GroupState, rng: &mut StdRng) -> (Handshake, GroupState) {
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, rng);
+        let (handshake, new_group_state, _) = group_state
+            .create_and_apply_commit_handshake(Vec::new(), new_path_secret, rng)
+            .unwrap();
+        (handshake, new_group_state)
+    }
+
+    fn as_public(message: &mut HandshakeMessage) -> &mut PublicMessage {
+        match message {
+            HandshakeMessage::Public(public) => public,
+            _ => panic!("handshake wasn't framed as a PublicMessage"),
+        }
+    }
+
+    // Check that a member can process a handshake that another member framed as a PublicMessage,
+    // that the message says what the handshake does, and that the membership tag and the
+    // recipient's policy are enforced
+    #[quickcheck]
+    fn public_message_handshakes(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let (mut group_state1, mut group_state2) = two_members_in_an_epoch(&mut rng);
+
+        // Frame a commit, and look at it like a delivery service would
+        let (handshake, new_group_state1) = empty_commit(&group_state1, &mut rng);
+        let mut message = group_state1.frame_handshake(handshake).unwrap();
+        assert_eq!(message.wire_format(), WireFormat::PublicMessage);
+        let public = as_public(&mut message);
+        assert_eq!(public.group_id(), &group_state1.group_id);
+        assert_eq!(public.epoch(), group_state1.epoch);
+        assert_eq!(public.sender(), group_state1.roster_index.unwrap());
+        assert!(public.is_commit());
+        assert_eq!(public.proposal_types(), Vec::<ProposalType>::new());
+
+        // Changing what the tag covers is caught
+        as_public(&mut message).handshake.signer_index ^= 1;
+        match group_state2.process_handshake_message(&message) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("PublicMessage with a bad membership tag was accepted"),
+        }
+        as_public(&mut message).handshake.signer_index ^= 1;
+
+        // So is a recipient whose view of the group differs, even with the same membership key
+        let mut diverged = group_state2.clone();
+        diverged.confirmed_transcript_hash = Digest::default();
+        match diverged.process_handshake_message(&message) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("PublicMessage was accepted under another group context"),
+        }
+
+        // A member who only accepts encrypted handshakes rejects it
+        let mut private_only = group_state2.clone();
+        private_only.set_wire_format_policy(WireFormatPolicy::PRIVATE);
+        assert!(private_only.process_handshake_message(&message).is_err());
+
+        let (new_group_state2, _) = group_state2.process_handshake_message(&message).unwrap();
+        assert_eq!(
+            new_group_state2.confirmed_transcript_hash.as_bytes(),
+            new_group_state1.confirmed_transcript_hash.as_bytes()
+        );
+    }
+
+    // Check that a member can process a handshake that another member encrypted, that its sender
+    // is only visible to members, that it can only be processed once, and that it doesn't decrypt
+    // if its header is changed
+    #[quickcheck]
+    fn private_message_handshakes(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let (mut group_state1, mut group_state2) = two_members_in_an_epoch(&mut rng);
+        group_state1.set_wire_format_policy(WireFormatPolicy::PRIVATE);
+        group_state2.set_wire_format_policy(WireFormatPolicy::PRIVATE);
+
+        // Encrypted handshakes come from the handshake ratchet, not the application one
+        let (handshake, new_group_state1) = empty_commit(&group_state1, &mut rng);
+        let mut message = group_state1.frame_handshake(handshake).unwrap();
+        assert_eq!(message.wire_format(), WireFormat::PrivateMessage);
+        let private = match &mut message {
+            HandshakeMessage::Private(private) => private,
+            _ => panic!("handshake wasn't framed as a PrivateMessage"),
+        };
+        assert_eq!(private.content_type(), ContentType::Handshake);
+
+        // Only members can tell who sent it, and under which generation
+        let sender_data_secret = group_state2.sender_data_secret().unwrap();
+        let sender_data = private.open_sender_data(group_state2.cs, sender_data_secret).unwrap();
+        assert_eq!(sender_data, (group_state1.roster_index.unwrap(), 0));
+        private.encrypted_sender_data[0] ^= 1;
+        assert!(private.open_sender_data(group_state2.cs, sender_data_secret).is_err());
+        private.encrypted_sender_data[0] ^= 1;
+
+        // Claiming to be an application message means using the wrong ratchet
+        private.content_type = ContentType::Application;
+        assert!(group_state2.process_handshake_message(&message).is_err());
+        if let HandshakeMessage::Private(private) = &mut message {
+            private.content_type = ContentType::Handshake;
+        }
+
+        // A member who only accepts PublicMessages rejects it
+        let mut public_only = group_state2.clone();
+        public_only.set_wire_format_policy(WireFormatPolicy::PUBLIC);
+        assert!(public_only.process_handshake_message(&message).is_err());
+
+        let (new_group_state2, _) = group_state2.process_handshake_message(&message).unwrap();
+        assert_eq!(
+            new_group_state2.confirmed_transcript_hash.as_bytes(),
+            new_group_state1.confirmed_transcript_hash.as_bytes()
+        );
+        match group_state2.process_handshake_message(&message) {
+            Err(Error::ReplayedMessage(..)) => (),
+            _ => panic!("replayed handshake was not reported as a replay"),
+        }
+    }
+
+    // Check that messages under another protocol version than the group's are rejected as such,
+    // whether they're handshakes or application messages
+    #[quickcheck]
+    fn version_mismatch(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let (mut group_state1, mut group_state2) = two_members_in_an_epoch(&mut rng);
+        let other_version = ProtocolVersion(MLS_DUMMY_VERSION.0.wrapping_add(1));
+
+        let (handshake, _) = empty_commit(&group_state1, &mut rng);
+        let mut message = group_state1.frame_handshake(handshake).unwrap();
+        assert_eq!(as_public(&mut message).version(), group_state1.protocol_version);
+        as_public(&mut message).version = other_version;
+        match group_state2.process_handshake_message(&message) {
+            Err(Error::ProtocolVersionMismatch(expected, found)) => {
+                assert_eq!((expected, found), (group_state2.protocol_version, other_version))
+            }
+            _ => panic!("PublicMessage under another version was accepted"),
+        }
+
+        let mut message = group_state1.encrypt_application_message(b"hello").unwrap();
+        message.version = other_version;
+        match group_state2.decrypt_application_message(message) {
+            Err(Error::ProtocolVersionMismatch(..)) => (),
+            _ => panic!("PrivateMessage under another version was accepted"),
+        }
+    }
+
+    // Check that the borrowed views of messages say what the messages do, and turn back into
+    // them
+    #[quickcheck]
+    fn borrowed_messages(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let (mut group_state1, _) = two_members_in_an_epoch(&mut rng);
+
+        let private =
+            group_state1.encrypt_application_message_with_aad(b"hello", b"route me").unwrap();
+        let bytes = MlsMessage::from(private).to_bytes().unwrap();
+        let private_ref = match MlsMessageRef::from_bytes(&bytes).unwrap() {
+            MlsMessageRef::PrivateMessage(private_ref) => private_ref,
+            _ => panic!("PrivateMessage was read as something else"),
+        };
+        assert_eq!(private_ref.group_id(), group_state1.group_id.as_bytes());
+        assert_eq!(private_ref.epoch(), group_state1.epoch);
+        assert_eq!(private_ref.content_type(), ContentType::Application);
+        assert_eq!(private_ref.authenticated_data(), b"route me");
+        // The views point into the buffer they were read from
+        let range = bytes.as_ptr_range();
+        assert!(range.contains(&private_ref.ciphertext().as_ptr()));
+        let private = private_ref.to_message().unwrap();
+        assert_eq!(MlsMessage::from(private).to_bytes().unwrap(), bytes);
+
+        // Trailing bytes are rejected
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(MlsMessageRef::from_bytes(&longer).is_err());
+
+        let (handshake, _) = empty_commit(&group_state1, &mut rng);
+        let public = match group_state1.frame_handshake(handshake).unwrap() {
+            HandshakeMessage::Public(public) => public,
+            _ => panic!("handshake wasn't framed as a PublicMessage"),
+        };
+        let bytes = tls_ser::serialize_to_bytes(&public).unwrap();
+        let public_ref = PublicMessageRef::from_bytes(&bytes).unwrap();
+        assert_eq!(public_ref.group_id(), group_state1.group_id.as_bytes());
+        assert_eq!(public_ref.epoch(), public.epoch());
+        assert_eq!(public_ref.version(), public.version());
+        let parsed = public_ref.to_message().unwrap();
+        assert_eq!(tls_ser::serialize_to_bytes(&parsed).unwrap(), bytes);
+    }
+
+    // Check that a message's header can be read without the rest of it, and that messages
+    // without headers are told apart from malformed ones
+    #[quickcheck]
+    fn message_header_peek(rng_seed: u64) {
+        let mut rng = StdRng::seed_from_u64(rng_seed);
+        let (mut group_state1, _) = two_members_in_an_epoch(&mut rng);
+
+        let private = group_state1.encrypt_application_message(b"hello").unwrap();
+        let bytes = MlsMessage::from(private).to_bytes().unwrap();
+        let header = MessageHeader::peek(&bytes).unwrap().unwrap();
+        assert_eq!(header.wire_format(), WireFormat::PrivateMessage);
+        assert_eq!(header.group_id(), group_state1.group_id.as_bytes());
+        assert_eq!(header.epoch(), group_state1.epoch);
+        assert_eq!(header.content_type(), ContentType::Application);
+        // Everything after the header may as well be missing
+        let header_len = 1 + 1 + 1 + header.group_id().len() + 4 + 1;
+        assert_eq!(MessageHeader::peek(&bytes[..header_len]).unwrap(), Some(header));
+        assert!(MessageHeader::peek(&bytes[..header_len - 1]).is_err());
+
+        let (handshake, _) = empty_commit(&group_state1, &mut rng);
+        let message = group_state1.frame_handshake(handshake).unwrap();
+        let bytes = MlsMessage::from(message).to_bytes().unwrap();
+        let header = MessageHeader::peek(&bytes).unwrap().unwrap();
+        assert_eq!(header.wire_format(), WireFormat::PublicMessage);
+        assert_eq!(header.group_id(), group_state1.group_id.as_bytes());
+        assert_eq!(header.epoch(), group_state1.epoch);
+        assert_eq!(header.content_type(), ContentType::Handshake);
+
+        let group_info = group_state1.group_info().unwrap();
+        let bytes = MlsMessage::from(group_info).to_bytes().unwrap();
+        assert_eq!(MessageHeader::peek(&bytes).unwrap(), None);
+        assert!(MessageHeader::peek(&[5]).is_err());
+        assert!(MessageHeader::peek(&[]).is_err());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct SenderDataCase {
+        sender_data_secret: String,
+        ciphertext: String,
+        key: String,
+        nonce: String,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct SenderDataVector {
+        cipher_suite: u16,
+        sender_data: SenderDataCase,
+    }
+
+    // Tests the sender data keys against the RFC 9420 secret tree test vectors, which are made by
+    // test_vectors/generate.py, as in official_secret_tree_kat of secret_tree. Keys
+    // and nonces can't be read back out, so they're compared by sealing the same message with
+    // them.
+    #[test]
+    fn official_sender_data_kat() {
+        let f = std::fs::File::open("test_vectors/secret-tree.json").unwrap();
+        let vectors: Vec<SenderDataVector> = serde_json::from_reader(f).unwrap();
+        let unhex = |s: &String| hex::decode(s).unwrap();
+        let seal = |cs: &CipherSuite, key: AeadKey, nonce: AeadNonce| {
+            let mut buf = b"sender data".to_vec();
+            buf.extend(vec![0u8; cs.aead_impl.tag_size()]);
+            cs.aead_impl.seal(&key, nonce, &mut buf).unwrap();
+            buf
+        };
+
+        let mut num_checked = 0;
+        for vector in vectors {
+            let cs = match CipherSuite::from_id(vector.cipher_suite) {
+                Some(cs) => cs,
+                None => continue,
+            };
+            let case = &vector.sender_data;
+
+            let sender_data_secret = HmacKey::new_from_bytes(&unhex(&case.sender_data_secret));
+            let (our_key, our_nonce) =
+                sender_data_key_nonce(cs, &sender_data_secret, &unhex(&case.ciphertext)).unwrap();
+            let key = AeadKey::new_from_bytes(cs.aead_impl, &unhex(&case.key)).unwrap();
+            let nonce = AeadNonce::new_from_bytes(cs.aead_impl, &unhex(&case.nonce)).unwrap();
+            assert_eq!(seal(cs, our_key, our_nonce), seal(cs, key, nonce));
+
+            num_checked += 1;
+        }
+
+        assert!(num_checked > 0);
+    }
+}
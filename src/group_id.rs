@@ -0,0 +1,103 @@
+//! Defines `GroupId`, the application-defined identifier of a group. This crate never interprets
+//! group IDs, it only compares them and puts them on the wire.
+
+use crate::{crypto::hash::Digest, error::Error};
+
+use std::{fmt, str::FromStr};
+
+/// The longest a group ID can be, since it goes on the wire as `opaque group_id<0..255>`
+pub const MAX_GROUP_ID_LEN: usize = 255;
+
+// opaque group_id<0..255>;
+/// An application-defined identifier for a group. This can be made from a UUID, a hash, or any
+/// bytes at all, so long as they fit in `MAX_GROUP_ID_LEN` bytes. It displays as lowercase hex,
+/// and parses from the same.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(bound = "u8")]
+pub struct GroupId(Vec<u8>);
+
+impl GroupId {
+    /// Makes a group ID out of the given bytes
+    ///
+    /// Returns: `Ok(group_id)` on success. If `bytes` is longer than `MAX_GROUP_ID_LEN`, returns
+    /// an `Error::ValidationError`.
+    pub fn new(bytes: Vec<u8>) -> Result<GroupId, Error> {
+        if bytes.len() > MAX_GROUP_ID_LEN {
+            Err(Error::ValidationError("Group ID is longer than 255 bytes"))
+        } else {
+            Ok(GroupId(bytes))
+        }
+    }
+
+    /// Makes a group ID out of the 16 bytes of a UUID, in their usual big-endian order
+    pub fn from_uuid_bytes(uuid: [u8; 16]) -> GroupId {
+        GroupId(uuid.to_vec())
+    }
+
+    /// Makes a group ID out of a hash, e.g., of some application-level description of the group
+    pub fn from_digest(digest: &Digest) -> GroupId {
+        // No hash function in this crate has a digest longer than 64 bytes
+        GroupId(digest.as_bytes().to_vec())
+    }
+
+    /// Returns the bytes of this group ID
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+impl fmt::Display for GroupId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for GroupId {
+    type Err = Error;
+
+    /// Parses a group ID from hex, as output by `GroupId`'s `Display` impl. Upper and lowercase
+    /// digits are both accepted.
+    fn from_str(s: &str) -> Result<GroupId, Error> {
+        if s.len() % 2 != 0 {
+            return Err(Error::ValidationError("Hex group ID has an odd number of digits"));
+        }
+
+        // from_str_radix allows a leading sign, so check the digits first
+        if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::ValidationError("Hex group ID has a non-hex digit"));
+        }
+        // Every digit is ASCII, so every pair of bytes is a str
+        let bytes = s
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect();
+
+        GroupId::new(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Group IDs should survive a trip through their hex form, and the wire format should be the
+    // same as that of an `opaque group_id<0..255>`
+    #[test]
+    fn group_id_forms() {
+        let group_id = GroupId::new(b"hello".to_vec()).unwrap();
+        assert_eq!(group_id.to_string(), "68656c6c6f");
+        assert_eq!("68656C6C6F".parse::<GroupId>().unwrap(), group_id);
+        assert!("6865f".parse::<GroupId>().is_err());
+        assert!("68zz".parse::<GroupId>().is_err());
+
+        let bytes = crate::tls_ser::serialize_to_bytes(&group_id).unwrap();
+        assert_eq!(bytes, b"\x05hello");
+
+        assert!(GroupId::new(vec![0u8; MAX_GROUP_ID_LEN]).is_ok());
+        assert!(GroupId::new(vec![0u8; MAX_GROUP_ID_LEN + 1]).is_err());
+    }
+}
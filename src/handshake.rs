@@ -1,90 +1,229 @@
 //! Defines group handshake-related data structures and operations. Not much public API here.
 
 use crate::{
-    credential::Credential,
+    credential::{Credential, CredentialType},
     crypto::{
         ciphersuite::CipherSuite,
         dh::{DhPrivateKey, DhPublicKey},
-        ecies::EciesCiphertext,
+        hash::Digest,
         hmac::Mac,
+        hpke::HpkeCiphertext,
+        key_ops::DhPrivateKeyOps,
         rng::CryptoRng,
-        sig::{SigSecretKey, Signature},
+        sig::{SigSecretKey, Signature, SignatureScheme},
     },
     error::Error,
+    extensions::{self, Extension, ExtensionType, LeafExtensions, Lifetime},
+    group_id::GroupId,
     group_state::WelcomeInfoHash,
+    psk::PreSharedKeyId,
     tls_ser,
 };
 
-/// Represents a version of the MLS protocol
+use std::sync::Arc;
+
+/// Represents a version of the MLS protocol. Like `ProposalType`, this is a code point rather
+/// than a closed set, so that whatever another stack advertises or sends can still be parsed, and
+/// then rejected with an `Error::ProtocolVersionMismatch` if this crate doesn't speak it.
 // uint8 ProtocolVersion;
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
-pub struct ProtocolVersion(u8);
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct ProtocolVersion(pub u8);
 
 /// A dummy protocol version
 // TODO: Remove this before going into production. Final last words, amirite
 pub const MLS_DUMMY_VERSION: ProtocolVersion = ProtocolVersion(0xba);
 
+impl ProtocolVersion {
+    /// Every protocol version this crate speaks, newest first
+    pub const SUPPORTED: &'static [ProtocolVersion] = &[MLS_DUMMY_VERSION];
+
+    /// Returns whether this crate speaks this protocol version
+    pub fn is_supported(self) -> bool {
+        ProtocolVersion::SUPPORTED.contains(&self)
+    }
+
+    /// Picks the protocol version to make a group under `cs` in with the owners of `init_keys`,
+    /// i.e., the one that every one of them supports under `cs`. Without any `UserInitKey`s, this
+    /// is the newest version this crate speaks.
+    ///
+    /// Returns: `Ok(version)` on success. If one of the `UserInitKey`s doesn't support `cs`, or is
+    /// invalid, returns an `Error::ValidationError`. If they support different versions under
+    /// `cs`, or one that this crate doesn't speak, returns an `Error::ProtocolVersionMismatch`.
+    pub fn negotiate(
+        cs: &'static CipherSuite,
+        init_keys: &[UserInitKey],
+    ) -> Result<ProtocolVersion, Error> {
+        let newest = ProtocolVersion::SUPPORTED[0];
+        let mut negotiated = None;
+        for init_key in init_keys {
+            let version = init_key
+                .get_supported_version(cs)?
+                .ok_or(Error::ValidationError("UserInitKey doesn't support the cipher suite"))?;
+            match negotiated {
+                Some(negotiated) if negotiated != version => {
+                    return Err(Error::ProtocolVersionMismatch(negotiated, version));
+                }
+                _ => negotiated = Some(version),
+            }
+        }
+
+        let version = negotiated.unwrap_or(newest);
+        if version.is_supported() {
+            Ok(version)
+        } else {
+            Err(Error::ProtocolVersionMismatch(newest, version))
+        }
+    }
+}
+
 /// Contains a node's new public key and the new node's secret, encrypted for everyone in that
 /// node's resolution
-#[derive(Deserialize, Serialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct DirectPathNodeMessage {
     pub(crate) public_key: DhPublicKey,
-    // ECIESCiphertext node_secrets<0..2^16-1>;
-    #[serde(rename = "node_secrets__bound_u16")]
-    pub(crate) node_secrets: Vec<EciesCiphertext>,
+    // HPKECiphertext node_secrets<0..2^16-1>;
+    #[tls(bound = "u16")]
+    pub(crate) node_secrets: Vec<HpkeCiphertext>,
 }
 
 /// Contains a direct path of node messages. The length of `node_secrets` for the first
 /// `DirectPathNodeMessage` MUST be zero.
-#[derive(Deserialize, Serialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct DirectPathMessage {
     // DirectPathNodeMessage nodes<0..2^16-1>;
-    #[serde(rename = "node_messages__bound_u16")]
+    #[tls(bound = "u16")]
     pub(crate) node_messages: Vec<DirectPathNodeMessage>,
 }
 
+impl DirectPathMessage {
+    /// Returns the `UpdatePath` that this message carries. The first node message is the leaf's,
+    /// and the rest are the parents'.
+    ///
+    /// Returns: `Ok(path)` on success. If there are no node messages, or the first one has node
+    /// secrets, returns an `Error::ValidationError`.
+    pub(crate) fn to_update_path(&self) -> Result<UpdatePath, Error> {
+        let (leaf, parents) = self
+            .node_messages
+            .split_first()
+            .ok_or(Error::ValidationError("DirectPathMessage has no node messages"))?;
+        if !leaf.node_secrets.is_empty() {
+            return Err(Error::ValidationError("DirectPathMessage has secrets for the leaf"));
+        }
+
+        let nodes = parents.iter().map(|node_msg| UpdatePathNode {
+            public_key: node_msg.public_key.clone(),
+            encrypted_path_secret: node_msg.node_secrets.clone(),
+        });
+        Ok(UpdatePath {
+            leaf_public_key: leaf.public_key.clone(),
+            nodes: nodes.collect(),
+        })
+    }
+}
+
+impl From<UpdatePath> for DirectPathMessage {
+    fn from(path: UpdatePath) -> DirectPathMessage {
+        let leaf = DirectPathNodeMessage {
+            public_key: path.leaf_public_key,
+            node_secrets: Vec::new(),
+        };
+        let parents = path.nodes.into_iter().map(|node| DirectPathNodeMessage {
+            public_key: node.public_key,
+            node_secrets: node.encrypted_path_secret,
+        });
+        DirectPathMessage {
+            node_messages: std::iter::once(leaf).chain(parents).collect(),
+        }
+    }
+}
+
+// struct {
+//     HPKEPublicKey public_key;
+//     HPKECiphertext encrypted_path_secret<0..2^32-1>;
+// } UpdatePathNode;
+/// A parent node on the sender's direct path: its new public key, and its path secret encrypted
+/// to every node in the resolution of its child on the sender's copath, in resolution order
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct UpdatePathNode {
+    pub(crate) public_key: DhPublicKey,
+    #[tls(bound = "u32")]
+    pub(crate) encrypted_path_secret: Vec<HpkeCiphertext>,
+}
+
+// struct {
+//     HPKEPublicKey leaf_public_key;
+//     UpdatePathNode nodes<0..2^32-1>;
+// } UpdatePath;
+/// New entropy for the tree, in the spec's shape: the sender's new leaf key, then a node for every
+/// parent on the leaf's direct path, from the bottom up. The leaf's path secret is fresh, and each
+/// parent's is derived from the one below it. The spec sends a whole new KeyPackage for the leaf,
+/// but a committer's credential and capabilities don't change here, so only the key is sent, and
+/// the commit's signature covers it.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct UpdatePath {
+    pub(crate) leaf_public_key: DhPublicKey,
+    #[tls(bound = "u32", limit = "tree")]
+    pub(crate) nodes: Vec<UpdatePathNode>,
+}
+
+impl UpdatePath {
+    /// Returns the new public keys of the sender's leaf and of every node in its direct path, from
+    /// the bottom up
+    pub(crate) fn public_keys(&self) -> impl Iterator<Item = &DhPublicKey> + Clone {
+        std::iter::once(&self.leaf_public_key).chain(self.nodes.iter().map(|node| &node.public_key))
+    }
+}
+
 /// This is used in lieu of negotiating public keys when a member is added. This has a bunch of
 /// published ephemeral keys that can be used to initiated communication with a previously
 /// uncontacted member.
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub struct UserInitKey {
     // opaque user_init_key_id<0..255>
     /// An identifier for this init key. This MUST be unique among the `UserInitKey` generated by
     /// the client
-    #[serde(rename = "user_init_key_id__bound_u8")]
+    #[tls(bound = "u8")]
     pub(crate) user_init_key_id: Vec<u8>,
 
     // ProtocolVersion supported_versions<0..255>;
     /// The protocol versions supported by the member. Each entry is the supported protocol version
     /// of the entry in `init_keys` of the same index. This MUST have the same length as
     /// `init_keys`.
-    #[serde(rename = "supported_versions__bound_u8")]
+    #[tls(bound = "u8")]
     supported_versions: Vec<ProtocolVersion>,
 
     // CipherSuite cipher_suites<0..255>
     /// The cipher suites supported by the member. Each cipher suite here corresponds uniquely to a
     /// DH public key in `init_keys`. As such, this MUST have the same length as `init_keys`.
-    #[serde(rename = "cipher_suites__bound_u8")]
+    #[tls(bound = "u8")]
     pub(crate) cipher_suites: Vec<&'static CipherSuite>,
 
     // HPKEPublicKey init_keys<1..2^16-1>
     /// The DH public keys owned by the member. Each public key corresponds uniquely to a cipher
     /// suite in `cipher_suites`. As such, this MUST have the same length as `cipher_suites`.
-    #[serde(rename = "init_keys__bound_u16")]
+    #[tls(bound = "u16")]
     pub(crate) init_keys: Vec<DhPublicKey>,
 
     /// The DH private keys owned by the member. This is only `Some` if this member is the creator
     /// of this `UserInitKey`. Each private key corresponds uniquely to a public key in
     /// `init_keys`. As such, this MUST have the same length as `init_keys`.
-    #[serde(skip)]
+    #[tls(skip)]
     pub(crate) private_keys: Option<Vec<DhPrivateKey>>,
 
     /// The identity information of the member
     pub(crate) credential: Credential,
 
+    // Extension extensions<0..2^32-1>;
+    /// Whatever else the member says about this key, e.g., for how long it may be used. These are
+    /// signed along with the rest, unlike the leaf extensions of the Add that adds the member.
+    #[tls(bound = "u32", limit = "extensions")]
+    pub(crate) extensions: Vec<Extension>,
+
     /// Contains the signature of all the other fields of this struct, under the identity key of
     /// the client.
     pub(crate) signature: Signature,
@@ -92,17 +231,19 @@ pub struct UserInitKey {
 
 // This struct is everything but the last field in UserInitKey. We use the serialized form
 // of this as the message that the signature is computed over
-#[derive(Serialize)]
+#[derive(TlsSerialize)]
 struct PartialUserInitKey<'a> {
-    #[serde(rename = "user_init_key_id__bound_u8")]
+    #[tls(bound = "u8")]
     user_init_key_id: &'a [u8],
-    #[serde(rename = "supported_versions__bound_u8")]
+    #[tls(bound = "u8")]
     supported_versions: &'a [ProtocolVersion],
-    #[serde(rename = "cipher_suites__bound_u8")]
+    #[tls(bound = "u8")]
     cipher_suites: &'a [&'static CipherSuite],
-    #[serde(rename = "init_keys__bound_u16")]
+    #[tls(bound = "u16")]
     init_keys: &'a [DhPublicKey],
     credential: &'a Credential,
+    #[tls(bound = "u32")]
+    extensions: &'a [Extension],
 }
 
 impl UserInitKey {
@@ -112,13 +253,77 @@ impl UserInitKey {
         identity_key: &SigSecretKey,
         user_init_key_id: Vec<u8>,
         credential: Credential,
-        mut cipher_suites: Vec<&'static CipherSuite>,
+        cipher_suites: Vec<&'static CipherSuite>,
         supported_versions: Vec<ProtocolVersion>,
         csprng: &mut R,
     ) -> Result<UserInitKey, Error>
     where
         R: CryptoRng,
     {
+        // Collect a private key for every ciphersuite in the given vector
+        let private_keys = cipher_suites
+            .iter()
+            .map(|cs| DhPrivateKey::new_from_random(cs.dh_impl, csprng))
+            .collect::<Result<Vec<DhPrivateKey>, Error>>()?;
+
+        UserInitKey::new_from_private_keys(
+            identity_key,
+            user_init_key_id,
+            credential,
+            cipher_suites,
+            supported_versions,
+            private_keys,
+        )
+    }
+
+    /// Makes a new `UserInitKey` with the key ID, credential, ciphersuites, and supported
+    /// versions, whose init keys are held outside of this process, e.g., in an HSM. Each handle in
+    /// `init_keys` is the init key of the ciphersuite of the same index. The identity key is needed
+    /// to sign the resulting structure.
+    ///
+    /// Returns: `Ok(user_init_key)` on success. If `init_keys` and `cipher_suites` differ in
+    /// length, returns an `Error::ValidationError`. If some handle's public key isn't valid for
+    /// its ciphersuite, returns an `Error::DhError`.
+    pub fn new_from_external_keys(
+        identity_key: &SigSecretKey,
+        user_init_key_id: Vec<u8>,
+        credential: Credential,
+        cipher_suites: Vec<&'static CipherSuite>,
+        supported_versions: Vec<ProtocolVersion>,
+        init_keys: Vec<Arc<dyn DhPrivateKeyOps>>,
+    ) -> Result<UserInitKey, Error> {
+        if init_keys.len() != cipher_suites.len() {
+            return Err(Error::ValidationError(
+                "Supported ciphersuites and init key vectors differ in length",
+            ));
+        }
+
+        let private_keys = cipher_suites
+            .iter()
+            .zip(init_keys.into_iter())
+            .map(|(cs, ops)| DhPrivateKey::new_external(cs.dh_impl, ops))
+            .collect::<Result<Vec<DhPrivateKey>, Error>>()?;
+
+        UserInitKey::new_from_private_keys(
+            identity_key,
+            user_init_key_id,
+            credential,
+            cipher_suites,
+            supported_versions,
+            private_keys,
+        )
+    }
+
+    /// Makes a new `UserInitKey` out of the given private keys, one per ciphersuite, and signs it
+    /// with the identity key
+    fn new_from_private_keys(
+        identity_key: &SigSecretKey,
+        user_init_key_id: Vec<u8>,
+        credential: Credential,
+        mut cipher_suites: Vec<&'static CipherSuite>,
+        supported_versions: Vec<ProtocolVersion>,
+        private_keys: Vec<DhPrivateKey>,
+    ) -> Result<UserInitKey, Error> {
         // Check the ciphersuite list for duplicates. We don't like this
         let old_cipher_suite_len = cipher_suites.len();
         cipher_suites.dedup();
@@ -134,17 +339,13 @@ impl UserInitKey {
             ));
         }
 
-        let mut init_keys = Vec::new();
-        let mut private_keys = Vec::new();
-
-        // Collect a keypair for every ciphersuite in the given vector
-        for cs in cipher_suites.iter() {
-            let scalar = DhPrivateKey::new_from_random(cs.dh_impl, csprng)?;
-            let public_key = DhPublicKey::new_from_private_key(cs.dh_impl, &scalar);
-
-            init_keys.push(public_key);
-            private_keys.push(scalar);
-        }
+        // Collect the public key of every private key. We never need to look inside the private
+        // keys to do this, so they might as well be external.
+        let init_keys: Vec<DhPublicKey> = cipher_suites
+            .iter()
+            .zip(private_keys.iter())
+            .map(|(cs, scalar)| DhPublicKey::new_from_private_key(cs.dh_impl, scalar))
+            .collect();
         // The UserInitKey has this as an Option
         let private_keys = Some(private_keys);
 
@@ -155,11 +356,12 @@ impl UserInitKey {
             cipher_suites: cipher_suites.as_slice(),
             init_keys: init_keys.as_slice(),
             credential: &credential,
+            extensions: &[],
         };
 
         let serialized_uik = tls_ser::serialize_to_bytes(&partial)?;
         let sig_scheme = credential.get_signature_scheme();
-        let signature = sig_scheme.sign(identity_key, &serialized_uik);
+        let signature = sig_scheme.sign(identity_key, &serialized_uik)?;
 
         Ok(UserInitKey {
             user_init_key_id,
@@ -168,10 +370,60 @@ impl UserInitKey {
             init_keys,
             private_keys,
             credential,
+            extensions: Vec::new(),
             signature,
         })
     }
 
+    /// Returns this `UserInitKey` with its extensions replaced by `extensions`, e.g., a lifetime
+    /// extension made with `Lifetime::to_extension`, and signed anew with the identity key
+    ///
+    /// Returns: `Ok(user_init_key)` on success. If two of the extensions have the same type,
+    /// returns an `Error::ValidationError`.
+    pub fn with_extensions(
+        mut self,
+        identity_key: &SigSecretKey,
+        extensions: Vec<Extension>,
+    ) -> Result<UserInitKey, Error> {
+        if extensions::has_duplicate_types(&extensions) {
+            return Err(Error::ValidationError("UserInitKey has two extensions of the same type"));
+        }
+        self.extensions = extensions;
+
+        let partial = PartialUserInitKey {
+            user_init_key_id: self.user_init_key_id.as_slice(),
+            supported_versions: self.supported_versions.as_slice(),
+            cipher_suites: self.cipher_suites.as_slice(),
+            init_keys: self.init_keys.as_slice(),
+            credential: &self.credential,
+            extensions: self.extensions.as_slice(),
+        };
+        let serialized_uik = tls_ser::serialize_to_bytes(&partial)?;
+        let sig_scheme = self.credential.get_signature_scheme();
+        self.signature = sig_scheme.sign(identity_key, &serialized_uik)?;
+
+        Ok(self)
+    }
+
+    /// Returns the extensions that the creator of this `UserInitKey` signed along with it
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    /// Returns the span of time in which this key may be used, if its creator gave it one
+    ///
+    /// Returns: `Ok(Some(lifetime))` if there's a lifetime extension, `Ok(None)` if there's none,
+    /// and an `Error` if it's malformed.
+    // This is just a wrapper around Lifetime::from_extensions
+    pub fn lifetime(&self) -> Result<Option<Lifetime>, Error> {
+        Lifetime::from_extensions(&self.extensions)
+    }
+
+    /// Returns whether this is a last-resort key, i.e., whether it has a last-resort extension
+    pub fn is_last_resort(&self) -> bool {
+        self.extensions.iter().any(|ext| ext.extension_type == extensions::LAST_RESORT_TYPE)
+    }
+
     /// Verifies this `UserInitKey` under the identity key specified in the `credential` field
     ///
     /// Returns: `Ok(())` on success, `Error::SignatureError` on verification failure, and
@@ -184,6 +436,7 @@ impl UserInitKey {
             cipher_suites: self.cipher_suites.as_slice(),
             init_keys: self.init_keys.as_slice(),
             credential: &self.credential,
+            extensions: self.extensions.as_slice(),
         };
         let serialized_uik = tls_ser::serialize_to_bytes(&partial)?;
 
@@ -193,6 +446,19 @@ impl UserInitKey {
         sig_scheme.verify(public_key, &serialized_uik, &self.signature)
     }
 
+    /// Returns the hash of this `UserInitKey` under the given cipher suite's hash function. A
+    /// `BatchWelcome` tells its joiners apart by this.
+    pub(crate) fn hash(&self, cs: &CipherSuite) -> Result<Digest, Error> {
+        cs.hash_impl.hash_serializable(self)
+    }
+
+    /// Returns the hash of this `UserInitKey` under the given cipher suite's hash function. This
+    /// is what `GroupState::member_by_key_package_hash` looks members up by.
+    // This is just a convenient wrapper around hash()
+    pub fn key_package_hash(&self, cs: &CipherSuite) -> Result<Vec<u8>, Error> {
+        Ok(self.hash(cs)?.as_bytes().to_vec())
+    }
+
     // TODO: URGENT: Figure out how to implement the mandatory check specified in section 6:
     // "UserInitKeys also contain an identifier chosen by the client, which the client MUST assure
     // uniquely identifies a given UserInitKey object among the set of UserInitKeys created by this
@@ -225,14 +491,24 @@ impl UserInitKey {
         // number has decreased.
         let mut cipher_suites = self.cipher_suites.clone();
         let original_len = cipher_suites.len();
-        cipher_suites.sort_by_key(|c| c.name);
-        cipher_suites.dedup_by_key(|c| c.name);
+        cipher_suites.sort_by_key(|c| c.id);
+        cipher_suites.dedup_by_key(|c| c.id);
         if cipher_suites.len() != original_len {
             return Err(Error::ValidationError(
                 "UserInitKey has init keys with duplicate ciphersuites",
             ));
         }
 
+        if extensions::has_duplicate_types(&self.extensions) {
+            return Err(Error::ValidationError("UserInitKey has two extensions of the same type"));
+        }
+        let last_resort_has_data = self.extensions.iter().any(|ext| {
+            ext.extension_type == extensions::LAST_RESORT_TYPE && !ext.extension_data.is_empty()
+        });
+        if last_resort_has_data {
+            return Err(Error::ValidationError("UserInitKey's last-resort extension has data"));
+        }
+
         Ok(())
     }
 
@@ -299,6 +575,11 @@ impl UserInitKey {
         Ok(None)
     }
 
+    /// Returns the protocol version of every init key, in the same order
+    pub(crate) fn supported_versions(&self) -> &[ProtocolVersion] {
+        &self.supported_versions
+    }
+
     /// Retrieves the supported protocol version in this `UserInitKey` that corresponds to the
     /// given cipher suite
     ///
@@ -332,12 +613,12 @@ impl UserInitKey {
 }
 
 /// This is currently not defined by the spec. See open issue in section 8.1
-#[derive(Deserialize, Serialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct GroupInit;
 
 /// Operation to add a partcipant to a group
-#[derive(Deserialize, Serialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct GroupAdd {
     // uint32 index;
@@ -355,14 +636,14 @@ pub(crate) struct GroupAdd {
 }
 
 /// Operation to add entropy to the group
-#[derive(Deserialize, Serialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct GroupUpdate {
     pub(crate) path: DirectPathMessage,
 }
 
 /// Operation to remove a partcipant from the group
-#[derive(Deserialize, Serialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct GroupRemove {
     /// The roster index of the removed member
@@ -372,21 +653,797 @@ pub(crate) struct GroupRemove {
     pub(crate) path: DirectPathMessage,
 }
 
+/// A proposal to add a member to the group
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct AddProposal {
+    /// Contains the public key used to add the new member. The new member goes in the leftmost
+    /// empty roster entry, or at the end of the roster if there is none.
+    pub(crate) init_key: UserInitKey,
+
+    /// What the new member supports. These have to satisfy the group's required_capabilities
+    /// extension, if it has one.
+    // The UserInitKey format has no room for these, so they travel next to it, unsigned by the new
+    // member. A committer who lies about them can only add someone who can't follow the group.
+    pub(crate) capabilities: Capabilities,
+
+    /// The extensions the new member attached to their leaf. Like `capabilities`, these travel
+    /// next to the `UserInitKey` rather than in it.
+    pub(crate) leaf_extensions: LeafExtensions,
+}
+
+// uint16 ProposalType;
+/// Identifies a kind of proposal, as listed in `Capabilities` and required_capabilities
+/// extensions
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct ProposalType(pub u16);
+
+impl ProposalType {
+    pub const ADD: ProposalType = ProposalType(0x0001);
+    pub const UPDATE: ProposalType = ProposalType(0x0002);
+    pub const REMOVE: ProposalType = ProposalType(0x0003);
+    pub const PRE_SHARED_KEY: ProposalType = ProposalType(0x0004);
+    pub const REINIT: ProposalType = ProposalType(0x0005);
+    pub const EXTERNAL_INIT: ProposalType = ProposalType(0x0006);
+    pub const GROUP_CONTEXT_EXTENSIONS: ProposalType = ProposalType(0x0007);
+
+    /// Every proposal type this crate can process
+    pub(crate) const ALL: &'static [ProposalType] = &[
+        ProposalType::ADD,
+        ProposalType::UPDATE,
+        ProposalType::REMOVE,
+        ProposalType::PRE_SHARED_KEY,
+        ProposalType::REINIT,
+        ProposalType::EXTERNAL_INIT,
+        ProposalType::GROUP_CONTEXT_EXTENSIONS,
+    ];
+}
+
+/// What a member supports: the protocol versions and cipher suites they can be in a group with,
+/// and the extension, proposal, and credential types they can handle. A group's
+/// required_capabilities extension lists what every member has to support, and
+/// `GroupState::common_capabilities` says what they all do.
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct Capabilities {
+    // ProtocolVersion versions<V>;
+    /// The protocol versions the member supports
+    #[tls(bound = "varint")]
+    pub(crate) versions: Vec<ProtocolVersion>,
+
+    // CipherSuite cipher_suites<V>;
+    /// The cipher suites the member supports
+    #[tls(bound = "varint")]
+    pub(crate) cipher_suites: Vec<&'static CipherSuite>,
+
+    // ExtensionType extensions<V>;
+    /// The GroupContext extension types the member supports
+    #[tls(bound = "varint", limit = "extensions")]
+    pub(crate) extensions: Vec<ExtensionType>,
+
+    // ProposalType proposals<V>;
+    /// The proposal types the member supports
+    #[tls(bound = "varint")]
+    pub(crate) proposals: Vec<ProposalType>,
+
+    // CredentialType credentials<V>;
+    /// The credential types the member supports
+    #[tls(bound = "varint")]
+    pub(crate) credentials: Vec<CredentialType>,
+}
+
+impl Capabilities {
+    /// Makes a description of a member who supports the given protocol versions, cipher suites,
+    /// and extension, proposal, and credential types
+    pub fn new(
+        versions: Vec<ProtocolVersion>,
+        cipher_suites: Vec<&'static CipherSuite>,
+        extensions: Vec<ExtensionType>,
+        proposals: Vec<ProposalType>,
+        credentials: Vec<CredentialType>,
+    ) -> Capabilities {
+        Capabilities {
+            versions,
+            cipher_suites,
+            extensions,
+            proposals,
+            credentials,
+        }
+    }
+
+    /// Returns what's assumed of the creator of `init_key` when nothing else is known: the
+    /// versions and cipher suites of `init_key`, no extensions, every proposal type this crate
+    /// knows, and the type of their own credential
+    pub fn for_init_key(init_key: &UserInitKey) -> Capabilities {
+        let mut versions = Vec::new();
+        for version in init_key.supported_versions.iter() {
+            if !versions.contains(version) {
+                versions.push(*version);
+            }
+        }
+        Capabilities {
+            versions,
+            cipher_suites: init_key.cipher_suites.clone(),
+            ..Capabilities::for_credential(&init_key.credential, Vec::new(), Vec::new())
+        }
+    }
+
+    /// Returns what's assumed of the holder of `credential` when all that's known is that they
+    /// support the given versions and cipher suites, like in `for_init_key`
+    pub(crate) fn for_credential(
+        credential: &Credential,
+        versions: Vec<ProtocolVersion>,
+        cipher_suites: Vec<&'static CipherSuite>,
+    ) -> Capabilities {
+        Capabilities {
+            versions,
+            cipher_suites,
+            extensions: Vec::new(),
+            proposals: ProposalType::ALL.to_vec(),
+            credentials: vec![credential.credential_type()],
+        }
+    }
+
+    /// Returns what both this and `other` support. Each list is in this one's order.
+    pub fn intersection(&self, other: &Capabilities) -> Capabilities {
+        fn common<T: Clone + PartialEq>(ours: &[T], theirs: &[T]) -> Vec<T> {
+            ours.iter().filter(|t| theirs.contains(t)).cloned().collect()
+        }
+
+        Capabilities {
+            versions: common(&self.versions, &other.versions),
+            cipher_suites: common(&self.cipher_suites, &other.cipher_suites),
+            extensions: common(&self.extensions, &other.extensions),
+            proposals: common(&self.proposals, &other.proposals),
+            credentials: common(&self.credentials, &other.credentials),
+        }
+    }
+
+    /// Returns whether this includes the given protocol version
+    pub fn supports_version(&self, version: ProtocolVersion) -> bool {
+        self.versions.contains(&version)
+    }
+
+    /// Returns whether this includes the given cipher suite
+    pub fn supports_cipher_suite(&self, cipher_suite: &CipherSuite) -> bool {
+        self.cipher_suites.iter().any(|&cs| cs == cipher_suite)
+    }
+
+    /// Returns whether this includes the given extension type
+    pub fn supports_extension(&self, extension_type: ExtensionType) -> bool {
+        self.extensions.contains(&extension_type)
+    }
+
+    /// Returns whether this includes the given proposal type
+    pub fn supports_proposal(&self, proposal_type: ProposalType) -> bool {
+        self.proposals.contains(&proposal_type)
+    }
+
+    /// Returns whether this includes the given credential type
+    pub fn supports_credential(&self, credential_type: CredentialType) -> bool {
+        self.credentials.contains(&credential_type)
+    }
+}
+
+// struct {
+//     Credential credential;
+//     opaque signature<0..2^16-1>;
+// } CredentialChange;
+/// A credential that the sender of an Update is replacing theirs with, e.g., a renewed certificate
+/// or a renamed identity, along with a signature under the new credential's key over the same
+/// contents as the Update's own signature. That shows the sender has the new key, while the
+/// Update's own signature shows that the member who's changing credentials agrees to it.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct CredentialChange {
+    /// The sender's new credential
+    pub(crate) credential: Credential,
+
+    /// The signature under the new credential's key. This is left raw until it's verified, like
+    /// `UpdateProposal::signature`.
+    pub(crate) signature: Signature,
+}
+
+/// A proposal to replace the sender's leaf public key with a fresh one, their credential, or both.
+/// Either can be rotated without the other, e.g., a member whose identity key never leaves their
+/// hardware can keep it while updating their leaf key, and a member can replace their credential
+/// without giving up the leaf key everyone already encrypts to.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct UpdateProposal {
+    /// The roster index of the member who made this proposal
+    pub(crate) sender_index: u32,
+
+    // optional<DHPublicKey> public_key;
+    /// The sender's new leaf public key, if they're replacing theirs
+    pub(crate) public_key: Option<DhPublicKey>,
+
+    // optional<CredentialChange> new_credential;
+    /// The sender's new credential, if they're replacing theirs
+    pub(crate) new_credential: Option<CredentialChange>,
+
+    /// The sender's signature over the group ID, the epoch this was made in, and the above fields,
+    /// except for the new credential's signature. This isn't upcast with the rest of the proposal,
+    /// since its signature scheme is that of the sender's credential, and only the roster knows
+    /// that.
+    pub(crate) signature: Signature,
+}
+
+// This is what the signatures in an UpdateProposal are computed over. The group ID and epoch are
+// in here so that the proposal can't be replayed in another group or epoch.
+#[derive(TlsSerialize)]
+struct UpdateProposalTbs<'a> {
+    group_id: &'a GroupId,
+    epoch: u32,
+    sender_index: u32,
+    public_key: Option<&'a DhPublicKey>,
+    new_credential: Option<&'a Credential>,
+}
+
+impl UpdateProposal {
+    /// Makes an `UpdateProposal` from the member at `sender_index` of the given group and epoch,
+    /// signed with that member's identity key. If `public_key` is given, the member is replacing
+    /// their leaf key with it. If `new_credential` is given, the member is replacing their
+    /// credential with it, and its identity key signs the proposal as well.
+    ///
+    /// Returns: `Ok(proposal)` on success. If neither is given, returns an
+    /// `Error::ValidationError`. Otherwise, returns whatever signing returns.
+    pub(crate) fn new_signed(
+        identity_key: &SigSecretKey,
+        ss: &SignatureScheme,
+        group_id: &GroupId,
+        epoch: u32,
+        sender_index: u32,
+        public_key: Option<DhPublicKey>,
+        new_credential: Option<(Credential, &SigSecretKey)>,
+    ) -> Result<UpdateProposal, Error> {
+        if public_key.is_none() && new_credential.is_none() {
+            return Err(Error::ValidationError("Update proposal has to replace something"));
+        }
+        let tbs = UpdateProposalTbs {
+            group_id,
+            epoch,
+            sender_index,
+            public_key: public_key.as_ref(),
+            new_credential: new_credential.as_ref().map(|(credential, _)| credential),
+        };
+        let tbs_bytes = tls_ser::serialize_to_bytes(&tbs)?;
+        let signature = ss.sign(identity_key, &tbs_bytes)?;
+        let new_credential = match new_credential {
+            Some((credential, new_identity_key)) => {
+                let new_ss = credential.get_signature_scheme();
+                let signature = new_ss.sign(new_identity_key, &tbs_bytes)?;
+                Some(CredentialChange {
+                    credential,
+                    signature,
+                })
+            }
+            None => None,
+        };
+
+        Ok(UpdateProposal {
+            sender_index,
+            public_key,
+            new_credential,
+            signature,
+        })
+    }
+
+    /// Verifies this proposal's signature under `sender`, which is the credential at
+    /// `sender_index` in the roster of the given group and epoch, and, if there's a new
+    /// credential, the signature under that too
+    ///
+    /// Returns: `Ok(())` on success, `Error::SignatureError` on verification failure, and
+    /// `Error::SerdeError` on some serialization failure.
+    pub(crate) fn verify_sig(
+        &self,
+        group_id: &GroupId,
+        epoch: u32,
+        sender: &Credential,
+    ) -> Result<(), Error> {
+        let tbs = UpdateProposalTbs {
+            group_id,
+            epoch,
+            sender_index: self.sender_index,
+            public_key: self.public_key.as_ref(),
+            new_credential: self.new_credential.as_ref().map(|change| &change.credential),
+        };
+        let tbs_bytes = tls_ser::serialize_to_bytes(&tbs)?;
+        let ss = sender.get_signature_scheme();
+        let signature = Signature::new_from_bytes(ss, &self.signature.as_bytes())?;
+        ss.verify(sender.get_public_key(), &tbs_bytes, &signature)?;
+
+        if let Some(change) = &self.new_credential {
+            let new_ss = change.credential.get_signature_scheme();
+            let signature = Signature::new_from_bytes(new_ss, &change.signature.as_bytes())?;
+            new_ss.verify(change.credential.get_public_key(), &tbs_bytes, &signature)?;
+        }
+        Ok(())
+    }
+}
+
+/// A proposal to remove a member from the group
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct RemoveProposal {
+    /// The roster index of the member to remove
+    pub(crate) removed_roster_index: u32,
+}
+
+/// A proposal that only appears in external commits, i.e., commits made by someone who isn't in
+/// the group yet. It carries what the new member encapsulated to the group's external public key.
+/// This is how the new member and the existing members agree on the init secret of the commit.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct ExternalInitProposal {
+    /// The KEM output of the new member's encapsulation to the group's external public key
+    pub(crate) kem_output: DhPublicKey,
+}
+
+/// A proposal to replace the group's GroupContext extensions with the given ones
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct GroupContextExtensionsProposal {
+    // Extension extensions<0..2^32-1>;
+    /// The group's new extensions. These replace all of the old ones.
+    #[tls(bound = "u32", limit = "extensions")]
+    pub(crate) extensions: Vec<Extension>,
+}
+
+/// A proposal to mix the given PSK into the key schedule of the commit's epoch. Every member has
+/// to have the PSK, or else they can't process the commit.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct PreSharedKeyProposal {
+    /// The PSK to mix in
+    pub(crate) psk: PreSharedKeyId,
+}
+
+/// A proposal to shut this group down and replace it with a new one with the given parameters,
+/// e.g., to move to a new cipher suite or protocol version. Once this is committed, the group can't
+/// change epochs anymore. Its members then make the successor group, which is tied to this one by
+/// the final epoch's resumption PSK.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct ReInitProposal {
+    // opaque group_id<0..255>;
+    /// The ID of the successor group
+    pub(crate) group_id: GroupId,
+
+    /// The protocol version of the successor group
+    pub(crate) protocol_version: ProtocolVersion,
+
+    /// The cipher suite of the successor group
+    pub(crate) cipher_suite: &'static CipherSuite,
+
+    // Extension extensions<0..2^32-1>;
+    /// The GroupContext extensions of the successor group
+    #[tls(bound = "u32", limit = "extensions")]
+    pub(crate) extensions: Vec<Extension>,
+}
+
+/// Enum of possible proposals
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+#[tls(tag = "u8")]
+pub(crate) enum GroupProposal {
+    Add(AddProposal),
+    Update(UpdateProposal),
+    Remove(RemoveProposal),
+    ExternalInit(ExternalInitProposal),
+    GroupContextExtensions(GroupContextExtensionsProposal),
+    ReInit(ReInitProposal),
+    PreSharedKey(PreSharedKeyProposal),
+}
+
+impl GroupProposal {
+    /// Returns the type of this proposal
+    pub(crate) fn proposal_type(&self) -> ProposalType {
+        match self {
+            GroupProposal::Add(_) => ProposalType::ADD,
+            GroupProposal::Update(_) => ProposalType::UPDATE,
+            GroupProposal::Remove(_) => ProposalType::REMOVE,
+            GroupProposal::ExternalInit(_) => ProposalType::EXTERNAL_INIT,
+            GroupProposal::GroupContextExtensions(_) => ProposalType::GROUP_CONTEXT_EXTENSIONS,
+            GroupProposal::ReInit(_) => ProposalType::REINIT,
+            GroupProposal::PreSharedKey(_) => ProposalType::PRE_SHARED_KEY,
+        }
+    }
+}
+
+/// A change to the group that is waiting to be committed. A `Proposal` does nothing by itself.
+/// It takes effect once some member puts it in a commit with
+/// `GroupState::create_and_apply_commit_handshake`, along with any other proposals they've
+/// collected.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Proposal(pub(crate) GroupProposal);
+
+impl Proposal {
+    /// Makes a proposal to add the creator of `init_key` to the group, assuming they have the
+    /// capabilities of `Capabilities::for_init_key`
+    pub fn new_add(init_key: UserInitKey) -> Proposal {
+        let capabilities = Capabilities::for_init_key(&init_key);
+        Proposal::new_add_with_capabilities(init_key, capabilities)
+    }
+
+    /// Makes a proposal to add the creator of `init_key` to the group, who has the given
+    /// capabilities. These usually come from the same place as `init_key`.
+    pub fn new_add_with_capabilities(
+        init_key: UserInitKey,
+        capabilities: Capabilities,
+    ) -> Proposal {
+        Proposal::new_add_with_leaf_extensions(init_key, capabilities, Vec::new())
+    }
+
+    /// Makes a proposal to add the creator of `init_key` to the group, who has the given
+    /// capabilities, and who attaches the given extensions to their leaf. The extensions usually
+    /// come from the same place as `init_key`, and every member can read them with
+    /// `GroupState::member_extensions` once the Add is committed.
+    pub fn new_add_with_leaf_extensions(
+        init_key: UserInitKey,
+        capabilities: Capabilities,
+        leaf_extensions: Vec<Extension>,
+    ) -> Proposal {
+        Proposal(GroupProposal::Add(AddProposal {
+            init_key,
+            capabilities,
+            leaf_extensions: LeafExtensions(leaf_extensions),
+        }))
+    }
+
+    /// Makes a proposal to remove the member at `roster_index` from the group
+    pub fn new_remove(roster_index: u32) -> Proposal {
+        Proposal(GroupProposal::Remove(RemoveProposal {
+            removed_roster_index: roster_index,
+        }))
+    }
+
+    /// Makes a proposal to replace all of the group's GroupContext extensions with `extensions`.
+    /// Every member has to support every one of the new extensions' types, or else the commit
+    /// will be rejected.
+    pub fn new_group_context_extensions(extensions: Vec<Extension>) -> Proposal {
+        Proposal(GroupProposal::GroupContextExtensions(GroupContextExtensionsProposal {
+            extensions,
+        }))
+    }
+
+    /// Makes a proposal to re-initialize the group as a new group with the given ID, protocol
+    /// version, cipher suite, and GroupContext extensions. This has to be the only proposal in its
+    /// commit. The successor group is made with `GroupState::new_reinit_successor`.
+    pub fn new_reinit(
+        group_id: GroupId,
+        protocol_version: ProtocolVersion,
+        cipher_suite: &'static CipherSuite,
+        extensions: Vec<Extension>,
+    ) -> Proposal {
+        Proposal(GroupProposal::ReInit(ReInitProposal {
+            group_id,
+            protocol_version,
+            cipher_suite,
+            extensions,
+        }))
+    }
+
+    /// Makes a proposal to mix the PSK with the given ID into the key schedule when this is
+    /// committed. This is how a committer requires everyone to prove they have the PSK, rather
+    /// than having every member inject it with `GroupState::inject_psks`. Resumption PSKs here
+    /// have to have `ResumptionPskUsage::Application`.
+    pub fn new_psk(psk: PreSharedKeyId) -> Proposal {
+        Proposal(GroupProposal::PreSharedKey(PreSharedKeyProposal {
+            psk,
+        }))
+    }
+}
+
+/// The part of an `ExternalProposal` that its sender signs
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct ExternalProposalContent {
+    // opaque group_id<0..255>;
+    /// The ID of the group this proposal is for
+    pub(crate) group_id: GroupId,
+
+    /// The epoch this proposal is for. Like every proposal, it's only valid in that epoch.
+    pub(crate) epoch: u32,
+
+    /// The index of the sender in the group's external_senders extension
+    pub(crate) sender_index: u32,
+
+    /// The proposal itself
+    pub(crate) proposal: GroupProposal,
+}
+
+/// A proposal from someone who isn't in the group, e.g., a delivery service removing a member on
+/// an admin's behalf. The sender has to be listed in the group's external_senders extension. A
+/// member checks this with `GroupState::process_external_proposal` and then commits it by
+/// reference. Only Adds and Removes can be sent this way.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct ExternalProposal {
+    pub(crate) content: ExternalProposalContent,
+
+    /// The sender's signature over `content`. This isn't upcast with the rest of the
+    /// `ExternalProposal`, since its signature scheme is that of the sender's credential, and only
+    /// the group knows that.
+    pub(crate) signature: Signature,
+}
+
+impl ExternalProposal {
+    /// Signs the given proposal as the external sender at `sender_index` of the group with ID
+    /// `group_id`, at epoch `epoch`
+    ///
+    /// Returns: `Ok(external_proposal)` on success. If `proposal` is neither an Add nor a Remove,
+    /// returns an `Error::ValidationError`.
+    pub fn new(
+        group_id: GroupId,
+        epoch: u32,
+        sender_index: u32,
+        proposal: Proposal,
+        signature_scheme: &'static SignatureScheme,
+        secret_key: &SigSecretKey,
+    ) -> Result<ExternalProposal, Error> {
+        let content = ExternalProposalContent {
+            group_id,
+            epoch,
+            sender_index,
+            proposal: proposal.0,
+        };
+        content.check_kind()?;
+        let signature =
+            signature_scheme.sign(secret_key, &tls_ser::serialize_to_bytes(&content)?)?;
+
+        Ok(ExternalProposal {
+            content,
+            signature,
+        })
+    }
+
+    /// Verifies this proposal's signature under the given credential of its sender
+    ///
+    /// Returns: `Ok(())` on success, and `Error::SignatureError` on verification failure
+    pub(crate) fn verify_sig(&self, sender: &Credential) -> Result<(), Error> {
+        let ss = sender.get_signature_scheme();
+        let signature = Signature::new_from_bytes(ss, &self.signature.as_bytes())?;
+
+        ss.verify(sender.get_public_key(), &tls_ser::serialize_to_bytes(&self.content)?, &signature)
+    }
+}
+
+impl ExternalProposalContent {
+    /// Checks that this is one of the kinds of proposal that external senders can make
+    pub(crate) fn check_kind(&self) -> Result<(), Error> {
+        match self.proposal {
+            GroupProposal::Add(_) | GroupProposal::Remove(_) => Ok(()),
+            _ => Err(Error::ValidationError("External senders can only propose Adds and Removes")),
+        }
+    }
+}
+
+/// The part of a `MemberProposal` that its sender signs
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct MemberProposalContent {
+    // opaque group_id<0..255>;
+    /// The ID of the group this proposal is for
+    pub(crate) group_id: GroupId,
+
+    /// The epoch this proposal is for
+    pub(crate) epoch: u32,
+
+    /// The roster index of the member who made this proposal
+    pub(crate) sender_index: u32,
+
+    /// The proposal itself
+    pub(crate) proposal: GroupProposal,
+}
+
+/// A proposal that a member sends to the group by itself, so that a commit can refer to it by
+/// reference. The sender signs it with their identity key, so that everyone who caches it knows
+/// which member it's from. Make these with `GroupState::create_proposal_message`, and check and
+/// cache them with `GroupState::cache_proposal`.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct MemberProposal {
+    pub(crate) content: MemberProposalContent,
+
+    /// The sender's signature over `content`. This is left raw until it's verified, like
+    /// `ExternalProposal::signature`.
+    pub(crate) signature: Signature,
+}
+
+impl MemberProposal {
+    /// Signs the given proposal with `identity_key` as the member at `sender_index` of the group
+    /// with ID `group_id`, at epoch `epoch`
+    ///
+    /// Returns: `Ok(member_proposal)` on success. Otherwise, returns whatever signing returns.
+    pub(crate) fn new(
+        identity_key: &SigSecretKey,
+        ss: &SignatureScheme,
+        group_id: GroupId,
+        epoch: u32,
+        sender_index: u32,
+        proposal: GroupProposal,
+    ) -> Result<MemberProposal, Error> {
+        let content = MemberProposalContent {
+            group_id,
+            epoch,
+            sender_index,
+            proposal,
+        };
+        let signature = ss.sign(identity_key, &tls_ser::serialize_to_bytes(&content)?)?;
+
+        Ok(MemberProposal {
+            content,
+            signature,
+        })
+    }
+
+    /// Verifies this proposal's signature under `sender`, which is the credential at
+    /// `sender_index` in the roster
+    ///
+    /// Returns: `Ok(())` on success, and `Error::SignatureError` on verification failure
+    pub(crate) fn verify_sig(&self, sender: &Credential) -> Result<(), Error> {
+        let ss = sender.get_signature_scheme();
+        let signature = Signature::new_from_bytes(ss, &self.signature.as_bytes())?;
+
+        ss.verify(sender.get_public_key(), &tls_ser::serialize_to_bytes(&self.content)?, &signature)
+    }
+}
+
+/// Who sent a proposal that was cached so that commits can refer to it. The tags are those of
+/// the `SenderType` in RFC 9420.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TlsDeserialize, TlsSerialize)]
+#[tls(tag = "u8")]
+pub enum ProposalSender {
+    /// The member at this roster index
+    #[tls(discriminant = 1)]
+    Member(u32),
+    /// The sender at this index of the group's external_senders extension
+    #[tls(discriminant = 2)]
+    External(u32),
+}
+
+/// A proposal that was sent to the group by itself, along with who sent it and their signature.
+/// This is the analogue of RFC 9420's `AuthenticatedContent`, and it's what a `ProposalRef` is
+/// the hash of, so two senders making the same proposal get different references.
+#[derive(TlsSerialize)]
+#[tls(tag = "u8")]
+pub(crate) enum AuthenticatedProposal<'a> {
+    #[tls(discriminant = 1)]
+    Member(&'a MemberProposal),
+    #[tls(discriminant = 2)]
+    External(&'a ExternalProposal),
+}
+
+/// The label of the `RefHash` that makes proposal references
+const PROPOSAL_REF_LABEL: &[u8] = b"MLS 1.0 Proposal Reference";
+
+// struct {
+//     opaque label<V>;
+//     opaque value<V>;
+// } RefHashInput;
+/// What's hashed to make a `ProposalRef`
+#[derive(TlsSerialize)]
+struct RefHashInput<'a> {
+    #[tls(bound = "varint")]
+    label: &'a [u8],
+    #[tls(bound = "varint")]
+    value: &'a AuthenticatedProposal<'a>,
+}
+
+/// Identifies a proposal that was sent to the group by itself, so that a commit can refer to it
+/// instead of repeating it. This is the hash of the authenticated proposal, i.e., the proposal
+/// along with its sender and their signature. Get these from `GroupState::cache_proposal` and
+/// `GroupState::process_external_proposal`.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct ProposalRef(Digest);
+
+impl ProposalRef {
+    /// Computes the reference of the given authenticated proposal, i.e.,
+    /// `RefHash("MLS 1.0 Proposal Reference", proposal)`
+    pub(crate) fn new(
+        cs: &'static CipherSuite,
+        proposal: &AuthenticatedProposal,
+    ) -> Result<ProposalRef, Error> {
+        let input = RefHashInput {
+            label: PROPOSAL_REF_LABEL,
+            value: proposal,
+        };
+        Ok(ProposalRef(cs.hash_impl.hash_serializable(&input)?))
+    }
+
+    /// Returns the bytes of this reference
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+// References are hashes of public data, so they don't need constant-time comparison
+impl PartialEq for ProposalRef {
+    fn eq(&self, other: &ProposalRef) -> bool {
+        self.0.as_bytes() == other.0.as_bytes()
+    }
+}
+
+/// A proposal in a commit, which is either the proposal itself or a reference to one that was
+/// sent earlier in the epoch
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+#[tls(tag = "u8")]
+pub(crate) enum ProposalOrRef {
+    Proposal(GroupProposal),
+    Reference(ProposalRef),
+}
+
+/// Operation to apply a list of proposals, and then add entropy to the group
+#[derive(TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct GroupCommit {
+    // ProposalOrRef proposals<0..2^32-1>;
+    /// The proposals to apply. These are applied in the order GroupContextExtensions, then
+    /// Updates, then Removes, then Adds, regardless of the order they appear in here. PSKs are
+    /// mixed into the key schedule in the order they appear in here. A ReInit is always alone.
+    /// Proposals by reference have to be in the `ProposalStore` of whoever processes the commit.
+    #[tls(bound = "u32")]
+    pub(crate) proposals: Vec<ProposalOrRef>,
+
+    // opaque welcome_info_hash<0..255>;
+    /// Contains the hash of the `WelcomeInfo` object of the group before this commit. This is what
+    /// the members added by this commit were sent. The maker of an external commit doesn't know
+    /// the prior init secret, so in that case this is the hash of the `GroupInfo` contents the
+    /// commit was made from.
+    pub(crate) welcome_info_hash: WelcomeInfoHash,
+
+    /// New entropy for the tree, from the committer's leaf. Everyone added by this commit can
+    /// decrypt it. Its path secrets are encrypted with `welcome_info_hash` as the HPKE context, so
+    /// they're tied to the state the commit was made against.
+    pub(crate) path: UpdatePath,
+
+    // opaque parent_hash<0..255>;
+    /// The parent hash that `path` gives the committer's leaf. Everyone who processes the commit
+    /// computes it themselves, and this is signed with the rest of the commit, so the committer
+    /// vouches for the whole parent-hash chain of their path.
+    pub(crate) parent_hash: Digest,
+}
+
+impl GroupCommit {
+    /// Returns this commit's ExternalInit proposal, if it's an external commit. ExternalInits are
+    /// always sent by value.
+    pub(crate) fn external_init(&self) -> Option<&ExternalInitProposal> {
+        self.proposals.iter().find_map(|proposal| match proposal {
+            ProposalOrRef::Proposal(GroupProposal::ExternalInit(external_init)) => {
+                Some(external_init)
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns whether any of this commit's proposals are by reference
+    pub(crate) fn has_references(&self) -> bool {
+        self.proposals.iter().any(|proposal| match proposal {
+            ProposalOrRef::Reference(_) => true,
+            ProposalOrRef::Proposal(_) => false,
+        })
+    }
+}
+
 /// Enum of possible group operations
-#[derive(Deserialize, Serialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
-#[serde(rename = "GroupOperation__enum_u8")]
+#[tls(tag = "u8")]
 pub(crate) enum GroupOperation {
     Init(GroupInit),
     Add(GroupAdd),
     Update(GroupUpdate),
     Remove(GroupRemove),
+    Commit(GroupCommit),
 }
 
-// TODO: Make confirmation a Mac enum for more type safety
+// TODO: Make confirmation_tag a Mac enum for more type safety
 
 /// A `Handshake` message, as defined in section 8 of the MLS spec
-#[derive(Deserialize, Serialize)]
+#[derive(TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub struct Handshake {
     /// This is equal to the epoch of the current `GroupState`
@@ -396,28 +1453,39 @@ pub struct Handshake {
     /// Position of the signer in the roster
     pub(crate) signer_index: u32,
     /// Signature over the `Group`'s history:
-    /// `Handshake.signature = Sign(identity_key, GroupState.transcript_hash)`
+    /// `Handshake.signature = Sign(identity_key, GroupState.confirmed_transcript_hash)`
     pub(crate) signature: Signature,
-    // opaque confirmation<1..255>;
-    /// HMAC over the group state and `Handshake` signature
-    /// `confirmation_data = GroupState.transcript_hash || Handshake.signature`
-    /// `Handshake.confirmation = HMAC(confirmation_key, confirmation_data)`
-    pub(crate) confirmation: Mac,
+    // opaque confirmation_tag<1..255>;
+    /// MAC over the confirmed transcript hash of the epoch the `Handshake` leads to, which proves
+    /// that the signer derived the same confirmation key as the recipient:
+    /// `Handshake.confirmation_tag = MAC(confirmation_key, GroupState.confirmed_transcript_hash)`
+    pub(crate) confirmation_tag: Mac,
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
+        application,
         crypto::{
-            ciphersuite::{CipherSuite, P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM},
-            sig::SignatureScheme,
+            aead::AES128GCM_IMPL,
+            ciphersuite::{
+                CipherSuite, P256_SHA256_AES128GCM, X25519_SHA256_AES128GCM, X448_SHA512_AES256GCM,
+            },
+            dh::P256_IMPL,
+            hash::SHA256,
+            provider::{CryptoProvider, BUILTIN_PROVIDER},
+            sig::{SignatureScheme, ECDSA_P256_IMPL, ED25519_IMPL},
         },
         error::Error,
-        group_state::{GroupState, Welcome, WelcomeInfo},
-        handshake::{Handshake, ProtocolVersion, UserInitKey, MLS_DUMMY_VERSION},
-        ratchet_tree::PathSecret,
+        extensions::{Extension, ExtensionType},
+        group_state::{GroupInfo, GroupState, Welcome, WelcomeInfo},
+        handshake::{
+            Handshake, Proposal, ProposalSender, ProtocolVersion, UserInitKey, MLS_DUMMY_VERSION,
+        },
+        message::MlsMessage,
+        ratchet_tree::{PathSecret, RatchetTreeNode},
         test_utils,
-        tls_de::TlsDeserializer,
+        tls_de::{TlsDeserialize, TlsDeserializer},
         tls_ser,
         upcast::{CryptoCtx, CryptoUpcast},
     };
@@ -427,7 +1495,6 @@ mod test {
 
     use quickcheck_macros::quickcheck;
     use rand::{RngCore, SeedableRng};
-    use serde::Deserialize;
 
     // Check that Update operations are consistent
     #[quickcheck]
@@ -458,6 +1525,537 @@ mod test {
         assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after Update");
     }
 
+    // Check that a member who makes an Update with create_update ends up in the same state as
+    // everyone else once the Update comes back to them
+    #[quickcheck]
+    fn create_update_round_trip(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 2 people, and a copy of it from another perspective
+        let (mut group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let new_index = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, new_index);
+
+        // Making the Update shouldn't change the epoch by itself
+        let old_epoch = group_state1.epoch;
+        let handshake = group_state1.create_update(&mut rng).unwrap();
+        assert_eq!(group_state1.epoch, old_epoch);
+
+        // Both members process the same Handshake, and should agree afterwards
+        let (group_state1, mut app_key_chain1) =
+            group_state1.process_handshake(&handshake).unwrap();
+        let (group_state2, mut app_key_chain2) =
+            group_state2.process_handshake(&handshake).unwrap();
+        assert_eq!(group_state1.epoch, old_epoch + 1);
+        assert!(group_state1.pending_update.is_none());
+        assert_serialized_eq!(group_state1, group_state2, "GroupStates disagree after Update");
+
+        // And their key chains should agree too
+        let app_message = application::encrypt_application_message(
+            b"rotated".to_vec(),
+            &group_state1,
+            &mut app_key_chain1,
+        )
+        .unwrap();
+        let plaintext = application::decrypt_application_message(
+            app_message,
+            &group_state2,
+            &mut app_key_chain2,
+        )
+        .unwrap();
+        assert_eq!(plaintext, b"rotated");
+    }
+
+    // Check that a commit with several kinds of proposals in it is applied the same way by the
+    // committer, the members who stay, and the members who are added, and that the removed member
+    // finds out
+    #[quickcheck]
+    fn commit_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 3 people. Member A commits, member B proposes an
+        // Update, and member C is removed.
+        let (group_state_a, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let index_a = group_state_a.roster_index.unwrap() as usize;
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a],
+            &mut rng,
+        );
+        let index_c = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a, index_b as usize],
+            &mut rng,
+        );
+        let mut group_state_b =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+        let group_state_c = test_utils::change_self_index(&group_state_a, &identity_keys, index_c);
+
+        // Make two new members and Welcome them to the group as it is before the commit
+        let mut new_group_states = Vec::new();
+        let mut proposals = Vec::new();
+        for _ in 0..2 {
+            let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+            let user_init_key_id = {
+                let mut buf = [0u8; 16];
+                rng.fill_bytes(&mut buf);
+                buf.to_vec()
+            };
+            let init_key = UserInitKey::new_from_random(
+                &identity_key,
+                user_init_key_id,
+                credential,
+                vec![&X25519_SHA256_AES128GCM],
+                vec![MLS_DUMMY_VERSION],
+                &mut rng,
+            )
+            .unwrap();
+            let (welcome, _) =
+                Welcome::from_group_state(&group_state_a, &init_key, &mut rng).unwrap();
+            new_group_states
+                .push(GroupState::from_welcome(welcome, identity_key, init_key.clone()).unwrap());
+            proposals.push(Proposal::new_add(init_key));
+        }
+        // Mix the Update and Remove in with the Adds. The order they're applied in shouldn't
+        // depend on this.
+        proposals.insert(1, group_state_b.create_update_proposal(&mut rng).unwrap());
+        proposals.insert(0, Proposal::new_remove(index_c));
+
+        // A commit that removes someone twice, or removes the committer, shouldn't go through
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let bad_proposals = vec![Proposal::new_remove(index_c), Proposal::new_remove(index_c)];
+        assert!(group_state_a
+            .create_and_apply_commit_handshake(bad_proposals, new_path_secret.clone(), &mut rng)
+            .is_err());
+        let bad_proposals = vec![Proposal::new_remove(index_a as u32)];
+        assert!(group_state_a
+            .create_and_apply_commit_handshake(bad_proposals, new_path_secret.clone(), &mut rng)
+            .is_err());
+
+        let (handshake, group_state_a, _) = group_state_a
+            .create_and_apply_commit_handshake(proposals, new_path_secret, &mut rng)
+            .unwrap();
+
+        // Everyone who's still in the group should agree
+        let (group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+        assert_serialized_eq!(group_state_a, group_state_b, "GroupStates disagree after commit");
+        let new_group_states: Vec<GroupState> = new_group_states
+            .into_iter()
+            .map(|gs| gs.process_handshake(&handshake).unwrap().0)
+            .collect();
+        for gs in new_group_states.iter() {
+            assert_serialized_eq!(group_state_a, gs, "New member disagrees after commit");
+        }
+        // One member left and two joined
+        assert_eq!(group_state_a.roster.credential_iter().count(), identity_keys.len() + 1);
+
+        // And the removed member should know they were removed
+        match group_state_c.process_handshake(&handshake) {
+            Err(Error::IAmRemoved) => (),
+            Err(e) => panic!("Removed party didn't give an Error::IAmRemoved, instead got {}", e),
+            Ok(_) => panic!("Removed party was able to process the commit"),
+        }
+
+        // Member B should be able to use their new leaf key, and the new members should be able to
+        // use theirs. An Update from A is encrypted to all of them.
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (update_handshake, group_state_a, _) =
+            group_state_a.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group_state_b, _) = group_state_b.process_handshake(&update_handshake).unwrap();
+        assert_serialized_eq!(group_state_a, group_state_b, "GroupStates disagree after Update");
+        for gs in new_group_states.iter() {
+            let (gs, _) = gs.process_handshake(&update_handshake).unwrap();
+            assert_serialized_eq!(group_state_a, gs, "New member disagrees after Update");
+        }
+    }
+
+    // Check that a commit can refer to a cached proposal by reference, that members who didn't
+    // cache it can't process the commit, and that cached proposals don't outlive their epoch
+    #[quickcheck]
+    fn proposals_by_reference(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 4 people. B proposes an Update, which A, B, and C
+        // cache, and D is removed.
+        let (mut group_state_a, identity_keys) = test_utils::random_full_group_state(4, &mut rng);
+        let index_a = group_state_a.roster_index.unwrap() as usize;
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a],
+            &mut rng,
+        );
+        let index_c = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a, index_b as usize],
+            &mut rng,
+        );
+        let mut group_state_b =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+        let mut group_state_c =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_c);
+
+        let update = group_state_b.create_update_proposal(&mut rng).unwrap();
+        let update = group_state_b.create_proposal_message(update).unwrap();
+        let update_ref = group_state_a.cache_proposal(update.clone()).unwrap();
+        assert_eq!(group_state_b.cache_proposal(update.clone()).unwrap(), update_ref);
+        // The cache knows who sent the Update, and nobody else can pass it off as their own
+        assert_eq!(
+            group_state_a.proposal_sender(&update_ref),
+            Some(ProposalSender::Member(index_b))
+        );
+        let mut forged = update.clone();
+        forged.content.sender_index = index_c;
+        assert!(group_state_c.clone().cache_proposal(forged).is_err());
+        let group_state_c_uncached = group_state_c.clone();
+        group_state_c.cache_proposal(update).unwrap();
+
+        // A commits to the Update by reference, along with a Remove by value
+        let index_d = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a, index_b as usize, index_c as usize],
+            &mut rng,
+        );
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, new_group_state_a, _) = group_state_a
+            .create_and_apply_commit_handshake_with_refs(
+                vec![update_ref.clone()],
+                vec![Proposal::new_remove(index_d)],
+                new_path_secret.clone(),
+                &mut rng,
+            )
+            .unwrap();
+        let (group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+        let (group_state_c, _) = group_state_c.process_handshake(&handshake).unwrap();
+        assert!(group_state_c_uncached.process_handshake(&handshake).is_err());
+        assert_serialized_eq!(new_group_state_a, group_state_b, "B disagrees after commit");
+        assert_serialized_eq!(new_group_state_a, group_state_c, "C disagrees after commit");
+
+        // The cached Update is gone in the new epoch
+        assert!(new_group_state_a
+            .create_and_apply_commit_handshake_with_refs(
+                vec![update_ref],
+                Vec::new(),
+                new_path_secret,
+                &mut rng,
+            )
+            .is_err());
+    }
+
+    // Check that a member can leave by proposing their own removal, that another member can
+    // commit it, and that the leaver finds out they're gone instead of following along
+    #[quickcheck]
+    fn self_remove_commit(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 3 people. B leaves, A commits, and C stays.
+        let (mut group_state_a, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let index_a = group_state_a.roster_index.unwrap() as usize;
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a],
+            &mut rng,
+        );
+        let index_c = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a, index_b as usize],
+            &mut rng,
+        );
+        let group_state_b = test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+        let mut group_state_c =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_c);
+
+        // B can't commit their own departure
+        let leave = group_state_b.create_leave_proposal().unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state_b.cs, &mut rng);
+        assert!(group_state_b
+            .create_and_apply_commit_handshake(vec![leave.clone()], new_path_secret, &mut rng)
+            .is_err());
+
+        // A commits it by reference instead
+        let leave = group_state_b.create_proposal_message(leave).unwrap();
+        let leave_ref = group_state_a.cache_proposal(leave.clone()).unwrap();
+        group_state_c.cache_proposal(leave).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, group_state_a, _) = group_state_a
+            .create_and_apply_commit_handshake_with_refs(
+                vec![leave_ref],
+                Vec::new(),
+                new_path_secret,
+                &mut rng,
+            )
+            .unwrap();
+        assert!(group_state_a.roster.0.get(index_b as usize).map_or(true, Option::is_none));
+
+        let (group_state_c, _) = group_state_c.process_handshake(&handshake).unwrap();
+        assert_serialized_eq!(group_state_a, group_state_c, "C disagrees after B leaves");
+        match group_state_b.process_handshake(&handshake) {
+            Err(Error::IAmRemoved) => (),
+            Err(e) => panic!("Leaver didn't find out they left: {:?}", e),
+            Ok(_) => panic!("Leaver processed their own removal"),
+        }
+    }
+
+    // Check that a commit can change the group's extensions, that it's rejected by any member who
+    // doesn't support them, and that members who join afterwards learn them
+    #[quickcheck]
+    fn group_context_extensions_commit(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 3 people. Members A and B support the new extension,
+        // and member C doesn't.
+        let (mut group_state_a, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let index_a = group_state_a.roster_index.unwrap() as usize;
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a],
+            &mut rng,
+        );
+        let index_c = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a, index_b as usize],
+            &mut rng,
+        );
+        let group_state_c = test_utils::change_self_index(&group_state_a, &identity_keys, index_c);
+        let ext_type = ExtensionType(0xff00);
+        group_state_a.set_supported_extensions(&[ext_type]);
+        let group_state_b = test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+
+        // A can't propose something they don't support themselves, or the same type twice
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let unsupported = Extension::new(ExtensionType(0xff01), b"nope".to_vec());
+        let bad_proposals = vec![Proposal::new_group_context_extensions(vec![unsupported])];
+        assert!(group_state_a
+            .create_and_apply_commit_handshake(bad_proposals, new_path_secret.clone(), &mut rng)
+            .is_err());
+        let extension = Extension::new(ext_type, b"some policy".to_vec());
+        let bad_proposals = vec![Proposal::new_group_context_extensions(vec![
+            extension.clone(),
+            extension.clone(),
+        ])];
+        assert!(group_state_a
+            .create_and_apply_commit_handshake(bad_proposals, new_path_secret.clone(), &mut rng)
+            .is_err());
+
+        let proposals = vec![Proposal::new_group_context_extensions(vec![extension.clone()])];
+        let (handshake, group_state_a, _) = group_state_a
+            .create_and_apply_commit_handshake(proposals, new_path_secret, &mut rng)
+            .unwrap();
+        assert_eq!(group_state_a.extensions(), &[extension.clone()]);
+
+        // B agrees, and C rejects the commit
+        let (group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+        assert_serialized_eq!(group_state_a, group_state_b, "GroupStates disagree after commit");
+        assert!(group_state_c.process_handshake(&handshake).is_err());
+
+        // Someone who's welcomed now gets the extensions along with everything else
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"new member".to_vec(),
+            credential,
+            vec![&X25519_SHA256_AES128GCM],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let (welcome, _) = Welcome::from_group_state(&group_state_a, &init_key, &mut rng).unwrap();
+        let group_state_new = GroupState::from_welcome(welcome, identity_key, init_key).unwrap();
+        assert_eq!(group_state_new.extensions(), &[extension]);
+    }
+
+    // Check that someone holding only a published GroupInfo can join with an external commit, that
+    // the members and the new member agree afterwards, and that stale GroupInfos are useless
+    #[quickcheck]
+    fn external_commit_correctness(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 2 people, and a copy of it from another perspective
+        let (group_state_a, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[group_state_a.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state_b = test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+
+        // Nobody knows the external key pair until they go through an epoch change
+        assert!(group_state_a.group_info().is_err());
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, group_state_a, _) =
+            group_state_a.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+
+        // A publishes a GroupInfo. Send it over the wire.
+        let group_info = {
+            let bytes = tls_ser::serialize_to_bytes(&group_state_a.group_info().unwrap()).unwrap();
+            let mut cursor = bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            let mut group_info = GroupInfo::tls_deserialize(&mut deserializer).unwrap();
+            group_info.upcast_crypto_values(&CryptoCtx::new()).unwrap();
+            group_info
+        };
+        assert_eq!(group_info.epoch(), group_state_a.epoch);
+
+        // Someone new joins from it
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"external joiner".to_vec(),
+            credential,
+            vec![&X25519_SHA256_AES128GCM],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, group_state_new, _) = GroupState::new_from_external_commit(
+            &group_info,
+            identity_key,
+            init_key,
+            new_path_secret,
+            &mut rng,
+        )
+        .unwrap();
+
+        // Everyone should agree on the new state and its secrets
+        let (group_state_a, _) = group_state_a.process_handshake(&handshake).unwrap();
+        let (group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+        assert_serialized_eq!(group_state_a, group_state_new, "New member disagrees with A");
+        assert_serialized_eq!(group_state_b, group_state_new, "New member disagrees with B");
+        assert_eq!(group_state_a.epoch_authenticator(), group_state_new.epoch_authenticator());
+        assert_eq!(group_state_a.roster.credential_iter().count(), identity_keys.len() + 1);
+
+        // The new member is a full member now, so an Update from them goes through
+        let new_path_secret = PathSecret::new_from_random(group_state_new.cs, &mut rng);
+        let (update_handshake, group_state_new, _) =
+            group_state_new.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let (group_state_a, _) = group_state_a.process_handshake(&update_handshake).unwrap();
+        assert_serialized_eq!(group_state_a, group_state_new, "GroupStates disagree after Update");
+
+        // The old GroupInfo is from two epochs ago, so an external commit made from it is rejected
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"late joiner".to_vec(),
+            credential,
+            vec![&X25519_SHA256_AES128GCM],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (stale_handshake, _, _) = GroupState::new_from_external_commit(
+            &group_info,
+            identity_key,
+            init_key,
+            new_path_secret,
+            &mut rng,
+        )
+        .unwrap();
+        assert!(group_state_a.process_handshake(&stale_handshake).is_err());
+    }
+
+    // Check that extensions of types nobody here knows survive a round trip through the wire
+    // byte for byte, so that the signatures over them still verify
+    #[quickcheck]
+    fn unknown_extensions_roundtrip(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let mut unknown_extension = || {
+            let mut data = vec![0u8; 1 + (rng.next_u32() % 64) as usize];
+            rng.fill_bytes(&mut data);
+            Extension::new(ExtensionType(0xff00 | (rng.next_u32() as u16 & 0xff)), data)
+        };
+        let extensions = vec![unknown_extension(), unknown_extension()];
+        let mut group_extension = unknown_extension();
+        // Extension types in a list have to be distinct
+        group_extension.extension_type = ExtensionType(0xfe00);
+
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"unknown extensions".to_vec(),
+            credential,
+            vec![&X25519_SHA256_AES128GCM],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        // Random types might collide, and that's caught before anything is signed
+        let init_key = match init_key.with_extensions(&identity_key, extensions.clone()) {
+            Ok(init_key) => init_key,
+            Err(_) => return,
+        };
+        let bytes = MlsMessage::from(init_key).to_bytes().unwrap();
+        match MlsMessage::from_bytes(&bytes).unwrap() {
+            MlsMessage::KeyPackage(received) => {
+                received.verify_sig().unwrap();
+                assert_eq!(received.extensions(), extensions.as_slice());
+                assert_eq!(MlsMessage::from(received).to_bytes().unwrap(), bytes);
+            }
+            _ => panic!("UserInitKey came back as something else"),
+        }
+
+        let (group_state, _) = test_utils::random_full_group_state(2, &mut rng);
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (_, mut group_state, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        group_state.extensions.push(group_extension);
+        let bytes = MlsMessage::from(group_state.group_info().unwrap()).to_bytes().unwrap();
+        match MlsMessage::from_bytes(&bytes).unwrap() {
+            MlsMessage::GroupInfo(received) => {
+                received.verify().unwrap();
+                assert_eq!(received.extensions(), group_state.extensions());
+                assert_eq!(MlsMessage::from(received).to_bytes().unwrap(), bytes);
+            }
+            _ => panic!("GroupInfo came back as something else"),
+        }
+    }
+
+    // Check that a published GroupInfo describes the group it came from, and that tampering with
+    // any part of it is caught on the consumer's side
+    #[quickcheck]
+    fn group_info_verification(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(2, &mut rng);
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (_, group_state, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+
+        let group_info = group_state.group_info().unwrap();
+        group_info.verify().unwrap();
+        assert_eq!(group_info.group_id(), group_state.group_id());
+        assert_eq!(group_info.epoch(), group_state.epoch);
+        assert_eq!(
+            group_info.confirmed_transcript_hash(),
+            group_state.confirmed_transcript_hash.as_bytes()
+        );
+        assert_eq!(
+            group_info.interim_transcript_hash(),
+            group_state.interim_transcript_hash.as_bytes()
+        );
+        assert_eq!(group_info.extensions(), group_state.extensions());
+        assert_eq!(Some(group_info.signer_index()), group_state.roster_index);
+
+        let mut tampered = group_info.clone();
+        tampered.content.epoch += 1;
+        assert!(tampered.verify().is_err());
+
+        // Move the signature to another member
+        let mut tampered = group_info.clone();
+        tampered.signer_index = group_state
+            .roster
+            .0
+            .iter()
+            .enumerate()
+            .position(|(i, c)| c.is_some() && Some(i as u32) != group_state.roster_index)
+            .unwrap() as u32;
+        assert!(tampered.verify().is_err());
+
+        // Swap out a node of the tree
+        let mut tampered = group_info.clone();
+        let filled_idx = tampered.content.tree.nodes.iter().position(|n| n.is_filled()).unwrap();
+        tampered.content.tree.nodes.set(filled_idx, RatchetTreeNode::Blank);
+        assert!(tampered.verify().is_err());
+    }
+
     // Check that Remove operations are consistent
     #[quickcheck]
     fn remove_correctness(rng_seed: u64) {
@@ -658,6 +2256,8 @@ mod test {
                     .unwrap();
             group_state1.tree.propagate_blank(new_tree_index);
             group_state1.roster.0[new_roster_index] = None;
+            group_state1.leaves[new_roster_index] = None;
+            group_state1.tree_hash = group_state1.tree.tree_hash(group_state1.cs).unwrap();
         }
 
         // Make the data necessary for a Welcome message
@@ -763,7 +2363,33 @@ mod test {
     // * The test cases for any supported ciphersuites should parse successfully
     // * All of the above parsed values should survive a marshal / unmarshal round-trip
 
-    #[derive(Debug, Deserialize, Serialize)]
+    // The test vectors predate the cipher suite registry, and give P256_SHA256_AES128GCM the ID
+    // 0x0000. This provider knows the two suites the vectors use, with that ID for P-256, and their
+    // signature schemes. The built-in provider has neither P-256 suite nor ECDSA-P256.
+    static DRAFT_P256_SHA256_AES128GCM: CipherSuite =
+        CipherSuite::new("P256_SHA256_AES128GCM", 0x0000, &P256_IMPL, &AES128GCM_IMPL, SHA256);
+
+    struct DraftProvider;
+
+    impl CryptoProvider for DraftProvider {
+        fn name(&self) -> &'static str {
+            "draft"
+        }
+
+        fn cipher_suites(&self) -> &'static [&'static CipherSuite] {
+            &[&DRAFT_P256_SHA256_AES128GCM, &X25519_SHA256_AES128GCM]
+        }
+
+        fn signature_schemes(&self) -> &'static [&'static SignatureScheme] {
+            &[&ECDSA_P256_IMPL, &ED25519_IMPL]
+        }
+
+        fn fill_random(&self, buf: &mut [u8]) -> Result<(), Error> {
+            BUILTIN_PROVIDER.fill_random(buf)
+        }
+    }
+
+    #[derive(Debug, TlsDeserialize, TlsSerialize)]
     struct MessagesCase {
         cipher_suite: &'static CipherSuite,
         signature_scheme: &'static SignatureScheme,
@@ -796,22 +2422,22 @@ mod test {
         }
     }
 
-    #[derive(Debug, Deserialize, Serialize)]
+    #[derive(Debug, TlsDeserialize, TlsSerialize)]
     struct MessagesTestVectors {
         epoch: u32,
         signer_index: u32,
         removed: u32,
-        #[serde(rename = "user_id__bound_u8")]
+        #[tls(bound = "u8")]
         user_id: Vec<u8>,
-        #[serde(rename = "group_id__bound_u8")]
+        #[tls(bound = "u8")]
         group_id: Vec<u8>,
-        #[serde(rename = "uik_id__bound_u8")]
+        #[tls(bound = "u8")]
         uik_id: Vec<u8>,
-        #[serde(rename = "dh_seed__bound_u8")]
+        #[tls(bound = "u8")]
         dh_seed: Vec<u8>,
-        #[serde(rename = "sig_seed__bound_u8")]
+        #[tls(bound = "u8")]
         sig_seed: Vec<u8>,
-        #[serde(rename = "random__bound_u8")]
+        #[tls(bound = "u8")]
         random: Vec<u8>,
         uik_all_scheme: &'static SignatureScheme,
         _user_init_key_all_len: u32,
@@ -836,6 +2462,44 @@ mod test {
         }
     }
 
+    // Check that the negotiated version is the one every UserInitKey supports under the cipher
+    // suite, and that UserInitKeys that disagree, or don't support the cipher suite, are rejected
+    #[quickcheck]
+    fn version_negotiation(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs = &X25519_SHA256_AES128GCM;
+        let other_version = ProtocolVersion(MLS_DUMMY_VERSION.0.wrapping_add(1));
+        let mut init_key = |cipher_suite: &'static CipherSuite, version: ProtocolVersion| {
+            let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+            UserInitKey::new_from_random(
+                &identity_key,
+                b"version".to_vec(),
+                credential,
+                vec![cipher_suite],
+                vec![version],
+                &mut rng,
+            )
+            .unwrap()
+        };
+        let ours = vec![init_key(cs, MLS_DUMMY_VERSION), init_key(cs, MLS_DUMMY_VERSION)];
+        let theirs = init_key(cs, other_version);
+        let elsewhere = init_key(&X448_SHA512_AES256GCM, MLS_DUMMY_VERSION);
+
+        assert_eq!(ProtocolVersion::negotiate(cs, &[]).unwrap(), ProtocolVersion::SUPPORTED[0]);
+        assert_eq!(ProtocolVersion::negotiate(cs, &ours).unwrap(), MLS_DUMMY_VERSION);
+        match ProtocolVersion::negotiate(cs, &[ours[0].clone(), theirs.clone()]) {
+            Err(Error::ProtocolVersionMismatch(expected, found)) => {
+                assert_eq!((expected, found), (MLS_DUMMY_VERSION, other_version))
+            }
+            _ => panic!("UserInitKeys with different versions were negotiated with"),
+        }
+        match ProtocolVersion::negotiate(cs, &[theirs]) {
+            Err(Error::ProtocolVersionMismatch(..)) => (),
+            _ => panic!("a version this crate doesn't speak was negotiated"),
+        }
+        assert!(ProtocolVersion::negotiate(cs, &[elsewhere]).is_err());
+    }
+
     // Tests our code against the official key schedule test vector. All this has to do is make
     // sure that the given test vector parses without error, and that the bytes are the same after
     // being reserialized
@@ -850,8 +2514,9 @@ mod test {
         // Deserialize the file's contents
         let test_vec = {
             let mut cursor = original_bytes.as_slice();
-            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
-            let raw = MessagesTestVectors::deserialize(&mut deserializer).unwrap();
+            let mut deserializer =
+                TlsDeserializer::from_reader(&mut cursor).with_provider(&DraftProvider);
+            let raw = MessagesTestVectors::tls_deserialize(&mut deserializer).unwrap();
             // We can't do the upcasting here. The documentation lied when it said that
             // UserInitKeys are validly signed. They are [0xd6; 32], which is not a valid Ed25519
             // signature. So skip this step and call it a mission success.
@@ -0,0 +1,309 @@
+//! Defines the checks that `GroupState::verify_integrity` makes of the parts of a group's state
+//! that have to agree with each other, and `IntegrityReport`, which says which of them failed.
+//! This is meant for state that could have been corrupted or tampered with since it was made,
+//! e.g., state that was just read back from storage, or that has a tree from an untrusted source.
+
+use crate::{
+    error::Error,
+    extensions::{self, RequiredCapabilities},
+    group_state::GroupState,
+};
+
+use subtle::ConstantTimeEq;
+
+/// The checks that `GroupState::verify_integrity` makes, in the order they're made
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IntegrityCheck {
+    /// The tree has an odd number of nodes, so it's a left-balanced binary tree. The checks that
+    /// look at the tree are skipped if this fails.
+    TreeShape,
+    /// The roster and the leaf info have an entry for every leaf of the tree, and a roster entry
+    /// is filled iff its leaf info and its leaf node are
+    RosterMatchesTree,
+    /// This member is in the roster and knows the private key of their leaf, or, if the group is
+    /// preliminary, has the `UserInitKey` they were welcomed with
+    OwnLeafKnown,
+    /// Looking up a member by identity finds the one the roster says it should
+    MemberIndexMatchesRoster,
+    /// The unmerged leaves of every node are filled leaves below it, and none is repeated
+    UnmergedLeavesValid,
+    /// The key of every filled parent node was set by a path update through one of its children
+    ParentHashesVerify,
+    /// The tree's hash is the group's tree hash. That's what the epoch's secrets were derived
+    /// with, so a tree that doesn't match isn't the one the rest of the group has.
+    TreeHashMatches,
+    /// The confirmed and interim transcript hashes are the size of the cipher suite's digests.
+    /// They can't be recomputed without the group's history, so this is all that can be checked.
+    TranscriptHashWellFormed,
+    /// None of this group's resumption PSKs is from an epoch after the current one
+    EpochConsistent,
+    /// No two GroupContext extensions have the same type, and every member has the capabilities
+    /// that the group's required_capabilities extension requires
+    ExtensionsConsistent,
+}
+
+/// What `GroupState::verify_integrity` found. Every check is made even if an earlier one failed,
+/// besides the tree checks after a failed `IntegrityCheck::TreeShape`.
+#[derive(Debug)]
+pub struct IntegrityReport {
+    /// Whether the checks that look at the tree were made
+    made_tree_checks: bool,
+    failures: Vec<(IntegrityCheck, Error)>,
+}
+
+impl IntegrityReport {
+    /// Returns whether every check passed
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Returns whether the given check was made and passed
+    pub fn passed(&self, check: IntegrityCheck) -> bool {
+        let skipped = is_tree_check(check) && !self.made_tree_checks;
+        !skipped && self.failures.iter().all(|(failed, _)| *failed != check)
+    }
+
+    /// Returns the checks that failed, in the order they were made, along with the error that
+    /// failed each of them
+    pub fn failures(&self) -> impl Iterator<Item = (IntegrityCheck, &Error)> + '_ {
+        self.failures.iter().map(|(check, err)| (*check, err))
+    }
+}
+
+/// Returns whether `check` looks at the tree, and is thus skipped if the tree is malformed
+fn is_tree_check(check: IntegrityCheck) -> bool {
+    match check {
+        IntegrityCheck::RosterMatchesTree
+        | IntegrityCheck::OwnLeafKnown
+        | IntegrityCheck::UnmergedLeavesValid
+        | IntegrityCheck::ParentHashesVerify
+        | IntegrityCheck::TreeHashMatches => true,
+        _ => false,
+    }
+}
+
+/// Makes every check of `IntegrityCheck` on the given state
+pub(crate) fn check_group_state(state: &GroupState) -> IntegrityReport {
+    let made_tree_checks = check_tree_shape(state).is_ok();
+    let checks: [(IntegrityCheck, fn(&GroupState) -> Result<(), Error>); 10] = [
+        (IntegrityCheck::TreeShape, check_tree_shape),
+        (IntegrityCheck::RosterMatchesTree, check_roster_matches_tree),
+        (IntegrityCheck::OwnLeafKnown, check_own_leaf_known),
+        (IntegrityCheck::MemberIndexMatchesRoster, check_member_index),
+        (IntegrityCheck::UnmergedLeavesValid, check_unmerged_leaves),
+        (IntegrityCheck::ParentHashesVerify, check_parent_hashes),
+        (IntegrityCheck::TreeHashMatches, check_tree_hash),
+        (IntegrityCheck::TranscriptHashWellFormed, check_transcript_hash),
+        (IntegrityCheck::EpochConsistent, check_epoch),
+        (IntegrityCheck::ExtensionsConsistent, check_extensions),
+    ];
+
+    let failures = checks
+        .iter()
+        .filter(|(check, _)| made_tree_checks || !is_tree_check(*check))
+        .filter_map(|(check, f)| f(state).err().map(|err| (*check, err)))
+        .collect();
+    IntegrityReport {
+        made_tree_checks,
+        failures,
+    }
+}
+
+/// Checks `IntegrityCheck::TreeShape`
+fn check_tree_shape(state: &GroupState) -> Result<(), Error> {
+    // This covers the empty tree too
+    if state.tree.size() % 2 == 1 {
+        Ok(())
+    } else {
+        Err(Error::TreeError("Tree doesn't have an odd number of nodes"))
+    }
+}
+
+/// Checks `IntegrityCheck::RosterMatchesTree`
+fn check_roster_matches_tree(state: &GroupState) -> Result<(), Error> {
+    if state.roster.len() != state.tree.num_leaves() {
+        return Err(Error::ValidationError("Roster doesn't have an entry for every leaf"));
+    }
+    if state.leaves.len() != state.roster.len() {
+        return Err(Error::ValidationError("Leaf info doesn't have an entry for every leaf"));
+    }
+
+    for (i, (entry, leaf)) in state.roster.0.iter().zip(state.leaves.iter()).enumerate() {
+        // The nth leaf node is at position 2n
+        let node_filled = state.tree.get(2 * i).map_or(false, |node| node.is_filled());
+        if entry.is_some() != node_filled || entry.is_some() != leaf.is_some() {
+            return Err(Error::ValidationError(
+                "Roster entry, leaf info, and leaf node don't agree on whether there's a member",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks `IntegrityCheck::OwnLeafKnown`
+fn check_own_leaf_known(state: &GroupState) -> Result<(), Error> {
+    let roster_index = match state.roster_index {
+        Some(idx) => idx,
+        None if state.initializing_user_init_key.is_some() => return Ok(()),
+        None => {
+            return Err(Error::ValidationError(
+                "Group has neither a roster index nor an initializing UserInitKey",
+            ))
+        }
+    };
+
+    let tree_idx = GroupState::roster_index_to_tree_index(roster_index)?;
+    let in_roster = state.roster.0.get(roster_index as usize).map_or(false, Option::is_some);
+    let knows_key = state.tree.get(tree_idx).and_then(|node| node.get_private_key()).is_some();
+    if !in_roster || !knows_key {
+        return Err(Error::ValidationError(
+            "This member's roster entry is empty, or they don't know their leaf's private key",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks `IntegrityCheck::MemberIndexMatchesRoster`
+fn check_member_index(state: &GroupState) -> Result<(), Error> {
+    if state.member_index.agrees_with(&state.roster) {
+        Ok(())
+    } else {
+        Err(Error::ValidationError("Member index doesn't agree with the roster"))
+    }
+}
+
+/// Checks `IntegrityCheck::UnmergedLeavesValid`
+fn check_unmerged_leaves(state: &GroupState) -> Result<(), Error> {
+    // Setting the unmerged leaves is where they're checked
+    let mut tree = state.tree.clone();
+    tree.set_unmerged_leaves(state.tree.unmerged_leaves())
+}
+
+/// Checks `IntegrityCheck::ParentHashesVerify`
+fn check_parent_hashes(state: &GroupState) -> Result<(), Error> {
+    state.tree.clone().verify_parent_hashes(state.cs)
+}
+
+/// Checks `IntegrityCheck::TreeHashMatches`
+fn check_tree_hash(state: &GroupState) -> Result<(), Error> {
+    let tree_hash = state.tree.clone().tree_hash(state.cs)?;
+    if bool::from(tree_hash.ct_eq(&state.tree_hash)) {
+        Ok(())
+    } else {
+        Err(Error::ValidationError("Tree's hash isn't the group's tree hash"))
+    }
+}
+
+/// Checks `IntegrityCheck::TranscriptHashWellFormed`
+fn check_transcript_hash(state: &GroupState) -> Result<(), Error> {
+    let digest_size = state.cs.hash_impl.digest_size();
+    if state.confirmed_transcript_hash.as_bytes().len() == digest_size
+        && state.interim_transcript_hash.as_bytes().len() == digest_size
+    {
+        Ok(())
+    } else {
+        Err(Error::ValidationError("Transcript hash isn't the size of the cipher suite's digests"))
+    }
+}
+
+/// Checks `IntegrityCheck::EpochConsistent`
+fn check_epoch(state: &GroupState) -> Result<(), Error> {
+    let from_future = state
+        .resumption_psks
+        .iter()
+        .any(|psk| psk.group_id == state.group_id && psk.epoch > state.epoch);
+    if from_future {
+        Err(Error::ValidationError(
+            "Group has a resumption PSK from an epoch after the current one",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks `IntegrityCheck::ExtensionsConsistent`
+fn check_extensions(state: &GroupState) -> Result<(), Error> {
+    if extensions::has_duplicate_types(&state.extensions) {
+        return Err(Error::ValidationError("Group has two extensions of the same type"));
+    }
+
+    if let Some(required) =
+        RequiredCapabilities::from_extensions(&state.extensions, state.decode_limits)?
+    {
+        if !state.members().all(|member| required.is_satisfied_by(member.capabilities())) {
+            return Err(Error::ValidationError(
+                "A member lacks the capabilities the group requires",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{crypto::hash::Digest, ratchet_tree::PathSecret, test_utils};
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Returns the checks that the report says failed
+    fn failed_checks(report: &IntegrityReport) -> Vec<IntegrityCheck> {
+        report.failures().map(|(check, _)| check).collect()
+    }
+
+    // Check that a group's state passes every check before and after an epoch change, and that
+    // corrupting each part of it fails the checks that cover that part
+    #[quickcheck]
+    fn integrity_report(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(2, &mut rng);
+        assert!(group_state.verify_integrity().is_ok());
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (_, group_state, _) =
+            group_state.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let report = group_state.verify_integrity();
+        assert!(report.is_ok());
+        assert!(report.passed(IntegrityCheck::TreeHashMatches));
+
+        // A tree hash that isn't the tree's
+        let mut state = group_state.clone();
+        state.tree_hash = Digest::new_from_zeros(state.cs.hash_impl);
+        assert_eq!(failed_checks(&state.verify_integrity()), [IntegrityCheck::TreeHashMatches]);
+
+        // Transcript hashes of the wrong size
+        let mut state = group_state.clone();
+        state.confirmed_transcript_hash = Digest::default();
+        let failed = failed_checks(&state.verify_integrity());
+        assert_eq!(failed, [IntegrityCheck::TranscriptHashWellFormed]);
+        let mut state = group_state.clone();
+        state.interim_transcript_hash = Digest::default();
+        let failed = failed_checks(&state.verify_integrity());
+        assert_eq!(failed, [IntegrityCheck::TranscriptHashWellFormed]);
+
+        // An epoch that's before the group's newest resumption PSK
+        let mut state = group_state.clone();
+        state.epoch -= 1;
+        assert_eq!(failed_checks(&state.verify_integrity()), [IntegrityCheck::EpochConsistent]);
+
+        // A roster entry that's emptied without its leaf, leaf info, or member index entry
+        let mut state = group_state.clone();
+        let other_index = (0..state.roster.len()).find(|&i| Some(i as u32) != state.roster_index);
+        state.roster.0[other_index.unwrap()] = None;
+        let failed = failed_checks(&state.verify_integrity());
+        assert!(failed.contains(&IntegrityCheck::RosterMatchesTree));
+        assert!(failed.contains(&IntegrityCheck::MemberIndexMatchesRoster));
+
+        // A tree that's malformed skips the other tree checks, but not the rest
+        let mut state = group_state;
+        state.tree.nodes.truncate(state.tree.size() - 1);
+        state.tree_hash = Digest::new_from_zeros(state.cs.hash_impl);
+        let report = state.verify_integrity();
+        assert_eq!(failed_checks(&report), [IntegrityCheck::TreeShape]);
+        assert!(!report.passed(IntegrityCheck::TreeHashMatches));
+        assert!(report.passed(IntegrityCheck::MemberIndexMatchesRoster));
+    }
+}
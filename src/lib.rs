@@ -6,7 +6,15 @@
 #[cfg(any(target_pointer_width = "16", target_pointer_width = "8"))]
 compile_error!("Molasses requires that the architecture's pointer width be at least 32 bits");
 
-// Can't make this work using edition 2018 syntax yet
+// Something has to implement the symmetric primitives
+#[cfg(not(any(feature = "ring", feature = "rustcrypto")))]
+compile_error!("Molasses requires either the \"ring\" or the \"rustcrypto\" feature");
+
+// Can't make this work using edition 2018 syntax yet. The wire types derive the codec traits in
+// tls_ser and tls_de, and only snapshots use serde's derives.
+#[macro_use]
+extern crate molasses_derive;
+#[cfg(feature = "snapshots")]
 #[macro_use]
 extern crate serde;
 
@@ -19,14 +27,36 @@ mod utils;
 mod test_utils;
 
 pub mod application;
+pub mod authentication;
+pub mod client;
 mod codec;
 pub mod credential;
 pub mod crypto;
 pub mod error;
+pub mod extensions;
+mod fragment;
+pub mod framing;
+pub mod group_id;
 pub mod group_state;
 pub mod handshake;
+pub mod integrity;
+pub mod member;
+pub mod message;
+mod node_store;
+pub mod pem;
+mod proposal_store;
+pub mod psk;
 pub mod ratchet_tree;
+mod replay;
+pub mod revocation;
+pub mod secret_tree;
+pub mod sframe;
+#[cfg(feature = "snapshots")]
+pub mod snapshot;
+pub mod time;
 pub mod tls_de;
 pub mod tls_ser;
 mod tree_math;
 pub mod upcast;
+pub mod validation;
+pub mod x509;
@@ -0,0 +1,293 @@
+//! Defines `Member`, which is how a `GroupState` describes one of its members to the application,
+//! `User`, which is every member that shares an identity, i.e., every device of one user,
+//! `RosterChange` and `UserChange`, which are how it describes what a handshake did to its members
+//! and users, `LeafInfo`, which is what the group keeps track of about each member's leaf besides
+//! their credential and public key, and `MemberIndex`, which is how members are looked up
+
+use crate::{
+    credential::{Credential, Roster},
+    crypto::ciphersuite::CipherSuite,
+    extensions::{Extension, LeafExtensions, Lifetime},
+    handshake::{Capabilities, ProtocolVersion},
+};
+
+use std::collections::{BTreeSet, HashMap};
+
+// struct {
+//     Capabilities capabilities;
+//     Extension extensions<0..2^32-1>;
+//     uint32 last_update_epoch;
+//     optional<Lifetime> key_lifetime;
+// } LeafInfo;
+/// What the group knows about a member's leaf besides their credential and public key. Everyone
+/// in the group agrees on this, so it's sent to new members along with the roster.
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub(crate) struct LeafInfo {
+    /// What the member said they support when they were added
+    pub(crate) capabilities: Capabilities,
+
+    /// The extensions the member attached to their leaf when they were added
+    pub(crate) extensions: LeafExtensions,
+
+    /// The first epoch in which the member's leaf had its current key, i.e., the epoch that the
+    /// member was added in, or the last one they updated their leaf in
+    pub(crate) last_update_epoch: u32,
+
+    /// The lifetime that the member attached to their leaf when they were added, or else the one
+    /// their `UserInitKey` was signed with. This is `None` if there's neither, or once they've
+    /// updated their leaf, since the new key isn't bound by it.
+    pub(crate) key_lifetime: Option<Lifetime>,
+}
+
+impl LeafInfo {
+    /// Makes the leaf info of a member about whom nothing is known besides their credential and
+    /// that they're in a group of the given version and cipher suite, and who got their current
+    /// key in `epoch`. This is what's assumed of the members of a `GroupState` made from parts,
+    /// and of members added by a `GroupAdd`.
+    pub(crate) fn for_credential(
+        credential: &Credential,
+        version: ProtocolVersion,
+        cs: &'static CipherSuite,
+        epoch: u32,
+    ) -> LeafInfo {
+        LeafInfo {
+            capabilities: Capabilities::for_credential(credential, vec![version], vec![cs]),
+            extensions: LeafExtensions::default(),
+            last_update_epoch: epoch,
+            key_lifetime: None,
+        }
+    }
+
+    /// Returns the leaf info of every entry of `roster`, as made by `for_credential`. Blank
+    /// entries have none.
+    pub(crate) fn for_roster(
+        roster: &Roster,
+        version: ProtocolVersion,
+        cs: &'static CipherSuite,
+        epoch: u32,
+    ) -> Vec<Option<LeafInfo>> {
+        roster
+            .0
+            .iter()
+            .map(|entry| {
+                entry.as_ref().map(|cred| LeafInfo::for_credential(cred, version, cs, epoch))
+            })
+            .collect()
+    }
+}
+
+/// Maps from what members are looked up by to their roster indices, so that lookups don't scan the
+/// roster. A `GroupState` keeps this in step with its roster on every Add, Update, and Remove.
+/// This is local bookkeeping, so it's never sent.
+#[derive(Clone, Default)]
+pub(crate) struct MemberIndex {
+    /// The roster indices of the members with each identity. There's more than one if the user is
+    /// in the group on several devices, i.e., with several leaves whose credentials share an
+    /// identity but not a signature key.
+    by_identity: HashMap<Vec<u8>, BTreeSet<u32>>,
+
+    /// The roster index of the member whose leaf came from the `UserInitKey` with each hash
+    by_key_package_hash: HashMap<Vec<u8>, u32>,
+
+    /// The identity of the member at each roster index, and the hash of the `UserInitKey` their
+    /// leaf came from, if this member knows it
+    by_roster_index: HashMap<u32, (Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl MemberIndex {
+    /// Makes an index of every member in `roster`. The `UserInitKey`s they were added with aren't
+    /// known, so none of them can be looked up by their hash.
+    pub(crate) fn for_roster(roster: &Roster) -> MemberIndex {
+        let mut index = MemberIndex::default();
+        for (roster_index, entry) in roster.0.iter().enumerate() {
+            if let Some(credential) = entry {
+                // The roster length fits in a u32, since it is serialized with a u32 length
+                index.insert(roster_index as u32, credential, None);
+            }
+        }
+        index
+    }
+
+    /// Records that the member at `roster_index` has the given credential, and, if it's given, a
+    /// leaf that came from the `UserInitKey` with hash `key_package_hash`. This replaces whatever
+    /// was recorded for `roster_index` before.
+    pub(crate) fn insert(
+        &mut self,
+        roster_index: u32,
+        credential: &Credential,
+        key_package_hash: Option<&[u8]>,
+    ) {
+        self.remove(roster_index);
+        let identity = credential.get_identity().as_bytes().to_vec();
+        self.by_identity.entry(identity.clone()).or_default().insert(roster_index);
+        if let Some(hash) = key_package_hash {
+            self.by_key_package_hash.insert(hash.to_vec(), roster_index);
+        }
+        self.by_roster_index.insert(roster_index, (identity, key_package_hash.map(<[u8]>::to_vec)));
+    }
+
+    /// Forgets the member at `roster_index`. This does nothing if there's none.
+    pub(crate) fn remove(&mut self, roster_index: u32) {
+        let (identity, key_package_hash) = match self.by_roster_index.remove(&roster_index) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if let Some(indices) = self.by_identity.get_mut(&identity) {
+            indices.remove(&roster_index);
+            if indices.is_empty() {
+                self.by_identity.remove(&identity);
+            }
+        }
+        if let Some(hash) = key_package_hash {
+            self.forget_hash(&hash, roster_index);
+        }
+    }
+
+    /// Forgets the `UserInitKey` hash of the member at `roster_index`. This is called when they get
+    /// a new leaf key, since their leaf no longer comes from that `UserInitKey`.
+    pub(crate) fn forget_key_package_hash(&mut self, roster_index: u32) {
+        if let Some((_, key_package_hash)) = self.by_roster_index.get_mut(&roster_index) {
+            if let Some(hash) = key_package_hash.take() {
+                self.forget_hash(&hash, roster_index);
+            }
+        }
+    }
+
+    /// Drops `key_package_hash` from the map if it's the one of the member at `roster_index`, and
+    /// not of someone who was later added with the same `UserInitKey`
+    fn forget_hash(&mut self, key_package_hash: &[u8], roster_index: u32) {
+        if self.by_key_package_hash.get(key_package_hash) == Some(&roster_index) {
+            self.by_key_package_hash.remove(key_package_hash);
+        }
+    }
+
+    /// Returns whether this is the index that `for_roster` would make of `roster`, apart from the
+    /// `UserInitKey` hashes, which only have to belong to members that are in it
+    pub(crate) fn agrees_with(&self, roster: &Roster) -> bool {
+        let fresh = MemberIndex::for_roster(roster);
+        let same_identities = fresh.by_identity == self.by_identity
+            && fresh.by_roster_index.len() == self.by_roster_index.len()
+            && fresh.by_roster_index.iter().all(|(i, (identity, _))| {
+                self.by_roster_index.get(i).map_or(false, |(own, _)| own == identity)
+            });
+        let hashes_belong = self.by_key_package_hash.iter().all(|(hash, i)| {
+            self.by_roster_index.get(i).map_or(false, |(_, own)| own.as_ref() == Some(hash))
+        });
+        same_identities && hashes_belong
+    }
+
+    /// Returns the roster index of the member with the given identity. If there's more than one,
+    /// this is the lowest.
+    pub(crate) fn roster_index_of_identity(&self, identity: &[u8]) -> Option<u32> {
+        self.by_identity.get(identity).and_then(|indices| indices.iter().next().cloned())
+    }
+
+    /// Returns the roster indices of every member with the given identity, in increasing order
+    pub(crate) fn roster_indices_of_identity(&self, identity: &[u8]) -> Vec<u32> {
+        self.by_identity
+            .get(identity)
+            .map(|indices| indices.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the roster index of the member whose leaf came from the `UserInitKey` with the
+    /// given hash
+    pub(crate) fn roster_index_of_key_package_hash(&self, key_package_hash: &[u8]) -> Option<u32> {
+        self.by_key_package_hash.get(key_package_hash).cloned()
+    }
+}
+
+/// One change that a handshake made to the roster, as returned by `GroupState::roster_changes`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RosterChange {
+    /// Someone was added at this roster index with this credential
+    Added(u32, Credential),
+    /// The member at this roster index, who had this credential, was removed
+    Removed(u32, Credential),
+    /// The member at this roster index replaced their credential in an Update. This holds the old
+    /// credential, then the new one.
+    CredentialChanged(u32, Credential, Credential),
+}
+
+/// A change that a handshake made to the users of a group, as returned by
+/// `GroupState::user_changes`. A user is everyone who shares an identity, so a user only joins
+/// with their first device and only leaves with their last. Whatever happens to the rest of
+/// their devices is only a `RosterChange`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UserChange {
+    /// The user with this identity had no devices in the group before the handshake, and has some
+    /// now
+    Joined(Vec<u8>),
+    /// The user with this identity had devices in the group before the handshake, and has none
+    /// now
+    Left(Vec<u8>),
+}
+
+/// Every member of a group with the same identity, i.e., every device of one user, as returned by
+/// `GroupState::users`
+#[derive(Clone, Debug)]
+pub struct User<'a> {
+    pub(crate) identity: &'a [u8],
+    pub(crate) devices: Vec<Member<'a>>,
+}
+
+impl<'a> User<'a> {
+    /// Returns the identity that this user's devices share
+    pub fn identity(&self) -> &'a [u8] {
+        self.identity
+    }
+
+    /// Returns every device of this user, in roster order. There's always at least one.
+    pub fn devices(&self) -> &[Member<'a>] {
+        &self.devices
+    }
+}
+
+/// A member of a group, as returned by `GroupState::members`
+#[derive(Clone, Copy, Debug)]
+pub struct Member<'a> {
+    pub(crate) roster_index: u32,
+    pub(crate) credential: &'a Credential,
+    pub(crate) leaf: &'a LeafInfo,
+}
+
+impl<'a> Member<'a> {
+    /// Returns this member's position in the roster. This is what Remove proposals and the
+    /// signer indices of handshakes refer to members by.
+    pub fn roster_index(&self) -> u32 {
+        self.roster_index
+    }
+
+    /// Returns this member's credential
+    pub fn credential(&self) -> &'a Credential {
+        self.credential
+    }
+
+    /// Returns the identity in this member's credential
+    pub fn identity(&self) -> &'a [u8] {
+        self.credential.get_identity().as_bytes()
+    }
+
+    /// Returns what this member said they support when they were added
+    pub fn capabilities(&self) -> &'a Capabilities {
+        &self.leaf.capabilities
+    }
+
+    /// Returns the extensions this member attached to their leaf
+    pub fn extensions(&self) -> &'a [Extension] {
+        &self.leaf.extensions.0
+    }
+
+    /// Returns the epoch that this member was added in, or last updated their leaf in. A member
+    /// whose leaf hasn't been updated in a long time is holding on to old key material, and should
+    /// be asked to update or be removed.
+    pub fn last_update_epoch(&self) -> u32 {
+        self.leaf.last_update_epoch
+    }
+
+    /// Returns the lifetime of this member's leaf key, if it's still the one they were added with
+    /// and they gave it a lifetime
+    pub fn key_lifetime(&self) -> Option<&'a Lifetime> {
+        self.leaf.key_lifetime.as_ref()
+    }
+}
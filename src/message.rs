@@ -0,0 +1,403 @@
+//! Defines `MlsMessage`, which is every kind of message this crate sends or receives, tagged with
+//! what kind it is. This is what goes on the wire between clients and the delivery service, so a
+//! recipient can deserialize whatever arrives with `MlsMessage::from_bytes` and hand it to
+//! `Client::process`, rather than working out what it is themselves. `MlsMessageRef` is a view of
+//! one that borrows from the bytes it's read from, for routing messages without copying them.
+//! `MessageHeader` is even less than that: just the few fields a delivery service routes by.
+
+use crate::{
+    crypto::provider::{CryptoProvider, DEFAULT_PROVIDER},
+    error::Error,
+    framing::{
+        ContentType, HandshakeMessage, PrivateMessage, PrivateMessageRef, PublicMessage,
+        PublicMessageRef, WireFormat,
+    },
+    group_id::GroupId,
+    group_state::{GroupInfo, Welcome},
+    handshake::UserInitKey,
+    tls_de::{DecodeLimits, SliceReader, TlsDeserialize, TlsDeserializer},
+    tls_ser,
+    upcast::{CryptoCtx, CryptoUpcast},
+};
+
+// enum {
+//     public_message(0),
+//     private_message(1),
+//     welcome(2),
+//     group_info(3),
+//     key_package(4),
+//     (255)
+// } MlsMessageType;
+//
+// struct {
+//     MlsMessageType msg_type;
+//     select (MlsMessage.msg_type) {
+//         case public_message:  PublicMessage;
+//         case private_message: PrivateMessage;
+//         case welcome:         Welcome;
+//         case group_info:      GroupInfo;
+//         case key_package:     UserInitKey;
+//     };
+// } MlsMessage;
+/// Any message that's sent between clients
+#[derive(TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+#[tls(tag = "u8")]
+pub enum MlsMessage {
+    /// A handshake in the clear
+    PublicMessage(PublicMessage),
+    /// An encrypted handshake or application message
+    PrivateMessage(PrivateMessage),
+    /// An invitation to a group
+    Welcome(Welcome),
+    /// A description of a group, which it can be joined from with an external commit
+    GroupInfo(GroupInfo),
+    /// Someone's keys, which they can be added to a group with
+    KeyPackage(UserInitKey),
+}
+
+impl MlsMessage {
+    /// Deserializes a message of any kind. `Welcome`s, `GroupInfo`s, and `KeyPackage`s say which
+    /// cipher suite they're under, so they're ready to use from here. The handshake in a
+    /// `PublicMessage` can only be interpreted under the cipher suite of the group it's for, so
+    /// `Client::process` does that once it knows the group.
+    ///
+    /// Returns: `Ok(message)` on success. If there's anything after the message, returns an
+    /// `Error::ValidationError`. Otherwise, if the message is malformed, returns some sort of
+    /// `Error`.
+    // This is just a wrapper around from_bytes_with_limits
+    pub fn from_bytes(bytes: &[u8]) -> Result<MlsMessage, Error> {
+        MlsMessage::from_bytes_with_limits(bytes, DecodeLimits::default())
+    }
+
+    /// Deserializes a message of any kind, like `MlsMessage::from_bytes`, under the given decode
+    /// limits rather than the default ones
+    ///
+    /// Returns: `Ok(message)` on success. If there's anything after the message, returns an
+    /// `Error::ValidationError`. If the message is over the limits, returns an
+    /// `Error::SerdeError`. Otherwise, if the message is malformed, returns some sort of `Error`.
+    // This is just a wrapper around from_bytes_with_provider
+    pub fn from_bytes_with_limits(bytes: &[u8], limits: DecodeLimits) -> Result<MlsMessage, Error> {
+        MlsMessage::from_bytes_with_provider(bytes, limits, DEFAULT_PROVIDER)
+    }
+
+    /// Deserializes a message of any kind, like `MlsMessage::from_bytes_with_limits`, looking up
+    /// the cipher suites and signature schemes in it, and in whatever is nested in it, in
+    /// `provider` rather than the default provider
+    ///
+    /// Returns: `Ok(message)` on success. If the message is under a cipher suite or signature
+    /// scheme that `provider` doesn't implement, or is over the limits, returns an
+    /// `Error::SerdeError`. Otherwise, returns what `MlsMessage::from_bytes_with_limits` does.
+    pub fn from_bytes_with_provider(
+        bytes: &[u8],
+        limits: DecodeLimits,
+        provider: &'static dyn CryptoProvider,
+    ) -> Result<MlsMessage, Error> {
+        let mut cursor = bytes;
+        let mut message = {
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor)
+                .with_limits(limits)
+                .with_provider(provider);
+            MlsMessage::tls_deserialize(&mut deserializer)?
+        };
+        if !cursor.is_empty() {
+            return Err(Error::ValidationError("MlsMessage has trailing bytes"));
+        }
+
+        message.upcast(limits, provider)?;
+        Ok(message)
+    }
+
+    /// Deserializes one message of any kind from a stream, e.g., a socket, as it arrives. Nothing
+    /// past the end of the message is read, so the stream can carry more messages after it. This
+    /// is otherwise the same as `MlsMessage::from_bytes`, and it's what to use for large
+    /// `Welcome`s, which needn't be read into memory first. See `TlsDeserializer::from_stream`.
+    ///
+    /// Returns: `Ok(message)` on success. If the stream ends in the middle of the message, or the
+    /// message is malformed, returns some sort of `Error`.
+    // This is just a wrapper around from_reader_with_limits
+    pub fn from_reader<R: std::io::Read>(reader: &mut R) -> Result<MlsMessage, Error> {
+        MlsMessage::from_reader_with_limits(reader, DecodeLimits::default())
+    }
+
+    /// Deserializes one message of any kind from a stream, like `MlsMessage::from_reader`, under
+    /// the given decode limits rather than the default ones
+    ///
+    /// Returns: `Ok(message)` on success. If the message is over the limits, returns an
+    /// `Error::SerdeError`. Otherwise, if the stream ends in the middle of the message, or the
+    /// message is malformed, returns some sort of `Error`.
+    // This is just a wrapper around from_reader_with_provider
+    pub fn from_reader_with_limits<R: std::io::Read>(
+        reader: &mut R,
+        limits: DecodeLimits,
+    ) -> Result<MlsMessage, Error> {
+        MlsMessage::from_reader_with_provider(reader, limits, DEFAULT_PROVIDER)
+    }
+
+    /// Deserializes one message of any kind from a stream, like
+    /// `MlsMessage::from_reader_with_limits`, looking up the cipher suites and signature schemes
+    /// in it in `provider` rather than the default provider
+    ///
+    /// Returns: `Ok(message)` on success. If the message is under a cipher suite or signature
+    /// scheme that `provider` doesn't implement, or is over the limits, returns an
+    /// `Error::SerdeError`. Otherwise, returns what `MlsMessage::from_reader_with_limits` does.
+    pub fn from_reader_with_provider<R: std::io::Read>(
+        reader: &mut R,
+        limits: DecodeLimits,
+        provider: &'static dyn CryptoProvider,
+    ) -> Result<MlsMessage, Error> {
+        let mut message = {
+            let mut deserializer =
+                TlsDeserializer::from_stream(reader).with_limits(limits).with_provider(provider);
+            MlsMessage::tls_deserialize(&mut deserializer)?
+        };
+
+        message.upcast(limits, provider)?;
+        Ok(message)
+    }
+
+    /// Upcasts the crypto values of a freshly deserialized message, as far as they can be without
+    /// knowing what group it's for. Whatever is nested in the message is deserialized under
+    /// `limits` and `provider` too, once it's opened.
+    fn upcast(
+        &mut self,
+        limits: DecodeLimits,
+        provider: &'static dyn CryptoProvider,
+    ) -> Result<(), Error> {
+        let ctx = CryptoCtx::new().set_decode_limits(limits).set_provider(provider);
+        match *self {
+            MlsMessage::Welcome(ref mut welcome) => {
+                welcome.upcast_crypto_values(&ctx)?;
+            }
+            MlsMessage::GroupInfo(ref mut group_info) => {
+                group_info.upcast_crypto_values(&ctx)?;
+            }
+            MlsMessage::KeyPackage(ref mut init_key) => {
+                init_key.upcast_crypto_values(&ctx)?;
+            }
+            // These are upcast once their group is known
+            MlsMessage::PublicMessage(_) | MlsMessage::PrivateMessage(_) => (),
+        }
+
+        Ok(())
+    }
+
+    /// Serializes this message, tagged with what kind of message it is
+    ///
+    /// Returns: `Ok(bytes)` on success. Otherwise, returns an `Error::SerdeError`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        tls_ser::serialize_to_bytes(self)
+    }
+
+    /// Serializes this message, like `to_bytes`, onto the end of `buf`. A server that reuses one
+    /// buffer for all the messages it sends only allocates when the buffer has to grow.
+    ///
+    /// Returns: `Ok(())` on success. Otherwise, returns an `Error::SerdeError`, and `buf` is left
+    /// the way it was.
+    // This is just a wrapper around tls_ser::serialize_into_vec
+    pub fn append_to(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        tls_ser::serialize_into_vec(self, buf)
+    }
+
+    /// Serializes this message, like `to_bytes`, into `writer`, e.g., a socket. A `bytes::BufMut`
+    /// can be written into through its `writer()` adapter. This doesn't stream: a length prefix
+    /// is only known once what it prefixes is serialized, so the whole message is serialized into
+    /// a buffer of `serialized_len` bytes first, and then written out in one go. Servers that send
+    /// a lot of messages can save the allocation with `append_to` and a buffer of their own.
+    ///
+    /// Returns: `Ok(())` on success. Otherwise, returns an `Error::SerdeError`. If the error came
+    /// from `writer`, some of the message may have been written.
+    // This is just a wrapper around tls_ser::serialize_into
+    pub fn write_to<W: std::io::Write>(&self, writer: &mut W) -> Result<(), Error> {
+        tls_ser::serialize_into(self, writer)
+    }
+
+    /// Returns how many bytes `to_bytes` would return, without storing any of them, so that
+    /// callers can allocate exactly that much beforehand
+    ///
+    /// Returns: `Ok(len)` on success. Otherwise, returns an `Error::SerdeError`.
+    // This is just a wrapper around tls_ser::serialized_len
+    pub fn serialized_len(&self) -> Result<usize, Error> {
+        tls_ser::serialized_len(self)
+    }
+
+    /// Returns the ID of the group this message is for, if it says so in the clear. `Welcome`s
+    /// only say so once they're decrypted, and `KeyPackage`s aren't for any group in particular.
+    pub fn group_id(&self) -> Option<&GroupId> {
+        match self {
+            MlsMessage::PublicMessage(public) => Some(public.group_id()),
+            MlsMessage::PrivateMessage(private) => Some(private.group_id()),
+            MlsMessage::GroupInfo(group_info) => Some(group_info.group_id()),
+            MlsMessage::Welcome(_) | MlsMessage::KeyPackage(_) => None,
+        }
+    }
+}
+
+/// An `MlsMessage` read in place, without copying anything out of the buffer it's in. This is for
+/// relays and delivery services, which route many messages by group and epoch without processing
+/// them. Whoever does process one should parse the same bytes with `MlsMessage::from_bytes`.
+#[derive(Clone, Copy, Debug)]
+pub enum MlsMessageRef<'a> {
+    /// A handshake in the clear
+    PublicMessage(PublicMessageRef<'a>),
+    /// An encrypted handshake or application message
+    PrivateMessage(PrivateMessageRef<'a>),
+    /// The encoding of a `Welcome`, which is left unparsed
+    Welcome(&'a [u8]),
+    /// The encoding of a `GroupInfo`, which is left unparsed
+    GroupInfo(&'a [u8]),
+    /// The encoding of a `UserInitKey`, which is left unparsed
+    KeyPackage(&'a [u8]),
+}
+
+impl<'a> MlsMessageRef<'a> {
+    /// Reads a message of any kind from `bytes`. Only `PublicMessage`s and `PrivateMessage`s are
+    /// parsed, as far as `PublicMessageRef` and `PrivateMessageRef` do.
+    ///
+    /// Returns: `Ok(message)` on success. If the message type is unknown, returns an
+    /// `Error::ValidationError`. Otherwise, if the message is malformed, returns some sort of
+    /// `Error`.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<MlsMessageRef<'a>, Error> {
+        let (msg_type, body) = match bytes.split_first() {
+            Some((msg_type, body)) => (*msg_type, body),
+            None => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+        };
+
+        match msg_type {
+            0 => PublicMessageRef::from_bytes(body).map(MlsMessageRef::PublicMessage),
+            1 => PrivateMessageRef::from_bytes(body).map(MlsMessageRef::PrivateMessage),
+            2 => Ok(MlsMessageRef::Welcome(body)),
+            3 => Ok(MlsMessageRef::GroupInfo(body)),
+            4 => Ok(MlsMessageRef::KeyPackage(body)),
+            _ => Err(Error::ValidationError("Unknown MlsMessage type")),
+        }
+    }
+
+    /// Returns the ID of the group this message is for, if it says so in the clear and it's
+    /// parsed. Unlike `MlsMessage::group_id`, this is `None` for `GroupInfo`s.
+    pub fn group_id(&self) -> Option<&'a [u8]> {
+        match self {
+            MlsMessageRef::PublicMessage(public) => Some(public.group_id()),
+            MlsMessageRef::PrivateMessage(private) => Some(private.group_id()),
+            _ => None,
+        }
+    }
+
+    /// Returns the epoch this message was made in, if it says so in the clear
+    pub fn epoch(&self) -> Option<u32> {
+        match self {
+            MlsMessageRef::PublicMessage(public) => Some(public.epoch()),
+            MlsMessageRef::PrivateMessage(private) => Some(private.epoch()),
+            _ => None,
+        }
+    }
+}
+
+/// The fields at the front of a `PublicMessage` or `PrivateMessage` that say where it goes. These
+/// are all in the clear, so a delivery service can route messages it can't decrypt by them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MessageHeader<'a> {
+    wire_format: WireFormat,
+    group_id: &'a [u8],
+    epoch: u32,
+    content_type: ContentType,
+}
+
+impl<'a> MessageHeader<'a> {
+    /// Reads the header off the front of the encoding of an `MlsMessage`. Nothing past the header
+    /// is read, so this says nothing about whether the rest of the message is well-formed, or
+    /// whether it's authentic.
+    ///
+    /// Returns: `Ok(Some(header))` on success. If the message is a `Welcome`, `GroupInfo`, or
+    /// `KeyPackage`, which don't have headers, returns `Ok(None)`. If the message type or content
+    /// type is unknown, returns an `Error::ValidationError`. Otherwise, if `bytes` ends before the
+    /// header does, returns an `Error::SerdeError`.
+    pub fn peek(bytes: &'a [u8]) -> Result<Option<MessageHeader<'a>>, Error> {
+        let mut reader = SliceReader::new(bytes);
+        let wire_format = match reader.read_u8()? {
+            0 => WireFormat::PublicMessage,
+            1 => WireFormat::PrivateMessage,
+            2..=4 => return Ok(None),
+            _ => return Err(Error::ValidationError("Unknown MlsMessage type")),
+        };
+        // Skip the version
+        reader.read_u8()?;
+        let group_id = reader.read_opaque_u8()?;
+        // This is a PublicMessage's handshake's prior_epoch, or a PrivateMessage's epoch
+        let epoch = reader.read_u32()?;
+        let content_type = match wire_format {
+            // Only handshakes are sent in the clear
+            WireFormat::PublicMessage => ContentType::Handshake,
+            WireFormat::PrivateMessage => match reader.read_u8()? {
+                0 => ContentType::Application,
+                1 => ContentType::Handshake,
+                _ => {
+                    return Err(Error::ValidationError(
+                        "PrivateMessage has an unknown content type",
+                    ))
+                }
+            },
+        };
+
+        Ok(Some(MessageHeader {
+            wire_format,
+            group_id,
+            epoch,
+            content_type,
+        }))
+    }
+
+    /// Returns whether the message is a `PublicMessage` or a `PrivateMessage`
+    pub fn wire_format(&self) -> WireFormat {
+        self.wire_format
+    }
+
+    /// Returns the ID of the group the message is for
+    pub fn group_id(&self) -> &'a [u8] {
+        self.group_id
+    }
+
+    /// Returns the epoch the message was made in
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns what the message carries. This is always `ContentType::Handshake` for a
+    /// `PublicMessage`.
+    pub fn content_type(&self) -> ContentType {
+        self.content_type
+    }
+}
+
+impl From<HandshakeMessage> for MlsMessage {
+    fn from(message: HandshakeMessage) -> MlsMessage {
+        match message {
+            HandshakeMessage::Public(public) => MlsMessage::PublicMessage(public),
+            HandshakeMessage::Private(private) => MlsMessage::PrivateMessage(private),
+        }
+    }
+}
+
+impl From<PrivateMessage> for MlsMessage {
+    fn from(message: PrivateMessage) -> MlsMessage {
+        MlsMessage::PrivateMessage(message)
+    }
+}
+
+impl From<Welcome> for MlsMessage {
+    fn from(welcome: Welcome) -> MlsMessage {
+        MlsMessage::Welcome(welcome)
+    }
+}
+
+impl From<GroupInfo> for MlsMessage {
+    fn from(group_info: GroupInfo) -> MlsMessage {
+        MlsMessage::GroupInfo(group_info)
+    }
+}
+
+impl From<UserInitKey> for MlsMessage {
+    fn from(init_key: UserInitKey) -> MlsMessage {
+        MlsMessage::KeyPackage(init_key)
+    }
+}
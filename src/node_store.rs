@@ -0,0 +1,253 @@
+//! Defines `NodeStore`, which is how a `RatchetTree` keeps its nodes. Nodes are kept either in a
+//! vector, or, for big trees that are mostly blank, in a map that only has the filled ones.
+
+use crate::{
+    error::Error,
+    ratchet_tree::{RatchetTreeNode, TreeStorage},
+    tls_de::{TlsDeserialize, TlsDeserializer},
+    tls_ser::{TlsSerialize, TlsSerializer},
+};
+
+use std::{collections::HashMap, ops::Index};
+
+/// The nodes of a `RatchetTree`, in node order. Either way of storing them behaves the same,
+/// except that `get_mut` on a sparse store only finds filled nodes. Changing a node that might be
+/// or become blank goes through `set` or `update`, so that a sparse store never keeps a blank
+/// node around.
+#[derive(Clone)]
+#[cfg_attr(test, derive(Debug))]
+pub(crate) enum NodeStore {
+    /// Every node
+    Dense(Vec<RatchetTreeNode>),
+    /// The number of nodes, the nodes that might not be blank, indexed by node index, and a blank
+    /// node, which is what every node that isn't in `filled` is
+    Sparse {
+        len: usize,
+        filled: HashMap<usize, RatchetTreeNode>,
+        blank: RatchetTreeNode,
+    },
+}
+
+impl NodeStore {
+    /// Returns how this store keeps its nodes
+    pub(crate) fn storage(&self) -> TreeStorage {
+        match self {
+            NodeStore::Dense(_) => TreeStorage::Dense,
+            NodeStore::Sparse {
+                ..
+            } => TreeStorage::Sparse,
+        }
+    }
+
+    /// Returns a store with the same nodes as this one, kept the way `storage` says
+    pub(crate) fn into_storage(self, storage: TreeStorage) -> NodeStore {
+        match (self, storage) {
+            (NodeStore::Dense(nodes), TreeStorage::Sparse) => {
+                let len = nodes.len();
+                let filled = nodes.into_iter().enumerate().filter(|(_, node)| node.is_filled());
+                NodeStore::Sparse {
+                    len,
+                    filled: filled.collect(),
+                    blank: RatchetTreeNode::Blank,
+                }
+            }
+            (
+                NodeStore::Sparse {
+                    len,
+                    mut filled,
+                    ..
+                },
+                TreeStorage::Dense,
+            ) => {
+                let nodes = (0..len).map(|i| filled.remove(&i).unwrap_or(RatchetTreeNode::Blank));
+                NodeStore::Dense(nodes.collect())
+            }
+            (store, _) => store,
+        }
+    }
+
+    /// Returns the number of nodes, blank or not
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            NodeStore::Dense(nodes) => nodes.len(),
+            NodeStore::Sparse {
+                len,
+                ..
+            } => *len,
+        }
+    }
+
+    /// Returns whether there are no nodes at all
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the node at the given index, if there is one
+    pub(crate) fn get(&self, idx: usize) -> Option<&RatchetTreeNode> {
+        match self {
+            NodeStore::Dense(nodes) => nodes.get(idx),
+            NodeStore::Sparse {
+                len,
+                ..
+            } if idx >= *len => None,
+            NodeStore::Sparse {
+                filled,
+                blank,
+                ..
+            } => Some(filled.get(&idx).unwrap_or(blank)),
+        }
+    }
+
+    /// Returns a mutable reference to the node at the given index, if it's stored. A sparse store
+    /// doesn't store blank nodes, so this returns `None` for them. Use `set` or `update` to fill a
+    /// node that might be blank.
+    pub(crate) fn get_mut(&mut self, idx: usize) -> Option<&mut RatchetTreeNode> {
+        match self {
+            NodeStore::Dense(nodes) => nodes.get_mut(idx),
+            NodeStore::Sparse {
+                filled,
+                ..
+            } => filled.get_mut(&idx),
+        }
+    }
+
+    /// Calls `f` on the node at the given index, blank or not, and stores the result with `set`
+    ///
+    /// Returns: `Some` of whatever `f` returns, or `None` if `idx >= self.len()`, in which case `f`
+    /// isn't called
+    pub(crate) fn update<T, F>(&mut self, idx: usize, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut RatchetTreeNode) -> T,
+    {
+        match self {
+            NodeStore::Dense(nodes) => nodes.get_mut(idx).map(f),
+            NodeStore::Sparse {
+                len,
+                ..
+            } if idx >= *len => None,
+            NodeStore::Sparse {
+                filled,
+                ..
+            } => {
+                let mut node = filled.remove(&idx).unwrap_or(RatchetTreeNode::Blank);
+                let ret = f(&mut node);
+                self.set(idx, node);
+                Some(ret)
+            }
+        }
+    }
+
+    /// Sets the node at the given index. A sparse store forgets blank nodes.
+    ///
+    /// Panics: If `idx >= self.len()`
+    pub(crate) fn set(&mut self, idx: usize, node: RatchetTreeNode) {
+        match self {
+            NodeStore::Dense(nodes) => nodes[idx] = node,
+            NodeStore::Sparse {
+                len,
+                filled,
+                ..
+            } => {
+                assert!(idx < *len, "node index out of bounds");
+                if node.is_filled() {
+                    filled.insert(idx, node);
+                } else {
+                    filled.remove(&idx);
+                }
+            }
+        }
+    }
+
+    /// Appends a node
+    pub(crate) fn push(&mut self, node: RatchetTreeNode) {
+        match self {
+            NodeStore::Dense(nodes) => nodes.push(node),
+            NodeStore::Sparse {
+                len,
+                filled,
+                ..
+            } => {
+                if node.is_filled() {
+                    filled.insert(*len, node);
+                }
+                *len += 1;
+            }
+        }
+    }
+
+    /// Drops every node at or after `new_len`. This does nothing if there are no such nodes.
+    pub(crate) fn truncate(&mut self, new_len: usize) {
+        match self {
+            NodeStore::Dense(nodes) => nodes.truncate(new_len),
+            NodeStore::Sparse {
+                len,
+                filled,
+                ..
+            } => {
+                if new_len < *len {
+                    filled.retain(|&i, _| i < new_len);
+                    *len = new_len;
+                }
+            }
+        }
+    }
+
+    /// Drops every node
+    pub(crate) fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Returns an iterator over every node, blank or not, in node order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &RatchetTreeNode> + '_ {
+        (0..self.len()).map(move |i| &self[i])
+    }
+
+    /// Returns an iterator over the index of and a mutable reference to every filled node. The
+    /// order isn't necessarily node order.
+    pub(crate) fn iter_filled_mut(
+        &mut self,
+    ) -> Box<dyn Iterator<Item = (usize, &mut RatchetTreeNode)> + '_> {
+        let nodes: Box<dyn Iterator<Item = (usize, &mut RatchetTreeNode)> + '_> = match self {
+            NodeStore::Dense(nodes) => Box::new(nodes.iter_mut().enumerate()),
+            NodeStore::Sparse {
+                filled,
+                ..
+            } => Box::new(filled.iter_mut().map(|(&i, node)| (i, node))),
+        };
+        Box::new(nodes.filter(|(_, node)| node.is_filled()))
+    }
+}
+
+impl Index<usize> for NodeStore {
+    type Output = RatchetTreeNode;
+
+    fn index(&self, idx: usize) -> &RatchetTreeNode {
+        self.get(idx).expect("node index out of bounds")
+    }
+}
+
+impl From<Vec<RatchetTreeNode>> for NodeStore {
+    fn from(nodes: Vec<RatchetTreeNode>) -> NodeStore {
+        NodeStore::Dense(nodes)
+    }
+}
+
+// Either way, the nodes are serialized like a Vec<RatchetTreeNode> would be. They're always
+// deserialized into a dense store, since that's the default.
+
+impl TlsSerialize for NodeStore {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        for node in self.iter() {
+            node.tls_serialize(serializer)?;
+        }
+        Ok(())
+    }
+}
+
+impl TlsDeserialize for NodeStore {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<NodeStore, Error> {
+        Vec::<RatchetTreeNode>::tls_deserialize(deserializer).map(NodeStore::Dense)
+    }
+}
@@ -0,0 +1,51 @@
+//! Defines the `ProposalStore`, which is where a member keeps the proposals that were sent to the
+//! group by themselves during the current epoch. Commits can refer to these by reference instead
+//! of repeating them.
+
+use crate::handshake::{GroupProposal, ProposalRef, ProposalSender};
+
+/// The proposals a member has received during the current epoch, and who sent them, indexed by
+/// their references.
+/// This is emptied at every epoch change, since proposals are only valid in the epoch they were
+/// made in.
+#[derive(Clone, Default)]
+pub(crate) struct ProposalStore {
+    entries: Vec<(ProposalRef, GroupProposal, ProposalSender)>,
+}
+
+impl ProposalStore {
+    /// Stores the given proposal from `sender` under the given reference. Storing the same
+    /// proposal twice does nothing.
+    pub(crate) fn insert(
+        &mut self,
+        proposal_ref: ProposalRef,
+        proposal: GroupProposal,
+        sender: ProposalSender,
+    ) {
+        if self.get(&proposal_ref).is_none() {
+            self.entries.push((proposal_ref, proposal, sender));
+        }
+    }
+
+    /// Returns the proposal with the given reference, if it's been stored
+    pub(crate) fn get(&self, proposal_ref: &ProposalRef) -> Option<&GroupProposal> {
+        self.entries.iter().find(|(r, _, _)| r == proposal_ref).map(|(_, proposal, _)| proposal)
+    }
+
+    /// Returns who sent the proposal with the given reference, if it's been stored
+    pub(crate) fn sender(&self, proposal_ref: &ProposalRef) -> Option<ProposalSender> {
+        self.entries.iter().find(|(r, _, _)| r == proposal_ref).map(|(_, _, sender)| *sender)
+    }
+
+    /// Returns every stored proposal, along with its reference and sender, in the order they were
+    /// stored
+    #[cfg(feature = "snapshots")]
+    pub(crate) fn entries(&self) -> &[(ProposalRef, GroupProposal, ProposalSender)] {
+        &self.entries
+    }
+
+    /// Forgets every stored proposal
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,349 @@
+//! Defines pre-shared keys (PSKs) and how they're mixed into the key schedule, as per the
+//! "Pre-Shared Keys" section of RFC 9420. A PSK is identified by a `PreSharedKeyId`, and its
+//! secret is looked up in a `PskStore` that the application provides.
+
+use crate::{
+    crypto::{ciphersuite::CipherSuite, hkdf, hmac::HmacKey, kdf, secret::Secret},
+    error::Error,
+    group_id::GroupId,
+    tls_ser,
+};
+
+// enum {
+//     reserved(0),
+//     application(1),
+//     reinit(2),
+//     branch(3),
+//     (255)
+// } ResumptionPSKUsage;
+/// What a resumption PSK is being used for
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(tag = "u8")]
+pub enum ResumptionPskUsage {
+    /// Proving knowledge of a past epoch of the same group
+    #[tls(discriminant = 1)]
+    Application,
+    /// Proving continuity with the group that a re-initialized group replaces
+    #[tls(discriminant = 2)]
+    Reinit,
+    /// Proving continuity with the group that a new group was branched off of
+    #[tls(discriminant = 3)]
+    Branch,
+}
+
+// enum {
+//     reserved(0),
+//     external(1),
+//     resumption(2),
+//     (255)
+// } PSKType;
+/// The kinds of pre-shared key there are, along with what identifies a key of that kind. This is
+/// tagged by its `PSKType`.
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+#[tls(tag = "u8")]
+pub(crate) enum PskKind {
+    /// A key that was provisioned out of band, identified by an application-defined ID
+    #[tls(discriminant = 1)]
+    External(ExternalPskId),
+    /// A key derived from a particular epoch of a particular group
+    #[tls(discriminant = 2)]
+    Resumption(ResumptionPskId),
+}
+
+/// What identifies an external PSK
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub(crate) struct ExternalPskId {
+    #[tls(bound = "varint")]
+    pub(crate) psk_id: Vec<u8>,
+}
+
+/// What identifies a resumption PSK. The group ID is kept as bytes, rather than as a `GroupId`,
+/// because it's a `<V>` vector here rather than a `<0..255>` one.
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub(crate) struct ResumptionPskId {
+    pub(crate) usage: ResumptionPskUsage,
+    #[tls(bound = "varint")]
+    pub(crate) psk_group_id: Vec<u8>,
+    pub(crate) psk_epoch: u64,
+}
+
+// struct {
+//     PSKType psktype;
+//     select (PreSharedKeyID.psktype) {
+//         case external:
+//             opaque psk_id<V>;
+//         case resumption:
+//             ResumptionPSKUsage usage;
+//             opaque psk_group_id<V>;
+//             uint64 psk_epoch;
+//     };
+//     opaque psk_nonce<V>;
+// } PreSharedKeyID;
+/// Identifies a pre-shared key, along with a nonce that makes the derived PSK input unique to its
+/// use
+#[derive(Clone, Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+pub struct PreSharedKeyId {
+    pub(crate) kind: PskKind,
+    #[tls(bound = "varint")]
+    pub(crate) psk_nonce: Vec<u8>,
+}
+
+impl PreSharedKeyId {
+    /// Makes the ID of an external PSK with the given application-defined `psk_id`. Every member
+    /// using this PSK has to use the same `psk_nonce`. The spec says this should be a fresh random
+    /// value that's as long as the cipher suite's hash.
+    pub fn new_external(psk_id: Vec<u8>, psk_nonce: Vec<u8>) -> PreSharedKeyId {
+        PreSharedKeyId {
+            kind: PskKind::External(ExternalPskId {
+                psk_id,
+            }),
+            psk_nonce,
+        }
+    }
+
+    /// Makes the ID of the given resumption PSK, to be used for `usage`. As with external PSKs,
+    /// every member using this PSK has to use the same `psk_nonce`.
+    pub fn new_resumption(
+        usage: ResumptionPskUsage,
+        psk: &ResumptionPsk,
+        psk_nonce: Vec<u8>,
+    ) -> PreSharedKeyId {
+        PreSharedKeyId {
+            kind: PskKind::Resumption(ResumptionPskId {
+                usage,
+                psk_group_id: psk.group_id.as_bytes().to_vec(),
+                psk_epoch: u64::from(psk.epoch),
+            }),
+            psk_nonce,
+        }
+    }
+
+    /// Returns whether this is the ID of the given resumption PSK
+    pub(crate) fn is_resumption_of(&self, psk: &ResumptionPsk) -> bool {
+        match &self.kind {
+            PskKind::Resumption(ResumptionPskId {
+                psk_group_id,
+                psk_epoch,
+                ..
+            }) => {
+                psk_group_id.as_slice() == psk.group_id.as_bytes()
+                    && *psk_epoch == u64::from(psk.epoch)
+            }
+            PskKind::External(_) => false,
+        }
+    }
+}
+
+/// A trait representing wherever the application keeps its pre-shared keys. This is consulted
+/// whenever the key schedule needs the secret behind a `PreSharedKeyId`.
+pub trait PskStore: Send + Sync {
+    /// Returns the secret of the external PSK with the given ID, or `None` if there isn't one
+    fn get_external_psk(&self, psk_id: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// The resumption PSK of a single epoch of a group, i.e., `DeriveSecret(epoch_secret,
+/// "resumption")`. Every member of the group derives the same one. Get these from
+/// `GroupState::resumption_psk`.
+#[derive(Clone, Debug)]
+pub struct ResumptionPsk {
+    pub(crate) group_id: GroupId,
+    pub(crate) epoch: u32,
+    pub(crate) secret: Secret,
+}
+
+impl ResumptionPsk {
+    /// Returns the ID of the group this PSK was derived in
+    pub fn group_id(&self) -> &GroupId {
+        &self.group_id
+    }
+
+    /// Returns the epoch this PSK was derived in
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+}
+
+/// This is called the `psk_secret` in the MLS key schedule. It's all zeros when no PSKs are in
+/// use.
+pub(crate) struct PskSecret(Secret);
+
+impl PskSecret {
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+// struct {
+//     PreSharedKeyID id;
+//     uint16 index;
+//     uint16 count;
+// } PSKLabel;
+/// The context of the `ExpandWithLabel` that makes the PSK input from a PSK
+#[derive(TlsSerialize)]
+struct PskLabel<'a> {
+    id: &'a PreSharedKeyId,
+    index: u16,
+    count: u16,
+}
+
+/// Looks up the secret behind every one of the given PSK IDs, and combines them into a single
+/// `psk_secret`, in order. External PSKs are looked up in `store`, and resumption PSKs are looked
+/// up in `resumption_psks`.
+///
+/// Returns: `Ok(psk_secret)` on success. If there are external PSKs but no store, or one of the
+/// PSKs can't be found, returns an `Error::ValidationError`. If a PSK ID is too long to
+/// serialize, returns an `Error::SerdeError`.
+pub(crate) fn compute_psk_secret(
+    cs: &'static CipherSuite,
+    psk_ids: &[PreSharedKeyId],
+    store: Option<&dyn PskStore>,
+    resumption_psks: &[ResumptionPsk],
+) -> Result<PskSecret, Error> {
+    let hash_impl = cs.hash_impl;
+    if psk_ids.len() > std::u16::MAX as usize {
+        return Err(Error::ValidationError("Too many PSKs"));
+    }
+    let count = psk_ids.len() as u16;
+
+    // psk_secret_[0] = 0
+    let mut psk_secret = Secret::new_from_zeros(hash_impl.digest_size());
+    for (index, id) in psk_ids.iter().enumerate() {
+        let psk: Secret = match &id.kind {
+            PskKind::External(ExternalPskId {
+                psk_id,
+            }) => store
+                .ok_or(Error::ValidationError("No PSK store to look PSKs up in"))?
+                .get_external_psk(psk_id)
+                .ok_or(Error::ValidationError("External PSK not found in PSK store"))?
+                .into(),
+            PskKind::Resumption(_) => resumption_psks
+                .iter()
+                .find(|p| id.is_resumption_of(p))
+                .ok_or(Error::ValidationError("Resumption PSK not found"))?
+                .secret
+                .clone(),
+        };
+
+        // psk_extracted_[i] = KDF.Extract(0, psk_[i])
+        let zero_salt = HmacKey::new_from_zeros(hash_impl);
+        let psk_extracted = hkdf::extract(hash_impl, &zero_salt, psk.as_bytes());
+
+        // psk_input_[i] = ExpandWithLabel(psk_extracted_[i], "derived psk", PSKLabel, KDF.Nh)
+        let psk_label = tls_ser::serialize_to_bytes(&PskLabel {
+            id,
+            index: index as u16,
+            count,
+        })?;
+        let mut psk_input = HmacKey::new_from_zeros(hash_impl);
+        kdf::expand_with_label(
+            hash_impl,
+            &psk_extracted,
+            b"derived psk",
+            &psk_label,
+            psk_input.0.as_mut_bytes(),
+        );
+
+        // psk_secret_[i] = KDF.Extract(psk_input_[i-1], psk_secret_[i-1])
+        psk_secret = hkdf::extract(hash_impl, &psk_input, psk_secret.as_bytes()).0;
+    }
+
+    Ok(PskSecret(psk_secret))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        crypto::ciphersuite::X25519_SHA256_AES128GCM,
+        test_utils::MapPskStore,
+        tls_de::{TlsDeserialize, TlsDeserializer},
+        tls_ser,
+    };
+
+    use std::collections::HashMap;
+
+    // PSK IDs go over the wire in PSK proposals, so make sure both kinds survive the trip
+    #[test]
+    fn psk_id_serialization() {
+        let resumption_psk = ResumptionPsk {
+            group_id: GroupId::new(b"group".to_vec()).unwrap(),
+            epoch: 3,
+            secret: Secret::new_from_bytes(&[0x44; 32]),
+        };
+        let psk_ids = [
+            PreSharedKeyId::new_external(b"psk".to_vec(), vec![0xee; 32]),
+            PreSharedKeyId::new_resumption(
+                ResumptionPskUsage::Application,
+                &resumption_psk,
+                vec![0xff; 32],
+            ),
+        ];
+
+        for psk_id in psk_ids.iter() {
+            let bytes = tls_ser::serialize_to_bytes(psk_id).unwrap();
+            let mut cursor = bytes.as_slice();
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            let decoded = PreSharedKeyId::tls_deserialize(&mut deserializer).unwrap();
+            assert_eq!(&decoded, psk_id);
+            assert!(cursor.is_empty());
+        }
+
+        // The external one is tagged with its PSKType, and its vectors have varint lengths
+        let expected = [&[0x01, 0x03][..], b"psk", &[0x20], &[0xee; 32]].concat();
+        assert_eq!(tls_ser::serialize_to_bytes(&psk_ids[0]).unwrap(), expected);
+    }
+
+    // These answers were computed with an independent implementation of the RFC 9420 definitions
+    // over HMAC-SHA256
+    #[test]
+    fn psk_secret_kat() {
+        let cs = &X25519_SHA256_AES128GCM;
+        let mut map = HashMap::new();
+        map.insert(b"psk a".to_vec(), vec![0x11; 32]);
+        map.insert(b"psk b".to_vec(), vec![0x22; 16]);
+        let store = MapPskStore(map);
+
+        // No PSKs means an all-zero psk_secret, and no store is needed
+        let psk_secret = compute_psk_secret(cs, &[], None, &[]).unwrap();
+        assert_eq!(psk_secret.as_bytes(), &[0u8; 32]);
+
+        let psk_a = PreSharedKeyId::new_external(b"psk a".to_vec(), vec![0xaa; 32]);
+        let psk_b = PreSharedKeyId::new_external(b"psk b".to_vec(), vec![0xbb; 32]);
+
+        let psk_secret = compute_psk_secret(cs, &[psk_a.clone()], Some(&store), &[]).unwrap();
+        assert_eq!(
+            hex::encode(psk_secret.as_bytes()),
+            "acbec98d7972cd2e0cad1e330c7f37ec0b07d62ac4efa7261ed719db1c1a6a13"
+        );
+
+        let psk_secret =
+            compute_psk_secret(cs, &[psk_a.clone(), psk_b], Some(&store), &[]).unwrap();
+        assert_eq!(
+            hex::encode(psk_secret.as_bytes()),
+            "17bf1628efa347acbd663c37e6a36b677ff795aa2d6fa4629478f957195508c8"
+        );
+
+        // Unknown PSKs and missing stores are errors
+        let psk_c = PreSharedKeyId::new_external(b"psk c".to_vec(), vec![0xcc; 32]);
+        assert!(compute_psk_secret(cs, &[psk_c], Some(&store), &[]).is_err());
+        assert!(compute_psk_secret(cs, &[psk_a], None, &[]).is_err());
+
+        // Resumption PSKs are found by group ID and epoch, and don't need a store
+        let resumption_psk = ResumptionPsk {
+            group_id: GroupId::new(b"group".to_vec()).unwrap(),
+            epoch: 7,
+            secret: Secret::new_from_bytes(&[0x33; 32]),
+        };
+        let psk_r = PreSharedKeyId::new_resumption(
+            ResumptionPskUsage::Branch,
+            &resumption_psk,
+            vec![0xdd; 32],
+        );
+        let psk_secret = compute_psk_secret(cs, &[psk_r.clone()], None, &[resumption_psk]).unwrap();
+        assert_eq!(
+            hex::encode(psk_secret.as_bytes()),
+            "bf9cf3e432cd72e6265adb964f858f009446f39cce15054aab988a19dbf32221"
+        );
+        assert!(compute_psk_secret(cs, &[psk_r], None, &[]).is_err());
+    }
+}
@@ -4,20 +4,33 @@ use crate::{
     crypto::{
         ciphersuite::CipherSuite,
         dh::{DhPrivateKey, DhPublicKey},
-        ecies,
+        hash::Digest,
         hmac::HmacKey,
+        hpke,
         rng::CryptoRng,
+        secret::Secret,
     },
     error::Error,
-    handshake::{DirectPathMessage, DirectPathNodeMessage},
-    tree_math, utils,
+    extensions::{Extension, RATCHET_TREE_TYPE},
+    group_state::GroupState,
+    handshake::{DirectPathMessage, UpdatePath, UpdatePathNode},
+    node_store::NodeStore,
+    tls_de::{DecodeLimits, TlsDeserialize, TlsDeserializer},
+    tls_ser, tree_math,
+    upcast::{CryptoCtx, CryptoUpcast},
+    utils,
 };
 
+use core::convert::TryFrom;
+
 use subtle::ConstantTimeEq;
 
+/// The HPKE label that path secrets in an `UpdatePath` are encrypted under
+const PATH_SECRET_LABEL: &[u8] = b"UpdatePathNode";
+
 /// This is called the "node secret" (section 5.2). If `Hash` is the current ciphersuite's hash
 /// algorithm, this MUST have length equal to `Hash.length`.
-pub(crate) struct NodeSecret(pub(crate) Vec<u8>);
+pub(crate) struct NodeSecret(pub(crate) Secret);
 
 /// This is called the "path secret" (section 5.2). If `Hash` is the current ciphersuite's hash
 /// algorithm, this MUST have length equal to `Hash.length`.
@@ -25,7 +38,7 @@ pub(crate) struct NodeSecret(pub(crate) Vec<u8>);
 pub struct PathSecret(HmacKey);
 
 impl PathSecret {
-    /// Wraps a `Vec<u8>` with a `ClearOnDrop` and makes it a `PathSecret`
+    /// Copies the given bytes into a `PathSecret`
     pub(crate) fn new_from_bytes(bytes: &[u8]) -> PathSecret {
         PathSecret(HmacKey::new_from_bytes(bytes))
     }
@@ -43,7 +56,7 @@ impl PathSecret {
     /// really really need to.
     fn as_bytes(&self) -> &[u8] {
         // Dig into the HMAC key and pull out a slice
-        (self.0).0.as_slice()
+        self.0.as_bytes()
     }
 
     /// Returns the length of the bytes-representation of the path secret
@@ -52,6 +65,13 @@ impl PathSecret {
     }
 }
 
+// HmacKey --> PathSecret trivially
+impl From<HmacKey> for PathSecret {
+    fn from(key: HmacKey) -> PathSecret {
+        PathSecret(key)
+    }
+}
+
 // PathSecret --> HmacKey trivially
 impl From<PathSecret> for HmacKey {
     fn from(p: PathSecret) -> HmacKey {
@@ -62,19 +82,29 @@ impl From<PathSecret> for HmacKey {
 // Ratchet trees are serialized in DirectPath messages as optional<PublicKey> tree<1..2^32-1> So we
 // encode RatchetTree as a Vec<RatchetTreeNode> with length bound u32, and we encode
 // RatchetTreeNode as enum { Blank, Filled { DhPublicKey } }, which is encoded in the same way as
-// an Option<DhPublicKey> would be.
+// an Option<DhPublicKey> would be. Unmerged leaves and parent hashes don't fit in that, so they're
+// sent separately as `UnmergedLeaves` and `ParentHashes`.
 
 /// A node in a `RatchetTree`. Every node must have a DH pubkey. It may also optionally contain the
 /// corresponding private key.
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
-#[serde(rename = "RatchetTreeNode__enum_u8")]
+#[tls(tag = "u8")]
 pub(crate) enum RatchetTreeNode {
     Blank,
     Filled {
         public_key: DhPublicKey,
-        #[serde(skip)]
+        #[tls(skip)]
         private_key: Option<DhPrivateKey>,
+        /// The leaf indices of the members below this node who were added since its key was last
+        /// set. They don't know its private key, so they're encrypted to directly. Only parent
+        /// nodes ever have unmerged leaves.
+        #[tls(skip)]
+        unmerged_leaves: Vec<u32>,
+        /// The hash that ties this node's key to its parent's, as of the path update that set
+        /// this node's key. It's empty at the root, and at a leaf whose key wasn't set by a path.
+        #[tls(skip)]
+        parent_hash: Digest,
     },
 }
 
@@ -89,6 +119,8 @@ impl RatchetTreeNode {
         RatchetTreeNode::Filled {
             public_key: pubkey,
             private_key: Some(private_key),
+            unmerged_leaves: Vec::new(),
+            parent_hash: Digest::default(),
         }
     }
 
@@ -103,19 +135,29 @@ impl RatchetTreeNode {
     }
 
     /// Updates the node's public key to the given one. This is the only way to convert a `Blank`
-    /// node into a `Filled` one.
+    /// node into a `Filled` one. Whoever set the new key sent it to everyone below the node, so
+    /// this merges all of its unmerged leaves. The node's parent hash is cleared, since it was
+    /// computed for the old key.
     pub(crate) fn update_public_key(&mut self, new_public_key: DhPublicKey) {
         match self {
             RatchetTreeNode::Blank => {
                 *self = RatchetTreeNode::Filled {
                     public_key: new_public_key,
                     private_key: None,
+                    unmerged_leaves: Vec::new(),
+                    parent_hash: Digest::default(),
                 };
             }
             RatchetTreeNode::Filled {
                 ref mut public_key,
+                ref mut unmerged_leaves,
+                ref mut parent_hash,
                 ..
-            } => *public_key = new_public_key,
+            } => {
+                *public_key = new_public_key;
+                unmerged_leaves.clear();
+                *parent_hash = Digest::default();
+            }
         }
     }
 
@@ -155,30 +197,816 @@ impl RatchetTreeNode {
             } => private_key.as_ref(),
         }
     }
+
+    /// Returns the leaf indices of this node's unmerged leaves, in the order they were added. A
+    /// `Blank` node has none.
+    pub(crate) fn unmerged_leaves(&self) -> &[u32] {
+        match self {
+            RatchetTreeNode::Blank => &[],
+            RatchetTreeNode::Filled {
+                ref unmerged_leaves,
+                ..
+            } => unmerged_leaves,
+        }
+    }
+
+    /// Returns this node's parent hash. If the node is `Blank`, returns `None`.
+    pub(crate) fn parent_hash(&self) -> Option<&Digest> {
+        match self {
+            RatchetTreeNode::Blank => None,
+            RatchetTreeNode::Filled {
+                ref parent_hash,
+                ..
+            } => Some(parent_hash),
+        }
+    }
+}
+
+// uint32 unmerged_leaves<0..2^32-1>;
+/// The unmerged leaves of a single node
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+#[tls(bound = "u32", limit = "tree")]
+struct NodeUnmergedLeaves(Vec<u32>);
+
+// NodeUnmergedLeaves unmerged_leaves<0..2^32-1>;
+/// The unmerged leaves of every node in a `RatchetTree`, in node order. The tree's own wire format
+/// has no room for these, so `Welcome`s and `GroupInfo`s carry them alongside the tree.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+#[tls(bound = "u32", limit = "tree")]
+pub(crate) struct UnmergedLeaves(Vec<NodeUnmergedLeaves>);
+
+// Digest parent_hashes<0..2^32-1>;
+/// The parent hash of every node in a `RatchetTree`, in node order. Blank nodes have empty ones.
+/// Like `UnmergedLeaves`, these are carried alongside the tree.
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
+#[cfg_attr(test, derive(Debug))]
+#[tls(bound = "u32", limit = "tree")]
+pub(crate) struct ParentHashes(Vec<Digest>);
+
+// struct {
+//     HPKEPublicKey public_key;
+//     opaque parent_hash<0..255>;
+//     HPKEPublicKey original_sibling_resolution<0..2^32-1>;
+// } ParentHashInput;
+/// What the parent hash of a node is computed over. The public key and parent hash are those of
+/// the node's parent, and the resolution is of the node's sibling, minus the parent's unmerged
+/// leaves. That's the set of keys that the path which set the parent's key was encrypted to.
+#[derive(TlsSerialize)]
+struct ParentHashInput<'a> {
+    public_key: &'a DhPublicKey,
+    parent_hash: &'a Digest,
+    #[tls(bound = "u32")]
+    original_sibling_resolution: Vec<&'a DhPublicKey>,
+}
+
+// struct {
+//     HPKEPublicKey public_key;
+//     opaque parent_hash<0..255>;
+// } LeafNode;
+/// The part of a non-blank leaf node that its hash covers
+#[derive(TlsSerialize)]
+struct LeafNode<'a> {
+    public_key: &'a DhPublicKey,
+    parent_hash: &'a Digest,
+}
+
+// struct {
+//     uint32 node_index;
+//     optional<LeafNode> leaf_node;
+// } LeafNodeHashInput;
+/// What the hash of a leaf node is computed over
+#[derive(TlsSerialize)]
+struct LeafNodeHashInput<'a> {
+    node_index: u32,
+    leaf_node: Option<LeafNode<'a>>,
+}
+
+// struct {
+//     HPKEPublicKey public_key;
+//     opaque parent_hash<0..255>;
+//     uint32 unmerged_leaves<0..2^32-1>;
+// } ParentNode;
+/// The part of a non-blank parent node that its hash covers
+#[derive(TlsSerialize)]
+struct ParentNode<'a> {
+    public_key: &'a DhPublicKey,
+    parent_hash: &'a Digest,
+    #[tls(bound = "u32")]
+    unmerged_leaves: &'a [u32],
+}
+
+// struct {
+//     uint32 node_index;
+//     optional<ParentNode> parent_node;
+//     opaque left_hash<0..255>;
+//     opaque right_hash<0..255>;
+// } ParentNodeHashInput;
+/// What the hash of a parent node is computed over
+#[derive(TlsSerialize)]
+struct ParentNodeHashInput<'a> {
+    node_index: u32,
+    parent_node: Option<ParentNode<'a>>,
+    left_hash: Digest,
+    right_hash: Digest,
+}
+
+// struct {
+//     HPKEPublicKey public_key;
+//     opaque parent_hash<0..255>;
+// } LeafNode;
+/// A non-blank leaf node, as it's sent in the spec's encoding of a tree
+#[derive(TlsDeserialize, TlsSerialize)]
+struct WireLeafNode {
+    public_key: DhPublicKey,
+    parent_hash: Digest,
+}
+
+// struct {
+//     HPKEPublicKey public_key;
+//     opaque parent_hash<0..255>;
+//     uint32 unmerged_leaves<0..2^32-1>;
+// } ParentNode;
+/// A non-blank parent node, as it's sent in the spec's encoding of a tree
+#[derive(TlsDeserialize, TlsSerialize)]
+struct WireParentNode {
+    public_key: DhPublicKey,
+    parent_hash: Digest,
+    #[tls(bound = "u32", limit = "tree")]
+    unmerged_leaves: Vec<u32>,
+}
+
+// enum { reserved(0), leaf(1), parent(2), (255) } NodeType;
+//
+// struct {
+//     NodeType node_type;
+//     select (Node.node_type) {
+//         case leaf:   LeafNode leaf_node;
+//         case parent: ParentNode parent_node;
+//     };
+// } Node;
+/// A non-blank node, as it's sent in the spec's encoding of a tree. `Reserved` is never sent, it's
+/// only there so that leaves and parents get the spec's `NodeType` values.
+#[derive(TlsDeserialize, TlsSerialize)]
+#[tls(tag = "u8")]
+enum WireNode {
+    Reserved,
+    Leaf(WireLeafNode),
+    Parent(WireParentNode),
+}
+
+// optional<Node> ratchet_tree<1..2^32-1>;
+/// A whole tree in the spec's encoding. Unlike the encoding of `RatchetTree`, this has the unmerged
+/// leaves and parent hashes in it, so it's what other MLS implementations send and expect.
+#[derive(TlsDeserialize, TlsSerialize)]
+#[tls(bound = "u32", limit = "tree")]
+struct WireTree(Vec<Option<WireNode>>);
+
+// struct {
+//     uint32 node_index;
+//     optional<Node> node;
+// } PatchedNode;
+/// A node that a `TreePatch` sets, in the spec's encoding
+#[derive(TlsDeserialize, TlsSerialize)]
+struct PatchedNode {
+    node_index: u32,
+    node: Option<WireNode>,
+}
+
+// struct {
+//     uint32 num_nodes;
+//     PatchedNode nodes<0..2^32-1>;
+// } TreePatch;
+/// The nodes that changed between two versions of a group's public tree. A delivery service can
+/// send this after each epoch instead of the whole tree, to anyone who has the tree of the
+/// epoch before, e.g., from a ratchet_tree extension. The patched tree is checked against the new
+/// tree hash when it's applied, so the delivery service doesn't have to be trusted with it. This
+/// is serialized with `tls_ser` like everything else.
+#[derive(TlsDeserialize, TlsSerialize)]
+pub struct TreePatch {
+    /// The number of nodes in the new tree
+    num_nodes: u32,
+    /// The nodes that are new or differ from the old tree, in increasing order of node index
+    #[tls(bound = "u32", limit = "tree")]
+    nodes: Vec<PatchedNode>,
+}
+
+impl TreePatch {
+    /// Works out the patch that takes the public tree in the ratchet_tree extension `old` to the
+    /// one in `new`. Both extensions are what `GroupState::ratchet_tree_extension` returns, for
+    /// one group in two of its epochs.
+    ///
+    /// Returns: `Ok(patch)` on success. If either extension isn't a well-formed ratchet_tree
+    /// extension under `cs`, returns an `Error`.
+    pub fn between(
+        cs: &'static CipherSuite,
+        old: &Extension,
+        new: &Extension,
+    ) -> Result<TreePatch, Error> {
+        // Both trees came out of GroupState::ratchet_tree_extension, so they're under the limits
+        let old = RatchetTree::from_extension(cs, old, DecodeLimits::default())?;
+        let new = RatchetTree::from_extension(cs, new, DecodeLimits::default())?;
+        old.diff(&new)
+    }
+
+    /// Returns the number of nodes this patch sets
+    pub fn num_patched_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Applies this patch to the public tree in the ratchet_tree extension `old`, and checks the
+    /// result against `expected_tree_hash`, which should come from somewhere the recipient trusts,
+    /// e.g., a `GroupInfo` of the new epoch. The parent hashes of the patched tree are verified
+    /// too.
+    ///
+    /// Returns: `Ok(new)` on success, where `new` is a ratchet_tree extension of the patched tree.
+    /// If `old` isn't a well-formed ratchet_tree extension under `cs`, or the patch doesn't apply
+    /// to it, or the patched tree's hash isn't `expected_tree_hash`, or its parent hashes don't
+    /// verify, returns an `Error`.
+    pub fn apply(
+        self,
+        cs: &'static CipherSuite,
+        old: &Extension,
+        expected_tree_hash: &[u8],
+    ) -> Result<Extension, Error> {
+        // The old tree is the recipient's own copy, so it's under the limits
+        let old = RatchetTree::from_extension(cs, old, DecodeLimits::default())?;
+        let mut tree = old.apply_patch(cs, self)?;
+        let tree_hash = tree.tree_hash(cs)?;
+        if !bool::from(tree_hash.as_bytes().ct_eq(expected_tree_hash)) {
+            return Err(Error::ValidationError("Patched tree doesn't have the expected tree hash"));
+        }
+        tree.verify_parent_hashes(cs)?;
+        tree.to_extension()
+    }
+}
+
+// HPKEPublicKey original_sibling_resolution<0..2^32-1>;
+/// The keys that a path update through one child of a parent node was encrypted to, i.e., the
+/// original sibling resolution in that child's `ParentHashInput`
+#[derive(TlsDeserialize, TlsSerialize)]
+#[tls(bound = "u32", limit = "tree")]
+struct OriginalSiblingResolution(Vec<DhPublicKey>);
+
+// enum { blank(0), through_path(1), through_sibling(2), (255) } ParentHashWitnessType;
+//
+// struct {
+//     ParentHashWitnessType witness_type;
+//     select (ParentHashWitness.witness_type) {
+//         case blank:           struct {};
+//         case through_path:    OriginalSiblingResolution resolution;
+//         case through_sibling: OriginalSiblingResolution resolution;
+//     };
+// } ParentHashWitness;
+/// What a `LeafProof` says about how a parent node on the path got its key. A filled parent's key
+/// was set by a path update through one of its children, and that child's parent hash covers the
+/// original sibling resolution given here.
+#[derive(TlsDeserialize, TlsSerialize)]
+#[tls(tag = "u8")]
+enum ParentHashWitness {
+    /// The parent is blank, so there's nothing to check
+    Blank,
+    /// The path update came up through the child that's on the proven leaf's path
+    ThroughPath(OriginalSiblingResolution),
+    /// The path update came up through the other child, i.e., the step's sibling
+    ThroughSibling(OriginalSiblingResolution),
+}
+
+// struct {
+//     optional<Node> sibling;
+//     opaque sibling_child_hashes<0..2^32-1>;
+//     optional<ParentNode> parent;
+//     ParentHashWitness parent_hash_witness;
+// } LeafProofStep;
+/// One level of a `LeafProof`, going from a node on the leaf's direct path to its parent
+#[derive(TlsDeserialize, TlsSerialize)]
+struct LeafProofStep {
+    /// The sibling of the node on the path
+    sibling: Option<WireNode>,
+    /// The hashes of the sibling's left and right children. This is empty if the sibling is a
+    /// leaf.
+    #[tls(bound = "u32", limit = "tree")]
+    sibling_child_hashes: Vec<Digest>,
+    /// The parent of the node on the path and its sibling
+    parent: Option<WireParentNode>,
+    /// How the parent got its key
+    parent_hash_witness: ParentHashWitness,
+}
+
+// struct {
+//     uint32 num_leaves;
+//     uint32 leaf_index;
+//     optional<LeafNode> leaf;
+//     LeafProofStep steps<0..2^32-1>;
+// } LeafProof;
+/// A proof that a leaf with a given public key sits at a given position in a group's tree, made
+/// by `GroupState::leaf_proof`. It has the nodes on the leaf's direct path and their siblings,
+/// which is enough to recompute the tree hash and check the parent hashes along the path, so a
+/// client or auditor who only trusts the tree hash, e.g., from a `GroupInfo`, can check one member
+/// without getting the whole tree. It has no secrets in it, and it's serialized with `tls_ser`
+/// like everything else.
+#[derive(TlsDeserialize, TlsSerialize)]
+pub struct LeafProof {
+    /// The number of leaves in the tree
+    num_leaves: u32,
+    /// The leaf index, i.e., the roster index, of the proven leaf
+    leaf_index: u32,
+    /// The proven leaf. This is `None` if it's blank.
+    leaf: Option<WireLeafNode>,
+    /// One step per node on the leaf's direct path, going up from the leaf
+    #[tls(bound = "u32", limit = "tree")]
+    steps: Vec<LeafProofStep>,
+}
+
+impl LeafProof {
+    /// Returns the leaf index, i.e., the roster index, of the proven leaf
+    pub fn leaf_index(&self) -> u32 {
+        self.leaf_index
+    }
+
+    /// Returns the number of leaves in the tree the proof is about
+    pub fn num_leaves(&self) -> u32 {
+        self.num_leaves
+    }
+
+    /// Returns the public key at the proven leaf, or `None` if the leaf is blank
+    pub fn leaf_public_key(&self) -> Option<&[u8]> {
+        self.leaf.as_ref().map(|leaf| leaf.public_key.as_bytes())
+    }
+
+    /// Checks this proof against `tree_hash`, which should come from somewhere the verifier
+    /// trusts, e.g., a `GroupInfo` of the epoch the proof was made in. This recomputes the tree
+    /// hash from the proven leaf up, and checks that every filled parent on the leaf's path got
+    /// its key from a path update through one of its children.
+    ///
+    /// Returns: `Ok(())` if the proof is well-formed, the recomputed tree hash is `tree_hash`, and
+    /// the parent hashes verify. Otherwise, returns an `Error`.
+    pub fn verify(&self, cs: &'static CipherSuite, tree_hash: &[u8]) -> Result<(), Error> {
+        let num_leaves = self.num_leaves as usize;
+        if num_leaves == 0
+            || num_leaves > tree_math::MAX_LEAVES
+            || self.leaf_index >= self.num_leaves
+        {
+            return Err(Error::ValidationError("Leaf proof has an out-of-range leaf index"));
+        }
+        let leaf_idx = 2 * self.leaf_index as usize;
+        let path: Vec<usize> = tree_math::node_direct_path(leaf_idx, num_leaves).collect();
+        if path.len() != self.steps.len() {
+            return Err(Error::ValidationError("Leaf proof has the wrong number of steps"));
+        }
+
+        let leaf_input = LeafNodeHashInput {
+            node_index: leaf_idx as u32,
+            leaf_node: self.leaf.as_ref().map(|leaf| LeafNode {
+                public_key: &leaf.public_key,
+                parent_hash: &leaf.parent_hash,
+            }),
+        };
+        let mut hash = cs.hash_impl.hash_serializable(&leaf_input)?;
+        let mut path_parent_hash = self.leaf.as_ref().map(|leaf| &leaf.parent_hash);
+
+        for (&child_idx, step) in path.iter().zip(self.steps.iter()) {
+            let sibling_idx = tree_math::node_sibling(child_idx, num_leaves);
+            let parent_idx = tree_math::node_parent(child_idx, num_leaves);
+            let sibling_hash = step.sibling_hash(cs, sibling_idx)?;
+
+            if let Some(ref parent) = step.parent {
+                let (linked_parent_hash, resolution) = match step.parent_hash_witness {
+                    ParentHashWitness::Blank => {
+                        return Err(Error::ValidationError(
+                            "Leaf proof has no parent hash witness for a filled parent",
+                        ))
+                    }
+                    ParentHashWitness::ThroughPath(ref resolution) => {
+                        (path_parent_hash, resolution)
+                    }
+                    ParentHashWitness::ThroughSibling(ref resolution) => {
+                        (step.sibling_parent_hash(), resolution)
+                    }
+                };
+                let input = ParentHashInput {
+                    public_key: &parent.public_key,
+                    parent_hash: &parent.parent_hash,
+                    original_sibling_resolution: resolution.0.iter().collect(),
+                };
+                let expected = cs.hash_impl.hash_serializable(&input)?;
+                if !linked_parent_hash.map_or(false, |h| bool::from(h.ct_eq(&expected))) {
+                    return Err(Error::ValidationError(
+                        "Parent node's key wasn't set by a path through either of its children",
+                    ));
+                }
+            }
+
+            let (left_hash, right_hash) = if child_idx < parent_idx {
+                (hash, sibling_hash)
+            } else {
+                (sibling_hash, hash)
+            };
+            let input = ParentNodeHashInput {
+                node_index: parent_idx as u32,
+                parent_node: step.parent.as_ref().map(|parent| ParentNode {
+                    public_key: &parent.public_key,
+                    parent_hash: &parent.parent_hash,
+                    unmerged_leaves: &parent.unmerged_leaves,
+                }),
+                left_hash,
+                right_hash,
+            };
+            hash = cs.hash_impl.hash_serializable(&input)?;
+            path_parent_hash = step.parent.as_ref().map(|parent| &parent.parent_hash);
+        }
+
+        if !bool::from(hash.as_bytes().ct_eq(tree_hash)) {
+            return Err(Error::ValidationError("Leaf proof doesn't have the expected tree hash"));
+        }
+
+        Ok(())
+    }
+}
+
+impl LeafProofStep {
+    /// Computes the hash of this step's sibling, which is at `sibling_idx`, from the sibling and
+    /// its children's hashes
+    ///
+    /// Returns: `Ok(hash)` on success. If the sibling is of the wrong node type, or the wrong
+    /// number of child hashes are given, returns an `Error::ValidationError`.
+    fn sibling_hash(&self, cs: &'static CipherSuite, sibling_idx: usize) -> Result<Digest, Error> {
+        // The tree has at most 2^32 nodes, so the node index fits in a u32
+        let node_index = sibling_idx as u32;
+
+        if tree_math::node_level(sibling_idx) == 0 {
+            let leaf_node = match (&self.sibling, self.sibling_child_hashes.len()) {
+                (None, 0) => None,
+                (Some(WireNode::Leaf(leaf)), 0) => Some(LeafNode {
+                    public_key: &leaf.public_key,
+                    parent_hash: &leaf.parent_hash,
+                }),
+                _ => return Err(Error::ValidationError("Leaf proof has a malformed sibling")),
+            };
+            let input = LeafNodeHashInput {
+                node_index,
+                leaf_node,
+            };
+            cs.hash_impl.hash_serializable(&input)
+        } else {
+            let parent_node = match (&self.sibling, self.sibling_child_hashes.len()) {
+                (None, 2) => None,
+                (Some(WireNode::Parent(parent)), 2) => Some(ParentNode {
+                    public_key: &parent.public_key,
+                    parent_hash: &parent.parent_hash,
+                    unmerged_leaves: &parent.unmerged_leaves,
+                }),
+                _ => return Err(Error::ValidationError("Leaf proof has a malformed sibling")),
+            };
+            let input = ParentNodeHashInput {
+                node_index,
+                parent_node,
+                left_hash: self.sibling_child_hashes[0].clone(),
+                right_hash: self.sibling_child_hashes[1].clone(),
+            };
+            cs.hash_impl.hash_serializable(&input)
+        }
+    }
+
+    /// Returns the parent hash of this step's sibling, or `None` if it's blank
+    fn sibling_parent_hash(&self) -> Option<&Digest> {
+        match self.sibling {
+            Some(WireNode::Leaf(ref leaf)) => Some(&leaf.parent_hash),
+            Some(WireNode::Parent(ref parent)) => Some(&parent.parent_hash),
+            _ => None,
+        }
+    }
+}
+
+/// How a node on a member's direct path links to its parent in the parent-hash chain, as
+/// reported by `GroupState::audit_member`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParentHashLink {
+    /// The node is the root, so it has no parent to link to
+    Root,
+    /// The node or its parent is blank, so there's no link to check
+    Blank,
+    /// The node's parent hash covers its parent, i.e., its parent's key was set by a path update
+    /// through it
+    Verified,
+    /// The node's parent hash doesn't cover its parent, but its sibling's does, i.e., its
+    /// parent's key was set by a path update through the other side of the tree
+    ThroughSibling,
+    /// Neither the node nor its sibling has a parent hash that covers their parent. This doesn't
+    /// happen in a tree whose parent hashes verify.
+    Broken,
+}
+
+/// What `GroupState::audit_member` found out about one node on a member's direct path
+#[derive(Clone, Debug)]
+pub struct PathNodeAudit {
+    pub(crate) node_index: u32,
+    pub(crate) is_blank: bool,
+    pub(crate) link: ParentHashLink,
+    pub(crate) unmerged_leaves: Vec<u32>,
+    pub(crate) member_is_unmerged: bool,
+    pub(crate) last_changed_epoch: u32,
+}
+
+impl PathNodeAudit {
+    /// Returns the index of this node in the tree
+    pub fn node_index(&self) -> u32 {
+        self.node_index
+    }
+
+    /// Returns whether this node is blank
+    pub fn is_blank(&self) -> bool {
+        self.is_blank
+    }
+
+    /// Returns how this node links to its parent in the parent-hash chain
+    pub fn link(&self) -> ParentHashLink {
+        self.link
+    }
+
+    /// Returns the leaf indices of this node's unmerged leaves, i.e., the leaves below it that
+    /// were added since its key was set and so don't know it
+    pub fn unmerged_leaves(&self) -> &[u32] {
+        &self.unmerged_leaves
+    }
+
+    /// Returns whether the audited member's leaf is one of this node's unmerged leaves
+    pub fn member_is_unmerged(&self) -> bool {
+        self.member_is_unmerged
+    }
+
+    /// Returns the epoch that this node last changed in, as far as this member has seen. Nodes
+    /// that haven't changed since this member joined have the epoch they joined in.
+    pub fn last_changed_epoch(&self) -> u32 {
+        self.last_changed_epoch
+    }
+}
+
+/// The state of the parent-hash chain along a member's direct path, as returned by
+/// `GroupState::audit_member`. This is meant for security reviews, and for showing users how much
+/// the group's view of a member can be trusted. It has no secrets in it.
+#[derive(Clone, Debug)]
+pub struct MemberAudit {
+    pub(crate) roster_index: u32,
+    pub(crate) path: Vec<PathNodeAudit>,
+}
+
+impl MemberAudit {
+    /// Returns the roster index of the audited member
+    pub fn roster_index(&self) -> u32 {
+        self.roster_index
+    }
+
+    /// Returns what was found out about every node on the member's extended direct path, going up
+    /// from their leaf to the root
+    pub fn path(&self) -> &[PathNodeAudit] {
+        &self.path
+    }
+
+    /// Returns whether no link on the member's path is `ParentHashLink::Broken`
+    pub fn chain_verifies(&self) -> bool {
+        self.path.iter().all(|node| node.link != ParentHashLink::Broken)
+    }
+}
+
+/// The public part of a node, as `NodeHistory` compares it
+#[derive(Clone, PartialEq)]
+struct NodeFingerprint {
+    public_key: Vec<u8>,
+    parent_hash: Vec<u8>,
+    unmerged_leaves: Vec<u32>,
+}
+
+/// The epoch that each node of a group's tree last changed in, along with what the node looked
+/// like then, in node order. Only the public part of a node counts. A member only sees the changes
+/// made from the epoch they joined in on, so nodes that are older than that are recorded as
+/// changing in that epoch. This is local bookkeeping, so it's never sent.
+#[derive(Clone, Default)]
+pub(crate) struct NodeHistory(Vec<(u32, Option<NodeFingerprint>)>);
+
+impl NodeHistory {
+    /// Makes a history of `tree` in which every node last changed in `epoch`
+    pub(crate) fn new(tree: &RatchetTree, epoch: u32) -> NodeHistory {
+        let mut history = NodeHistory::default();
+        history.record(tree, epoch);
+        history
+    }
+
+    /// Records that every node of `tree` that's new, or differs from what it was the last time this
+    /// was called, changed in `epoch`
+    pub(crate) fn record(&mut self, tree: &RatchetTree, epoch: u32) {
+        self.0.truncate(tree.size());
+        for idx in 0..tree.size() {
+            let fingerprint = tree.fingerprint(idx);
+            match self.0.get_mut(idx) {
+                Some(entry) if entry.1 == fingerprint => (),
+                Some(entry) => *entry = (epoch, fingerprint),
+                None => self.0.push((epoch, fingerprint)),
+            }
+        }
+    }
+
+    /// Makes a history of `tree` in which the node at every index last changed in the epoch at
+    /// the same index of `epochs`. This is how the history of a snapshot is made back.
+    ///
+    /// Returns: `Ok(history)` on success. If there isn't an epoch for exactly every node, returns
+    /// an `Error::ValidationError`.
+    #[cfg(feature = "snapshots")]
+    pub(crate) fn from_epochs(tree: &RatchetTree, epochs: &[u32]) -> Result<NodeHistory, Error> {
+        if epochs.len() != tree.size() {
+            return Err(Error::ValidationError("Node history doesn't match the tree"));
+        }
+        let entries = epochs.iter().enumerate().map(|(idx, &e)| (e, tree.fingerprint(idx)));
+        Ok(NodeHistory(entries.collect()))
+    }
+
+    /// Returns the epoch that the node at `idx` last changed in
+    ///
+    /// Panics: If `idx` is out of bounds
+    pub(crate) fn last_changed_epoch(&self, idx: usize) -> u32 {
+        self.0[idx].0
+    }
+
+    /// Returns the epoch that every node last changed in, in node order
+    #[cfg(feature = "snapshots")]
+    pub(crate) fn epochs(&self) -> Vec<u32> {
+        self.0.iter().map(|(epoch, _)| *epoch).collect()
+    }
+}
+
+/// How a group keeps the nodes of its tree in memory. This changes nothing about how the group
+/// behaves or what it sends, only how much memory and time its tree operations take.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TreeStorage {
+    /// Every node is kept in a vector, blank or not. This is the default, and it's the fastest
+    /// when most nodes are filled, which is the case in groups whose members update regularly.
+    Dense,
+    /// Only the filled nodes are kept, in a map. Every node lookup costs a hash, but a group of
+    /// tens of thousands of members where most nodes are blank, e.g., right after a big batch of
+    /// Adds or Removes, takes a fraction of the memory.
+    Sparse,
+}
+
+/// How `debug_dump` renders a tree
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DumpFormat {
+    /// One line per node, in node order, indented by the node's level. This is for reading in a
+    /// terminal or a log.
+    Ascii,
+    /// A graphviz `digraph`, with an edge from every parent to its children. Blank nodes are
+    /// dashed.
+    Graphviz,
+}
+
+/// How many bytes of each key and hash `debug_dump` shows. That's enough to tell them apart at a
+/// glance, and to match them up with what another member dumped.
+const DUMP_PREFIX_LEN: usize = 4;
+
+/// Renders the tree of the given group for debugging. Every node is labeled with its index, the
+/// identity of its member if it's a leaf, its unmerged leaves, and the first few bytes of its
+/// public key, parent hash, and node hash. Private keys and path secrets are never shown, so a
+/// dump is safe to put in a log or a bug report.
+///
+/// Returns: `Ok(dump)` on success. If the group's tree can't be hashed, returns an `Error`.
+pub fn debug_dump(group_state: &GroupState, format: DumpFormat) -> Result<String, Error> {
+    let identities: Vec<Option<&[u8]>> = group_state
+        .roster
+        .0
+        .iter()
+        .map(|entry| entry.as_ref().map(|cred| cred.get_identity().as_bytes()))
+        .collect();
+    // Hashing fills the tree's hash cache, so this works on a copy
+    group_state.tree.clone().dump(group_state.cs, &identities, format)
+}
+
+/// Returns the first `DUMP_PREFIX_LEN` bytes of `bytes` in hex, followed by an ellipsis if there
+/// are more. Empty input is shown as "-".
+fn hex_prefix(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    let prefix: String = bytes.iter().take(DUMP_PREFIX_LEN).map(|b| format!("{:02x}", b)).collect();
+    if bytes.len() > DUMP_PREFIX_LEN {
+        format!("{}..", prefix)
+    } else {
+        prefix
+    }
 }
 
 /// A left-balanced binary tree of `RatchetTreeNode`s
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, TlsDeserialize, TlsSerialize)]
 #[cfg_attr(test, derive(Debug))]
 pub(crate) struct RatchetTree {
-    #[serde(rename = "nodes__bound_u32")]
-    pub(crate) nodes: Vec<RatchetTreeNode>,
+    #[tls(bound = "u32", limit = "tree")]
+    pub(crate) nodes: NodeStore,
+
+    /// The hash of every node whose hash has been computed since it or any of its descendants
+    /// last changed, indexed like `nodes`. This is what keeps `tree_hash` from rehashing the
+    /// whole tree when only one path changed. The methods here that change nodes forget the
+    /// hashes that cover them, so anything else that changes a node in a way that the tree hash
+    /// covers has to go through `set` or `update`.
+    #[tls(skip)]
+    hash_cache: Vec<Option<Digest>>,
+
+    /// The resolution of every blank parent node whose resolution has been computed since any of
+    /// its descendants last changed, indexed like `nodes`. This is what keeps `resolution` from
+    /// walking the same blank subtrees for every path that's encrypted or checked. A resolution
+    /// depends on the same nodes as a hash does, so it's forgotten along with the hash.
+    #[tls(skip)]
+    resolution_cache: Vec<Option<Vec<usize>>>,
 }
 
 impl RatchetTree {
+    /// Makes a tree out of the given nodes, in node order. The nodes are kept in
+    /// `TreeStorage::Dense`.
+    pub(crate) fn new_from_nodes(nodes: Vec<RatchetTreeNode>) -> RatchetTree {
+        RatchetTree {
+            nodes: nodes.into(),
+            hash_cache: Vec::new(),
+            resolution_cache: Vec::new(),
+        }
+    }
+
+    /// Returns how this tree keeps its nodes
+    pub(crate) fn storage(&self) -> TreeStorage {
+        self.nodes.storage()
+    }
+
+    /// Changes how this tree keeps its nodes. The nodes themselves, and the cached hashes and
+    /// resolutions, stay the same.
+    pub(crate) fn set_storage(&mut self, storage: TreeStorage) {
+        let nodes = std::mem::replace(&mut self.nodes, NodeStore::Dense(Vec::new()));
+        self.nodes = nodes.into_storage(storage);
+    }
+
     /// Returns the number of nodes in the tree
     pub(crate) fn size(&self) -> usize {
         self.nodes.len()
     }
 
+    /// Returns the number of leaves in the tree, blank or not
+    pub(crate) fn num_leaves(&self) -> usize {
+        if self.nodes.is_empty() {
+            0
+        } else {
+            tree_math::num_leaves_in_tree(self.size())
+        }
+    }
+
     /// Returns the node at the given index
     pub(crate) fn get(&self, idx: usize) -> Option<&RatchetTreeNode> {
         self.nodes.get(idx)
     }
 
-    /// Returns a mutable reference to the node at the given index
-    pub(crate) fn get_mut(&mut self, idx: usize) -> Option<&mut RatchetTreeNode> {
-        self.nodes.get_mut(idx)
+    /// Sets the node at the given index, and forgets the cached hashes and resolutions of it and
+    /// its ancestors
+    ///
+    /// Returns: An `Error::TreeError` if `idx` is out of range
+    pub(crate) fn set(&mut self, idx: usize, node: RatchetTreeNode) -> Result<(), Error> {
+        self.update(idx, |old| *old = node)
+    }
+
+    /// Calls `f` on the node at the given index, blank or not, and keeps the changed node. The
+    /// cached hashes and resolutions of the node and its ancestors are forgotten.
+    ///
+    /// Returns: `Ok` of whatever `f` returns, or an `Error::TreeError` if `idx` is out of range
+    pub(crate) fn update<T, F>(&mut self, idx: usize, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut RatchetTreeNode) -> T,
+    {
+        if idx >= self.size() {
+            return Err(Error::TreeError("Node index is out of range"));
+        }
+        self.forget_cached(idx);
+        Ok(self.nodes.update(idx, f).expect("node index was checked above"))
+    }
+
+    /// Forgets the cached hashes and resolutions of the node at `idx` and of all of its ancestors,
+    /// since they all depend on it
+    ///
+    /// Requires: `idx < self.size()`
+    fn forget_cached(&mut self, idx: usize) {
+        if self.hash_cache.is_empty() && self.resolution_cache.is_empty() {
+            // Nothing is cached, so there's nothing to forget
+            return;
+        }
+
+        let num_leaves = self.num_leaves();
+        for i in tree_math::node_extended_direct_path(idx, num_leaves) {
+            if let Some(cached) = self.hash_cache.get_mut(i) {
+                *cached = None;
+            }
+            if let Some(cached) = self.resolution_cache.get_mut(i) {
+                *cached = None;
+            }
+        }
+    }
+
+    /// Forgets every cached hash and resolution
+    fn clear_cached(&mut self) {
+        self.hash_cache.clear();
+        self.resolution_cache.clear();
     }
 
     // It turns out that appending to the tree in this way preserves the left-balanced property
@@ -202,6 +1030,25 @@ impl RatchetTree {
             self.nodes.push(RatchetTreeNode::Blank);
             self.nodes.push(node);
         }
+
+        // The only old nodes whose subtrees changed are the ancestors of the new leaf
+        self.forget_cached(self.size() - 1);
+    }
+
+    /// Grows the tree, if it has fewer than `num_leaves` leaves, to the smallest power of two
+    /// number of leaves that's at least `num_leaves`. The new leaves are all blank. Growing by
+    /// more than one leaf leaves room for the next few Adds, so the tree doesn't change shape on
+    /// every one of them.
+    pub(crate) fn extend_to_fit(&mut self, num_leaves: usize) {
+        let old_num_leaves = self.num_leaves();
+        if old_num_leaves >= num_leaves {
+            return;
+        }
+
+        // add_leaf_node keeps everything in place, so the new leaves all go on the right
+        for _ in old_num_leaves..num_leaves.next_power_of_two() {
+            self.add_leaf_node(RatchetTreeNode::Blank);
+        }
     }
 
     /// Blanks out the direct path of the given node, as well as the root node
@@ -212,8 +1059,9 @@ impl RatchetTree {
         // Blank the extended direct path (direct path + root node)
         for i in direct_path {
             // No need to check index here. By construction, there's no way this is out of bounds
-            self.nodes[i] = RatchetTreeNode::Blank;
+            self.nodes.set(i, RatchetTreeNode::Blank);
         }
+        self.forget_cached(start_idx);
     }
 
     // This always produces a valid tree. To see this, note that truncating to a leaf node when
@@ -236,42 +1084,876 @@ impl RatchetTree {
 
         match last_nonblank_leaf {
             // If there are no nonempty entries in the roster, clear it
-            None => self.nodes.clear(),
+            None => {
+                self.nodes.clear();
+                self.clear_cached();
+            }
             Some(i) => {
                 // This can't fail, because i is an index
                 let num_elements_to_retain = i + 1;
-                self.nodes.truncate(num_elements_to_retain)
+                self.nodes.truncate(num_elements_to_retain);
+                self.hash_cache.truncate(num_elements_to_retain);
+                self.resolution_cache.truncate(num_elements_to_retain);
+                // The only nodes whose subtrees lost leaves are the ancestors of the new last leaf
+                self.forget_cached(i);
             }
         }
     }
 
     /// Returns the indices of the resolution of a given node: this an ordered sequence of minimal
     /// set of non-blank nodes that collectively cover (A "covers" B iff A is an ancestor of B) all
-    /// non-blank descendants of the given node. The ordering is ascending by node index.
-    pub(crate) fn resolution(&self, idx: usize) -> Vec<usize> {
-        // Helper function that accumulates the resolution recursively
-        fn helper(tree: &RatchetTree, i: usize, acc: &mut Vec<usize>) {
-            if let RatchetTreeNode::Blank = tree.nodes[i] {
-                if tree_math::node_level(i) == 0 {
-                    // The resolution of a blank leaf node is the empty list
+    /// non-blank descendants of the given node. Unmerged leaves aren't covered by their ancestors,
+    /// so each one comes right after the node it's unmerged at. Otherwise, the ordering is
+    /// ascending by node index. The resolutions of blank parent nodes are cached, so only the
+    /// ones that changed since the last call are recomputed.
+    pub(crate) fn resolution(&mut self, idx: usize) -> Vec<usize> {
+        self.resolution_cache.resize(self.size(), None);
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+        self.node_resolution(idx, num_leaves)
+    }
+
+    /// Returns the resolution of the node at `idx`, as described in `resolution`, and caches it
+    /// if it's a blank parent node
+    ///
+    /// Requires: `self.resolution_cache.len() == self.size()`
+    fn node_resolution(&mut self, idx: usize, num_leaves: usize) -> Vec<usize> {
+        if self.nodes[idx].is_filled() {
+            // The resolution of a non-blank node is a list containing the node itself, followed
+            // by its unmerged leaves. That's quick to get, so it's not cached.
+            let unmerged_leaves = self.nodes[idx].unmerged_leaves();
+            let mut ret = Vec::with_capacity(1 + unmerged_leaves.len());
+            ret.push(idx);
+            ret.extend(unmerged_leaves.iter().map(|&leaf_idx| 2 * leaf_idx as usize));
+            return ret;
+        }
+        if tree_math::node_level(idx) == 0 {
+            // The resolution of a blank leaf node is the empty list
+            return Vec::new();
+        }
+        if let Some(ref cached) = self.resolution_cache[idx] {
+            return cached.clone();
+        }
+
+        // The resolution of a blank intermediate node is the result of concatinating the
+        // resolution of its left child with the resolution of its right child, in that order
+        let mut ret = self.node_resolution(tree_math::node_left_child(idx), num_leaves);
+        ret.extend(self.node_resolution(tree_math::node_right_child(idx, num_leaves), num_leaves));
+        self.resolution_cache[idx] = Some(ret.clone());
+        ret
+    }
+
+    /// Returns the tree hash of this tree, i.e., the hash of its root. A leaf's hash covers its
+    /// index, public key, and parent hash. A parent's hash covers its index, public key, parent
+    /// hash, unmerged leaves, and the hashes of its children. Private keys aren't covered, so
+    /// everyone in a group computes the same tree hash. Node hashes are cached, so only the ones
+    /// that changed since the last call are recomputed.
+    ///
+    /// Returns: `Ok(tree_hash)` on success. If the tree is empty, returns an `Error::TreeError`.
+    pub(crate) fn tree_hash(&mut self, cs: &'static CipherSuite) -> Result<Digest, Error> {
+        if self.nodes.is_empty() {
+            return Err(Error::TreeError("Cannot hash an empty tree"));
+        }
+
+        self.hash_cache.resize(self.size(), None);
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+        self.node_hash(cs, tree_math::root_idx(num_leaves), num_leaves)
+    }
+
+    /// Returns the hash of the subtree rooted at `idx`, as described in `tree_hash`, and caches
+    /// it along with the hashes of its descendants
+    ///
+    /// Requires: `self.hash_cache.len() == self.size()`
+    fn node_hash(
+        &mut self,
+        cs: &'static CipherSuite,
+        idx: usize,
+        num_leaves: usize,
+    ) -> Result<Digest, Error> {
+        if let Some(ref cached) = self.hash_cache[idx] {
+            return Ok(cached.clone());
+        }
+
+        let hash = self.uncached_node_hash(cs, idx, num_leaves)?;
+        self.hash_cache[idx] = Some(hash.clone());
+        Ok(hash)
+    }
+
+    /// Computes the hash of the node at `idx` from the node itself and the cached hashes of its
+    /// children
+    fn uncached_node_hash(
+        &mut self,
+        cs: &'static CipherSuite,
+        idx: usize,
+        num_leaves: usize,
+    ) -> Result<Digest, Error> {
+        // The tree has at most 2^32 nodes, so the node index fits in a u32
+        let node_index = idx as u32;
+
+        if tree_math::node_level(idx) == 0 {
+            let leaf_node = match self.nodes[idx] {
+                RatchetTreeNode::Blank => None,
+                RatchetTreeNode::Filled {
+                    ref public_key,
+                    ref parent_hash,
+                    ..
+                } => Some(LeafNode {
+                    public_key,
+                    parent_hash,
+                }),
+            };
+            let input = LeafNodeHashInput {
+                node_index,
+                leaf_node,
+            };
+            cs.hash_impl.hash_serializable(&input)
+        } else {
+            let left_hash = self.node_hash(cs, tree_math::node_left_child(idx), num_leaves)?;
+            let right_idx = tree_math::node_right_child(idx, num_leaves);
+            let right_hash = self.node_hash(cs, right_idx, num_leaves)?;
+            let node = &self.nodes[idx];
+            let parent_node = match node {
+                RatchetTreeNode::Blank => None,
+                RatchetTreeNode::Filled {
+                    ref public_key,
+                    ref parent_hash,
+                    ref unmerged_leaves,
+                    ..
+                } => Some(ParentNode {
+                    public_key,
+                    parent_hash,
+                    unmerged_leaves,
+                }),
+            };
+            let input = ParentNodeHashInput {
+                node_index,
+                parent_node,
+                left_hash,
+                right_hash,
+            };
+            cs.hash_impl.hash_serializable(&input)
+        }
+    }
+
+    /// Renders this tree as described in `debug_dump`. `identities` is indexed by leaf index, and
+    /// has the identity of the member at each leaf, if there is one.
+    ///
+    /// Returns: `Ok(dump)` on success. If the tree can't be hashed, returns an `Error`.
+    fn dump(
+        &mut self,
+        cs: &'static CipherSuite,
+        identities: &[Option<&[u8]>],
+        format: DumpFormat,
+    ) -> Result<String, Error> {
+        if self.nodes.is_empty() {
+            return Ok(match format {
+                DumpFormat::Ascii => "empty tree\n".to_string(),
+                DumpFormat::Graphviz => "digraph tree {\n}\n".to_string(),
+            });
+        }
+
+        let tree_hash = self.tree_hash(cs)?;
+        let num_leaves = self.num_leaves();
+        let labels = (0..self.size()).map(|idx| {
+            let level = tree_math::node_level(idx);
+            let mut label = format!(
+                "{} {}",
+                if level == 0 {
+                    "leaf"
                 } else {
-                    // The resolution of a blank intermediate node is the result of concatinating
-                    // the resolution of its left child with the resolution of its right child, in
-                    // that order
-                    let num_leaves = tree_math::num_leaves_in_tree(tree.nodes.len());
-                    helper(tree, tree_math::node_left_child(i), acc);
-                    helper(tree, tree_math::node_right_child(i, num_leaves), acc);
+                    "parent"
+                },
+                idx
+            );
+            if level == 0 {
+                match identities.get(idx / 2).cloned().flatten() {
+                    Some(identity) => {
+                        label.push_str(&format!(" {:?}", String::from_utf8_lossy(identity)))
+                    }
+                    None => label.push_str(" (no member)"),
+                }
+            }
+            match self.nodes[idx] {
+                RatchetTreeNode::Blank => label.push_str(" blank"),
+                RatchetTreeNode::Filled {
+                    ref public_key,
+                    ref parent_hash,
+                    ref unmerged_leaves,
+                    ..
+                } => {
+                    label.push_str(&format!(
+                        " pk={} ph={}",
+                        hex_prefix(public_key.as_bytes()),
+                        hex_prefix(parent_hash.as_bytes())
+                    ));
+                    if !unmerged_leaves.is_empty() {
+                        label.push_str(&format!(" unmerged={:?}", unmerged_leaves));
+                    }
+                }
+            }
+            // tree_hash just cached the hash of every node
+            let node_hash = self.hash_cache[idx].as_ref().map_or(&[][..], |h| h.as_bytes());
+            label.push_str(&format!(" h={}", hex_prefix(node_hash)));
+            label
+        });
+
+        let mut out = String::new();
+        match format {
+            DumpFormat::Ascii => {
+                out.push_str(&format!(
+                    "tree of {} leaves, tree hash {}\n",
+                    num_leaves,
+                    hex_prefix(tree_hash.as_bytes())
+                ));
+                let root_level = tree_math::node_level(tree_math::root_idx(num_leaves));
+                for (idx, label) in labels.enumerate() {
+                    let indent = 2 * (root_level - tree_math::node_level(idx));
+                    out.push_str(&format!("{:indent$}{}\n", "", label, indent = indent));
+                }
+            }
+            DumpFormat::Graphviz => {
+                out.push_str("digraph tree {\n");
+                for (idx, label) in labels.enumerate() {
+                    let style = if self.nodes[idx].is_filled() {
+                        "solid"
+                    } else {
+                        "dashed"
+                    };
+                    out.push_str(&format!("  n{} [label={:?}, style={}];\n", idx, label, style));
+                }
+                for idx in (0..self.size()).filter(|&idx| tree_math::node_level(idx) > 0) {
+                    let left = tree_math::node_left_child(idx);
+                    let right = tree_math::node_right_child(idx, num_leaves);
+                    out.push_str(&format!("  n{} -> n{};\n  n{} -> n{};\n", idx, left, idx, right));
+                }
+                out.push_str("}\n");
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Records the leaf at `leaf_tree_idx` as unmerged at every non-blank node above it, up to
+    /// and including the root. This is how a new member is put in the tree without blanking the
+    /// nodes whose keys they don't know.
+    pub(crate) fn add_unmerged_leaf(&mut self, leaf_tree_idx: usize) {
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+        // The leaf itself starts the extended direct path, and it isn't unmerged at itself
+        let ancestors = tree_math::node_extended_direct_path(leaf_tree_idx, num_leaves).skip(1);
+        // The tree has at most 2^32 leaves, so the leaf index fits in a u32
+        let leaf_idx = (leaf_tree_idx / 2) as u32;
+        self.forget_cached(leaf_tree_idx);
+
+        for i in ancestors {
+            // No need to check index here. By construction, there's no way this is out of bounds
+            if let RatchetTreeNode::Filled {
+                ref mut unmerged_leaves,
+                ..
+            } = self.nodes[i]
+            {
+                unmerged_leaves.push(leaf_idx);
+            }
+        }
+    }
+
+    /// Returns the unmerged leaves of every node in this tree, for sending alongside it
+    pub(crate) fn unmerged_leaves(&self) -> UnmergedLeaves {
+        let nodes =
+            self.nodes.iter().map(|node| NodeUnmergedLeaves(node.unmerged_leaves().to_vec()));
+        UnmergedLeaves(nodes.collect())
+    }
+
+    /// Sets the unmerged leaves of every node in this tree to the ones that were sent alongside
+    /// it
+    ///
+    /// Returns: `Ok(())` on success. If `unmerged` isn't the same size as the tree, or it has
+    /// unmerged leaves at a leaf or blank node, or at a node that isn't above them, or it has
+    /// blank or repeated unmerged leaves, returns an `Error::ValidationError`.
+    pub(crate) fn set_unmerged_leaves(&mut self, unmerged: UnmergedLeaves) -> Result<(), Error> {
+        self.clear_cached();
+        if unmerged.0.len() != self.size() {
+            return Err(Error::ValidationError("Unmerged leaves don't match the tree's size"));
+        }
+        if unmerged.0.iter().all(|leaves| leaves.0.is_empty()) {
+            // Nothing to check, and the tree might be empty, which tree_math doesn't like
+            return Ok(());
+        }
+
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+        for (i, NodeUnmergedLeaves(leaves)) in unmerged.0.iter().enumerate() {
+            if leaves.is_empty() {
+                continue;
+            }
+            if tree_math::node_level(i) == 0 || !self.nodes[i].is_filled() {
+                return Err(Error::ValidationError("Unmerged leaves at a leaf or blank node"));
+            }
+            for (j, &leaf_idx) in leaves.iter().enumerate() {
+                let leaf_tree_idx = 2 * leaf_idx as usize;
+                let is_filled_descendant = leaf_tree_idx < self.size()
+                    && self.nodes[leaf_tree_idx].is_filled()
+                    && tree_math::is_ancestor(i, leaf_tree_idx, num_leaves);
+                if !is_filled_descendant || leaves[..j].contains(&leaf_idx) {
+                    return Err(Error::ValidationError(
+                        "Unmerged leaf is repeated, blank, or not below its node",
+                    ));
                 }
+            }
+        }
+
+        // Only filled nodes can have unmerged leaves, and that was checked above
+        let mut unmerged = unmerged.0;
+        for (i, node) in self.nodes.iter_filled_mut() {
+            if let RatchetTreeNode::Filled {
+                ref mut unmerged_leaves,
+                ..
+            } = node
+            {
+                *unmerged_leaves = std::mem::take(&mut unmerged[i].0);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the parent hash that the node at `idx` would have if its parent's key was set by a
+    /// path update that came up through it. See `ParentHashInput` for what this covers.
+    ///
+    /// Requires: `idx` isn't the root
+    ///
+    /// Returns: `Ok(parent_hash)` on success. If the parent of `idx` is blank, returns an
+    /// `Error::TreeError`.
+    fn expected_parent_hash(
+        &mut self,
+        cs: &'static CipherSuite,
+        idx: usize,
+        num_leaves: usize,
+    ) -> Result<Digest, Error> {
+        let original_sibling_resolution = self.original_sibling_resolution(idx, num_leaves);
+        let parent = &self.nodes[tree_math::node_parent(idx, num_leaves)];
+        let (public_key, parent_hash) = match parent {
+            RatchetTreeNode::Blank => {
+                return Err(Error::TreeError("Blank nodes don't have children's parent hashes"))
+            }
+            RatchetTreeNode::Filled {
+                ref public_key,
+                ref parent_hash,
+                ..
+            } => (public_key, parent_hash),
+        };
+
+        // We can unwrap() here because the resolution only has indices of nodes that are
+        // non-blank
+        let original_sibling_resolution = original_sibling_resolution
+            .into_iter()
+            .map(|i| self.nodes[i].get_public_key().unwrap())
+            .collect();
+
+        let input = ParentHashInput {
+            public_key,
+            parent_hash,
+            original_sibling_resolution,
+        };
+        cs.hash_impl.hash_serializable(&input)
+    }
+
+    /// Returns the resolution of the sibling of the node at `idx`, minus the unmerged leaves of
+    /// their parent. See `ParentHashInput` for what this is.
+    ///
+    /// Requires: `idx` isn't the root
+    fn original_sibling_resolution(&mut self, idx: usize, num_leaves: usize) -> Vec<usize> {
+        let sibling_resolution = self.resolution(tree_math::node_sibling(idx, num_leaves));
+        let parent = &self.nodes[tree_math::node_parent(idx, num_leaves)];
+
+        // Leaves that were added after the parent's key was set weren't encrypted to, so they
+        // don't count
+        sibling_resolution
+            .into_iter()
+            .filter(|&i| !parent.unmerged_leaves().iter().any(|&leaf| 2 * leaf as usize == i))
+            .collect()
+    }
+
+    /// Makes a `LeafProof` of the leaf at `leaf_tree_idx`. See `LeafProof` for what's in it.
+    ///
+    /// Requires: `leaf_tree_idx` is a leaf of this tree
+    ///
+    /// Returns: `Ok(proof)` on success. If a filled parent on the leaf's path didn't get its key
+    /// through either of its children, returns an `Error::TreeError`.
+    pub(crate) fn leaf_proof(
+        &mut self,
+        cs: &'static CipherSuite,
+        leaf_tree_idx: usize,
+    ) -> Result<LeafProof, Error> {
+        // This fills the hash cache, so the node hashes below are all cached
+        self.tree_hash(cs)?;
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+
+        let leaf = match self.wire_node(leaf_tree_idx) {
+            Some(WireNode::Leaf(leaf)) => Some(leaf),
+            _ => None,
+        };
+        let path: Vec<usize> = tree_math::node_direct_path(leaf_tree_idx, num_leaves).collect();
+        let mut steps = Vec::with_capacity(path.len());
+        for child_idx in path {
+            let sibling_idx = tree_math::node_sibling(child_idx, num_leaves);
+            let parent_idx = tree_math::node_parent(child_idx, num_leaves);
+
+            let sibling_child_hashes = if tree_math::node_level(sibling_idx) == 0 {
+                Vec::new()
             } else {
-                // The resolution of a non-blank node is a one element list containing the node
-                // itself
-                acc.push(i);
+                let left_idx = tree_math::node_left_child(sibling_idx);
+                let right_idx = tree_math::node_right_child(sibling_idx, num_leaves);
+                vec![
+                    self.node_hash(cs, left_idx, num_leaves)?,
+                    self.node_hash(cs, right_idx, num_leaves)?,
+                ]
+            };
+            let parent = match self.wire_node(parent_idx) {
+                Some(WireNode::Parent(parent)) => Some(parent),
+                _ => None,
+            };
+            let parent_hash_witness = if parent.is_none() {
+                ParentHashWitness::Blank
+            } else if self.has_path_parent_hash(cs, child_idx, num_leaves)? {
+                ParentHashWitness::ThroughPath(self.original_sibling_keys(child_idx, num_leaves))
+            } else if self.has_path_parent_hash(cs, sibling_idx, num_leaves)? {
+                ParentHashWitness::ThroughSibling(
+                    self.original_sibling_keys(sibling_idx, num_leaves),
+                )
+            } else {
+                return Err(Error::TreeError(
+                    "Parent node's key wasn't set by a path through either of its children",
+                ));
+            };
+
+            steps.push(LeafProofStep {
+                sibling: self.wire_node(sibling_idx),
+                sibling_child_hashes,
+                parent,
+                parent_hash_witness,
+            });
+        }
+
+        Ok(LeafProof {
+            // The tree has at most 2^32 nodes, so these fit in a u32
+            num_leaves: num_leaves as u32,
+            leaf_index: (leaf_tree_idx / 2) as u32,
+            leaf,
+            steps,
+        })
+    }
+
+    /// Works out what `GroupState::audit_member` reports about the member at the leaf at
+    /// `leaf_tree_idx`. `history` is the group's `NodeHistory` of this tree.
+    ///
+    /// Requires: `leaf_tree_idx` is a leaf of this tree, and `history` has an entry for every node
+    ///
+    /// Returns: `Ok(audit)` on success. If a parent hash can't be computed, returns an `Error`.
+    pub(crate) fn audit_member(
+        &mut self,
+        cs: &'static CipherSuite,
+        leaf_tree_idx: usize,
+        history: &NodeHistory,
+    ) -> Result<MemberAudit, Error> {
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+        // The tree has at most 2^32 nodes, so the leaf index fits in a u32
+        let leaf_index = (leaf_tree_idx / 2) as u32;
+        let root = tree_math::root_idx(num_leaves);
+
+        let mut path = Vec::new();
+        for idx in tree_math::node_extended_direct_path(leaf_tree_idx, num_leaves) {
+            let node = &self.nodes[idx];
+            let is_blank = !node.is_filled();
+            let unmerged_leaves = node.unmerged_leaves().to_vec();
+            let link = if idx == root {
+                ParentHashLink::Root
+            } else if is_blank || !self.nodes[tree_math::node_parent(idx, num_leaves)].is_filled() {
+                ParentHashLink::Blank
+            } else if self.has_path_parent_hash(cs, idx, num_leaves)? {
+                ParentHashLink::Verified
+            } else if self.has_path_parent_hash(
+                cs,
+                tree_math::node_sibling(idx, num_leaves),
+                num_leaves,
+            )? {
+                ParentHashLink::ThroughSibling
+            } else {
+                ParentHashLink::Broken
+            };
+
+            path.push(PathNodeAudit {
+                // The tree has at most 2^32 nodes, so the node index fits in a u32
+                node_index: idx as u32,
+                is_blank,
+                link,
+                member_is_unmerged: unmerged_leaves.contains(&leaf_index),
+                unmerged_leaves,
+                last_changed_epoch: history.last_changed_epoch(idx),
+            });
+        }
+
+        Ok(MemberAudit {
+            roster_index: leaf_index,
+            path,
+        })
+    }
+
+    /// Returns the public part of the node at `idx`, as `NodeHistory` compares it
+    fn fingerprint(&self, idx: usize) -> Option<NodeFingerprint> {
+        match &self.nodes[idx] {
+            RatchetTreeNode::Blank => None,
+            RatchetTreeNode::Filled {
+                public_key,
+                unmerged_leaves,
+                parent_hash,
+                ..
+            } => Some(NodeFingerprint {
+                public_key: public_key.as_bytes().to_vec(),
+                parent_hash: parent_hash.as_bytes().to_vec(),
+                unmerged_leaves: unmerged_leaves.clone(),
+            }),
+        }
+    }
+
+    /// Returns whether the node at `idx` has the parent hash that `expected_parent_hash` gives
+    /// it, i.e., whether its parent's key was set by a path update through it
+    ///
+    /// Requires: `idx` isn't the root, and its parent is filled
+    fn has_path_parent_hash(
+        &mut self,
+        cs: &'static CipherSuite,
+        idx: usize,
+        num_leaves: usize,
+    ) -> Result<bool, Error> {
+        let expected = self.expected_parent_hash(cs, idx, num_leaves)?;
+        Ok(self.nodes[idx].parent_hash().map_or(false, |h| bool::from(h.ct_eq(&expected))))
+    }
+
+    /// Returns the public keys of the original sibling resolution of the node at `idx`
+    ///
+    /// Requires: `idx` isn't the root
+    fn original_sibling_keys(
+        &mut self,
+        idx: usize,
+        num_leaves: usize,
+    ) -> OriginalSiblingResolution {
+        let resolution = self.original_sibling_resolution(idx, num_leaves);
+        // We can unwrap() here because the resolution only has indices of nodes that are
+        // non-blank
+        OriginalSiblingResolution(
+            resolution
+                .into_iter()
+                .map(|i| self.nodes[i].get_public_key().unwrap().clone())
+                .collect(),
+        )
+    }
+
+    /// Sets the parent hashes of every node in the extended direct path of the leaf at
+    /// `leaf_tree_idx`, all of whose keys were just set by a path update from that leaf. This
+    /// goes from the root, whose parent hash is empty, down to the leaf.
+    ///
+    /// Returns: `Ok(parent_hash)` on success, where `parent_hash` is the leaf's new parent hash.
+    /// If a node on the path is blank, returns an `Error::TreeError`.
+    pub(crate) fn set_path_parent_hashes(
+        &mut self,
+        cs: &'static CipherSuite,
+        leaf_tree_idx: usize,
+    ) -> Result<Digest, Error> {
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+        let path: Vec<usize> =
+            tree_math::node_extended_direct_path(leaf_tree_idx, num_leaves).collect();
+
+        let mut new_parent_hash = Digest::default();
+        for (pos, &path_node_idx) in path.iter().enumerate().rev() {
+            if pos + 1 < path.len() {
+                new_parent_hash = self.expected_parent_hash(cs, path_node_idx, num_leaves)?;
+            }
+            match self.nodes[path_node_idx] {
+                RatchetTreeNode::Blank => {
+                    return Err(Error::TreeError("Path update left a blank node on its path"))
+                }
+                RatchetTreeNode::Filled {
+                    ref mut parent_hash,
+                    ..
+                } => *parent_hash = new_parent_hash.clone(),
             }
         }
+        self.forget_cached(leaf_tree_idx);
 
-        let mut ret = Vec::new();
-        helper(self, idx, &mut ret);
-        ret
+        Ok(new_parent_hash)
+    }
+
+    /// Checks that the key of every non-blank parent node in this tree was set by a path update.
+    /// That path came up through one of the node's children, so that child has to have the
+    /// parent hash that `expected_parent_hash` gives it.
+    ///
+    /// Returns: `Ok(())` if every parent node has such a child. Otherwise, returns an
+    /// `Error::ValidationError`.
+    pub(crate) fn verify_parent_hashes(&mut self, cs: &'static CipherSuite) -> Result<(), Error> {
+        if self.nodes.is_empty() {
+            return Ok(());
+        }
+
+        let num_leaves = tree_math::num_leaves_in_tree(self.size());
+        for idx in 0..self.size() {
+            if tree_math::node_level(idx) == 0 || !self.nodes[idx].is_filled() {
+                continue;
+            }
+
+            let children =
+                [tree_math::node_left_child(idx), tree_math::node_right_child(idx, num_leaves)];
+            let mut has_path_child = false;
+            for &child in children.iter() {
+                let expected = self.expected_parent_hash(cs, child, num_leaves)?;
+                if let Some(parent_hash) = self.nodes[child].parent_hash() {
+                    has_path_child |= bool::from(parent_hash.ct_eq(&expected));
+                }
+            }
+            if !has_path_child {
+                return Err(Error::ValidationError(
+                    "Parent node's key wasn't set by a path through either of its children",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the public part of this tree in the spec's `optional<Node>
+    /// ratchet_tree<1..2^32-1>` encoding, i.e., with the unmerged leaves and parent hashes in the
+    /// nodes
+    pub(crate) fn to_wire(&self) -> Result<Vec<u8>, Error> {
+        tls_ser::serialize_to_bytes(&WireTree(self.wire_nodes().collect()))
+    }
+
+    /// Returns every node of this tree in the spec's encoding, in node order
+    fn wire_nodes(&self) -> impl Iterator<Item = Option<WireNode>> + '_ {
+        (0..self.size()).map(move |i| self.wire_node(i))
+    }
+
+    /// Returns the node at `idx` in the spec's encoding
+    fn wire_node(&self, idx: usize) -> Option<WireNode> {
+        match &self.nodes[idx] {
+            RatchetTreeNode::Blank => None,
+            RatchetTreeNode::Filled {
+                public_key,
+                unmerged_leaves,
+                parent_hash,
+                ..
+            } => {
+                let public_key = public_key.clone();
+                let parent_hash = parent_hash.clone();
+                if tree_math::node_level(idx) == 0 {
+                    Some(WireNode::Leaf(WireLeafNode {
+                        public_key,
+                        parent_hash,
+                    }))
+                } else {
+                    Some(WireNode::Parent(WireParentNode {
+                        public_key,
+                        parent_hash,
+                        unmerged_leaves: unmerged_leaves.clone(),
+                    }))
+                }
+            }
+        }
+    }
+
+    /// Undoes `RatchetTree::to_wire`. This doesn't check the tree against anything, the caller has
+    /// to compare its tree hash to one they trust, and verify its parent hashes.
+    ///
+    /// Returns: `Ok(tree)` on success. If `bytes` isn't a well-formed tree under `cs`, i.e., it
+    /// doesn't have an odd number of nodes, or has a leaf where a parent should be or vice versa,
+    /// or has bad unmerged leaves, or has trailing data, returns an `Error`. If it's over `limits`,
+    /// returns an `Error::SerdeError`.
+    pub(crate) fn from_wire(
+        cs: &'static CipherSuite,
+        bytes: &[u8],
+        limits: DecodeLimits,
+    ) -> Result<RatchetTree, Error> {
+        let WireTree(wire_nodes) = {
+            let mut cursor = bytes;
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor).with_limits(limits);
+            let wire_tree = WireTree::tls_deserialize(&mut deserializer)?;
+            if !cursor.is_empty() {
+                return Err(Error::ValidationError("Tree has trailing data"));
+            }
+            wire_tree
+        };
+        RatchetTree::from_wire_nodes(cs, wire_nodes)
+    }
+
+    /// Makes a public tree out of nodes in the spec's encoding, as described in `from_wire`
+    fn from_wire_nodes(
+        cs: &'static CipherSuite,
+        wire_nodes: Vec<Option<WireNode>>,
+    ) -> Result<RatchetTree, Error> {
+        // This covers the empty tree too
+        if wire_nodes.len() % 2 == 0 {
+            return Err(Error::ValidationError("Tree doesn't have an odd number of nodes"));
+        }
+
+        // Make the nodes first, then set their unmerged leaves and parent hashes, since that's
+        // where those are checked
+        let mut nodes = Vec::with_capacity(wire_nodes.len());
+        let mut unmerged_leaves = Vec::with_capacity(wire_nodes.len());
+        let mut parent_hashes = Vec::with_capacity(wire_nodes.len());
+        for (i, wire_node) in wire_nodes.into_iter().enumerate() {
+            let is_leaf = tree_math::node_level(i) == 0;
+            let (public_key, parent_hash, leaves) = match wire_node {
+                None => {
+                    nodes.push(RatchetTreeNode::Blank);
+                    unmerged_leaves.push(NodeUnmergedLeaves(Vec::new()));
+                    parent_hashes.push(Digest::default());
+                    continue;
+                }
+                Some(WireNode::Leaf(leaf)) if is_leaf => {
+                    (leaf.public_key, leaf.parent_hash, Vec::new())
+                }
+                Some(WireNode::Parent(parent)) if !is_leaf => {
+                    (parent.public_key, parent.parent_hash, parent.unmerged_leaves)
+                }
+                Some(_) => {
+                    return Err(Error::ValidationError("Tree has a node of the wrong type"));
+                }
+            };
+            nodes.push(RatchetTreeNode::Filled {
+                public_key,
+                private_key: None,
+                unmerged_leaves: Vec::new(),
+                parent_hash: Digest::default(),
+            });
+            unmerged_leaves.push(NodeUnmergedLeaves(leaves));
+            parent_hashes.push(parent_hash);
+        }
+
+        let mut tree = RatchetTree::new_from_nodes(nodes);
+        tree.upcast_crypto_values(&CryptoCtx::new().set_cipher_suite(cs))?;
+        tree.set_unmerged_leaves(UnmergedLeaves(unmerged_leaves))?;
+        tree.set_parent_hashes(ParentHashes(parent_hashes))?;
+        Ok(tree)
+    }
+
+    /// Returns the patch that takes the public part of this tree to the public part of `other`.
+    /// A node is in the patch if it's in `other` but not in this tree, or if its encoding differs
+    /// between the two.
+    pub(crate) fn diff(&self, other: &RatchetTree) -> Result<TreePatch, Error> {
+        let mut old_nodes = self.wire_nodes();
+        let mut nodes = Vec::new();
+        for (i, new_node) in other.wire_nodes().enumerate() {
+            let unchanged = match old_nodes.next() {
+                Some(old_node) => {
+                    tls_ser::serialize_to_bytes(&old_node)?
+                        == tls_ser::serialize_to_bytes(&new_node)?
+                }
+                None => false,
+            };
+            if !unchanged {
+                nodes.push(PatchedNode {
+                    node_index: u32::try_from(i).expect("tree size exceeds u32::MAX"),
+                    node: new_node,
+                });
+            }
+        }
+
+        Ok(TreePatch {
+            num_nodes: u32::try_from(other.size()).expect("tree size exceeds u32::MAX"),
+            nodes,
+        })
+    }
+
+    /// Applies `patch` to the public part of this tree. Like `from_wire`, this doesn't check the
+    /// result against anything.
+    ///
+    /// Returns: `Ok(tree)` on success, where `tree` has no private keys. If the patch sets a node
+    /// that's out of bounds, or sets nodes out of order, or the patched tree isn't well-formed
+    /// under `cs` in the way that `from_wire` describes, returns an `Error`.
+    pub(crate) fn apply_patch(
+        &self,
+        cs: &'static CipherSuite,
+        patch: TreePatch,
+    ) -> Result<RatchetTree, Error> {
+        let num_nodes = patch.num_nodes as usize;
+        let mut wire_nodes: Vec<Option<WireNode>> = self.wire_nodes().take(num_nodes).collect();
+        wire_nodes.resize_with(num_nodes, || None);
+
+        let mut last_index = None;
+        for PatchedNode {
+            node_index,
+            node,
+        } in patch.nodes
+        {
+            if last_index.map_or(false, |last| node_index <= last) {
+                return Err(Error::ValidationError("Tree patch sets nodes out of order"));
+            }
+            last_index = Some(node_index);
+            match wire_nodes.get_mut(node_index as usize) {
+                Some(wire_node) => *wire_node = node,
+                None => return Err(Error::ValidationError("Tree patch sets a node out of bounds")),
+            }
+        }
+
+        RatchetTree::from_wire_nodes(cs, wire_nodes)
+    }
+
+    /// Packages up the public part of this tree as a ratchet_tree extension
+    pub(crate) fn to_extension(&self) -> Result<Extension, Error> {
+        Ok(Extension::new(RATCHET_TREE_TYPE, self.to_wire()?))
+    }
+
+    /// Undoes `RatchetTree::to_extension`. Like `from_wire`, this doesn't check the tree against
+    /// anything.
+    ///
+    /// Returns: `Ok(tree)` on success. If `ext` isn't a well-formed ratchet_tree extension under
+    /// `cs` and `limits`, returns an `Error`.
+    pub(crate) fn from_extension(
+        cs: &'static CipherSuite,
+        ext: &Extension,
+        limits: DecodeLimits,
+    ) -> Result<RatchetTree, Error> {
+        if ext.extension_type() != RATCHET_TREE_TYPE {
+            return Err(Error::ValidationError("Extension isn't a ratchet_tree extension"));
+        }
+        RatchetTree::from_wire(cs, ext.extension_data(), limits)
+    }
+
+    /// Returns the parent hashes of every node in this tree, for sending alongside it
+    pub(crate) fn parent_hashes(&self) -> ParentHashes {
+        let nodes = self.nodes.iter().map(|node| node.parent_hash().cloned().unwrap_or_default());
+        ParentHashes(nodes.collect())
+    }
+
+    /// Sets the parent hashes of every node in this tree to the ones that were sent alongside it.
+    /// This doesn't check that they verify, `verify_parent_hashes` does that.
+    ///
+    /// Returns: `Ok(())` on success. If `parent_hashes` isn't the same size as the tree, or it has
+    /// a non-empty parent hash at a blank node, returns an `Error::ValidationError`.
+    pub(crate) fn set_parent_hashes(&mut self, parent_hashes: ParentHashes) -> Result<(), Error> {
+        self.hash_cache.clear();
+        if parent_hashes.0.len() != self.size() {
+            return Err(Error::ValidationError("Parent hashes don't match the tree's size"));
+        }
+
+        let blank_has_parent_hash = self
+            .nodes
+            .iter()
+            .zip(parent_hashes.0.iter())
+            .any(|(node, hash)| !node.is_filled() && !hash.as_bytes().is_empty());
+        if blank_has_parent_hash {
+            return Err(Error::ValidationError("Blank node has a parent hash"));
+        }
+
+        // Blank nodes' parent hashes are all empty, as checked above
+        let mut parent_hashes = parent_hashes.0;
+        for (i, node) in self.nodes.iter_filled_mut() {
+            if let RatchetTreeNode::Filled {
+                ref mut parent_hash,
+                ..
+            } = node
+            {
+                *parent_hash = std::mem::take(&mut parent_hashes[i]);
+            }
+        }
+
+        Ok(())
     }
 
     /// Overwrites all the public keys in the extended (including root) direct path of
@@ -302,10 +1984,8 @@ impl RatchetTree {
                 // We reached the stopping node
                 break;
             } else {
-                let node = self
-                    .get_mut(path_node_idx)
-                    .ok_or(Error::ValidationError("Direct path node is out of range"))?;
-                node.update_public_key(pubkey.clone());
+                self.update(path_node_idx, |node| node.update_public_key(pubkey.clone()))
+                    .map_err(|_| Error::ValidationError("Direct path node is out of range"))?;
             }
         }
 
@@ -364,19 +2044,43 @@ impl RatchetTree {
         }
     }
 
-    /// Given a path secret, constructs a `DirectPathMessage` containing encrypted copies of the
-    /// appropriately ratcheted path secret for the rest of the ratchet tree. See section
-    /// 5.2 in the spec for details.
+    /// Given a path secret, constructs a `DirectPathMessage` containing encrypted copies of the
+    /// appropriately ratcheted path secret for the rest of the ratchet tree. See section
+    /// 5.2 in the spec for details. The secrets are encrypted with an empty HPKE context.
+    ///
+    /// Requires: `starting_tree_idx` to be a leaf node. Otherwise, any child of ours would be
+    /// unable to decrypt this message.
+    // This is just a convenient wrapper around encrypt_update_path
+    pub(crate) fn encrypt_direct_path_secrets<R>(
+        &mut self,
+        cs: &'static CipherSuite,
+        starting_tree_idx: usize,
+        starting_path_secret: PathSecret,
+        csprng: &mut R,
+    ) -> Result<DirectPathMessage, Error>
+    where
+        R: CryptoRng,
+    {
+        let path =
+            self.encrypt_update_path(cs, starting_tree_idx, starting_path_secret, b"", csprng)?;
+        Ok(DirectPathMessage::from(path))
+    }
+
+    /// Given the fresh path secret of the leaf at `starting_tree_idx`, constructs an `UpdatePath`
+    /// with the public keys of the leaf and its direct path, and each parent's path secret
+    /// encrypted to every node in the resolution of its copath child. The secrets are encrypted
+    /// with `context` as the HPKE context, so they can only be decrypted by someone who knows it.
     ///
     /// Requires: `starting_tree_idx` to be a leaf node. Otherwise, any child of ours would be
     /// unable to decrypt this message.
-    pub(crate) fn encrypt_direct_path_secrets<R>(
-        &self,
+    pub(crate) fn encrypt_update_path<R>(
+        &mut self,
         cs: &'static CipherSuite,
         starting_tree_idx: usize,
         starting_path_secret: PathSecret,
+        context: &[u8],
         csprng: &mut R,
-    ) -> Result<DirectPathMessage, Error>
+    ) -> Result<UpdatePath, Error>
     where
         R: CryptoRng,
     {
@@ -388,15 +2092,12 @@ impl RatchetTree {
         let num_leaves = tree_math::num_leaves_in_tree(self.size());
         let direct_path = tree_math::node_direct_path(starting_tree_idx as usize, num_leaves);
 
-        let mut node_messages = Vec::new();
+        let mut nodes = Vec::new();
 
-        // The first message should be just the starting node's pubkey and no encrypted messages
-        let (starting_node_public_key, _, _, mut parent_path_secret) =
+        // The leaf only gets its public key. Nobody needs its path secret, since everyone who
+        // needs one gets the one at their common ancestor with the leaf.
+        let (leaf_public_key, _, _, mut parent_path_secret) =
             utils::derive_node_values(cs, starting_path_secret)?;
-        node_messages.push(DirectPathNodeMessage {
-            public_key: starting_node_public_key.clone(),
-            node_secrets: Vec::with_capacity(0),
-        });
 
         // Go up the direct path of the starting index
         for path_node_idx in direct_path {
@@ -410,38 +2111,56 @@ impl RatchetTree {
             // indices that are actually in the tree.
             let mut encrypted_path_secrets = Vec::new();
             let copath_node_idx = tree_math::node_sibling(path_node_idx, num_leaves);
-            for res_node in self.resolution(copath_node_idx).iter().map(|&i| &self.nodes[i]) {
+            let copath_resolution = self.resolution(copath_node_idx);
+            for res_node in copath_resolution.iter().map(|&i| &self.nodes[i]) {
                 // We can unwrap() here because self.resolution only returns indices of nodes
                 // that are non-blank, by definition of "resolution"
                 let others_public_key = res_node.get_public_key().unwrap();
                 // Encrypt the parent's path secret with the resolution node's pubkey
-                let ciphertext = ecies::encrypt(
+                let ciphertext = hpke::encrypt_with_label(
                     cs,
                     others_public_key,
+                    PATH_SECRET_LABEL,
+                    context,
                     parent_path_secret.as_bytes().to_vec(), // TODO: Make this not copy secrets
                     csprng,
                 )?;
                 encrypted_path_secrets.push(ciphertext);
             }
 
-            // Push the collection to the message list
-            node_messages.push(DirectPathNodeMessage {
+            // Push the collection to the node list
+            nodes.push(UpdatePathNode {
                 public_key: parent_public_key.clone(),
-                node_secrets: encrypted_path_secrets,
+                encrypted_path_secret: encrypted_path_secrets,
             });
 
             // Ratchet up the path secret
             parent_path_secret = grandparent_path_secret;
         }
 
-        Ok(DirectPathMessage {
-            node_messages,
+        Ok(UpdatePath {
+            leaf_public_key,
+            nodes,
         })
     }
 
     /// Finds the (unique) ciphertext in the given direct path message that is meant for this
-    /// member and decrypts it. `starting_node_idx` is the the index of the starting node of the
-    /// encoded direct path.
+    /// member and decrypts it, under an empty HPKE context. See `decrypt_update_path`.
+    // This is just a convenient wrapper around decrypt_update_path
+    pub(crate) fn decrypt_direct_path_message(
+        &mut self,
+        cs: &'static CipherSuite,
+        direct_path_msg: &DirectPathMessage,
+        starting_tree_idx: usize,
+        my_tree_idx: usize,
+    ) -> Result<(PathSecret, usize), Error> {
+        let path = direct_path_msg.to_update_path()?;
+        self.decrypt_update_path(cs, &path, starting_tree_idx, my_tree_idx, b"")
+    }
+
+    /// Finds the (unique) ciphertext in the given `UpdatePath` that is meant for this member and
+    /// decrypts it under the HPKE context `context`. `starting_node_idx` is the the index of the
+    /// leaf that the path starts at.
     ///
     /// Requires: `starting_tree_idx` cannot be an ancestor of `my_tree_idx`, nor vice-versa. We
     /// cannot decrypt messages that violate this.
@@ -450,12 +2169,13 @@ impl RatchetTree {
     /// `idx` is the common ancestor of `starting_tree_idx` and `my_tree_idx`. If no decryptable
     /// ciphertext exists, returns an `Error::TreeError`. If decryption fails, returns an
     /// `Error::EncryptionError`.
-    pub(crate) fn decrypt_direct_path_message(
-        &self,
+    pub(crate) fn decrypt_update_path(
+        &mut self,
         cs: &'static CipherSuite,
-        direct_path_msg: &DirectPathMessage,
+        path: &UpdatePath,
         starting_tree_idx: usize,
         my_tree_idx: usize,
+        context: &[u8],
     ) -> Result<(PathSecret, usize), Error> {
         let num_leaves = tree_math::num_leaves_in_tree(self.size());
 
@@ -475,17 +2195,16 @@ impl RatchetTree {
 
         // This holds the secret of the intermediate node, encrypted for all the nodes in the
         // resolution of the copath node.
-        let node_msg = {
-            // To get this value, we have to figure out the correct index into node_message
-            let (pos_in_msg_vec, _) =
+        let path_node = {
+            // To get this value, we have to figure out the correct index into the path's nodes.
+            // Those are the parents, i.e., the extended direct path without the leaf.
+            let (pos_in_path, _) =
                 tree_math::node_extended_direct_path(starting_tree_idx, num_leaves)
+                    .skip(1)
                     .enumerate()
                     .find(|&(_, dp_idx)| dp_idx == common_ancestor_idx)
                     .expect("common ancestor somehow did not appear in direct path");
-            direct_path_msg
-                .node_messages
-                .get(pos_in_msg_vec)
-                .ok_or(Error::TreeError("Malformed DirectPathMessage"))?
+            path.nodes.get(pos_in_path).ok_or(Error::TreeError("Malformed UpdatePath"))?
         };
 
         // This is the unique acnestor of the receiver that is in the copath of the sender. This is
@@ -515,14 +2234,20 @@ impl RatchetTree {
                 // We found the ancestor in the resolution. Now get the decryption key and
                 // corresponding ciphertext
                 let decryption_key = res_node.get_private_key().unwrap();
-                let ciphertext_for_me = node_msg
-                    .node_secrets
+                let ciphertext_for_me = path_node
+                    .encrypted_path_secret
                     .get(pos_in_res)
-                    .ok_or(Error::TreeError("Malformed DirectPathMessage"))?;
+                    .ok_or(Error::TreeError("Malformed UpdatePath"))?;
 
                 // Finally, decrypt the thing and return the plaintext and common ancestor
-                let plaintext = ecies::decrypt(cs, decryption_key, ciphertext_for_me.clone())?;
-                let path_secret = PathSecret::new_from_bytes(&plaintext);
+                let plaintext = hpke::decrypt_with_label(
+                    cs,
+                    decryption_key,
+                    PATH_SECRET_LABEL,
+                    context,
+                    ciphertext_for_me.clone(),
+                )?;
+                let path_secret = PathSecret::from(HmacKey(Secret::from(plaintext)));
                 return Ok((path_secret, common_ancestor_idx));
             }
         }
@@ -536,7 +2261,7 @@ impl RatchetTree {
     /// _not_ roll back the operation, so the caller should expect this object to be in an invalid
     /// state.
     ///
-    /// Requires: `path_secret.len() == cs.hash_alg.output_len`
+    /// Requires: `path_secret.len() == cs.hash_impl.digest_size()`
     ///
     /// Panics: If above condition is not satisfied
     ///
@@ -556,9 +2281,6 @@ impl RatchetTree {
         // Go up the tree, setting the node secrets and keypairs. The last calculated node secret
         // is that of the root. This is our return value
         let root_node_secret = loop {
-            let current_node =
-                self.get_mut(current_node_idx).expect("reached invalid node in secret propagation");
-
             // Derive the new values
             let (node_public_key, node_private_key, node_secret, new_path_secret) =
                 utils::derive_node_values(cs, path_secret)?;
@@ -566,8 +2288,11 @@ impl RatchetTree {
             // Update the current node with all the new values. Note: the order here matters. You
             // have to update the public key first, because you can't update a Blank node's secret
             // key (it must have a public key first)
-            current_node.update_public_key(node_public_key);
-            current_node.update_private_key(node_private_key);
+            self.update(current_node_idx, |current_node| {
+                current_node.update_public_key(node_public_key);
+                current_node.update_private_key(node_private_key);
+            })
+            .expect("reached invalid node in secret propagation");
 
             if current_node_idx == root_node_idx {
                 // If we just updated the root, we're done
@@ -591,13 +2316,12 @@ mod test {
             ciphersuite::X25519_SHA256_AES128GCM,
             dh::{DhPublicKey, DhPublicKeyRaw},
         },
-        tls_de::TlsDeserializer,
+        tls_de::{TlsDeserialize, TlsDeserializer},
     };
 
     use quickcheck_macros::quickcheck;
     use rand::SeedableRng;
     use rand::{Rng, RngCore};
-    use serde::Deserialize;
 
     // The following test vector is from
     // https://github.com/mlswg/mls-implementations/tree/master/test_vectors
@@ -626,18 +2350,18 @@ mod test {
     // Parses the bits of a u32 from right to left, interpreting a 0 as a Blank node and 1 as a
     // Filled node (unimportant what the pubkey is)
 
-    #[derive(Debug, Deserialize)]
-    #[serde(rename = "Resolution__bound_u8")]
+    #[derive(Debug, TlsDeserialize)]
+    #[tls(bound = "u8")]
     struct Resolution(Vec<u8>);
 
-    #[derive(Debug, Deserialize)]
-    #[serde(rename = "ResolutionCase__bound_u16")]
+    #[derive(Debug, TlsDeserialize)]
+    #[tls(bound = "u16")]
     struct ResolutionCase(Vec<Resolution>);
 
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, TlsDeserialize)]
     struct ResolutionTestVectors {
         num_leaves: u32,
-        #[serde(rename = "cases__bound_u32")]
+        #[tls(bound = "u32")]
         cases: Vec<ResolutionCase>,
     }
 
@@ -654,9 +2378,7 @@ mod test {
         let num_nodes = tree_math::num_nodes_in_tree(num_leaves);
 
         // Fill a tree with Blanks
-        let mut tree = RatchetTree {
-            nodes: Vec::new(),
-        };
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
         for _ in 0..num_leaves {
             tree.add_leaf_node(RatchetTreeNode::Blank);
         }
@@ -721,11 +2443,777 @@ mod test {
         assert_eq!(derived_path_secret.0, expected_path_secret.0);
     }
 
+    // Check that a leaf added under filled nodes is in their resolutions until a path goes
+    // through, and that it can decrypt a path without knowing any of the keys above it
+    #[quickcheck]
+    fn unmerged_leaves_resolution(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+
+        // A tree with 4 filled leaves, where leaf 2 then gets a new member in place
+        //        3
+        //      /   \
+        //     1     5
+        //    / \   / \
+        //   0   2 4   6
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        for _ in 0..4 {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        for i in 0..4u8 {
+            let initial_path_secret = PathSecret::new_from_bytes(&[i; 32]);
+            tree.propagate_new_path_secret(cs, initial_path_secret, 2 * i as usize).unwrap();
+        }
+        let new_member_key = DhPrivateKey::new_from_random(cs.dh_impl, &mut rng).unwrap();
+        tree.nodes.set(4, RatchetTreeNode::new_from_private_key(cs, new_member_key));
+        tree.add_unmerged_leaf(4);
+
+        assert_eq!(tree.nodes[5].unmerged_leaves(), &[2]);
+        assert_eq!(tree.nodes[3].unmerged_leaves(), &[2]);
+        assert!(tree.nodes[4].unmerged_leaves().is_empty());
+        assert_eq!(tree.resolution(5), vec![5, 4]);
+        assert_eq!(tree.resolution(3), vec![3, 4]);
+
+        // Unmerged leaves survive a trip alongside the tree, and bad ones are rejected
+        let mut copy = tree.clone();
+        copy.set_unmerged_leaves(tree.unmerged_leaves()).unwrap();
+        assert_eq!(copy.resolution(5), vec![5, 4]);
+        let mut bad = tree.unmerged_leaves();
+        bad.0[0].0.push(2);
+        assert!(copy.set_unmerged_leaves(bad).is_err());
+
+        // The new member only knows their own key. They get the path secret of node 3 from
+        // leaf 0 by decrypting with their leaf key.
+        let mut new_member_tree = tree.clone();
+        for (i, node) in new_member_tree.nodes.iter_filled_mut() {
+            if let RatchetTreeNode::Filled {
+                ref mut private_key,
+                ..
+            } = node
+            {
+                if i != 4 {
+                    *private_key = None;
+                }
+            }
+        }
+        let sender_path_secret = PathSecret::new_from_bytes(&[0xaa; 32]);
+        let direct_path_msg =
+            tree.encrypt_direct_path_secrets(cs, 0, sender_path_secret.clone(), &mut rng).unwrap();
+        assert_eq!(direct_path_msg.node_messages[2].node_secrets.len(), 2);
+        let (derived_path_secret, common_ancestor_idx) =
+            new_member_tree.decrypt_direct_path_message(cs, &direct_path_msg, 0, 4).unwrap();
+        assert_eq!(common_ancestor_idx, 3);
+        let (_, _, _, path_secret_1) = utils::derive_node_values(cs, sender_path_secret).unwrap();
+        let (_, _, _, path_secret_3) = utils::derive_node_values(cs, path_secret_1).unwrap();
+        assert_eq!(derived_path_secret.0, path_secret_3.0);
+
+        // A path through the root merges the new member there, but not at node 5
+        tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&[0xbb; 32]), 0).unwrap();
+        assert!(tree.nodes[3].unmerged_leaves().is_empty());
+        assert_eq!(tree.resolution(5), vec![5, 4]);
+    }
+
+    // Check that the tree hash covers the public keys and unmerged leaves of the tree, but not
+    // its private keys
+    #[quickcheck]
+    fn tree_hash_covers_public_state(num_leaves: u8, rng_seed: u64) {
+        if num_leaves > 30 || num_leaves < 2 {
+            return;
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        for i in 0..num_leaves {
+            let mut buf = [0u8; 32];
+            rng.fill_bytes(&mut buf);
+            tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&buf), 2 * i as usize)
+                .unwrap();
+        }
+        let tree_hash = tree.tree_hash(cs).unwrap();
+
+        // Forgetting the private keys doesn't change anything
+        let mut public_tree = tree.clone();
+        for (_, node) in public_tree.nodes.iter_filled_mut() {
+            if let RatchetTreeNode::Filled {
+                ref mut private_key,
+                ..
+            } = node
+            {
+                *private_key = None;
+            }
+        }
+        assert_eq!(public_tree.tree_hash(cs).unwrap().as_bytes(), tree_hash.as_bytes());
+
+        // Blanking a node or adding an unmerged leaf does
+        let leaf_idx = 2 * rng.gen_range(0, num_leaves as usize);
+        let mut unmerged_tree = tree.clone();
+        unmerged_tree.add_unmerged_leaf(leaf_idx);
+        assert_ne!(unmerged_tree.tree_hash(cs).unwrap().as_bytes(), tree_hash.as_bytes());
+        let mut blanked_tree = tree.clone();
+        blanked_tree.set(leaf_idx, RatchetTreeNode::Blank).unwrap();
+        let blanked_tree_hash = blanked_tree.tree_hash(cs).unwrap();
+        assert_ne!(blanked_tree_hash.as_bytes(), tree_hash.as_bytes());
+
+        // The cached hashes that were cloned along with the trees were forgotten where they
+        // changed, so the hashes match ones computed from scratch
+        let mut uncached_tree =
+            RatchetTree::new_from_nodes(blanked_tree.nodes.iter().cloned().collect());
+        assert_eq!(uncached_tree.tree_hash(cs).unwrap().as_bytes(), blanked_tree_hash.as_bytes());
+        let mut uncached_tree =
+            RatchetTree::new_from_nodes(unmerged_tree.nodes.iter().cloned().collect());
+        assert_eq!(
+            uncached_tree.tree_hash(cs).unwrap().as_bytes(),
+            unmerged_tree.tree_hash(cs).unwrap().as_bytes()
+        );
+
+        assert!(RatchetTree::new_from_nodes(Vec::new()).tree_hash(cs).is_err());
+    }
+
+    // Check that a dump names every node and member, marks blank nodes, and doesn't depend on any
+    // private keys
+    #[quickcheck]
+    fn tree_debug_dump(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut group_state, _) = crate::test_utils::random_full_group_state(2, &mut rng);
+        let num_nodes = group_state.tree.size();
+        let blank_idx = 2 * rng.gen_range(0, group_state.roster.0.len());
+        group_state.tree.propagate_blank(blank_idx);
+
+        let ascii = debug_dump(&group_state, DumpFormat::Ascii).unwrap();
+        let graphviz = debug_dump(&group_state, DumpFormat::Graphviz).unwrap();
+        assert_eq!(ascii.lines().count(), num_nodes + 1);
+        for idx in 0..num_nodes {
+            let kind = if tree_math::node_level(idx) == 0 {
+                "leaf"
+            } else {
+                "parent"
+            };
+            assert!(ascii.contains(&format!("{} {} ", kind, idx)));
+            assert!(graphviz.contains(&format!("  n{} [", idx)));
+        }
+        for cred in group_state.roster.0.iter().flatten() {
+            let identity = String::from_utf8_lossy(cred.get_identity().as_bytes());
+            assert!(ascii.contains(&format!("{:?}", identity)));
+        }
+        assert!(ascii
+            .lines()
+            .any(|l| l.contains(&format!("leaf {} ", blank_idx)) && l.contains("blank")));
+        assert!(graphviz.contains("style=dashed"));
+
+        // Forgetting every private key changes nothing
+        let mut public_state = group_state.clone();
+        for (_, node) in public_state.tree.nodes.iter_filled_mut() {
+            if let RatchetTreeNode::Filled {
+                ref mut private_key,
+                ..
+            } = node
+            {
+                *private_key = None;
+            }
+        }
+        assert_eq!(debug_dump(&public_state, DumpFormat::Ascii).unwrap(), ascii);
+        assert_eq!(debug_dump(&public_state, DumpFormat::Graphviz).unwrap(), graphviz);
+    }
+
+    // Check that trees made by path updates verify, including after an Add, and that trees whose
+    // keys didn't come from path updates don't
+    #[quickcheck]
+    fn parent_hash_chain(num_leaves: u8, rng_seed: u64) {
+        if num_leaves > 30 || num_leaves < 2 {
+            return;
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        // Everyone but the last leaf does a path update
+        for i in 0..(num_leaves - 1) {
+            let mut buf = [0u8; 32];
+            rng.fill_bytes(&mut buf);
+            let leaf_idx = 2 * i as usize;
+            tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&buf), leaf_idx).unwrap();
+            tree.set_path_parent_hashes(cs, leaf_idx).unwrap();
+            tree.verify_parent_hashes(cs).unwrap();
+        }
+
+        // Then the last leaf is added. It wasn't encrypted to, so it's left out of the parent
+        // hashes of the nodes it's unmerged at.
+        let new_leaf_idx = 2 * (num_leaves as usize - 1);
+        let public_key = tree.nodes[0].get_public_key().unwrap().clone();
+        tree.nodes.update(new_leaf_idx, |node| node.update_public_key(public_key));
+        tree.add_unmerged_leaf(new_leaf_idx);
+        tree.verify_parent_hashes(cs).unwrap();
+        let mut merged_tree = tree.clone();
+        let no_unmerged_leaves = vec![NodeUnmergedLeaves(Vec::new()); tree.size()];
+        merged_tree.set_unmerged_leaves(UnmergedLeaves(no_unmerged_leaves)).unwrap();
+        assert!(merged_tree.verify_parent_hashes(cs).is_err());
+
+        // Parent hashes survive a trip alongside the tree, and the tree doesn't verify without them
+        let mut copy = tree.clone();
+        copy.set_parent_hashes(ParentHashes(vec![Digest::default(); tree.size()])).unwrap();
+        assert!(copy.verify_parent_hashes(cs).is_err());
+        copy.set_parent_hashes(tree.parent_hashes()).unwrap();
+        copy.verify_parent_hashes(cs).unwrap();
+
+        // A key that someone made up doesn't verify
+        let root_idx = tree_math::root_idx(tree_math::num_leaves_in_tree(tree.size()));
+        let made_up_key = tree.nodes[new_leaf_idx].get_public_key().unwrap().clone();
+        let mut tampered_tree = tree.clone();
+        tampered_tree.nodes.update(root_idx, |node| node.update_public_key(made_up_key));
+        assert!(tampered_tree.verify_parent_hashes(cs).is_err());
+    }
+
+    // Check that a tree survives a trip through the spec's encoding along with its unmerged leaves
+    // and parent hashes, and that trees that are malformed in that encoding are rejected
+    #[quickcheck]
+    fn wire_tree_roundtrip(num_leaves: u8, rng_seed: u64) {
+        if num_leaves > 30 || num_leaves < 2 {
+            return;
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let limits = DecodeLimits::default();
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        // Like in parent_hash_chain, everyone but the last leaf does a path update, and the last
+        // leaf is unmerged
+        for i in 0..(num_leaves - 1) {
+            let mut buf = [0u8; 32];
+            rng.fill_bytes(&mut buf);
+            let leaf_idx = 2 * i as usize;
+            tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&buf), leaf_idx).unwrap();
+            tree.set_path_parent_hashes(cs, leaf_idx).unwrap();
+        }
+        let new_leaf_idx = 2 * (num_leaves as usize - 1);
+        let public_key = tree.nodes[0].get_public_key().unwrap().clone();
+        tree.nodes.update(new_leaf_idx, |node| node.update_public_key(public_key.clone()));
+        tree.add_unmerged_leaf(new_leaf_idx);
+
+        // The spec tags leaves with 1 and parents with 2. The first node is a filled leaf, and it
+        // comes right after the length of the vector.
+        let bytes = tree.to_wire().unwrap();
+        assert_eq!(&bytes[4..6], &[1, 1]);
+
+        let mut imported = RatchetTree::from_wire(cs, &bytes, limits).unwrap();
+        assert_eq!(
+            imported.tree_hash(cs).unwrap().as_bytes(),
+            tree.tree_hash(cs).unwrap().as_bytes()
+        );
+        imported.verify_parent_hashes(cs).unwrap();
+        assert!(imported.nodes.iter().all(|node| node.get_private_key().is_none()));
+        assert_eq!(imported.to_wire().unwrap(), bytes);
+
+        // Trailing data, an even number of nodes, a parent where a leaf should be, and more nodes
+        // than the decode limit are all rejected
+        let too_big = DecodeLimits::new(limits.max_vec_len(), tree.size() - 1, 256, 32);
+        assert!(RatchetTree::from_wire(cs, &bytes, too_big).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(RatchetTree::from_wire(cs, &trailing, limits).is_err());
+        let even =
+            RatchetTree::new_from_nodes(tree.nodes.iter().take(tree.size() - 1).cloned().collect());
+        assert!(RatchetTree::from_wire(cs, &even.to_wire().unwrap(), limits).is_err());
+        let misplaced_parent = WireNode::Parent(WireParentNode {
+            public_key,
+            parent_hash: Digest::default(),
+            unmerged_leaves: Vec::new(),
+        });
+        let wrong_type = WireTree(vec![Some(misplaced_parent), None, None]);
+        let wrong_type_bytes = tls_ser::serialize_to_bytes(&wrong_type).unwrap();
+        assert!(RatchetTree::from_wire(cs, &wrong_type_bytes, limits).is_err());
+        assert!(RatchetTree::from_wire(cs, &[0, 0, 0, 0], limits).is_err());
+    }
+
+    // Check that a patch between two epochs' trees takes the old tree to the new one, only has
+    // what changed, and is checked against the new tree hash
+    #[quickcheck]
+    fn tree_patch_roundtrip(num_leaves: u8, rng_seed: u64) {
+        if num_leaves > 30 || num_leaves < 2 {
+            return;
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        let mut update_path = |tree: &mut RatchetTree, leaf_idx: usize| {
+            let mut buf = [0u8; 32];
+            rng.fill_bytes(&mut buf);
+            tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&buf), leaf_idx).unwrap();
+            tree.set_path_parent_hashes(cs, leaf_idx).unwrap();
+        };
+        for i in 0..(num_leaves as usize) {
+            update_path(&mut tree, 2 * i);
+        }
+        let parse = |bytes: &[u8]| {
+            let mut cursor = bytes;
+            TreePatch::tls_deserialize(&mut TlsDeserializer::from_reader(&mut cursor)).unwrap()
+        };
+
+        // Someone updates their path. Only the nodes on it change, and the patch survives a trip
+        // over the wire.
+        let old = tree.to_extension().unwrap();
+        let leaf_idx = 2 * (rng_seed % num_leaves as u64) as usize;
+        update_path(&mut tree, leaf_idx);
+        let new = tree.to_extension().unwrap();
+        let patch = TreePatch::between(cs, &old, &new).unwrap();
+        let path_len = tree_math::node_extended_direct_path(leaf_idx, num_leaves as usize).count();
+        assert!(patch.num_patched_nodes() <= path_len);
+        let patch_bytes = tls_ser::serialize_to_bytes(&patch).unwrap();
+        let tree_hash = tree.tree_hash(cs).unwrap();
+        let patched = parse(&patch_bytes).apply(cs, &old, tree_hash.as_bytes()).unwrap();
+        assert_eq!(patched, new);
+
+        // The patched tree has to have the hash it's supposed to
+        let mut old_tree = RatchetTree::from_extension(cs, &old, DecodeLimits::default()).unwrap();
+        let old_tree_hash = old_tree.tree_hash(cs).unwrap();
+        assert!(parse(&patch_bytes).apply(cs, &old, old_tree_hash.as_bytes()).is_err());
+
+        // Patches also grow and shrink the tree
+        let public_key = tree.nodes[0].get_public_key().unwrap().clone();
+        tree.add_leaf_node(RatchetTreeNode::Blank);
+        let new_leaf_idx = tree.size() - 1;
+        tree.update(new_leaf_idx, |node| node.update_public_key(public_key)).unwrap();
+        let grown = tree.to_extension().unwrap();
+        let patch = TreePatch::between(cs, &new, &grown).unwrap();
+        let tree_hash = tree.tree_hash(cs).unwrap();
+        assert_eq!(patch.apply(cs, &new, tree_hash.as_bytes()).unwrap(), grown);
+        tree.set(new_leaf_idx, RatchetTreeNode::Blank).unwrap();
+        tree.truncate_to_last_nonblank();
+        let shrunk = tree.to_extension().unwrap();
+        let patch = TreePatch::between(cs, &grown, &shrunk).unwrap();
+        assert_eq!(patch.num_patched_nodes(), 0);
+        let tree_hash = tree.tree_hash(cs).unwrap();
+        assert_eq!(patch.apply(cs, &grown, tree_hash.as_bytes()).unwrap(), shrunk);
+
+        // Nodes out of order and out of bounds are rejected
+        let mut patch = parse(&patch_bytes);
+        patch.nodes.reverse();
+        if patch.nodes.len() > 1 {
+            assert!(old_tree.apply_patch(cs, patch).is_err());
+        }
+        let out_of_bounds = TreePatch {
+            num_nodes: old_tree.size() as u32,
+            nodes: vec![PatchedNode {
+                node_index: old_tree.size() as u32,
+                node: None,
+            }],
+        };
+        assert!(old_tree.apply_patch(cs, out_of_bounds).is_err());
+    }
+
+    // Check that the proof of every leaf verifies against the tree hash, including after a trip
+    // over the wire and with blank nodes on the path, and that tampering with it is caught
+    #[quickcheck]
+    fn leaf_proof_roundtrip(num_leaves: u8, rng_seed: u64) {
+        if num_leaves > 30 || num_leaves < 2 {
+            return;
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        for i in 0..(num_leaves as usize) {
+            let mut buf = [0u8; 32];
+            rng.fill_bytes(&mut buf);
+            tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&buf), 2 * i).unwrap();
+            tree.set_path_parent_hashes(cs, 2 * i).unwrap();
+        }
+        tree.propagate_blank(2 * rng.gen_range(0, num_leaves as usize));
+        let tree_hash = tree.tree_hash(cs).unwrap();
+        let parse = |bytes: &[u8]| {
+            let mut cursor = bytes;
+            LeafProof::tls_deserialize(&mut TlsDeserializer::from_reader(&mut cursor)).unwrap()
+        };
+
+        for leaf_index in 0..(num_leaves as u32) {
+            let leaf_tree_idx = 2 * leaf_index as usize;
+            let proof = tree.leaf_proof(cs, leaf_tree_idx).unwrap();
+            assert_eq!(proof.leaf_index(), leaf_index);
+            assert_eq!(proof.num_leaves(), num_leaves as u32);
+            assert_eq!(
+                proof.leaf_public_key(),
+                tree.nodes[leaf_tree_idx].get_public_key().map(DhPublicKey::as_bytes)
+            );
+            proof.verify(cs, tree_hash.as_bytes()).unwrap();
+            let proof_bytes = tls_ser::serialize_to_bytes(&proof).unwrap();
+            parse(&proof_bytes).verify(cs, tree_hash.as_bytes()).unwrap();
+
+            // The proof is only good for the tree hash it was made against
+            assert!(proof.verify(cs, &[0u8; 32]).is_err());
+
+            // It can't be moved to another leaf
+            let mut moved = parse(&proof_bytes);
+            moved.leaf_index = (leaf_index + 1) % num_leaves as u32;
+            assert!(moved.verify(cs, tree_hash.as_bytes()).is_err());
+
+            // Changing the leaf, or a parent hash on the path, breaks it
+            let mut changed_leaf = parse(&proof_bytes);
+            changed_leaf.leaf = match changed_leaf.leaf {
+                Some(_) => None,
+                None => Some(WireLeafNode {
+                    public_key: DhPublicKey::Raw(DhPublicKeyRaw(vec![0u8; 32])),
+                    parent_hash: Digest::default(),
+                }),
+            };
+            assert!(changed_leaf.verify(cs, tree_hash.as_bytes()).is_err());
+            let mut changed_parent = parse(&proof_bytes);
+            let filled_parent =
+                changed_parent.steps.iter_mut().filter_map(|step| step.parent.as_mut()).next();
+            if let Some(parent) = filled_parent {
+                parent.parent_hash = Digest::new_from_zeros(cs.hash_impl);
+                assert!(changed_parent.verify(cs, tree_hash.as_bytes()).is_err());
+            }
+
+            // And so does dropping a step
+            let mut truncated = parse(&proof_bytes);
+            truncated.steps.pop();
+            assert!(truncated.verify(cs, tree_hash.as_bytes()).is_err());
+        }
+    }
+
+    // Check that an UpdatePath has a node for every parent on the sender's direct path, with a
+    // ciphertext for every node in the copath child's resolution, that every other leaf decrypts
+    // the path secret of its common ancestor with the sender, and that the HPKE context matters
+    #[quickcheck]
+    fn update_path_roundtrip(num_leaves: u8, rng_seed: u64) {
+        if num_leaves > 30 || num_leaves < 2 {
+            return;
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let num_leaves = num_leaves as usize;
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        for i in 0..num_leaves {
+            let mut buf = [0u8; 32];
+            rng.fill_bytes(&mut buf);
+            tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&buf), 2 * i).unwrap();
+        }
+
+        let sender_idx = 2 * rng.gen_range(0, num_leaves);
+        let leaf_secret = PathSecret::new_from_random(cs, &mut rng);
+        let path = tree
+            .encrypt_update_path(cs, sender_idx, leaf_secret.clone(), b"context", &mut rng)
+            .unwrap();
+        let direct_path: Vec<usize> = tree_math::node_direct_path(sender_idx, num_leaves).collect();
+        assert_eq!(path.nodes.len(), direct_path.len());
+        for (node, &path_node_idx) in path.nodes.iter().zip(direct_path.iter()) {
+            let copath_node_idx = tree_math::node_sibling(path_node_idx, num_leaves);
+            assert_eq!(node.encrypted_path_secret.len(), tree.resolution(copath_node_idx).len());
+        }
+
+        // The path secret of every node above the sender, from the bottom up
+        let mut path_secrets = vec![leaf_secret];
+        for _ in 0..direct_path.len() {
+            let below = path_secrets.last().unwrap().clone();
+            let (_, _, _, path_secret) = utils::derive_node_values(cs, below).unwrap();
+            path_secrets.push(path_secret);
+        }
+        let extended_direct_path: Vec<usize> =
+            tree_math::node_extended_direct_path(sender_idx, num_leaves).collect();
+
+        for my_idx in (0..num_leaves).map(|i| 2 * i).filter(|&i| i != sender_idx) {
+            let (path_secret, common_ancestor) =
+                tree.decrypt_update_path(cs, &path, sender_idx, my_idx, b"context").unwrap();
+            let pos = extended_direct_path.iter().position(|&i| i == common_ancestor).unwrap();
+            assert_eq!(path_secret.0, path_secrets[pos].0);
+            assert!(tree.decrypt_update_path(cs, &path, sender_idx, my_idx, b"other").is_err());
+        }
+
+        // The path survives a trip through a DirectPathMessage, whose first node is the leaf's
+        let mut direct_path_msg = DirectPathMessage::from(path.clone());
+        assert_eq!(direct_path_msg.node_messages.len(), path.nodes.len() + 1);
+        let roundtrip = direct_path_msg.to_update_path().unwrap();
+        assert_eq!(
+            tls_ser::serialize_to_bytes(&roundtrip).unwrap(),
+            tls_ser::serialize_to_bytes(&path).unwrap()
+        );
+        let ciphertexts = direct_path_msg.node_messages[1].node_secrets.clone();
+        direct_path_msg.node_messages[0].node_secrets = ciphertexts;
+        assert!(direct_path_msg.to_update_path().is_err());
+    }
+
+    // Check that a tree behaves the same whichever way it keeps its nodes, including after it's
+    // truncated, and after it's switched back
+    #[quickcheck]
+    fn sparse_storage_agrees(num_leaves: u8, rng_seed: u64) {
+        if num_leaves > 30 || num_leaves < 2 {
+            return;
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let mut dense = RatchetTree::new_from_nodes(Vec::new());
+        let mut sparse = RatchetTree::new_from_nodes(Vec::new());
+        sparse.set_storage(TreeStorage::Sparse);
+        for _ in 0..num_leaves {
+            dense.add_leaf_node(RatchetTreeNode::Blank);
+            sparse.add_leaf_node(RatchetTreeNode::Blank);
+        }
+
+        // Leave plenty of blanks: every third leaf updates, then one leaf's path is blanked and
+        // the last leaf is unmerged
+        for i in (0..num_leaves).step_by(3) {
+            let mut buf = [0u8; 32];
+            rng.fill_bytes(&mut buf);
+            let leaf_idx = 2 * i as usize;
+            for tree in [&mut dense, &mut sparse].iter_mut() {
+                tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&buf), leaf_idx)
+                    .unwrap();
+                tree.set_path_parent_hashes(cs, leaf_idx).unwrap();
+            }
+        }
+        let blanked_idx = 2 * rng.gen_range(0, num_leaves as usize);
+        let unmerged_idx = 2 * (num_leaves as usize - 1);
+        let public_key = dense.nodes[0].get_public_key().unwrap().clone();
+        for tree in [&mut dense, &mut sparse].iter_mut() {
+            tree.propagate_blank(blanked_idx);
+            tree.update(unmerged_idx, |node| node.update_public_key(public_key.clone())).unwrap();
+            tree.add_unmerged_leaf(unmerged_idx);
+        }
+
+        assert_eq!(sparse.storage(), TreeStorage::Sparse);
+        assert_eq!(
+            dense.tree_hash(cs).unwrap().as_bytes(),
+            sparse.tree_hash(cs).unwrap().as_bytes()
+        );
+        assert_eq!(dense.to_wire().unwrap(), sparse.to_wire().unwrap());
+        assert_eq!(
+            tls_ser::serialize_to_bytes(&dense).unwrap(),
+            tls_ser::serialize_to_bytes(&sparse).unwrap()
+        );
+        for idx in 0..dense.size() {
+            assert_eq!(dense.resolution(idx), sparse.resolution(idx));
+        }
+        // Blanking a node through the tree's methods doesn't leave it in the map
+        if let NodeStore::Sparse {
+            ref filled,
+            ..
+        } = sparse.nodes
+        {
+            assert!(filled.values().all(RatchetTreeNode::is_filled));
+        }
+
+        dense.truncate_to_last_nonblank();
+        sparse.truncate_to_last_nonblank();
+        assert_eq!(dense.to_wire().unwrap(), sparse.to_wire().unwrap());
+        sparse.set_storage(TreeStorage::Dense);
+        assert_eq!(sparse.storage(), TreeStorage::Dense);
+        assert_eq!(dense.to_wire().unwrap(), sparse.to_wire().unwrap());
+    }
+
+    // Check that a sparse store only ever holds filled nodes, so that it stays small, even after
+    // every node has been looked up mutably, gone through `update`, and been blanked
+    #[test]
+    fn sparse_store_stays_bounded() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let num_leaves = 64;
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        tree.set_storage(TreeStorage::Sparse);
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        let mut buf = [0u8; 32];
+        rng.fill_bytes(&mut buf);
+        tree.propagate_new_path_secret(cs, PathSecret::new_from_bytes(&buf), 0).unwrap();
+
+        let num_stored = |tree: &RatchetTree| match tree.nodes {
+            NodeStore::Sparse {
+                ref filled,
+                ..
+            } => filled.len(),
+            NodeStore::Dense(_) => panic!("tree isn't sparse"),
+        };
+        let num_filled = tree.nodes.iter().filter(|node| node.is_filled()).count();
+        assert_eq!(num_stored(&tree), num_filled);
+
+        // Looking at every node, mutably or not, doesn't store the blank ones
+        for idx in 0..tree.size() {
+            if let Some(node) = tree.nodes.get_mut(idx) {
+                assert!(node.is_filled());
+            }
+            tree.update(idx, |_| ()).unwrap();
+        }
+        assert_eq!(num_stored(&tree), num_filled);
+
+        // Blanking every path, starting from every leaf, forgets every node on the way
+        for leaf_idx in (0..tree.size()).step_by(2) {
+            tree.propagate_blank(leaf_idx);
+        }
+        tree.set(0, RatchetTreeNode::Blank).unwrap();
+        assert_eq!(num_stored(&tree), 0);
+        assert_eq!(tree.size(), tree_math::num_nodes_in_tree(num_leaves));
+    }
+
+    // Check that cached resolutions are forgotten whenever a change could affect them, by
+    // comparing them to ones computed from scratch after every kind of change
+    #[quickcheck]
+    fn resolution_cache_invalidation(num_leaves: u8, rng_seed: u64) {
+        if num_leaves > 30 || num_leaves < 2 {
+            return;
+        }
+
+        fn check_resolutions(tree: &mut RatchetTree) {
+            let mut uncached_tree =
+                RatchetTree::new_from_nodes(tree.nodes.iter().cloned().collect());
+            for idx in 0..tree.size() {
+                assert_eq!(tree.resolution(idx), uncached_tree.resolution(idx));
+            }
+        }
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        for _ in 0..num_leaves {
+            tree.add_leaf_node(RatchetTreeNode::Blank);
+        }
+        let mut random_path_secret = || {
+            let mut buf = [0u8; 32];
+            rng.fill_bytes(&mut buf);
+            PathSecret::new_from_bytes(&buf)
+        };
+
+        // Every other leaf updates, so plenty of parents are blank
+        for i in (0..num_leaves as usize).step_by(2) {
+            tree.propagate_new_path_secret(cs, random_path_secret(), 2 * i).unwrap();
+        }
+        check_resolutions(&mut tree);
+
+        // A new leaf is added in place and unmerged, and then another one is appended
+        let new_leaf_idx = 2 * (num_leaves as usize - 1);
+        let public_key = tree.get(0).unwrap().get_public_key().unwrap().clone();
+        tree.update(new_leaf_idx, |node| node.update_public_key(public_key)).unwrap();
+        tree.add_unmerged_leaf(new_leaf_idx);
+        check_resolutions(&mut tree);
+        tree.add_leaf_node(RatchetTreeNode::Blank);
+        check_resolutions(&mut tree);
+
+        // A path is blanked, another one updates through it, and then the tree is truncated
+        tree.propagate_blank(0);
+        check_resolutions(&mut tree);
+        tree.propagate_new_path_secret(cs, random_path_secret(), 2).unwrap();
+        check_resolutions(&mut tree);
+        tree.truncate_to_last_nonblank();
+        check_resolutions(&mut tree);
+        tree.set_unmerged_leaves(tree.unmerged_leaves()).unwrap();
+        check_resolutions(&mut tree);
+    }
+
+    // Not a test, but a benchmark of the two kinds of storage on a big tree where one in 16
+    // leaves is filled and every parent is blank, as right after a big batch of Adds. Run it with
+    // `cargo test --release tree_storage_tradeoff -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn tree_storage_tradeoff() {
+        let cs: &'static CipherSuite = &X25519_SHA256_AES128GCM;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let num_leaves = 1 << 14;
+        let mut nodes = vec![RatchetTreeNode::Blank; tree_math::num_nodes_in_tree(num_leaves)];
+        for leaf_idx in (0..num_leaves).step_by(16) {
+            let private_key = DhPrivateKey::new_from_random(cs.dh_impl, &mut rng).unwrap();
+            nodes[2 * leaf_idx] = RatchetTreeNode::new_from_private_key(cs, private_key);
+        }
+        let root_idx = tree_math::root_idx(num_leaves);
+
+        for &storage in [TreeStorage::Dense, TreeStorage::Sparse].iter() {
+            let mut tree = RatchetTree::new_from_nodes(nodes.clone());
+            tree.set_storage(storage);
+
+            // This is only what the store itself takes up. The heap data of the filled nodes is
+            // the same either way.
+            let store_size = match tree.nodes {
+                NodeStore::Dense(ref nodes) => {
+                    nodes.capacity() * std::mem::size_of::<RatchetTreeNode>()
+                }
+                NodeStore::Sparse {
+                    ref filled,
+                    ..
+                } => filled.capacity() * (std::mem::size_of::<(usize, RatchetTreeNode)>() + 1),
+            };
+            let start = std::time::Instant::now();
+            tree.tree_hash(cs).unwrap();
+            let tree_hash_time = start.elapsed();
+            let start = std::time::Instant::now();
+            for _ in 0..100 {
+                tree.resolution(root_idx);
+            }
+            let resolution_time = start.elapsed();
+
+            println!(
+                "{:?}: {} bytes of node storage, tree hash in {:?}, 100 root resolutions in {:?}",
+                storage, store_size, tree_hash_time, resolution_time
+            );
+        }
+    }
+
+    // Check that growing a tree goes to the next power of two leaves, on both sides of the
+    // boundary sizes, and that truncating undoes it
+    #[test]
+    fn tree_extension_and_truncation() {
+        for num_leaves in 1..=17usize {
+            let mut tree = RatchetTree::new_from_nodes(Vec::new());
+            for _ in 0..num_leaves {
+                tree.add_leaf_node(RatchetTreeNode::Filled {
+                    public_key: DhPublicKey::Raw(DhPublicKeyRaw(Vec::new())),
+                    private_key: None,
+                    unmerged_leaves: Vec::new(),
+                    parent_hash: Digest::default(),
+                });
+            }
+
+            // A tree that's big enough stays the way it is
+            tree.extend_to_fit(num_leaves);
+            assert_eq!(tree.num_leaves(), num_leaves);
+
+            // One more leaf than there's room for grows the tree to the next power of two. The
+            // old nodes stay where they were, and everything new is blank.
+            let old_size = tree.size();
+            tree.extend_to_fit(num_leaves + 1);
+            assert_eq!(tree.num_leaves(), (num_leaves + 1).next_power_of_two());
+            assert!(tree.nodes.iter().take(old_size).step_by(2).all(RatchetTreeNode::is_filled));
+            assert!(tree.nodes.iter().skip(old_size).all(|node| !node.is_filled()));
+
+            // Filling the new leaves doesn't grow it again
+            tree.extend_to_fit(tree.num_leaves());
+            assert_eq!(tree.num_leaves(), (num_leaves + 1).next_power_of_two());
+
+            tree.truncate_to_last_nonblank();
+            assert_eq!(tree.num_leaves(), num_leaves);
+        }
+
+        // An empty tree grows to exactly the leaves it needs if that's a power of two
+        let mut tree = RatchetTree::new_from_nodes(Vec::new());
+        tree.extend_to_fit(4);
+        assert_eq!(tree.num_leaves(), 4);
+        tree.truncate_to_last_nonblank();
+        assert_eq!(tree.num_leaves(), 0);
+    }
+
     // Tests against the official tree math test vector. See above comment for explanation.
     #[test]
     fn official_resolution_kat() {
         // Helper function
-        fn u8_resolution(tree: &RatchetTree, idx: usize) -> Vec<u8> {
+        fn u8_resolution(tree: &mut RatchetTree, idx: usize) -> Vec<u8> {
             tree.resolution(idx)
                 .into_iter()
                 .map(|i| {
@@ -752,29 +3240,29 @@ mod test {
                     nodes.push(RatchetTreeNode::Filled {
                         public_key: DhPublicKey::Raw(DhPublicKeyRaw(Vec::new())),
                         private_key: None,
+                        unmerged_leaves: Vec::new(),
+                        parent_hash: Digest::default(),
                     });
                 }
                 bit_mask <<= 1;
             }
 
-            RatchetTree {
-                nodes,
-            }
+            RatchetTree::new_from_nodes(nodes)
         }
 
         let mut f = std::fs::File::open("test_vectors/resolution.bin").unwrap();
         let mut deserializer = TlsDeserializer::from_reader(&mut f);
-        let test_vec = ResolutionTestVectors::deserialize(&mut deserializer).unwrap();
+        let test_vec = ResolutionTestVectors::tls_deserialize(&mut deserializer).unwrap();
         let num_nodes = tree_math::num_nodes_in_tree(test_vec.num_leaves as usize);
 
         // encoded_tree is the index into the case; this can be decoded into a RatchetTree by
         // parsing the u32 bit by bit
         for (encoded_tree, case) in test_vec.cases.into_iter().enumerate() {
-            let tree = make_tree_from_int(encoded_tree, num_nodes);
+            let mut tree = make_tree_from_int(encoded_tree, num_nodes);
 
             // We compute the resolution of every node in the tree
             for (idx, expected_resolution) in case.0.into_iter().enumerate() {
-                let derived_resolution = u8_resolution(&tree, idx);
+                let derived_resolution = u8_resolution(&mut tree, idx);
                 assert_eq!(derived_resolution, expected_resolution.0);
             }
         }
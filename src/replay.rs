@@ -0,0 +1,125 @@
+//! Defines the `ReplayGuard`, which is how a member remembers which messages they've already
+//! decrypted, so that a message that's delivered again is rejected as a replay, rather than with
+//! whatever error its used-up key happens to cause.
+
+use crate::{error::Error, secret_tree::RatchetType};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The sender, ratchet, and generation of every `PrivateMessage` this member decrypted in each of
+/// the last few epochs. Handshakes and application messages are encrypted under different
+/// ratchets, whose generations both start at 0, so the ratchet is part of what's remembered too.
+/// This is local bookkeeping, so it's never sent.
+#[derive(Clone)]
+pub(crate) struct ReplayGuard {
+    /// How many epochs before the newest one with a decrypted message are still remembered
+    retention_epochs: u32,
+    /// What was decrypted in each remembered epoch
+    seen: BTreeMap<u32, BTreeSet<(u32, RatchetType, u32)>>,
+}
+
+impl ReplayGuard {
+    /// Makes a guard that remembers messages from the newest epoch it's seen one from, and from
+    /// the `retention_epochs` epochs before that
+    pub(crate) fn new(retention_epochs: u32) -> ReplayGuard {
+        ReplayGuard {
+            retention_epochs,
+            seen: BTreeMap::new(),
+        }
+    }
+
+    /// Returns how many epochs before the newest one are remembered
+    pub(crate) fn retention_epochs(&self) -> u32 {
+        self.retention_epochs
+    }
+
+    /// Sets how many epochs before the newest one are remembered, and forgets those that are
+    /// now outside of it
+    pub(crate) fn set_retention_epochs(&mut self, retention_epochs: u32) {
+        self.retention_epochs = retention_epochs;
+        self.prune();
+    }
+
+    /// Checks that no message from `sender` under `generation` of their ratchet of type
+    /// `ratchet_type` was decrypted in `epoch`
+    ///
+    /// Returns: `Ok(())` if there was none, or if `epoch` is too old to be remembered. Otherwise,
+    /// returns an `Error::ReplayedMessage`.
+    pub(crate) fn check(
+        &self,
+        epoch: u32,
+        sender: u32,
+        ratchet_type: RatchetType,
+        generation: u32,
+    ) -> Result<(), Error> {
+        let seen = self.seen.get(&epoch);
+        if seen.map_or(false, |seen| seen.contains(&(sender, ratchet_type, generation))) {
+            Err(Error::ReplayedMessage(epoch, sender, generation))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remembers that a message from `sender` under `generation` of their ratchet of type
+    /// `ratchet_type` was decrypted in `epoch`, and forgets the epochs that are now too old
+    pub(crate) fn record(
+        &mut self,
+        epoch: u32,
+        sender: u32,
+        ratchet_type: RatchetType,
+        generation: u32,
+    ) {
+        self.seen.entry(epoch).or_default().insert((sender, ratchet_type, generation));
+        self.prune();
+    }
+
+    /// Forgets every epoch more than `retention_epochs` before the newest one
+    fn prune(&mut self) {
+        let newest = match self.seen.keys().next_back() {
+            Some(&epoch) => epoch,
+            None => return,
+        };
+        self.seen = self.seen.split_off(&newest.saturating_sub(self.retention_epochs));
+    }
+}
+
+impl Default for ReplayGuard {
+    /// Remembers the newest epoch and the one before it, so that replays are caught across an
+    /// epoch change
+    fn default() -> ReplayGuard {
+        ReplayGuard::new(1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::Error, replay::ReplayGuard, secret_tree::RatchetType};
+
+    // Check that recorded messages are caught as replays for as long as their epoch is retained,
+    // and that the ratchet type is part of what's remembered
+    #[test]
+    fn retention_horizon() {
+        let mut guard = ReplayGuard::new(2);
+        guard.record(3, 1, RatchetType::Application, 0);
+        match guard.check(3, 1, RatchetType::Application, 0) {
+            Err(Error::ReplayedMessage(3, 1, 0)) => (),
+            _ => panic!("replayed message wasn't caught"),
+        }
+        assert!(guard.check(3, 1, RatchetType::Handshake, 0).is_ok());
+        assert!(guard.check(3, 0, RatchetType::Application, 0).is_ok());
+        assert!(guard.check(3, 1, RatchetType::Application, 1).is_ok());
+        assert!(guard.check(4, 1, RatchetType::Application, 0).is_ok());
+
+        // Epoch 3 is remembered until a message from epoch 6 is recorded
+        guard.record(5, 0, RatchetType::Handshake, 7);
+        assert!(guard.check(3, 1, RatchetType::Application, 0).is_err());
+        guard.record(6, 0, RatchetType::Handshake, 0);
+        assert!(guard.check(3, 1, RatchetType::Application, 0).is_ok());
+        assert!(guard.check(5, 0, RatchetType::Handshake, 7).is_err());
+
+        // Narrowing the horizon forgets what's outside of it right away
+        guard.set_retention_epochs(0);
+        assert!(guard.check(5, 0, RatchetType::Handshake, 7).is_ok());
+        assert!(guard.check(6, 0, RatchetType::Handshake, 0).is_err());
+    }
+}
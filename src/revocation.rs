@@ -0,0 +1,126 @@
+//! Defines `RevocationChecker` and `AsyncRevocationChecker`, which are how a `GroupState` asks the
+//! application whether the certificates in X.509 credentials have been revoked, e.g., by fetching
+//! a CRL or asking an OCSP responder, `RevocationCache`, which keeps their answers until they
+//! expire, and `RevokedMember`, which is how a group says that one of its members has been revoked
+
+use crate::{
+    credential::{Credential, X509CertData},
+    handshake::Proposal,
+};
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Mutex, MutexGuard},
+};
+
+/// What a revocation checker says about a certificate
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RevocationStatus {
+    /// The certificate hasn't been revoked
+    Good,
+    /// The certificate has been revoked
+    Revoked,
+    /// The checker couldn't find out, e.g., because the responder couldn't be reached. Such
+    /// certificates are accepted, since otherwise an outage would keep everyone out of the group.
+    Unknown,
+}
+
+/// A trait representing an application-provided revocation check that answers right away, e.g.,
+/// from a CRL the application keeps up to date. This is consulted for the leaf certificate of
+/// every X.509 credential encountered in an Add or an Update whose status isn't cached, after
+/// its chain is validated. A handshake with a revoked credential fails with an
+/// `Error::CredentialRejected`.
+pub trait RevocationChecker: Send + Sync {
+    /// Returns the revocation status of the leaf certificate of `chain`, which is leaf first, and
+    /// how many seconds the answer may be cached for
+    fn check(&self, chain: &[X509CertData]) -> (RevocationStatus, u64);
+}
+
+/// A future that resolves to what a `RevocationChecker::check` would return
+pub type RevocationFuture = Pin<Box<dyn Future<Output = (RevocationStatus, u64)> + Send>>;
+
+/// A trait representing an application-provided revocation check that takes a while, e.g., an
+/// OCSP request. Handshakes can't wait for these, so they're run ahead of time by
+/// `GroupState::check_member_revocations_async`, and handshakes use the cached answers.
+pub trait AsyncRevocationChecker: Send + Sync {
+    /// Finds the revocation status of the leaf certificate of `chain`, which is leaf first, and
+    /// how many seconds the answer may be cached for
+    fn check(&self, chain: Vec<X509CertData>) -> RevocationFuture;
+}
+
+/// The revocation statuses that checkers have given, by leaf certificate, along with when they
+/// expire, in seconds since the Unix epoch. A `GroupState` shares its cache with its successors,
+/// since revocation has nothing to do with epochs.
+#[derive(Default)]
+pub struct RevocationCache {
+    statuses: Mutex<HashMap<Vec<u8>, (RevocationStatus, u64)>>,
+}
+
+impl RevocationCache {
+    /// Makes a cache with nothing in it
+    pub fn new() -> RevocationCache {
+        RevocationCache::default()
+    }
+
+    /// Locks the map of statuses. No panic can leave it half-updated, so a poisoned lock is as
+    /// good as any.
+    fn statuses(&self) -> MutexGuard<'_, HashMap<Vec<u8>, (RevocationStatus, u64)>> {
+        self.statuses.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Returns the cached status of the leaf certificate of `chain`, if there is one that hasn't
+    /// expired by `now`
+    pub(crate) fn get(&self, chain: &[X509CertData], now: u64) -> Option<RevocationStatus> {
+        let leaf = chain.first()?;
+        match self.statuses().get(leaf.as_der()) {
+            Some((status, expires_at)) if now < *expires_at => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Caches `status` for the leaf certificate of `chain` for `ttl` seconds from `now`
+    pub(crate) fn insert(
+        &self,
+        chain: &[X509CertData],
+        status: RevocationStatus,
+        ttl: u64,
+        now: u64,
+    ) {
+        if let Some(leaf) = chain.first() {
+            self.statuses().insert(leaf.as_der().to_vec(), (status, now.saturating_add(ttl)));
+        }
+    }
+
+    /// Forgets every cached status, e.g., after a new CRL came out
+    pub fn clear(&self) {
+        self.statuses().clear();
+    }
+}
+
+/// A member of a group whose credential has been revoked, as returned by
+/// `GroupState::check_member_revocations`. They should be removed from the group.
+#[derive(Clone, Debug)]
+pub struct RevokedMember {
+    pub(crate) roster_index: u32,
+    pub(crate) credential: Credential,
+}
+
+impl RevokedMember {
+    /// Returns the member's position in the roster
+    pub fn roster_index(&self) -> u32 {
+        self.roster_index
+    }
+
+    /// Returns the member's revoked credential
+    pub fn credential(&self) -> &Credential {
+        &self.credential
+    }
+
+    /// Makes a proposal to remove the member, which is what should be done about them
+    // This is just a wrapper around Proposal::new_remove
+    pub fn remove_proposal(&self) -> Proposal {
+        Proposal::new_remove(self.roster_index)
+    }
+}
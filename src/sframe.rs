@@ -0,0 +1,252 @@
+//! Defines how SFrame media keys are derived from a group, as in the MLS mode of SFrame (RFC 9605,
+//! section 5.2). Every epoch, every member derives the same base key for each sender from the
+//! epoch's exporter secret, and an SFrame key ID that says whose base key it is and which epoch
+//! it's from. SFrame then derives its AEAD key and salt from the base key as it always does, which
+//! `SframeSenderKey::key_and_salt` also does for applications that don't have that part already.
+
+use crate::{
+    crypto::{ciphersuite::CipherSuite, hkdf, hmac::HmacKey, secret::Secret},
+    error::Error,
+};
+
+/// The label that a group's SFrame epoch secrets are exported under
+pub(crate) const SFRAME_EXPORTER_LABEL: &[u8] = b"SFrame 1.0 Base Key";
+
+/// How an SFrame key ID is split up. From the most significant bit down, it's an
+/// application-chosen context ID, then the sender's roster index in `index_bits` bits, then the
+/// low `epoch_bits` bits of the epoch. Recipients only need the low bits of the epoch, since they
+/// only keep the keys of the last few epochs around.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SframeKidLayout {
+    index_bits: u32,
+    epoch_bits: u32,
+}
+
+impl SframeKidLayout {
+    /// Makes a layout with the given number of bits for the sender's roster index and the epoch
+    ///
+    /// Returns: `Ok(layout)` on success. If `index_bits` exceeds 32, or `epoch_bits` exceeds 32,
+    /// or together they exceed 64, returns an `Error::ValidationError`.
+    pub fn new(index_bits: u32, epoch_bits: u32) -> Result<SframeKidLayout, Error> {
+        if index_bits > 32 || epoch_bits > 32 || index_bits + epoch_bits > 64 {
+            return Err(Error::ValidationError("SFrame key ID layout doesn't fit in 64 bits"));
+        }
+        Ok(SframeKidLayout {
+            index_bits,
+            epoch_bits,
+        })
+    }
+
+    /// Returns the key ID of the sender at `sender_index` in `epoch`, in the context `context_id`:
+    /// `KID = (context_id << (index_bits + epoch_bits)) + (sender_index << epoch_bits) +
+    /// (epoch % 2^epoch_bits)`
+    ///
+    /// Returns: `Ok(kid)` on success. If `sender_index` doesn't fit in `index_bits` bits, or
+    /// `context_id` doesn't fit in what's left, returns an `Error::ValidationError`.
+    pub fn kid(&self, context_id: u64, sender_index: u32, epoch: u32) -> Result<u64, Error> {
+        let context_bits = 64 - self.index_bits - self.epoch_bits;
+        if !fits(u64::from(sender_index), self.index_bits) {
+            return Err(Error::ValidationError("Sender index doesn't fit in the SFrame key ID"));
+        }
+        if !fits(context_id, context_bits) {
+            return Err(Error::ValidationError("Context ID doesn't fit in the SFrame key ID"));
+        }
+
+        // A part with no bits is 0, so shifting it all the way out leaves it as it is
+        let context_part = shift_left(context_id, self.index_bits + self.epoch_bits);
+        let index_part = shift_left(u64::from(sender_index), self.epoch_bits);
+        let epoch_part = u64::from(epoch) & low_bits_mask(self.epoch_bits);
+        Ok(context_part | index_part | epoch_part)
+    }
+
+    /// Splits `kid` back up into its context ID, sender index, and the low bits of its epoch
+    pub fn parse(&self, kid: u64) -> (u64, u32, u32) {
+        let context_id = shift_right(kid, self.index_bits + self.epoch_bits);
+        // These fit in 32 bits, since index_bits and epoch_bits are at most 32
+        let sender_index =
+            (shift_right(kid, self.epoch_bits) & low_bits_mask(self.index_bits)) as u32;
+        let epoch_bits = (kid & low_bits_mask(self.epoch_bits)) as u32;
+        (context_id, sender_index, epoch_bits)
+    }
+}
+
+/// Returns whether `value` fits in `bits` bits
+fn fits(value: u64, bits: u32) -> bool {
+    value & !low_bits_mask(bits) == 0
+}
+
+/// Returns the mask of the low `bits` bits of a `u64`
+fn low_bits_mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        std::u64::MAX
+    } else {
+        (1u64 << bits) - 1
+    }
+}
+
+/// Shifts `value` left by `bits`, which is 0 if `bits` is 64 or more
+fn shift_left(value: u64, bits: u32) -> u64 {
+    value.checked_shl(bits).unwrap_or(0)
+}
+
+/// Shifts `value` right by `bits`, which is 0 if `bits` is 64 or more
+fn shift_right(value: u64, bits: u32) -> u64 {
+    value.checked_shr(bits).unwrap_or(0)
+}
+
+/// One sender's SFrame base key for an epoch, along with the key ID their media is encrypted
+/// under. This is what `GroupState::sframe_sender_key` returns.
+pub struct SframeSenderKey {
+    cs: &'static CipherSuite,
+    kid: u64,
+    base_key: Secret,
+}
+
+impl SframeSenderKey {
+    /// Derives the base key of the sender at `sender_index` from an epoch's SFrame epoch secret,
+    /// which is `MLS-Exporter("SFrame 1.0 Base Key", "", Hash.length)`:
+    /// `base_key = HKDF-Expand(sframe_epoch_secret, encode_big_endian(sender_index, 4),
+    /// Hash.length)`
+    pub(crate) fn new(
+        cs: &'static CipherSuite,
+        sframe_epoch_secret: &[u8],
+        sender_index: u32,
+        kid: u64,
+    ) -> Result<SframeSenderKey, Error> {
+        let hash_impl = cs.hash_impl;
+        let epoch_secret = HmacKey::new_from_bytes(sframe_epoch_secret);
+        let mut base_key = Secret::new_from_zeros(hash_impl.digest_size());
+        // A u32 serializes as its 4 big-endian bytes
+        hkdf::expand(hash_impl, &epoch_secret, &sender_index, base_key.as_mut_bytes())?;
+
+        Ok(SframeSenderKey {
+            cs,
+            kid,
+            base_key,
+        })
+    }
+
+    /// Returns the SFrame key ID that this key is used under
+    pub fn kid(&self) -> u64 {
+        self.kid
+    }
+
+    /// Returns the SFrame base key. Handle this with care: anyone who has it can encrypt media as
+    /// its sender.
+    pub fn base_key(&self) -> &[u8] {
+        self.base_key.as_bytes()
+    }
+
+    /// Derives the AEAD key and salt that SFrame encrypts under with this base key, for the SFrame
+    /// cipher suite with ID `sframe_cipher_suite`, whose AEAD has keys of `key_len` bytes and
+    /// nonces of `salt_len` bytes. The SFrame cipher suite has to use the same hash function as
+    /// the group's cipher suite.
+    /// ```text
+    /// sframe_secret = HKDF-Extract("", base_key)
+    /// sframe_key = HKDF-Expand(sframe_secret,
+    ///                          "SFrame 1.0 Secret key " + KID + cipher_suite, key_len)
+    /// sframe_salt = HKDF-Expand(sframe_secret,
+    ///                           "SFrame 1.0 Secret salt " + KID + cipher_suite, salt_len)
+    /// ```
+    /// where `KID` and `cipher_suite` are encoded as 8 and 2 big-endian bytes respectively.
+    ///
+    /// Returns: `Ok((key, salt))` on success. If `key_len` or `salt_len` exceeds
+    /// `255 * Hash.length`, returns an `Error::KdfError`.
+    pub fn key_and_salt(
+        &self,
+        sframe_cipher_suite: u16,
+        key_len: usize,
+        salt_len: usize,
+    ) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let hash_impl = self.cs.hash_impl;
+        if key_len.max(salt_len) > 255 * hash_impl.digest_size() {
+            return Err(Error::KdfError("Requested SFrame key or salt is too long"));
+        }
+
+        let sframe_secret =
+            hkdf::extract(hash_impl, &HmacKey::new_from_bytes(&[]), self.base_key());
+        let info = |label: &[u8]| -> Vec<u8> {
+            [label, &self.kid.to_be_bytes()[..], &sframe_cipher_suite.to_be_bytes()[..]].concat()
+        };
+
+        let mut key = vec![0u8; key_len];
+        hkdf::expand(hash_impl, &sframe_secret, &info(b"SFrame 1.0 Secret key "), &mut key)?;
+        let mut salt = vec![0u8; salt_len];
+        hkdf::expand(hash_impl, &sframe_secret, &info(b"SFrame 1.0 Secret salt "), &mut salt)?;
+        Ok((key, salt))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::Error, ratchet_tree::PathSecret, sframe::SframeKidLayout, test_utils};
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Check that key IDs come apart into what they were made of, and that what doesn't fit is
+    // rejected
+    #[quickcheck]
+    fn kid_layout(context_id: u8, sender_index: u16, epoch: u32) {
+        let layout = SframeKidLayout::new(16, 8).unwrap();
+        let kid = layout.kid(u64::from(context_id), u32::from(sender_index), epoch).unwrap();
+        assert_eq!(kid >> 24, u64::from(context_id));
+        assert_eq!(
+            layout.parse(kid),
+            (u64::from(context_id), u32::from(sender_index), epoch & 0xff)
+        );
+
+        match layout.kid(0, 1 << 16, epoch) {
+            Err(Error::ValidationError(_)) => (),
+            _ => panic!("sender index that doesn't fit was accepted"),
+        }
+        assert!(layout.kid(1 << 40, 0, epoch).is_err());
+        assert!(SframeKidLayout::new(33, 0).is_err());
+
+        // Layouts that use up all 64 bits leave no room for a context ID
+        let full = SframeKidLayout::new(32, 32).unwrap();
+        assert_eq!(full.kid(0, 1, 2).unwrap(), (1 << 32) + 2);
+        assert!(full.kid(1, 1, 2).is_err());
+    }
+
+    // Check that every member derives the same key for each sender, and that the keys differ
+    // between senders and between epochs
+    #[quickcheck]
+    fn sender_key_agreement(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let layout = SframeKidLayout::new(16, 8).unwrap();
+
+        let (group_state1, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index2 = test_utils::random_roster_index_with_exceptions(
+            group_state1.roster.len(),
+            &[group_state1.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state2 = test_utils::change_self_index(&group_state1, &identity_keys, index2);
+        let index1 = group_state1.roster_index.unwrap();
+
+        let key1 = group_state1.sframe_sender_key(&layout, 7, index1).unwrap();
+        let key2 = group_state2.sframe_sender_key(&layout, 7, index1).unwrap();
+        assert_eq!(key1.kid(), key2.kid());
+        assert_eq!(key1.kid(), layout.kid(7, index1, group_state1.epoch).unwrap());
+        assert_eq!(key1.base_key(), key2.base_key());
+        assert_eq!(key1.key_and_salt(1, 16, 12).unwrap(), key2.key_and_salt(1, 16, 12).unwrap());
+
+        // Another sender, or another SFrame cipher suite, gets other keys
+        let other_sender = group_state1.sframe_sender_key(&layout, 7, index2).unwrap();
+        assert_ne!(key1.base_key(), other_sender.base_key());
+        assert_ne!(key1.key_and_salt(1, 16, 12).unwrap(), key1.key_and_salt(2, 16, 12).unwrap());
+
+        // Someone who isn't in the group has no key
+        let absent_index = group_state1.roster.len() as u32;
+        assert!(group_state1.sframe_sender_key(&layout, 7, absent_index).is_err());
+
+        // The keys change with the epoch
+        let new_path_secret = PathSecret::new_from_random(group_state1.cs, &mut rng);
+        let (_, new_group_state1, _) =
+            group_state1.create_and_apply_update_handshake(new_path_secret, &mut rng).unwrap();
+        let new_key1 = new_group_state1.sframe_sender_key(&layout, 7, index1).unwrap();
+        assert_ne!(key1.base_key(), new_key1.base_key());
+        assert_ne!(key1.kid(), new_key1.kid());
+    }
+}
@@ -0,0 +1,1014 @@
+//! Defines snapshots of `GroupState`s, `UserInitKey`s, and ratchet trees in the ordinary serde
+//! data model, so they can be persisted with any serde format, like JSON or CBOR, or dumped for
+//! debugging. The wire types themselves only have `TlsSerialize` and `TlsDeserialize` impls,
+//! which describe their TLS encoding, and mean nothing to any other format. Secrets are left out
+//! of a snapshot unless it's made with `SecretPolicy::Include`, in which case they're kept in
+//! `WrappedSecret`s. A `GroupState` can be made back from a snapshot with its secrets, so that a
+//! member can persist their groups and pick them up again later. This module is only built with
+//! the `snapshots` feature.
+
+use crate::{
+    credential::{Credential, Roster},
+    crypto::{
+        ciphersuite::CipherSuite,
+        dh::{DhPrivateKey, DhPublicKey},
+        hash::Digest,
+        hmac::HmacKey,
+        provider::CryptoProvider,
+        secret::Secret,
+        sig::SigSecretKey,
+    },
+    error::Error,
+    extensions::Extension,
+    group_id::GroupId,
+    group_state::{GroupState, PastEpoch},
+    handshake::{
+        GroupProposal, ProposalRef, ProposalSender, ProtocolVersion, ReInitProposal, UserInitKey,
+    },
+    member::LeafInfo,
+    psk::{PreSharedKeyId, ResumptionPsk},
+    ratchet_tree::{NodeHistory, ParentHashes, RatchetTree, RatchetTreeNode, UnmergedLeaves},
+    secret_tree::{SecretTree, SenderRatchet},
+    tls_de::{TlsDeserialize, TlsDeserializer},
+    tls_ser,
+    upcast::{CryptoCtx, CryptoUpcast},
+    validation,
+};
+
+use std::collections::BTreeMap;
+
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+/// Whether a snapshot holds on to secrets
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SecretPolicy {
+    /// Leave every secret out. The snapshot is safe to log.
+    Exclude,
+    /// Keep the secrets that there are bytes for, in `WrappedSecret`s. Keys held outside of this
+    /// process are left out regardless.
+    Include,
+}
+
+/// A secret byte string in a snapshot. It zeroes itself when it's dropped, and its contents never
+/// show up in `Debug` output. They are serialized as-is, though, so a snapshot made with
+/// `SecretPolicy::Include` should be stored as carefully as the secrets themselves.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct WrappedSecret(Vec<u8>);
+
+impl WrappedSecret {
+    fn new(bytes: &[u8]) -> WrappedSecret {
+        WrappedSecret(bytes.to_vec())
+    }
+
+    fn from_hmac_key(key: &HmacKey) -> WrappedSecret {
+        WrappedSecret::new(key.as_bytes())
+    }
+
+    fn to_hmac_key(&self) -> HmacKey {
+        HmacKey::new_from_bytes(&self.0)
+    }
+
+    /// Returns the secret bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for WrappedSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+// Do constant-time comparison by comparing the underlying bytes
+impl ConstantTimeEq for WrappedSecret {
+    fn ct_eq(&self, other: &WrappedSecret) -> subtle::Choice {
+        self.0.ct_eq(&other.0)
+    }
+}
+
+// Snapshots are compared with ==, e.g., to check that one round-tripped, so that's constant-time
+// too
+impl PartialEq for WrappedSecret {
+    fn eq(&self, other: &WrappedSecret) -> bool {
+        self.ct_eq(other).into()
+    }
+}
+
+impl Eq for WrappedSecret {}
+
+impl core::fmt::Debug for WrappedSecret {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("WrappedSecret: CONTENTS OMITTED")
+    }
+}
+
+/// A snapshot of a credential. The certificates of X.509 credentials are left out; they're in the
+/// `encoding` of the `KeyPackageSnapshot`, or in the `GroupState` itself.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CredentialSnapshot {
+    pub identity: Vec<u8>,
+    pub credential_type: u16,
+    pub signature_scheme: u16,
+    pub signature_key: Vec<u8>,
+}
+
+impl CredentialSnapshot {
+    fn new(credential: &Credential) -> CredentialSnapshot {
+        CredentialSnapshot {
+            identity: credential.get_identity().as_bytes().to_vec(),
+            credential_type: credential.credential_type().0,
+            signature_scheme: credential.get_signature_scheme().id(),
+            signature_key: credential.get_public_key().as_bytes().to_vec(),
+        }
+    }
+}
+
+/// A snapshot of an extension
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ExtensionSnapshot {
+    pub extension_type: u16,
+    pub extension_data: Vec<u8>,
+}
+
+impl ExtensionSnapshot {
+    fn new(extension: &Extension) -> ExtensionSnapshot {
+        ExtensionSnapshot {
+            extension_type: extension.extension_type.0,
+            extension_data: extension.extension_data.clone(),
+        }
+    }
+}
+
+/// A snapshot of a `UserInitKey`. The readable fields are there for looking at. The `UserInitKey`
+/// is made back from `encoding`, which is its TLS encoding, signature and all, and from
+/// `private_keys`, if they were included.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct KeyPackageSnapshot {
+    pub user_init_key_id: Vec<u8>,
+    pub supported_versions: Vec<u8>,
+    pub cipher_suites: Vec<u16>,
+    pub init_keys: Vec<Vec<u8>>,
+    pub credential: CredentialSnapshot,
+    pub extensions: Vec<ExtensionSnapshot>,
+    pub encoding: Vec<u8>,
+    /// The private key of every init key, in the same order, if they were included and there are
+    /// bytes for all of them
+    pub private_keys: Option<Vec<WrappedSecret>>,
+}
+
+impl KeyPackageSnapshot {
+    /// Makes a snapshot of the given `UserInitKey`
+    ///
+    /// Returns: `Ok(snapshot)` on success. If the `UserInitKey` can't be serialized, returns an
+    /// `Error::SerdeError`.
+    pub fn new(init_key: &UserInitKey, secrets: SecretPolicy) -> Result<KeyPackageSnapshot, Error> {
+        let private_keys = match (secrets, &init_key.private_keys) {
+            (SecretPolicy::Include, Some(private_keys)) => private_keys
+                .iter()
+                .map(|key| key.to_secret_bytes().map(|bytes| WrappedSecret::new(bytes.as_bytes())))
+                .collect::<Option<Vec<WrappedSecret>>>(),
+            _ => None,
+        };
+
+        Ok(KeyPackageSnapshot {
+            user_init_key_id: init_key.user_init_key_id.clone(),
+            supported_versions: init_key.supported_versions().iter().map(|v| v.0).collect(),
+            cipher_suites: init_key.cipher_suites.iter().map(|cs| cs.id).collect(),
+            init_keys: init_key.init_keys.iter().map(|key| key.as_bytes().to_vec()).collect(),
+            credential: CredentialSnapshot::new(&init_key.credential),
+            extensions: init_key.extensions.iter().map(ExtensionSnapshot::new).collect(),
+            encoding: tls_ser::serialize_to_bytes(init_key)?,
+            private_keys,
+        })
+    }
+
+    /// Makes the `UserInitKey` back from its encoding, along with its private keys, if they're in
+    /// the snapshot. The readable fields are ignored.
+    ///
+    /// Returns: `Ok(init_key)` on success. If the encoding is malformed or its signature doesn't
+    /// verify, returns some sort of `Error`. If the private keys don't match the init keys,
+    /// returns an `Error::ValidationError`.
+    pub fn to_user_init_key(&self) -> Result<UserInitKey, Error> {
+        let mut cursor = self.encoding.as_slice();
+        let mut init_key = {
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            UserInitKey::tls_deserialize(&mut deserializer)?
+        };
+        if !cursor.is_empty() {
+            return Err(Error::ValidationError("UserInitKey encoding has trailing bytes"));
+        }
+        init_key.upcast_crypto_values(&CryptoCtx::new())?;
+        init_key.verify_sig()?;
+
+        if let Some(ref wrapped_keys) = self.private_keys {
+            if wrapped_keys.len() != init_key.cipher_suites.len() {
+                return Err(Error::ValidationError(
+                    "Snapshot has the wrong number of init private keys",
+                ));
+            }
+
+            let mut private_keys = Vec::new();
+            for ((cs, public_key), wrapped_key) in
+                init_key.cipher_suites.iter().zip(init_key.init_keys.iter()).zip(wrapped_keys)
+            {
+                let private_key = DhPrivateKey::new_from_bytes(cs.dh_impl, wrapped_key.as_bytes())?;
+                let derived_public_key =
+                    DhPublicKey::new_from_private_key(cs.dh_impl, &private_key);
+                if derived_public_key.as_bytes() != public_key.as_bytes() {
+                    return Err(Error::ValidationError(
+                        "Snapshot has an init private key that doesn't match its public key",
+                    ));
+                }
+                private_keys.push(private_key);
+            }
+            init_key.private_keys = Some(private_keys);
+        }
+
+        Ok(init_key)
+    }
+}
+
+/// A snapshot of a filled node of a ratchet tree
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TreeNodeSnapshot {
+    pub public_key: Vec<u8>,
+    pub unmerged_leaves: Vec<u32>,
+    pub parent_hash: Vec<u8>,
+    pub private_key: Option<WrappedSecret>,
+}
+
+/// A snapshot of a ratchet tree. Blank nodes are `None`. Nodes are indexed as in the tree, where
+/// leaves are the even indices.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TreeSnapshot {
+    pub cipher_suite: u16,
+    pub tree_hash: Vec<u8>,
+    pub nodes: Vec<Option<TreeNodeSnapshot>>,
+}
+
+impl TreeSnapshot {
+    /// Makes a snapshot of the ratchet tree of the given group. Node private keys are only known
+    /// along the group's own path.
+    pub fn new(group_state: &GroupState, secrets: SecretPolicy) -> TreeSnapshot {
+        let tree = &group_state.tree;
+        let nodes = (0..tree.size())
+            .map(|idx| match tree.get(idx) {
+                Some(RatchetTreeNode::Filled {
+                    public_key,
+                    private_key,
+                    unmerged_leaves,
+                    parent_hash,
+                }) => Some(TreeNodeSnapshot {
+                    public_key: public_key.as_bytes().to_vec(),
+                    unmerged_leaves: unmerged_leaves.clone(),
+                    parent_hash: parent_hash.as_bytes().to_vec(),
+                    private_key: match secrets {
+                        SecretPolicy::Include => private_key
+                            .as_ref()
+                            .and_then(DhPrivateKey::to_secret_bytes)
+                            .map(|bytes| WrappedSecret::new(bytes.as_bytes())),
+                        SecretPolicy::Exclude => None,
+                    },
+                }),
+                _ => None,
+            })
+            .collect();
+
+        TreeSnapshot {
+            cipher_suite: group_state.cs.id,
+            tree_hash: group_state.tree_hash.as_bytes().to_vec(),
+            nodes,
+        }
+    }
+}
+
+/// A snapshot of a member of a group
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MemberSnapshot {
+    pub roster_index: u32,
+    pub credential: CredentialSnapshot,
+    pub extensions: Vec<ExtensionSnapshot>,
+    pub last_update_epoch: u32,
+}
+
+/// A snapshot of one of a member's ratchets in a secret tree
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RatchetSnapshot {
+    pub generation: u32,
+    pub secret: WrappedSecret,
+    /// The secrets of the skipped generations whose keys haven't been used yet, by generation
+    pub skipped: Vec<(u32, WrappedSecret)>,
+}
+
+impl RatchetSnapshot {
+    fn new(ratchet: &SenderRatchet) -> RatchetSnapshot {
+        RatchetSnapshot {
+            generation: ratchet.generation(),
+            secret: WrappedSecret::from_hmac_key(ratchet.secret()),
+            skipped: ratchet
+                .skipped()
+                .iter()
+                .map(|(&generation, secret)| (generation, WrappedSecret::from_hmac_key(secret)))
+                .collect(),
+        }
+    }
+
+    fn to_ratchet(&self) -> Result<SenderRatchet, Error> {
+        let skipped: BTreeMap<u32, HmacKey> = self
+            .skipped
+            .iter()
+            .map(|(generation, secret)| (*generation, secret.to_hmac_key()))
+            .collect();
+        SenderRatchet::from_parts(self.secret.to_hmac_key(), self.generation, skipped)
+    }
+}
+
+/// A snapshot of the handshake and application ratchets of one leaf of a secret tree
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LeafRatchetsSnapshot {
+    pub handshake: RatchetSnapshot,
+    pub application: RatchetSnapshot,
+}
+
+/// A snapshot of the secret tree of an epoch. Nodes are indexed as in the ratchet tree. A node's
+/// secret is `None` if it hasn't been derived yet, or if its children's have been, and a leaf's
+/// ratchets are `None` until they're derived.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SecretTreeSnapshot {
+    pub nodes: Vec<Option<WrappedSecret>>,
+    pub ratchets: Vec<Option<LeafRatchetsSnapshot>>,
+}
+
+impl SecretTreeSnapshot {
+    fn new(secret_tree: &SecretTree) -> SecretTreeSnapshot {
+        SecretTreeSnapshot {
+            nodes: secret_tree
+                .node_secrets()
+                .iter()
+                .map(|secret| secret.as_ref().map(WrappedSecret::from_hmac_key))
+                .collect(),
+            ratchets: secret_tree
+                .leaf_ratchets()
+                .map(|ratchets| {
+                    ratchets.map(|(handshake, application)| LeafRatchetsSnapshot {
+                        handshake: RatchetSnapshot::new(handshake),
+                        application: RatchetSnapshot::new(application),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn to_secret_tree(&self, cs: &'static CipherSuite) -> Result<SecretTree, Error> {
+        let nodes = self.nodes.iter().map(|s| s.as_ref().map(WrappedSecret::to_hmac_key)).collect();
+        let ratchets = self
+            .ratchets
+            .iter()
+            .map(|ratchets| match ratchets {
+                Some(r) => Ok(Some((r.handshake.to_ratchet()?, r.application.to_ratchet()?))),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        SecretTree::from_parts(cs, nodes, ratchets)
+    }
+}
+
+/// A snapshot of an epoch before the current one, whose secrets are kept so that messages that
+/// were in flight when it ended can still be decrypted
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PastEpochSnapshot {
+    pub epoch: u32,
+    /// The TLS encoding of the epoch's roster, which the senders of its messages are checked
+    /// against
+    pub roster: Vec<u8>,
+    pub sender_data_secret: WrappedSecret,
+    pub secret_tree: SecretTreeSnapshot,
+}
+
+/// A snapshot of a resumption PSK
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ResumptionPskSnapshot {
+    pub group_id: Vec<u8>,
+    pub epoch: u32,
+    pub secret: WrappedSecret,
+}
+
+/// A snapshot of a proposal that was cached in the current epoch, so that commits can refer to
+/// it. The proposal itself is in the `encoding` of the `GroupStateSnapshot`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct CachedProposalSnapshot {
+    pub proposal_ref: Vec<u8>,
+    /// 1 if the sender is a member, and 2 if it's an external sender, as in RFC 9420's
+    /// `SenderType`
+    pub sender_type: u8,
+    pub sender_index: u32,
+}
+
+/// The secrets of a group's current epoch and the ones before it that are still kept, the
+/// group's own identity key, and the private keys of this member's pending Updates
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EpochSecretsSnapshot {
+    /// This is `None` if the identity key is held outside of this process
+    pub identity_key: Option<WrappedSecret>,
+    pub init_secret: WrappedSecret,
+    pub exporter_secret: WrappedSecret,
+    pub epoch_authenticator: WrappedSecret,
+    /// This and the three below are `None` until the first epoch change this member takes part in
+    pub external_secret: Option<WrappedSecret>,
+    pub membership_key: Option<WrappedSecret>,
+    pub sender_data_secret: Option<WrappedSecret>,
+    pub secret_tree: Option<SecretTreeSnapshot>,
+    pub past_epochs: Vec<PastEpochSnapshot>,
+    pub resumption_psks: Vec<ResumptionPskSnapshot>,
+    /// The leaf private keys of this member's Update proposals in the current epoch. Keys held
+    /// outside of this process are left out.
+    pub pending_leaf_keys: Vec<WrappedSecret>,
+    /// The identity keys of the credentials that this member's Update proposals in the current
+    /// epoch replace theirs with, in the order the credentials are in the `encoding`. An entry is
+    /// `None` if the key is held outside of this process.
+    pub pending_identity_keys: Vec<Option<WrappedSecret>>,
+}
+
+impl EpochSecretsSnapshot {
+    fn new(group_state: &GroupState) -> Result<EpochSecretsSnapshot, Error> {
+        let past_epochs = group_state
+            .past_epochs
+            .iter()
+            .map(|past_epoch| {
+                Ok(PastEpochSnapshot {
+                    epoch: past_epoch.epoch,
+                    roster: tls_ser::serialize_to_bytes(&past_epoch.roster)?,
+                    sender_data_secret: WrappedSecret::from_hmac_key(
+                        &past_epoch.sender_data_secret,
+                    ),
+                    secret_tree: SecretTreeSnapshot::new(&past_epoch.secret_tree),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let resumption_psks = group_state
+            .resumption_psks
+            .iter()
+            .map(|psk| ResumptionPskSnapshot {
+                group_id: psk.group_id.as_bytes().to_vec(),
+                epoch: psk.epoch,
+                secret: WrappedSecret::new(psk.secret.as_bytes()),
+            })
+            .collect();
+
+        Ok(EpochSecretsSnapshot {
+            identity_key: group_state
+                .identity_key
+                .to_secret_bytes()
+                .map(|bytes| WrappedSecret::new(bytes.as_bytes())),
+            init_secret: WrappedSecret::from_hmac_key(&group_state.init_secret),
+            exporter_secret: WrappedSecret::from_hmac_key(&group_state.exporter_secret),
+            epoch_authenticator: WrappedSecret::from_hmac_key(&group_state.epoch_authenticator),
+            external_secret: group_state.external_secret.as_ref().map(WrappedSecret::from_hmac_key),
+            membership_key: group_state.membership_key.as_ref().map(WrappedSecret::from_hmac_key),
+            sender_data_secret: group_state
+                .sender_data_secret
+                .as_ref()
+                .map(WrappedSecret::from_hmac_key),
+            secret_tree: group_state.secret_tree.as_ref().map(SecretTreeSnapshot::new),
+            past_epochs,
+            resumption_psks,
+            pending_leaf_keys: group_state
+                .pending_leaf_keys
+                .iter()
+                .filter_map(DhPrivateKey::to_secret_bytes)
+                .map(|bytes| WrappedSecret::new(bytes.as_bytes()))
+                .collect(),
+            pending_identity_keys: group_state
+                .pending_credentials
+                .iter()
+                .map(|(_, key)| {
+                    key.to_secret_bytes().map(|bytes| WrappedSecret::new(bytes.as_bytes()))
+                })
+                .collect(),
+        })
+    }
+}
+
+// A proposal in the `ProposalStore`, as it's kept in a `GroupStateEncoding`
+#[derive(TlsDeserialize, TlsSerialize)]
+struct CachedProposal {
+    proposal_ref: ProposalRef,
+    proposal: GroupProposal,
+    sender: ProposalSender,
+}
+
+// This is the `encoding` of a `GroupStateSnapshot`. It's what the group sends new members in a
+// `Welcome`, minus the init secret, followed by the rest of the public state that a `GroupState`
+// is made back from.
+#[derive(TlsDeserialize, TlsSerialize)]
+struct GroupStateEncoding {
+    protocol_version: ProtocolVersion,
+    group_id: GroupId,
+    epoch: u32,
+    #[tls(bound = "u32", limit = "tree")]
+    roster: Roster,
+    tree: RatchetTree,
+    unmerged_leaves: UnmergedLeaves,
+    parent_hashes: ParentHashes,
+    tree_hash: Digest,
+    confirmed_transcript_hash: Digest,
+    interim_transcript_hash: Digest,
+    #[tls(bound = "u32", limit = "extensions")]
+    extensions: Vec<Extension>,
+    #[tls(bound = "u32", limit = "tree")]
+    leaves: Vec<Option<LeafInfo>>,
+    #[tls(bound = "u32", limit = "tree")]
+    node_epochs: Vec<u32>,
+    roster_index: Option<u32>,
+    #[tls(bound = "u32")]
+    pending_psks: Vec<PreSharedKeyId>,
+    #[tls(bound = "u32")]
+    proposals: Vec<CachedProposal>,
+    #[tls(bound = "u32")]
+    pending_credentials: Vec<Credential>,
+    reinit: Option<ReInitProposal>,
+}
+
+impl CryptoUpcast for GroupStateEncoding {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.roster.upcast_crypto_values(ctx)?;
+        self.tree.upcast_crypto_values(ctx)?;
+        for cached in self.proposals.iter_mut() {
+            cached.proposal.upcast_crypto_values(ctx)?;
+        }
+        self.pending_credentials.upcast_crypto_values(ctx)?;
+        // No change in context
+        Ok(*ctx)
+    }
+}
+
+/// Deserializes all of `bytes` as a `T`, looking up its algorithms in `provider`, and upcasts it
+/// under the group's cipher suite
+fn decode<T>(
+    bytes: &[u8],
+    cs: &'static CipherSuite,
+    provider: &'static dyn CryptoProvider,
+) -> Result<T, Error>
+where
+    T: TlsDeserialize + CryptoUpcast,
+{
+    let mut cursor = bytes;
+    let mut value = {
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor).with_provider(provider);
+        T::tls_deserialize(&mut deserializer)?
+    };
+    if !cursor.is_empty() {
+        return Err(Error::ValidationError("Snapshot encoding has trailing bytes"));
+    }
+    value.upcast_crypto_values(&CryptoCtx::new().set_cipher_suite(cs).set_provider(provider))?;
+
+    Ok(value)
+}
+
+/// A snapshot of a `GroupState`. This is for persisting a group and for dumping it while
+/// debugging. The readable fields are there for looking at. The `GroupState` is made back from
+/// `encoding`, which is the TLS encoding of its public state, and from `secrets`, if they were
+/// included.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct GroupStateSnapshot {
+    pub group_id: Vec<u8>,
+    pub epoch: u32,
+    pub protocol_version: u8,
+    pub cipher_suite: u16,
+    pub roster_index: Option<u32>,
+    pub confirmed_transcript_hash: Vec<u8>,
+    pub interim_transcript_hash: Vec<u8>,
+    pub extensions: Vec<ExtensionSnapshot>,
+    pub members: Vec<MemberSnapshot>,
+    pub tree: TreeSnapshot,
+    pub pending_proposals: Vec<CachedProposalSnapshot>,
+    /// The TLS encoding of the ID of every PSK that's mixed into the key schedule at the next
+    /// epoch change
+    pub pending_psks: Vec<Vec<u8>>,
+    pub encoding: Vec<u8>,
+    /// This is `None` unless the snapshot was made with `SecretPolicy::Include`
+    pub secrets: Option<EpochSecretsSnapshot>,
+}
+
+impl GroupStateSnapshot {
+    /// Makes a snapshot of the given group
+    ///
+    /// Returns: `Ok(snapshot)` on success. If the group's state can't be serialized, returns an
+    /// `Error::SerdeError`.
+    pub fn new(
+        group_state: &GroupState,
+        secrets: SecretPolicy,
+    ) -> Result<GroupStateSnapshot, Error> {
+        let members = group_state
+            .members()
+            .map(|member| MemberSnapshot {
+                roster_index: member.roster_index(),
+                credential: CredentialSnapshot::new(member.credential()),
+                extensions: member.extensions().iter().map(ExtensionSnapshot::new).collect(),
+                last_update_epoch: member.last_update_epoch(),
+            })
+            .collect();
+        let cached_proposals = group_state.proposal_store.entries();
+        let pending_proposals = cached_proposals
+            .iter()
+            .map(|(proposal_ref, _, sender)| {
+                let (sender_type, sender_index) = match *sender {
+                    ProposalSender::Member(index) => (1, index),
+                    ProposalSender::External(index) => (2, index),
+                };
+                CachedProposalSnapshot {
+                    proposal_ref: proposal_ref.as_bytes().to_vec(),
+                    sender_type,
+                    sender_index,
+                }
+            })
+            .collect();
+        let pending_psks = group_state
+            .pending_psks
+            .iter()
+            .map(tls_ser::serialize_to_bytes)
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let encoding = GroupStateEncoding {
+            protocol_version: group_state.protocol_version,
+            group_id: group_state.group_id.clone(),
+            epoch: group_state.epoch,
+            roster: group_state.roster.clone(),
+            tree: group_state.tree.clone(),
+            unmerged_leaves: group_state.tree.unmerged_leaves(),
+            parent_hashes: group_state.tree.parent_hashes(),
+            tree_hash: group_state.tree_hash.clone(),
+            confirmed_transcript_hash: group_state.confirmed_transcript_hash.clone(),
+            interim_transcript_hash: group_state.interim_transcript_hash.clone(),
+            extensions: group_state.extensions.clone(),
+            leaves: group_state.leaves.clone(),
+            node_epochs: group_state.node_history.epochs(),
+            roster_index: group_state.roster_index,
+            pending_psks: group_state.pending_psks.clone(),
+            proposals: cached_proposals
+                .iter()
+                .map(|(proposal_ref, proposal, sender)| CachedProposal {
+                    proposal_ref: proposal_ref.clone(),
+                    proposal: proposal.clone(),
+                    sender: *sender,
+                })
+                .collect(),
+            pending_credentials: group_state
+                .pending_credentials
+                .iter()
+                .map(|(credential, _)| credential.clone())
+                .collect(),
+            reinit: group_state.reinit.clone(),
+        };
+
+        let epoch_secrets = match secrets {
+            SecretPolicy::Include => Some(EpochSecretsSnapshot::new(group_state)?),
+            SecretPolicy::Exclude => None,
+        };
+
+        Ok(GroupStateSnapshot {
+            group_id: group_state.group_id.as_bytes().to_vec(),
+            epoch: group_state.epoch,
+            protocol_version: group_state.protocol_version.0,
+            cipher_suite: group_state.cs.id,
+            roster_index: group_state.roster_index,
+            confirmed_transcript_hash: group_state.confirmed_transcript_hash.as_bytes().to_vec(),
+            interim_transcript_hash: group_state.interim_transcript_hash.as_bytes().to_vec(),
+            extensions: group_state.extensions.iter().map(ExtensionSnapshot::new).collect(),
+            members,
+            tree: TreeSnapshot::new(group_state, secrets),
+            pending_proposals,
+            pending_psks,
+            encoding: tls_ser::serialize_to_bytes(&encoding)?,
+            secrets: epoch_secrets,
+        })
+    }
+
+    /// Returns the cipher suite the group is under, if `provider` implements it
+    pub fn cipher_suite(
+        &self,
+        provider: &'static dyn CryptoProvider,
+    ) -> Option<&'static CipherSuite> {
+        provider.cipher_suite_by_id(self.cipher_suite)
+    }
+
+    /// Makes the `GroupState` back from its encoding and secrets, looking up its algorithms in
+    /// `provider`. The readable fields are ignored. What a snapshot leaves out comes back the way
+    /// it is in a group that was just joined: local policy, e.g., the `CredentialValidator` and the
+    /// `OutOfOrderPolicy`, is back to the defaults, and the Update made with
+    /// `GroupState::create_update` that hasn't come back yet, the messages kept from later epochs,
+    /// the fragments being reassembled, and the record of decrypted messages are gone. The
+    /// application sets its policy again, like it does after `GroupState::from_welcome`.
+    ///
+    /// Returns: `Ok(group_state)` on success. If the snapshot was made without its secrets, or its
+    /// identity key or a pending identity key is held outside of this process, or it's of a
+    /// preliminary `GroupState`, or its parts don't fit together, returns an
+    /// `Error::ValidationError`. If the encoding is malformed, returns some sort of `Error`.
+    pub fn to_group_state(
+        &self,
+        provider: &'static dyn CryptoProvider,
+    ) -> Result<GroupState, Error> {
+        let secrets = self
+            .secrets
+            .as_ref()
+            .ok_or(Error::ValidationError("Snapshot was made without its secrets"))?;
+        let cs = self
+            .cipher_suite(provider)
+            .ok_or(Error::ValidationError("Snapshot's cipher suite isn't in the provider"))?;
+        let encoding: GroupStateEncoding = decode(&self.encoding, cs, provider)?;
+        let roster_index = encoding
+            .roster_index
+            .ok_or(Error::ValidationError("Cannot restore a preliminary GroupState"))?;
+        let leaves_match_roster = encoding.leaves.len() == encoding.roster.len()
+            && encoding
+                .leaves
+                .iter()
+                .zip(encoding.roster.0.iter())
+                .all(|(l, c)| l.is_some() == c.is_some());
+        if !leaves_match_roster {
+            return Err(Error::ValidationError(
+                "Snapshot doesn't have leaf info for exactly the filled roster entries",
+            ));
+        }
+
+        let mut tree = encoding.tree;
+        tree.set_unmerged_leaves(encoding.unmerged_leaves)?;
+        tree.set_parent_hashes(encoding.parent_hashes)?;
+        if self.tree.nodes.len() != tree.size() {
+            return Err(Error::ValidationError("Snapshot's tree doesn't match its encoding"));
+        }
+        for (idx, node) in self.tree.nodes.iter().enumerate() {
+            let wrapped_key = match node.as_ref().and_then(|node| node.private_key.as_ref()) {
+                Some(wrapped_key) => wrapped_key,
+                None => continue,
+            };
+            let new_private_key = DhPrivateKey::new_from_bytes(cs.dh_impl, wrapped_key.as_bytes())?;
+            let derived_public_key =
+                DhPublicKey::new_from_private_key(cs.dh_impl, &new_private_key);
+            tree.update(idx, |node| match node {
+                RatchetTreeNode::Filled {
+                    public_key,
+                    private_key,
+                    ..
+                } if public_key.as_bytes() == derived_public_key.as_bytes() => {
+                    *private_key = Some(new_private_key);
+                    Ok(())
+                }
+                _ => Err(Error::ValidationError(
+                    "Snapshot has a node private key that doesn't match its public key",
+                )),
+            })??;
+        }
+        let node_history = NodeHistory::from_epochs(&tree, &encoding.node_epochs)?;
+
+        let my_credential = validation::signer_credential(&encoding.roster, roster_index)?;
+        let identity_key = match secrets.identity_key {
+            Some(ref wrapped_key) => SigSecretKey::new_from_bytes(
+                my_credential.get_signature_scheme(),
+                wrapped_key.as_bytes(),
+            )?,
+            None => {
+                return Err(Error::ValidationError(
+                    "Snapshot's identity key is held outside of this process",
+                ))
+            }
+        };
+        if secrets.pending_identity_keys.len() != encoding.pending_credentials.len() {
+            return Err(Error::ValidationError(
+                "Snapshot has the wrong number of pending identity keys",
+            ));
+        }
+        let pending_credentials = encoding
+            .pending_credentials
+            .into_iter()
+            .zip(secrets.pending_identity_keys.iter())
+            .map(|(credential, wrapped_key)| {
+                let wrapped_key = wrapped_key.as_ref().ok_or(Error::ValidationError(
+                    "Snapshot's pending identity key is held outside of this process",
+                ))?;
+                let ss = credential.get_signature_scheme();
+                let identity_key = SigSecretKey::new_from_bytes(ss, wrapped_key.as_bytes())?;
+                Ok((credential, identity_key))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let pending_leaf_keys = secrets
+            .pending_leaf_keys
+            .iter()
+            .map(|wrapped_key| DhPrivateKey::new_from_bytes(cs.dh_impl, wrapped_key.as_bytes()))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let past_epochs = secrets
+            .past_epochs
+            .iter()
+            .map(|past_epoch| {
+                Ok(PastEpoch {
+                    epoch: past_epoch.epoch,
+                    roster: decode(&past_epoch.roster, cs, provider)?,
+                    sender_data_secret: past_epoch.sender_data_secret.to_hmac_key(),
+                    secret_tree: past_epoch.secret_tree.to_secret_tree(cs)?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let resumption_psks = secrets
+            .resumption_psks
+            .iter()
+            .map(|psk| {
+                Ok(ResumptionPsk {
+                    group_id: GroupId::new(psk.group_id.clone())?,
+                    epoch: psk.epoch,
+                    secret: Secret::new_from_bytes(psk.secret.as_bytes()),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let secret_tree = match secrets.secret_tree {
+            Some(ref secret_tree) => {
+                if secret_tree.ratchets.len() != encoding.roster.len() {
+                    return Err(Error::ValidationError(
+                        "Snapshot's secret tree doesn't match its roster",
+                    ));
+                }
+                Some(secret_tree.to_secret_tree(cs)?)
+            }
+            None => None,
+        };
+
+        let mut group_state = GroupState::new_from_parts(
+            cs,
+            encoding.protocol_version,
+            identity_key,
+            encoding.group_id,
+            encoding.roster,
+            roster_index,
+            tree,
+        )?;
+        group_state.epoch = encoding.epoch;
+        group_state.tree_hash = encoding.tree_hash;
+        group_state.confirmed_transcript_hash = encoding.confirmed_transcript_hash;
+        group_state.interim_transcript_hash = encoding.interim_transcript_hash;
+        group_state.extensions = encoding.extensions;
+        group_state.leaves = encoding.leaves;
+        group_state.node_history = node_history;
+        group_state.init_secret = secrets.init_secret.to_hmac_key();
+        group_state.exporter_secret = secrets.exporter_secret.to_hmac_key();
+        group_state.epoch_authenticator = secrets.epoch_authenticator.to_hmac_key();
+        group_state.external_secret =
+            secrets.external_secret.as_ref().map(WrappedSecret::to_hmac_key);
+        group_state.secret_tree = secret_tree;
+        group_state.membership_key =
+            secrets.membership_key.as_ref().map(WrappedSecret::to_hmac_key);
+        group_state.sender_data_secret =
+            secrets.sender_data_secret.as_ref().map(WrappedSecret::to_hmac_key);
+        group_state.acceptable_signature_schemes = provider.signature_schemes().to_vec();
+        group_state.provider = provider;
+        group_state.past_epochs = past_epochs;
+        group_state.pending_psks = encoding.pending_psks;
+        group_state.resumption_psks = resumption_psks;
+        group_state.pending_leaf_keys = pending_leaf_keys;
+        group_state.pending_credentials = pending_credentials;
+        for cached in encoding.proposals {
+            group_state.proposal_store.insert(cached.proposal_ref, cached.proposal, cached.sender);
+        }
+        group_state.reinit = encoding.reinit;
+
+        Ok(group_state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        crypto::{ciphersuite::X25519_SHA256_AES128GCM, provider::DEFAULT_PROVIDER},
+        handshake::{UserInitKey, MLS_DUMMY_VERSION},
+        ratchet_tree::PathSecret,
+        snapshot::{GroupStateSnapshot, KeyPackageSnapshot, SecretPolicy},
+        test_utils,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    // Snapshots round-trip through JSON, and leave secrets out unless they're asked for
+    #[quickcheck]
+    fn group_snapshot(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, _) = test_utils::random_full_group_state(4, &mut rng);
+
+        let snapshot = GroupStateSnapshot::new(&group_state, SecretPolicy::Exclude).unwrap();
+        assert!(snapshot.secrets.is_none());
+        assert!(snapshot.tree.nodes.iter().flatten().all(|node| node.private_key.is_none()));
+        assert_eq!(snapshot.members.len(), group_state.members().count());
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: GroupStateSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, snapshot);
+
+        // Our own leaf's private key is known, so there's at least one in the tree
+        let snapshot = GroupStateSnapshot::new(&group_state, SecretPolicy::Include).unwrap();
+        let secrets = snapshot.secrets.as_ref().unwrap();
+        assert_eq!(secrets.init_secret.as_bytes(), group_state.init_secret.as_bytes());
+        assert!(snapshot.tree.nodes.iter().flatten().any(|node| node.private_key.is_some()));
+        // Secrets never show up in debugging dumps
+        assert!(format!("{:?}", snapshot).contains("CONTENTS OMITTED"));
+    }
+
+    // A group made back from its snapshot picks up where it left off: it decrypts messages that
+    // were sent before the snapshot, sends ones the rest of the group can decrypt, and processes a
+    // commit of the proposal it cached before the snapshot
+    #[quickcheck]
+    fn group_snapshot_restore(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state_a, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[group_state_a.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let group_state_b = test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+
+        // Commit once, so that there's a secret tree, and exchange a message
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, mut group_state_a, _) = group_state_a
+            .create_and_apply_commit_handshake(Vec::new(), new_path_secret, &mut rng)
+            .unwrap();
+        let (mut group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+        let first = group_state_a.encrypt_application_message(b"first").unwrap();
+        assert_eq!(group_state_b.decrypt_application_message(first).unwrap(), b"first");
+        let in_flight = group_state_a.encrypt_application_message(b"in flight").unwrap();
+
+        // B proposes an Update, which both cache, and then B is snapshotted and made back
+        let update = group_state_b.create_update_proposal(&mut rng).unwrap();
+        let update = group_state_b.create_proposal_message(update).unwrap();
+        let update_ref = group_state_a.cache_proposal(update.clone()).unwrap();
+        group_state_b.cache_proposal(update).unwrap();
+        let snapshot = GroupStateSnapshot::new(&group_state_b, SecretPolicy::Include).unwrap();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: GroupStateSnapshot = serde_json::from_str(&json).unwrap();
+        let mut restored = parsed.to_group_state(DEFAULT_PROVIDER).unwrap();
+        assert_serialized_eq!(restored, group_state_b, "Restored group differs");
+        assert_eq!(GroupStateSnapshot::new(&restored, SecretPolicy::Include).unwrap(), snapshot);
+
+        // The restored B keeps exchanging messages
+        assert_eq!(restored.decrypt_application_message(in_flight).unwrap(), b"in flight");
+        let reply = restored.encrypt_application_message(b"reply").unwrap();
+        assert_eq!(group_state_a.decrypt_application_message(reply).unwrap(), b"reply");
+
+        // A commits B's Update by reference, and the restored B ends up in the same epoch
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, group_state_a, _) = group_state_a
+            .create_and_apply_commit_handshake_with_refs(
+                vec![update_ref],
+                Vec::new(),
+                new_path_secret,
+                &mut rng,
+            )
+            .unwrap();
+        let (restored, _) = restored.process_handshake(&handshake).unwrap();
+        assert_eq!(
+            group_state_a.export_secret(b"snapshot", b"", 32).unwrap(),
+            restored.export_secret(b"snapshot", b"", 32).unwrap()
+        );
+
+        // There's no making a group back without its secrets
+        let snapshot = GroupStateSnapshot::new(&restored, SecretPolicy::Exclude).unwrap();
+        assert!(snapshot.to_group_state(DEFAULT_PROVIDER).is_err());
+    }
+
+    // A UserInitKey comes back from its snapshot with its private keys, iff they were included
+    #[quickcheck]
+    fn key_package_snapshot(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"snapshot".to_vec(),
+            credential,
+            vec![&X25519_SHA256_AES128GCM],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+
+        let snapshot = KeyPackageSnapshot::new(&init_key, SecretPolicy::Exclude).unwrap();
+        let json = serde_json::to_vec(&snapshot).unwrap();
+        let parsed: KeyPackageSnapshot = serde_json::from_slice(&json).unwrap();
+        let restored = parsed.to_user_init_key().unwrap();
+        assert!(restored.private_keys.is_none());
+        assert_eq!(
+            restored.key_package_hash(&X25519_SHA256_AES128GCM).unwrap(),
+            init_key.key_package_hash(&X25519_SHA256_AES128GCM).unwrap()
+        );
+
+        let snapshot = KeyPackageSnapshot::new(&init_key, SecretPolicy::Include).unwrap();
+        let restored = snapshot.to_user_init_key().unwrap();
+        let private_key = restored.private_keys.as_ref().unwrap()[0].to_secret_bytes().unwrap();
+        let original = init_key.private_keys.as_ref().unwrap()[0].to_secret_bytes().unwrap();
+        assert_eq!(private_key.as_bytes(), original.as_bytes());
+
+        // A private key that isn't the init key's is rejected
+        let mut tampered = snapshot.clone();
+        tampered.private_keys.as_mut().unwrap()[0].0[1] ^= 0x01;
+        assert!(tampered.to_user_init_key().is_err());
+    }
+}
@@ -1,19 +1,37 @@
 use crate::{
-    credential::{self, BasicCredential, Credential, Roster},
+    application::PaddingScheme,
+    authentication::OfflineAuthenticationService,
+    credential::{self, BasicCredential, Credential, CredentialType, Roster},
     crypto::{
         ciphersuite::{CipherSuite, X25519_SHA256_AES128GCM},
         hash::Digest,
         hmac::HmacKey,
+        provider::DEFAULT_PROVIDER,
         rng::CryptoRng,
-        sig::{SigPublicKey, SigSecretKey, SignatureScheme, ED25519_IMPL},
+        sig::{
+            SigPublicKey, SigSecretKey, SignatureScheme, ECDSA_P521_IMPL, ED25519_IMPL, ED448_IMPL,
+        },
     },
-    group_state::GroupState,
+    fragment::Reassembler,
+    framing::{MessageSizeLimits, WireFormatPolicy},
+    group_id::GroupId,
+    group_state::{GroupState, DEFAULT_FUTURE_MESSAGE_LIMIT, DEFAULT_PAST_EPOCH_RETENTION},
     handshake::MLS_DUMMY_VERSION,
-    ratchet_tree::{PathSecret, RatchetTree, RatchetTreeNode},
+    member::{LeafInfo, MemberIndex},
+    proposal_store::ProposalStore,
+    psk::PskStore,
+    ratchet_tree::{NodeHistory, PathSecret, RatchetTree, RatchetTreeNode},
+    replay::ReplayGuard,
+    revocation::RevocationCache,
+    secret_tree::OutOfOrderPolicy,
+    time::SystemClock,
+    tls_de::DecodeLimits,
     tree_math,
+    x509::TrustStore,
 };
 
 use core::convert::TryFrom;
+use std::{collections::HashMap, sync::Arc};
 
 use rand::seq::SliceRandom;
 
@@ -27,6 +45,15 @@ macro_rules! assert_serialized_eq {
     };
 }
 
+// A PSK store that's just a map from external PSK IDs to secrets
+pub(crate) struct MapPskStore(pub(crate) HashMap<Vec<u8>, Vec<u8>>);
+
+impl PskStore for MapPskStore {
+    fn get_external_psk(&self, psk_id: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(psk_id).cloned()
+    }
+}
+
 // Generates a random roster index within the given bounds, and guarantees that the output is not in
 // `forbidden_indices`
 pub(crate) fn random_roster_index_with_exceptions<R: rand::Rng>(
@@ -76,9 +103,7 @@ fn random_tree<R: rand::Rng + CryptoRng>(
 ) -> RatchetTree {
     // Make a tree of Blanks, then fill it with private keys
     let num_nodes = tree_math::num_nodes_in_tree(num_leaves);
-    let mut tree = RatchetTree {
-        nodes: vec![RatchetTreeNode::Blank; num_nodes],
-    };
+    let mut tree = RatchetTree::new_from_nodes(vec![RatchetTreeNode::Blank; num_nodes]);
 
     // In a random order, fill the tree
     // We cannot say the word "leaf index" because that means something else
@@ -92,6 +117,7 @@ fn random_tree<R: rand::Rng + CryptoRng>(
         };
         tree.propagate_new_path_secret(cs, path_secret, idx)
             .expect("couldn't propagate random secrets in a random tree");
+        tree.set_path_parent_hashes(cs, idx).expect("couldn't set parent hashes in a random tree");
     }
 
     tree
@@ -126,7 +152,7 @@ pub(crate) fn random_full_group_state<R: rand::Rng + CryptoRng>(
     let my_identity_key = identity_keys[my_roster_idx as usize].clone();
 
     // Make a full tree with all secrets known
-    let tree = random_tree(rng, cs, group_size as usize);
+    let mut tree = random_tree(rng, cs, group_size as usize);
 
     // Make a random 16 byte group ID
     let group_id = {
@@ -135,22 +161,75 @@ pub(crate) fn random_full_group_state<R: rand::Rng + CryptoRng>(
         buf
     };
 
-    // Make a random init_secret and a zero transcript_hash
+    // Make a random init_secret and zero transcript hashes
     let init_secret = HmacKey::new_from_random(cs.hash_impl, rng);
     let transcript_hash = Digest::new_from_zeros(cs.hash_impl);
+    // Every member runs this crate, so they support every credential type it does
+    let mut leaves = LeafInfo::for_roster(&roster, MLS_DUMMY_VERSION, cs, 0);
+    for leaf in leaves.iter_mut().flatten() {
+        leaf.capabilities.credentials =
+            vec![CredentialType::BASIC, CredentialType::X509, CredentialType::TOKEN];
+    }
+    let member_index = MemberIndex::for_roster(&roster);
+    let tree_hash = tree.tree_hash(cs).unwrap();
+    let epoch = rng.gen();
+    let node_history = NodeHistory::new(&tree, epoch);
 
     let group_state = GroupState {
         cs: cs,
         protocol_version: MLS_DUMMY_VERSION,
         identity_key: my_identity_key,
-        group_id: group_id.to_vec(),
-        epoch: rng.gen(),
+        group_id: GroupId::from_uuid_bytes(group_id),
+        epoch: epoch,
         roster: roster,
         tree: tree,
-        transcript_hash: transcript_hash,
+        tree_hash: tree_hash,
+        confirmed_transcript_hash: transcript_hash.clone(),
+        interim_transcript_hash: transcript_hash,
+        extensions: Vec::new(),
+        leaves: leaves,
+        member_index: member_index,
+        node_history: node_history,
         roster_index: Some(my_roster_idx),
         initializing_user_init_key: None,
         init_secret: init_secret,
+        exporter_secret: HmacKey::new_from_zeros(cs.hash_impl),
+        epoch_authenticator: HmacKey::new_from_zeros(cs.hash_impl),
+        external_secret: None,
+        secret_tree: None,
+        membership_key: None,
+        sender_data_secret: None,
+        acceptable_signature_schemes: DEFAULT_PROVIDER.signature_schemes().to_vec(),
+        supported_extensions: Vec::new(),
+        psk_store: None,
+        time_source: Arc::new(SystemClock),
+        trust_store: Arc::new(TrustStore::new()),
+        token_verifier: None,
+        credential_validator: None,
+        authentication_service: Arc::new(OfflineAuthenticationService),
+        revocation_checker: None,
+        revocation_cache: Arc::new(RevocationCache::new()),
+        out_of_order_policy: OutOfOrderPolicy::default(),
+        padding_scheme: PaddingScheme::default(),
+        wire_format_policy: WireFormatPolicy::default(),
+        replay_guard: ReplayGuard::default(),
+        future_messages: Vec::new(),
+        future_message_limit: DEFAULT_FUTURE_MESSAGE_LIMIT,
+        buffered_messages: Vec::new(),
+        message_size_limits: MessageSizeLimits::default(),
+        decode_limits: DecodeLimits::default(),
+        provider: DEFAULT_PROVIDER,
+        reassembler: Reassembler::default(),
+        past_epochs: Vec::new(),
+        past_epoch_limit: DEFAULT_PAST_EPOCH_RETENTION,
+        pending_psks: Vec::new(),
+        resumption_psks: Vec::new(),
+        pending_update: None,
+        pending_leaf_keys: Vec::new(),
+        pending_credentials: Vec::new(),
+        roster_changes: Vec::new(),
+        proposal_store: ProposalStore::default(),
+        reinit: None,
     };
 
     (group_state, identity_keys)
@@ -167,8 +246,8 @@ pub(crate) fn random_basic_credential<R: rand::Rng + CryptoRng>(
         credential::Identity(buf.to_vec())
     };
 
-    // TODO: Expand the number of available ciphersuites once more are available
-    let signature_schemes = [&ED25519_IMPL];
+    // Signature schemes are independent of the cipher suite, so pick any one that can sign
+    let signature_schemes = [&ED25519_IMPL, &ED448_IMPL, &ECDSA_P521_IMPL];
     let ss = *signature_schemes.choose(rng).unwrap();
 
     // Generate a random keypair
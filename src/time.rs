@@ -0,0 +1,24 @@
+//! Defines `TimeSource`, which is where a `GroupState` gets the current time from when it checks
+//! the lifetimes of its members' keys
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A trait representing wherever the application gets the current time from. This is consulted
+/// whenever a key's `Lifetime` is checked. Members whose clocks disagree by more than the
+/// lifetimes allow for disagree on which Adds are valid, so this should be a clock that's kept in
+/// sync.
+pub trait TimeSource: Send + Sync {
+    /// Returns the current time, in seconds since the Unix epoch
+    fn now(&self) -> u64;
+}
+
+/// The system clock. This is the `TimeSource` that every `GroupState` starts out with.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl TimeSource for SystemClock {
+    fn now(&self) -> u64 {
+        // A clock that's set before 1970 is as good as one that's set to 1970
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
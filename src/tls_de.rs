@@ -1,532 +1,519 @@
-//! An MLS deserializer. This is the same wire format as TLS.
+//! An MLS deserializer. This is the same wire format as TLS. See `tls_ser` for how wire types
+//! describe their encodings.
 
-use crate::error::Error;
+use crate::{
+    crypto::provider::{CryptoProvider, DEFAULT_PROVIDER},
+    error::Error,
+    tls_ser::Bound,
+};
 
-use std::io::Read;
+use std::{cell::Cell, convert::TryFrom};
 
 use byteorder::{BigEndian, ReadBytesExt};
-use serde::de::{Deserializer, IntoDeserializer, Visitor};
 
-// TODO: Make this parser more conservative in what it accepts. Currently, it will happily return
+// TODO: Make the default mode as conservative as streaming mode. Currently, it will happily return
 // incomplete vectors (i.e., it'll read a length, get to the end of a buffer that's too short, and
 // then return what it has instead of blocking or erroring).
 // TODO: Consider the blocking behavior of this deserializer. Can we provide non-blocking options?
 
+/// A type that can be read off of the wire. Wire types get this from `#[derive(TlsDeserialize)]`.
+pub trait TlsDeserialize: Sized {
+    /// Reads a value of this type with the given deserializer
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<Self, Error>;
+}
+
 /// Makes an `error::Error::SerdeError(std::io::Error)` given some formattable input
-fn make_custom_error<T: core::fmt::Display>(msg: T) -> Error {
-    <Error as serde::de::Error>::custom(msg)
+pub(crate) fn make_custom_error<T: core::fmt::Display>(msg: T) -> Error {
+    Error::SerdeError(std::io::Error::new(std::io::ErrorKind::Other, msg.to_string()))
 }
 
-/// Given a reader and the name of a field or unit struct, find the length of the upcoming data.
-/// This only makes sense for variable-length data types. So for example if we were parsing the `v`
-/// field of
-/// ```
-/// # use serde::Deserialize;
-/// #[derive(Deserialize)]
-/// struct Foo {
-///     #[serde(rename = "v__bound_u8")]
-///     v: Vec<u8>
-/// }
-/// ```
-/// we would have `field == "v__bound_u8"` and look for a single byte representing the length of
-/// `v`. Similarly for newtype structs,
-/// ```
-/// # use serde::Deserialize;
-/// #[derive(Deserialize)]
-/// #[serde(rename = "Foo__bound_u8")]
-/// struct Foo(Vec<u8>);
-/// ```
-/// we would have `field == "Foo__bound_u8` and look for a single byte representing the length of
-/// the contained vector.
-fn get_field_len<R>(field: &'static str, reader: &mut R) -> Result<Option<u64>, Error>
-where
-    R: std::io::Read,
-{
-    let res = if field.ends_with("__bound_u8") {
-        Some(reader.read_u8()?.into())
-    } else if field.ends_with("__bound_u16") {
-        Some(reader.read_u16::<BigEndian>()?.into())
-    } else if field.ends_with("__bound_u24") {
-        Some(reader.read_u24::<BigEndian>()?.into())
-    } else if field.ends_with("__bound_u32") {
-        Some(reader.read_u32::<BigEndian>()?.into())
-    } else if field.ends_with("__bound_u64") {
-        Some(reader.read_u64::<BigEndian>()?)
-    } else {
-        None
+/// Makes the error for an enum tag that isn't the tag of any of the enum's variants. This is what
+/// `#[derive(TlsDeserialize)]` returns for them.
+pub(crate) fn unknown_variant(enum_name: &str, tag: u64) -> Error {
+    make_custom_error(format_args!("{} has no variant with the tag {}", enum_name, tag))
+}
+
+/// Reads a variable-length integer, as written by `tls_ser::write_varint`
+///
+/// Returns: `Ok(value)` on success. If the first two bits are `0b11`, which is reserved, or the
+/// integer isn't in its shortest encoding, returns an `Error::SerdeError`.
+pub(crate) fn read_varint<R: std::io::Read>(reader: &mut R) -> Result<u64, Error> {
+    let first = reader.read_u8()?;
+    let top_bits = u64::from(first & 0x3f);
+    let (value, min_value) = match first >> 6 {
+        0 => (top_bits, 0),
+        1 => ((top_bits << 8) | u64::from(reader.read_u8()?), 1 << 6),
+        2 => ((top_bits << 24) | u64::from(reader.read_u24::<BigEndian>()?), 1 << 14),
+        _ => return Err(make_custom_error("varint has the reserved length prefix 0b11")),
     };
 
-    Ok(res)
+    if value < min_value {
+        Err(make_custom_error("varint isn't in its shortest encoding"))
+    } else {
+        Ok(value)
+    }
 }
 
-/// This implements some subset of the TLS wire format. I still don't have a good source on the
-/// format, but it seems as though the idea is "concat everything, and specify length in the
-/// prefix".
-pub struct TlsDeserializer<'a, R: std::io::Read> {
-    reader: &'a mut R,
+/// How much `TlsDeserializer` is willing to decode. Every length on the wire is chosen by the
+/// sender, so without these, a malicious peer could declare absurd lengths and make a client
+/// allocate and work through as much as it sends. Values over a limit are rejected with an
+/// `Error::SerdeError`. The defaults are generous for real groups; servers can tighten them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DecodeLimits {
+    pub(crate) max_vec_len: u64,
+    pub(crate) max_tree_size: usize,
+    pub(crate) max_extensions: usize,
+    pub(crate) max_depth: usize,
 }
 
-impl<'a, R: std::io::Read> TlsDeserializer<'a, R> {
-    /// Makes a new `TlsDeserializer` from the given byte reader
-    pub fn from_reader(reader: &'a mut R) -> TlsDeserializer<R> {
-        TlsDeserializer {
-            reader,
+impl DecodeLimits {
+    /// Makes limits under which every vector may be declared up to `max_vec_len` bytes long, a
+    /// ratchet tree may have up to `max_tree_size` nodes, a list of extensions may have up to
+    /// `max_extensions` entries, and vectors may be nested up to `max_depth` deep
+    pub fn new(
+        max_vec_len: u64,
+        max_tree_size: usize,
+        max_extensions: usize,
+        max_depth: usize,
+    ) -> DecodeLimits {
+        DecodeLimits {
+            max_vec_len,
+            max_tree_size,
+            max_extensions,
+            max_depth,
         }
     }
-}
-
-impl<'de, 'a, 'b, R: std::io::Read> Deserializer<'de> for &'b mut TlsDeserializer<'a, R> {
-    type Error = Error;
-
-    //
-    // Implemented stuff
-    //
 
-    /// Hint that the `Deserialize` type is expecting a `u8` value.
-    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        visitor.visit_u8(self.reader.read_u8()?)
+    /// Returns how many bytes long a vector may be declared to be
+    pub fn max_vec_len(&self) -> u64 {
+        self.max_vec_len
     }
 
-    /// Hint that the `Deserialize` type is expecting a `u16` value.
-    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        visitor.visit_u16(self.reader.read_u16::<BigEndian>()?)
+    /// Returns how many nodes, blank or not, a ratchet tree may have
+    pub fn max_tree_size(&self) -> usize {
+        self.max_tree_size
     }
 
-    /// Hint that the `Deserialize` type is expecting a `u32` value.
-    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        visitor.visit_u32(self.reader.read_u32::<BigEndian>()?)
+    /// Returns how many entries a list of extensions may have
+    pub fn max_extensions(&self) -> usize {
+        self.max_extensions
     }
 
-    /// Hint that the `Deserialize` type is expecting a `u64` value.
-    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        visitor.visit_u64(self.reader.read_u64::<BigEndian>()?)
+    /// Returns how deeply vectors may be nested in one another
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
     }
 
-    /// Hint that the `Deserialize` type is expecting an `Option` value. This reads a single byte
-    /// that's a 0 or 1, then reads nothing or the contents of the `Some` variant, respectively.
-    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
-        let value: u8 = serde::de::Deserialize::deserialize(&mut *self)?;
-        match value {
-            0 => visitor.visit_none(),
-            1 => visitor.visit_some(&mut *self),
-            _ => Err(make_custom_error("expected binary tag for Option type")),
+    /// Returns how many items a vector under the given count limit may have, if it's under one
+    fn max_items(&self, limit: Option<ItemLimit>) -> Option<usize> {
+        match limit {
+            Some(ItemLimit::TreeSize) => Some(self.max_tree_size),
+            Some(ItemLimit::Extensions) => Some(self.max_extensions),
+            None => None,
         }
     }
+}
 
-    /// Hint that the `Deserialize` type is expecting a newtype struct with a particular name. This
-    /// will use our hacky naming scheme to find the length of the inner type (if it is a
-    /// variable-length type) and deserialize the contents normally.
-    fn deserialize_newtype_struct<V>(
-        self,
-        name: &'static str,
-        visitor: V,
-    ) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        // If the inner type is variable-length, this will return the length of the inner type in
-        // bytes
-        let field_len = get_field_len(name, &mut self.reader)?;
-
-        // Make a sub-reader that only reads the number of bytes specified by the length tag. Then
-        // deserialize the contents normally. It will finish when it runs out of things to read.
-        // This is guaranteed by the logic in TlsVecSeq.
-        if let Some(len) = field_len {
-            // Make a new deserializer with a sub-buffer
-            let mut sub_reader = self.reader.take(len);
-            let mut sub_deserializer = TlsDeserializer::from_reader(&mut sub_reader);
-
-            // Deserialize the contents normally
-            visitor.visit_newtype_struct(&mut sub_deserializer)
-        } else {
-            // Otherwise, if the inner type is not variable-length, deserialize the contents
-            // normally
-            visitor.visit_newtype_struct(self)
-        }
+impl Default for DecodeLimits {
+    /// Returns limits of 64 MiB per vector, trees of up to 2^17 nodes, i.e., 2^16 members, 256
+    /// extensions per list, and vectors nested 32 deep. Nothing this crate sends comes near the
+    /// depth limit.
+    fn default() -> DecodeLimits {
+        DecodeLimits::new(64 << 20, 1 << 17, 256, 32)
     }
+}
 
-    /// Hint that the `Deserialize` type is expecting a sequence of values. This will make a new
-    /// `TlsVecSeq` object and run `Visitor::visit_seq` on that.
-    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        let s = TlsVecSeq::new(self);
-        visitor.visit_seq(s)
-    }
+/// Which of the `DecodeLimits` on counts a vector is under. A field is put under one with
+/// `#[tls(limit = "tree")]` or `#[tls(limit = "extensions")]`, and every other vector is only
+/// limited by its length.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ItemLimit {
+    /// The vector has an entry per node or leaf of a ratchet tree, or fewer
+    TreeSize,
+    /// The vector is a list of extensions, or of extension types
+    Extensions,
+}
 
-    fn deserialize_enum<V>(
-        self,
-        name: &'static str,
-        _variants: &'static [&'static str],
-        visitor: V,
-    ) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        if name.ends_with("__enum_u8") {
-            let s = TlsEnumU8::new(self);
-            visitor.visit_enum(s)
-        } else {
-            Err(make_custom_error(format_args!(
-                "don't know how to deserialize non-__enum_u8 enums: {}",
-                name
-            )))
+/// Reads TLS-encoded values out of a byte slice by reference, rather than copying them out. This
+/// is what the borrowed views of messages, like `PrivateMessageRef`, are parsed with.
+pub(crate) struct SliceReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> SliceReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> SliceReader<'a> {
+        SliceReader {
+            bytes,
         }
     }
 
-    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        let s = TlsTupleSeq::new(self, len);
-        visitor.visit_seq(s)
+    /// Returns the next `len` bytes of the slice
+    ///
+    /// Returns: `Ok(bytes)` on success. If there are fewer than `len` bytes left, returns an
+    /// `Error::SerdeError`.
+    pub(crate) fn read_slice(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
     }
 
-    /// Hint that the `Deserialize` type is expecting a struct with a particular name and fields.
-    /// This will make a new `TlsStructSeq` object with the given fields and run
-    /// `Visitor::visit_seq` on that.
-    fn deserialize_struct<V>(
-        self,
-        _name: &'static str,
-        fields: &'static [&'static str],
-        visitor: V,
-    ) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        let s = TlsStructSeq::new(self, fields);
-        visitor.visit_seq(s)
+    pub(crate) fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(ReadBytesExt::read_u8(&mut self.bytes)?)
     }
 
-    /// I don't care who you are. This is not a human-readable format.
-    #[inline]
-    fn is_human_readable(&self) -> bool {
-        false
+    pub(crate) fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(ReadBytesExt::read_u32::<BigEndian>(&mut self.bytes)?)
     }
 
-    //
-    // Unimplemented stuff
-    //
-
-    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_bool<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_i8<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_i16<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_i32<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_i64<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_i128<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_u128<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_f32<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_f64<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_char<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_str<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_string<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_bytes<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_byte_buf<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_unit<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+    /// Returns the contents of the next `opaque<0..255>`
+    pub(crate) fn read_opaque_u8(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.read_u8()?;
+        self.read_slice(len.into())
     }
-    fn deserialize_unit_struct<V>(
-        self,
-        _name: &'static str,
-        _visitor: V,
-    ) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
-    }
-    fn deserialize_tuple_struct<V>(
-        self,
-        _name: &'static str,
-        _len: usize,
-        _visitor: V,
-    ) -> Result<V::Value, Self::Error>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
-    }
-    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
-    }
-    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+
+    /// Returns the contents of the next `opaque<0..2^32-1>`
+    pub(crate) fn read_opaque_u32(&mut self) -> Result<&'a [u8], Error> {
+        // The cast is fine, since this crate requires 32-bit pointers
+        let len = self.read_u32()? as usize;
+        self.read_slice(len)
     }
-    fn deserialize_ignored_any<V: Visitor<'de>>(
-        self,
-        _visitor: V,
-    ) -> Result<V::Value, Self::Error> {
-        unimplemented!()
+
+    /// Returns what's left of the slice
+    pub(crate) fn remaining(&self) -> &'a [u8] {
+        self.bytes
     }
 }
 
-/// This deals with the logic of deserializing structs. This is just a sequence of fields. If the
-/// field name has a length tag size, this is handled appropriately.
-struct TlsStructSeq<'a, 'b, R: std::io::Read> {
-    /// A reference to the deserializer that called us
-    de: &'a mut TlsDeserializer<'b, R>,
-    /// The fields of this struct
-    fields: &'static [&'static str],
-    /// An index to which field is currently being deserialized
-    field_idx: usize,
+/// A reader over the contents of a field with a declared length. It reads no further than
+/// `remaining`, which is shared with the deserializer reading from it, so that it can tell where
+/// the field ends. The inner reader is a trait object, so that fields nested in fields are read
+/// by the same type of deserializer as the fields they're in.
+struct BoundedReader<'a> {
+    inner: &'a mut (dyn std::io::Read + 'a),
+    remaining: &'a Cell<u64>,
+    /// If this is set, the inner reader running out before the field does is an error. Otherwise,
+    /// it's treated like the end of the field, which is what `std::io::Take` does.
+    strict: bool,
 }
 
-impl<'a, 'b, R: std::io::Read> TlsStructSeq<'a, 'b, R> {
-    /// Returns a new `TlsStructSeq` with the given deserializer, the given fields, and starting
-    /// index 0
-    fn new(
-        de: &'a mut TlsDeserializer<'b, R>,
-        fields: &'static [&'static str],
-    ) -> TlsStructSeq<'a, 'b, R> {
-        TlsStructSeq {
-            de,
-            fields,
-            field_idx: 0,
+impl<'a> std::io::Read for BoundedReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.remaining.get();
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        // The cast is fine, since the min is at most buf.len()
+        let max_len = core::cmp::min(buf.len() as u64, remaining) as usize;
+        let num_read = self.inner.read(&mut buf[..max_len])?;
+        if num_read == 0 && self.strict {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "input ended before the declared length of a field",
+            ));
         }
+
+        self.remaining.set(remaining - num_read as u64);
+        Ok(num_read)
     }
 }
 
-/// This deals with the logic of deserializing tuples. It's just a fixed-length sequence of items
-struct TlsTupleSeq<'a, 'b, R: std::io::Read> {
-    /// A reference to the deserializer that called us
-    de: &'a mut TlsDeserializer<'b, R>,
-    /// The number of elements in the tuple
-    len: usize,
-    /// Our current position in the tuple
-    idx: usize,
+/// This implements some subset of the TLS wire format. I still don't have a good source on the
+/// format, but it seems as though the idea is "concat everything, and specify length in the
+/// prefix".
+pub struct TlsDeserializer<'a, R: std::io::Read> {
+    reader: &'a mut R,
+    /// Whether this was made with `TlsDeserializer::from_stream`
+    streaming: bool,
+    /// The number of bytes left in the innermost field with a declared length that's being
+    /// deserialized. This is `None` at the top level.
+    remaining: Option<&'a Cell<u64>>,
+    limits: DecodeLimits,
+    /// What cipher suite and signature scheme IDs are looked up in
+    provider: &'static dyn CryptoProvider,
+    /// How many fields with declared lengths the current one is nested in
+    depth: usize,
+    /// How many items the vector in the current field may have, if it's limited by count
+    max_items: Option<usize>,
 }
 
-impl<'a, 'b, R: std::io::Read> TlsTupleSeq<'a, 'b, R> {
-    /// Returns a new `TlsTupleSeq` with the given deserializer and length, and sets the starting
-    /// index to 0
-    fn new(de: &'a mut TlsDeserializer<'b, R>, len: usize) -> TlsTupleSeq<'a, 'b, R> {
-        TlsTupleSeq {
-            de,
-            len,
-            idx: 0,
+impl<'a, R: std::io::Read> TlsDeserializer<'a, R> {
+    /// Makes a new `TlsDeserializer` from the given byte reader
+    pub fn from_reader(reader: &'a mut R) -> TlsDeserializer<R> {
+        TlsDeserializer {
+            reader,
+            streaming: false,
+            remaining: None,
+            limits: DecodeLimits::default(),
+            provider: DEFAULT_PROVIDER,
+            depth: 0,
+            max_items: None,
         }
     }
-}
 
-impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsTupleSeq<'a, 'b, R> {
-    type Error = Error;
+    /// Makes a new `TlsDeserializer` that reads from a stream, e.g., a socket. The declared
+    /// length of every field is enforced as its contents are read: a field whose contents end
+    /// before or after its declared length is an error, and so is the stream ending in the middle
+    /// of a field, rather than either being read as a shorter field. Nothing is read past the end
+    /// of the value being deserialized, and nothing is buffered, so large messages needn't be read
+    /// into memory first. Wrap unbuffered readers in a `std::io::BufReader`, since this reads a few
+    /// bytes at a time.
+    pub fn from_stream(reader: &'a mut R) -> TlsDeserializer<R> {
+        TlsDeserializer {
+            reader,
+            streaming: true,
+            remaining: None,
+            limits: DecodeLimits::default(),
+            provider: DEFAULT_PROVIDER,
+            depth: 0,
+            max_items: None,
+        }
+    }
 
-    /// Deserializes the next field in the tuple
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+    /// Replaces the `DecodeLimits` that this enforces, which are the defaults to begin with
+    pub fn with_limits(mut self, limits: DecodeLimits) -> TlsDeserializer<'a, R> {
+        self.limits = limits;
+        self
+    }
+
+    /// Replaces the `CryptoProvider` that cipher suites and signature schemes are looked up in by
+    /// their IDs, which is the default provider to begin with. An ID that the provider doesn't
+    /// implement is an error.
+    pub fn with_provider(
+        mut self,
+        provider: &'static dyn CryptoProvider,
+    ) -> TlsDeserializer<'a, R> {
+        self.provider = provider;
+        self
+    }
+
+    /// Returns the `CryptoProvider` that cipher suites and signature schemes are looked up in
+    pub(crate) fn provider(&self) -> &'static dyn CryptoProvider {
+        self.provider
+    }
+
+    /// Reads a length prefix of the given width, then deserializes a `T` out of exactly that many
+    /// bytes. This is what a field called `field` that's marked `#[tls(bound = "...")]` is
+    /// deserialized with. If the field is also marked `#[tls(limit = "...")]`, the vector in it is
+    /// under that count limit.
+    ///
+    /// Returns: `Ok(value)` on success. If the length, the count, or the nesting depth is over the
+    /// limits, returns an `Error::SerdeError`. Otherwise, returns whatever error deserializing a
+    /// `T` does.
+    pub(crate) fn read_bounded<T: TlsDeserialize>(
+        &mut self,
+        bound: Bound,
+        limit: Option<ItemLimit>,
+        field: &'static str,
+    ) -> Result<T, Error> {
+        let len = match bound.width() {
+            Some(width) => self.reader.read_uint::<BigEndian>(width)?,
+            None => read_varint(&mut self.reader)?,
+        };
+
+        self.deserialize_bounded(field, limit, len, |sub_deserializer| {
+            T::tls_deserialize(sub_deserializer)
+        })
+    }
+
+    /// Runs `f` on a deserializer over the next `len` bytes, which are the contents of the field
+    /// called `field`, whose length was declared to be `len`, and whose vector is under `limit`
+    ///
+    /// Returns: `Ok(value)` on success. If `len` or the nesting depth is over the limits, returns
+    /// an `Error::SerdeError`. In streaming mode, if `f` didn't read exactly `len` bytes, returns
+    /// an `Error::SerdeError`. Otherwise, returns whatever error `f` does.
+    fn deserialize_bounded<T, F>(
+        &mut self,
+        field: &'static str,
+        limit: Option<ItemLimit>,
+        len: u64,
+        f: F,
+    ) -> Result<T, Error>
     where
-        T: serde::de::DeserializeSeed<'de>,
+        F: FnOnce(&mut TlsDeserializer<'_, BoundedReader<'_>>) -> Result<T, Error>,
     {
-        // If we're done, return None. Otherwise increment the counter and deserialize the next
-        // thing
-        if self.idx >= self.len {
-            Ok(None)
-        } else {
-            self.idx += 1;
-            seed.deserialize(&mut *self.de).map(Some)
+        if len > self.limits.max_vec_len {
+            return Err(make_custom_error(format_args!(
+                "{} is declared to be {} bytes long, which is over the decode limit of {}",
+                field, len, self.limits.max_vec_len
+            )));
+        }
+        if self.depth >= self.limits.max_depth {
+            return Err(make_custom_error("vectors are nested deeper than the decode limit"));
         }
-    }
-}
 
-impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsStructSeq<'a, 'b, R> {
-    type Error = Error;
+        let remaining = Cell::new(len);
+        let res = {
+            let mut sub_reader = BoundedReader {
+                inner: &mut *self.reader,
+                remaining: &remaining,
+                strict: self.streaming,
+            };
+            let mut sub_deserializer = TlsDeserializer {
+                reader: &mut sub_reader,
+                streaming: self.streaming,
+                remaining: Some(&remaining),
+                limits: self.limits,
+                provider: self.provider,
+                depth: self.depth + 1,
+                max_items: self.limits.max_items(limit),
+            };
+            f(&mut sub_deserializer)
+        };
+
+        if !self.streaming {
+            return res;
+        }
 
-    /// Deserializes the next field in the struct
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
-    where
-        T: serde::de::DeserializeSeed<'de>,
-    {
-        // This function will not be called more times than there are fields in the struct. If it
-        // is, we will panic
-        let field =
-            self.fields.get(self.field_idx).expect("in unknown field while deserializing a struct");
-        self.field_idx += 1;
-
-        // If this is a variable-length field, read off the length
-        let field_len = get_field_len(field, &mut self.de.reader)?;
-
-        // As in TlsDeserializer::deserialize_newtype_struct, make a sub-reader that only reads the
-        // number of bytes specified by the length tag. Then deserialize the contents normally. It
-        // will finish when it runs out of things to read. This is guaranteed by the logic in
-        // TlsVecSeq.
-        if let Some(len) = field_len {
-            // Make a sub-buffer to read from
-            let mut sub_reader = self.de.reader.take(len);
-            let mut sub_deserializer = TlsDeserializer::from_reader(&mut sub_reader);
-
-            // Deserialize from it normally
-            seed.deserialize(&mut sub_deserializer).map(Some)
-        } else {
-            // If no length is specified, do the natural thing
-            seed.deserialize(&mut *self.de).map(Some)
+        match res {
+            // Running out of the field's bytes in the middle of something is an error here. It
+            // can't be allowed to look like the end of the input, since a vector in an enclosing
+            // field would take it to mean that the vector is over.
+            Err(Error::SerdeError(ref e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Err(make_custom_error("a value runs past the declared length of its field"))
+            }
+            Ok(_) if remaining.get() != 0 => {
+                Err(make_custom_error("a field is shorter than its declared length"))
+            }
+            res => res,
         }
+    }
 
-        // We can't wrap errors like below. Recall that the sequence deserializer will stop
-        // deserializing once it hits an io::ErrorKind::UnexpectedEof. If `res` above is one of
-        // those, then changing it to an ErrorKind::Custom will make the sequence deserializer
-        // incorrectly think that an error occurred.
-        // TL;DR be better
-        //
-        //res.map_err(|e| make_custom_error(format!("Error reading field {}\n{}", field, e)))
+    /// Returns whether the field being deserialized is known to have nothing left in it. This is
+    /// only ever known in streaming mode. Otherwise, the end of a vector is only found by trying
+    /// to read past it.
+    fn field_is_done(&self) -> bool {
+        self.streaming && self.remaining.map_or(false, |remaining| remaining.get() == 0)
     }
 }
 
-/// This deals with the logic of deserializing sequences (mostly `Vec`s). The logic is simple: keep
-/// deserializing items until you run out of buffer space. The reader that this is given is limited
-/// to the total number of bytes we're supposed to read, so there's no fear of overrun.
-struct TlsVecSeq<'a, 'b, R: std::io::Read> {
-    de: &'a mut TlsDeserializer<'b, R>,
-}
+//
+// Here are the building blocks that everything else is deserialized out of
+//
 
-impl<'a, 'b, R: std::io::Read> TlsVecSeq<'a, 'b, R> {
-    /// Makes a new `TlsVecSeq` object from the given deserializer
-    fn new(de: &'a mut TlsDeserializer<'b, R>) -> TlsVecSeq<'a, 'b, R> {
-        TlsVecSeq {
-            de,
-        }
+impl TlsDeserialize for u8 {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<u8, Error> {
+        Ok(deserializer.reader.read_u8()?)
     }
 }
 
-impl<'de, 'a, 'b, R: std::io::Read> serde::de::SeqAccess<'de> for TlsVecSeq<'a, 'b, R> {
-    type Error = Error;
+impl TlsDeserialize for u16 {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<u16, Error> {
+        Ok(deserializer.reader.read_u16::<BigEndian>()?)
+    }
+}
 
-    /// Deserializes the next item in the list
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
-    where
-        T: serde::de::DeserializeSeed<'de>,
-    {
-        // Try to deserialize the next item
-        match seed.deserialize(&mut *self.de) {
-            // If it's all good, return it
-            Ok(a) => Ok(Some(a)),
-            Err(Error::SerdeError(io_err)) => {
-                if io_err.kind() == std::io::ErrorKind::UnexpectedEof {
-                    // If we've reached the end of the buffer, that means we're done reading into
-                    // this list
-                    Ok(None)
-                } else {
-                    // Otherwise, it's some other error. Return it
-                    Err(Error::SerdeError(io_err))
-                }
-            }
-            // We can't receive a non-serde error from a deserialize method
-            _ => unreachable!(),
-        }
+impl TlsDeserialize for u32 {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<u32, Error> {
+        Ok(deserializer.reader.read_u32::<BigEndian>()?)
     }
 }
 
-/// This deals with the logic of deserializing enums with variant indices of size u8
-struct TlsEnumU8<'a, 'b, R: std::io::Read> {
-    de: &'a mut TlsDeserializer<'b, R>,
+impl TlsDeserialize for u64 {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<u64, Error> {
+        Ok(deserializer.reader.read_u64::<BigEndian>()?)
+    }
 }
 
-impl<'a, 'b, R: std::io::Read> TlsEnumU8<'a, 'b, R> {
-    /// Makes a new `TlsEnumU8` object from the given deserializer
-    fn new(de: &'a mut TlsDeserializer<'b, R>) -> TlsEnumU8<'a, 'b, R> {
-        TlsEnumU8 {
-            de,
+/// This reads a single byte that's a 0 or 1, then reads nothing or the contents of the `Some`
+/// variant, respectively
+impl<T: TlsDeserialize> TlsDeserialize for Option<T> {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<Option<T>, Error> {
+        match u8::tls_deserialize(deserializer)? {
+            0 => Ok(None),
+            1 => T::tls_deserialize(deserializer).map(Some),
+            _ => Err(make_custom_error("expected binary tag for Option type")),
         }
     }
 }
 
-impl<'de, 'a, 'b, R: std::io::Read> serde::de::EnumAccess<'de> for TlsEnumU8<'a, 'b, R> {
-    type Error = Error;
-    type Variant = Self;
+/// This deals with the logic of deserializing vectors. The logic is simple: keep deserializing
+/// items until you run out of buffer space. The reader that this is given is limited to the total
+/// number of bytes we're supposed to read, so there's no fear of overrun. In streaming mode, the
+/// vector ends exactly when its field does.
+impl<T: TlsDeserialize> TlsDeserialize for Vec<T> {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<Vec<T>, Error> {
+        // The limit is for this vector, not any unbounded ones inside of it
+        let max_items = deserializer.max_items.take();
+        let mut items = Vec::new();
+
+        loop {
+            // In streaming mode, we know how much is left of the field, so there's no need to
+            // guess from where the input ends. Running out in the middle of an item is caught by
+            // TlsDeserializer::deserialize_bounded.
+            if deserializer.field_is_done() {
+                break;
+            }
 
-    /// Deserializes an enum variant
-    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Error>
-    where
-        V: serde::de::DeserializeSeed<'de>,
-    {
-        // The variant index is a u8. Serde lets us turn that number into a deserializer which we
-        // can then use to get find correct variant.
-        let idx: u8 = serde::de::Deserialize::deserialize(&mut *self.de)?;
-        let variant_de: serde::de::value::U8Deserializer<Error> = idx.into_deserializer();
-        // Now that we have the variant index, deserialize the contents (if there are any)
-        let val = seed.deserialize(variant_de)?;
-        Ok((val, self))
-    }
-}
+            // Try to deserialize the next item
+            match T::tls_deserialize(deserializer) {
+                Ok(item) => items.push(item),
+                // If we've reached the end of the buffer, that means we're done reading into this
+                // list. In streaming mode, the end is never reached like this, since the stream
+                // ending early is an error.
+                Err(Error::SerdeError(ref e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof
+                        && !(deserializer.streaming && deserializer.remaining.is_some()) =>
+                {
+                    break
+                }
+                // Otherwise, it's some other error. Return it
+                Err(e) => return Err(e),
+            }
 
-impl<'de, 'a, 'b, R> serde::de::VariantAccess<'de> for TlsEnumU8<'a, 'b, R>
-where
-    R: std::io::Read,
-{
-    type Error = Error;
+            // Outside of streaming mode, the end of the list is only found by trying to read
+            // another item, so the count is checked after the item is read
+            match max_items {
+                Some(max_items) if items.len() > max_items => {
+                    return Err(make_custom_error(format_args!(
+                        "a list has more than the decode limit of {} items",
+                        max_items
+                    )))
+                }
+                _ => (),
+            }
+        }
 
-    fn unit_variant(self) -> Result<(), Error> {
-        Ok(())
+        Ok(items)
     }
+}
 
-    // For newtypes, just deserialize the insides
-    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
-    where
-        T: serde::de::DeserializeSeed<'de>,
-    {
-        seed.deserialize(&mut *self.de)
-    }
+impl<T: TlsDeserialize, const N: usize> TlsDeserialize for [T; N] {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<[T; N], Error> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::tls_deserialize(deserializer)?);
+        }
 
-    // For tuples, deserialize the insides as a tuple
-    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        self.de.deserialize_tuple(len, visitor)
+        // There are exactly N items, so this can't fail
+        <[T; N]>::try_from(items).map_err(|_| unreachable!("array has the wrong number of items"))
     }
+}
 
-    // For structs, deserialize the insides as a tuple
-    fn struct_variant<V>(
-        self,
-        fields: &'static [&'static str],
-        visitor: V,
-    ) -> Result<V::Value, Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        self.de.deserialize_tuple(fields.len(), visitor)
+impl<T: TlsDeserialize> TlsDeserialize for Box<T> {
+    fn tls_deserialize<R: std::io::Read>(
+        deserializer: &mut TlsDeserializer<'_, R>,
+    ) -> Result<Box<T>, Error> {
+        T::tls_deserialize(deserializer).map(Box::new)
     }
 }
 
@@ -534,9 +521,10 @@ where
 mod test {
     use super::*;
     // Use the test vectors from the serialization code
-    use crate::tls_ser::test::{make_biff, Biff, BIFF_BYTES};
-
-    use serde::de::Deserialize;
+    use crate::tls_ser::{
+        self,
+        test::{make_biff, Biff, Zork, BIFF_BYTES},
+    };
 
     // Make a byte sequence by hand whose Biff-deserialization we know, then test that it is what
     // we expect. This uses some stupidly named structs.
@@ -545,8 +533,158 @@ mod test {
         let mut buf = BIFF_BYTES;
         let mut deserializer = TlsDeserializer::from_reader(&mut buf);
         let expected_biff = make_biff();
-        let deserialized_biff = Biff::deserialize(&mut deserializer).unwrap();
+        let deserialized_biff = Biff::tls_deserialize(&mut deserializer).unwrap();
 
         assert_eq!(deserialized_biff, expected_biff);
     }
+
+    // Varint-bounded vectors come back the way they went out, and lengths that aren't in their
+    // shortest encoding or use the reserved prefix are rejected
+    #[test]
+    fn varint_roundtrip() {
+        let zork = Zork {
+            za: vec![0x55; 300],
+            zb: Vec::new(),
+        };
+        let serialized = tls_ser::serialize_to_bytes(&zork).unwrap();
+        let mut buf = serialized.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut buf);
+        assert_eq!(Zork::tls_deserialize(&mut deserializer).unwrap(), zork);
+
+        // 37 takes 1 byte, so its 2-byte encoding isn't allowed
+        assert_eq!(read_varint(&mut &[0x25u8][..]).unwrap(), 37);
+        assert!(read_varint(&mut &[0x40u8, 0x25][..]).is_err());
+        assert!(read_varint(&mut &[0xc0u8, 0, 0, 0, 0, 0, 0, 0x25][..]).is_err());
+    }
+
+    // Lengths, counts, and depths over the decode limits are rejected, in either mode
+    #[test]
+    fn decode_limits() {
+        #[derive(Debug, Eq, PartialEq, TlsDeserialize)]
+        struct Lists {
+            #[tls(bound = "u16", limit = "extensions")]
+            extensions: Vec<u16>,
+            #[tls(bound = "u16")]
+            nodes: Vec<u16>,
+        }
+        // Three extensions, then three things that aren't counted, despite the field's name
+        let bytes = [0x00, 0x06, 0, 1, 0, 2, 0, 3, 0x00, 0x06, 0, 4, 0, 5, 0, 6];
+        let decode = |limits: DecodeLimits, streaming: bool| {
+            let mut buf = &bytes[..];
+            let deserializer = if streaming {
+                TlsDeserializer::from_stream(&mut buf)
+            } else {
+                TlsDeserializer::from_reader(&mut buf)
+            };
+            Lists::tls_deserialize(&mut deserializer.with_limits(limits))
+        };
+
+        for &streaming in &[false, true] {
+            // Exactly at the limits is fine
+            assert!(decode(DecodeLimits::new(6, 0, 3, 1), streaming).is_ok());
+            // Only the extensions are counted
+            assert!(decode(DecodeLimits::new(6, 0, 2, 1), streaming).is_err());
+            assert!(decode(DecodeLimits::new(5, 0, 3, 1), streaming).is_err());
+            assert!(decode(DecodeLimits::new(6, 0, 3, 0), streaming).is_err());
+        }
+
+        // Biff has a vector nested in a vector
+        let mut buf = BIFF_BYTES;
+        let mut deserializer =
+            TlsDeserializer::from_reader(&mut buf).with_limits(DecodeLimits::new(64, 0, 0, 1));
+        assert!(Biff::tls_deserialize(&mut deserializer).is_err());
+    }
+
+    // Optionals and enums with explicit, wider tags come back the way they went out, and unknown
+    // tags and presence bytes are rejected
+    #[test]
+    fn tags_roundtrip() {
+        #[derive(Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+        #[tls(tag = "u16")]
+        enum Kind {
+            #[tls(discriminant = 7)]
+            Seven(#[tls(bound = "u8")] Vec<u8>),
+            Other,
+        }
+
+        #[derive(Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+        struct Holder {
+            kind: Option<Kind>,
+            rest: Option<u32>,
+        }
+
+        let decode = |bytes: &[u8]| {
+            let mut buf = bytes;
+            Holder::tls_deserialize(&mut TlsDeserializer::from_reader(&mut buf))
+        };
+
+        let holders = [
+            (
+                Holder {
+                    kind: Some(Kind::Seven(vec![0xaa, 0xbb])),
+                    rest: None,
+                },
+                vec![0x01, 0x00, 0x07, 0x02, 0xaa, 0xbb, 0x00],
+            ),
+            (
+                Holder {
+                    kind: Some(Kind::Other),
+                    rest: Some(9),
+                },
+                vec![0x01, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x09],
+            ),
+        ];
+        for (holder, bytes) in holders.iter() {
+            assert_eq!(&tls_ser::serialize_to_bytes(holder).unwrap(), bytes);
+            assert_eq!(&decode(bytes).unwrap(), holder);
+        }
+
+        // Kind has no variant 0, and 2 isn't a presence byte
+        assert!(decode(&[0x01, 0x00, 0x00, 0x00]).is_err());
+        assert!(decode(&[0x02]).is_err());
+    }
+
+    // A reader that hands out one byte per read, like a slow socket might
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> std::io::Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    // Streaming mode reads the same Biff, and reads nothing past it
+    #[test]
+    fn streaming_kat() {
+        let trailing = [0xde, 0xad];
+        let bytes = [BIFF_BYTES, &trailing].concat();
+        let mut stream = Trickle(&bytes);
+        let mut deserializer = TlsDeserializer::from_stream(&mut stream);
+        let deserialized_biff = Biff::tls_deserialize(&mut deserializer).unwrap();
+
+        assert_eq!(deserialized_biff, make_biff());
+        assert_eq!(stream.0, &trailing);
+    }
+
+    // Streaming mode rejects input that ends early, or that doesn't fit its declared lengths,
+    // rather than returning shorter vectors
+    #[test]
+    fn streaming_enforces_lengths() {
+        // Cut off in the middle of the second Fan's Shake
+        let mut truncated = Trickle(&BIFF_BYTES[..38]);
+        let mut deserializer = TlsDeserializer::from_stream(&mut truncated);
+        assert!(Biff::tls_deserialize(&mut deserializer).is_err());
+
+        // Say that the first Fan's Vec<u32> is 11 bytes long, so its last u32 runs past it
+        let mut bad_len = BIFF_BYTES.to_vec();
+        bad_len[10] = 0x0b;
+        let mut buf = bad_len.as_slice();
+        let mut deserializer = TlsDeserializer::from_stream(&mut buf);
+        assert!(Biff::tls_deserialize(&mut deserializer).is_err());
+    }
 }
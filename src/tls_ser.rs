@@ -1,533 +1,451 @@
 //! An MLS serializer. This is the same wire format as TLS.
+//!
+//! Wire types don't implement any of this by hand. They `#[derive(TlsSerialize, TlsDeserialize)]`
+//! from `molasses_derive`, and say how the TLS presentation language lays them out with `tls`
+//! attributes:
+//!
+//! * A field marked `#[tls(bound = "uN")]`, or a newtype struct marked that way, where `N` is 8,
+//!   16, 24, 32, or 64, is a vector with a length prefix of that many bits, e.g.,
+//!   `opaque name<0..2^16-1>` is `#[tls(bound = "u16")] name: Vec<u8>`.
+//! * A field or newtype struct marked `#[tls(bound = "varint")]` is a vector whose length prefix is
+//!   a variable-length integer, as in the current MLS wire format, i.e., `opaque name<V>`. A
+//!   structure is converted to that format by changing its vectors' bounds.
+//! * An enum marked `#[tls(tag = "u8")]` is tagged with its variant index in one byte, or two with
+//!   `"u16"`. A variant marked `#[tls(discriminant = N)]` is tagged with `N` instead. Variants with
+//!   contents follow the tag with their contents, like a struct.
+//! * A field marked `#[tls(skip)]` isn't on the wire at all.
+//! * An `Option` is a presence byte, then the value if the byte is 1, i.e., `optional<T>`.
+//! * Everything else, i.e., integers, arrays, and structs, is the concatenation of its parts.
+//!
+//! Types whose encoding isn't a function of their Rust structure, like cipher suites, which are
+//! sent as their IDs, have hand-written impls in the `codec` module. Every wire type's TLS
+//! definition goes in a `//` comment right above it, so the two can be compared.
 
 use crate::error::Error;
 
 use std::convert::TryFrom;
 
 use byteorder::{BigEndian, WriteBytesExt};
-use doc_comment::doc_comment;
-use serde::ser::{Serialize, SerializeSeq, Serializer};
 
-/// Uses `TlsSerializer` to serialize the input to a vector of bytes
-pub(crate) fn serialize_to_bytes<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
-    let mut serializer = TlsSerializer::new();
-    value.serialize(&mut serializer)?;
-    Ok(serializer.buf.into_inner())
+/// A type that can be put on the wire. Wire types get this from `#[derive(TlsSerialize)]`.
+pub trait TlsSerialize {
+    /// Writes this value out with the given serializer
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error>;
 }
 
-// This macro gives us a way of serializing things with Tls notation like <1..2^16-1>. Here's how
-// it works: we're given some serializable value: &T and we want to encode it so that we can
-// specify its length in bytes as a prefix. So we first write 0 to the serialization buffer (that's
-// TlsSerializer.buf), then serialize the whole value out. Once it's serialized, we now know how
-// many bytes its serialization takes, so we seek back to the prefix location, and put that in as
-// the length. One downside of this: we have to serialize the whole thing before we can reject it
-// as too long. But this is nice and simple and I don't think it'll backfire unless the local
-// member is actively trying to take up a ton of memory.
-macro_rules! serialize_with_bound {
-    ($t:ty, $ti:ident, $fn_name:ident, $write_fn:ident, $endianness:ty ) => {
-        doc_comment! {
-            concat!(
-                "Serializes an object with a length in bytes that must be representable by `",
-                stringify!($t),
-                "`",
-            ),
-            pub(crate) fn $fn_name<'a, T: Serialize + ?Sized>(
-                value: &T,
-                serializer: &mut &'a mut TlsSerializer,
-            ) -> Result<
-                <&'a mut TlsSerializer as Serializer>::Ok,
-                <&'a mut TlsSerializer as Serializer>::Error,
-            > {
-                use std::convert::TryFrom;
-
-                // Starting position
-                let len_pos = serializer.buf.position();
-                // Write a dummy zero here, then serialize everything we get, then rewrite the
-                // correct length in the position of the dummy zero.
-                serializer.buf.$write_fn::<$endianness>(0)?;
-                value.serialize(&mut **serializer)?;
-                // End position - start position - size of length tag = length of serialized output
-                // The length is a u64 because an std::Cursor can only seek up to 2^64-1 bytes
-                let len: u64 = {
-                    let ty_size = u64::try_from(std::mem::size_of::<$t>())
-                        .expect(&format!("Type {} is too big to be serialized", stringify!(t)));
-                    serializer.buf.position() - len_pos - ty_size
-                };
-
-                if len > u64::from(std::$ti::MAX) {
-                    let err = <Error as serde::ser::Error>::custom(
-                        format_args!(
-                            "tried to serialize a {}-bounded object that was too long",
-                            stringify!($t)
-                        )
-                    );
-                    return Err(err)
-                }
-
-                // If we haven't errored out yet, we're within the bound
-                let len: $t = len as $t;
-                // Save the position at the end of the buffer, seek to the length tag, write, the
-                // length, then seek back to the end.
-                let curr_pos = serializer.buf.position();
-                serializer.buf.set_position(len_pos);
-                serializer.buf.$write_fn::<$endianness>(len)?;
-                serializer.buf.set_position(curr_pos);
-
-                Ok(())
-            }
-        }
-    };
+/// Makes an `Error::SerdeError` given some formattable input
+fn make_custom_error<T: core::fmt::Display>(msg: T) -> Error {
+    Error::SerdeError(std::io::Error::new(std::io::ErrorKind::Other, msg.to_string()))
 }
 
-serialize_with_bound!(u16, u16, serialize_with_bound_u16, write_u16, BigEndian);
-serialize_with_bound!(u32, u32, serialize_with_bound_u32, write_u32, BigEndian);
-serialize_with_bound!(u64, u64, serialize_with_bound_u64, write_u64, BigEndian);
-
-// We need some custom instances for u8 and u24, since byteorder's write_u8 isn't parametric over
-// any type, and u24 is not a type.
+/// Uses `TlsSerializer` to serialize the input to a vector of bytes
+pub(crate) fn serialize_to_bytes<T: TlsSerialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut serializer = TlsSerializer::new();
+    value.tls_serialize(&mut serializer)?;
+    Ok(serializer.into_vec())
+}
 
-/// Serializes an object with a length in bytes that must be representable by `u8`
-pub(crate) fn serialize_with_bound_u8<'a, T: Serialize + ?Sized>(
+/// Uses `TlsSerializer` to serialize the input onto the end of `buf`. Nothing else is allocated,
+/// so a caller that reuses `buf` from one value to the next only allocates when it has to grow.
+///
+/// Returns: `Ok(())` on success. Otherwise, returns an `Error::SerdeError`, and `buf` is left the
+/// way it was.
+pub(crate) fn serialize_into_vec<T: TlsSerialize + ?Sized>(
     value: &T,
-    serializer: &mut &'a mut TlsSerializer,
-) -> Result<<&'a mut TlsSerializer as Serializer>::Ok, <&'a mut TlsSerializer as Serializer>::Error>
-{
-    // Starting position
-    let len_pos = serializer.buf.position();
-    // Write a dummy zero here, then serialize everything we get, then rewrite the correct
-    // length in the position of the dummy zero.
-    serializer.buf.write_u8(0)?;
-    value.serialize(&mut **serializer)?;
-    // End position - start position - size of length tag = length of serialized output
-    let len: u64 = serializer.buf.position() - len_pos - 1;
-
-    if len > std::u8::MAX.into() {
-        let err = <Error as serde::ser::Error>::custom(
-            "tried to serialize a u8-bounded object that was too long",
-        );
-        return Err(err);
-    }
-
-    // If we haven't errored out yet, we're within the bound
-    let len: u8 = len as u8;
-    // Save the position at the end of the buffer, seek to the length tag, write, the
-    // length, then seek back to the end.
-    let curr_pos = serializer.buf.position();
-    serializer.buf.set_position(len_pos);
-    serializer.buf.write_u8(len)?;
-    serializer.buf.set_position(curr_pos);
+    buf: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let start = buf.len();
+    let mut serializer = TlsSerializer::with_buffer(std::mem::take(buf));
+    let res = value.tls_serialize(&mut serializer);
+    *buf = serializer.into_vec();
+    if res.is_err() {
+        buf.truncate(start);
+    }
 
-    Ok(())
+    res
 }
 
-/// Serializes an object with a length in bytes that must be representable by `u24` (i.e. 3 bytes)
-pub(crate) fn serialize_with_bound_u24<'a, T: Serialize + ?Sized>(
+/// Uses `TlsSerializer` to serialize the input into `writer`. Length prefixes are filled in after
+/// the things they prefix are serialized, so the serialization is built up in memory, in a buffer
+/// of exactly `serialized_len(value)` bytes, before it's written out.
+///
+/// Returns: `Ok(())` on success. Otherwise, returns an `Error::SerdeError`. If the error came from
+/// `writer`, some of the serialization may have been written.
+pub(crate) fn serialize_into<T: TlsSerialize + ?Sized, W: std::io::Write>(
     value: &T,
-    serializer: &mut &'a mut TlsSerializer,
-) -> Result<<&'a mut TlsSerializer as Serializer>::Ok, <&'a mut TlsSerializer as Serializer>::Error>
-{
-    // Starting position
-    let len_pos = serializer.buf.position();
-    // Write a dummy zero here, then serialize everything we get, then rewrite the correct
-    // length in the position of the dummy zero.
-    serializer.buf.write_u24::<BigEndian>(0)?;
-    value.serialize(&mut **serializer)?;
-    // End position - start position - size of length tag = length of serialized output
-    let len: u64 = serializer.buf.position() - len_pos - 3;
-
-    if len >= (1u64 << 24) {
-        let err = <Error as serde::ser::Error>::custom(
-            "tried to serialize a u24-bounded object that was too long",
-        );
-        return Err(err);
-    }
-
-    // If we haven't errored out yet, we're within the bound
-    let len: u32 = len as u32;
-    // Save the position at the end of the buffer, seek to the length tag, write, the
-    // length, then seek back to the end.
-    let curr_pos = serializer.buf.position();
-    serializer.buf.set_position(len_pos);
-    serializer.buf.write_u24::<BigEndian>(len)?;
-    serializer.buf.set_position(curr_pos);
+    writer: &mut W,
+) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(serialized_len(value)?);
+    serialize_into_vec(value, &mut buf)?;
+    writer.write_all(&buf)?;
 
     Ok(())
 }
 
-/// Tries to match the suffix fo the given field with a `__bound_u*` and calls the appropriate
-/// deserialization function. If no such suffix is found, this just calls `deserialize`.
-pub(crate) fn serialize_with_optional_bound<'a, T>(
-    field: &'static str,
-    value: &T,
-    serializer: &mut &'a mut TlsSerializer,
-) -> Result<<&'a mut TlsSerializer as Serializer>::Ok, <&'a mut TlsSerializer as Serializer>::Error>
-where
-    T: Serialize + ?Sized,
-{
-    if field.ends_with("__bound_u8") {
-        serialize_with_bound_u8(value, serializer)
-    } else if field.ends_with("__bound_u16") {
-        serialize_with_bound_u16(value, serializer)
-    } else if field.ends_with("__bound_u24") {
-        serialize_with_bound_u24(value, serializer)
-    } else if field.ends_with("__bound_u32") {
-        serialize_with_bound_u32(value, serializer)
-    } else if field.ends_with("__bound_u64") {
-        serialize_with_bound_u64(value, serializer)
+/// Counts the bytes that `TlsSerializer` would serialize the input to, without storing any of
+/// them, so that callers can allocate exactly as much as they need beforehand
+///
+/// Returns: `Ok(len)` on success. If the input can't be serialized, e.g., because a vector is too
+/// long for its length prefix, returns an `Error::SerdeError`.
+pub(crate) fn serialized_len<T: TlsSerialize + ?Sized>(value: &T) -> Result<usize, Error> {
+    let mut serializer = TlsSerializer {
+        buf: Sink::Counter {
+            pos: 0,
+            end: 0,
+        },
+    };
+    value.tls_serialize(&mut serializer)?;
+    match serializer.buf {
+        Sink::Counter {
+            end,
+            ..
+        } => usize::try_from(end)
+            .map_err(|_| make_custom_error("serialization is too long to fit in memory")),
+        Sink::Buffer(_) => unreachable!("serializer was made with a counter"),
+    }
+}
+
+/// The width of the length prefix of a vector, in the TLS presentation language. This is what
+/// `#[tls(bound = "...")]` says.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bound {
+    /// `<0..2^8-1>`
+    U8,
+    /// `<0..2^16-1>`
+    U16,
+    /// `<0..2^24-1>`
+    U24,
+    /// `<0..2^32-1>`
+    U32,
+    /// `<0..2^64-1>`
+    U64,
+    /// `<V>`, a variable-length integer
+    Varint,
+}
+
+impl Bound {
+    /// Returns how many bytes the length prefix takes up, or `None` if that depends on the length
+    pub(crate) fn width(self) -> Option<usize> {
+        match self {
+            Bound::U8 => Some(1),
+            Bound::U16 => Some(2),
+            Bound::U24 => Some(3),
+            Bound::U32 => Some(4),
+            Bound::U64 => Some(8),
+            Bound::Varint => None,
+        }
+    }
+
+    /// Returns the longest that a vector with this bound can be, in bytes
+    pub(crate) fn max_len(self) -> u64 {
+        match self {
+            Bound::U8 => u64::from(std::u8::MAX),
+            Bound::U16 => u64::from(std::u16::MAX),
+            Bound::U24 => (1 << 24) - 1,
+            Bound::U32 => u64::from(std::u32::MAX),
+            Bound::U64 => std::u64::MAX,
+            Bound::Varint => MAX_VARINT,
+        }
+    }
+}
+
+/// The largest number that a variable-length integer can hold, i.e., 2^30 - 1
+pub(crate) const MAX_VARINT: u64 = (1 << 30) - 1;
+
+/// Writes `value` as a variable-length integer. This is QUIC's encoding, restricted to at most 4
+/// bytes: the top two bits of the first byte say whether the integer is 1, 2, or 4 bytes long,
+/// and the rest of the bits are the integer in big-endian order. The shortest encoding is always
+/// used.
+///
+/// Returns: `Ok(())` on success. If `value > MAX_VARINT`, returns an `Error::SerdeError`.
+pub(crate) fn write_varint<W: std::io::Write>(writer: &mut W, value: u64) -> Result<(), Error> {
+    if value < (1 << 6) {
+        writer.write_u8(value as u8)?;
+    } else if value < (1 << 14) {
+        writer.write_u16::<BigEndian>(0x4000 | value as u16)?;
+    } else if value <= MAX_VARINT {
+        writer.write_u32::<BigEndian>(0x8000_0000 | value as u32)?;
     } else {
-        value.serialize(&mut **serializer)
+        return Err(make_custom_error(
+            "tried to serialize a varint-bounded object that was too long",
+        ));
     }
+
+    Ok(())
 }
 
 /// This implements some subset of the TLS wire format. I still don't have a good source on the
 /// format, but it seems as though the idea is "concat everything, and specify length in the
 /// prefix". The output of this is verified against known serializations.
 pub struct TlsSerializer {
-    buf: std::io::Cursor<Vec<u8>>,
+    buf: Sink,
 }
 
 impl TlsSerializer {
     /// Makes a new empty `TlsSerializer` object
     pub fn new() -> TlsSerializer {
+        TlsSerializer::with_buffer(Vec::new())
+    }
+
+    /// Makes a new `TlsSerializer` object that serializes onto the end of `buf`, e.g., to reuse a
+    /// buffer that something was serialized into before
+    pub fn with_buffer(buf: Vec<u8>) -> TlsSerializer {
+        let mut cursor = std::io::Cursor::new(buf);
+        // The cast is fine, since usize is at most 64 bits
+        cursor.set_position(cursor.get_ref().len() as u64);
+
         TlsSerializer {
-            buf: std::io::Cursor::new(Vec::new()),
+            buf: Sink::Buffer(cursor),
         }
     }
 
     /// Returns this objects internal buffer
     pub fn into_vec(self) -> Vec<u8> {
-        self.buf.into_inner()
+        match self.buf {
+            Sink::Buffer(cursor) => cursor.into_inner(),
+            // Counters are only made by serialized_len, which never calls this
+            Sink::Counter {
+                ..
+            } => Vec::new(),
+        }
     }
-}
-
-// Alright here's the big stuff. A Serializer needs to implement a _lot_ of methods. Fortunately
-// for us, we don't actually need that much functionality out of our serializer. So we're going to
-// leave most things unimplemented, and then implement them if we ever end up needing them.
 
-impl<'a> Serializer for &'a mut TlsSerializer {
-    type Ok = ();
-    type Error = crate::error::Error;
-
-    type SerializeSeq = Self;
-    type SerializeTuple = Self;
-    type SerializeTupleStruct = Self;
-    type SerializeTupleVariant = Self;
-    type SerializeMap = Self;
-    type SerializeStruct = Self;
-    type SerializeStructVariant = Self;
+    // This gives us a way of serializing things with Tls notation like <1..2^16-1>. Here's how it
+    // works: we're given some serializable value: &T and we want to encode it so that we can
+    // specify its length in bytes as a prefix. So we first write 0 to the serialization buffer
+    // (that's TlsSerializer.buf), then serialize the whole value out. Once it's serialized, we now
+    // know how many bytes its serialization takes, so we seek back to the prefix location, and
+    // put that in as the length. One downside of this: we have to serialize the whole thing
+    // before we can reject it as too long. But this is nice and simple and I don't think it'll
+    // backfire unless the local member is actively trying to take up a ton of memory.
+    /// Serializes `value` with a length prefix of the given width. This is what a field marked
+    /// `#[tls(bound = "...")]` is serialized with.
+    ///
+    /// Returns: `Ok(())` on success. If the serialization of `value` is longer than `bound`
+    /// allows, returns an `Error::SerdeError`.
+    pub(crate) fn write_bounded<T: TlsSerialize + ?Sized>(
+        &mut self,
+        bound: Bound,
+        value: &T,
+    ) -> Result<(), Error> {
+        let width = match bound.width() {
+            Some(width) => width,
+            None => return self.write_varint_bounded(value),
+        };
+
+        // Starting position
+        let len_pos = self.buf.position();
+        // Write a dummy zero here, then serialize everything we get, then rewrite the correct
+        // length in the position of the dummy zero.
+        self.buf.write_uint::<BigEndian>(0, width)?;
+        value.tls_serialize(self)?;
+        // End position - start position - size of length tag = length of serialized output. The
+        // cast is fine, since the width is at most 8.
+        let len: u64 = self.buf.position() - len_pos - width as u64;
+
+        if len > bound.max_len() {
+            return Err(make_custom_error(format_args!(
+                "tried to serialize a {:?}-bounded object that was too long",
+                bound
+            )));
+        }
 
-    //
-    // Implemented stuff
-    //
+        // If we haven't errored out yet, we're within the bound. Save the position at the end of
+        // the buffer, seek to the length tag, write the length, then seek back to the end.
+        let curr_pos = self.buf.position();
+        self.buf.set_position(len_pos);
+        self.buf.write_uint::<BigEndian>(len, width)?;
+        self.buf.set_position(curr_pos);
 
-    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        self.buf.write_u8(v)?;
         Ok(())
     }
 
-    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        self.buf.write_u16::<BigEndian>(v)?;
-        Ok(())
-    }
+    /// Serializes `value` with a length in bytes that's written as a variable-length integer. The
+    /// width of the length isn't known until the object is serialized, so room is left for the
+    /// widest one, 4 bytes, and once the length is filled in, the serialization is moved back
+    /// over whatever room is left. Every byte is moved at most once per varint-bounded vector it's
+    /// nested in, rather than the whole object being serialized twice at every level.
+    fn write_varint_bounded<T: TlsSerialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        const MAX_WIDTH: u64 = 4;
 
-    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.buf.write_u32::<BigEndian>(v)?;
-        Ok(())
-    }
+        let len_pos = self.buf.position();
+        self.buf.write_u32::<BigEndian>(0)?;
+        value.tls_serialize(self)?;
+        let len = self.buf.position() - len_pos - MAX_WIDTH;
 
-    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.buf.write_u64::<BigEndian>(v)?;
-        Ok(())
-    }
+        // This fails if the length is over MAX_VARINT
+        self.buf.set_position(len_pos);
+        write_varint(&mut self.buf, len)?;
+        let width = self.buf.position() - len_pos;
+        self.buf.close_gap(len_pos + width, MAX_WIDTH - width, len);
 
-    // From the spec:
-    // struct {
-    //   uint8 present;
-    //   switch (present) {
-    //     case 0: struct{};
-    //     case 1: T value;
-    //   }
-    // } optional<T>;
-
-    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        self.serialize_u8(0)
+        Ok(())
     }
+}
 
-    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Self::Error> {
-        self.serialize_u8(1)?;
-        value.serialize(self)
-    }
+/// Where a `TlsSerializer` puts what it serializes. The length prefixes of vectors are written as
+/// zeros, then overwritten once the length is known, so either one has to be able to seek back.
+enum Sink {
+    /// Keeps the bytes
+    Buffer(std::io::Cursor<Vec<u8>>),
+    /// Drops the bytes, and only keeps track of how many there would be, for `serialized_len`
+    Counter {
+        pos: u64,
+        end: u64,
+    },
+}
 
-    /// Serializes a newtype struct. This is a bit of a hack: if the name of the struct ends with
-    /// `__bound_uX` where X = 8, 16, 24, 32, or 64, then we prefix the serialized inner type with
-    /// its length in bytes. This length tag will be the width of the specified X.
-    fn serialize_newtype_struct<T>(
-        mut self,
-        name: &'static str,
-        value: &T,
-    ) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        serialize_with_optional_bound(name, value, &mut self)
-    }
-
-    /// This just forwards to `serialize_seq`
-    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        let mut seq = self.serialize_seq(Some(v.len()))?;
-        for b in v {
-            seq.serialize_element(b)?;
-        }
-        seq.end()
-    }
-
-    /// `TlsSerializer` is also a `SerializeSeq` (see impl below)
-    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        Ok(self)
-    }
-
-    /// `TlsSerializer` is also a `SerializeStruct` (see impl below)
-    fn serialize_struct(
-        self,
-        _name: &'static str,
-        _len: usize,
-    ) -> Result<Self::SerializeStruct, Self::Error> {
-        Ok(self)
-    }
-
-    /// To serialize unit types, just write the variant down as a number
-    fn serialize_unit_variant(
-        self,
-        name: &'static str,
-        variant_index: u32,
-        _variant: &'static str,
-    ) -> Result<Self::Ok, Self::Error> {
-        if name.ends_with("__enum_u8") {
-            // Make sure the variant index isn't out of our range
-            let byte = u8::try_from(variant_index).expect("enum variant index out of bounds");
-            self.serialize_u8(byte)
-        } else {
-            let err = <Error as serde::ser::Error>::custom(
-                "don't know how to serialize a non-__enum_u8 enum",
-            );
-            Err(err)
+impl Sink {
+    /// Moves the `len` bytes that come `gap` bytes after `start` back to `start`, and drops the
+    /// `gap` bytes that were left over at the end. The position goes to the new end.
+    ///
+    /// Requires: That `start + gap + len` is the end of what's been written
+    fn close_gap(&mut self, start: u64, gap: u64, len: u64) {
+        match self {
+            Sink::Buffer(cursor) => {
+                // The casts are fine, since everything here is already in memory
+                let (start, gap, len) = (start as usize, gap as usize, len as usize);
+                let buf = cursor.get_mut();
+                buf.copy_within(start + gap..start + gap + len, start);
+                buf.truncate(start + len);
+                cursor.set_position((start + len) as u64);
+            }
+            Sink::Counter {
+                pos,
+                end,
+            } => {
+                *end -= gap;
+                *pos = *end;
+            }
         }
     }
 
-    /// To serialize newtypes, we serialize it like a unit type, and then serialize the contents.
-    fn serialize_newtype_variant<T>(
-        self,
-        name: &'static str,
-        variant_index: u32,
-        variant: &'static str,
-        value: &T,
-    ) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        self.serialize_unit_variant(name, variant_index, variant)?;
-        value.serialize(self)
+    fn position(&self) -> u64 {
+        match self {
+            Sink::Buffer(cursor) => cursor.position(),
+            Sink::Counter {
+                pos,
+                ..
+            } => *pos,
+        }
     }
 
-    /// Same thing as newtype variant. Serialize a struct variant by treating it as a unit variant,
-    /// then serializing the struct it contains. `TlsSerializer` is also a `SerializeStructVariant`
-    fn serialize_struct_variant(
-        self,
-        name: &'static str,
-        variant_index: u32,
-        variant: &'static str,
-        _len: usize,
-    ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        // Serialize the variant and then return myself as a SerializeStructVariant
-        self.serialize_unit_variant(name, variant_index, variant)?;
-        Ok(self)
+    fn set_position(&mut self, new_pos: u64) {
+        match self {
+            Sink::Buffer(cursor) => cursor.set_position(new_pos),
+            Sink::Counter {
+                pos,
+                ..
+            } => *pos = new_pos,
+        }
     }
+}
 
-    /// `TlsSerializer` is also a `SerializeTuple` (see impl below)
-    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        Ok(self)
+impl std::io::Write for Sink {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::Buffer(cursor) => std::io::Write::write(cursor, bytes),
+            Sink::Counter {
+                pos,
+                end,
+            } => {
+                // The cast is fine, since usize is at most 64 bits
+                *pos += bytes.len() as u64;
+                *end = std::cmp::max(*end, *pos);
+                Ok(bytes.len())
+            }
+        }
     }
 
-    //
-    // Unimplemented stuff
-    //
-
-    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_char(self, _v: char) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_str(self, _v: &str) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_tuple_struct(
-        self,
-        _name: &'static str,
-        _len: usize,
-    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        unimplemented!()
-    }
-    fn serialize_tuple_variant(
-        self,
-        _name: &'static str,
-        _variant_index: u32,
-        _variant: &'static str,
-        _len: usize,
-    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        unimplemented!()
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
-/// Serializes slices, vecs, etc.
-impl<'a> serde::ser::SerializeSeq for &'a mut TlsSerializer {
-    type Ok = ();
-    type Error = Error;
-
-    /// Sequences (slices, vecs, etc.) get serialized in the naive way: sequentially serialize all
-    /// the elements
-    fn serialize_element<T>(&mut self, value: &T) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        value.serialize(&mut **self)
-    }
+//
+// Here are the building blocks that everything else is serialized out of
+//
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+impl TlsSerialize for u8 {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        serializer.buf.write_u8(*self)?;
         Ok(())
     }
 }
 
-/// Serializes structs. This does the same thing as `TlsSerializer as SerializeSeq`
-impl<'a> serde::ser::SerializeStruct for &'a mut TlsSerializer {
-    type Ok = ();
-    type Error = crate::error::Error;
-
-    /// Structs are serialized sequentially as well, without any delimiters between fields, since
-    /// variable-sized fields are length-prefixed
-    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        serialize_with_optional_bound(key, value, self)
+impl TlsSerialize for u16 {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        serializer.buf.write_u16::<BigEndian>(*self)?;
+        Ok(())
     }
+}
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+impl TlsSerialize for u32 {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        serializer.buf.write_u32::<BigEndian>(*self)?;
         Ok(())
     }
 }
 
-/// Serializes tuples. this does the same thing as `TlsSerializer as SerializeSeq`
-impl<'a> serde::ser::SerializeTuple for &'a mut TlsSerializer {
-    type Ok = ();
-    type Error = Error;
-
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
-    where
-        T: Serialize,
-    {
-        value.serialize(&mut **self)
-    }
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+impl TlsSerialize for u64 {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        serializer.buf.write_u64::<BigEndian>(*self)?;
         Ok(())
     }
 }
 
-//
-// More unimplemented stuff
-//
-
-impl<'a> serde::ser::SerializeTupleStruct for &'a mut TlsSerializer {
-    type Ok = ();
-    type Error = crate::error::Error;
-
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        unimplemented!();
-    }
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        unimplemented!();
+// From the spec:
+// struct {
+//   uint8 present;
+//   switch (present) {
+//     case 0: struct{};
+//     case 1: T value;
+//   }
+// } optional<T>;
+impl<T: TlsSerialize> TlsSerialize for Option<T> {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        match self {
+            None => 0u8.tls_serialize(serializer),
+            Some(value) => {
+                1u8.tls_serialize(serializer)?;
+                value.tls_serialize(serializer)
+            }
+        }
     }
 }
 
-impl<'a> serde::ser::SerializeTupleVariant for &'a mut TlsSerializer {
-    type Ok = ();
-    type Error = crate::error::Error;
-
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        unimplemented!();
-    }
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        unimplemented!();
+/// Sequences (slices, vecs, etc.) get serialized in the naive way: sequentially serialize all the
+/// elements. Their length prefixes come from the bounds of the fields they're in.
+impl<T: TlsSerialize> TlsSerialize for [T] {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        for item in self {
+            item.tls_serialize(serializer)?;
+        }
+        Ok(())
     }
 }
 
-impl<'a> serde::ser::SerializeMap for &'a mut TlsSerializer {
-    type Ok = ();
-    type Error = crate::error::Error;
-
-    fn serialize_key<T>(&mut self, _key: &T) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        unimplemented!()
-    }
-    fn serialize_value<T>(&mut self, _value: &T) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        unimplemented!()
-    }
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+impl<T: TlsSerialize, const N: usize> TlsSerialize for [T; N] {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        self[..].tls_serialize(serializer)
     }
 }
 
-impl<'a> serde::ser::SerializeStructVariant for &'a mut TlsSerializer {
-    type Ok = ();
-    type Error = crate::error::Error;
+impl<T: TlsSerialize> TlsSerialize for Vec<T> {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        self.as_slice().tls_serialize(serializer)
+    }
+}
 
-    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<Self::Ok, Self::Error>
-    where
-        T: ?Sized + Serialize,
-    {
-        serialize_with_optional_bound(key, value, self)
+impl<T: TlsSerialize + ?Sized> TlsSerialize for Box<T> {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        (**self).tls_serialize(serializer)
     }
+}
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+impl<'a, T: TlsSerialize + ?Sized> TlsSerialize for &'a T {
+    fn tls_serialize(&self, serializer: &mut TlsSerializer) -> Result<(), Error> {
+        (**self).tls_serialize(serializer)
     }
 }
 
@@ -540,23 +458,23 @@ pub(crate) mod test {
     // We're making some of these pub(crate), because tls_de will use these data structures for
     // testing deserialization
 
-    #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[derive(Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
     struct Ripp(u16);
 
-    #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
-    #[serde(rename = "Biff__bound_u16")]
+    #[derive(Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+    #[tls(bound = "u16")]
     struct Shake(Vec<u16>);
 
-    #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[derive(Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
     struct Fan {
-        #[serde(rename = "fv__bound_u8")]
+        #[tls(bound = "u8")]
         fv: Vec<u32>,
         fp: Ripp,
         fs: Shake,
     }
 
-    #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
-    #[serde(rename = "Draxx__enum_u8")]
+    #[derive(Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+    #[tls(tag = "u8")]
     enum Draxx {
         Them,
         Sklounst {
@@ -565,12 +483,12 @@ pub(crate) mod test {
         },
     }
 
-    #[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[derive(Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
     pub(crate) struct Biff {
         a: u32,
         b: [u8; 3],
         c: u8,
-        #[serde(rename = "d__bound_u16")]
+        #[tls(bound = "u16")]
         d: Vec<Fan>,
         e: u32,
         f: Draxx,
@@ -641,4 +559,86 @@ pub(crate) mod test {
 
         assert_eq!(serialized.as_slice(), expected_bytes);
     }
+
+    #[derive(Debug, Eq, PartialEq, TlsDeserialize, TlsSerialize)]
+    pub(crate) struct Zork {
+        #[tls(bound = "varint")]
+        pub(crate) za: Vec<u8>,
+        #[tls(bound = "varint")]
+        pub(crate) zb: Vec<u16>,
+    }
+
+    // Check the varint encoding against the examples in RFC 9000, and that too-large numbers are
+    // rejected
+    #[test]
+    fn varint_kat() {
+        let encode = |value| {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value).map(|_| buf)
+        };
+        assert_eq!(encode(37).unwrap(), [0x25]);
+        assert_eq!(encode(15293).unwrap(), [0x7b, 0xbd]);
+        assert_eq!(encode(494878333).unwrap(), [0x9d, 0x7f, 0x3e, 0x7d]);
+        // The shortest encoding is used, so these are the edges
+        assert_eq!(encode(0).unwrap(), [0x00]);
+        assert_eq!(encode(63).unwrap(), [0x3f]);
+        assert_eq!(encode(64).unwrap(), [0x40, 0x40]);
+        assert_eq!(encode(16383).unwrap(), [0x7f, 0xff]);
+        assert_eq!(encode(16384).unwrap(), [0x80, 0x00, 0x40, 0x00]);
+        assert_eq!(encode(MAX_VARINT).unwrap(), [0xbf, 0xff, 0xff, 0xff]);
+        assert!(encode(MAX_VARINT + 1).is_err());
+
+        // A 64-byte vector needs a 2-byte length
+        let zork = Zork {
+            za: vec![0xaa; 64],
+            zb: vec![0x0102],
+        };
+        let serialized = serialize_to_bytes(&zork).unwrap();
+        assert_eq!(&serialized[..2], &[0x40, 0x40]);
+        assert_eq!(&serialized[66..], &[0x02, 0x01, 0x02]);
+
+        // Vectors in vectors have the room left over after their lengths taken out at every level,
+        // and are counted the same way
+        #[derive(TlsSerialize)]
+        #[tls(bound = "varint")]
+        struct Zorks(Vec<Zork>);
+        let zorks = Zorks(vec![zork]);
+        let serialized_zorks = serialize_to_bytes(&zorks).unwrap();
+        assert_eq!(&serialized_zorks[..2], &[0x40, 69]);
+        assert_eq!(&serialized_zorks[2..], serialized.as_slice());
+        assert_eq!(serialized_len(&zorks).unwrap(), serialized_zorks.len());
+    }
+
+    // Check that serializing into a writer or onto the end of a buffer gives the same bytes as
+    // serialize_to_bytes, and that serialized_len counts exactly that many
+    #[test]
+    fn writer_serialization() {
+        let biff = make_biff();
+        let zork = Zork {
+            za: vec![0xaa; 64],
+            zb: vec![0x0102],
+        };
+        let zork_bytes = serialize_to_bytes(&zork).unwrap();
+        assert_eq!(serialized_len(&biff).unwrap(), BIFF_BYTES.len());
+        assert_eq!(serialized_len(&zork).unwrap(), zork_bytes.len());
+
+        let mut written = Vec::new();
+        serialize_into(&biff, &mut written).unwrap();
+        assert_eq!(written.as_slice(), BIFF_BYTES);
+
+        // Appending leaves what was already there alone
+        let mut buf = b"prefix".to_vec();
+        serialize_into_vec(&zork, &mut buf).unwrap();
+        serialize_into_vec(&biff, &mut buf).unwrap();
+        assert_eq!(&buf[..6], b"prefix");
+        assert_eq!(&buf[6..6 + zork_bytes.len()], zork_bytes.as_slice());
+        assert_eq!(&buf[6 + zork_bytes.len()..], BIFF_BYTES);
+
+        // A failed serialization leaves the buffer as it was, and can't be counted either
+        let too_long = Shake(vec![0u16; 1 << 15]);
+        let mut buf = b"prefix".to_vec();
+        assert!(serialize_into_vec(&too_long, &mut buf).is_err());
+        assert_eq!(buf, b"prefix");
+        assert!(serialized_len(&too_long).is_err());
+    }
 }
@@ -291,12 +291,11 @@ pub(crate) fn tree_leaves(num_leaves: usize) -> impl DoubleEndedIterator<Item =
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::tls_de::TlsDeserializer;
+    use crate::tls_de::{TlsDeserialize, TlsDeserializer};
 
     use quickcheck::TestResult;
     use quickcheck_macros::quickcheck;
     use rand::{Rng, SeedableRng};
-    use serde::de::Deserialize;
 
     #[test]
     fn log2_kat() {
@@ -644,18 +643,18 @@ mod test {
     //   * parent[i] is the index of the parent of node i
     //   * sibling[i] is the index of the sibling of node i
 
-    #[derive(Deserialize)]
+    #[derive(TlsDeserialize)]
     struct TreeMathTestVectors {
         tree_size: u32,
-        #[serde(rename = "root__bound_u32")]
+        #[tls(bound = "u32")]
         root: Vec<u32>,
-        #[serde(rename = "left__bound_u32")]
+        #[tls(bound = "u32")]
         left: Vec<u32>,
-        #[serde(rename = "right__bound_u32")]
+        #[tls(bound = "u32")]
         right: Vec<u32>,
-        #[serde(rename = "parent__bound_u32")]
+        #[tls(bound = "u32")]
         parent: Vec<u32>,
-        #[serde(rename = "sibling__bound_u32")]
+        #[tls(bound = "u32")]
         sibling: Vec<u32>,
     }
 
@@ -664,7 +663,7 @@ mod test {
     fn official_tree_math_kat() {
         let mut f = std::fs::File::open("test_vectors/tree_math.bin").unwrap();
         let mut deserializer = TlsDeserializer::from_reader(&mut f);
-        let test_vec = TreeMathTestVectors::deserialize(&mut deserializer).unwrap();
+        let test_vec = TreeMathTestVectors::tls_deserialize(&mut deserializer).unwrap();
 
         let size = test_vec.tree_size as usize;
         let num_root_ops = test_vec.root.len();
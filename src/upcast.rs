@@ -18,18 +18,22 @@ use crate::{
     crypto::{
         ciphersuite::CipherSuite,
         dh::{DhPrivateKey, DhPublicKey},
+        provider::{CryptoProvider, DEFAULT_PROVIDER},
         sig::{SigPublicKey, Signature, SignatureScheme},
     },
     error::Error,
     ratchet_tree,
+    tls_de::DecodeLimits,
 };
 
 /// The context necessary for a `CryptoUpcast`. This specifies the ambient ciphersuite and
-/// signature scheme.
+/// signature scheme, and the decode limits and crypto provider of the message being upcast.
 #[derive(Clone, Copy)]
 pub struct CryptoCtx {
     cs: Option<&'static CipherSuite>,
     ss: Option<&'static SignatureScheme>,
+    limits: DecodeLimits,
+    provider: &'static dyn CryptoProvider,
 }
 
 // TODO: Figure out when to check for coherence in ciphersuites
@@ -40,6 +44,8 @@ impl CryptoCtx {
         CryptoCtx {
             cs: None,
             ss: None,
+            limits: DecodeLimits::default(),
+            provider: DEFAULT_PROVIDER,
         }
     }
 
@@ -56,6 +62,36 @@ impl CryptoCtx {
         new_ctx.ss = Some(ss);
         new_ctx
     }
+
+    /// Returns a new `CryptoCtx` object with the specified decode limits. These are what the
+    /// payloads nested in a message, e.g., the encrypted `WelcomeInfo` in a `Welcome`, are
+    /// deserialized under once they're opened.
+    pub fn set_decode_limits(&self, limits: DecodeLimits) -> CryptoCtx {
+        let mut new_ctx = *self;
+        new_ctx.limits = limits;
+        new_ctx
+    }
+
+    /// Returns the decode limits of the message being upcast, which are the defaults unless
+    /// `set_decode_limits` says otherwise
+    pub(crate) fn decode_limits(&self) -> DecodeLimits {
+        self.limits
+    }
+
+    /// Returns a new `CryptoCtx` object with the specified crypto provider. This is what the
+    /// cipher suites and signature schemes in the payloads nested in a message are looked up in
+    /// once they're opened.
+    pub fn set_provider(&self, provider: &'static dyn CryptoProvider) -> CryptoCtx {
+        let mut new_ctx = *self;
+        new_ctx.provider = provider;
+        new_ctx
+    }
+
+    /// Returns the crypto provider of the message being upcast, which is the default provider
+    /// unless `set_provider` says otherwise
+    pub(crate) fn provider(&self) -> &'static dyn CryptoProvider {
+        self.provider
+    }
 }
 
 /// This trait describes how an object's "raw" parts are to be interpreted given the context of the
@@ -119,9 +155,42 @@ impl CryptoUpcast for Signature {
     }
 }
 
+impl CryptoUpcast for crate::crypto::hpke::HpkeCiphertext {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // The KEM output is an encapsulation. For DH schemes this is the same thing as a public
+        // key, but for hybrid KEMs it is not.
+        let raw =
+            enum_variant!(&self.kem_output, DhPublicKey::Raw, "can't upcast a non-raw KEM output");
+        match ctx.cs {
+            Some(cs) => {
+                self.kem_output = DhPublicKey::new_encapsulation_from_bytes(cs.dh_impl, &raw.0)?;
+                // No change to context
+                Ok(*ctx)
+            }
+            None => Err(Error::UpcastError("Need a CipherSuite to upcast an HpkeCiphertext")),
+        }
+    }
+}
+
+#[cfg(test)]
 impl CryptoUpcast for crate::crypto::ecies::EciesCiphertext {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
-        self.ephemeral_public_key.upcast_crypto_values(ctx)
+        // The ephemeral public key is really a KEM encapsulation. For DH schemes these are the
+        // same thing, but for hybrid KEMs they are not.
+        let raw = enum_variant!(
+            &self.ephemeral_public_key,
+            DhPublicKey::Raw,
+            "can't upcast a non-raw ephemeral public key"
+        );
+        match ctx.cs {
+            Some(cs) => {
+                self.ephemeral_public_key =
+                    DhPublicKey::new_encapsulation_from_bytes(cs.dh_impl, &raw.0)?;
+                // No change to context
+                Ok(*ctx)
+            }
+            None => Err(Error::UpcastError("Need a CipherSuite to upcast an EciesCiphertext")),
+        }
     }
 }
 
@@ -155,6 +224,7 @@ impl CryptoUpcast for ratchet_tree::RatchetTreeNode {
         if let ratchet_tree::RatchetTreeNode::Filled {
             ref mut public_key,
             ref mut private_key,
+            ..
         } = self
         {
             public_key.upcast_crypto_values(ctx)?;
@@ -167,7 +237,11 @@ impl CryptoUpcast for ratchet_tree::RatchetTreeNode {
 
 impl CryptoUpcast for ratchet_tree::RatchetTree {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
-        self.nodes.upcast_crypto_values(ctx)
+        for (_, node) in self.nodes.iter_filled_mut() {
+            node.upcast_crypto_values(ctx)?;
+        }
+        // No change in context
+        Ok(*ctx)
     }
 }
 
@@ -182,11 +256,27 @@ impl CryptoUpcast for Credential {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         match self {
             Credential::Basic(b) => b.upcast_crypto_values(ctx),
-            _ => unimplemented!("Cannot do X.509 upcasting yet"),
+            Credential::X509(x) => x.upcast_crypto_values(ctx),
+            Credential::Token(t) => t.upcast_crypto_values(ctx),
         }
     }
 }
 
+impl CryptoUpcast for crate::credential::TokenCredential {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        let new_ctx = ctx.set_signature_scheme(self.signature_scheme);
+        self.public_key.upcast_crypto_values(&new_ctx)
+    }
+}
+
+impl CryptoUpcast for crate::credential::X509Credential {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // The signature scheme and key come out of the leaf certificate
+        self.read_leaf()?;
+        Ok(ctx.set_signature_scheme(self.leaf().signature_scheme))
+    }
+}
+
 impl CryptoUpcast for crate::group_state::WelcomeInfo {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         self.roster.upcast_crypto_values(ctx)?;
@@ -196,13 +286,47 @@ impl CryptoUpcast for crate::group_state::WelcomeInfo {
     }
 }
 
+impl CryptoUpcast for crate::group_state::GroupInfoContent {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.roster.upcast_crypto_values(ctx)?;
+        self.tree.upcast_crypto_values(ctx)?;
+        self.external_pub.upcast_crypto_values(ctx)?;
+        // No change in context
+        Ok(*ctx)
+    }
+}
+
+impl CryptoUpcast for crate::group_state::GroupInfo {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // The signature is left raw. See the comment on GroupInfo::signature.
+        let new_ctx = ctx.set_cipher_suite(self.cipher_suite);
+        self.content.upcast_crypto_values(&new_ctx)
+    }
+}
+
 impl CryptoUpcast for crate::group_state::Welcome {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // The WelcomeInfo is deserialized under the same limits and provider once it's decrypted
+        self.decode_limits = ctx.decode_limits();
+        self.provider = ctx.provider();
         let new_ctx = ctx.set_cipher_suite(self.cipher_suite);
         self.encrypted_welcome_info.upcast_crypto_values(&new_ctx)
     }
 }
 
+impl CryptoUpcast for crate::group_state::BatchWelcome {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.decode_limits = ctx.decode_limits();
+        self.provider = ctx.provider();
+        let new_ctx = ctx.set_cipher_suite(self.cipher_suite);
+        for entry in self.secrets.iter_mut() {
+            entry.encrypted_group_secrets.upcast_crypto_values(&new_ctx)?;
+        }
+        // No change in context
+        Ok(*ctx)
+    }
+}
+
 impl CryptoUpcast for crate::handshake::UserInitKey {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         // Try to upcast the private keys if they're around
@@ -257,6 +381,28 @@ impl CryptoUpcast for crate::handshake::DirectPathMessage {
     }
 }
 
+impl CryptoUpcast for crate::handshake::UpdatePathNode {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.public_key.upcast_crypto_values(ctx)?;
+        for ct in self.encrypted_path_secret.iter_mut() {
+            ct.upcast_crypto_values(ctx)?;
+        }
+        // No change to context
+        Ok(*ctx)
+    }
+}
+
+impl CryptoUpcast for crate::handshake::UpdatePath {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.leaf_public_key.upcast_crypto_values(ctx)?;
+        for node in self.nodes.iter_mut() {
+            node.upcast_crypto_values(ctx)?;
+        }
+        // No change to context
+        Ok(*ctx)
+    }
+}
+
 impl CryptoUpcast for crate::handshake::GroupInit {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         // GroupInit is empty; this is a no-op
@@ -282,6 +428,76 @@ impl CryptoUpcast for crate::handshake::GroupRemove {
     }
 }
 
+impl CryptoUpcast for crate::handshake::GroupProposal {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        use crate::handshake::GroupProposal::*;
+        match self {
+            Add(add) => add.init_key.upcast_crypto_values(ctx),
+            // The signatures are left raw. See the comment on UpdateProposal::signature.
+            Update(update) => {
+                if let Some(change) = &mut update.new_credential {
+                    change.credential.upcast_crypto_values(ctx)?;
+                }
+                update.public_key.upcast_crypto_values(ctx)
+            }
+            Remove(_) | GroupContextExtensions(_) | ReInit(_) | PreSharedKey(_) => Ok(*ctx),
+            // The KEM output is an encapsulation. See the comment in the HpkeCiphertext impl.
+            ExternalInit(external_init) => {
+                let raw = enum_variant!(
+                    &external_init.kem_output,
+                    DhPublicKey::Raw,
+                    "can't upcast a non-raw KEM output"
+                );
+                match ctx.cs {
+                    Some(cs) => {
+                        external_init.kem_output =
+                            DhPublicKey::new_encapsulation_from_bytes(cs.dh_impl, &raw.0)?;
+                        Ok(*ctx)
+                    }
+                    None => Err(Error::UpcastError("Need a CipherSuite to upcast an ExternalInit")),
+                }
+            }
+        }
+    }
+}
+
+impl CryptoUpcast for crate::handshake::Proposal {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.0.upcast_crypto_values(ctx)
+    }
+}
+
+impl CryptoUpcast for crate::handshake::ExternalProposal {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // The signature is left raw. See the comment on ExternalProposal::signature.
+        self.content.proposal.upcast_crypto_values(ctx)
+    }
+}
+
+impl CryptoUpcast for crate::handshake::MemberProposal {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // The signature is left raw. See the comment on MemberProposal::signature.
+        self.content.proposal.upcast_crypto_values(ctx)
+    }
+}
+
+impl CryptoUpcast for crate::handshake::ProposalOrRef {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        use crate::handshake::ProposalOrRef::*;
+        match self {
+            Proposal(proposal) => proposal.upcast_crypto_values(ctx),
+            Reference(_) => Ok(*ctx),
+        }
+    }
+}
+
+impl CryptoUpcast for crate::handshake::GroupCommit {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.proposals.upcast_crypto_values(ctx)?;
+        self.path.upcast_crypto_values(ctx)
+    }
+}
+
 impl CryptoUpcast for crate::handshake::GroupOperation {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         use crate::handshake::GroupOperation::*;
@@ -290,6 +506,7 @@ impl CryptoUpcast for crate::handshake::GroupOperation {
             Add(add) => add.upcast_crypto_values(ctx),
             Update(update) => update.upcast_crypto_values(ctx),
             Remove(remove) => remove.upcast_crypto_values(ctx),
+            Commit(commit) => commit.upcast_crypto_values(ctx),
         }
     }
 }
@@ -297,12 +514,29 @@ impl CryptoUpcast for crate::handshake::GroupOperation {
 impl CryptoUpcast for crate::handshake::Handshake {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         self.operation.upcast_crypto_values(ctx)?;
-        self.signature.upcast_crypto_values(ctx)?;
+        // The signer's signature scheme is only known once they're looked up in the roster, so
+        // without one in the context, the signature is left raw until the handshake is processed
+        if ctx.ss.is_some() {
+            self.signature.upcast_crypto_values(ctx)?;
+        }
         // No change to context
         Ok(*ctx)
     }
 }
 
+impl CryptoUpcast for crate::framing::PublicMessage {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        self.handshake.upcast_crypto_values(ctx)
+    }
+}
+
+impl CryptoUpcast for crate::framing::PrivateMessage {
+    fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
+        // No-op. The handshake inside is upcast once it's decrypted.
+        Ok(*ctx)
+    }
+}
+
 impl CryptoUpcast for crate::application::ApplicationMessage {
     fn upcast_crypto_values(&mut self, ctx: &CryptoCtx) -> Result<CryptoCtx, Error> {
         // No-op
@@ -2,8 +2,8 @@ use crate::{
     crypto::{
         ciphersuite::CipherSuite,
         dh::{DhPrivateKey, DhPublicKey},
-        hkdf,
         hmac::HmacKey,
+        kdf,
     },
     error::Error,
     ratchet_tree::{NodeSecret, PathSecret},
@@ -47,19 +47,17 @@ pub(crate) fn derive_node_values(
     // PathSecrets are secretly HMAC keys
     let prk: HmacKey = path_secret.into();
 
-    // node_secret[n] = HKDF-Expand-Label(path_secret[n], "node", "", Hash.Length)
-    let mut node_secret_buf = vec![0u8; digest_size];
-    hkdf::expand_label(cs.hash_impl, &prk, b"node", b"", &mut node_secret_buf);
+    // node_secret[n] = DeriveSecret(path_secret[n], "node")
+    let node_secret = kdf::derive_secret(cs.hash_impl, &prk, b"node").0;
 
-    // path_secret[n] = HKDF-Expand-Label(path_secret[n-1], "path", "", Hash.Length)
-    let mut path_secret_buf = vec![0u8; digest_size];
-    hkdf::expand_label(cs.hash_impl, &prk, b"path", b"", &mut path_secret_buf);
+    // path_secret[n] = DeriveSecret(path_secret[n-1], "path")
+    let new_path_secret = kdf::derive_secret(cs.hash_impl, &prk, b"path");
 
     // Derive the private and public keys and assign them to the node
-    let (node_public_key, node_private_key) = cs.derive_key_pair(&node_secret_buf)?;
+    let (node_public_key, node_private_key) = cs.derive_key_pair(node_secret.as_bytes())?;
 
     // Wrap the new values and return them
-    let node_secret = NodeSecret(node_secret_buf);
-    let new_path_secret = PathSecret::new_from_bytes(&path_secret_buf);
+    let node_secret = NodeSecret(node_secret);
+    let new_path_secret = PathSecret::from(new_path_secret);
     Ok((node_public_key, node_private_key, node_secret, new_path_secret))
 }
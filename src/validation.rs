@@ -0,0 +1,1479 @@
+//! Defines the rules that incoming commits are checked against, and `CommitRule`, which names
+//! them. When a commit breaks one of these rules, processing it fails with an
+//! `Error::InvalidCommit` that says which rule was broken, so that an application can tell, e.g.,
+//! a stale commit from a forged one.
+
+use crate::{
+    credential::{Credential, CredentialContext, CredentialType, Roster},
+    crypto::{hash::Digest, sig::SigPublicKey},
+    error::Error,
+    extensions::{self, Extension, ExternalSenders, Lifetime, RequiredCapabilities},
+    group_state::GroupState,
+    handshake::{
+        AddProposal, Capabilities, GroupProposal, ProtocolVersion, UpdatePath, UpdateProposal,
+        UserInitKey,
+    },
+    psk::{PskKind, ResumptionPskUsage},
+    ratchet_tree::{RatchetTree, RatchetTreeNode},
+    revocation::RevocationStatus,
+    tls_de::DecodeLimits,
+    tree_math,
+};
+
+use subtle::ConstantTimeEq;
+
+/// The rules that every incoming commit has to follow, in the order they're checked
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CommitRule {
+    /// The commit was made in the group's current epoch. If it wasn't, the error is an
+    /// `Error::StaleEpoch` or an `Error::FutureEpoch`.
+    EpochMatches,
+    /// The committer is a member of the group. The maker of an external commit only becomes a
+    /// member through the commit, so for them this is checked afterwards.
+    SenderIsMember,
+    /// Every proposal makes sense on its own: proposals by reference are known, Updates and
+    /// Removes refer to current members, this member supports any extensions being set, new
+    /// members' `UserInitKey`s pass every `KeyPackageRule`, and every updating member's credential
+    /// is acceptable, as `check_credential` says, as is every credential change
+    ProposalsWellFormed,
+    /// The proposals can go in a commit together, e.g., nobody is removed twice, a ReInit is
+    /// alone, and nobody is added who is already in the group
+    ProposalsDontConflict,
+    /// The commit's path covers the committer's entire direct path
+    PathPresent,
+    /// The parent hash that the committer gives for their leaf is the one that their path gives
+    /// it
+    ParentHashVerifies,
+    /// The commit's signature verifies under the committer's credential
+    SignatureVerifies,
+    /// The commit's confirmation MAC verifies under the new epoch's confirmation key
+    ConfirmationVerifies,
+}
+
+/// The checks that the `UserInitKey` of everyone being added has to pass, whether by an Add
+/// proposal, a `GroupAdd`, or an external commit, in the order they're checked. When one fails,
+/// the error is an `Error::InvalidKeyPackage` that says which.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyPackageRule {
+    /// The `UserInitKey` is well formed: it has as many versions as cipher suites and init keys,
+    /// its cipher suites are distinct, and neither it nor the leaf extensions it's added with
+    /// repeat an extension type
+    WellFormed,
+    /// Its signature verifies under its credential
+    SignatureVerifies,
+    /// It has an init key for the group's cipher suite under the group's version, and the
+    /// capabilities it's added with include both
+    VersionAndCipherSuite,
+    /// The lifetimes it was signed with and added with, if any, cover the current time
+    LifetimeCurrent,
+    /// The capabilities it's added with satisfy the group's required capabilities and include the
+    /// credential types of its own credential and of everyone staying in the group, and everyone
+    /// staying supports its credential type, so that everyone can authenticate everyone else
+    CapabilitiesSufficient,
+    /// Its credential's signature scheme is acceptable to the group, and the credential is
+    /// acceptable to this member, as `check_credential` says
+    CredentialAcceptable,
+    /// Neither its init key nor its signature key is already used by someone staying in the group
+    KeysUnique,
+}
+
+/// Tags the error of a failed check with the rule being checked. `Error::IAmRemoved` isn't a
+/// rule failure, and errors that are already tagged keep their tag, so both pass through as is.
+pub(crate) fn check<T>(rule: CommitRule, result: Result<T, Error>) -> Result<T, Error> {
+    result.map_err(|err| match err {
+        Error::IAmRemoved | Error::InvalidCommit(..) => err,
+        err => Error::InvalidCommit(rule, Box::new(err)),
+    })
+}
+
+/// Tags the error of a failed check of a `UserInitKey` with the rule being checked
+fn check_key_package_rule<T>(rule: KeyPackageRule, result: Result<T, Error>) -> Result<T, Error> {
+    result.map_err(|err| Error::InvalidKeyPackage(rule, Box::new(err)))
+}
+
+/// Checks that a handshake or message made in `prior_epoch` can be processed by a group in `epoch`
+///
+/// Returns: `Ok(())` if the epochs are the same. Otherwise, returns an `Error::StaleEpoch` or an
+/// `Error::FutureEpoch`, depending on which one is behind.
+pub(crate) fn check_epoch(epoch: u32, prior_epoch: u32) -> Result<(), Error> {
+    if prior_epoch < epoch {
+        Err(Error::StaleEpoch(epoch, prior_epoch))
+    } else if prior_epoch > epoch {
+        Err(Error::FutureEpoch(epoch, prior_epoch))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that a message under protocol version `version` can be processed by a group under
+/// `group_version`
+///
+/// Returns: `Ok(())` if the versions are the same. Otherwise, returns an
+/// `Error::ProtocolVersionMismatch`.
+pub(crate) fn check_version(
+    group_version: ProtocolVersion,
+    version: ProtocolVersion,
+) -> Result<(), Error> {
+    if version == group_version {
+        Ok(())
+    } else {
+        Err(Error::ProtocolVersionMismatch(group_version, version))
+    }
+}
+
+/// Returns the credential of the member at `signer_index` in the given roster
+///
+/// Returns: `Ok(credential)` on success. If the index is out of bounds or the roster entry is
+/// empty, returns an `Error::ValidationError`.
+pub(crate) fn signer_credential(roster: &Roster, signer_index: u32) -> Result<&Credential, Error> {
+    roster
+        .0
+        .get(signer_index as usize)
+        .ok_or(Error::ValidationError("Handshake's signer index is out of bounds"))?
+        .as_ref()
+        .ok_or(Error::ValidationError("Handshake's signer credential is empty"))
+}
+
+/// Checks the given proposals against `CommitRule::ProposalsWellFormed` and then
+/// `CommitRule::ProposalsDontConflict`, where the committer is at `committer_index`, or is `None`
+/// if the committer isn't in the group yet
+///
+/// Returns: `Ok(())` if the proposals are valid together. Otherwise, returns an
+/// `Error::InvalidCommit`.
+pub(crate) fn validate_proposals(
+    state: &GroupState,
+    proposals: &[GroupProposal],
+    committer_index: Option<u32>,
+) -> Result<(), Error> {
+    check(
+        CommitRule::ProposalsWellFormed,
+        check_proposals_well_formed(state, proposals, committer_index),
+    )?;
+    check(
+        CommitRule::ProposalsDontConflict,
+        check_proposals_dont_conflict(state, proposals, committer_index),
+    )
+}
+
+/// Checks each proposal on its own. Every Update and Remove has to refer to a non-empty roster
+/// entry, every Update has to replace the sender's leaf key, credential, or both, the extensions
+/// of GroupContextExtensions and ReInit proposals have to be acceptable to this member, every Add
+/// has to pass `check_key_package` against the extensions that the group will have after the
+/// commit, resumption PSKs in PSK proposals have to be for application use, and the credentials
+/// of everyone updating have to pass `check_credential` and be of a type that everyone staying in
+/// the group supports. If there's no `committer_index`, this is an external commit, and its Add is
+/// the committer joining.
+fn check_proposals_well_formed(
+    state: &GroupState,
+    proposals: &[GroupProposal],
+    committer_index: Option<u32>,
+) -> Result<(), Error> {
+    // New members have to satisfy the extensions as of the end of the commit, since
+    // GroupContextExtensions are applied first
+    let extensions_after = proposals
+        .iter()
+        .find_map(|proposal| match proposal {
+            GroupProposal::GroupContextExtensions(gce) => Some(gce.extensions.as_slice()),
+            _ => None,
+        })
+        .unwrap_or(&state.extensions);
+    // Whoever is removed by the commit can be added back with the same signature key
+    let removed_indices: Vec<u32> = proposals
+        .iter()
+        .filter_map(|proposal| match proposal {
+            GroupProposal::Remove(remove) => Some(remove.removed_roster_index),
+            _ => None,
+        })
+        .collect();
+
+    for proposal in proposals {
+        let idx = match proposal {
+            GroupProposal::Update(update) => update.sender_index,
+            GroupProposal::Remove(remove) => remove.removed_roster_index,
+            GroupProposal::GroupContextExtensions(gce) => {
+                check_group_context_extensions(state, &gce.extensions)?;
+                continue;
+            }
+            // The successor group starts out with these extensions, so every member has to
+            // support them
+            GroupProposal::ReInit(reinit) => {
+                check_group_context_extensions(state, &reinit.extensions)?;
+                continue;
+            }
+            GroupProposal::PreSharedKey(psk) => {
+                if let PskKind::Resumption(id) = &psk.psk.kind {
+                    if id.usage != ResumptionPskUsage::Application {
+                        return Err(Error::ValidationError(
+                            "PSK proposal's resumption PSK isn't for application use",
+                        ));
+                    }
+                }
+                continue;
+            }
+            GroupProposal::Add(add) => {
+                let context = match committer_index {
+                    Some(_) => CredentialContext::Add,
+                    None => CredentialContext::ExternalJoin,
+                };
+                check_key_package(
+                    state,
+                    &add.init_key,
+                    &add.capabilities,
+                    &add.leaf_extensions.0,
+                    extensions_after,
+                    &removed_indices,
+                    context,
+                )?;
+                continue;
+            }
+            GroupProposal::ExternalInit(_) => continue,
+        };
+
+        let credential = state
+            .roster
+            .0
+            .get(idx as usize)
+            .and_then(Option::as_ref)
+            .ok_or(Error::ValidationError("Proposal refers to an empty roster entry"))?;
+        if let GroupProposal::Update(update) = proposal {
+            if update.public_key.is_none() && update.new_credential.is_none() {
+                return Err(Error::ValidationError("Update proposal doesn't replace anything"));
+            }
+            check_credential(state, credential, CredentialContext::Update)?;
+            if let Some(change) = &update.new_credential {
+                check_credential_change(state, credential, &change.credential)?;
+                check_members_support_credential_type(
+                    state,
+                    change.credential.credential_type(),
+                    &removed_indices,
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that `init_key` can be added to the group with the given capabilities and leaf
+/// extensions, where the group will have `group_extensions` once it's added, and the members at
+/// `removed_indices` are on their way out. This goes through every `KeyPackageRule` in order.
+///
+/// Returns: `Ok(())` if `init_key` can be added. Otherwise, returns an `Error::InvalidKeyPackage`
+/// that says which rule was broken.
+pub(crate) fn check_key_package(
+    state: &GroupState,
+    init_key: &UserInitKey,
+    capabilities: &Capabilities,
+    leaf_extensions: &[Extension],
+    group_extensions: &[Extension],
+    removed_indices: &[u32],
+    context: CredentialContext,
+) -> Result<(), Error> {
+    check_key_package_rule(KeyPackageRule::WellFormed, {
+        init_key.validate().and_then(|()| {
+            if extensions::has_duplicate_types(leaf_extensions) {
+                Err(Error::ValidationError("Add has two leaf extensions of the same type"))
+            } else {
+                Ok(())
+            }
+        })
+    })?;
+    check_key_package_rule(KeyPackageRule::SignatureVerifies, init_key.verify_sig())?;
+    check_key_package_rule(
+        KeyPackageRule::VersionAndCipherSuite,
+        check_key_package_version(state, init_key, capabilities),
+    )?;
+    check_key_package_rule(
+        KeyPackageRule::LifetimeCurrent,
+        check_key_package_lifetimes(state, init_key, leaf_extensions),
+    )?;
+    check_key_package_rule(KeyPackageRule::CapabilitiesSufficient, {
+        check_joiner_capabilities(group_extensions, capabilities, state.decode_limits).and_then(
+            |()| {
+                check_joiner_credential_types(
+                    state,
+                    &init_key.credential,
+                    capabilities,
+                    removed_indices,
+                )
+            },
+        )
+    })?;
+    check_key_package_rule(KeyPackageRule::CredentialAcceptable, {
+        state
+            .check_signature_scheme(&init_key.credential)
+            .and_then(|()| check_credential(state, &init_key.credential, context))
+    })?;
+    check_key_package_rule(
+        KeyPackageRule::KeysUnique,
+        check_key_package_keys(state, init_key, removed_indices),
+    )
+}
+
+/// Checks `KeyPackageRule::VersionAndCipherSuite`. The joiner's `UserInitKey` has to be for the
+/// group's version, whatever their capabilities say.
+fn check_key_package_version(
+    state: &GroupState,
+    init_key: &UserInitKey,
+    capabilities: &Capabilities,
+) -> Result<(), Error> {
+    match init_key.get_supported_version(state.cs)? {
+        Some(version) => check_version(state.protocol_version, version)?,
+        None => {
+            return Err(Error::ValidationError(
+                "UserInitKey has no init key for the group's cipher suite",
+            ))
+        }
+    }
+    if !capabilities.supports_version(state.protocol_version)
+        || !capabilities.supports_cipher_suite(state.cs)
+    {
+        return Err(Error::ValidationError(
+            "Add's capabilities don't include the group's version and cipher suite",
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the credential types half of `KeyPackageRule::CapabilitiesSufficient`, where the
+/// members at `removed_indices` are on their way out
+fn check_joiner_credential_types(
+    state: &GroupState,
+    credential: &Credential,
+    capabilities: &Capabilities,
+    removed_indices: &[u32],
+) -> Result<(), Error> {
+    if !capabilities.supports_credential(credential.credential_type()) {
+        return Err(Error::ValidationError(
+            "Add's capabilities don't include its own credential type",
+        ));
+    }
+    let supports_members = state
+        .members()
+        .filter(|member| !removed_indices.contains(&member.roster_index()))
+        .all(|member| capabilities.supports_credential(member.credential().credential_type()));
+    if !supports_members {
+        return Err(Error::ValidationError(
+            "Add's capabilities don't include the credential type of a member",
+        ));
+    }
+    check_members_support_credential_type(state, credential.credential_type(), removed_indices)
+}
+
+/// Checks that everyone staying in the group, i.e., everyone except the members at
+/// `removed_indices`, supports `credential_type`, as far as their capabilities say. Members whose
+/// capabilities aren't known are assumed to support only their own credential's type.
+///
+/// Returns: `Ok(())` if they all do. Otherwise, returns an `Error::ValidationError`.
+fn check_members_support_credential_type(
+    state: &GroupState,
+    credential_type: CredentialType,
+    removed_indices: &[u32],
+) -> Result<(), Error> {
+    let all_support = state
+        .members()
+        .filter(|member| !removed_indices.contains(&member.roster_index()))
+        .all(|member| member.capabilities().supports_credential(credential_type));
+    if all_support {
+        Ok(())
+    } else {
+        Err(Error::ValidationError("Credential type isn't supported by every member"))
+    }
+}
+
+/// Checks `KeyPackageRule::LifetimeCurrent`
+fn check_key_package_lifetimes(
+    state: &GroupState,
+    init_key: &UserInitKey,
+    leaf_extensions: &[Extension],
+) -> Result<(), Error> {
+    let now = state.time_source.now();
+    if let Some(lifetime) = Lifetime::from_extensions(leaf_extensions)? {
+        if !lifetime.covers(now) {
+            return Err(Error::ValidationError(
+                "Add's key lifetime doesn't cover the current time",
+            ));
+        }
+    }
+    match init_key.lifetime()? {
+        Some(lifetime) if !lifetime.covers(now) => {
+            Err(Error::ValidationError("UserInitKey's lifetime doesn't cover the current time"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks `KeyPackageRule::KeysUnique`. Members are told apart by their signature keys, and an
+/// init key that's already in the tree would have the joiner share secrets with whoever has it.
+fn check_key_package_keys(
+    state: &GroupState,
+    init_key: &UserInitKey,
+    removed_indices: &[u32],
+) -> Result<(), Error> {
+    let signature_key = init_key.credential.get_public_key();
+    let signature_key_taken = state
+        .roster
+        .0
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !removed_indices.contains(&(*i as u32)))
+        .filter_map(|(_, cred)| cred.as_ref())
+        .any(|cred| cred.get_public_key() == signature_key);
+    if signature_key_taken {
+        return Err(Error::ValidationError("UserInitKey's signature key is a member's"));
+    }
+
+    // This was checked to be there by check_key_package_version
+    let dh_key = init_key.get_public_key(state.cs)?.ok_or(Error::ValidationError(
+        "UserInitKey has no init key for the group's cipher suite",
+    ))?;
+    let dh_key_taken = (0..state.tree.size())
+        .filter_map(|idx| state.tree.get(idx))
+        .filter_map(RatchetTreeNode::get_public_key)
+        .any(|public_key| bool::from(public_key.as_bytes().ct_eq(dh_key.as_bytes())));
+    if dh_key_taken {
+        return Err(Error::ValidationError("UserInitKey's init key is already in the tree"));
+    }
+
+    Ok(())
+}
+
+/// Checks that `credential`, which was just encountered in `context`, is acceptable to this member.
+/// An X.509 credential's chain has to end at one of the roots in the group's `TrustStore`, or have
+/// a leaf whose key is pinned there, be valid at the current time, and not have a leaf certificate
+/// that the group's `RevocationChecker` or revocation cache says is revoked, a token credential's
+/// token has to pass the group's `TokenVerifier` and not have expired, the group's
+/// `AuthenticationService` has to vouch for the credential, i.e., not have revoked it and know its
+/// key for its identity if it knows any, and then the application's `CredentialValidator`, if it
+/// set one, has to accept the credential.
+///
+/// Returns: `Ok(())` if the credential is acceptable. If it's revoked, or the authentication
+/// service or the validator rejects it, returns an `Error::CredentialRejected`. Otherwise, returns
+/// whatever error the chain or token failed validation with.
+pub(crate) fn check_credential(
+    state: &GroupState,
+    credential: &Credential,
+    context: CredentialContext,
+) -> Result<(), Error> {
+    match credential {
+        Credential::X509(x509) => {
+            x509.validate(&state.trust_store, state.time_source.now())?;
+            if state.revocation_status(x509.chain()) == RevocationStatus::Revoked {
+                return Err(Error::CredentialRejected(context, "Certificate has been revoked"));
+            }
+        }
+        Credential::Token(token) => match state.token_verifier {
+            Some(ref verifier) => token.validate(verifier.as_ref(), state.time_source.now())?,
+            None => return Err(Error::TokenError("Group has no TokenVerifier for tokens")),
+        },
+        Credential::Basic(_) => (),
+    }
+
+    let service = &state.authentication_service;
+    if service.is_revoked(credential) {
+        return Err(Error::CredentialRejected(context, "Credential has been revoked"));
+    }
+    if let Some(keys) = service.expected_signature_keys(credential.get_identity()) {
+        if !keys.contains(credential.get_public_key()) {
+            return Err(Error::CredentialRejected(
+                context,
+                "Credential's key isn't one the authentication service knows for its identity",
+            ));
+        }
+    }
+
+    match state.credential_validator {
+        Some(ref validator) => validator
+            .validate(credential, context)
+            .map_err(|reason| Error::CredentialRejected(context, reason)),
+        None => Ok(()),
+    }
+}
+
+/// Checks that a member whose credential is `old_credential` can replace it with
+/// `new_credential`. The new credential has to pass `check_credential` as an Update, and then the
+/// application's `CredentialValidator`, if it set one, has to accept the change.
+///
+/// Returns: `Ok(())` if the change is acceptable. If the validator rejects it, returns an
+/// `Error::CredentialRejected`. Otherwise, returns whatever error `check_credential` returns.
+fn check_credential_change(
+    state: &GroupState,
+    old_credential: &Credential,
+    new_credential: &Credential,
+) -> Result<(), Error> {
+    check_credential(state, new_credential, CredentialContext::Update)?;
+    match state.credential_validator {
+        Some(ref validator) => validator
+            .validate_change(old_credential, new_credential)
+            .map_err(|reason| Error::CredentialRejected(CredentialContext::Update, reason)),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the proposals can go in a commit together. Every Update and Remove has to refer to
+/// a distinct member other than the committer, there's at most one GroupContextExtensions, a
+/// ReInit is alone, no PSK is proposed twice, nobody can be added, or change their credential to
+/// one, that has the key of someone else staying in the group or being added, and everyone being
+/// added has to support the credential types of everyone else being added and of the new
+/// credentials of everyone updating.
+fn check_proposals_dont_conflict(
+    state: &GroupState,
+    proposals: &[GroupProposal],
+    committer_index: Option<u32>,
+) -> Result<(), Error> {
+    let mut removed_indices = Vec::new();
+    let mut touched_indices = Vec::new();
+    let mut num_extension_changes = 0;
+    let mut psk_kinds = Vec::new();
+    for proposal in proposals {
+        let idx = match proposal {
+            GroupProposal::Update(update) => update.sender_index,
+            GroupProposal::Remove(remove) => {
+                removed_indices.push(remove.removed_roster_index);
+                remove.removed_roster_index
+            }
+            GroupProposal::GroupContextExtensions(_) => {
+                num_extension_changes += 1;
+                continue;
+            }
+            GroupProposal::ReInit(_) => {
+                if proposals.len() != 1 {
+                    return Err(Error::ValidationError(
+                        "ReInit has to be the only proposal in its commit",
+                    ));
+                }
+                continue;
+            }
+            GroupProposal::PreSharedKey(psk) => {
+                if psk_kinds.contains(&&psk.psk.kind) {
+                    return Err(Error::ValidationError(
+                        "Commit has multiple PSK proposals for the same PSK",
+                    ));
+                }
+                psk_kinds.push(&psk.psk.kind);
+                continue;
+            }
+            GroupProposal::Add(_) | GroupProposal::ExternalInit(_) => continue,
+        };
+
+        // The committer's path already updates their leaf, and committers can't remove
+        // themselves
+        if Some(idx) == committer_index {
+            return Err(Error::ValidationError("Commit updates or removes its own committer"));
+        }
+        if touched_indices.contains(&idx) {
+            return Err(Error::ValidationError(
+                "Commit has multiple Updates or Removes for the same member",
+            ));
+        }
+        touched_indices.push(idx);
+    }
+    if num_extension_changes > 1 {
+        return Err(Error::ValidationError("Commit has multiple GroupContextExtensions proposals"));
+    }
+
+    // Tell members apart by their signature public keys. Start with everyone who isn't being
+    // removed, and make sure no Add or new credential collides with any of them or with another
+    // Add or new credential. Members may keep their key when they change credentials.
+    let mut signature_keys: Vec<&SigPublicKey> = state
+        .roster
+        .0
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !removed_indices.contains(&(*i as u32)))
+        .filter_map(|(_, cred)| cred.as_ref().map(Credential::get_public_key))
+        .collect();
+    for proposal in proposals {
+        if let GroupProposal::Add(add) = proposal {
+            let signature_key = add.init_key.credential.get_public_key();
+            if signature_keys.contains(&signature_key) {
+                return Err(Error::ValidationError(
+                    "Commit adds someone who is already in the group",
+                ));
+            }
+            signature_keys.push(signature_key);
+        }
+        if let GroupProposal::Update(UpdateProposal {
+            sender_index,
+            new_credential: Some(change),
+            ..
+        }) = proposal
+        {
+            let signature_key = change.credential.get_public_key();
+            let old_signature_key = state
+                .roster
+                .0
+                .get(*sender_index as usize)
+                .and_then(Option::as_ref)
+                .map(Credential::get_public_key);
+            if Some(signature_key) != old_signature_key && signature_keys.contains(&signature_key) {
+                return Err(Error::ValidationError(
+                    "Commit changes a credential to one with someone else's key",
+                ));
+            }
+            signature_keys.push(signature_key);
+        }
+    }
+
+    // Members staying in the group were checked against these by check_proposals_well_formed
+    let new_credential_types: Vec<CredentialType> = proposals
+        .iter()
+        .filter_map(|proposal| match proposal {
+            GroupProposal::Add(add) => Some(add.init_key.credential.credential_type()),
+            GroupProposal::Update(UpdateProposal {
+                new_credential: Some(change),
+                ..
+            }) => Some(change.credential.credential_type()),
+            _ => None,
+        })
+        .collect();
+    for proposal in proposals {
+        if let GroupProposal::Add(add) = proposal {
+            let supports_all =
+                new_credential_types.iter().all(|t| add.capabilities.supports_credential(*t));
+            if !supports_all {
+                return Err(Error::ValidationError(
+                    "Commit adds someone who doesn't support a credential type it introduces",
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the given extensions can be the group's new GroupContext extensions. This member
+/// has to support every one of their types, and no type can appear twice. Every member checks
+/// this when processing the commit, so a commit is only accepted by the whole group if every
+/// member supports the new extensions.
+///
+/// Returns: `Ok(())` if the extensions are acceptable. Otherwise, returns an
+/// `Error::ValidationError`.
+pub(crate) fn check_group_context_extensions(
+    state: &GroupState,
+    new_extensions: &[Extension],
+) -> Result<(), Error> {
+    if extensions::has_duplicate_types(new_extensions) {
+        return Err(Error::ValidationError("GroupContext extensions have a duplicate type"));
+    }
+    let all_supported =
+        new_extensions.iter().all(|ext| state.supported_extensions.contains(&ext.extension_type));
+    if !all_supported {
+        return Err(Error::ValidationError(
+            "GroupContext extensions have a type this member doesn't support",
+        ));
+    }
+    // The external senders are read out of the extensions whenever one sends a proposal, so make
+    // sure that's going to work
+    ExternalSenders::from_extensions(new_extensions, state.decode_limits, state.provider)?;
+    if let Some(required) =
+        RequiredCapabilities::from_extensions(new_extensions, state.decode_limits)?
+    {
+        if !required.is_satisfied_by(&state.capabilities()) {
+            return Err(Error::ValidationError(
+                "This member doesn't have the group's new required capabilities",
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that someone with the given capabilities can be added to a group with the given
+/// GroupContext extensions, i.e., that they satisfy its required_capabilities extension, if any.
+/// The extension is parsed under `limits`.
+///
+/// Returns: `Ok(())` if they can be added. Otherwise, returns an `Error::ValidationError`.
+pub(crate) fn check_joiner_capabilities(
+    extensions: &[Extension],
+    capabilities: &Capabilities,
+    limits: DecodeLimits,
+) -> Result<(), Error> {
+    match RequiredCapabilities::from_extensions(extensions, limits)? {
+        Some(required) if !required.is_satisfied_by(capabilities) => {
+            Err(Error::ValidationError("New member doesn't have the group's required capabilities"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the proposals of an external commit have exactly one ExternalInit and exactly one
+/// Add, which adds the committer. An external commit can't have any Updates,
+/// GroupContextExtensions, or ReInits, but it can have Removes, e.g., of the committer's old leaf
+/// if they're rejoining, and PSKs.
+///
+/// Returns: `Ok(add)` on success, where `add` is the commit's only Add. Otherwise, returns an
+/// `Error::InvalidCommit`.
+pub(crate) fn check_external_commit_proposals(
+    proposals: &[GroupProposal],
+) -> Result<&AddProposal, Error> {
+    let mut num_external_inits = 0;
+    let mut adds = Vec::new();
+    for proposal in proposals {
+        let err = match proposal {
+            GroupProposal::ExternalInit(_) => {
+                num_external_inits += 1;
+                continue;
+            }
+            GroupProposal::Add(add) => {
+                adds.push(add);
+                continue;
+            }
+            GroupProposal::Remove(_) | GroupProposal::PreSharedKey(_) => continue,
+            GroupProposal::Update(_) => "External commit contains an Update",
+            GroupProposal::GroupContextExtensions(_) => {
+                "External commit contains a GroupContextExtensions proposal"
+            }
+            GroupProposal::ReInit(_) => "External commit contains a ReInit",
+        };
+        return check(CommitRule::ProposalsDontConflict, Err(Error::ValidationError(err)));
+    }
+    if num_external_inits != 1 || adds.len() != 1 {
+        return check(
+            CommitRule::ProposalsDontConflict,
+            Err(Error::ValidationError(
+                "External commit doesn't have exactly one ExternalInit and one Add",
+            )),
+        );
+    }
+
+    Ok(adds[0])
+}
+
+/// Checks that the given path has a node for the committer's leaf at `committer_tree_idx` and for
+/// every node in its direct path, in the tree that the commit's proposals made
+///
+/// Returns: `Ok(())` if the path is complete. Otherwise, returns an `Error::InvalidCommit`.
+pub(crate) fn check_path(
+    tree: &RatchetTree,
+    committer_tree_idx: usize,
+    path: &UpdatePath,
+) -> Result<(), Error> {
+    if committer_tree_idx >= tree.size() {
+        return check(
+            CommitRule::PathPresent,
+            Err(Error::ValidationError("Commit's path starts outside of the tree")),
+        );
+    }
+    let num_leaves = tree_math::num_leaves_in_tree(tree.size());
+    let direct_path_len = tree_math::node_direct_path(committer_tree_idx, num_leaves).count();
+    if path.nodes.len() == direct_path_len {
+        Ok(())
+    } else {
+        check(
+            CommitRule::PathPresent,
+            Err(Error::ValidationError("Commit's path doesn't cover the committer's direct path")),
+        )
+    }
+}
+
+/// Checks that the parent hash which a commit gives for the committer's leaf, `claimed`, is the
+/// one that the leaf at `committer_tree_idx` got from the commit's path
+///
+/// Returns: `Ok(())` if they match. Otherwise, returns an `Error::InvalidCommit`.
+pub(crate) fn check_parent_hash(
+    tree: &RatchetTree,
+    committer_tree_idx: usize,
+    claimed: &Digest,
+) -> Result<(), Error> {
+    let parent_hash = tree.get(committer_tree_idx).and_then(RatchetTreeNode::parent_hash);
+    match parent_hash {
+        Some(parent_hash) if bool::from(parent_hash.ct_eq(claimed)) => Ok(()),
+        _ => check(
+            CommitRule::ParentHashVerifies,
+            Err(Error::ValidationError("Commit's parent hash doesn't match its path")),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        authentication::AuthenticationService,
+        credential::{
+            BasicCredential, CredentialType, CredentialValidator, Identity, X509Credential,
+        },
+        crypto::{
+            ciphersuite::X25519_SHA256_CHACHA20POLY1305,
+            hmac::{self, HmacKey},
+        },
+        extensions::{ExtensionType, EXTERNAL_SENDERS_TYPE, REQUIRED_CAPABILITIES_TYPE},
+        handshake::{
+            GroupOperation, Handshake, Proposal, ProposalType, UserInitKey, MLS_DUMMY_VERSION,
+        },
+        member::RosterChange,
+        ratchet_tree::PathSecret,
+        test_utils, x509,
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    use std::sync::Arc;
+
+    // Returns the rule that the given result says was broken, if any
+    fn broken_rule<T>(result: Result<T, Error>) -> Option<CommitRule> {
+        match result {
+            Err(Error::InvalidCommit(rule, _)) => Some(rule),
+            _ => None,
+        }
+    }
+
+    // Check that every kind of bad commit is rejected with the rule it breaks
+    #[quickcheck]
+    fn commit_rule_reporting(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        // Make a starting group of at least 3 people. A commits, B processes, and C makes an
+        // Update proposal that only A caches.
+        let (mut group_state_a, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let index_a = group_state_a.roster_index.unwrap() as usize;
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a],
+            &mut rng,
+        );
+        let index_c = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a, index_b as usize],
+            &mut rng,
+        );
+        let group_state_b = test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+        let mut group_state_c =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_c);
+        let update = group_state_c.create_update_proposal(&mut rng).unwrap();
+        let update = group_state_c.create_proposal_message(update).unwrap();
+        let update_ref = group_state_a.cache_proposal(update).unwrap();
+
+        let make_commit = |rng: &mut rand::rngs::StdRng| -> Handshake {
+            let new_path_secret = PathSecret::new_from_random(group_state_a.cs, rng);
+            group_state_a
+                .create_and_apply_commit_handshake(Vec::new(), new_path_secret, rng)
+                .unwrap()
+                .0
+        };
+
+        // A good commit goes through once, and is stale after that
+        let handshake = make_commit(&mut rng);
+        let (new_group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+        let result = new_group_state_b.process_handshake(&handshake);
+        assert_eq!(broken_rule(result), Some(CommitRule::EpochMatches));
+
+        let mut handshake = make_commit(&mut rng);
+        handshake.signer_index = group_state_b.roster.len() as u32;
+        let result = group_state_b.process_handshake(&handshake);
+        assert_eq!(broken_rule(result), Some(CommitRule::SenderIsMember));
+
+        let mut handshake = make_commit(&mut rng);
+        if let GroupOperation::Commit(ref mut commit) = handshake.operation {
+            commit.path.nodes.pop();
+        }
+        let result = group_state_b.process_handshake(&handshake);
+        assert_eq!(broken_rule(result), Some(CommitRule::PathPresent));
+
+        let mut handshake = make_commit(&mut rng);
+        if let GroupOperation::Commit(ref mut commit) = handshake.operation {
+            commit.parent_hash = Digest::default();
+        }
+        let result = group_state_b.process_handshake(&handshake);
+        assert_eq!(broken_rule(result), Some(CommitRule::ParentHashVerifies));
+
+        let mut handshake = make_commit(&mut rng);
+        let ss = group_state_b.get_signature_scheme();
+        handshake.signature = ss.sign(&identity_keys[index_b as usize], b"forged").unwrap();
+        let result = group_state_b.process_handshake(&handshake);
+        assert_eq!(broken_rule(result), Some(CommitRule::SignatureVerifies));
+
+        let mut handshake = make_commit(&mut rng);
+        let wrong_key = HmacKey::new_from_bytes(&[0u8; 32]);
+        handshake.confirmation_tag = hmac::sign(group_state_b.cs.hash_impl, &wrong_key, b"forged");
+        let result = group_state_b.process_handshake(&handshake);
+        assert_eq!(broken_rule(result), Some(CommitRule::ConfirmationVerifies));
+
+        // B never cached C's Update, so a commit to it by reference is unknown to them
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, _, _) = group_state_a
+            .create_and_apply_commit_handshake_with_refs(
+                vec![update_ref],
+                Vec::new(),
+                new_path_secret,
+                &mut rng,
+            )
+            .unwrap();
+        let result = group_state_b.process_handshake(&handshake);
+        assert_eq!(broken_rule(result), Some(CommitRule::ProposalsWellFormed));
+
+        // Committers check the same rules on their own commits
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let proposals = vec![Proposal::new_remove(index_b), Proposal::new_remove(index_b)];
+        let result =
+            group_state_a.create_and_apply_commit_handshake(proposals, new_path_secret, &mut rng);
+        assert_eq!(broken_rule(result), Some(CommitRule::ProposalsDontConflict));
+    }
+
+    // Check that a group can only require what its members support, and that new members have to
+    // support what the group requires
+    #[quickcheck]
+    fn required_capabilities(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut group_state, _) = test_utils::random_full_group_state(1, &mut rng);
+        group_state.set_supported_extensions(&[EXTERNAL_SENDERS_TYPE, REQUIRED_CAPABILITIES_TYPE]);
+
+        let require = |extension_types| {
+            let required = RequiredCapabilities::new(
+                extension_types,
+                vec![ProposalType::ADD, ProposalType::REMOVE],
+                vec![CredentialType::BASIC],
+            );
+            Proposal::new_group_context_extensions(vec![required.to_extension().unwrap()])
+        };
+
+        // This member doesn't support the extension type, so it can't be required
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let proposals = vec![require(vec![ExtensionType(0xff00)])];
+        let result =
+            group_state.create_and_apply_commit_handshake(proposals, new_path_secret, &mut rng);
+        assert_eq!(broken_rule(result), Some(CommitRule::ProposalsWellFormed));
+
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let proposals = vec![require(vec![EXTERNAL_SENDERS_TYPE])];
+        let (_, group_state, _) = group_state
+            .create_and_apply_commit_handshake(proposals, new_path_secret, &mut rng)
+            .unwrap();
+
+        // Someone who isn't known to support external_senders can't be added, but someone who is
+        // can be
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"joiner".to_vec(),
+            credential,
+            vec![group_state.cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let proposals = vec![Proposal::new_add(init_key.clone())];
+        let result = group_state.create_and_apply_commit_handshake(
+            proposals,
+            new_path_secret.clone(),
+            &mut rng,
+        );
+        assert_eq!(broken_rule(result), Some(CommitRule::ProposalsWellFormed));
+
+        let mut capabilities = Capabilities::for_init_key(&init_key);
+        capabilities.extensions.push(EXTERNAL_SENDERS_TYPE);
+        let proposals = vec![Proposal::new_add_with_capabilities(init_key, capabilities)];
+        assert!(group_state
+            .create_and_apply_commit_handshake(proposals, new_path_secret, &mut rng)
+            .is_ok());
+    }
+
+    // A validator that rejects every credential it's asked about in one context
+    struct RejectIn(CredentialContext);
+
+    impl CredentialValidator for RejectIn {
+        fn validate(
+            &self,
+            _credential: &Credential,
+            context: CredentialContext,
+        ) -> Result<(), &'static str> {
+            if context == self.0 {
+                Err("not allowed")
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    // Check that a UserInitKey being added says which of its checks it failed
+    #[quickcheck]
+    fn key_package_rules(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let member_index = test_utils::random_roster_index_with_exceptions(
+            group_state.roster.len(),
+            &[group_state.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let cs = group_state.cs;
+
+        let broken_key_package_rule = |init_key: UserInitKey, rng: &mut rand::rngs::StdRng| {
+            let new_path_secret = PathSecret::new_from_random(cs, rng);
+            let proposals = vec![Proposal::new_add(init_key)];
+            match group_state.create_and_apply_commit_handshake(proposals, new_path_secret, rng) {
+                Err(Error::InvalidCommit(CommitRule::ProposalsWellFormed, err)) => match *err {
+                    Error::InvalidKeyPackage(rule, _) => Some(rule),
+                    _ => None,
+                },
+                _ => None,
+            }
+        };
+
+        // Someone who's already in the group can't be added again with their signature key
+        let member_credential = group_state.roster.0[member_index as usize].clone().unwrap();
+        let init_key = UserInitKey::new_from_random(
+            &identity_keys[member_index as usize],
+            b"again".to_vec(),
+            member_credential,
+            vec![cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(broken_key_package_rule(init_key, &mut rng), Some(KeyPackageRule::KeysUnique));
+
+        // A joiner's UserInitKey has to be signed by them, and be for the group's cipher suite
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let new_init_key = |cipher_suites, rng: &mut rand::rngs::StdRng| {
+            let versions = vec![MLS_DUMMY_VERSION];
+            UserInitKey::new_from_random(
+                &identity_key,
+                b"joiner".to_vec(),
+                credential.clone(),
+                cipher_suites,
+                versions,
+                rng,
+            )
+            .unwrap()
+        };
+        let mut forged = new_init_key(vec![cs], &mut rng);
+        let ss = credential.get_signature_scheme();
+        forged.signature = ss.sign(&identity_key, b"forged").unwrap();
+        assert_eq!(
+            broken_key_package_rule(forged, &mut rng),
+            Some(KeyPackageRule::SignatureVerifies)
+        );
+
+        let other_cs = new_init_key(vec![&X25519_SHA256_CHACHA20POLY1305], &mut rng);
+        assert_eq!(
+            broken_key_package_rule(other_cs, &mut rng),
+            Some(KeyPackageRule::VersionAndCipherSuite)
+        );
+
+        // Everything else gets in
+        let init_key = new_init_key(vec![cs], &mut rng);
+        assert_eq!(broken_key_package_rule(init_key, &mut rng), None);
+    }
+
+    // Check that the application's validator is asked about new and updating members, both when
+    // making commits and when processing them, and that whatever it rejects stays out
+    #[quickcheck]
+    fn credential_validator_consulted(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut group_state_a, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[group_state_a.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state_b =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"joiner".to_vec(),
+            credential,
+            vec![group_state_a.cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let add = vec![Proposal::new_add(init_key)];
+
+        // A committer whose validator rejects new members can't add anyone, and says why
+        let mut strict_a = group_state_a.clone();
+        strict_a.set_credential_validator(Arc::new(RejectIn(CredentialContext::Add)));
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        match strict_a.create_and_apply_commit_handshake(add.clone(), new_path_secret, &mut rng) {
+            Err(Error::InvalidCommit(CommitRule::ProposalsWellFormed, err)) => match *err {
+                Error::InvalidKeyPackage(KeyPackageRule::CredentialAcceptable, err) => {
+                    assert!(matches!(
+                        *err,
+                        Error::CredentialRejected(CredentialContext::Add, "not allowed")
+                    ))
+                }
+                _ => panic!("rejection didn't say where or why"),
+            },
+            _ => panic!("validator was ignored by the committer"),
+        }
+
+        // Nor can anyone else add someone that this member's validator would reject
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, _, _) = group_state_a
+            .create_and_apply_commit_handshake(add, new_path_secret, &mut rng)
+            .unwrap();
+        let mut strict_b = group_state_b.clone();
+        strict_b.set_credential_validator(Arc::new(RejectIn(CredentialContext::Add)));
+        let result = strict_b.process_handshake(&handshake);
+        assert_eq!(broken_rule(result), Some(CommitRule::ProposalsWellFormed));
+        // A validator that only minds Updates lets the Add through
+        group_state_b.set_credential_validator(Arc::new(RejectIn(CredentialContext::Update)));
+        group_state_b.process_handshake(&handshake).unwrap();
+
+        // Updating members are asked about too
+        let update = group_state_b.create_update_proposal(&mut rng).unwrap();
+        group_state_a.set_credential_validator(Arc::new(RejectIn(CredentialContext::Update)));
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let result = group_state_a.create_and_apply_commit_handshake(
+            vec![update],
+            new_path_secret,
+            &mut rng,
+        );
+        assert_eq!(broken_rule(result), Some(CommitRule::ProposalsWellFormed));
+    }
+
+    // An authentication service that knows the keys of a few credentials' identities, and has
+    // revoked a few credentials
+    struct Directory {
+        known: Vec<Credential>,
+        revoked: Vec<Credential>,
+    }
+
+    impl AuthenticationService for Directory {
+        fn expected_signature_keys(&self, identity: &Identity) -> Option<Vec<SigPublicKey>> {
+            let keys: Vec<SigPublicKey> = self
+                .known
+                .iter()
+                .filter(|credential| credential.get_identity() == identity)
+                .map(|credential| credential.get_public_key().clone())
+                .collect();
+            if keys.is_empty() {
+                None
+            } else {
+                Some(keys)
+            }
+        }
+
+        fn is_revoked(&self, credential: &Credential) -> bool {
+            self.revoked.contains(credential)
+        }
+    }
+
+    // Check that the authentication service is asked about new and updating members, and that
+    // impostors and revoked credentials stay out, while identities it doesn't know get in
+    #[quickcheck]
+    fn authentication_service_consulted(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut group_state_a, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[group_state_a.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state_b =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+
+        // Someone shows up with the identity of someone the service knows, but another key
+        let (credential, identity_key) = test_utils::random_basic_credential(&mut rng);
+        let (other, _) = test_utils::random_basic_credential(&mut rng);
+        let genuine = Credential::Basic(BasicCredential::new(
+            credential.get_identity().clone(),
+            other.get_signature_scheme(),
+            other.get_public_key().clone(),
+        ));
+        let init_key = UserInitKey::new_from_random(
+            &identity_key,
+            b"impostor".to_vec(),
+            credential.clone(),
+            vec![group_state_a.cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let add = vec![Proposal::new_add(init_key)];
+
+        let mut strict_a = group_state_a.clone();
+        strict_a.set_authentication_service(Arc::new(Directory {
+            known: vec![genuine],
+            revoked: Vec::new(),
+        }));
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        match strict_a.create_and_apply_commit_handshake(add.clone(), new_path_secret, &mut rng) {
+            Err(Error::InvalidCommit(CommitRule::ProposalsWellFormed, err)) => match *err {
+                Error::InvalidKeyPackage(KeyPackageRule::CredentialAcceptable, err) => {
+                    assert!(matches!(*err, Error::CredentialRejected(CredentialContext::Add, _)))
+                }
+                _ => panic!("impostor's rejection didn't say where"),
+            },
+            _ => panic!("impostor was added"),
+        }
+
+        // Once the service knows their key, or if it knows nothing, they get in
+        let mut known_a = group_state_a.clone();
+        known_a.set_authentication_service(Arc::new(Directory {
+            known: vec![credential],
+            revoked: Vec::new(),
+        }));
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        assert!(known_a.create_and_apply_commit_handshake(add, new_path_secret, &mut rng).is_ok());
+
+        // A member whose credential was revoked can't update
+        let revoked = group_state_b.roster.0[index_b as usize].clone().unwrap();
+        let update = group_state_b.create_update_proposal(&mut rng).unwrap();
+        group_state_a.set_authentication_service(Arc::new(Directory {
+            known: Vec::new(),
+            revoked: vec![revoked],
+        }));
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let result = group_state_a.create_and_apply_commit_handshake(
+            vec![update],
+            new_path_secret,
+            &mut rng,
+        );
+        assert_eq!(broken_rule(result), Some(CommitRule::ProposalsWellFormed));
+    }
+
+    // A validator that accepts every credential, but no credential changes
+    struct NoChanges;
+
+    impl CredentialValidator for NoChanges {
+        fn validate(&self, _: &Credential, _: CredentialContext) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        fn validate_change(&self, _: &Credential, _: &Credential) -> Result<(), &'static str> {
+            Err("no renaming")
+        }
+    }
+
+    // Check that a member can replace their credential in an Update, that everyone sees the
+    // change, that the member signs with the new key afterwards, and that validators can refuse
+    #[quickcheck]
+    fn credential_rotation(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state_a, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[group_state_a.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state_b =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+        let old_credential = group_state_b.roster.0[index_b as usize].clone().unwrap();
+
+        // The new credential's key has to be the one that's given
+        let (new_credential, new_identity_key) = test_utils::random_basic_credential(&mut rng);
+        let (_, wrong_key) = test_utils::random_basic_credential(&mut rng);
+        match group_state_b.create_update_proposal_with_credential(
+            new_credential.clone(),
+            wrong_key,
+            &mut rng,
+        ) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("credential change with the wrong key was made"),
+        }
+        let update = group_state_b
+            .create_update_proposal_with_credential(
+                new_credential.clone(),
+                new_identity_key,
+                &mut rng,
+            )
+            .unwrap();
+
+        // A committer who doesn't allow credential changes won't commit it
+        let mut strict_a = group_state_a.clone();
+        strict_a.set_credential_validator(Arc::new(NoChanges));
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        match strict_a.create_and_apply_commit_handshake(
+            vec![update.clone()],
+            new_path_secret,
+            &mut rng,
+        ) {
+            Err(Error::InvalidCommit(CommitRule::ProposalsWellFormed, err)) => match *err {
+                Error::CredentialRejected(CredentialContext::Update, "no renaming") => (),
+                _ => panic!("rejection didn't say where or why"),
+            },
+            _ => panic!("credential change validator was ignored"),
+        }
+
+        // Otherwise, both members see the change once it's committed
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, new_group_state_a, _) = group_state_a
+            .create_and_apply_commit_handshake(vec![update], new_path_secret, &mut rng)
+            .unwrap();
+        let (new_group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+        let expected_changes =
+            [RosterChange::CredentialChanged(index_b, old_credential, new_credential.clone())];
+        for group_state in &[&new_group_state_a, &new_group_state_b] {
+            assert_eq!(group_state.roster.0[index_b as usize], Some(new_credential.clone()));
+            assert_eq!(group_state.roster_changes(), &expected_changes[..]);
+        }
+
+        // B signs as the new credential from now on
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, _, _) = new_group_state_b
+            .create_and_apply_commit_handshake(Vec::new(), new_path_secret, &mut rng)
+            .unwrap();
+        let (newer_group_state_a, _) = new_group_state_a.process_handshake(&handshake).unwrap();
+        assert!(newer_group_state_a.roster_changes().is_empty());
+    }
+
+    // Check that a member can rotate their identity key without touching their leaf key, and the
+    // other way around
+    #[quickcheck]
+    fn independent_key_rotation(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (group_state_a, identity_keys) = test_utils::random_full_group_state(2, &mut rng);
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[group_state_a.roster_index.unwrap() as usize],
+            &mut rng,
+        );
+        let mut group_state_b =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+        let leaf_idx_b = GroupState::roster_index_to_tree_index(index_b).unwrap();
+        let leaf_key = |group_state: &GroupState| -> Vec<u8> {
+            let public_key = group_state.tree.get(leaf_idx_b).unwrap().get_public_key().unwrap();
+            public_key.as_bytes().to_vec()
+        };
+        let old_leaf_key = leaf_key(&group_state_b);
+
+        // Replacing only the credential leaves B's leaf key as it was
+        let (new_credential, new_identity_key) = test_utils::random_basic_credential(&mut rng);
+        let update = group_state_b
+            .create_credential_update_proposal(new_credential.clone(), new_identity_key)
+            .unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, new_group_state_a, _) = group_state_a
+            .create_and_apply_commit_handshake(vec![update], new_path_secret, &mut rng)
+            .unwrap();
+        let (mut new_group_state_b, _) = group_state_b.process_handshake(&handshake).unwrap();
+        for group_state in &[&new_group_state_a, &new_group_state_b] {
+            assert_eq!(group_state.roster.0[index_b as usize], Some(new_credential.clone()));
+            assert_eq!(leaf_key(group_state), old_leaf_key);
+        }
+
+        // Replacing only the leaf key leaves B's new credential as it is, and B still signs with
+        // the new identity key
+        let update = new_group_state_b.create_update_proposal(&mut rng).unwrap();
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, newer_group_state_a, _) = new_group_state_a
+            .create_and_apply_commit_handshake(vec![update], new_path_secret, &mut rng)
+            .unwrap();
+        let (newer_group_state_b, _) = new_group_state_b.process_handshake(&handshake).unwrap();
+        for group_state in &[&newer_group_state_a, &newer_group_state_b] {
+            assert_eq!(group_state.roster.0[index_b as usize], Some(new_credential.clone()));
+            assert_ne!(leaf_key(group_state), old_leaf_key);
+        }
+        let new_path_secret = PathSecret::new_from_random(group_state_a.cs, &mut rng);
+        let (handshake, _, _) = newer_group_state_b
+            .create_and_apply_commit_handshake(Vec::new(), new_path_secret, &mut rng)
+            .unwrap();
+        newer_group_state_a.process_handshake(&handshake).unwrap();
+    }
+
+    // Check that nobody can bring a credential type into the group that a member doesn't support,
+    // whether by being added or by changing credentials, and that joiners have to support the
+    // types that are already there
+    #[quickcheck]
+    fn credential_type_negotiation(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut group_state_a, identity_keys) = test_utils::random_full_group_state(3, &mut rng);
+        let cs = group_state_a.cs;
+        let index_a = group_state_a.roster_index.unwrap();
+        let index_b = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a as usize],
+            &mut rng,
+        );
+        let index_c = test_utils::random_roster_index_with_exceptions(
+            group_state_a.roster.len(),
+            &[index_a as usize, index_b as usize],
+            &mut rng,
+        );
+
+        // C only supports basic credentials
+        let (root, chain, leaf_key) = x509::test::random_chain(&mut rng);
+        group_state_a.trust_store_mut().roots_mut().add_certificate(&root).unwrap();
+        let leaf_c = group_state_a.leaves[index_c as usize].as_mut().unwrap();
+        leaf_c.capabilities.credentials = vec![CredentialType::BASIC];
+        let mut group_state_b =
+            test_utils::change_self_index(&group_state_a, &identity_keys, index_b);
+
+        let x509_credential = Credential::X509(X509Credential::new(chain).unwrap());
+        let x509_init_key = UserInitKey::new_from_random(
+            &leaf_key,
+            b"x509".to_vec(),
+            x509_credential.clone(),
+            vec![cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let (basic_credential, basic_key) = test_utils::random_basic_credential(&mut rng);
+        let basic_init_key = UserInitKey::new_from_random(
+            &basic_key,
+            b"basic".to_vec(),
+            basic_credential,
+            vec![cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+
+        // Joiners who run this crate support every credential type
+        let capabilities = group_state_a.capabilities();
+        let add = |init_key: &UserInitKey| {
+            Proposal::new_add_with_capabilities(init_key.clone(), capabilities.clone())
+        };
+        let commit = |group_state: &GroupState, proposals, rng: &mut rand::rngs::StdRng| {
+            let new_path_secret = PathSecret::new_from_random(cs, rng);
+            group_state.create_and_apply_commit_handshake(proposals, new_path_secret, rng)
+        };
+        let broken_rules = |result: Result<_, Error>| match result {
+            Err(Error::InvalidCommit(rule, err)) => match *err {
+                Error::InvalidKeyPackage(key_package_rule, _) => {
+                    Some((rule, Some(key_package_rule)))
+                }
+                _ => Some((rule, None)),
+            },
+            _ => None,
+        };
+
+        // C couldn't authenticate an X.509 joiner, or B once they switched to X.509
+        assert_eq!(
+            broken_rules(commit(&group_state_a, vec![add(&x509_init_key)], &mut rng)),
+            Some((CommitRule::ProposalsWellFormed, Some(KeyPackageRule::CapabilitiesSufficient)))
+        );
+        let update = group_state_b
+            .create_credential_update_proposal(x509_credential, leaf_key.clone())
+            .unwrap();
+        assert_eq!(
+            broken_rules(commit(&group_state_a, vec![update.clone()], &mut rng)),
+            Some((CommitRule::ProposalsWellFormed, None))
+        );
+
+        // Without C, both are fine
+        let remove_c = Proposal::new_remove(index_c);
+        commit(&group_state_a, vec![remove_c.clone(), update], &mut rng).unwrap();
+        let (_, group_state, _) =
+            commit(&group_state_a, vec![remove_c, add(&x509_init_key)], &mut rng).unwrap();
+        assert_eq!(
+            group_state.credential_types(),
+            vec![CredentialType::BASIC, CredentialType::X509]
+        );
+
+        // Now joiners have to support X.509, including ones being added along with the X.509
+        // member
+        let basic_only_add = Proposal::new_add(basic_init_key.clone());
+        assert_eq!(
+            broken_rules(commit(&group_state, vec![basic_only_add.clone()], &mut rng)),
+            Some((CommitRule::ProposalsWellFormed, Some(KeyPackageRule::CapabilitiesSufficient)))
+        );
+        commit(&group_state, vec![add(&basic_init_key)], &mut rng).unwrap();
+
+        let leaf_c = group_state_a.leaves[index_c as usize].as_mut().unwrap();
+        leaf_c.capabilities.credentials = capabilities.credentials.clone();
+        assert_eq!(
+            broken_rules(commit(
+                &group_state_a,
+                vec![add(&x509_init_key), basic_only_add],
+                &mut rng
+            )),
+            Some((CommitRule::ProposalsDontConflict, None))
+        );
+    }
+}
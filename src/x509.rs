@@ -0,0 +1,1375 @@
+//! Defines how the X.509 certificate chains in `X509Credential`s are read and validated. This is a
+//! minimal DER reader that only pulls out what MLS needs from a certificate, i.e., its names,
+//! validity period, key, and the extensions that say what it may sign, `TrustAnchors`, which chains
+//! are validated against, and `TrustStore`, which is the trust anchors and pinned keys that a group
+//! validates credentials with. Certificates have to be signed with Ed25519, Ed448, or ECDSA over
+//! P-521 with SHA-512, since those are the signature schemes this crate implements. This also
+//! converts signature keys to and from the DER encodings that other tools use for them, i.e.,
+//! SubjectPublicKeyInfo for public keys and PKCS#8 for secret keys.
+
+use crate::{
+    credential::X509CertData,
+    crypto::sig::{
+        SigPublicKey, SigSecretKey, Signature, SignatureScheme, ECDSA_P521_IMPL, ED25519_IMPL,
+        ED448_IMPL,
+    },
+    error::Error,
+    tls_de::{TlsDeserialize, TlsDeserializer},
+    tls_ser,
+};
+
+// The DER tags that show up in certificates
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const VERSION: u8 = 0xa0;
+const ISSUER_UNIQUE_ID: u8 = 0x81;
+const SUBJECT_UNIQUE_ID: u8 = 0x82;
+const EXTENSIONS: u8 = 0xa3;
+
+// The DER tags that show up in PKCS#8 and ECPrivateKey (RFC 5915) structures
+const PKCS8_ATTRIBUTES: u8 = 0xa0;
+const PKCS8_PUBLIC_KEY: u8 = 0x81;
+const EC_PARAMETERS: u8 = 0xa0;
+const EC_PUBLIC_KEY: u8 = 0xa1;
+
+// The contents of the OIDs that are understood here
+/// id-Ed25519, 1.3.101.112. This names both the key type and the signature algorithm.
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+/// id-Ed448, 1.3.101.113. This names both the key type and the signature algorithm.
+const OID_ED448: &[u8] = &[0x2b, 0x65, 0x71];
+/// id-ecPublicKey, 1.2.840.10045.2.1
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// secp521r1, 1.3.132.0.35
+const OID_SECP521R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x23];
+/// ecdsa-with-SHA512, 1.2.840.10045.4.3.4
+const OID_ECDSA_WITH_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x04];
+/// id-ce-keyUsage, 2.5.29.15
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+/// id-ce-subjectAltName, 2.5.29.17
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+/// id-ce-basicConstraints, 2.5.29.19
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+
+// The bits of the first byte of a keyUsage extension that matter here
+const KEY_USAGE_DIGITAL_SIGNATURE: u8 = 0x80;
+const KEY_USAGE_KEY_CERT_SIGN: u8 = 0x04;
+
+/// Size of P-521 scalars, in bytes. ECDSA signatures in certificates are DER integers, and the
+/// signature scheme takes them as two scalars of exactly this size.
+const P521_SCALAR_SIZE: usize = 66;
+
+/// Reads DER elements off the front of a buffer
+struct DerReader<'a>(&'a [u8]);
+
+impl<'a> DerReader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Reads the next element
+    ///
+    /// Returns: `Ok((tag, contents, encoding))` on success, where `encoding` is the whole element,
+    /// tag and length included. If the element is cut off, or its tag or length isn't in DER
+    /// form, returns an `Error::CertificateError`.
+    fn read_any(&mut self) -> Result<(u8, &'a [u8], &'a [u8]), Error> {
+        let bytes = self.0;
+        if bytes.len() < 2 {
+            return Err(Error::CertificateError("DER element is cut off"));
+        }
+        // Certificates never need tags past 30
+        let tag = bytes[0];
+        if tag & 0x1f == 0x1f {
+            return Err(Error::CertificateError("DER element has a multi-byte tag"));
+        }
+
+        let (len, header_len) = match bytes[1] {
+            len @ 0x00..=0x7f => (usize::from(len), 2),
+            0x81..=0x84 => {
+                let num_len_bytes = usize::from(bytes[1] & 0x7f);
+                let len_bytes = bytes
+                    .get(2..2 + num_len_bytes)
+                    .ok_or(Error::CertificateError("DER element is cut off"))?;
+                // DER lengths are as short as they can be
+                if len_bytes[0] == 0 {
+                    return Err(Error::CertificateError("DER length has leading zeros"));
+                }
+                let len = len_bytes.iter().fold(0usize, |acc, b| (acc << 8) | usize::from(*b));
+                if len < 0x80 {
+                    return Err(Error::CertificateError("DER length isn't in its shortest form"));
+                }
+                (len, 2 + num_len_bytes)
+            }
+            _ => return Err(Error::CertificateError("DER element has an unsupported length")),
+        };
+
+        let end = header_len
+            .checked_add(len)
+            .filter(|end| *end <= bytes.len())
+            .ok_or(Error::CertificateError("DER element is cut off"))?;
+        self.0 = &bytes[end..];
+        Ok((tag, &bytes[header_len..end], &bytes[..end]))
+    }
+
+    /// Reads the next element, whose tag has to be `tag`, and returns its contents
+    fn read(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        let (found, contents, _) = self.read_any()?;
+        if found != tag {
+            return Err(Error::CertificateError("DER element has an unexpected tag"));
+        }
+        Ok(contents)
+    }
+
+    /// Reads the next element, whose tag has to be `tag`, and returns all of it, tag and length
+    /// included
+    fn read_encoding(&mut self, tag: u8) -> Result<&'a [u8], Error> {
+        let (found, _, encoding) = self.read_any()?;
+        if found != tag {
+            return Err(Error::CertificateError("DER element has an unexpected tag"));
+        }
+        Ok(encoding)
+    }
+
+    /// Reads the next element if its tag is `tag`, and returns its contents
+    fn read_optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>, Error> {
+        if self.0.first() == Some(&tag) {
+            self.read(tag).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Checks that everything has been read
+    fn finish(&self) -> Result<(), Error> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::CertificateError("DER element has trailing bytes"))
+        }
+    }
+}
+
+/// Returns the contents of the only element in `bytes`, whose tag has to be `tag`
+fn read_single(bytes: &[u8], tag: u8) -> Result<&[u8], Error> {
+    let mut reader = DerReader(bytes);
+    let contents = reader.read(tag)?;
+    reader.finish()?;
+    Ok(contents)
+}
+
+/// Reads a BIT STRING that's a whole number of bytes, e.g., a key or a signature
+fn read_octet_aligned_bits<'a>(reader: &mut DerReader<'a>) -> Result<&'a [u8], Error> {
+    match reader.read(BIT_STRING)?.split_first() {
+        Some((0, bits)) => Ok(bits),
+        _ => Err(Error::CertificateError("BIT STRING isn't a whole number of bytes")),
+    }
+}
+
+/// Reads an AlgorithmIdentifier
+///
+/// Returns: `Ok((oid, parameters))` on success, where `oid` is the contents of the algorithm's
+/// OID, and `parameters` is the encoding of its parameters, if it has any. Otherwise, returns an
+/// `Error::CertificateError`.
+fn read_algorithm<'a>(reader: &mut DerReader<'a>) -> Result<(&'a [u8], Option<&'a [u8]>), Error> {
+    let mut algorithm = DerReader(reader.read(SEQUENCE)?);
+    let oid = algorithm.read(OID)?;
+    let parameters = if algorithm.is_empty() {
+        None
+    } else {
+        Some(algorithm.read_any()?.2)
+    };
+    algorithm.finish()?;
+    Ok((oid, parameters))
+}
+
+/// Reads the AlgorithmIdentifier of a certificate's signature
+///
+/// Returns: `Ok(scheme)` on success, where `scheme` is the signature scheme it names. If the
+/// algorithm isn't one this crate implements, returns an `Error::CertificateError`.
+fn read_signature_algorithm(reader: &mut DerReader) -> Result<&'static SignatureScheme, Error> {
+    match read_algorithm(reader)? {
+        (OID_ED25519, None) => Ok(&ED25519_IMPL),
+        (OID_ED448, None) => Ok(&ED448_IMPL),
+        (OID_ECDSA_WITH_SHA512, None) => Ok(&ECDSA_P521_IMPL),
+        _ => Err(Error::CertificateError("Certificate has an unsupported signature algorithm")),
+    }
+}
+
+/// Reads a SubjectPublicKeyInfo
+///
+/// Returns: `Ok((scheme, public_key))` on success. If the key isn't for a signature scheme this
+/// crate implements, returns an `Error::CertificateError`. If the key isn't valid in its scheme,
+/// returns an `Error::SignatureError`.
+fn read_public_key(
+    reader: &mut DerReader,
+) -> Result<(&'static SignatureScheme, SigPublicKey), Error> {
+    let mut key_info = DerReader(reader.read(SEQUENCE)?);
+    let scheme = match read_algorithm(&mut key_info)? {
+        (OID_ED25519, None) => &ED25519_IMPL,
+        (OID_ED448, None) => &ED448_IMPL,
+        (OID_EC_PUBLIC_KEY, Some(curve)) if read_single(curve, OID)? == OID_SECP521R1 => {
+            &ECDSA_P521_IMPL
+        }
+        _ => return Err(Error::CertificateError("Certificate has an unsupported key type")),
+    };
+    let key_bytes = read_octet_aligned_bits(&mut key_info)?;
+    key_info.finish()?;
+
+    let public_key = SigPublicKey::new_from_bytes(scheme, key_bytes)?;
+    Ok((scheme, public_key))
+}
+
+/// Encodes a DER element
+fn write_der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let len = contents.len();
+    let mut out = vec![tag];
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        // The length in as few big-endian bytes as it fits in, after a byte saying how many
+        let len_bytes = len.to_be_bytes();
+        let skip = len_bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (len_bytes.len() - skip) as u8);
+        out.extend_from_slice(&len_bytes[skip..]);
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+/// Encodes the AlgorithmIdentifier of keys of the given signature scheme
+///
+/// Returns: `Ok(algorithm)` on success. If the scheme has no standard key encoding here, returns
+/// an `Error::SignatureError`.
+fn write_key_algorithm(scheme: &SignatureScheme) -> Result<Vec<u8>, Error> {
+    let identifier = if scheme == &ED25519_IMPL {
+        write_der(OID, OID_ED25519)
+    } else if scheme == &ED448_IMPL {
+        write_der(OID, OID_ED448)
+    } else if scheme == &ECDSA_P521_IMPL {
+        [write_der(OID, OID_EC_PUBLIC_KEY), write_der(OID, OID_SECP521R1)].concat()
+    } else {
+        return Err(Error::SignatureError("Signature scheme has no standard key encoding"));
+    };
+    Ok(write_der(SEQUENCE, &identifier))
+}
+
+/// Checks that `public_key`, which is the contents of a BIT STRING that came along with a secret
+/// key, is the public key of `secret_key`
+fn check_public_key_bits(
+    scheme: &SignatureScheme,
+    secret_key: &SigSecretKey,
+    public_key: &[u8],
+) -> Result<(), Error> {
+    let derived = SigPublicKey::new_from_secret_key(scheme, secret_key);
+    match public_key.split_first() {
+        Some((0, bits)) if bits == derived.as_bytes() => Ok(()),
+        _ => Err(Error::CertificateError("Public key doesn't match the secret key")),
+    }
+}
+
+// SubjectPublicKeyInfo ::= SEQUENCE {
+//     algorithm AlgorithmIdentifier,
+//     subjectPublicKey BIT STRING
+// }
+/// Encodes a public key as a DER SubjectPublicKeyInfo, which is what PEM files labeled
+/// `PUBLIC KEY` hold
+///
+/// Returns: `Ok(der)` on success. If the key hasn't been upcast, or is of a scheme with no
+/// standard key encoding here, returns an `Error::SignatureError`.
+pub fn public_key_to_der(public_key: &SigPublicKey) -> Result<Vec<u8>, Error> {
+    let scheme = public_key
+        .signature_scheme()
+        .ok_or(Error::SignatureError("Public key hasn't been upcast"))?;
+    let key_bits = write_der(BIT_STRING, &[&[0][..], public_key.as_bytes()].concat());
+    Ok(write_der(SEQUENCE, &[write_key_algorithm(scheme)?, key_bits].concat()))
+}
+
+/// Reads a public key out of a DER SubjectPublicKeyInfo
+///
+/// Returns: `Ok((scheme, public_key))` on success. If the encoding is malformed, or the key isn't
+/// for a signature scheme this crate implements, returns an `Error::CertificateError`. If the key
+/// isn't valid in its scheme, returns an `Error::SignatureError`.
+pub fn public_key_from_der(der: &[u8]) -> Result<(&'static SignatureScheme, SigPublicKey), Error> {
+    let mut reader = DerReader(der);
+    let ret = read_public_key(&mut reader)?;
+    reader.finish()?;
+    Ok(ret)
+}
+
+// PrivateKeyInfo ::= SEQUENCE {
+//     version INTEGER,
+//     privateKeyAlgorithm AlgorithmIdentifier,
+//     privateKey OCTET STRING,
+//     attributes [0] IMPLICIT Attributes OPTIONAL,
+//     publicKey [1] IMPLICIT BIT STRING OPTIONAL
+// }
+//
+// where privateKey holds
+//
+// CurvePrivateKey ::= OCTET STRING
+//
+// for Ed25519 and Ed448 (RFC 8410), and
+//
+// ECPrivateKey ::= SEQUENCE {
+//     version INTEGER,
+//     privateKey OCTET STRING,
+//     parameters [0] EXPLICIT ECParameters OPTIONAL,
+//     publicKey [1] EXPLICIT BIT STRING OPTIONAL
+// }
+//
+// for ECDSA keys (RFC 5915)
+/// Encodes a secret key as a DER PKCS#8 PrivateKeyInfo, which is what PEM files labeled
+/// `PRIVATE KEY` hold. ECDSA keys carry their public key along, as OpenSSL does it. Handle the
+/// result with care: it's the secret key itself.
+///
+/// Returns: `Ok(der)` on success. If the key is held outside of this process, or is of a scheme
+/// with no standard key encoding here, returns an `Error::SignatureError`.
+pub fn secret_key_to_der(secret_key: &SigSecretKey) -> Result<Vec<u8>, Error> {
+    let scheme = secret_key
+        .signature_scheme()
+        .ok_or(Error::SignatureError("Signature scheme has no standard key encoding"))?;
+    let secret_bytes = secret_key
+        .to_secret_bytes()
+        .ok_or(Error::SignatureError("Secret key is held outside of this process"))?;
+
+    let private_key = if scheme == &ECDSA_P521_IMPL {
+        let public_key = SigPublicKey::new_from_secret_key(scheme, secret_key);
+        let public_key_bits = write_der(BIT_STRING, &[&[0][..], public_key.as_bytes()].concat());
+        let ec_private_key = [
+            write_der(INTEGER, &[1]),
+            write_der(OCTET_STRING, secret_bytes.as_bytes()),
+            write_der(EC_PUBLIC_KEY, &public_key_bits),
+        ]
+        .concat();
+        write_der(SEQUENCE, &ec_private_key)
+    } else {
+        write_der(OCTET_STRING, secret_bytes.as_bytes())
+    };
+
+    let private_key_info = [
+        write_der(INTEGER, &[0]),
+        write_key_algorithm(scheme)?,
+        write_der(OCTET_STRING, &private_key),
+    ]
+    .concat();
+    Ok(write_der(SEQUENCE, &private_key_info))
+}
+
+/// Reads the secret scalar out of a DER ECPrivateKey for a P-521 key, and checks the public key in
+/// it, if there is one, against it
+fn read_p521_private_key(der: &[u8]) -> Result<SigSecretKey, Error> {
+    let mut ec_private_key = DerReader(read_single(der, SEQUENCE)?);
+    if ec_private_key.read(INTEGER)? != [1] {
+        return Err(Error::CertificateError("ECPrivateKey has an unsupported version"));
+    }
+    let secret_key =
+        SigSecretKey::new_from_bytes(&ECDSA_P521_IMPL, ec_private_key.read(OCTET_STRING)?)?;
+    if let Some(parameters) = ec_private_key.read_optional(EC_PARAMETERS)? {
+        if read_single(parameters, OID)? != OID_SECP521R1 {
+            return Err(Error::CertificateError("ECPrivateKey is for another curve"));
+        }
+    }
+    if let Some(public_key) = ec_private_key.read_optional(EC_PUBLIC_KEY)? {
+        let bits = read_single(public_key, BIT_STRING)?;
+        check_public_key_bits(&ECDSA_P521_IMPL, &secret_key, bits)?;
+    }
+    ec_private_key.finish()?;
+    Ok(secret_key)
+}
+
+/// Reads a secret key out of a DER PKCS#8 PrivateKeyInfo or OneAsymmetricKey. If a public key
+/// came along with it, that's checked to be the secret key's.
+///
+/// Returns: `Ok(secret_key)` on success. If the encoding is malformed, the key isn't for a
+/// signature scheme this crate implements, or the public key that came along isn't the secret
+/// key's, returns an `Error::CertificateError`. If the key isn't valid in its scheme, returns an
+/// `Error::SignatureError`.
+pub fn secret_key_from_der(der: &[u8]) -> Result<SigSecretKey, Error> {
+    let mut key_info = DerReader(read_single(der, SEQUENCE)?);
+    // Version 1 is OneAsymmetricKey (RFC 5958), which may have the public key at the end
+    let version = key_info.read(INTEGER)?;
+    if version != [0] && version != [1] {
+        return Err(Error::CertificateError("PKCS#8 key has an unsupported version"));
+    }
+    let algorithm = read_algorithm(&mut key_info)?;
+    let private_key = key_info.read(OCTET_STRING)?;
+    // Attributes don't matter here
+    key_info.read_optional(PKCS8_ATTRIBUTES)?;
+    let public_key = key_info.read_optional(PKCS8_PUBLIC_KEY)?;
+    key_info.finish()?;
+
+    let (scheme, secret_key) = match algorithm {
+        (OID_ED25519, None) => {
+            let secret_bytes = read_single(private_key, OCTET_STRING)?;
+            (&ED25519_IMPL, SigSecretKey::new_from_bytes(&ED25519_IMPL, secret_bytes)?)
+        }
+        (OID_ED448, None) => {
+            let secret_bytes = read_single(private_key, OCTET_STRING)?;
+            (&ED448_IMPL, SigSecretKey::new_from_bytes(&ED448_IMPL, secret_bytes)?)
+        }
+        (OID_EC_PUBLIC_KEY, Some(curve)) if read_single(curve, OID)? == OID_SECP521R1 => {
+            (&ECDSA_P521_IMPL, read_p521_private_key(private_key)?)
+        }
+        _ => return Err(Error::CertificateError("PKCS#8 key has an unsupported key type")),
+    };
+    if let Some(public_key) = public_key {
+        check_public_key_bits(scheme, &secret_key, public_key)?;
+    }
+    Ok(secret_key)
+}
+
+/// Reads a Time, i.e., a UTCTime or a GeneralizedTime, both of which DER requires to be in UTC
+/// and to the second
+///
+/// Returns: `Ok(time)` on success, where `time` is in seconds since the Unix epoch, and times
+/// before it are the epoch itself. Otherwise, returns an `Error::CertificateError`.
+fn read_time(reader: &mut DerReader) -> Result<u64, Error> {
+    let (tag, contents, _) = reader.read_any()?;
+    let (year, rest) = match (tag, contents.len()) {
+        // UTCTime years from 50 on are in the 1900s
+        (UTC_TIME, 13) => {
+            let year = read_digits(&contents[..2])?;
+            (
+                if year < 50 {
+                    2000 + year
+                } else {
+                    1900 + year
+                },
+                &contents[2..],
+            )
+        }
+        (GENERALIZED_TIME, 15) => (read_digits(&contents[..4])?, &contents[4..]),
+        _ => return Err(Error::CertificateError("Certificate has a malformed time")),
+    };
+    if rest[10] != b'Z' {
+        return Err(Error::CertificateError("Certificate has a time that isn't in UTC"));
+    }
+
+    let month = read_digits(&rest[0..2])?;
+    let day = read_digits(&rest[2..4])?;
+    let hour = read_digits(&rest[4..6])?;
+    let minute = read_digits(&rest[6..8])?;
+    let second = read_digits(&rest[8..10])?;
+    if month < 1 || month > 12 || day < 1 || day > 31 || hour > 23 || minute > 59 || second > 59 {
+        return Err(Error::CertificateError("Certificate has a malformed time"));
+    }
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    Ok(seconds.max(0) as u64)
+}
+
+/// Reads ASCII decimal digits as a number
+fn read_digits(digits: &[u8]) -> Result<u64, Error> {
+    digits.iter().try_fold(0u64, |acc, digit| match digit {
+        b'0'..=b'9' => Ok(acc * 10 + u64::from(digit - b'0')),
+        _ => Err(Error::CertificateError("Certificate has a malformed time")),
+    })
+}
+
+/// Returns the number of days between the Unix epoch and the given day of the proleptic Gregorian
+/// calendar. This is Howard Hinnant's `days_from_civil`, where years start in March so that leap
+/// days come at the end of them.
+fn days_from_civil(year: u64, month: u64, day: u64) -> i64 {
+    // Years are at most 9999 and months and days are in range, so none of this overflows
+    let year = if month <= 2 {
+        year as i64 - 1
+    } else {
+        year as i64
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// What the extensions of a certificate allow its key to do
+struct KeyPermissions {
+    /// Whether the basicConstraints extension says this is a CA
+    is_ca: bool,
+    /// Whether the keyUsage extension, if there is one, includes digitalSignature
+    can_sign: bool,
+    /// Whether the keyUsage extension, if there is one, includes keyCertSign
+    can_sign_certs: bool,
+}
+
+/// Reads the extensions of a certificate, i.e., the contents of its `[3]` field
+///
+/// Returns: `Ok(permissions)` on success. If an extension is malformed, or there's a critical one
+/// that isn't understood here, returns an `Error::CertificateError`.
+fn read_extensions(bytes: &[u8]) -> Result<KeyPermissions, Error> {
+    let mut permissions = KeyPermissions {
+        is_ca: false,
+        can_sign: true,
+        can_sign_certs: true,
+    };
+
+    let mut extensions = DerReader(read_single(bytes, SEQUENCE)?);
+    while !extensions.is_empty() {
+        let mut extension = DerReader(extensions.read(SEQUENCE)?);
+        let oid = extension.read(OID)?;
+        let critical = match extension.read_optional(BOOLEAN)? {
+            None | Some([0x00]) => false,
+            Some([0xff]) => true,
+            Some(_) => return Err(Error::CertificateError("Certificate has a malformed BOOLEAN")),
+        };
+        let value = extension.read(OCTET_STRING)?;
+        extension.finish()?;
+
+        match oid {
+            OID_BASIC_CONSTRAINTS => {
+                let mut constraints = DerReader(read_single(value, SEQUENCE)?);
+                permissions.is_ca = constraints.read_optional(BOOLEAN)? == Some(&[0xff][..]);
+                // Path lengths aren't enforced
+                constraints.read_optional(INTEGER)?;
+                constraints.finish()?;
+            }
+            OID_KEY_USAGE => {
+                // The first byte of a BIT STRING is its number of unused bits
+                let bits = read_single(value, BIT_STRING)?;
+                let usage = bits.get(1).copied().unwrap_or(0);
+                permissions.can_sign = usage & KEY_USAGE_DIGITAL_SIGNATURE != 0;
+                permissions.can_sign_certs = usage & KEY_USAGE_KEY_CERT_SIGN != 0;
+            }
+            // Names don't restrict anything here
+            OID_SUBJECT_ALT_NAME => (),
+            _ if critical => {
+                return Err(Error::CertificateError(
+                    "Certificate has a critical extension that isn't understood",
+                ))
+            }
+            _ => (),
+        }
+    }
+
+    Ok(permissions)
+}
+
+/// The parts of an X.509 certificate that MLS needs, borrowed from its DER encoding
+pub(crate) struct Certificate<'a> {
+    /// The whole certificate
+    der: &'a [u8],
+    /// The encoding of the TBSCertificate, which is what the issuer signed
+    tbs: &'a [u8],
+    /// The encoding of the issuer's Name
+    issuer: &'a [u8],
+    /// The encoding of the subject's Name
+    pub(crate) subject: &'a [u8],
+    not_before: u64,
+    not_after: u64,
+    /// The signature scheme of the subject's key
+    pub(crate) signature_scheme: &'static SignatureScheme,
+    /// The subject's key
+    pub(crate) public_key: SigPublicKey,
+    /// The signature scheme that the issuer signed with
+    signature_algorithm: &'static SignatureScheme,
+    /// The issuer's signature over `tbs`, as it's encoded in the certificate
+    signature: &'a [u8],
+    permissions: KeyPermissions,
+}
+
+impl<'a> Certificate<'a> {
+    /// Parses a DER-encoded certificate
+    ///
+    /// Returns: `Ok(cert)` on success. If the certificate is malformed, or uses an algorithm or a
+    /// critical extension that isn't supported here, returns an `Error::CertificateError`. If its
+    /// key isn't valid, returns an `Error::SignatureError`.
+    pub(crate) fn parse(der: &'a [u8]) -> Result<Certificate<'a>, Error> {
+        // struct {
+        //     TBSCertificate tbsCertificate;
+        //     AlgorithmIdentifier signatureAlgorithm;
+        //     BIT STRING signatureValue;
+        // } Certificate;
+        let mut cert = DerReader(read_single(der, SEQUENCE)?);
+        let tbs = cert.read_encoding(SEQUENCE)?;
+        let signature_algorithm = read_signature_algorithm(&mut cert)?;
+        let signature = read_octet_aligned_bits(&mut cert)?;
+        cert.finish()?;
+
+        let mut fields = DerReader(read_single(tbs, SEQUENCE)?);
+        // The version is v1 if it's left out. Only v3 certificates have extensions.
+        let version = match fields.read_optional(VERSION)? {
+            Some(version) => read_single(version, INTEGER)?,
+            None => &[0],
+        };
+        if version != [0] && version != [1] && version != [2] {
+            return Err(Error::CertificateError("Certificate has an unknown version"));
+        }
+        // The serial number
+        fields.read(INTEGER)?;
+        // This has to be the same as the certificate's signatureAlgorithm
+        if read_signature_algorithm(&mut fields)? != signature_algorithm {
+            return Err(Error::CertificateError("Certificate's signature algorithms disagree"));
+        }
+        let issuer = fields.read_encoding(SEQUENCE)?;
+        let mut validity = DerReader(fields.read(SEQUENCE)?);
+        let not_before = read_time(&mut validity)?;
+        let not_after = read_time(&mut validity)?;
+        validity.finish()?;
+        let subject = fields.read_encoding(SEQUENCE)?;
+        let (signature_scheme, public_key) = read_public_key(&mut fields)?;
+        fields.read_optional(ISSUER_UNIQUE_ID)?;
+        fields.read_optional(SUBJECT_UNIQUE_ID)?;
+        let permissions = match fields.read_optional(EXTENSIONS)? {
+            Some(extensions) => read_extensions(extensions)?,
+            None => KeyPermissions {
+                is_ca: false,
+                can_sign: true,
+                can_sign_certs: true,
+            },
+        };
+        fields.finish()?;
+
+        Ok(Certificate {
+            der,
+            tbs,
+            issuer,
+            subject,
+            not_before,
+            not_after,
+            signature_scheme,
+            public_key,
+            signature_algorithm,
+            signature,
+            permissions,
+        })
+    }
+
+    /// Returns whether this certificate is valid at `now`, in seconds since the Unix epoch
+    fn is_valid_at(&self, now: u64) -> bool {
+        self.not_before <= now && now <= self.not_after
+    }
+
+    /// Checks that this certificate was signed by `issuer_key`, under `issuer_scheme`
+    ///
+    /// Returns: `Ok(())` if the signature verifies. If the certificate is signed with a scheme
+    /// other than `issuer_scheme`, returns an `Error::CertificateError`. If the signature doesn't
+    /// verify, returns an `Error::SignatureError`.
+    fn verify_signature(
+        &self,
+        issuer_scheme: &'static SignatureScheme,
+        issuer_key: &SigPublicKey,
+    ) -> Result<(), Error> {
+        if self.signature_algorithm != issuer_scheme {
+            return Err(Error::CertificateError(
+                "Certificate is signed with a scheme other than its issuer's",
+            ));
+        }
+
+        let signature = if issuer_scheme == &ECDSA_P521_IMPL {
+            // ECDSA signatures are DER integers r and s. The scheme wants them fixed-width.
+            let mut integers = DerReader(read_single(self.signature, SEQUENCE)?);
+            let r = integers.read(INTEGER)?;
+            let s = integers.read(INTEGER)?;
+            integers.finish()?;
+            let fixed_width = [to_fixed_width(r)?, to_fixed_width(s)?].concat();
+            Signature::new_from_bytes(issuer_scheme, &fixed_width)?
+        } else {
+            Signature::new_from_bytes(issuer_scheme, self.signature)?
+        };
+        issuer_scheme.verify(issuer_key, self.tbs, &signature)
+    }
+}
+
+/// Pads the contents of a nonnegative DER integer to a P-521 scalar
+fn to_fixed_width(integer: &[u8]) -> Result<Vec<u8>, Error> {
+    // DER puts a zero byte before integers whose top bit is set, so they don't read as negative
+    let integer = match integer.split_first() {
+        Some((0, rest)) if !rest.is_empty() => rest,
+        _ => integer,
+    };
+    if integer.len() > P521_SCALAR_SIZE {
+        return Err(Error::SignatureError("ECDSA signature integer is too long"));
+    }
+
+    let mut fixed_width = vec![0u8; P521_SCALAR_SIZE - integer.len()];
+    fixed_width.extend_from_slice(integer);
+    Ok(fixed_width)
+}
+
+/// A certificate that chains are trusted to end at, with the parts of it that are needed to check
+/// what it issued
+#[derive(Clone)]
+struct TrustAnchor {
+    der: Vec<u8>,
+    subject: Vec<u8>,
+    signature_scheme: &'static SignatureScheme,
+    public_key: SigPublicKey,
+}
+
+/// The certificates, e.g., an organization's root CAs, that the chains of `X509Credential`s are
+/// trusted to end at. A chain is valid if it contains one of these, or if its last certificate was
+/// issued by one of them. Anchors are trusted as they are, so their own validity periods and
+/// extensions aren't checked. There are none to begin with, so no chain is valid until some are
+/// added.
+#[derive(Clone, Default)]
+pub struct TrustAnchors {
+    anchors: Vec<TrustAnchor>,
+}
+
+impl TrustAnchors {
+    /// Makes a set of trust anchors with nothing in it
+    pub fn new() -> TrustAnchors {
+        TrustAnchors::default()
+    }
+
+    /// Adds the DER-encoded certificate `der` to the set
+    ///
+    /// Returns: `Ok(())` on success. If the certificate is malformed or unsupported, returns an
+    /// `Error::CertificateError`. If its key isn't valid, returns an `Error::SignatureError`.
+    pub fn add_certificate(&mut self, der: &[u8]) -> Result<(), Error> {
+        let cert = Certificate::parse(der)?;
+        self.anchors.push(TrustAnchor {
+            der: der.to_vec(),
+            subject: cert.subject.to_vec(),
+            signature_scheme: cert.signature_scheme,
+            public_key: cert.public_key,
+        });
+        Ok(())
+    }
+
+    /// Removes the DER-encoded certificate `der` from the set
+    ///
+    /// Returns: whether it was in the set
+    pub fn remove_certificate(&mut self, der: &[u8]) -> bool {
+        let len_before = self.anchors.len();
+        self.anchors.retain(|anchor| anchor.der != der);
+        self.anchors.len() != len_before
+    }
+
+    /// Returns whether there are no trust anchors in the set
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// Returns the DER encodings of the trust anchors, in the order they were added
+    pub fn certificates(&self) -> impl Iterator<Item = &[u8]> {
+        self.anchors.iter().map(|anchor| anchor.der.as_slice())
+    }
+
+    /// Returns whether `cert` is one of the trust anchors
+    fn contains(&self, cert: &Certificate) -> bool {
+        self.anchors.iter().any(|anchor| anchor.der == cert.der)
+    }
+
+    /// Returns whether `cert` was issued by one of the trust anchors
+    fn issued(&self, cert: &Certificate) -> bool {
+        self.anchors.iter().any(|anchor| {
+            anchor.subject == cert.issuer
+                && cert.verify_signature(anchor.signature_scheme, &anchor.public_key).is_ok()
+        })
+    }
+}
+
+// opaque pinned_key<1..2^16-1>;
+//
+// struct {
+//     X509CertData roots<0..2^32-1>;
+//     pinned_key pinned_keys<0..2^32-1>;
+// } TrustStore;
+/// What a group trusts the credentials of its members with: the root certificates their chains
+/// can end at, as `TrustAnchors`, and the keys that are trusted as they are, e.g., of a member
+/// whose certificate was checked some other way. A leaf certificate whose key is pinned needs no
+/// chain, but still has to be valid at the current time and allowed to sign. Every group has its
+/// own, which can be changed at any time with `GroupState::trust_store_mut`, and which is saved
+/// and restored along with the rest of the group with `TrustStore::to_bytes` and
+/// `TrustStore::from_bytes`.
+#[derive(Clone, Default)]
+pub struct TrustStore {
+    roots: TrustAnchors,
+    pinned_keys: Vec<Vec<u8>>,
+}
+
+// This is how a TrustStore is written out. The roots are parsed again when it's read back in.
+#[derive(TlsDeserialize, TlsSerialize)]
+struct TrustStoreBytes {
+    #[tls(bound = "u32")]
+    roots: Vec<X509CertData>,
+    #[tls(bound = "u32")]
+    pinned_keys: Vec<PinnedKey>,
+}
+
+#[derive(TlsDeserialize, TlsSerialize)]
+#[tls(bound = "u16")]
+struct PinnedKey(Vec<u8>);
+
+impl TrustStore {
+    /// Makes a trust store with no roots and no pinned keys, which trusts nobody
+    pub fn new() -> TrustStore {
+        TrustStore::default()
+    }
+
+    /// Returns the root certificates that chains can end at
+    pub fn roots(&self) -> &TrustAnchors {
+        &self.roots
+    }
+
+    /// Returns the root certificates that chains can end at, so that they can be changed
+    pub fn roots_mut(&mut self) -> &mut TrustAnchors {
+        &mut self.roots
+    }
+
+    /// Trusts leaf certificates with the key `public_key`, whoever issued them. This does nothing
+    /// if it's already pinned.
+    pub fn pin_key(&mut self, public_key: &SigPublicKey) {
+        if !self.is_pinned(public_key) {
+            self.pinned_keys.push(public_key.as_bytes().to_vec());
+        }
+    }
+
+    /// Stops trusting leaf certificates with the key `public_key` as they are
+    ///
+    /// Returns: whether it was pinned
+    pub fn unpin_key(&mut self, public_key: &SigPublicKey) -> bool {
+        let len_before = self.pinned_keys.len();
+        self.pinned_keys.retain(|key| key.as_slice() != public_key.as_bytes());
+        self.pinned_keys.len() != len_before
+    }
+
+    /// Returns whether `public_key` is pinned
+    pub fn is_pinned(&self, public_key: &SigPublicKey) -> bool {
+        self.pinned_keys.iter().any(|key| key.as_slice() == public_key.as_bytes())
+    }
+
+    /// Serializes this trust store, so that it can be saved along with its group
+    ///
+    /// Returns: `Ok(bytes)` on success. Otherwise, returns an `Error::SerdeError`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let bytes = TrustStoreBytes {
+            roots: self
+                .roots
+                .certificates()
+                .map(|der| X509CertData::from_der(der.to_vec()))
+                .collect(),
+            pinned_keys: self.pinned_keys.iter().cloned().map(PinnedKey).collect(),
+        };
+        tls_ser::serialize_to_bytes(&bytes)
+    }
+
+    /// Deserializes a trust store that was serialized with `TrustStore::to_bytes`
+    ///
+    /// Returns: `Ok(store)` on success. If there's anything after the trust store, returns an
+    /// `Error::ValidationError`. If a root certificate is malformed or unsupported, returns an
+    /// `Error::CertificateError`. Otherwise, if the bytes are malformed, returns some sort of
+    /// `Error`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TrustStore, Error> {
+        let mut cursor = bytes;
+        let stored = {
+            let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+            TrustStoreBytes::tls_deserialize(&mut deserializer)?
+        };
+        if !cursor.is_empty() {
+            return Err(Error::ValidationError("TrustStore has trailing bytes"));
+        }
+
+        let mut store = TrustStore::new();
+        for root in stored.roots {
+            store.roots.add_certificate(root.as_der())?;
+        }
+        store.pinned_keys = stored.pinned_keys.into_iter().map(|key| key.0).collect();
+        Ok(store)
+    }
+}
+
+impl From<TrustAnchors> for TrustStore {
+    fn from(roots: TrustAnchors) -> TrustStore {
+        TrustStore {
+            roots,
+            pinned_keys: Vec::new(),
+        }
+    }
+}
+
+/// Validates the certificate chain `chain`, leaf first, at `now`, against `store`. If the leaf's
+/// key is pinned, only the leaf is checked. Otherwise, this is `validate_chain` against the
+/// store's roots.
+///
+/// Returns: `Ok(())` if the chain is valid. Otherwise, returns what `validate_chain` would.
+pub(crate) fn validate_against_store(
+    chain: &[X509CertData],
+    store: &TrustStore,
+    now: u64,
+) -> Result<(), Error> {
+    let leaf_der = chain.first().ok_or(Error::CertificateError("Certificate chain is empty"))?;
+    let leaf = Certificate::parse(leaf_der.as_der())?;
+    if !store.is_pinned(&leaf.public_key) {
+        return validate_chain(chain, &store.roots, now);
+    }
+
+    if !leaf.permissions.can_sign {
+        return Err(Error::CertificateError("Leaf certificate's key isn't allowed to sign"));
+    }
+    if !leaf.is_valid_at(now) {
+        return Err(Error::CertificateError("Certificate isn't valid at the current time"));
+    }
+    Ok(())
+}
+
+/// Validates the certificate chain `chain`, leaf first, at `now`, in seconds since the Unix epoch.
+/// Every certificate up to the first trust anchor in the chain, or all of them if the last was
+/// issued by a trust anchor, has to be valid at `now` and signed by the next one. Every issuer has
+/// to be a CA that's allowed to sign certificates, and the leaf has to be allowed to sign, since
+/// its key signs this member's handshakes.
+///
+/// Returns: `Ok(())` if the chain is valid. If it's empty, malformed, or doesn't end at a trust
+/// anchor, or any certificate in it isn't valid at `now`, returns an `Error::CertificateError`. If
+/// a signature in it doesn't verify, returns an `Error::SignatureError`.
+pub(crate) fn validate_chain(
+    chain: &[X509CertData],
+    anchors: &TrustAnchors,
+    now: u64,
+) -> Result<(), Error> {
+    let certs: Vec<Certificate> =
+        chain.iter().map(|cert| Certificate::parse(cert.as_der())).collect::<Result<_, _>>()?;
+    let leaf = certs.first().ok_or(Error::CertificateError("Certificate chain is empty"))?;
+    if !leaf.permissions.can_sign {
+        return Err(Error::CertificateError("Leaf certificate's key isn't allowed to sign"));
+    }
+
+    for (i, cert) in certs.iter().enumerate() {
+        if anchors.contains(cert) {
+            return Ok(());
+        }
+        if !cert.is_valid_at(now) {
+            return Err(Error::CertificateError("Certificate isn't valid at the current time"));
+        }
+
+        match certs.get(i + 1) {
+            Some(issuer) => {
+                if issuer.subject != cert.issuer {
+                    return Err(Error::CertificateError(
+                        "Certificate's issuer isn't the next one in the chain",
+                    ));
+                }
+                if !issuer.permissions.is_ca || !issuer.permissions.can_sign_certs {
+                    return Err(Error::CertificateError(
+                        "Certificate is issued by one that isn't allowed to issue certificates",
+                    ));
+                }
+                cert.verify_signature(issuer.signature_scheme, &issuer.public_key)?;
+            }
+            None => {
+                if !anchors.issued(cert) {
+                    return Err(Error::CertificateError(
+                        "Certificate chain doesn't end at a trust anchor",
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::{
+        credential::{Credential, X509CertData, X509Credential},
+        crypto::{
+            rng::CryptoRng,
+            sig::{SigPublicKey, SigSecretKey, ED25519_IMPL},
+        },
+        error::Error,
+        group_state::GroupState,
+        handshake::{Proposal, UserInitKey, MLS_DUMMY_VERSION},
+        ratchet_tree::PathSecret,
+        revocation::{
+            AsyncRevocationChecker, RevocationChecker, RevocationFuture, RevocationStatus,
+        },
+        test_utils,
+        tls_de::{TlsDeserialize, TlsDeserializer},
+        tls_ser,
+        upcast::{CryptoCtx, CryptoUpcast},
+        x509::{self, Certificate, TrustAnchors, TrustStore},
+    };
+
+    use quickcheck_macros::quickcheck;
+    use rand::SeedableRng;
+
+    use std::{
+        future::Future,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    // Encodes a DER element
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let len = contents.len();
+        let mut out = vec![tag];
+        if len < 0x80 {
+            out.push(len as u8);
+        } else if len < 0x100 {
+            out.extend_from_slice(&[0x81, len as u8]);
+        } else {
+            out.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]);
+        }
+        out.extend_from_slice(contents);
+        out
+    }
+
+    // Encodes a Name with just a common name in it
+    fn name(common_name: &str) -> Vec<u8> {
+        let attribute =
+            [der(0x06, &[0x55, 0x04, 0x03]), der(0x0c, common_name.as_bytes())].concat();
+        der(0x30, &der(0x31, &der(0x30, &attribute)))
+    }
+
+    // Makes a DER-encoded Ed25519 certificate for `subject_key`, issued by `issuer` with
+    // `issuer_key`, valid from 2020 through 2099
+    pub(crate) fn ed25519_cert(
+        subject: &str,
+        subject_key: &SigPublicKey,
+        issuer: &str,
+        issuer_key: &SigSecretKey,
+        is_ca: bool,
+    ) -> Vec<u8> {
+        let algorithm = der(0x30, &der(0x06, x509::OID_ED25519));
+        let validity =
+            der(0x30, &[der(0x17, b"200101000000Z"), der(0x18, b"20991231235959Z")].concat());
+        let key_info = der(
+            0x30,
+            &[algorithm.clone(), der(0x03, &[&[0][..], subject_key.as_bytes()].concat())].concat(),
+        );
+        let basic_constraints = if is_ca {
+            der(0x30, &der(0x01, &[0xff]))
+        } else {
+            der(0x30, &[])
+        };
+        let extension = der(
+            0x30,
+            &[der(0x06, x509::OID_BASIC_CONSTRAINTS), der(0x04, &basic_constraints)].concat(),
+        );
+        let tbs = der(
+            0x30,
+            &[
+                der(0xa0, &der(0x02, &[2])),
+                der(0x02, &[1]),
+                algorithm.clone(),
+                name(issuer),
+                validity,
+                name(subject),
+                key_info,
+                der(0xa3, &der(0x30, &extension)),
+            ]
+            .concat(),
+        );
+
+        let signature = ED25519_IMPL.sign(issuer_key, &tbs).unwrap().as_bytes();
+        der(0x30, &[tbs, algorithm, der(0x03, &[&[0][..], &signature[..]].concat())].concat())
+    }
+
+    // Makes a root, an intermediate CA, and a leaf, and returns the root's certificate, the chain
+    // from the leaf to the intermediate, and the leaf's secret key
+    pub(crate) fn random_chain<R: rand::Rng + CryptoRng>(
+        rng: &mut R,
+    ) -> (Vec<u8>, Vec<X509CertData>, SigSecretKey) {
+        let ss = &ED25519_IMPL;
+        let keys: Vec<SigSecretKey> =
+            (0..3).map(|_| SigSecretKey::new_from_random(ss, rng).unwrap()).collect();
+        let public = |i: usize| SigPublicKey::new_from_secret_key(ss, &keys[i]);
+
+        let root = ed25519_cert("root", &public(0), "root", &keys[0], true);
+        let intermediate = ed25519_cert("intermediate", &public(1), "root", &keys[0], true);
+        let leaf = ed25519_cert("leaf", &public(2), "intermediate", &keys[1], false);
+        let chain = vec![X509CertData::from_der(leaf), X509CertData::from_der(intermediate)];
+        (root, chain, keys[2].clone())
+    }
+
+    // Some time in 2025
+    const NOW: u64 = 1_750_000_000;
+
+    // Check that chains are accepted exactly when they end at a trust anchor, are valid at the
+    // current time, and are signed all the way through
+    #[quickcheck]
+    fn chain_validation(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (root, chain, leaf_key) = random_chain(&mut rng);
+
+        let leaf = Certificate::parse(chain[0].as_der()).unwrap();
+        assert_eq!(leaf.not_before, 1_577_836_800);
+        assert_eq!(leaf.not_after, 4_102_444_799);
+        assert_eq!(leaf.public_key, SigPublicKey::new_from_secret_key(&ED25519_IMPL, &leaf_key));
+
+        let mut anchors = TrustAnchors::new();
+        assert!(x509::validate_chain(&chain, &anchors, NOW).is_err());
+        anchors.add_certificate(&root).unwrap();
+        x509::validate_chain(&chain, &anchors, NOW).unwrap();
+        // The root can be sent along too
+        let with_root = [&chain[..], &[X509CertData::from_der(root.clone())][..]].concat();
+        x509::validate_chain(&with_root, &anchors, NOW).unwrap();
+
+        // Certificates are only valid in their validity periods
+        match x509::validate_chain(&chain, &anchors, 5_000_000_000) {
+            Err(Error::CertificateError(_)) => (),
+            _ => panic!("expired chain was accepted"),
+        }
+        // The leaf alone doesn't chain to the root, but it does chain to the intermediate
+        assert!(x509::validate_chain(&chain[..1], &anchors, NOW).is_err());
+        let mut intermediate_anchors = TrustAnchors::new();
+        intermediate_anchors.add_certificate(chain[1].as_der()).unwrap();
+        x509::validate_chain(&chain[..1], &intermediate_anchors, NOW).unwrap();
+
+        // A certificate that isn't a CA can't issue others
+        let ss = &ED25519_IMPL;
+        let other_key = SigSecretKey::new_from_random(ss, &mut rng).unwrap();
+        let other_public = SigPublicKey::new_from_secret_key(ss, &other_key);
+        let issued_by_leaf = ed25519_cert("other", &other_public, "leaf", &leaf_key, false);
+        let too_long = [&[X509CertData::from_der(issued_by_leaf)][..], &chain[..]].concat();
+        match x509::validate_chain(&too_long, &anchors, NOW) {
+            Err(Error::CertificateError(_)) => (),
+            _ => panic!("certificate issued by a non-CA was accepted"),
+        }
+
+        // A tampered certificate doesn't verify
+        let mut tampered = chain.clone();
+        let mut leaf_der = tampered[0].as_der().to_vec();
+        let last = leaf_der.len() - 1;
+        leaf_der[last] ^= 1;
+        tampered[0] = X509CertData::from_der(leaf_der);
+        match x509::validate_chain(&tampered, &anchors, NOW) {
+            Err(Error::SignatureError(_)) => (),
+            _ => panic!("tampered chain was accepted"),
+        }
+
+        // Neither does one that's cut off, or has something after it
+        let mut truncated = chain[0].as_der().to_vec();
+        truncated.pop();
+        assert!(Certificate::parse(&truncated).is_err());
+        let mut extended = chain[0].as_der().to_vec();
+        extended.push(0);
+        assert!(Certificate::parse(&extended).is_err());
+    }
+
+    // Check that X.509 credentials get their identity and key from the leaf certificate, and
+    // still have them after a trip over the wire
+    #[quickcheck]
+    fn x509_credential_round_trip(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (_, chain, leaf_key) = random_chain(&mut rng);
+
+        let credential = Credential::X509(X509Credential::new(chain.clone()).unwrap());
+        let leaf = Certificate::parse(chain[0].as_der()).unwrap();
+        assert_eq!(credential.get_identity().as_bytes(), leaf.subject);
+        assert_eq!(credential.get_signature_scheme(), &ED25519_IMPL);
+        assert_eq!(
+            credential.get_public_key(),
+            &SigPublicKey::new_from_secret_key(&ED25519_IMPL, &leaf_key)
+        );
+
+        let bytes = tls_ser::serialize_to_bytes(&credential).unwrap();
+        let mut cursor = bytes.as_slice();
+        let mut deserializer = TlsDeserializer::from_reader(&mut cursor);
+        let mut received = Credential::tls_deserialize(&mut deserializer).unwrap();
+        received.upcast_crypto_values(&CryptoCtx::new()).unwrap();
+        assert_eq!(received, credential);
+        assert_eq!(received.get_public_key(), credential.get_public_key());
+
+        // A chain without a leaf can't be a credential
+        assert!(X509Credential::new(Vec::new()).is_err());
+    }
+
+    // Check that someone with an X.509 credential can only be added once their chain ends at one
+    // of the group's trust anchors
+    #[quickcheck]
+    fn x509_add_checked(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut group_state, _) = test_utils::random_full_group_state(1, &mut rng);
+        let (root, chain, leaf_key) = random_chain(&mut rng);
+
+        let credential = Credential::X509(X509Credential::new(chain).unwrap());
+        let init_key = UserInitKey::new_from_random(
+            &leaf_key,
+            b"x509 add".to_vec(),
+            credential,
+            vec![group_state.cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        init_key.verify_sig().unwrap();
+
+        let commit_add = |group_state: &GroupState, rng: &mut rand::rngs::StdRng| {
+            let new_path_secret = PathSecret::new_from_random(group_state.cs, rng);
+            // The new member runs this crate too, so they support the group's basic credentials
+            let capabilities = group_state.capabilities();
+            let add = Proposal::new_add_with_capabilities(init_key.clone(), capabilities);
+            group_state.create_and_apply_commit_handshake(vec![add], new_path_secret, rng)
+        };
+        assert!(commit_add(&group_state, &mut rng).is_err());
+
+        let mut anchors = TrustAnchors::new();
+        anchors.add_certificate(&root).unwrap();
+        group_state.set_trust_anchors(anchors);
+        commit_add(&group_state, &mut rng).unwrap();
+    }
+
+    // Check that a trust store trusts chains that end at its roots and leaves whose keys are
+    // pinned, that it can be changed while the group is in use, and that it survives being saved
+    #[quickcheck]
+    fn trust_store(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (root, chain, leaf_key) = random_chain(&mut rng);
+        let leaf_public_key = SigPublicKey::new_from_secret_key(&ED25519_IMPL, &leaf_key);
+
+        // A pinned leaf needs no chain, but still has to be valid at the current time
+        let mut store = TrustStore::new();
+        assert!(x509::validate_against_store(&chain[..1], &store, NOW).is_err());
+        store.pin_key(&leaf_public_key);
+        assert!(store.is_pinned(&leaf_public_key));
+        x509::validate_against_store(&chain[..1], &store, NOW).unwrap();
+        assert!(x509::validate_against_store(&chain[..1], &store, 5_000_000_000).is_err());
+        assert!(store.unpin_key(&leaf_public_key));
+        assert!(!store.unpin_key(&leaf_public_key));
+        assert!(x509::validate_against_store(&chain, &store, NOW).is_err());
+
+        // Roots work like TrustAnchors, and can be taken away again
+        store.roots_mut().add_certificate(&root).unwrap();
+        x509::validate_against_store(&chain, &store, NOW).unwrap();
+        assert!(store.roots_mut().remove_certificate(&root));
+        assert!(x509::validate_against_store(&chain, &store, NOW).is_err());
+
+        // A saved store trusts what it did before
+        store.roots_mut().add_certificate(&root).unwrap();
+        store.pin_key(&leaf_public_key);
+        let restored = TrustStore::from_bytes(&store.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.roots().certificates().collect::<Vec<_>>(), vec![root.as_slice()]);
+        assert!(restored.is_pinned(&leaf_public_key));
+        x509::validate_against_store(&chain, &restored, NOW).unwrap();
+        let mut extended = store.to_bytes().unwrap();
+        extended.push(0);
+        assert!(TrustStore::from_bytes(&extended).is_err());
+
+        // A group's store can be filled in after the group is made, for that group alone
+        let (mut group_state, _) = test_utils::random_full_group_state(1, &mut rng);
+        let other_group_state = group_state.clone();
+        group_state.trust_store_mut().pin_key(&leaf_public_key);
+        assert!(group_state.trust_store().is_pinned(&leaf_public_key));
+        assert!(!other_group_state.trust_store().is_pinned(&leaf_public_key));
+    }
+
+    // A revocation checker that revokes the given leaf certificates, and counts how often it's
+    // asked
+    struct RevokedLeaves(Vec<Vec<u8>>, AtomicUsize);
+
+    impl RevocationChecker for RevokedLeaves {
+        fn check(&self, chain: &[X509CertData]) -> (RevocationStatus, u64) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            if self.0.iter().any(|der| der.as_slice() == chain[0].as_der()) {
+                (RevocationStatus::Revoked, 60)
+            } else {
+                (RevocationStatus::Good, 60)
+            }
+        }
+    }
+
+    // An OCSP responder that says everything is revoked, right away
+    struct RevokesEverything;
+
+    impl AsyncRevocationChecker for RevokesEverything {
+        fn check(&self, _chain: Vec<X509CertData>) -> RevocationFuture {
+            Box::pin(std::future::ready((RevocationStatus::Revoked, 60)))
+        }
+    }
+
+    // Runs a future that never has to wait to completion
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct NoopWaker;
+        impl Wake for NoopWaker {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    // Check that revoked certificates keep their owners out, that answers are cached, and that
+    // members who were revoked after they joined are reported, whichever kind of checker says so
+    #[quickcheck]
+    fn revocation_checked(rng_seed: u64) {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(rng_seed);
+        let (mut group_state, _) = test_utils::random_full_group_state(1, &mut rng);
+        let (root, chain, leaf_key) = random_chain(&mut rng);
+        let leaf_der = chain[0].as_der().to_vec();
+        group_state.trust_store_mut().roots_mut().add_certificate(&root).unwrap();
+
+        let credential = Credential::X509(X509Credential::new(chain).unwrap());
+        let init_key = UserInitKey::new_from_random(
+            &leaf_key,
+            b"revocation".to_vec(),
+            credential,
+            vec![group_state.cs],
+            vec![MLS_DUMMY_VERSION],
+            &mut rng,
+        )
+        .unwrap();
+        let commit_add = |group_state: &GroupState, rng: &mut rand::rngs::StdRng| {
+            let new_path_secret = PathSecret::new_from_random(group_state.cs, rng);
+            // The new member runs this crate too, so they support the group's basic credentials
+            let capabilities = group_state.capabilities();
+            let add = Proposal::new_add_with_capabilities(init_key.clone(), capabilities);
+            group_state.create_and_apply_commit_handshake(vec![add], new_path_secret, rng)
+        };
+
+        // A revoked certificate can't be added
+        let mut strict = group_state.clone();
+        strict.set_revocation_checker(Arc::new(RevokedLeaves(vec![leaf_der.clone()], 0.into())));
+        match commit_add(&strict, &mut rng) {
+            Err(Error::InvalidCommit(_, err)) => match *err {
+                Error::InvalidKeyPackage(_, err) => {
+                    assert!(matches!(*err, Error::CredentialRejected(_, _)))
+                }
+                _ => panic!("revocation didn't reject the UserInitKey"),
+            },
+            _ => panic!("revoked certificate was added"),
+        }
+        // The clone shares its cache with the original
+        group_state.revocation_cache().clear();
+
+        // One that isn't revoked can, and the checker's answer is kept until it expires
+        let checker = Arc::new(RevokedLeaves(Vec::new(), 0.into()));
+        group_state.set_revocation_checker(checker.clone());
+        let (_, group_state, _) = commit_add(&group_state, &mut rng).unwrap();
+        assert!(group_state.check_member_revocations().is_empty());
+        assert_eq!(checker.1.load(Ordering::SeqCst), 1);
+
+        // If it's revoked later, that's only noticed once the cached answer is gone
+        let mut group_state = group_state;
+        group_state.set_revocation_checker(Arc::new(RevokedLeaves(vec![leaf_der], 0.into())));
+        assert!(group_state.check_member_revocations().is_empty());
+        group_state.revocation_cache().clear();
+        let revoked = group_state.check_member_revocations();
+        assert_eq!(revoked.len(), 1);
+        let revoked_index = revoked[0].roster_index();
+        let new_path_secret = PathSecret::new_from_random(group_state.cs, &mut rng);
+        let (_, removed_state, _) = group_state
+            .create_and_apply_commit_handshake(
+                vec![revoked[0].remove_proposal()],
+                new_path_secret,
+                &mut rng,
+            )
+            .unwrap();
+        assert!(removed_state.member(revoked_index).is_none());
+
+        // An asynchronous checker fills in the cache for later handshakes
+        group_state.revocation_cache().clear();
+        let revoked = block_on(group_state.check_member_revocations_async(&RevokesEverything));
+        assert_eq!(revoked.len(), 1);
+        assert_eq!(revoked[0].roster_index(), revoked_index);
+    }
+}
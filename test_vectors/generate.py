@@ -0,0 +1,648 @@
+#!/usr/bin/env python3
+"""Generates the RFC 9420 test vectors in this directory.
+
+The files are laid out like the ones at
+https://github.com/mlswg/mls-implementations/tree/main/test-vectors, so those can be dropped in
+instead. Everything here is computed straight from the definitions in RFC 9420 and RFC 9180, with
+nothing from molasses, so these are an independent check on it. All the "random" inputs come out
+of SHAKE256 over a fixed label, so running this again gives the same files.
+
+    python3 test_vectors/generate.py
+
+This needs the `cryptography` package.
+"""
+
+import hashlib
+import hmac
+import json
+import os
+import struct
+
+#
+# Inputs
+#
+
+
+def rand(label, n):
+    """Returns n bytes that are fixed by label"""
+    return hashlib.shake_256(b"molasses test vectors " + label.encode()).digest(n)
+
+
+#
+# Encoding
+#
+
+
+def u8(x):
+    return struct.pack(">B", x)
+
+
+def u16(x):
+    return struct.pack(">H", x)
+
+
+def u32(x):
+    return struct.pack(">I", x)
+
+
+def u64(x):
+    return struct.pack(">Q", x)
+
+
+def varint(n):
+    """The variable-length integer of RFC 9420 section 2.1.2"""
+    if n < 1 << 6:
+        return u8(n)
+    if n < 1 << 14:
+        return u16(0x4000 | n)
+    if n < 1 << 30:
+        return u32(0x80000000 | n)
+    raise ValueError("too long for a varint")
+
+
+def opaque(data):
+    """opaque data<V>"""
+    return varint(len(data)) + data
+
+
+#
+# Cipher suites
+#
+
+
+class Suite:
+    def __init__(self, suite_id, hash_name, nh, nk, nn, kem):
+        self.id = suite_id
+        self.hash_name = hash_name
+        self.nh = nh
+        self.nk = nk
+        self.nn = nn
+        self.kem = kem
+
+    def hash(self, data):
+        return hashlib.new(self.hash_name, data).digest()
+
+
+# (kem_id, KEM hash, Nsecret, Nsk)
+X25519_KEM = (0x0020, "sha256", 32, 32)
+X448_KEM = (0x0021, "sha512", 64, 56)
+P256_KEM = (0x0010, "sha256", 32, 32)
+P384_KEM = (0x0011, "sha384", 48, 48)
+P521_KEM = (0x0012, "sha512", 64, 66)
+
+SUITES = [
+    Suite(0x0001, "sha256", 32, 16, 12, X25519_KEM),
+    Suite(0x0002, "sha256", 32, 16, 12, P256_KEM),
+    Suite(0x0003, "sha256", 32, 32, 12, X25519_KEM),
+    Suite(0x0004, "sha512", 64, 32, 12, X448_KEM),
+    Suite(0x0005, "sha512", 64, 32, 12, P521_KEM),
+    Suite(0x0006, "sha512", 64, 32, 12, X448_KEM),
+    Suite(0x0007, "sha384", 48, 32, 12, P384_KEM),
+]
+
+# The AEAD of each suite, by RFC 9180 id
+AEAD_IDS = {0x0001: 1, 0x0002: 1, 0x0003: 3, 0x0004: 2, 0x0005: 2, 0x0006: 3, 0x0007: 2}
+# The KDF of each hash, by RFC 9180 id
+KDF_IDS = {"sha256": 1, "sha384": 2, "sha512": 3}
+
+#
+# HKDF and the labeled functions of RFC 9420
+#
+
+
+def extract(hash_name, salt, ikm):
+    if not salt:
+        salt = bytes(hashlib.new(hash_name).digest_size)
+    return hmac.new(salt, ikm, hash_name).digest()
+
+
+def expand(hash_name, prk, info, length):
+    out, t, i = b"", b"", 1
+    while len(out) < length:
+        t = hmac.new(prk, t + info + u8(i), hash_name).digest()
+        out += t
+        i += 1
+    return out[:length]
+
+
+def expand_with_label(suite, secret, label, context, length):
+    kdf_label = u16(length) + opaque(b"MLS 1.0 " + label) + opaque(context)
+    return expand(suite.hash_name, secret, kdf_label, length)
+
+
+def derive_secret(suite, secret, label):
+    return expand_with_label(suite, secret, label, b"", suite.nh)
+
+
+def derive_tree_secret(suite, secret, label, generation, length):
+    return expand_with_label(suite, secret, label, u32(generation), length)
+
+
+def mac(suite, key, data):
+    return hmac.new(key, data, suite.hash_name).digest()
+
+
+def ref_hash(suite, label, value):
+    return suite.hash(opaque(label) + opaque(value))
+
+
+#
+# HPKE, base mode only (RFC 9180)
+#
+
+
+def dh_keys(kem, sk_bytes):
+    """Returns (sk, pk_bytes) for the private key sk_bytes of the given KEM"""
+    from cryptography.hazmat.primitives import serialization
+    from cryptography.hazmat.primitives.asymmetric import ec, x448, x25519
+
+    kem_id = kem[0]
+    if kem_id == 0x0020:
+        sk = x25519.X25519PrivateKey.from_private_bytes(sk_bytes)
+        pk = sk.public_key().public_bytes(
+            serialization.Encoding.Raw, serialization.PublicFormat.Raw
+        )
+    elif kem_id == 0x0021:
+        sk = x448.X448PrivateKey.from_private_bytes(sk_bytes)
+        pk = sk.public_key().public_bytes(
+            serialization.Encoding.Raw, serialization.PublicFormat.Raw
+        )
+    else:
+        curve = {0x0010: ec.SECP256R1(), 0x0011: ec.SECP384R1(), 0x0012: ec.SECP521R1()}[kem_id]
+        sk = ec.derive_private_key(int.from_bytes(sk_bytes, "big"), curve)
+        pk = sk.public_key().public_bytes(
+            serialization.Encoding.X962, serialization.PublicFormat.UncompressedPoint
+        )
+    return sk, pk
+
+
+def hpke_labeled_extract(hash_name, suite_id, salt, label, ikm):
+    return extract(hash_name, salt, b"HPKE-v1" + suite_id + label + ikm)
+
+
+def hpke_labeled_expand(hash_name, suite_id, prk, label, info, length):
+    labeled_info = u16(length) + b"HPKE-v1" + suite_id + label + info
+    return expand(hash_name, prk, labeled_info, length)
+
+
+def derive_key_pair(kem, ikm):
+    """DeriveKeyPair of RFC 9180 section 7.1.3. Returns (sk_bytes, pk_bytes)."""
+    kem_id, hash_name, _, nsk = kem
+    suite_id = b"KEM" + u16(kem_id)
+    dkp_prk = hpke_labeled_extract(hash_name, suite_id, b"", b"dkp_prk", ikm)
+    if kem_id in (0x0020, 0x0021):
+        sk = hpke_labeled_expand(hash_name, suite_id, dkp_prk, b"sk", b"", nsk)
+        return sk, dh_keys(kem, sk)[1]
+
+    order = {
+        0x0010: 0xFFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551,
+        0x0011: int(
+            "ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf"
+            "581a0db248b0a77aecec196accc52973",
+            16,
+        ),
+        0x0012: int(
+            "01ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
+            "fffa51868783bf2f966b7fcc0148f709a5d03bb5c9b8899c47aebb6fb71e91386409",
+            16,
+        ),
+    }[kem_id]
+    bitmask = 0x01 if kem_id == 0x0012 else 0xFF
+    for counter in range(256):
+        candidate = bytearray(
+            hpke_labeled_expand(hash_name, suite_id, dkp_prk, b"candidate", u8(counter), nsk)
+        )
+        candidate[0] &= bitmask
+        sk = int.from_bytes(candidate, "big")
+        if 0 < sk < order:
+            sk = bytes(candidate)
+            return sk, dh_keys(kem, sk)[1]
+    raise ValueError("DeriveKeyPairError")
+
+
+def hpke_encap(kem, pk_r, sk_e_bytes):
+    """Encap with the given ephemeral private key. Returns (shared_secret, enc)."""
+    from cryptography.hazmat.primitives.asymmetric import ec, x448, x25519
+
+    kem_id, kem_hash, nsecret, _ = kem
+    sk_e, enc = dh_keys(kem, sk_e_bytes)
+    if kem_id == 0x0020:
+        dh = sk_e.exchange(x25519.X25519PublicKey.from_public_bytes(pk_r))
+    elif kem_id == 0x0021:
+        dh = sk_e.exchange(x448.X448PublicKey.from_public_bytes(pk_r))
+    else:
+        peer = ec.EllipticCurvePublicKey.from_encoded_point(sk_e.curve, pk_r)
+        dh = sk_e.exchange(ec.ECDH(), peer)
+
+    kem_suite_id = b"KEM" + u16(kem_id)
+    eae_prk = hpke_labeled_extract(kem_hash, kem_suite_id, b"", b"eae_prk", dh)
+    shared_secret = hpke_labeled_expand(
+        kem_hash, kem_suite_id, eae_prk, b"shared_secret", enc + pk_r, nsecret
+    )
+    return shared_secret, enc
+
+
+def hpke_seal_base(suite, pk_r, info, aad, plaintext, sk_e_bytes):
+    """SealBase with the given ephemeral private key. Returns (enc, ciphertext)."""
+    shared_secret, enc = hpke_encap(suite.kem, pk_r, sk_e_bytes)
+    key, base_nonce, _ = hpke_key_schedule(suite, shared_secret, info)
+    return enc, aead_seal(suite, key, base_nonce, aad, plaintext)
+
+
+def hpke_key_schedule(suite, shared_secret, info):
+    """The base mode KeySchedule. Returns (key, base_nonce, exporter_secret)."""
+    h = suite.hash_name
+    suite_id = b"HPKE" + u16(suite.kem[0]) + u16(KDF_IDS[h]) + u16(AEAD_IDS[suite.id])
+    psk_id_hash = hpke_labeled_extract(h, suite_id, b"", b"psk_id_hash", b"")
+    info_hash = hpke_labeled_extract(h, suite_id, b"", b"info_hash", info)
+    context = u8(0) + psk_id_hash + info_hash
+    secret = hpke_labeled_extract(h, suite_id, shared_secret, b"secret", b"")
+    key = hpke_labeled_expand(h, suite_id, secret, b"key", context, suite.nk)
+    base_nonce = hpke_labeled_expand(h, suite_id, secret, b"base_nonce", context, suite.nn)
+    exporter_secret = hpke_labeled_expand(
+        h, suite_id, secret, b"exp", context, hashlib.new(h).digest_size
+    )
+    return key, base_nonce, exporter_secret
+
+
+def aead_seal(suite, key, nonce, aad, plaintext):
+    from cryptography.hazmat.primitives.ciphers.aead import AESGCM, ChaCha20Poly1305
+
+    cipher = ChaCha20Poly1305(key) if AEAD_IDS[suite.id] == 3 else AESGCM(key)
+    return cipher.encrypt(nonce, plaintext, aad)
+
+
+#
+# Self-checks against published answers
+#
+
+
+def self_check():
+    suite = SUITES[0]
+
+    # The KDF answers in src/crypto/kdf.rs
+    secret = bytes(range(32))
+    assert (
+        expand_with_label(suite, secret, b"test", b"context", 40).hex()
+        == "193095217454c5fb694eb3f263798a42ecc7e6eea9f3d7aa7b728dbdc502d065536062a6a3fa8d74"
+    )
+    assert (
+        expand_with_label(suite, secret, b"test", b"\xab" * 100, 32).hex()
+        == "b9a1d92d1a66a54caaa030ed1b7deb32c68d3f179c991ce81251d00aa37159ad"
+    )
+
+    # RFC 9180 A.1.1, whose KEM, KDF, and AEAD are those of suite 1. These are the answers in
+    # src/crypto/hpke.rs.
+    info = bytes.fromhex("4f6465206f6e2061204772656369616e2055726e")
+    sk_e = bytes.fromhex("52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736")
+    sk_r = bytes.fromhex("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8")
+    shared_secret, enc = hpke_encap(X25519_KEM, dh_keys(X25519_KEM, sk_r)[1], sk_e)
+    assert enc.hex() == "37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431"
+    _, base_nonce, exporter_secret = hpke_key_schedule(suite, shared_secret, info)
+    assert base_nonce.hex() == "56d890e5accaaf011cff4b7d"
+    assert (
+        exporter_secret.hex()
+        == "45ff1c2e220db587171952c0592d5f5ebe103f1561a2614e38f2ffd47e99e3f8"
+    )
+
+    # RFC 9180 A.1.1's DeriveKeyPair
+    ikm_e = bytes.fromhex("7268600d403fce431561aef583ee1613527cff655c1343f29812e66706df3234")
+    assert derive_key_pair(X25519_KEM, ikm_e)[0] == sk_e
+
+
+#
+# key-schedule.json
+#
+
+
+def group_context(suite, group_id, epoch, tree_hash, confirmed_transcript_hash):
+    """A GroupContext of MLS 1.0 with no extensions"""
+    return (
+        u16(1)
+        + u16(suite.id)
+        + opaque(group_id)
+        + u64(epoch)
+        + opaque(tree_hash)
+        + opaque(confirmed_transcript_hash)
+        + opaque(b"")
+    )
+
+
+def key_schedule_vector(suite):
+    tag = "key-schedule/%d/" % suite.id
+    group_id = rand(tag + "group_id", 16)
+    initial_init_secret = rand(tag + "initial_init_secret", suite.nh)
+
+    init_secret = initial_init_secret
+    epochs = []
+    for epoch in range(3):
+        etag = tag + "%d/" % epoch
+        tree_hash = rand(etag + "tree_hash", suite.nh)
+        commit_secret = rand(etag + "commit_secret", suite.nh)
+        # Only the second epoch has a PSK
+        if epoch == 1:
+            psk_secret = rand(etag + "psk_secret", suite.nh)
+        else:
+            psk_secret = bytes(suite.nh)
+        confirmed_transcript_hash = rand(etag + "confirmed_transcript_hash", suite.nh)
+        context = group_context(suite, group_id, epoch, tree_hash, confirmed_transcript_hash)
+
+        extracted = extract(suite.hash_name, init_secret, commit_secret)
+        joiner_secret = expand_with_label(suite, extracted, b"joiner", context, suite.nh)
+        member_secret = extract(suite.hash_name, joiner_secret, psk_secret)
+        welcome_secret = derive_secret(suite, member_secret, b"welcome")
+        epoch_secret = expand_with_label(suite, member_secret, b"epoch", context, suite.nh)
+
+        derived = {
+            "init_secret": b"init",
+            "sender_data_secret": b"sender data",
+            "encryption_secret": b"encryption",
+            "exporter_secret": b"exporter",
+            "epoch_authenticator": b"authentication",
+            "external_secret": b"external",
+            "confirmation_key": b"confirm",
+            "membership_key": b"membership",
+            "resumption_psk": b"resumption",
+        }
+        derived = {k: derive_secret(suite, epoch_secret, v) for k, v in derived.items()}
+        external_pub = derive_key_pair(suite.kem, derived["external_secret"])[1]
+
+        exporter_label = rand(etag + "exporter/label", 8)
+        exporter_context = rand(etag + "exporter/context", 16)
+        exporter_length = 32
+        exported = expand_with_label(
+            suite,
+            derive_secret(suite, derived["exporter_secret"], exporter_label),
+            b"exported",
+            suite.hash(exporter_context),
+            exporter_length,
+        )
+
+        epochs.append(
+            {
+                "tree_hash": tree_hash.hex(),
+                "commit_secret": commit_secret.hex(),
+                "psk_secret": psk_secret.hex(),
+                "confirmed_transcript_hash": confirmed_transcript_hash.hex(),
+                "group_context": context.hex(),
+                "joiner_secret": joiner_secret.hex(),
+                "welcome_secret": welcome_secret.hex(),
+                "init_secret": derived["init_secret"].hex(),
+                "sender_data_secret": derived["sender_data_secret"].hex(),
+                "encryption_secret": derived["encryption_secret"].hex(),
+                "exporter_secret": derived["exporter_secret"].hex(),
+                "epoch_authenticator": derived["epoch_authenticator"].hex(),
+                "external_secret": derived["external_secret"].hex(),
+                "confirmation_key": derived["confirmation_key"].hex(),
+                "membership_key": derived["membership_key"].hex(),
+                "resumption_psk": derived["resumption_psk"].hex(),
+                "external_pub": external_pub.hex(),
+                "exporter": {
+                    "label": exporter_label.hex(),
+                    "context": exporter_context.hex(),
+                    "length": exporter_length,
+                    "secret": exported.hex(),
+                },
+            }
+        )
+        init_secret = derived["init_secret"]
+
+    return {
+        "cipher_suite": suite.id,
+        "group_id": group_id.hex(),
+        "initial_init_secret": initial_init_secret.hex(),
+        "epochs": epochs,
+    }
+
+
+#
+# secret-tree.json
+#
+
+
+def leaf_secrets(suite, secret, num_leaves):
+    """The leaf secrets of a secret tree with a power of 2 many leaves"""
+    if num_leaves == 1:
+        return [secret]
+    left = expand_with_label(suite, secret, b"tree", b"left", suite.nh)
+    right = expand_with_label(suite, secret, b"tree", b"right", suite.nh)
+    half = num_leaves // 2
+    return leaf_secrets(suite, left, half) + leaf_secrets(suite, right, half)
+
+
+def ratchet_key_nonce(suite, secret, generation):
+    """The key and nonce of the given generation of a ratchet that starts at secret"""
+    for g in range(generation):
+        secret = derive_tree_secret(suite, secret, b"secret", g, suite.nh)
+    return (
+        derive_tree_secret(suite, secret, b"key", generation, suite.nk),
+        derive_tree_secret(suite, secret, b"nonce", generation, suite.nn),
+    )
+
+
+def secret_tree_vector(suite, num_leaves):
+    tag = "secret-tree/%d/%d/" % (suite.id, num_leaves)
+
+    sender_data_secret = rand(tag + "sender_data_secret", suite.nh)
+    ciphertext = rand(tag + "ciphertext", 77)
+    sample = ciphertext[: suite.nh]
+    sender_data = {
+        "sender_data_secret": sender_data_secret.hex(),
+        "ciphertext": ciphertext.hex(),
+        "key": expand_with_label(suite, sender_data_secret, b"key", sample, suite.nk).hex(),
+        "nonce": expand_with_label(suite, sender_data_secret, b"nonce", sample, suite.nn).hex(),
+    }
+
+    encryption_secret = rand(tag + "encryption_secret", suite.nh)
+    leaves = []
+    for leaf_secret in leaf_secrets(suite, encryption_secret, num_leaves):
+        handshake = derive_secret(suite, leaf_secret, b"handshake")
+        application = derive_secret(suite, leaf_secret, b"application")
+        generations = []
+        for generation in [0, 1, 15]:
+            handshake_key, handshake_nonce = ratchet_key_nonce(suite, handshake, generation)
+            application_key, application_nonce = ratchet_key_nonce(suite, application, generation)
+            generations.append(
+                {
+                    "generation": generation,
+                    "handshake_key": handshake_key.hex(),
+                    "handshake_nonce": handshake_nonce.hex(),
+                    "application_key": application_key.hex(),
+                    "application_nonce": application_nonce.hex(),
+                }
+            )
+        leaves.append(generations)
+
+    return {
+        "cipher_suite": suite.id,
+        "sender_data": sender_data,
+        "encryption_secret": encryption_secret.hex(),
+        "leaves": leaves,
+    }
+
+
+#
+# application-key-schedule.json
+#
+
+
+def application_key_schedule_vector(suite, sender):
+    """The per-sender key chain of src/application.rs, which predates the secret tree. Every sender
+    gets write_secret = ExpandWithLabel(application_secret, "app sender", sender, Hash.length),
+    which is then ratcheted like a secret tree ratchet."""
+    tag = "application-key-schedule/%d/" % suite.id
+    application_secret = rand(tag + "application_secret", suite.nh)
+    write_secret = expand_with_label(
+        suite, application_secret, b"app sender", u32(sender), suite.nh
+    )
+
+    generations = []
+    for generation in range(3):
+        key, nonce = ratchet_key_nonce(suite, write_secret, generation)
+        generations.append({"generation": generation, "key": key.hex(), "nonce": nonce.hex()})
+
+    return {
+        "cipher_suite": suite.id,
+        "application_secret": application_secret.hex(),
+        "sender": sender,
+        "generations": generations,
+    }
+
+
+#
+# welcome.json
+#
+
+# The EdDSA scheme of each suite that has one. The others sign with ECDSA, whose signatures aren't
+# deterministic, so they're left out.
+EDDSA_SUITES = {0x0001: "ed25519", 0x0003: "ed25519", 0x0004: "ed448", 0x0006: "ed448"}
+# The private key size of each EdDSA scheme
+EDDSA_KEY_SIZES = {"ed25519": 32, "ed448": 57}
+
+
+def signature_keys(scheme, sk_bytes):
+    """Returns (sk, pk_bytes) for the private key sk_bytes of the given EdDSA scheme"""
+    from cryptography.hazmat.primitives import serialization
+    from cryptography.hazmat.primitives.asymmetric import ed448, ed25519
+
+    if scheme == "ed25519":
+        sk = ed25519.Ed25519PrivateKey.from_private_bytes(sk_bytes)
+    else:
+        sk = ed448.Ed448PrivateKey.from_private_bytes(sk_bytes)
+    pk = sk.public_key().public_bytes(serialization.Encoding.Raw, serialization.PublicFormat.Raw)
+    return sk, pk
+
+
+def sign_with_label(sk, label, content):
+    return sk.sign(opaque(b"MLS 1.0 " + label) + opaque(content))
+
+
+def key_package(suite, tag, init_pub):
+    """A KeyPackage of a joiner with a basic credential, signed by a key of its own"""
+    scheme = EDDSA_SUITES[suite.id]
+    sk, pk = signature_keys(scheme, rand(tag + "joiner/signature_priv", EDDSA_KEY_SIZES[scheme]))
+    encryption_pub = dh_keys(suite.kem, rand(tag + "joiner/encryption_priv", suite.kem[3]))[1]
+
+    credential = u16(1) + opaque(b"joiner")
+    capabilities = (
+        opaque(u16(1)) + opaque(u16(suite.id)) + opaque(b"") + opaque(b"") + opaque(u16(1))
+    )
+    lifetime = u64(0) + u64(0xFFFFFFFFFFFFFFFF)
+    leaf_node_tbs = (
+        opaque(encryption_pub)
+        + opaque(pk)
+        + credential
+        + capabilities
+        + u8(1)
+        + lifetime
+        + opaque(b"")
+    )
+    leaf_node = leaf_node_tbs + opaque(sign_with_label(sk, b"LeafNodeTBS", leaf_node_tbs))
+
+    key_package_tbs = u16(1) + u16(suite.id) + opaque(init_pub) + leaf_node + opaque(b"")
+    return key_package_tbs + opaque(sign_with_label(sk, b"KeyPackageTBS", key_package_tbs))
+
+
+def welcome_vector(suite):
+    tag = "welcome/%d/" % suite.id
+    scheme = EDDSA_SUITES[suite.id]
+    signer_priv = rand(tag + "signer_priv", EDDSA_KEY_SIZES[scheme])
+    signer_sk, signer_pub = signature_keys(scheme, signer_priv)
+    init_priv = rand(tag + "init_priv", suite.kem[3])
+    init_pub = dh_keys(suite.kem, init_priv)[1]
+    kp = key_package(suite, tag, init_pub)
+
+    # The group info, encrypted under the welcome secret. There are no PSKs.
+    joiner_secret = rand(tag + "joiner_secret", suite.nh)
+    member_secret = extract(suite.hash_name, joiner_secret, bytes(suite.nh))
+    welcome_secret = derive_secret(suite, member_secret, b"welcome")
+    confirmed_transcript_hash = rand(tag + "confirmed_transcript_hash", suite.nh)
+    context = group_context(
+        suite,
+        rand(tag + "group_id", 16),
+        7,
+        rand(tag + "tree_hash", suite.nh),
+        confirmed_transcript_hash,
+    )
+    epoch_secret = expand_with_label(suite, member_secret, b"epoch", context, suite.nh)
+    confirmation_key = derive_secret(suite, epoch_secret, b"confirm")
+    confirmation_tag = mac(suite, confirmation_key, confirmed_transcript_hash)
+    group_info_tbs = context + opaque(b"") + opaque(confirmation_tag) + u32(1)
+    group_info = group_info_tbs + opaque(
+        sign_with_label(signer_sk, b"GroupInfoTBS", group_info_tbs)
+    )
+    encrypted_group_info = aead_seal(
+        suite,
+        expand_with_label(suite, welcome_secret, b"key", b"", suite.nk),
+        expand_with_label(suite, welcome_secret, b"nonce", b"", suite.nn),
+        b"",
+        group_info,
+    )
+
+    # The group secrets, encrypted to the joiner's init key with
+    # EncryptWithLabel(init_key, "Welcome", encrypted_group_info, group_secrets)
+    group_secrets = opaque(joiner_secret) + u8(0) + opaque(b"")
+    info = opaque(b"MLS 1.0 Welcome") + opaque(encrypted_group_info)
+    kem_output, ciphertext = hpke_seal_base(
+        suite, init_pub, info, b"", group_secrets, rand(tag + "ephemeral_priv", suite.kem[3])
+    )
+    encrypted_group_secrets = (
+        opaque(ref_hash(suite, b"MLS 1.0 KeyPackage Reference", kp))
+        + opaque(kem_output)
+        + opaque(ciphertext)
+    )
+    welcome = u16(suite.id) + opaque(encrypted_group_secrets) + opaque(encrypted_group_info)
+
+    # Both go out as MLSMessages of MLS 1.0
+    return {
+        "cipher_suite": suite.id,
+        "init_priv": init_priv.hex(),
+        "signer_pub": signer_pub.hex(),
+        "key_package": (u16(1) + u16(5) + kp).hex(),
+        "welcome": (u16(1) + u16(3) + welcome).hex(),
+    }
+
+
+def write(name, vectors):
+    path = os.path.join(os.path.dirname(os.path.abspath(__file__)), name)
+    with open(path, "w") as f:
+        json.dump(vectors, f, indent=2)
+        f.write("\n")
+
+
+def main():
+    self_check()
+    write("key-schedule.json", [key_schedule_vector(s) for s in SUITES])
+    write(
+        "secret-tree.json",
+        [secret_tree_vector(s, n) for s in SUITES for n in [1, 8, 32]],
+    )
+    write(
+        "application-key-schedule.json",
+        [application_key_schedule_vector(s, 1) for s in SUITES if s.id == 0x0001],
+    )
+    write("welcome.json", [welcome_vector(s) for s in SUITES if s.id in EDDSA_SUITES])
+
+
+if __name__ == "__main__":
+    main()